@@ -0,0 +1,137 @@
+//! The Base58Check encoding of Bitcoin: a version byte and a payload, followed by the first
+//! four bytes of their double SHA-256 as a checksum, written with an alphabet leaving out the
+//! characters that are easily mistaken for one another.
+
+use crypto::Hasher;
+
+const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const CHECKSUM_LEN: usize = 4;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DecodingError {
+    /// The text contains a character out of the Base58 alphabet.
+    InvalidCharacter(char),
+    /// The decoded bytes are too short to hold a version byte and a checksum.
+    TooShort,
+    InvalidChecksum,
+}
+
+pub fn encode(bytes: &[u8]) -> String {
+    // Every leading zero byte is written as the first character of the alphabet.
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+
+    // The base 58 digits of the rest of the bytes, least significant first.
+    let mut digits: Vec<u8> = vec![];
+    for byte in &bytes[zeros..] {
+        let mut carry = *byte as u32;
+        for digit in &mut digits {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    let mut encoded = String::with_capacity(zeros + digits.len());
+    for _i in 0..zeros {
+        encoded.push(ALPHABET[0] as char);
+    }
+    for digit in digits.iter().rev() {
+        encoded.push(ALPHABET[*digit as usize] as char);
+    }
+    encoded
+}
+
+pub fn decode(encoded: &str) -> Result<Vec<u8>, DecodingError> {
+    let zeros = encoded.chars().take_while(|character| *character == ALPHABET[0] as char).count();
+
+    // The bytes of the number, least significant first.
+    let mut bytes: Vec<u8> = vec![];
+    for character in encoded.chars().skip(zeros) {
+        let mut carry = ALPHABET.iter()
+            .position(|symbol| *symbol as char == character)
+            .ok_or(DecodingError::InvalidCharacter(character))? as u32;
+        for byte in &mut bytes {
+            carry += (*byte as u32) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+
+    let mut decoded = vec![0u8; zeros];
+    decoded.extend(bytes.iter().rev());
+    Ok(decoded)
+}
+
+pub fn encode_check(version: u8, payload: &[u8]) -> String {
+    let mut bytes = Vec::with_capacity(1 + payload.len() + CHECKSUM_LEN);
+    bytes.push(version);
+    bytes.extend_from_slice(payload);
+    let checksum = checksum(&bytes);
+    bytes.extend_from_slice(&checksum);
+    encode(&bytes)
+}
+
+/// Returns the version byte and the payload of the encoded text.
+pub fn decode_check(encoded: &str) -> Result<(u8, Vec<u8>), DecodingError> {
+    let mut bytes = decode(encoded)?;
+    if bytes.len() < 1 + CHECKSUM_LEN {
+        return Err(DecodingError::TooShort);
+    }
+
+    let checksum_index = bytes.len() - CHECKSUM_LEN;
+    if checksum(&bytes[..checksum_index]) != bytes[checksum_index..] {
+        return Err(DecodingError::InvalidChecksum);
+    }
+
+    bytes.truncate(checksum_index);
+    let payload = bytes.split_off(1);
+    Ok((bytes[0], payload))
+}
+
+fn checksum(bytes: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let mut checksum = [0u8; CHECKSUM_LEN];
+    checksum.copy_from_slice(&Hasher::DoubleSha256.digest(bytes).as_ref()[..CHECKSUM_LEN]);
+    checksum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_and_decodes_base58() {
+        assert_eq!("", encode(&[]));
+        assert_eq!("2NEpo7TZRRrLZSi2U", encode(b"Hello World!"));
+        assert_eq!("11233QC4", encode(&[0, 0, 0x28, 0x7f, 0xb4, 0xcd]));
+
+        assert_eq!(Ok(b"Hello World!".to_vec()), decode("2NEpo7TZRRrLZSi2U"));
+        assert_eq!(Ok(vec![0, 0, 0x28, 0x7f, 0xb4, 0xcd]), decode("11233QC4"));
+        assert_eq!(Err(DecodingError::InvalidCharacter('0')), decode("2NEpo0"));
+    }
+
+    #[test]
+    fn checks_the_checksum() {
+        // The Bitcoin address of the hash160 of the public key of the first block's coinbase.
+        let payload = [
+            0x62, 0xe9, 0x07, 0xb1, 0x5c, 0xbf, 0x27, 0xd5, 0x42, 0x53,
+            0x99, 0xeb, 0xf6, 0xf0, 0xfb, 0x50, 0xeb, 0xb8, 0x8f, 0x18,
+        ];
+        let encoded = encode_check(0, &payload);
+        assert_eq!("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa", encoded);
+        assert_eq!(Ok((0, payload.to_vec())), decode_check(&encoded));
+
+        assert_eq!(
+            Err(DecodingError::InvalidChecksum),
+            decode_check("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNb")
+        );
+        assert_eq!(Err(DecodingError::TooShort), decode_check("1A1z"));
+    }
+}
@@ -0,0 +1,166 @@
+use bincode;
+use blockchain::Body;
+use chain_params::ChainParams;
+use mempool::Mempool;
+use transaction::{Address, SignedTx, TxOut};
+use Error;
+
+/// Assembles the body of a candidate block from the pending transactions of a mempool, so that
+/// the selection of the transactions does not depend on how the block is mined.
+///
+/// The transactions with the highest fees per byte are taken first. A transaction that does not
+/// fit in the remaining space is skipped, and smaller ones may still fill it. The coinbase pays
+/// the block reward of the network and the fees of the selected transactions to the coinbase
+/// address.
+pub struct BlockTemplateBuilder {
+    params: ChainParams,
+    coinbase_address: Address,
+    max_size: usize,
+    max_transactions: usize,
+}
+
+/// A candidate block body, along with the fees and the size of its transactions.
+pub struct BlockTemplate {
+    body: Body,
+    fees: u32,
+    size: usize,
+}
+
+impl BlockTemplateBuilder {
+    /// Builds the blocks of the given network, up to its maximum block size.
+    pub fn new(params: &ChainParams, coinbase_address: Address) -> BlockTemplateBuilder {
+        BlockTemplateBuilder {
+            params: params.clone(),
+            coinbase_address,
+            max_size: params.max_block_size(),
+            max_transactions: usize::MAX,
+        }
+    }
+
+    /// The maximum size of the serialized body, coinbase included, in bytes. Above the one of
+    /// the network, the blocks would be rejected.
+    pub fn with_max_size(mut self, max_size: usize) -> BlockTemplateBuilder {
+        self.max_size = max_size;
+        self
+    }
+
+    /// The maximum number of transactions, coinbase excluded.
+    pub fn with_max_transactions(mut self, max_transactions: usize) -> BlockTemplateBuilder {
+        self.max_transactions = max_transactions;
+        self
+    }
+
+    /// Builds the body of the block at the given height from the transactions of the pool.
+    pub fn build(&self, mempool: &Mempool, height: u32) -> Result<BlockTemplate, Error> {
+        let reward = self.params.block_reward(height);
+        // The amount of the coinbase is a fixed-size integer: the size of an empty body does
+        // not depend on the fees.
+        let mut size = bincode::serialize(&Body::new(self.coinbase_tx_out(reward), vec![]))?.len();
+        let mut fees = 0u32;
+        let mut transactions: Vec<SignedTx> = vec![];
+
+        for entry in mempool.select_for_block(usize::MAX) {
+            if transactions.len() >= self.max_transactions {
+                break;
+            }
+            if size + entry.size() > self.max_size {
+                continue;
+            }
+            let entry_fees = match fees.checked_add(*entry.fees()) {
+                Some(entry_fees) if reward.checked_add(entry_fees).is_some() => entry_fees,
+                _ => continue,
+            };
+
+            fees = entry_fees;
+            size += entry.size();
+            transactions.push(entry.transaction().clone());
+        }
+
+        Ok(BlockTemplate {
+            body: Body::new(self.coinbase_tx_out(reward + fees), transactions),
+            fees,
+            size,
+        })
+    }
+
+    fn coinbase_tx_out(&self, amount: u32) -> TxOut {
+        TxOut::new(amount, self.coinbase_address.clone())
+    }
+}
+
+impl BlockTemplate {
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    pub fn into_body(self) -> Body {
+        self.body
+    }
+
+    /// The fees of the selected transactions, which the coinbase collects.
+    pub fn fees(&self) -> &u32 {
+        &self.fees
+    }
+
+    /// The size of the serialized body, in bytes.
+    pub fn size(&self) -> &usize {
+        &self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::{COINBASE_AMOUNT, COINBASE_MATURITY};
+    use utxo::UtxoSet;
+    use wallet::Wallet;
+
+    const NEXT_HEIGHT: u32 = COINBASE_MATURITY + 3;
+
+    #[test]
+    fn selects_the_highest_fee_rates_that_fit_in_the_block() {
+        let mut utxo_set = UtxoSet::new();
+        let mut mempool = Mempool::new();
+        for (height, fees) in [5, 20, 10].iter().enumerate() {
+            let mut wallet = Wallet::new();
+            let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT, wallet.new_address().unwrap());
+            let body = Body::new(coinbase_tx_out, vec![]);
+            utxo_set.apply(&body, height as u32).unwrap();
+            wallet.connect_block(&body, height as u32).unwrap();
+
+            let to_address = wallet.new_address().unwrap();
+            let transaction = wallet.new_transaction(100, to_address, *fees, &utxo_set).unwrap();
+            mempool.add(transaction, &utxo_set, NEXT_HEIGHT).unwrap();
+        }
+        let coinbase_address = Wallet::new().new_address().unwrap();
+        let params = ChainParams::main();
+
+        let template = BlockTemplateBuilder::new(&params, coinbase_address.clone())
+            .build(&mempool, NEXT_HEIGHT)
+            .unwrap();
+        assert_eq!(&35, template.fees());
+        assert_eq!(3, template.body().transactions().len());
+        assert_eq!(bincode::serialize(template.body()).unwrap().len(), *template.size());
+        assert_eq!(&(params.block_reward(NEXT_HEIGHT) + 35), template.body().coinbase_tx().0.amount());
+        template.body().verify(&utxo_set, NEXT_HEIGHT, &params).unwrap();
+
+        let template = BlockTemplateBuilder::new(&params, coinbase_address.clone())
+            .with_max_transactions(2)
+            .build(&mempool, NEXT_HEIGHT)
+            .unwrap();
+        assert_eq!(&30, template.fees());
+
+        // Room for a single transaction, the one paying the most.
+        let max_size = *BlockTemplateBuilder::new(&params, coinbase_address.clone())
+            .with_max_transactions(1)
+            .build(&mempool, NEXT_HEIGHT)
+            .unwrap()
+            .size();
+        let template = BlockTemplateBuilder::new(&params, coinbase_address)
+            .with_max_size(max_size)
+            .build(&mempool, NEXT_HEIGHT)
+            .unwrap();
+        assert_eq!(&20, template.fees());
+        template.into_body().verify(&utxo_set, NEXT_HEIGHT, &params).unwrap();
+    }
+}
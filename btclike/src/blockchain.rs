@@ -1,18 +1,27 @@
 use bincode;
+use chain_params::ChainParams;
 use crypto::Hash;
 use crypto::hash;
+use genesis::GenesisConfig;
+use hex;
 use Error;
+use merkle::MerkleTree;
 use ring::digest::SHA256_OUTPUT_LEN;
-use serde::ser::SerializeTuple;
+use serde::de;
+use serde::ser::SerializeSeq;
+use serde::Deserialize;
+use serde::Deserializer;
 use serde::Serialize;
 use serde::Serializer;
+use std::fmt;
 use std::sync::Arc;
-use std::u8::MAX as U8_MAX;
 use transaction::Address;
 use transaction::SignedTx;
 use transaction::TxOut;
 use transaction::UtxoStore;
 use transaction::CoinbaseTx;
+use u256::U256;
+use utxo::UtxoSet;
 
 pub struct Chain{
     head: Block,
@@ -20,18 +29,22 @@ pub struct Chain{
 }
 
 impl Chain{
-    pub fn mine_new_genesis(difficulty: Difficulty, coinbase_address: Address) -> Result<Chain, Error> {
-        let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT, coinbase_address);
-        let body = Body::new(coinbase_tx_out, vec![]);
-        let serialized_body = bincode::serialize(&body)?;
+    /// Mines the genesis block of a new chain of the given network, as defined by `genesis`.
+    pub fn mine_new_genesis(
+        params: &ChainParams,
+        genesis: &GenesisConfig,
+        coinbase_address: Address
+    ) -> Result<Chain, Error>
+    {
+        let coinbase_tx_out = TxOut::new(params.block_reward(0), coinbase_address);
+        let body = genesis.body(coinbase_tx_out)?;
 
-        let previous_block_hash = Hash::min();
         let mut header = Header::new(
             Nonce::new(),
-            difficulty,
-            previous_block_hash,
+            genesis.difficulty_or(params),
+            genesis.previous_block_hash(),
             0,
-            &serialized_body
+            body.merkle_root()?
         )?;
 
         loop {
@@ -46,7 +59,8 @@ impl Chain{
 
                     return chain.verify(
                         chain.head_hash(),
-                        &EmptyUtxoStore
+                        &UtxoSet::new(),
+                        params
                     ).map(|_|{
                         chain
                     });
@@ -61,23 +75,95 @@ impl Chain{
         }
     }
 
+    /// Mines a block on top of the chain, with the same difficulty, and paying the
+    /// block reward and the fees of the given transactions to the given address.
+    pub fn mine_next_block(
+        chain: Chain,
+        params: &ChainParams,
+        transactions: Vec<SignedTx>,
+        fees: u32,
+        coinbase_address: Address
+    ) -> Result<Chain, Error>
+    {
+        let reward = params.block_reward(chain.height() + 1);
+        let coinbase_tx_out = TxOut::new(reward + fees, coinbase_address);
+        let body = Body::new(coinbase_tx_out, transactions);
+
+        let mut header = Header::new(
+            Nonce::new(),
+            chain.head.header().difficulty().clone(),
+            chain.head_hash().clone(),
+            chain.height() + 1,
+            body.merkle_root()?
+        )?;
+
+        loop {
+            match header.verify() {
+                Ok(()) => {
+                    return Ok(Chain {
+                        head: Block::new(header, body),
+                        tail: Some(Arc::new(chain)),
+                    });
+                },
+                Err(Error::HashIsTooHigh) => {
+                    header.increment_nonce()?;
+                },
+                Err(err) => {
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Links the given blocks, genesis first, without verifying them.
+    pub fn from_blocks(blocks: Vec<Block>) -> Option<Chain> {
+        let mut blocks = blocks.into_iter();
+
+        let mut chain = Chain {
+            head: blocks.next()?,
+            tail: None,
+        };
+
+        for block in blocks {
+            chain = Chain {
+                head: block,
+                tail: Some(Arc::new(chain)),
+            };
+        }
+
+        Some(chain)
+    }
+
+    pub fn head(&self) -> &Block {
+        &self.head
+    }
+
+    /// The chain without its head block, none for the genesis block alone.
+    pub fn tail(&self) -> Option<&Chain> {
+        self.tail.as_deref()
+    }
+
     pub fn head_hash(&self) -> &Hash {
-        &self.head.header().hash()
+        self.head.header().hash()
+    }
+
+    pub fn height(&self) -> u32 {
+        *self.head.header().height()
     }
 
     // PERFORMANCE an iterative verification would be more efficient and would avoid stack overflow.
-    pub fn verify<S>(&self, expected_genesis_hash: &Hash, utxo_store: &S)
+    pub fn verify<S>(&self, expected_genesis_hash: &Hash, utxo_store: &S, params: &ChainParams)
                      -> Result<(), Error>
         where
             S: UtxoStore,
     {
-        self.head.verify(utxo_store)?;
+        self.head.verify(utxo_store, params)?;
 
-        if let &Some(ref tail) = &self.tail {
+        if let Some(ref tail) = self.tail {
             let t_header = tail.head.header();
             let h_header = self.head.header();
 
-            if t_header.previous_block_hash() != h_header.previous_block_hash() {
+            if t_header.hash() != h_header.previous_block_hash() {
                 return Err(Error::HeadAndTailHashMismatch);
             }
 
@@ -85,11 +171,11 @@ impl Chain{
                 return Err(Error::InvalidDifficulty);
             }
 
-            if t_header.height() + 1 != *h_header.height() {
+            if t_header.height().checked_add(1) != Some(*h_header.height()) {
                 return Err(Error::InvalidHeight);
             }
 
-            tail.verify(expected_genesis_hash, utxo_store)
+            tail.verify(expected_genesis_hash, utxo_store, params)
         } else if self.head.header().hash() == expected_genesis_hash{
             Ok(())
         } else {
@@ -98,14 +184,42 @@ impl Chain{
     }
 }
 
-struct EmptyUtxoStore;
+/// Serialized as the sequence of its blocks, from the genesis one to the head.
+impl Serialize for Chain
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+    {
+        let mut blocks = vec![&self.head];
+        let mut chain = self;
+        while let Some(ref tail) = chain.tail {
+            blocks.push(&tail.head);
+            chain = tail;
+        }
+
+        let mut seq = serializer.serialize_seq(Some(blocks.len()))?;
+        for block in blocks.iter().rev() {
+            seq.serialize_element(block)?;
+        }
+        seq.end()
+    }
+}
 
-impl UtxoStore for EmptyUtxoStore {
-    fn find(&self, _transaction_hash: &Hash, _txo_index: &u8) -> Option<&TxOut> {
-        None
+/// The blocks are not verified: `Chain::verify` must be called on the result.
+impl<'de> Deserialize<'de> for Chain
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+    {
+        let blocks = Vec::<Block>::deserialize(deserializer)?;
+        Chain::from_blocks(blocks)
+            .ok_or_else(|| de::Error::invalid_length(0, &"at least one block"))
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Block {
     header: Header,
     body: Body,
@@ -119,14 +233,26 @@ impl Block{
         }
     }
 
-    pub fn verify<S>(&self, utxo_store: &S) -> Result<(), Error>
+    /// Verifies the block against the consensus rules of the given network.
+    pub fn verify<S>(&self, utxo_store: &S, params: &ChainParams) -> Result<(), Error>
         where
             S: UtxoStore,
     {
+        self.verify_header(params)?;
+        self.body.verify(utxo_store, *self.header.height(), params)
+    }
+
+    /// Verifies the proof of work, that the difficulty is not easier than the limit of the
+    /// network, and that the header commits to the transactions of the body, without checking
+    /// them against a UTXO set.
+    pub fn verify_header(&self, params: &ChainParams) -> Result<(), Error> {
         self.header.verify()?;
-        self.body.verify(utxo_store)?;
 
-        if self.body.hash()? == self.header.hashed_content.body_hash {
+        if self.header.difficulty().threshold() > params.pow_limit().threshold() {
+            return Err(Error::InvalidDifficulty);
+        }
+
+        if self.body.merkle_root()? == self.header.hashed_content.merkle_root {
             Ok(())
         } else {
             Err(Error::HeaderAndBodyHashMismatch)
@@ -136,6 +262,10 @@ impl Block{
     pub fn header(&self) -> &Header{
         &self.header
     }
+
+    pub fn body(&self) -> &Body{
+        &self.body
+    }
 }
 
 pub struct Header {
@@ -149,16 +279,14 @@ impl Header {
         difficulty: Difficulty,
         previous_block_hash: Hash,
         height: u32,
-        serialized_body: &[u8],
+        merkle_root: Hash,
     ) -> Result<Header, Error>{
-        let body_hash = hash(&serialized_body);
-
         let hashed_content = HeaderHashedContent {
             nonce,
             difficulty,
             previous_block_hash,
             height,
-            body_hash,
+            merkle_root,
         };
 
         Ok(Header{
@@ -168,7 +296,7 @@ impl Header {
     }
 
     pub fn increment_nonce(&mut self) -> Result<(), Error>{
-        self.hashed_content.nonce.increment();
+        self.hashed_content.nonce.increment()?;
         self.hash = self.hashed_content.hash()?;
         Ok(())
     }
@@ -189,6 +317,11 @@ impl Header {
         &self.hashed_content.height
     }
 
+    /// The root of the Merkle tree of the transactions of the body, coinbase first.
+    pub fn merkle_root(&self) -> &Hash {
+        &self.hashed_content.merkle_root
+    }
+
     pub fn verify(&self) -> Result<(), Error>{
         let computed_hash = self.hashed_content.hash()?;
 
@@ -202,13 +335,41 @@ impl Header {
     }
 }
 
-#[derive(Serialize)]
+/// Only the hashed content is serialized, the hash is computed again on deserialization.
+impl Serialize for Header
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+    {
+        self.hashed_content.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Header
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+    {
+        let hashed_content = HeaderHashedContent::deserialize(deserializer)?;
+        let hash = hashed_content.hash()
+            .map_err(|err| de::Error::custom(format!("{:?}", err)))?;
+
+        Ok(Header {
+            hash,
+            hashed_content,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 struct HeaderHashedContent {
     nonce: Nonce,
     difficulty: Difficulty,
     previous_block_hash: Hash,
     height: u32,
-    body_hash: Hash,
+    merkle_root: Hash,
 }
 
 impl HeaderHashedContent{
@@ -218,9 +379,16 @@ impl HeaderHashedContent{
     }
 }
 
+/// The reward of the first blocks of the main network.
 pub const COINBASE_AMOUNT:u32 = 1000;
-
-#[derive(Serialize, Clone)]
+/// The number of blocks after which the reward of the main network is halved.
+pub const HALVING_INTERVAL:u32 = 210;
+/// The number of blocks a coinbase output of the main network must wait for before being spent,
+/// counting the one including it: an output created at height `h` can be spent from height
+/// `h + COINBASE_MATURITY`.
+pub const COINBASE_MATURITY:u32 = 10;
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Body {
     coinbase_tx: CoinbaseTx,
     transactions: Vec<SignedTx>,
@@ -237,27 +405,62 @@ impl Body{
         }
     }
 
-    pub fn hash(&self) -> Result<Hash, Error> {
-        let serialized = bincode::serialize(&self)?;
-        Ok(hash(&serialized))
+    /// The Merkle tree of the transaction hashes, the coinbase one being the first leaf.
+    pub fn merkle_tree(&self) -> Result<MerkleTree, Error> {
+        let mut leaves = Vec::with_capacity(self.transactions.len() + 1);
+        leaves.push(self.coinbase_tx.hash()?);
+        for transaction in &self.transactions {
+            leaves.push(transaction.hash()?);
+        }
+
+        Ok(MerkleTree::new(leaves))
+    }
+
+    pub fn merkle_root(&self) -> Result<Hash, Error> {
+        Ok(self.merkle_tree()?.root())
+    }
+
+    pub fn coinbase_tx(&self) -> &CoinbaseTx {
+        &self.coinbase_tx
     }
 
-    fn verify<S>(&self, utxo_store: &S) -> Result<(), Error>
+    pub fn transactions(&self) -> &[SignedTx] {
+        &self.transactions
+    }
+
+    /// Verifies the body as the one of the block at the given height of the given network.
+    /// Only the consensus rules apply: a block is valid whatever the relay policies of the
+    /// nodes think of its transactions.
+    pub fn verify<S>(&self, utxo_store: &S, height: u32, params: &ChainParams) -> Result<(), Error>
         where
             S: UtxoStore
     {
-        let mut fees = 0;
+        if bincode::serialized_size(self)? > params.max_block_size() as u64 {
+            return Err(Error::BlockTooLarge);
+        }
+
+        // The genesis block is known by its hash: its transactions spend nothing, they create
+        // the premined outputs.
+        if height == 0 {
+            if self.transactions.iter().any(|transaction| !transaction.input().is_empty()) {
+                return Err(Error::InvalidGenesis);
+            }
+            return self.verify_coinbase_tx(0, height, params);
+        }
+
+        let mut fees = 0u32;
         for transaction in &self.transactions {
-            fees += transaction.verify(utxo_store)?;
+            fees = fees.checked_add(transaction.verify(utxo_store, height, params)?)
+                .ok_or(Error::InvalidTxAmount)?;
         }
 
-        self.verify_coinbase_tx(fees)?;
+        self.verify_coinbase_tx(fees, height, params)?;
 
         Ok(())
     }
 
-    fn verify_coinbase_tx(&self, fees: u32) -> Result<(), Error> {
-        if self.coinbase_tx.0.amount() != &(COINBASE_AMOUNT + fees) {
+    fn verify_coinbase_tx(&self, fees: u32, height: u32, params: &ChainParams) -> Result<(), Error> {
+        if Some(*self.coinbase_tx.0.amount()) != params.block_reward(height).checked_add(fees) {
             Err(Error::InvalidCoinbaseAmount)
         } else {
             Ok(())
@@ -265,7 +468,11 @@ impl Body{
     }
 }
 
-const DIFFICULTY_BYTES_LEN: usize = SHA256_OUTPUT_LEN;
+/// The sign bit of the mantissa of a compact difficulty. The thresholds are never negative.
+const COMPACT_SIGN_BIT: u32 = 0x0080_0000;
+const COMPACT_MANTISSA_LEN: usize = 3;
+
+/// The threshold hashes must not exceed, always representable as compact bits.
 #[derive(Clone, PartialEq, Eq)]
 pub struct Difficulty {
     threshold: [u8; SHA256_OUTPUT_LEN],
@@ -273,44 +480,160 @@ pub struct Difficulty {
 
 impl Difficulty {
     pub fn min_difficulty() -> Difficulty {
-        let array = [U8_MAX as u8; SHA256_OUTPUT_LEN];
-        Difficulty { threshold: array }
+        let array = [u8::MAX; SHA256_OUTPUT_LEN];
+        Difficulty { threshold: array }.normalized()
     }
 
-    pub fn increase(&mut self) {
-        self.divide_threshold_by_two()
-    }
+    /// Decodes the compact bits of Bitcoin's headers, "nBits": the most significant byte is
+    /// the length in bytes of the threshold, and the three others are its most significant
+    /// bytes. Negative and overflowing thresholds are rejected, and so are the encodings
+    /// other than the one `to_compact` returns, so that a difficulty has a single encoding.
+    pub fn from_compact(bits: u32) -> Result<Difficulty, Error> {
+        if bits & COMPACT_SIGN_BIT != 0 {
+            return Err(Error::InvalidDifficulty);
+        }
 
-    fn divide_threshold_by_two(&mut self) {
-        let mut index_to_split = 0;
+        let len = (bits >> 24) as usize;
+        let mut threshold = [0u8; SHA256_OUTPUT_LEN];
+        for index in 0..COMPACT_MANTISSA_LEN {
+            let byte = (bits >> (8 * (COMPACT_MANTISSA_LEN - 1 - index))) as u8;
 
-        let max_index = self.threshold.len();
-        while self.threshold[index_to_split] == 0 {
-            index_to_split += 1;
+            // The bytes after the end of a short threshold are dropped.
+            if len <= index {
+                continue;
+            }
 
-            if index_to_split >= max_index {
-                panic!("Exceeded the maximum difficulty.")
+            let index_from_end = len - 1 - index;
+            if index_from_end < SHA256_OUTPUT_LEN {
+                threshold[SHA256_OUTPUT_LEN - 1 - index_from_end] = byte;
+            } else if byte != 0 {
+                return Err(Error::InvalidDifficulty);
             }
         }
 
-        self.threshold[index_to_split] /= 2;
+        let difficulty = Difficulty { threshold };
+        if difficulty.to_compact() == bits {
+            Ok(difficulty)
+        } else {
+            Err(Error::InvalidDifficulty)
+        }
+    }
 
-        if self.threshold[index_to_split] == 0 {
-            let next_index = index_to_split + 1;
+    /// The difficulty of the given threshold, rounded down to the closest one the compact bits
+    /// can represent.
+    pub fn from_threshold(threshold: U256) -> Difficulty {
+        Difficulty { threshold: threshold.to_be_bytes() }.normalized()
+    }
 
-            if next_index >= max_index {
-                panic!("Exceeded the maximum difficulty.")
-            }
+    pub fn to_compact(&self) -> u32 {
+        let leading_zeros = self.threshold.iter().take_while(|byte| **byte == 0).count();
+        let mut len = SHA256_OUTPUT_LEN - leading_zeros;
+
+        let mut mantissa = 0u32;
+        for index in leading_zeros..leading_zeros + COMPACT_MANTISSA_LEN {
+            mantissa <<= 8;
+            mantissa |= *self.threshold.get(index).unwrap_or(&0) as u32;
+        }
 
-            self.threshold[next_index] = U8_MAX / 2;
+        // The mantissa would be read as negative, it loses its least significant byte.
+        if mantissa & COMPACT_SIGN_BIT != 0 {
+            mantissa >>= 8;
+            len += 1;
         }
+
+        ((len as u32) << 24) | mantissa
+    }
+
+    /// Fails, leaving the difficulty unchanged, if the threshold cannot be lowered anymore.
+    pub fn increase(&mut self) -> Result<(), Error> {
+        divide_threshold_by_two(&mut self.threshold)?;
+        *self = self.normalized();
+        Ok(())
+    }
+
+    /// The difficulty after blocks took `actual_timespan` to be mined instead of
+    /// `expected_timespan`, in any unit: the threshold is scaled by their ratio, so that the
+    /// next blocks take the expected time. Like Bitcoin, the ratio is kept between a quarter
+    /// and four, and the difficulty never goes below the minimum one.
+    pub fn retarget(&self, actual_timespan: u64, expected_timespan: u64) -> Difficulty {
+        let expected_timespan = expected_timespan.max(1);
+        let actual_timespan = actual_timespan
+            .max((expected_timespan / 4).max(1))
+            .min(expected_timespan.saturating_mul(4));
+
+        let min_difficulty = Difficulty::min_difficulty();
+        let threshold = match self.threshold().checked_mul_u64(actual_timespan) {
+            Some(product) => product.div_u64(expected_timespan),
+            None => return min_difficulty,
+        };
+
+        if threshold > min_difficulty.threshold() {
+            min_difficulty
+        } else {
+            Difficulty::from_threshold(threshold)
+        }
+    }
+
+    /// The expected number of hashes needed to mine a block.
+    pub fn work(&self) -> U256 {
+        work(&self.threshold())
+    }
+
+    pub fn threshold(&self) -> U256 {
+        U256::from_be_bytes(&self.threshold)
     }
 
     pub fn is_lower_than(&self, hash: Hash) -> bool {
         &self.threshold < hash.as_ref()
     }
+
+    /// Rounds the threshold down to the closest one the compact bits can represent.
+    fn normalized(&self) -> Difficulty {
+        Difficulty::from_compact(self.to_compact())
+            .expect("The compact bits of a threshold are valid")
+    }
+}
+
+/// The threshold, in hexadecimal.
+impl fmt::Debug for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(&self.threshold))
+    }
+}
+
+/// Lowers a threshold, of a normalized difficulty or not, to about half of it: the first
+/// non-zero byte is halved, or moved to the next byte when it is 1. Fails, leaving the
+/// threshold unchanged, if it cannot be lowered anymore.
+fn divide_threshold_by_two(threshold: &mut [u8; SHA256_OUTPUT_LEN]) -> Result<(), Error> {
+    let index_to_split = threshold.iter()
+        .position(|byte| *byte != 0)
+        .ok_or(Error::MaxDifficultyExceeded)?;
+
+    if threshold[index_to_split] == 1 {
+        let next_index = index_to_split + 1;
+
+        if next_index >= threshold.len() {
+            return Err(Error::MaxDifficultyExceeded);
+        }
+
+        threshold[index_to_split] = 0;
+        threshold[next_index] = u8::MAX / 2;
+    } else {
+        threshold[index_to_split] /= 2;
+    }
+
+    Ok(())
 }
 
+/// The expected number of hashes below the threshold needed to mine a block:
+/// 2^256 / (threshold + 1), computed as (2^256 - threshold - 1) / (threshold + 1) + 1 to fit
+/// in 256 bits.
+pub fn work(threshold: &U256) -> U256 {
+    let divisor = threshold.saturating_add(&U256::one());
+    (!*threshold / divisor).saturating_add(&U256::one())
+}
+
+/// Serialized as its compact bits.
 impl Serialize for Difficulty
 {
     #[inline]
@@ -318,15 +641,23 @@ impl Serialize for Difficulty
         where
             S: Serializer,
     {
-        let mut seq = serializer.serialize_tuple(DIFFICULTY_BYTES_LEN)?;
-        for e in self.threshold.iter() {
-            seq.serialize_element(e)?;
-        }
-        seq.end()
+        serializer.serialize_u32(self.to_compact())
+    }
+}
+
+impl<'de> Deserialize<'de> for Difficulty
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+    {
+        let bits = u32::deserialize(deserializer)?;
+        Difficulty::from_compact(bits)
+            .map_err(|err| de::Error::custom(format!("{:?}", err)))
     }
 }
 
-#[derive(Serialize, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct Nonce(u64);
 
 impl Nonce {
@@ -334,8 +665,16 @@ impl Nonce {
         Nonce(0u64)
     }
 
-    pub fn increment(&mut self) {
-        self.0 += 1;
+    /// Fails, leaving the nonce unchanged, once every nonce was tried.
+    pub fn increment(&mut self) -> Result<(), Error> {
+        self.0 = self.0.checked_add(1).ok_or(Error::NonceExhausted)?;
+        Ok(())
+    }
+}
+
+impl Default for Nonce {
+    fn default() -> Self {
+        Nonce::new()
     }
 }
 
@@ -357,13 +696,15 @@ mod tests {
         let nonce = Nonce::new();
         let difficulty = Difficulty::min_difficulty();
         let body = Body::new(coinbase_tx_out, vec![]);
-        let serialized_body = bincode::serialize(&body).ok().unwrap();
         let previous_block_hash = Hash::min();
-        let header = Header::new(nonce, difficulty, previous_block_hash, 0, &serialized_body).ok().unwrap();
+        let merkle_root = body.merkle_root().ok().unwrap();
+        let header = Header::new(nonce, difficulty, previous_block_hash, 0, merkle_root).ok().unwrap();
 
         let block = Block::new(header, body);
 
-        block.verify(&EmptyUtxoStore{}).ok().unwrap()
+        block.verify(&UtxoSet::new(), &ChainParams::main()).ok().unwrap();
+        // Easier than the limit of the test network.
+        assert_eq!(Error::InvalidDifficulty, block.verify(&UtxoSet::new(), &ChainParams::testnet()).err().unwrap());
     }
 
     #[test]
@@ -382,45 +723,65 @@ mod tests {
         }
 
         assert_eq!(&10, chain.head.header.height());
-        match chain.verify(&genesis_hash, &EmptyUtxoStore) {
-            Err(error) => {
-                panic!("Invalid chain: {:?}", error);
-            }
-            Ok(()) => {}
+        if let Err(error) = chain.verify(&genesis_hash, &UtxoSet::new(), &ChainParams::main()) {
+            panic!("Invalid chain: {:?}", error);
         }
     }
 
     fn mine_new_chain(chain: Chain) -> Result<Chain, Error>{
-        let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT, random_address());
-        let body = Body::new(coinbase_tx_out, vec![]);
+        Chain::mine_next_block(chain, &ChainParams::main(), vec![], 0, random_address())
+    }
 
-        let block = {
-            let current_chain_header = &chain.head.header.hashed_content;
-            let header = mine_new_header(
-                &body,
-                current_chain_header.height + 1,
-                current_chain_header.difficulty.clone()
-            )?;
+    #[test]
+    fn halves_the_block_reward() {
+        let params = ChainParams::main();
+        assert_eq!(COINBASE_AMOUNT, params.block_reward(0));
+        assert_eq!(COINBASE_AMOUNT, params.block_reward(HALVING_INTERVAL - 1));
+        assert_eq!(COINBASE_AMOUNT / 2, params.block_reward(HALVING_INTERVAL));
+        assert_eq!(COINBASE_AMOUNT / 4, params.block_reward(2 * HALVING_INTERVAL + 1));
+        assert_eq!(0, params.block_reward(32 * HALVING_INTERVAL));
+        assert_eq!(0, params.block_reward(u32::MAX));
+
+        let body = Body::new(TxOut::new(COINBASE_AMOUNT, random_address()), vec![]);
+        body.verify(&UtxoSet::new(), HALVING_INTERVAL - 1, &params).ok().unwrap();
+        assert_eq!(
+            Error::InvalidCoinbaseAmount,
+            body.verify(&UtxoSet::new(), HALVING_INTERVAL, &params).err().unwrap()
+        );
+        // The reward of the regression test network is halved sooner.
+        assert_eq!(
+            Error::InvalidCoinbaseAmount,
+            body.verify(&UtxoSet::new(), HALVING_INTERVAL - 1, &ChainParams::regtest()).err().unwrap()
+        );
+    }
 
-            Block::new(header, body)
-        };
+    #[test]
+    fn rejects_unlinked_blocks() {
+        let chain = mine_new_genesis().ok().unwrap();
+        let genesis_hash = chain.head_hash().clone();
 
-        Ok(Chain{
-            head: block,
+        let body = Body::new(TxOut::new(COINBASE_AMOUNT, random_address()), vec![]);
+        let header = mine_new_header(&body, Hash::min(), 1, chain.head.header.difficulty().clone())
+            .ok().unwrap();
+        let chain = Chain {
+            head: Block::new(header, body),
             tail: Some(Arc::new(chain)),
-        })
-    }
+        };
 
-    fn mine_new_header(body: &Body, height: u32, difficulty: Difficulty) -> Result<Header, Error> {
-        let serialized_body = bincode::serialize(&body)?;
+        let params = ChainParams::main();
+        assert_eq!(
+            Error::HeadAndTailHashMismatch,
+            chain.verify(&genesis_hash, &UtxoSet::new(), &params).err().unwrap()
+        );
+    }
 
-        let previous_block_hash = Hash::min();
+    fn mine_new_header(body: &Body, previous_block_hash: Hash, height: u32, difficulty: Difficulty) -> Result<Header, Error> {
         let mut header = Header::new(
             Nonce::new(),
             difficulty,
             previous_block_hash,
             height,
-            &serialized_body
+            body.merkle_root()?
         )?;
 
         while {
@@ -452,7 +813,7 @@ mod tests {
         let mut new_nonce = Nonce::new();
 
         while previous_nonce == new_nonce {
-            new_nonce.increment()
+            new_nonce.increment().ok().unwrap();
         }
 
         chain.head.header.hashed_content.nonce = new_nonce;
@@ -460,7 +821,7 @@ mod tests {
         assert_eq!(Error::InvalidHeaderHash, verify_genesis_chain(&chain).err().unwrap());
 
         let mut chain = mine_new_genesis().ok().unwrap();
-        chain.head.header.hashed_content.body_hash = hash(b"Garneray");
+        chain.head.header.hashed_content.merkle_root = hash(b"Garneray");
         assert_eq!(Error::InvalidHeaderHash, verify_genesis_chain(&chain).err().unwrap());
 
         let mut chain = mine_new_genesis().ok().unwrap();
@@ -482,11 +843,104 @@ mod tests {
         assert_eq!(Error::HeaderAndBodyHashMismatch, verify_genesis_chain(&chain).err().unwrap());
     }
 
-    fn mine_new_genesis() -> Result<Chain, Error>{
+    #[test]
+    fn can_deserialize_chains() {
+        let mut chain = mine_new_genesis().ok().unwrap();
+        let genesis_hash = chain.head_hash().clone();
+
+        for _i in 0..3 {
+            chain = mine_new_chain(chain).ok().unwrap();
+        }
+
+        let serialized = bincode::serialize(&chain).ok().unwrap();
+        let deserialized: Chain = bincode::deserialize(&serialized).ok().unwrap();
+
+        assert!(chain.head_hash() == deserialized.head_hash());
+        assert_eq!(&3, deserialized.head.header.height());
+        deserialized.verify(&genesis_hash, &UtxoSet::new(), &ChainParams::main()).ok().unwrap();
+        assert_eq!(serialized, bincode::serialize(&deserialized).ok().unwrap());
+
+        let no_blocks: Vec<Block> = vec![];
+        let serialized = bincode::serialize(&no_blocks).ok().unwrap();
+        assert!(bincode::deserialize::<Chain>(&serialized).is_err());
+    }
+
+    #[test]
+    fn header_wire_format_is_stable() {
+        let chain = mine_new_genesis().ok().unwrap();
+        let serialized = bincode::serialize(&chain.head.header).ok().unwrap();
+
+        // Nonce, difficulty, previous block hash, height and Merkle root, without the hash.
+        assert_eq!(8 + 4 + 32 + 4 + 32, serialized.len());
+        assert_eq!(&bincode::serialize(&chain.head.header.hashed_content.nonce).ok().unwrap()[..], &serialized[..8]);
+
+        let deserialized: Header = bincode::deserialize(&serialized).ok().unwrap();
+        assert!(deserialized.hash() == chain.head.header.hash());
+        deserialized.verify().ok().unwrap();
+
+        assert!(bincode::deserialize::<Header>(&serialized[..serialized.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn encodes_difficulties_as_compact_bits() {
+        let bitcoin_genesis = Difficulty::from_compact(0x1d00ffff).ok().unwrap();
+        let mut threshold = [0u8; SHA256_OUTPUT_LEN];
+        threshold[4] = 0xff;
+        threshold[5] = 0xff;
+        assert!(threshold == bitcoin_genesis.threshold);
+        assert_eq!(0x1d00ffff, bitcoin_genesis.to_compact());
+
+        let mut threshold = [0u8; SHA256_OUTPUT_LEN];
+        threshold[28] = 0x92;
+        threshold[29] = 0x34;
+        assert!(threshold == Difficulty::from_compact(0x05009234).ok().unwrap().threshold);
+        assert_eq!(0x1b0404cb, Difficulty::from_compact(0x1b0404cb).ok().unwrap().to_compact());
+        assert_eq!(0x2100ffff, Difficulty::min_difficulty().to_compact());
+
+        // Negative, overflowing and non canonical encodings.
+        assert_eq!(Error::InvalidDifficulty, Difficulty::from_compact(0x04923456).err().unwrap());
+        assert_eq!(Error::InvalidDifficulty, Difficulty::from_compact(0x2201ffff).err().unwrap());
+        assert_eq!(Error::InvalidDifficulty, Difficulty::from_compact(0x01003456).err().unwrap());
+        assert_eq!(Error::InvalidDifficulty, Difficulty::from_compact(0x04000080).err().unwrap());
+    }
+
+    #[test]
+    fn retargets_difficulties() {
+        let difficulty = Difficulty::from_compact(0x1d00ffff).ok().unwrap();
+
+        // Blocks mined twice as fast halve the threshold.
+        assert_eq!(0x1c7fff80, difficulty.retarget(300, 600).to_compact());
+        assert_eq!(0x1d00ffff, difficulty.retarget(600, 600).to_compact());
+        // The ratio is bounded.
+        assert_eq!(0x1c3fffc0, difficulty.retarget(1, 600).to_compact());
+        assert_eq!(0x1d03fffc, difficulty.retarget(1_000_000, 600).to_compact());
+        // And the difficulty cannot go below the minimum one.
+        assert!(Difficulty::min_difficulty() == Difficulty::min_difficulty().retarget(1200, 600));
+    }
+
+    #[test]
+    fn cannot_exceed_the_max_difficulty_or_nonce() {
         let mut difficulty = Difficulty::min_difficulty();
-        difficulty.increase();
+        while difficulty.increase().is_ok() {}
+
+        assert_eq!(0x01010000, difficulty.to_compact());
+        assert_eq!(Error::MaxDifficultyExceeded, difficulty.increase().err().unwrap());
+        assert_eq!(0x01010000, difficulty.to_compact());
 
-        let chain = Chain::mine_new_genesis(difficulty, random_address())?;
+        let mut nonce = Nonce(u64::MAX);
+        assert_eq!(Error::NonceExhausted, nonce.increment().err().unwrap());
+        assert!(Nonce(u64::MAX) == nonce);
+    }
+
+    #[test]
+    fn computes_the_work_of_difficulties() {
+        // The chain work of Bitcoin's genesis block.
+        assert_eq!(U256::from(0x0100010001), Difficulty::from_compact(0x1d00ffff).ok().unwrap().work());
+        assert_eq!(U256::one(), Difficulty::min_difficulty().work());
+    }
+
+    fn mine_new_genesis() -> Result<Chain, Error>{
+        let chain = Chain::mine_new_genesis(&ChainParams::main(), &GenesisConfig::default(), random_address())?;
 
         verify_genesis_chain(&chain)?;
 
@@ -494,7 +948,7 @@ mod tests {
     }
 
     fn verify_genesis_chain(chain: &Chain) -> Result<(), Error>{
-        chain.verify(chain.head_hash(), &EmptyUtxoStore{})
+        chain.verify(chain.head_hash(), &UtxoSet::new(), &ChainParams::main())
     }
 
     fn random_address() -> Address{
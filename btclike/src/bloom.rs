@@ -0,0 +1,130 @@
+use crypto::hash;
+use rand::{self, Rng};
+use transaction::{Address, SignedTx};
+use Error;
+
+/// The size limit of a filter in bytes, as in Bitcoin's BIP37.
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+/// The limit of hash functions of a filter, as in Bitcoin's BIP37.
+pub const MAX_HASH_FUNCTIONS: u32 = 50;
+
+/// A probabilistic set: it may contain elements that were never inserted, at the false positive
+/// rate it was sized for, but always contains the ones that were. Light clients send one to
+/// the full nodes so that they only relay the transactions that may concern them, without
+/// telling which addresses are theirs.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    hash_functions: u32,
+    /// Changes the bits an element sets from one filter to the other.
+    tweak: u32,
+}
+
+impl BloomFilter {
+    /// An empty filter sized for the given number of elements, and the false positive rate
+    /// expected once they are all inserted.
+    pub fn new(elements: usize, false_positive_rate: f64) -> Result<BloomFilter, Error> {
+        if elements == 0 || !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+            return Err(Error::InvalidBloomFilter);
+        }
+
+        let ln_2 = ::std::f64::consts::LN_2;
+        let bit_count = -(elements as f64) * false_positive_rate.ln() / (ln_2 * ln_2);
+        let size = ((bit_count / 8.0).ceil() as usize).clamp(1, MAX_BLOOM_FILTER_SIZE);
+        let hash_functions = ((size * 8) as f64 / elements as f64 * ln_2).round() as u32;
+
+        Ok(BloomFilter {
+            bits: vec![0; size],
+            hash_functions: hash_functions.clamp(1, MAX_HASH_FUNCTIONS),
+            tweak: rand::thread_rng().gen(),
+        })
+    }
+
+    pub fn insert(&mut self, data: &[u8]) {
+        let indexes: Vec<usize> = self.bit_indexes(data).collect();
+        for index in indexes {
+            self.bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    pub fn insert_address(&mut self, address: &Address) {
+        self.insert(address.as_hash().as_ref());
+    }
+
+    pub fn contains(&self, data: &[u8]) -> bool {
+        self.bit_indexes(data)
+            .all(|index| self.bits[index / 8] & (1 << (index % 8)) != 0)
+    }
+
+    /// Whether the transaction, an address it pays or a transaction it spends is in the filter.
+    pub fn matches(&self, transaction: &SignedTx) -> bool {
+        transaction.hash().is_ok_and(|hash| self.contains(hash.as_ref()))
+            || transaction
+                .output()
+                .iter()
+                .any(|tx_out| self.contains(tx_out.address().as_hash().as_ref()))
+            || transaction
+                .input()
+                .iter()
+                .any(|tx_in| self.contains(tx_in.prev_tx_hash().as_ref()))
+    }
+
+    /// The size of the filter sent to a peer, in bytes.
+    pub fn size(&self) -> usize {
+        self.bits.len()
+    }
+
+    pub fn hash_functions(&self) -> u32 {
+        self.hash_functions
+    }
+
+    /// The bits set by the element: a single hash gives two values, combined into as many
+    /// indexes as there are hash functions, as Kirsch and Mitzenmacher suggest.
+    fn bit_indexes<'a>(&'a self, data: &[u8]) -> impl Iterator<Item = usize> + 'a {
+        let mut input = data.to_vec();
+        input.extend_from_slice(&self.tweak.to_le_bytes());
+        let digest = hash(&input);
+        let mut halves = [0u8; 8];
+        halves.copy_from_slice(&digest.as_ref()[..8]);
+        let first = u64::from_le_bytes(halves);
+        halves.copy_from_slice(&digest.as_ref()[8..16]);
+        let second = u64::from_le_bytes(halves);
+        let bit_count = (self.bits.len() * 8) as u64;
+
+        (0..self.hash_functions as u64)
+            .map(move |i| (first.wrapping_add(i.wrapping_mul(second)) % bit_count) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_the_filter_for_the_false_positive_rate() {
+        assert_eq!(Some(Error::InvalidBloomFilter), BloomFilter::new(0, 0.01).err());
+        assert_eq!(Some(Error::InvalidBloomFilter), BloomFilter::new(10, 0.0).err());
+        assert_eq!(Some(Error::InvalidBloomFilter), BloomFilter::new(10, 1.0).err());
+
+        let elements = 1000;
+        let false_positive_rate = 0.01;
+        let mut filter = BloomFilter::new(elements, false_positive_rate).unwrap();
+        assert_eq!(1199, filter.size());
+        assert_eq!(7, filter.hash_functions());
+
+        for i in 0..elements {
+            filter.insert(format!("Inserted {}", i).as_bytes());
+        }
+        for i in 0..elements {
+            assert!(filter.contains(format!("Inserted {}", i).as_bytes()));
+        }
+
+        let trials = 10_000;
+        let false_positives = (0..trials)
+            .filter(|i| filter.contains(format!("Never inserted {}", i).as_bytes()))
+            .count();
+        assert!((false_positives as f64 / trials as f64) < 2.0 * false_positive_rate);
+
+        assert_eq!(MAX_BLOOM_FILTER_SIZE, BloomFilter::new(1_000_000, 0.0001).unwrap().size());
+    }
+}
@@ -0,0 +1,143 @@
+use blockchain::{Difficulty, COINBASE_AMOUNT, COINBASE_MATURITY, HALVING_INTERVAL};
+use std::time::Duration;
+
+/// The default maximum size of a serialized block body, in bytes.
+pub const MAX_BLOCK_SIZE: usize = 1_000_000;
+
+/// The rules and the constants of a network: the nodes of a network only accept the blocks and
+/// the transactions following its rules, starting from a genesis block mined with its initial
+/// difficulty.
+///
+/// `main` is the network of the simulations, `testnet` a harder one with a shorter coinbase
+/// maturity, and `regtest` a network for tests, where any difficulty goes and the coinbase
+/// outputs can be spent from the next block on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainParams {
+    name: &'static str,
+    /// The bytes identifying the network, which the messages and the files of a node start with.
+    magic: [u8; 4],
+    coinbase_amount: u32,
+    halving_interval: u32,
+    coinbase_maturity: u32,
+    max_block_size: usize,
+    /// The easiest difficulty a block can be mined with.
+    pow_limit: u32,
+    genesis_difficulty: u32,
+    target_block_interval: Duration,
+}
+
+impl ChainParams {
+    pub fn main() -> ChainParams {
+        ChainParams {
+            name: "main",
+            magic: [0xf9, 0xbe, 0xb4, 0xd9],
+            coinbase_amount: COINBASE_AMOUNT,
+            halving_interval: HALVING_INTERVAL,
+            coinbase_maturity: COINBASE_MATURITY,
+            max_block_size: MAX_BLOCK_SIZE,
+            pow_limit: 0x2100ffff,
+            genesis_difficulty: 0x200fff00,
+            target_block_interval: Duration::from_secs(10),
+        }
+    }
+
+    pub fn testnet() -> ChainParams {
+        ChainParams {
+            name: "testnet",
+            magic: [0x0b, 0x11, 0x09, 0x07],
+            coinbase_maturity: 5,
+            pow_limit: 0x200fff00,
+            genesis_difficulty: 0x2001ff00,
+            target_block_interval: Duration::from_secs(5),
+            ..ChainParams::main()
+        }
+    }
+
+    pub fn regtest() -> ChainParams {
+        ChainParams {
+            name: "regtest",
+            magic: [0xfa, 0xbf, 0xb5, 0xda],
+            halving_interval: 150,
+            coinbase_maturity: 1,
+            genesis_difficulty: 0x2100ffff,
+            target_block_interval: Duration::from_secs(1),
+            ..ChainParams::main()
+        }
+    }
+
+    /// The preset of the given name, if any.
+    pub fn by_name(name: &str) -> Option<ChainParams> {
+        match name {
+            "main" => Some(ChainParams::main()),
+            "testnet" => Some(ChainParams::testnet()),
+            "regtest" => Some(ChainParams::regtest()),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn magic(&self) -> &[u8; 4] {
+        &self.magic
+    }
+
+    /// The amount a coinbase transaction can create at the given height, fees excluded.
+    /// It is halved every `halving_interval` blocks, until it reaches zero.
+    pub fn block_reward(&self, height: u32) -> u32 {
+        self.coinbase_amount.checked_shr(height / self.halving_interval.max(1)).unwrap_or(0)
+    }
+
+    /// The number of blocks a coinbase output must wait for before being spent, counting the
+    /// one including it: an output created at height `h` can be spent from height
+    /// `h + coinbase_maturity`.
+    pub fn coinbase_maturity(&self) -> u32 {
+        self.coinbase_maturity
+    }
+
+    /// The maximum size of a serialized block body, coinbase included, in bytes.
+    pub fn max_block_size(&self) -> usize {
+        self.max_block_size
+    }
+
+    pub fn pow_limit(&self) -> Difficulty {
+        Difficulty::from_compact(self.pow_limit).expect("The compact bits of a preset are valid")
+    }
+
+    pub fn genesis_difficulty(&self) -> Difficulty {
+        Difficulty::from_compact(self.genesis_difficulty).expect("The compact bits of a preset are valid")
+    }
+
+    /// The interval between two blocks the difficulty of the network is meant for.
+    pub fn target_block_interval(&self) -> Duration {
+        self.target_block_interval
+    }
+}
+
+impl Default for ChainParams {
+    fn default() -> Self {
+        ChainParams::main()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_have_valid_difficulties() {
+        for name in &["main", "testnet", "regtest"] {
+            let params = ChainParams::by_name(name).unwrap();
+            assert_eq!(*name, params.name());
+            assert!(params.genesis_difficulty().threshold() <= params.pow_limit().threshold());
+        }
+        assert_eq!(Difficulty::min_difficulty().to_compact(), ChainParams::main().pow_limit().to_compact());
+        assert!(ChainParams::by_name("mainnet").is_none());
+
+        let regtest = ChainParams::regtest();
+        assert_eq!(COINBASE_AMOUNT, regtest.block_reward(149));
+        assert_eq!(COINBASE_AMOUNT / 2, regtest.block_reward(150));
+        assert_eq!(0, regtest.block_reward(u32::MAX));
+    }
+}
@@ -0,0 +1,463 @@
+use bincode;
+use blockchain::{Block, Body};
+use chain_params::ChainParams;
+use crypto::Hash;
+use mempool;
+use wallet;
+use Error;
+use std::collections::HashMap;
+use u256::U256;
+use utxo::UtxoSet;
+
+/// A block known to the store, with the total work of the branch it is the tip of.
+struct StoredBlock {
+    block: Block,
+    cumulative_work: U256,
+    fees: BlockFees,
+}
+
+/// The fees paid by the transactions of a block, used to estimate the fees of new ones.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BlockFees {
+    fees: u32,
+    size: usize,
+    transaction_count: usize,
+}
+
+impl BlockFees {
+    /// The fees of a valid block are what its coinbase collects above the block reward of
+    /// its network.
+    pub fn new(body: &Body, height: u32, params: &ChainParams) -> Result<BlockFees, Error> {
+        let mut size = 0;
+        for transaction in body.transactions() {
+            size += bincode::serialize(transaction)?.len();
+        }
+
+        Ok(BlockFees {
+            fees: body.coinbase_tx().0.amount().saturating_sub(params.block_reward(height)),
+            size,
+            transaction_count: body.transactions().len(),
+        })
+    }
+
+    pub fn fees(&self) -> &u32 {
+        &self.fees
+    }
+
+    /// The size of the serialized transactions of the block, coinbase excluded.
+    pub fn size(&self) -> &usize {
+        &self.size
+    }
+
+    pub fn transaction_count(&self) -> &usize {
+        &self.transaction_count
+    }
+
+    /// The average fees per kilobyte of the transactions of the block.
+    pub fn fee_rate(&self) -> u64 {
+        mempool::fee_rate(self.fees, self.size)
+    }
+}
+
+/// The blocks to disconnect then to connect to move a UTXO set from the old best tip
+/// to the new one.
+pub struct Reorg {
+    /// From the old tip down to the first block after the fork point.
+    disconnect: Vec<Hash>,
+    /// From the first block after the fork point up to the new tip.
+    connect: Vec<Hash>,
+}
+
+impl Reorg {
+    pub fn disconnect(&self) -> &[Hash] {
+        &self.disconnect
+    }
+
+    pub fn connect(&self) -> &[Hash] {
+        &self.connect
+    }
+
+    /// A reorganization that only extends the best branch does not disconnect any block.
+    pub fn is_extension(&self) -> bool {
+        self.disconnect.is_empty()
+    }
+}
+
+/// Tracks every branch grown from a genesis block and selects the best one, which is the
+/// branch with the most cumulative work. On equal work, the branch seen first is kept.
+///
+/// The store only checks the headers: the transactions must be verified against the UTXO
+/// set when the blocks are connected.
+pub struct ChainStore {
+    blocks: HashMap<Hash, StoredBlock>,
+    genesis_hash: Hash,
+    best_tip: Hash,
+    params: ChainParams,
+}
+
+impl ChainStore {
+    /// Tracks the branches of the given genesis block, following the rules of the given network.
+    pub fn new(genesis: Block, params: ChainParams) -> Result<ChainStore, Error> {
+        if *genesis.header().height() != 0 {
+            return Err(Error::InvalidGenesis);
+        }
+
+        genesis.verify(&UtxoSet::new(), &params)?;
+
+        let genesis_hash = genesis.header().hash().clone();
+        let cumulative_work = genesis.header().difficulty().work();
+        let fees = BlockFees::new(genesis.body(), 0, &params)?;
+
+        let mut blocks = HashMap::new();
+        blocks.insert(genesis_hash.clone(), StoredBlock {
+            block: genesis,
+            cumulative_work,
+            fees,
+        });
+
+        Ok(ChainStore {
+            blocks,
+            genesis_hash: genesis_hash.clone(),
+            best_tip: genesis_hash,
+            params,
+        })
+    }
+
+    /// Adds a block on top of a known one. Returns the reorganization to apply if the block
+    /// makes a new best branch, `None` otherwise.
+    pub fn add_block(&mut self, block: Block) -> Result<Option<Reorg>, Error> {
+        let hash = block.header().hash().clone();
+
+        if self.blocks.contains_key(&hash) {
+            return Err(Error::DuplicateBlock);
+        }
+
+        let cumulative_work = {
+            let parent = self.blocks.get(block.header().previous_block_hash())
+                .ok_or(Error::UnknownParent)?;
+            let parent_header = parent.block.header();
+
+            if parent_header.difficulty() != block.header().difficulty() {
+                return Err(Error::InvalidDifficulty);
+            }
+
+            if parent_header.height() + 1 != *block.header().height() {
+                return Err(Error::InvalidHeight);
+            }
+
+            block.verify_header(&self.params)?;
+
+            parent.cumulative_work.saturating_add(&block.header().difficulty().work())
+        };
+        let fees = BlockFees::new(block.body(), *block.header().height(), &self.params)?;
+
+        self.blocks.insert(hash.clone(), StoredBlock {
+            block,
+            cumulative_work,
+            fees,
+        });
+
+        if cumulative_work > self.stored(&self.best_tip).cumulative_work {
+            let reorg = self.reorg(&self.best_tip, &hash);
+            self.best_tip = hash;
+            Ok(Some(reorg))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Computes the blocks to disconnect and connect to move from one known block to another.
+    /// Both hashes must be part of the store.
+    pub fn reorg(&self, from: &Hash, to: &Hash) -> Reorg {
+        let mut disconnect = vec![];
+        let mut connect = vec![];
+
+        let mut from = self.stored(from);
+        let mut to = self.stored(to);
+
+        while from.block.header().height() > to.block.header().height() {
+            disconnect.push(from.block.header().hash().clone());
+            from = self.parent(from);
+        }
+
+        while to.block.header().height() > from.block.header().height() {
+            connect.push(to.block.header().hash().clone());
+            to = self.parent(to);
+        }
+
+        while from.block.header().hash() != to.block.header().hash() {
+            disconnect.push(from.block.header().hash().clone());
+            from = self.parent(from);
+            connect.push(to.block.header().hash().clone());
+            to = self.parent(to);
+        }
+
+        connect.reverse();
+
+        Reorg {
+            disconnect,
+            connect,
+        }
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&Block> {
+        self.blocks.get(hash).map(|stored| &stored.block)
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    pub fn genesis_hash(&self) -> &Hash {
+        &self.genesis_hash
+    }
+
+    pub fn best_tip(&self) -> &Block {
+        &self.stored(&self.best_tip).block
+    }
+
+    /// The cumulative work of the branch ending with the given block.
+    pub fn cumulative_work(&self, hash: &Hash) -> Option<U256> {
+        self.blocks.get(hash).map(|stored| stored.cumulative_work)
+    }
+
+    /// The fees paid by the transactions of the given block.
+    pub fn fees(&self, hash: &Hash) -> Option<&BlockFees> {
+        self.blocks.get(hash).map(|stored| &stored.fees)
+    }
+
+    /// The number of blocks of every branch, including the genesis one.
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    fn stored(&self, hash: &Hash) -> &StoredBlock {
+        self.blocks.get(hash).expect("Unknown block")
+    }
+
+    /// Only blocks with a known parent are stored, so every block but the genesis has one.
+    fn parent(&self, stored: &StoredBlock) -> &StoredBlock {
+        self.stored(stored.block.header().previous_block_hash())
+    }
+}
+
+impl wallet::FeeHistory for ChainStore {
+    fn recent_block_fees(&self, count: usize) -> Vec<BlockFees> {
+        let mut recent_block_fees = vec![];
+        let mut stored = self.stored(&self.best_tip);
+
+        while recent_block_fees.len() < count {
+            recent_block_fees.push(stored.fees.clone());
+
+            if *stored.block.header().height() == 0 {
+                break;
+            }
+            stored = self.parent(stored);
+        }
+
+        recent_block_fees
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::{Body, Difficulty, Header, Nonce, COINBASE_AMOUNT, COINBASE_MATURITY};
+    use transaction::{SignedTx, TxOut, UtxoStore};
+    use utxo::BlockUndo;
+    use wallet::{FeeHistory, Wallet, FEE_ESTIMATION_BLOCKS};
+
+    #[test]
+    fn extends_the_best_branch() {
+        let mut wallet = Wallet::new();
+        let mut store = ChainStore::new(mine_block(&mut wallet, None, vec![]), ChainParams::main()).unwrap();
+        let genesis_hash = store.genesis_hash().clone();
+
+        let block = mine_block(&mut wallet, store.get(&genesis_hash), vec![]);
+        let block_hash = block.header().hash().clone();
+        let reorg = store.add_block(block).unwrap().unwrap();
+
+        assert!(reorg.is_extension());
+        assert!(&block_hash == reorg.connect().first().unwrap());
+        assert!(&block_hash == store.best_tip().header().hash());
+        assert_eq!(2, store.len());
+    }
+
+    #[test]
+    fn rejects_invalid_blocks() {
+        let mut wallet = Wallet::new();
+        let genesis = mine_block(&mut wallet, None, vec![]);
+        let unknown_block = mine_block(&mut wallet, Some(&genesis), vec![]);
+        let mut store = ChainStore::new(genesis, ChainParams::main()).unwrap();
+        let genesis_hash = store.genesis_hash().clone();
+
+        let block = mine_block(&mut wallet, store.get(&genesis_hash), vec![]);
+        let orphan = mine_block(&mut wallet, Some(&unknown_block), vec![]);
+        assert_eq!(Error::UnknownParent, store.add_block(orphan).err().unwrap());
+
+        let same_block = Block::new(
+            header(block.body(), store.get(&genesis_hash)),
+            block.body().clone(),
+        );
+        store.add_block(block).unwrap();
+        assert_eq!(Error::DuplicateBlock, store.add_block(same_block).err().unwrap());
+    }
+
+    #[test]
+    fn keeps_the_first_branch_on_equal_work() {
+        let mut wallet = Wallet::new();
+        let mut store = ChainStore::new(mine_block(&mut wallet, None, vec![]), ChainParams::main()).unwrap();
+        let genesis_hash = store.genesis_hash().clone();
+
+        let first = mine_block(&mut wallet, store.get(&genesis_hash), vec![]);
+        let first_hash = first.header().hash().clone();
+        let second = mine_block(&mut wallet, store.get(&genesis_hash), vec![]);
+        store.add_block(first).unwrap();
+
+        assert!(store.add_block(second).unwrap().is_none());
+        assert!(&first_hash == store.best_tip().header().hash());
+        assert_eq!(3, store.len());
+    }
+
+    #[test]
+    fn tracks_the_fees_of_the_best_branch() {
+        let mut wallet = Wallet::new();
+        let genesis = mine_block(&mut wallet, None, vec![]);
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.apply(genesis.body(), 0).unwrap();
+        wallet.connect_block(genesis.body(), 0).unwrap();
+        let mut store = ChainStore::new(genesis, ChainParams::main()).unwrap();
+        let genesis_hash = store.genesis_hash().clone();
+
+        // The store does not verify the transactions, so the coinbase can be spent right away.
+        let to_address = wallet.new_address().unwrap();
+        let transaction = wallet.new_transaction(100, to_address, 10, &utxo_set).unwrap();
+        let size = bincode::serialize(&transaction).unwrap().len();
+        let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT + 10, wallet.new_address().unwrap());
+        let body = Body::new(coinbase_tx_out, vec![transaction]);
+        let block = Block::new(header(&body, store.get(&genesis_hash)), body);
+        let block_hash = block.header().hash().clone();
+        store.add_block(block).unwrap();
+
+        let fees = store.fees(&block_hash).unwrap().clone();
+        assert_eq!(&10, fees.fees());
+        assert_eq!(&size, fees.size());
+        assert_eq!(&1, fees.transaction_count());
+        assert_eq!(10 * 1000 / size as u64, fees.fee_rate());
+
+        let recent_block_fees = store.recent_block_fees(FEE_ESTIMATION_BLOCKS);
+        assert_eq!(vec![fees, store.fees(&genesis_hash).unwrap().clone()], recent_block_fees);
+        assert_eq!(0, recent_block_fees[1].fee_rate());
+    }
+
+    #[test]
+    fn moves_the_utxo_set_to_the_best_branch() {
+        let mut wallet = Wallet::new();
+        let genesis = mine_block(&mut wallet, None, vec![]);
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.apply(genesis.body(), 0).unwrap();
+        wallet.connect_block(genesis.body(), 0).unwrap();
+        let mut store = ChainStore::new(genesis, ChainParams::main()).unwrap();
+        let mut undos = HashMap::new();
+
+        // Both branches fork once the genesis coinbase is mature.
+        let mut fork_hash = store.genesis_hash().clone();
+        for _height in 1..COINBASE_MATURITY {
+            let block = mine_block(&mut wallet, store.get(&fork_hash), vec![]);
+            fork_hash = block.header().hash().clone();
+            let reorg = store.add_block(block).unwrap().unwrap();
+            apply_reorg(&store, &reorg, &mut utxo_set, &mut undos).unwrap();
+        }
+        let fork_utxo_set = utxo_set.clone();
+
+        // The first branch spends the genesis coinbase.
+        let to_address = wallet.new_address().unwrap();
+        let first_transaction = wallet.new_transaction(100, to_address, 0, &utxo_set).unwrap();
+        let first_block = mine_block(&mut wallet, store.get(&fork_hash), vec![first_transaction.clone()]);
+        let first_hash = first_block.header().hash().clone();
+        let reorg = store.add_block(first_block).unwrap().unwrap();
+        apply_reorg(&store, &reorg, &mut utxo_set, &mut undos).unwrap();
+        assert!(utxo_set.find(&first_transaction.hash().unwrap(), &0).is_some());
+
+        // A longer competing branch spends the same coinbase differently.
+        let to_address = wallet.new_address().unwrap();
+        let other_transaction = wallet.new_transaction(200, to_address, 0, &fork_utxo_set).unwrap();
+        let other_block = mine_block(&mut wallet, store.get(&fork_hash), vec![other_transaction.clone()]);
+        let other_hash = other_block.header().hash().clone();
+        assert!(store.add_block(other_block).unwrap().is_none());
+
+        let last_block = mine_block(&mut wallet, store.get(&other_hash), vec![]);
+        let reorg = store.add_block(last_block).unwrap().unwrap();
+        assert!(&first_hash == reorg.disconnect().first().unwrap());
+        assert_eq!(1, reorg.disconnect().len());
+        assert!(&other_hash == reorg.connect().first().unwrap());
+        assert_eq!(2, reorg.connect().len());
+
+        apply_reorg(&store, &reorg, &mut utxo_set, &mut undos).unwrap();
+        assert!(utxo_set.find(&first_transaction.hash().unwrap(), &0).is_none());
+        assert!(utxo_set.find(&other_transaction.hash().unwrap(), &0).is_some());
+        assert_eq!(&(COINBASE_MATURITY + 1), store.best_tip().header().height());
+
+        // Moving back to the first branch restores its outputs.
+        let reorg = store.reorg(store.best_tip().header().hash(), &first_hash);
+        apply_reorg(&store, &reorg, &mut utxo_set, &mut undos).unwrap();
+        assert!(utxo_set.find(&first_transaction.hash().unwrap(), &0).is_some());
+        assert!(utxo_set.find(&other_transaction.hash().unwrap(), &0).is_none());
+    }
+
+    fn apply_reorg(
+        store: &ChainStore,
+        reorg: &Reorg,
+        utxo_set: &mut UtxoSet,
+        undos: &mut HashMap<Hash, BlockUndo>,
+    ) -> Result<(), Error> {
+        for hash in reorg.disconnect() {
+            utxo_set.rollback(&undos.remove(hash).unwrap())?;
+        }
+
+        for hash in reorg.connect() {
+            let block = store.get(hash).unwrap();
+            let height = *block.header().height();
+            block.body().verify(utxo_set, height, &ChainParams::main())?;
+            undos.insert(hash.clone(), utxo_set.apply(block.body(), height)?);
+        }
+
+        Ok(())
+    }
+
+    /// Mines a block paying the coinbase to a new address of the wallet.
+    fn mine_block(wallet: &mut Wallet, parent: Option<&Block>, transactions: Vec<SignedTx>) -> Block {
+        let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT, wallet.new_address().unwrap());
+        let body = Body::new(coinbase_tx_out, transactions);
+        let header = header(&body, parent);
+        Block::new(header, body)
+    }
+
+    fn header(body: &Body, parent: Option<&Block>) -> Header {
+        let (previous_block_hash, height) = match parent {
+            Some(parent) => (parent.header().hash().clone(), parent.header().height() + 1),
+            None => (Hash::min(), 0),
+        };
+
+        let mut difficulty = Difficulty::min_difficulty();
+        difficulty.increase().ok().unwrap();
+
+        let mut header = Header::new(
+            Nonce::new(),
+            difficulty,
+            previous_block_hash,
+            height,
+            body.merkle_root().unwrap(),
+        ).unwrap();
+
+        while header.verify().is_err() {
+            header.increment_nonce().unwrap();
+        }
+
+        header
+    }
+}
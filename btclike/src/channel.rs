@@ -0,0 +1,358 @@
+use crypto::Hash;
+use crypto::PubKey;
+use crypto::Signature;
+use crypto::SignatureAlgorithm;
+use script::Script;
+use transaction::Address;
+use transaction::PartiallySignedTx;
+use transaction::RawTx;
+use transaction::RawTxIn;
+use transaction::SignedTx;
+use transaction::TxOut;
+use wallet::Wallet;
+use Error;
+
+/// What both sides of a payment channel agree on before it is funded.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ChannelTerms {
+    payer: Address,
+    payee: Address,
+    expiry_height: u32,
+}
+
+impl ChannelTerms {
+    /// Both must be public key addresses.
+    pub fn new(payer: Address, payee: Address, expiry_height: u32) -> ChannelTerms {
+        ChannelTerms {
+            payer,
+            payee,
+            expiry_height,
+        }
+    }
+
+    pub fn payer(&self) -> &Address {
+        &self.payer
+    }
+
+    pub fn payee(&self) -> &Address {
+        &self.payee
+    }
+
+    /// The height of the first block the payer can take the funding output back in, alone.
+    pub fn expiry_height(&self) -> u32 {
+        self.expiry_height
+    }
+
+    pub fn script(&self) -> Script {
+        Script::payment_channel(&self.payer, &self.payee, self.expiry_height)
+    }
+
+    /// The address the funding transaction pays to, the hash of the script.
+    pub fn address(&self) -> Address {
+        Address::from_script(&self.script())
+    }
+}
+
+/// A new balance of a payment channel, sent by the payer to the payee over their direct
+/// connection instead of being published: the signature of the payer on the transaction
+/// closing the channel with this balance.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChannelUpdate {
+    /// Increases with every update.
+    sequence: u32,
+    /// The total paid to the payee since the channel was funded.
+    paid: u32,
+    pub_key: PubKey,
+    signature: Signature,
+}
+
+impl ChannelUpdate {
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    pub fn paid(&self) -> u32 {
+        self.paid
+    }
+}
+
+/// A payment channel from a payer to a payee, as seen by either side.
+///
+/// The payer locks coins in a funding output both sides must sign to spend, or the payer
+/// alone once the channel expired. It then pays the payee off the chain: each update signs a
+/// transaction closing the channel, which pays a larger share of the output to the payee and
+/// the rest back to the payer. The payee closes the channel by adding its own signature to
+/// the last update, and must do so before the expiry, when the payer could take everything
+/// back. The payments only flow from the payer to the payee, so the payee has no reason to
+/// publish an older update, and the payer cannot publish any without the payee.
+pub struct PaymentChannel {
+    terms: ChannelTerms,
+    funding_tx_hash: Hash,
+    funding_output_index: u8,
+    /// The funding output, which the signatures of the closing transactions commit to.
+    funding_output: TxOut,
+    capacity: u32,
+    /// The fees of the transactions closing the channel, paid by the payer.
+    fees: u32,
+    signature_algorithm: SignatureAlgorithm,
+    sequence: u32,
+    paid: u32,
+    /// The signature of the payer on the last update, kept by the payee.
+    payer_signature: Option<(PubKey, Signature)>,
+}
+
+impl PaymentChannel {
+    /// The channel funded by the given transaction, once it is confirmed. Its closing
+    /// transactions pay the given fees. Fails unless the transaction pays more than the fees
+    /// to the address of the terms.
+    pub fn open(terms: ChannelTerms, funding_tx: &SignedTx, fees: u32) -> Result<PaymentChannel, Error> {
+        let address = terms.address();
+        let (funding_output_index, funding_output) = funding_tx.output().iter()
+            .enumerate()
+            .find(|(_index, tx_out)| tx_out.address() == address)
+            .ok_or(Error::UtxoNotFound)?;
+        if *funding_output.amount() <= fees {
+            return Err(Error::NotEnoughTokens);
+        }
+
+        Ok(PaymentChannel {
+            terms,
+            funding_tx_hash: funding_tx.hash()?,
+            funding_output_index: funding_output_index as u8,
+            funding_output: funding_output.clone(),
+            capacity: *funding_output.amount(),
+            fees,
+            signature_algorithm: SignatureAlgorithm::Ed25519,
+            sequence: 0,
+            paid: 0,
+            payer_signature: None,
+        })
+    }
+
+    /// The scheme the payer signs the updates with. Default: Ed25519
+    pub fn with_signature_algorithm(mut self, signature_algorithm: SignatureAlgorithm) -> PaymentChannel {
+        self.signature_algorithm = signature_algorithm;
+        self
+    }
+
+    pub fn terms(&self) -> &ChannelTerms {
+        &self.terms
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// The total paid to the payee so far.
+    pub fn paid(&self) -> u32 {
+        self.paid
+    }
+
+    /// What the payer can still pay: the capacity, less the fees and what it already paid.
+    pub fn available(&self) -> u32 {
+        self.capacity - self.fees - self.paid
+    }
+
+    /// Pays the given amount on the payer side, signing the new balance with the key of the
+    /// payer in the wallet. The update is then sent to the payee.
+    pub fn pay(&mut self, amount: u32, wallet: &Wallet) -> Result<ChannelUpdate, Error> {
+        if amount > self.available() {
+            return Err(Error::NotEnoughTokens);
+        }
+
+        let paid = self.paid + amount;
+        let mut transaction = self.closing_transaction(paid)?;
+        wallet.sign_input(&mut transaction, 0, &self.terms.payer)?;
+        let (pub_key, signature) = transaction.signatures(0)[0].clone();
+
+        self.sequence += 1;
+        self.paid = paid;
+        Ok(ChannelUpdate {
+            sequence: self.sequence,
+            paid,
+            pub_key,
+            signature,
+        })
+    }
+
+    /// Accepts an update on the payee side, returning the amount it pays. The update must
+    /// follow the last one, pay at least as much, and the payer must have signed it.
+    pub fn receive(&mut self, update: &ChannelUpdate) -> Result<u32, Error> {
+        if update.sequence <= self.sequence
+            || update.paid < self.paid
+            || update.paid > self.capacity - self.fees
+            || Address::from_pub_key(&update.pub_key) != self.terms.payer {
+            return Err(Error::InvalidChannelUpdate);
+        }
+
+        let mut transaction = self.closing_transaction(update.paid)?;
+        transaction
+            .add_signature(0, update.pub_key.clone(), update.signature.clone(), self.signature_algorithm)
+            .map_err(|_err| Error::InvalidChannelUpdate)?;
+
+        let amount = update.paid - self.paid;
+        self.sequence = update.sequence;
+        self.paid = update.paid;
+        self.payer_signature = Some((update.pub_key.clone(), update.signature.clone()));
+        Ok(amount)
+    }
+
+    /// The transaction closing the channel with the current balance, for both sides to sign
+    /// it with `Wallet::sign_input`, on its only input. Either side can then publish it, the
+    /// payer included, before the expiry.
+    pub fn cooperative_close(&self) -> Result<PartiallySignedTx, Error> {
+        self.closing_transaction(self.paid)
+    }
+
+    /// Closes the channel on the payee side alone, with the last update and the key of the
+    /// payee in the wallet. Fails if no update was received.
+    pub fn close(&self, wallet: &Wallet) -> Result<SignedTx, Error> {
+        let (pub_key, signature) = self.payer_signature.clone().ok_or(Error::InvalidChannelUpdate)?;
+        let mut transaction = self.closing_transaction(self.paid)?;
+        transaction.add_signature(0, pub_key, signature, self.signature_algorithm)?;
+        wallet.sign_input(&mut transaction, 0, &self.terms.payee)?;
+        Ok(transaction.into_signed_tx())
+    }
+
+    /// Takes the whole funding output back on the payer side alone, with the key of the payer
+    /// in the wallet: the payee did not close the channel in time. The transaction is valid
+    /// from the expiry height on.
+    pub fn refund(&self, wallet: &Wallet) -> Result<SignedTx, Error> {
+        let raw_tx = RawTx {
+            input: vec![self.funding_input()],
+            output: vec![TxOut::new(self.capacity - self.fees, self.terms.payer.clone())],
+        };
+
+        let mut transaction = PartiallySignedTx::new(raw_tx, &[&self.funding_output])?;
+        transaction.set_condition(0, false);
+        transaction.set_redeem_script(0, self.terms.script());
+        wallet.sign_input(&mut transaction, 0, &self.terms.payer)?;
+        Ok(transaction.into_signed_tx())
+    }
+
+    /// Pays the given total to the payee and the rest back to the payer, leaving out the
+    /// outputs of nothing.
+    fn closing_transaction(&self, paid: u32) -> Result<PartiallySignedTx, Error> {
+        let mut output = vec![];
+        if paid > 0 {
+            output.push(TxOut::new(paid, self.terms.payee.clone()));
+        }
+        let change = self.capacity - self.fees - paid;
+        if change > 0 {
+            output.push(TxOut::new(change, self.terms.payer.clone()));
+        }
+
+        let raw_tx = RawTx {
+            input: vec![self.funding_input()],
+            output,
+        };
+        let mut transaction = PartiallySignedTx::new(raw_tx, &[&self.funding_output])?;
+        transaction.set_condition(0, true);
+        transaction.set_redeem_script(0, self.terms.script());
+        Ok(transaction)
+    }
+
+    fn funding_input(&self) -> RawTxIn {
+        RawTxIn {
+            prev_tx_hash: self.funding_tx_hash.clone(),
+            prev_tx_output_index: self.funding_output_index,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bincode;
+    use chain_params::ChainParams;
+    use swap::tests::TestChain;
+    use wallet;
+
+    /// A channel of 600 funded by the payer, with both sides of it.
+    fn funded_channel(expiry: u32) -> (Wallet, PaymentChannel, Wallet, PaymentChannel, TestChain) {
+        let mut payer = Wallet::new();
+        let mut payee = Wallet::new();
+        let mut chain = TestChain::new(ChainParams::main(), &mut payer);
+
+        let expiry_height = chain.height + expiry;
+        let terms = ChannelTerms::new(payer.new_address().unwrap(), payee.new_address().unwrap(), expiry_height);
+        let funding_tx = payer.new_transaction(600, terms.address(), 10, &chain.utxo_set).unwrap();
+        chain.mine(vec![funding_tx.clone()]).unwrap();
+
+        let payer_channel = PaymentChannel::open(terms.clone(), &funding_tx, 10).unwrap();
+        let payee_channel = PaymentChannel::open(terms, &funding_tx, 10).unwrap();
+        (payer, payer_channel, payee, payee_channel, chain)
+    }
+
+    /// Sends the update over the connection of both sides, as bytes.
+    fn send(update: &ChannelUpdate) -> ChannelUpdate {
+        bincode::deserialize(&bincode::serialize(update).unwrap()).unwrap()
+    }
+
+    fn received(chain: &TestChain, address: &Address) -> u32 {
+        wallet::UtxoStore::find_for_address(&chain.utxo_set, address).iter()
+            .map(|output| *output.amount())
+            .sum()
+    }
+
+    #[test]
+    fn pays_off_the_chain_then_closes_with_the_last_update() {
+        let (payer, mut payer_channel, payee, mut payee_channel, mut chain) = funded_channel(100);
+
+        let mut updates = vec![];
+        for amount in [100, 50, 200] {
+            let update = send(&payer_channel.pay(amount, &payer).unwrap());
+            assert_eq!(amount, payee_channel.receive(&update).unwrap());
+            updates.push(update);
+        }
+        assert_eq!(350, payee_channel.paid());
+        assert_eq!(240, payer_channel.available());
+        assert_eq!(Error::NotEnoughTokens, payer_channel.pay(241, &payer).err().unwrap());
+
+        // The payee takes neither an older update nor one it was not signed for.
+        assert_eq!(Error::InvalidChannelUpdate, payee_channel.receive(&updates[1]).err().unwrap());
+        let mut forged = updates[2].clone();
+        forged.sequence = 4;
+        forged.paid = 500;
+        assert_eq!(Error::InvalidChannelUpdate, payee_channel.receive(&forged).err().unwrap());
+
+        // Nothing was published until the payee closes the channel.
+        chain.mine(vec![payee_channel.close(&payee).unwrap()]).unwrap();
+        assert_eq!(350, received(&chain, payee_channel.terms().payee()));
+        assert_eq!(240, received(&chain, payee_channel.terms().payer()));
+    }
+
+    #[test]
+    fn closes_cooperatively() {
+        let (payer, mut payer_channel, payee, mut payee_channel, mut chain) = funded_channel(100);
+        let update = send(&payer_channel.pay(120, &payer).unwrap());
+        payee_channel.receive(&update).unwrap();
+
+        // The balance of the payer side takes both signatures.
+        let mut transaction = payer_channel.cooperative_close().unwrap();
+        payee.sign_input(&mut transaction, 0, payee_channel.terms().payee()).unwrap();
+        let mut payee_only = payer_channel.cooperative_close().unwrap();
+        payee.sign_input(&mut payee_only, 0, payee_channel.terms().payee()).unwrap();
+        assert_eq!(Error::InvalidScript, chain.mine(vec![payee_only.into_signed_tx()]).err().unwrap());
+
+        payer.sign_input(&mut transaction, 0, payer_channel.terms().payer()).unwrap();
+        chain.mine(vec![transaction.into_signed_tx()]).unwrap();
+        assert_eq!(120, received(&chain, payer_channel.terms().payee()));
+        assert_eq!(470, received(&chain, payer_channel.terms().payer()));
+    }
+
+    #[test]
+    fn takes_the_funds_back_once_the_channel_expired() {
+        let (payer, mut payer_channel, _payee, mut payee_channel, mut chain) = funded_channel(5);
+        let update = send(&payer_channel.pay(120, &payer).unwrap());
+        payee_channel.receive(&update).unwrap();
+
+        // The payee never closes the channel.
+        let refund = payer_channel.refund(&payer).unwrap();
+        assert_eq!(Error::InvalidScript, chain.mine(vec![refund.clone()]).err().unwrap());
+        chain.mine_until(payer_channel.terms().expiry_height());
+        chain.mine(vec![refund]).unwrap();
+        assert_eq!(590, received(&chain, payer_channel.terms().payer()));
+        assert_eq!(0, received(&chain, payer_channel.terms().payee()));
+    }
+}
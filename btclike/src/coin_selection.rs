@@ -0,0 +1,204 @@
+use rand::{self, Rng};
+use wallet::TxOutReference;
+
+/// The maximum number of branches explored by `BranchAndBound` before giving up.
+const MAX_BRANCH_AND_BOUND_TRIES: usize = 100_000;
+
+/// Chooses the unspent outputs funding a transaction.
+pub trait CoinSelector {
+    /// Returns the indexes of the candidates to spend, whose total amount is at least the
+    /// target, or `None` if all the candidates together do not cover it.
+    fn select(&self, candidates: &[TxOutReference], target: u32) -> Option<Vec<usize>>;
+}
+
+/// Spends the candidates in the order they are given, the outputs of the oldest accounts
+/// first.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct InOrder;
+
+impl CoinSelector for InOrder {
+    fn select(&self, candidates: &[TxOutReference], target: u32) -> Option<Vec<usize>> {
+        accumulate(candidates, 0..candidates.len(), target)
+    }
+}
+
+/// Spends the largest outputs first, which minimizes the number of inputs.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct LargestFirst;
+
+impl CoinSelector for LargestFirst {
+    fn select(&self, candidates: &[TxOutReference], target: u32) -> Option<Vec<usize>> {
+        accumulate(candidates, by_decreasing_amount(candidates), target)
+    }
+}
+
+/// Searches for outputs adding up exactly to the target, so that the transaction needs no
+/// change output. Falls back to `LargestFirst` if there is no such combination or if it
+/// cannot be found quickly enough.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct BranchAndBound;
+
+impl CoinSelector for BranchAndBound {
+    fn select(&self, candidates: &[TxOutReference], target: u32) -> Option<Vec<usize>> {
+        let sorted = by_decreasing_amount(candidates);
+        let amounts: Vec<u64> = sorted.iter().map(|index| *candidates[*index].amount() as u64).collect();
+
+        // The total amount of the candidates following each one, to prune the branches that
+        // cannot reach the target anymore.
+        let mut remaining = vec![0u64; amounts.len() + 1];
+        for index in (0..amounts.len()).rev() {
+            remaining[index] = remaining[index + 1] + amounts[index];
+        }
+
+        let mut selection = vec![];
+        let mut tries = 0;
+        if search_exact_match(&amounts, &remaining, target as u64, 0, &mut selection, &mut tries) {
+            Some(selection.into_iter().map(|index| sorted[index]).collect())
+        } else {
+            LargestFirst.select(candidates, target)
+        }
+    }
+}
+
+/// Spends random outputs until the target is covered, then keeps adding random outputs as
+/// long as they bring the total closer to twice the target, without exceeding three times
+/// the target. The change outputs then look like payments, which keeps the wallet able to
+/// fund similar payments later with few inputs.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct RandomImprove;
+
+impl CoinSelector for RandomImprove {
+    fn select(&self, candidates: &[TxOutReference], target: u32) -> Option<Vec<usize>> {
+        let mut order: Vec<usize> = (0..candidates.len()).collect();
+        rand::thread_rng().shuffle(&mut order);
+
+        let mut selection = accumulate(candidates, order.iter().cloned(), target)?;
+        let mut total: u64 = selection.iter().map(|index| *candidates[*index].amount() as u64).sum();
+
+        let ideal = 2 * target as u64;
+        let max = 3 * target as u64;
+        // The random selection is a prefix of the order, the remaining outputs follow it.
+        for &index in &order[selection.len()..] {
+            let improved_total = total + *candidates[index].amount() as u64;
+
+            if improved_total <= max && distance(improved_total, ideal) < distance(total, ideal) {
+                selection.push(index);
+                total = improved_total;
+            }
+        }
+
+        Some(selection)
+    }
+}
+
+/// Selects the candidates in the given order until the target is covered.
+fn accumulate<I>(candidates: &[TxOutReference], order: I, target: u32) -> Option<Vec<usize>>
+    where I: IntoIterator<Item = usize>
+{
+    let mut selection = vec![];
+    let mut total = 0u64;
+
+    for index in order {
+        if total >= target as u64 {
+            break;
+        }
+
+        selection.push(index);
+        total += *candidates[index].amount() as u64;
+    }
+
+    if total >= target as u64 {
+        Some(selection)
+    } else {
+        None
+    }
+}
+
+fn by_decreasing_amount(candidates: &[TxOutReference]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..candidates.len()).collect();
+    order.sort_by(|one, other| candidates[*other].amount().cmp(candidates[*one].amount()));
+    order
+}
+
+/// Explores the combinations of the amounts from the given index, including each amount
+/// before excluding it. `selection` holds the indexes of the current branch.
+fn search_exact_match(
+    amounts: &[u64],
+    remaining: &[u64],
+    target: u64,
+    index: usize,
+    selection: &mut Vec<usize>,
+    tries: &mut usize,
+) -> bool {
+    if target == 0 {
+        return true;
+    }
+
+    *tries += 1;
+    if index == amounts.len() || remaining[index] < target || *tries > MAX_BRANCH_AND_BOUND_TRIES {
+        return false;
+    }
+
+    if amounts[index] <= target {
+        selection.push(index);
+        if search_exact_match(amounts, remaining, target - amounts[index], index + 1, selection, tries) {
+            return true;
+        }
+        selection.pop();
+    }
+
+    search_exact_match(amounts, remaining, target, index + 1, selection, tries)
+}
+
+fn distance(one: u64, other: u64) -> u64 {
+    one.max(other) - one.min(other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::Hash;
+
+    #[test]
+    fn selects_in_order_or_largest_first() {
+        let candidates = references(&[3, 10, 5]);
+
+        assert_eq!(Some(vec![0, 1]), InOrder.select(&candidates, 12));
+        assert_eq!(Some(vec![1, 2]), LargestFirst.select(&candidates, 12));
+        assert_eq!(Some(vec![1]), LargestFirst.select(&candidates, 10));
+        assert_eq!(None, InOrder.select(&candidates, 19));
+        assert_eq!(None, LargestFirst.select(&candidates, 19));
+    }
+
+    #[test]
+    fn finds_exact_matches() {
+        let candidates = references(&[7, 10, 4, 5, 1]);
+
+        let mut selection = BranchAndBound.select(&candidates, 16).unwrap();
+        selection.sort();
+        assert_eq!(vec![1, 3, 4], selection);
+
+        // Without an exact match, the largest outputs are spent.
+        let without_match = references(&[7, 10, 4]);
+        assert_eq!(Some(vec![1, 0]), BranchAndBound.select(&without_match, 12));
+        assert_eq!(None, BranchAndBound.select(&without_match, 22));
+    }
+
+    #[test]
+    fn improves_random_selections() {
+        let candidates = references(&[1; 30]);
+
+        for _i in 0..10 {
+            let selection = RandomImprove.select(&candidates, 5).unwrap();
+            // The total is as close as possible to twice the target.
+            assert_eq!(10, selection.len());
+        }
+        assert_eq!(None, RandomImprove.select(&candidates, 31));
+    }
+
+    fn references(amounts: &[u32]) -> Vec<TxOutReference> {
+        amounts.iter()
+            .map(|amount| TxOutReference::new(Hash::min(), 0, *amount))
+            .collect()
+    }
+}
@@ -0,0 +1,132 @@
+use rand::{self, Rng};
+use coin_selection::InOrder;
+use transaction::{self, PartiallySignedTx, RawTx, RawTxIn, TxOut};
+use wallet::Wallet;
+use Error;
+
+/// A transaction mixing the coins of several wallets, so that an observer of the chain cannot
+/// tell who paid whom.
+///
+/// Each participant spends outputs worth at least the denomination and its share of the fees,
+/// and receives the denomination at a new address, along with its change at another one. The
+/// outputs of the denomination all look the same, and the inputs and the outputs are shuffled:
+/// any of them may belong to any participant. The change outputs still link back to their
+/// inputs by their amounts, as in the CoinJoins of Bitcoin.
+///
+/// The coordinator gathers the participants, then builds the transaction each of them checks
+/// and signs with `Wallet::sign_owned_inputs`. The signatures commit to every input and every
+/// output, so the transaction is valid once the last participant signed it, and not before.
+pub struct CoinJoin {
+    denomination: u32,
+    /// The fees each participant pays.
+    fees: u32,
+    inputs: Vec<RawTxIn>,
+    spent_outputs: Vec<TxOut>,
+    outputs: Vec<TxOut>,
+    participants: usize,
+}
+
+impl CoinJoin {
+    /// A CoinJoin where every participant receives the given denomination and pays the given
+    /// fees.
+    pub fn new(denomination: u32, fees: u32) -> CoinJoin {
+        CoinJoin {
+            denomination,
+            fees,
+            inputs: vec![],
+            spent_outputs: vec![],
+            outputs: vec![],
+            participants: 0,
+        }
+    }
+
+    /// Adds the wallet to the participants, spending its unspent outputs in order. Fails if
+    /// they are not worth the denomination and the fees.
+    pub fn register<S>(&mut self, wallet: &mut Wallet, utxo_store: &S) -> Result<(), Error>
+        where S: transaction::UtxoStore
+    {
+        let total_cost = self.denomination.checked_add(self.fees).ok_or(Error::InvalidTxAmount)?;
+        let selection = wallet.select_inputs(total_cost, utxo_store, &InOrder)?;
+        self.inputs.extend(selection.inputs);
+        self.spent_outputs.extend(selection.spent_outputs.into_iter().cloned());
+
+        self.outputs.push(TxOut::new(self.denomination, wallet.new_address()?));
+        let change = selection.amount - total_cost;
+        if change > 0 {
+            self.outputs.push(TxOut::new(change, wallet.new_address()?));
+        }
+        self.participants += 1;
+        Ok(())
+    }
+
+    pub fn participants(&self) -> usize {
+        self.participants
+    }
+
+    pub fn denomination(&self) -> u32 {
+        self.denomination
+    }
+
+    /// The transaction of every participant, its inputs and outputs shuffled, for each of
+    /// them to sign.
+    pub fn build(&self) -> Result<PartiallySignedTx, Error> {
+        let mut rng = rand::thread_rng();
+        let mut inputs: Vec<(&RawTxIn, &TxOut)> = self.inputs.iter().zip(&self.spent_outputs).collect();
+        rng.shuffle(&mut inputs);
+        let mut output = self.outputs.clone();
+        rng.shuffle(&mut output);
+
+        let raw_tx = RawTx {
+            input: inputs.iter().map(|(raw_tx_in, _spent_output)| (*raw_tx_in).clone()).collect(),
+            output,
+        };
+        let spent_outputs: Vec<&TxOut> = inputs.into_iter().map(|(_raw_tx_in, spent_output)| spent_output).collect();
+        PartiallySignedTx::new(raw_tx, &spent_outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::Body;
+    use chain_params::ChainParams;
+    use swap::tests::TestChain;
+
+    #[test]
+    fn mixes_the_coins_of_several_wallets() {
+        let mut funder = Wallet::new();
+        let mut chain = TestChain::new(ChainParams::main(), &mut funder);
+        let mut participants: Vec<Wallet> = (0..3).map(|_i| Wallet::new()).collect();
+        let burn_address = Wallet::new().new_address().unwrap();
+        for (amount, participant) in [150, 200, 110].iter().zip(participants.iter_mut()) {
+            let address = participant.new_address().unwrap();
+            let payment = funder.new_transaction(*amount, address, 5, &chain.utxo_set).unwrap();
+            let body = Body::new(TxOut::new(0, burn_address.clone()), vec![payment.clone()]);
+            funder.connect_block(&body, chain.height).unwrap();
+            participant.connect_block(&body, chain.height).unwrap();
+            chain.mine(vec![payment]).unwrap();
+        }
+
+        let mut coinjoin = CoinJoin::new(100, 10);
+        for participant in participants.iter_mut() {
+            coinjoin.register(participant, &chain.utxo_set).unwrap();
+        }
+        assert_eq!(3, coinjoin.participants());
+        let mut poor = Wallet::new();
+        assert_eq!(Error::NotEnoughTokens, coinjoin.register(&mut poor, &chain.utxo_set).err().unwrap());
+
+        let mut transaction = coinjoin.build().unwrap();
+        assert_eq!(3, transaction.raw_tx().input.len());
+        // The denomination for each, plus the change of the two participants with some.
+        let output = &transaction.raw_tx().output;
+        assert_eq!(3, output.iter().filter(|tx_out| *tx_out.amount() == 100).count());
+        assert_eq!(5, output.len());
+
+        for participant in &participants {
+            assert_eq!(1, participant.sign_owned_inputs(&mut transaction));
+            assert_eq!(0, participant.sign_owned_inputs(&mut transaction));
+        }
+        assert_eq!(0, funder.sign_owned_inputs(&mut transaction));
+        chain.mine(vec![transaction.into_signed_tx()]).unwrap();
+    }
+}
@@ -1,57 +1,211 @@
 use ring::{rand, signature};
 use ring::signature::Ed25519KeyPair;
-use ring::rand::SystemRandom;
+use ring::rand::{SecureRandom, SystemRandom};
 use ring::digest;
-use ring::digest::SHA256;
+use ring::digest::{Digest, SHA256};
+use ring::digest::{SHA512, SHA512_256};
+use ring::hmac;
+use ring::constant_time;
+use ring::{aead, pbkdf2};
 use ring::error::Unspecified;
 use ring::signature::ED25519;
+use secp256k1::{self, SECP256K1};
 use untrusted::{self, Input};
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{self, SeqAccess, Visitor};
 use serde::ser::SerializeTuple;
+use std::fmt;
+use std::str::FromStr;
+use hex::{self, DecodingError};
 
 pub struct KeyPairGenerator{
     rng: SystemRandom,
+    algorithm: SignatureAlgorithm,
 }
 
 impl KeyPairGenerator{
     pub fn new() -> KeyPairGenerator {
         KeyPairGenerator{
             rng: rand::SystemRandom::new(),
+            algorithm: SignatureAlgorithm::default(),
         }
     }
 
+    pub fn with_signature_algorithm(mut self, algorithm: SignatureAlgorithm) -> KeyPairGenerator {
+        self.algorithm = algorithm;
+        self
+    }
+
     pub fn random_keypair(&self) -> Result<KeyPair, Unspecified>{
-        let pkcs8_bytes = signature::Ed25519KeyPair::generate_pkcs8(&self.rng)?;
+        // Some schemes reject a tiny fraction of the random private keys, try again.
+        loop {
+            let mut private_key = [0u8; PRIVATE_KEY_LEN];
+            self.rng.fill(&mut private_key)?;
+
+            if let Ok(key_pair) = KeyPair::from_private_key(self.algorithm, private_key) {
+                return Ok(key_pair);
+            }
+        }
+    }
 
-        let key_pair =
-            Ed25519KeyPair::from_pkcs8(Input::from(&pkcs8_bytes))?;
+    pub fn random_seed(&self) -> Result<Seed, Unspecified> {
+        let mut seed = [0u8; SEED_LEN];
+        self.rng.fill(&mut seed)?;
+        Ok(Seed(seed))
+    }
+}
 
-        Ok(KeyPair(key_pair))
+impl Default for KeyPairGenerator {
+    fn default() -> Self {
+        KeyPairGenerator::new()
     }
 }
 
-const PUBKEY_LEN: usize = 32;
-#[derive(Serialize, Clone)]
-pub struct PubKey([u8; PUBKEY_LEN]);
+const PRIVATE_KEY_LEN: usize = 32;
 
-impl PubKey{
-    pub fn as_bytes(&self) -> &[u8] {
-        &self.0
+/// A digital signature algorithm. The private keys of every scheme are 32 bytes long, so they
+/// are generated or derived from a seed the same way whatever the scheme.
+pub trait SignatureScheme: Sync {
+    /// Computes the public key of a private key. Fails if the bytes are not a valid private
+    /// key for the scheme.
+    fn pub_key(&self, private_key: &[u8; PRIVATE_KEY_LEN]) -> Result<PubKey, Unspecified>;
+
+    /// Signs the message with a private key accepted by `pub_key`.
+    fn sign(&self, private_key: &[u8; PRIVATE_KEY_LEN], message: &[u8]) -> Signature;
+
+    fn verify(&self, pub_key: &PubKey, message: &[u8], signature: &Signature) -> Result<(), Unspecified>;
+
+    /// The length of the serialized public keys.
+    fn pub_key_len(&self) -> usize;
+}
+
+/// The Ed25519 scheme, as implemented by ring.
+pub struct Ed25519;
+
+impl SignatureScheme for Ed25519 {
+    fn pub_key(&self, private_key: &[u8; PRIVATE_KEY_LEN]) -> Result<PubKey, Unspecified> {
+        let key_pair = Ed25519KeyPair::from_seed_unchecked(Input::from(private_key))?;
+        Ok(PubKey(key_pair.public_key_bytes().to_vec()))
     }
 
-    pub fn verify_signature(&self, input_bytes: &[u8], signature: &Signature) -> Result<(), Unspecified> {
-        let peer_public_key = untrusted::Input::from(&self.0);
+    fn sign(&self, private_key: &[u8; PRIVATE_KEY_LEN], message: &[u8]) -> Signature {
+        let key_pair = Ed25519KeyPair::from_seed_unchecked(Input::from(private_key))
+            .expect("Every 32 bytes seed is a valid Ed25519 private key");
+        let raw_signature = key_pair.sign(message);
+
+        let mut signature_bytes = [0u8; SIGNATURE_LEN];
+        signature_bytes.copy_from_slice(raw_signature.as_ref());
+        Signature(signature_bytes)
+    }
+
+    fn verify(&self, pub_key: &PubKey, message: &[u8], signature: &Signature) -> Result<(), Unspecified> {
+        let peer_public_key = untrusted::Input::from(&pub_key.0);
         let sig = untrusted::Input::from(&signature.0);
-        let msg = untrusted::Input::from(input_bytes);
+        let msg = untrusted::Input::from(message);
 
         signature::verify(&ED25519, peer_public_key, msg, sig)
     }
+
+    fn pub_key_len(&self) -> usize {
+        32
+    }
+}
+
+/// ECDSA over the secp256k1 curve, the scheme of Bitcoin. The SHA-256 hash of the message is
+/// signed. Public keys are serialized in their compressed form and signatures in their
+/// compact form.
+pub struct Secp256k1;
+
+impl SignatureScheme for Secp256k1 {
+    fn pub_key(&self, private_key: &[u8; PRIVATE_KEY_LEN]) -> Result<PubKey, Unspecified> {
+        let secret_key = secp256k1::SecretKey::from_slice(private_key).map_err(|_| Unspecified)?;
+        let public_key = secp256k1::PublicKey::from_secret_key(SECP256K1, &secret_key);
+        Ok(PubKey(public_key.serialize().to_vec()))
+    }
+
+    fn sign(&self, private_key: &[u8; PRIVATE_KEY_LEN], message: &[u8]) -> Signature {
+        let secret_key = secp256k1::SecretKey::from_slice(private_key)
+            .expect("The private key was accepted when the key pair was built");
+        let raw_signature = SECP256K1.sign_ecdsa(&secp256k1_message(message), &secret_key);
+        Signature(raw_signature.serialize_compact())
+    }
+
+    fn verify(&self, pub_key: &PubKey, message: &[u8], signature: &Signature) -> Result<(), Unspecified> {
+        let public_key = secp256k1::PublicKey::from_slice(&pub_key.0).map_err(|_| Unspecified)?;
+        let signature = secp256k1::ecdsa::Signature::from_compact(&signature.0).map_err(|_| Unspecified)?;
+
+        SECP256K1.verify_ecdsa(&secp256k1_message(message), &signature, &public_key)
+            .map_err(|_| Unspecified)
+    }
+
+    fn pub_key_len(&self) -> usize {
+        secp256k1::constants::PUBLIC_KEY_SIZE
+    }
+}
+
+fn secp256k1_message(message: &[u8]) -> secp256k1::Message {
+    secp256k1::Message::from_digest(*hash(message).as_ref())
+}
+
+/// The signature scheme the transactions of a chain are signed with.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SignatureAlgorithm {
+    #[default]
+    Ed25519,
+    Secp256k1,
+}
+
+impl SignatureAlgorithm {
+    pub fn scheme(&self) -> &'static dyn SignatureScheme {
+        match *self {
+            SignatureAlgorithm::Ed25519 => &Ed25519,
+            SignatureAlgorithm::Secp256k1 => &Secp256k1,
+        }
+    }
+}
+
+/// The longest public key of the supported schemes.
+const MAX_PUBKEY_LEN: usize = 33;
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PubKey(Vec<u8>);
+
+impl PubKey{
+    /// Returns `None` if the slice is empty or longer than the public keys of every scheme.
+    /// The length is checked against the actual scheme when verifying a signature.
+    pub fn from_bytes(bytes: &[u8]) -> Option<PubKey> {
+        if bytes.is_empty() || bytes.len() > MAX_PUBKEY_LEN {
+            return None;
+        }
+
+        Some(PubKey(bytes.to_vec()))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
 }
 
 const SIGNATURE_LEN: usize = 64;
 #[derive(Clone)]
 pub struct Signature([u8; SIGNATURE_LEN]);
 
+impl Signature {
+    /// Returns `None` if the slice does not have the length of a signature.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Signature> {
+        if bytes.len() != SIGNATURE_LEN {
+            return None;
+        }
+
+        let mut signature = [0u8; SIGNATURE_LEN];
+        signature.copy_from_slice(bytes);
+        Some(Signature(signature))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 impl Serialize for Signature
 {
     #[inline]
@@ -67,14 +221,89 @@ impl Serialize for Signature
     }
 }
 
-const HASH_LEN: usize = 32;
-#[derive(Serialize, Clone, Eq, PartialEq, Hash)]
+impl<'de> Deserialize<'de> for Signature
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+    {
+        let mut bytes = [0u8; SIGNATURE_LEN];
+        deserialize_byte_tuple(deserializer, &mut bytes)?;
+        Ok(Signature(bytes))
+    }
+}
+
+/// Fills the given buffer with a tuple of bytes, the way fixed-size arrays are serialized
+/// when they are too long for serde to derive the implementation.
+pub(crate) fn deserialize_byte_tuple<'de, D>(deserializer: D, bytes: &mut [u8]) -> Result<(), D::Error>
+    where
+        D: Deserializer<'de>,
+{
+    let len = bytes.len();
+    deserializer.deserialize_tuple(len, ByteTupleVisitor(bytes))
+}
+
+struct ByteTupleVisitor<'a>(&'a mut [u8]);
+
+impl<'de, 'a> Visitor<'de> for ByteTupleVisitor<'a> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{} bytes", self.0.len())
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<(), A::Error>
+        where
+            A: SeqAccess<'de>,
+    {
+        for index in 0..self.0.len() {
+            self.0[index] = seq.next_element()?
+                .ok_or_else(|| de::Error::invalid_length(index, &self))?;
+        }
+
+        Ok(())
+    }
+}
+
+pub const HASH_LEN: usize = 32;
+
+/// The 32 bytes digests identifying the blocks and the transactions, of the ledger and of the
+/// proof of work simulation alike.
+#[derive(Serialize, Deserialize, Clone, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub struct Hash([u8; HASH_LEN]);
 
 impl Hash {
     pub fn min() -> Hash{
         Hash([0u8; HASH_LEN])
     }
+
+    /// Returns `None` if the slice does not have the length of a hash.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Hash> {
+        if bytes.len() != HASH_LEN {
+            return None;
+        }
+
+        let mut hash = [0u8; HASH_LEN];
+        hash.copy_from_slice(bytes);
+        Some(Hash(hash))
+    }
+
+    /// Whether the hash, read as a big-endian number, is strictly lower than the target, the
+    /// threshold of a difficulty for instance.
+    pub fn is_below(&self, target: &[u8; HASH_LEN]) -> bool {
+        &self.0 < target
+    }
+
+    /// Compares the hashes in a time independent of their contents, for the ones of secrets.
+    pub fn constant_time_eq(&self, other: &Hash) -> bool {
+        constant_time::verify_slices_are_equal(&self.0, &other.0).is_ok()
+    }
+}
+
+impl From<[u8; HASH_LEN]> for Hash {
+    fn from(bytes: [u8; HASH_LEN]) -> Hash {
+        Hash(bytes)
+    }
 }
 
 impl AsRef<[u8; HASH_LEN]> for Hash {
@@ -83,33 +312,317 @@ impl AsRef<[u8; HASH_LEN]> for Hash {
     }
 }
 
-pub struct KeyPair(Ed25519KeyPair);
+/// Writes the hash in hexadecimal, to identify blocks and transactions in logs, reports and
+/// command lines.
+impl fmt::Display for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(&self.0))
+    }
+}
+
+impl fmt::Debug for Hash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum HashParseError {
+    Encoding(DecodingError),
+    /// The bytes decoded are not as many as the ones of a hash.
+    InvalidLength,
+}
+
+impl From<DecodingError> for HashParseError {
+    fn from(err: DecodingError) -> Self {
+        HashParseError::Encoding(err)
+    }
+}
+
+impl FromStr for Hash {
+    type Err = HashParseError;
+
+    fn from_str(encoded: &str) -> Result<Hash, HashParseError> {
+        Hash::from_bytes(&hex::decode(encoded)?).ok_or(HashParseError::InvalidLength)
+    }
+}
+
+/// The hash functions a chain can build its proof of work on. Their outputs are all 32 bytes
+/// long, so they can be compared to the same difficulty thresholds.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Hasher {
+    #[default]
+    Sha256,
+    /// SHA-256 applied twice, like Bitcoin does.
+    DoubleSha256,
+    /// SHA-512 truncated to 256 bits, usually faster than SHA-256 on 64 bits processors.
+    Sha512Trunc256,
+}
+
+impl Hasher {
+    pub fn digest(&self, data: &[u8]) -> Digest {
+        match *self {
+            Hasher::Sha256 => digest::digest(&SHA256, data),
+            Hasher::DoubleSha256 => digest::digest(&SHA256, digest::digest(&SHA256, data).as_ref()),
+            Hasher::Sha512Trunc256 => digest::digest(&SHA512_256, data),
+        }
+    }
+
+    pub fn hash(&self, data: &[u8]) -> Hash {
+        Hash::from_bytes(self.digest(data).as_ref()).expect("Every hash function has 32 bytes outputs")
+    }
+}
+
+const SEED_LEN: usize = 32;
+/// The secret every key of a deterministic wallet is derived from.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Seed([u8; SEED_LEN]);
+
+impl Seed {
+    /// Returns `None` if the slice does not have the length of a seed.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Seed> {
+        if bytes.len() != SEED_LEN {
+            return None;
+        }
+
+        let mut seed = [0u8; SEED_LEN];
+        seed.copy_from_slice(bytes);
+        Some(Seed(seed))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// The HMAC key of the master key derivation, as specified by SLIP-0010 for Ed25519.
+const MASTER_KEY_HMAC_KEY: &[u8] = b"ed25519 seed";
+/// Ed25519 only supports hardened derivation, so every child index has this bit set.
+const HARDENED_INDEX_BIT: u32 = 0x8000_0000;
+
+/// A private key along with the chain code needed to derive its children, following
+/// SLIP-0010, the adaptation of BIP32 to Ed25519. Only hardened children can be derived:
+/// the children of a key cannot be derived from its public key alone.
+#[derive(Clone)]
+pub struct ExtendedKey {
+    private_key: [u8; SEED_LEN],
+    chain_code: [u8; SEED_LEN],
+}
+
+impl ExtendedKey {
+    pub fn master(seed: &[u8]) -> ExtendedKey {
+        ExtendedKey::from_hmac(MASTER_KEY_HMAC_KEY, seed)
+    }
+
+    /// Derives the hardened child with the given index. Indexes are 31 bits long, the most
+    /// significant bit is ignored.
+    pub fn derive_child(&self, index: u32) -> ExtendedKey {
+        let mut data = Vec::with_capacity(1 + SEED_LEN + 4);
+        data.push(0u8);
+        data.extend_from_slice(&self.private_key);
+        data.extend_from_slice(&(index | HARDENED_INDEX_BIT).to_be_bytes());
+
+        ExtendedKey::from_hmac(&self.chain_code, &data)
+    }
+
+    /// The key pair of the private key for the given algorithm. Secp256k1 keys are not
+    /// derived as specified by SLIP-0010 for this curve: the private key is used as is, so
+    /// the derivation fails in the unlikely case it is not a valid secp256k1 key.
+    pub fn key_pair(&self, algorithm: SignatureAlgorithm) -> Result<KeyPair, Unspecified> {
+        KeyPair::from_private_key(algorithm, self.private_key)
+    }
+
+    fn from_hmac(key: &[u8], data: &[u8]) -> ExtendedKey {
+        let signing_key = hmac::SigningKey::new(&SHA512, key);
+        let output = hmac::sign(&signing_key, data);
+        let (private_key_bytes, chain_code_bytes) = output.as_ref().split_at(SEED_LEN);
+
+        let mut private_key = [0u8; SEED_LEN];
+        private_key.copy_from_slice(private_key_bytes);
+        let mut chain_code = [0u8; SEED_LEN];
+        chain_code.copy_from_slice(chain_code_bytes);
+
+        ExtendedKey {
+            private_key,
+            chain_code,
+        }
+    }
+}
+
+/// The number of PBKDF2 iterations deriving an encryption key from a passphrase.
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+static PASSPHRASE_AEAD: &aead::Algorithm = &aead::CHACHA20_POLY1305;
+
+/// Encrypts and authenticates the data with a key derived from the passphrase with
+/// PBKDF2-SHA256. The random salt and nonce are prepended to the ciphertext.
+pub fn encrypt_with_passphrase(passphrase: &str, data: &[u8]) -> Result<Vec<u8>, Unspecified> {
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; SALT_LEN];
+    rng.fill(&mut salt)?;
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill(&mut nonce)?;
+
+    let key = aead::SealingKey::new(PASSPHRASE_AEAD, &passphrase_key(passphrase, &salt))?;
+    let mut in_out = data.to_vec();
+    in_out.extend_from_slice(&[0u8; aead::MAX_TAG_LEN]);
+    let sealed_len = aead::seal_in_place(&key, &nonce, &[], &mut in_out, PASSPHRASE_AEAD.tag_len())?;
+    in_out.truncate(sealed_len);
+
+    let mut encrypted = Vec::with_capacity(SALT_LEN + NONCE_LEN + in_out.len());
+    encrypted.extend_from_slice(&salt);
+    encrypted.extend_from_slice(&nonce);
+    encrypted.extend_from_slice(&in_out);
+    Ok(encrypted)
+}
+
+/// Decrypts data encrypted by `encrypt_with_passphrase`. Fails if the passphrase is wrong or
+/// if the data was altered.
+pub fn decrypt_with_passphrase(passphrase: &str, encrypted: &[u8]) -> Result<Vec<u8>, Unspecified> {
+    if encrypted.len() < SALT_LEN + NONCE_LEN {
+        return Err(Unspecified);
+    }
+
+    let (salt, encrypted) = encrypted.split_at(SALT_LEN);
+    let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+
+    let key = aead::OpeningKey::new(PASSPHRASE_AEAD, &passphrase_key(passphrase, salt))?;
+    let mut in_out = ciphertext.to_vec();
+    let data_len = aead::open_in_place(&key, nonce, &[], 0, &mut in_out)?.len();
+    in_out.truncate(data_len);
+    Ok(in_out)
+}
+
+fn passphrase_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::derive(&SHA256, PBKDF2_ITERATIONS, salt, passphrase.as_bytes(), &mut key);
+    key
+}
+
+pub struct KeyPair {
+    algorithm: SignatureAlgorithm,
+    private_key: [u8; PRIVATE_KEY_LEN],
+    pub_key: PubKey,
+}
 
 impl KeyPair{
-    pub fn pub_key(&self) -> PubKey {
-        // PERFORMANCE Not optimal: could get rid of the copy operation.
-        let mut bytes = [0u8; PUBKEY_LEN];
-        bytes[..PUBKEY_LEN].clone_from_slice(self.0.public_key_bytes());
-        PubKey(bytes)
+    /// Fails if the bytes are not a valid private key for the algorithm.
+    pub fn from_private_key(algorithm: SignatureAlgorithm, private_key: [u8; PRIVATE_KEY_LEN]) -> Result<KeyPair, Unspecified> {
+        let pub_key = algorithm.scheme().pub_key(&private_key)?;
+
+        Ok(KeyPair {
+            algorithm,
+            private_key,
+            pub_key,
+        })
     }
 
-    pub fn sign(&self, input_bytes: &[u8]) -> Signature {
-        // PERFORMANCE Not optimal: could get rid of the copy operation.
-        let raw_signature = self.0.sign(input_bytes);
+    pub fn algorithm(&self) -> SignatureAlgorithm {
+        self.algorithm
+    }
 
-        let mut signature_bytes = [0u8; SIGNATURE_LEN];
-        signature_bytes[..SIGNATURE_LEN].clone_from_slice(raw_signature.as_ref());
+    pub fn pub_key(&self) -> PubKey {
+        self.pub_key.clone()
+    }
 
-        Signature(signature_bytes)
+    pub fn sign(&self, input_bytes: &[u8]) -> Signature {
+        self.algorithm.scheme().sign(&self.private_key, input_bytes)
     }
 }
 
 pub fn hash(input_bytes: &[u8]) -> Hash{
     // PERFORMANCE Not optimal: could get rid of the copy operation.
-    let digest = digest::digest(&SHA256, &input_bytes);
+    let digest = digest::digest(&SHA256, input_bytes);
 
     let mut hash_bytes = [0u8; HASH_LEN];
     hash_bytes[..HASH_LEN].clone_from_slice(digest.as_ref());
 
     Hash(hash_bytes)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_the_slip_0010_test_vector_keys() {
+        let master = ExtendedKey::master(&from_hex("000102030405060708090a0b0c0d0e0f"));
+        assert_eq!(
+            from_hex("2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7"),
+            master.private_key.to_vec()
+        );
+        assert_eq!(
+            from_hex("90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb"),
+            master.chain_code.to_vec()
+        );
+
+        let child = master.derive_child(0);
+        assert_eq!(
+            from_hex("68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3"),
+            child.private_key.to_vec()
+        );
+        assert_eq!(
+            from_hex("8b59aa11380b624e81507a27fedda59fea6d0b779a778918a2fd3590e16e9c69"),
+            child.chain_code.to_vec()
+        );
+    }
+
+    #[test]
+    fn hashes_with_the_selected_function() {
+        assert!(Hasher::Sha256.hash(b"abc") == hash(b"abc"));
+        assert_eq!(
+            from_hex("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            Hasher::Sha256.hash(b"abc").as_ref().to_vec()
+        );
+        assert_eq!(
+            from_hex("4f8b42c22dd3729b519ba6f68d2da7cc5b2d606d05daed5ad5128cc03e6c6358"),
+            Hasher::DoubleSha256.hash(b"abc").as_ref().to_vec()
+        );
+        assert_eq!(
+            from_hex("53048e2681941ef99b2e29b76b4c7dabe4c2d0c634fc6d46e0e2f13107e7af23"),
+            Hasher::Sha512Trunc256.hash(b"abc").as_ref().to_vec()
+        );
+    }
+
+    #[test]
+    fn writes_and_parses_hashes_in_hex() {
+        let sha256_abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(sha256_abc, hash(b"abc").to_string());
+        assert_eq!(sha256_abc, format!("{:?}", hash(b"abc")));
+        assert_eq!(Ok(hash(b"abc")), sha256_abc.to_uppercase().parse());
+
+        assert_eq!(Err(HashParseError::InvalidLength), "00ff".parse::<Hash>());
+        assert_eq!(Err(HashParseError::Encoding(DecodingError::OddLength)), "abc".parse::<Hash>());
+    }
+
+    #[test]
+    fn compares_hashes_to_targets() {
+        let mut target = [0u8; HASH_LEN];
+        target[1] = 0x10;
+        let mut bytes = target;
+        assert!(!Hash::from(bytes).is_below(&target));
+        bytes[HASH_LEN - 1] = 1;
+        assert!(!Hash::from(bytes).is_below(&target));
+        bytes[1] = 0x0f;
+        assert!(Hash::from(bytes).is_below(&target));
+
+        assert!(hash(b"abc").constant_time_eq(&hash(b"abc")));
+        assert!(!hash(b"abc").constant_time_eq(&hash(b"abd")));
+    }
+
+    #[test]
+    fn decrypts_with_the_right_passphrase_only() {
+        let encrypted = encrypt_with_passphrase("passphrase", b"secret").unwrap();
+        assert_eq!(b"secret".to_vec(), decrypt_with_passphrase("passphrase", &encrypted).unwrap());
+        assert!(decrypt_with_passphrase("other passphrase", &encrypted).is_err());
+
+        let mut altered = encrypted.clone();
+        *altered.last_mut().unwrap() ^= 1;
+        assert!(decrypt_with_passphrase("passphrase", &altered).is_err());
+        assert!(decrypt_with_passphrase("passphrase", &encrypted[..SALT_LEN]).is_err());
+    }
+
+    fn from_hex(encoded: &str) -> Vec<u8> {
+        ::hex::decode(encoded).unwrap()
+    }
+}
@@ -0,0 +1,272 @@
+use blockchain::{Block, Body};
+use crypto::Hash;
+use std::collections::HashMap;
+use store::BlockStore;
+use transaction::{Address, TxOut};
+use Error;
+
+/// A transaction of the indexed chain, with the block it was mined in.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct IndexedTx {
+    height: u32,
+    coinbase: bool,
+    /// The outputs it spends: the hash of their transaction and their index.
+    inputs: Vec<(Hash, u8)>,
+    outputs: Vec<TxOut>,
+}
+
+impl IndexedTx {
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.coinbase
+    }
+
+    pub fn inputs(&self) -> &[(Hash, u8)] {
+        &self.inputs
+    }
+
+    pub fn outputs(&self) -> &[TxOut] {
+        &self.outputs
+    }
+}
+
+/// What a transaction did to the outputs of an address.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct HistoryEntry {
+    tx_hash: Hash,
+    height: u32,
+    /// The amount of the outputs it created for the address.
+    received: u32,
+    /// The amount of the outputs of the address it spent.
+    spent: u32,
+}
+
+impl HistoryEntry {
+    pub fn tx_hash(&self) -> &Hash {
+        &self.tx_hash
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+
+    pub fn spent(&self) -> u32 {
+        self.spent
+    }
+}
+
+/// Indexes the transactions of a chain by hash and the transactions funding or spending the
+/// outputs of each address, to answer the queries of an explorer. The blocks are connected in
+/// order from the genesis one, and the index remembers the last one so that it can be kept up
+/// to date alongside a `BlockStore`.
+#[derive(Serialize, Deserialize, Default)]
+pub struct ExplorerIndex {
+    transactions: HashMap<Hash, IndexedTx>,
+    history: HashMap<Address, Vec<HistoryEntry>>,
+    tip_hash: Option<Hash>,
+}
+
+impl ExplorerIndex {
+    pub fn new() -> ExplorerIndex {
+        ExplorerIndex::default()
+    }
+
+    /// Indexes the given blocks, genesis first.
+    pub fn from_blocks(blocks: &[Block]) -> Result<ExplorerIndex, Error> {
+        let mut index = ExplorerIndex::new();
+        for block in blocks {
+            index.connect_block(block)?;
+        }
+        Ok(index)
+    }
+
+    /// The stored index, brought up to date with the stored blocks, or a new index of every
+    /// stored block if it does not match them anymore.
+    pub fn load_or_rebuild<S: BlockStore>(store: &mut S) -> Result<ExplorerIndex, Error> {
+        let blocks = store.blocks()?;
+        let mut index = match store.load_explorer_index()? {
+            Some(index) => index,
+            None => return ExplorerIndex::from_blocks(&blocks),
+        };
+
+        let next_block = match index.tip_hash {
+            Some(ref tip_hash) => blocks.iter()
+                .position(|block| block.header().hash() == tip_hash)
+                .map(|tip| tip + 1),
+            None => Some(0),
+        };
+        match next_block {
+            Some(next_block) => {
+                for block in &blocks[next_block..] {
+                    index.connect_block(block)?;
+                }
+                Ok(index)
+            },
+            None => ExplorerIndex::from_blocks(&blocks),
+        }
+    }
+
+    /// Indexes the block on top of the last connected one.
+    pub fn connect_block(&mut self, block: &Block) -> Result<(), Error> {
+        let header = block.header();
+        match self.tip_hash {
+            Some(ref tip_hash) if header.previous_block_hash() != tip_hash => {
+                return Err(Error::HeadAndTailHashMismatch);
+            },
+            None if *header.height() != 0 => return Err(Error::InvalidGenesis),
+            _ => {},
+        }
+
+        self.connect_body(block.body(), *header.height())?;
+        self.tip_hash = Some(header.hash().clone());
+        Ok(())
+    }
+
+    /// Indexes the transactions of a block body without checking that it extends the last
+    /// connected one, for the chains that do not keep their blocks as `Block`s.
+    pub fn connect_body(&mut self, body: &Body, height: u32) -> Result<(), Error> {
+        let coinbase_output = body.coinbase_tx().0.clone();
+        self.index_transaction(body.coinbase_tx().hash()?, height, true, vec![], vec![coinbase_output])?;
+
+        for transaction in body.transactions() {
+            let inputs = transaction.input().iter()
+                .map(|tx_in| (tx_in.prev_tx_hash().clone(), *tx_in.prev_tx_output_index()))
+                .collect();
+            self.index_transaction(transaction.hash()?, height, false, inputs, transaction.output().to_vec())?;
+        }
+        Ok(())
+    }
+
+    fn index_transaction(
+        &mut self,
+        tx_hash: Hash,
+        height: u32,
+        coinbase: bool,
+        inputs: Vec<(Hash, u8)>,
+        outputs: Vec<TxOut>,
+    ) -> Result<(), Error> {
+        // The received and the spent amounts of every address involved.
+        let mut amounts: HashMap<Address, (u32, u32)> = HashMap::new();
+        for (prev_tx_hash, prev_tx_output_index) in &inputs {
+            let prev_tx_out = self.transactions.get(prev_tx_hash)
+                .and_then(|prev_tx| prev_tx.outputs.get(*prev_tx_output_index as usize))
+                .ok_or(Error::UtxoNotFound)?;
+            let spent = &mut amounts.entry(prev_tx_out.address()).or_insert((0, 0)).1;
+            *spent = spent.saturating_add(*prev_tx_out.amount());
+        }
+        for tx_out in &outputs {
+            let received = &mut amounts.entry(tx_out.address()).or_insert((0, 0)).0;
+            *received = received.saturating_add(*tx_out.amount());
+        }
+
+        for (address, (received, spent)) in amounts {
+            self.history.entry(address).or_default().push(HistoryEntry {
+                tx_hash: tx_hash.clone(),
+                height,
+                received,
+                spent,
+            });
+        }
+        self.transactions.insert(tx_hash, IndexedTx {
+            height,
+            coinbase,
+            inputs,
+            outputs,
+        });
+        Ok(())
+    }
+
+    /// The transactions funding or spending the outputs of the address, oldest first.
+    pub fn get_history(&self, address: &Address) -> &[HistoryEntry] {
+        self.history.get(address).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn get_tx(&self, tx_hash: &Hash) -> Option<&IndexedTx> {
+        self.transactions.get(tx_hash)
+    }
+
+    /// The amount of the unspent outputs of the address.
+    pub fn balance(&self, address: &Address) -> u64 {
+        balance(self.get_history(address))
+    }
+
+    /// The balance of every address the chain ever paid, in no particular order.
+    pub fn balances<'a>(&'a self) -> impl Iterator<Item = (&'a Address, u64)> + 'a {
+        self.history.iter().map(|(address, history)| (address, balance(history)))
+    }
+
+    /// The hash of the last connected block.
+    pub fn tip_hash(&self) -> Option<&Hash> {
+        self.tip_hash.as_ref()
+    }
+
+    pub fn transaction_count(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn address_count(&self) -> usize {
+        self.history.len()
+    }
+}
+
+fn balance(history: &[HistoryEntry]) -> u64 {
+    history.iter()
+        .map(|entry| i64::from(entry.received) - i64::from(entry.spent))
+        .sum::<i64>() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::Chain;
+    use chain_params::ChainParams;
+    use genesis::GenesisConfig;
+    use utxo::UtxoSet;
+    use wallet::Wallet;
+
+    #[test]
+    fn indexes_the_history_of_the_addresses() {
+        let params = ChainParams::regtest();
+        let mut wallet = Wallet::new();
+        let premine_address = wallet.new_address().unwrap();
+        let payee = Wallet::new().new_address().unwrap();
+        let genesis = GenesisConfig::new(1).with_premine(premine_address.clone(), 100);
+        let coinbase_address = Wallet::new().new_address().unwrap();
+        let chain = Chain::mine_new_genesis(&params, &genesis, coinbase_address).unwrap();
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.apply(chain.head().body(), 0).unwrap();
+        wallet.connect_block(chain.head().body(), 0).unwrap();
+
+        let payment = wallet.new_transaction(30, payee.clone(), 2, &utxo_set).unwrap();
+        let payment_hash = payment.hash().unwrap();
+        let miner = Wallet::new().new_address().unwrap();
+        let chain = Chain::mine_next_block(chain, &params, vec![payment], 2, miner.clone()).unwrap();
+
+        let mut index = ExplorerIndex::new();
+        index.connect_block(chain.tail().unwrap().head()).unwrap();
+        assert_eq!(100, index.balance(&premine_address));
+        index.connect_block(chain.head()).unwrap();
+        assert!(index.connect_block(chain.head()).is_err());
+
+        assert_eq!(30, index.balance(&payee));
+        assert_eq!(0, index.balance(&premine_address));
+        assert_eq!(params.block_reward(1) as u64 + 2, index.balance(&miner));
+        let history = index.get_history(&premine_address);
+        assert_eq!(2, history.len());
+        assert!((history[1].received(), history[1].spent()) == (0, 100));
+        assert!(history[1].tx_hash() == &payment_hash);
+
+        let payment = index.get_tx(&payment_hash).unwrap();
+        assert_eq!(1, payment.height());
+        assert!(!payment.is_coinbase());
+        assert_eq!(1, payment.inputs().len());
+        assert!(index.get_history(&Wallet::new().new_address().unwrap()).is_empty());
+    }
+}
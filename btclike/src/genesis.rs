@@ -0,0 +1,219 @@
+use bincode;
+use blockchain::{Block, Body, Chain, Difficulty};
+use chain_params::ChainParams;
+use crypto::Hash;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+use toml;
+use transaction::{Address, RawTx, SignedTx, TxOut};
+use utxo::UtxoSet;
+use Error;
+
+/// The definition of a genesis block, shared by the nodes of separate runs or processes so that
+/// they start the same chain. It is read from a TOML file such as:
+///
+/// ```toml
+/// timestamp = 1231006505
+/// # Optional: the initial difficulty of the network otherwise.
+/// difficulty = 0x2000ffff
+///
+/// [[premine]]
+/// address = "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2"
+/// amount = 5000
+/// ```
+///
+/// The premined outputs are created by a transaction of the genesis block spending nothing,
+/// and can be spent right away, unlike the coinbase ones.
+#[derive(Clone, Default, PartialEq)]
+pub struct GenesisConfig {
+    /// Seconds since the Unix epoch.
+    timestamp: u64,
+    difficulty: Option<Difficulty>,
+    premine: Vec<(Address, u32)>,
+}
+
+/// The format of the files, where the addresses and the difficulty are human-readable.
+#[derive(Serialize, Deserialize)]
+struct GenesisFile {
+    timestamp: u64,
+    /// The compact bits of the difficulty.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    difficulty: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    premine: Vec<PremineOutput>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PremineOutput {
+    address: String,
+    amount: u32,
+}
+
+impl GenesisConfig {
+    pub fn new(timestamp: u64) -> GenesisConfig {
+        GenesisConfig {
+            timestamp,
+            ..GenesisConfig::default()
+        }
+    }
+
+    /// The same genesis block at another time, which gives it another hash.
+    pub fn with_timestamp(mut self, timestamp: u64) -> GenesisConfig {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Mines the genesis block with this difficulty instead of the initial one of the network.
+    pub fn with_difficulty(mut self, difficulty: Difficulty) -> GenesisConfig {
+        self.difficulty = Some(difficulty);
+        self
+    }
+
+    /// Creates an output of the given amount for the given address in the genesis block.
+    pub fn with_premine(mut self, address: Address, amount: u32) -> GenesisConfig {
+        self.premine.push((address, amount));
+        self
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<GenesisConfig, Error> {
+        let mut content = String::new();
+        File::open(path)?.read_to_string(&mut content)?;
+        let file: GenesisFile = toml::from_str(&content)
+            .map_err(|err| Error::SerializationError(format!("Invalid genesis file: {}", err)))?;
+
+        let mut genesis = GenesisConfig::new(file.timestamp);
+        if let Some(bits) = file.difficulty {
+            genesis = genesis.with_difficulty(Difficulty::from_compact(bits)?);
+        }
+        for output in file.premine {
+            genesis = genesis.with_premine(output.address.parse()?, output.amount);
+        }
+        Ok(genesis)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let file = GenesisFile {
+            timestamp: self.timestamp,
+            difficulty: self.difficulty.as_ref().map(Difficulty::to_compact),
+            premine: self.premine.iter()
+                .map(|(address, amount)| PremineOutput {
+                    address: address.to_string(),
+                    amount: *amount,
+                })
+                .collect(),
+        };
+        let content = toml::to_string(&file)
+            .map_err(|err| Error::SerializationError(format!("Could not write the genesis file: {}", err)))?;
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// The difficulty of the definition, if any.
+    pub fn difficulty(&self) -> Option<&Difficulty> {
+        self.difficulty.as_ref()
+    }
+
+    /// The difficulty of the definition, or the initial one of the given network.
+    pub fn difficulty_or(&self, params: &ChainParams) -> Difficulty {
+        self.difficulty.clone().unwrap_or_else(|| params.genesis_difficulty())
+    }
+
+    /// The genesis block has no parent: the previous block hash in its header holds the
+    /// timestamp instead, little-endian, so that two definitions creating the same outputs do
+    /// not share their genesis block. A timestamp of zero gives the minimum hash.
+    pub fn previous_block_hash(&self) -> Hash {
+        let mut bytes = [0u8; 32];
+        bytes[..8].copy_from_slice(&self.timestamp.to_le_bytes());
+        Hash::from_bytes(&bytes).expect("The hash has the expected length")
+    }
+
+    /// The body of the genesis block: the given coinbase output, then the premine transaction
+    /// if any output is premined.
+    pub fn body(&self, coinbase_tx_out: TxOut) -> Result<Body, Error> {
+        if self.premine.is_empty() {
+            return Ok(Body::new(coinbase_tx_out, vec![]));
+        }
+
+        let raw_tx = RawTx {
+            input: vec![],
+            output: self.premine.iter()
+                .map(|(address, amount)| TxOut::new(*amount, address.clone()))
+                .collect(),
+        };
+        Ok(Body::new(coinbase_tx_out, vec![SignedTx::from_raw_tx(raw_tx, &[], vec![])?]))
+    }
+}
+
+/// Writes the genesis block of the chain, so that other runs and processes can start from it
+/// with `import_genesis` instead of mining their own.
+pub fn export_genesis<P: AsRef<Path>>(chain: &Chain, path: P) -> Result<(), Error> {
+    let mut genesis = chain;
+    while let Some(tail) = genesis.tail() {
+        genesis = tail;
+    }
+
+    File::create(path)?.write_all(&bincode::serialize(genesis.head())?)?;
+    Ok(())
+}
+
+/// Reads a genesis block written by `export_genesis`, and verifies it against the rules of the
+/// given network.
+pub fn import_genesis<P: AsRef<Path>>(path: P, params: &ChainParams) -> Result<Chain, Error> {
+    let mut bytes = vec![];
+    File::open(path)?.read_to_end(&mut bytes)?;
+    let block: Block = bincode::deserialize(&bytes)?;
+    if *block.header().height() != 0 {
+        return Err(Error::InvalidGenesis);
+    }
+
+    let chain = Chain::from_blocks(vec![block]).ok_or(Error::InvalidGenesis)?;
+    chain.verify(chain.head_hash(), &UtxoSet::new(), params)?;
+    Ok(chain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use wallet::Wallet;
+
+    #[test]
+    fn shares_the_genesis_block_of_a_definition() {
+        let mut wallet = Wallet::new();
+        let premine_address = wallet.new_address().unwrap();
+        let directory = env::temp_dir();
+        let definition_path = directory.join(format!("btclike-genesis-{}.toml", ::std::process::id()));
+        let block_path = directory.join(format!("btclike-genesis-{}.bin", ::std::process::id()));
+        let params = ChainParams::main();
+
+        let genesis = GenesisConfig::new(1231006505)
+            .with_difficulty(params.pow_limit())
+            .with_premine(premine_address.clone(), 5000);
+        genesis.save(&definition_path).unwrap();
+        let loaded_genesis = GenesisConfig::load(&definition_path).unwrap();
+        assert!(genesis == loaded_genesis);
+
+        let coinbase_address = Wallet::new().new_address().unwrap();
+        let chain = Chain::mine_new_genesis(&params, &loaded_genesis, coinbase_address).unwrap();
+        assert!(loaded_genesis.previous_block_hash() == *chain.head().header().previous_block_hash());
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.apply(chain.head().body(), 0).unwrap();
+        wallet.connect_block(chain.head().body(), 0).unwrap();
+        // Unlike the coinbase output, the premined one is spendable from the first block on.
+        let transaction = wallet.new_transaction(100, premine_address, 1, &utxo_set).unwrap();
+        transaction.verify(&utxo_set, 1, &params).unwrap();
+
+        export_genesis(&chain, &block_path).unwrap();
+        let imported_chain = import_genesis(&block_path, &params).unwrap();
+        assert!(chain.head_hash() == imported_chain.head_hash());
+        assert_eq!(Error::InvalidDifficulty, import_genesis(&block_path, &ChainParams::testnet()).err().unwrap());
+
+        fs::remove_file(definition_path).unwrap();
+        fs::remove_file(block_path).unwrap();
+    }
+}
@@ -0,0 +1,49 @@
+//! The hexadecimal encoding of byte strings, two lowercase digits per byte, to exchange
+//! serialized transactions as text.
+
+const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DecodingError {
+    /// The text contains a character that is not a hexadecimal digit.
+    InvalidCharacter(char),
+    /// The text has an odd number of digits.
+    OddLength,
+}
+
+pub fn encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        encoded.push(DIGITS[(byte >> 4) as usize] as char);
+        encoded.push(DIGITS[(byte & 0x0f) as usize] as char);
+    }
+    encoded
+}
+
+/// Decodes upper or lowercase digits, ignoring the surrounding whitespace.
+pub fn decode(encoded: &str) -> Result<Vec<u8>, DecodingError> {
+    let encoded = encoded.trim();
+    if !encoded.len().is_multiple_of(2) {
+        return Err(DecodingError::OddLength);
+    }
+
+    let digits = encoded.chars()
+        .map(|character| character.to_digit(16).ok_or(DecodingError::InvalidCharacter(character)))
+        .collect::<Result<Vec<u32>, DecodingError>>()?;
+    Ok(digits.chunks(2).map(|pair| ((pair[0] << 4) | pair[1]) as u8).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_and_decodes_hex() {
+        assert_eq!("", encode(&[]));
+        assert_eq!("00ff7f0a", encode(&[0, 0xff, 0x7f, 0x0a]));
+
+        assert_eq!(Ok(vec![0, 0xff, 0x7f, 0x0a]), decode("00FF7f0a\n"));
+        assert_eq!(Err(DecodingError::OddLength), decode("abc"));
+        assert_eq!(Err(DecodingError::InvalidCharacter('g')), decode("0g"));
+    }
+}
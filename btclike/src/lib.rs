@@ -0,0 +1,212 @@
+//! Every type exchanged or persisted by the nodes implements `Serialize` and `Deserialize`.
+//! Their wire format is the one of bincode's default configuration: fixed-size little-endian
+//! integers, vectors prefixed with their length as a u64 and fields in declaration order.
+//! Hashes are computed on this format, so changing it is a consensus change.
+
+extern crate ring;
+extern crate untrusted;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate bincode;
+extern crate rand;
+extern crate secp256k1;
+extern crate toml;
+#[cfg(test)] extern crate proptest;
+
+pub mod base58;
+pub mod block_template;
+pub mod blockchain;
+pub mod chain_params;
+pub mod bloom;
+pub mod chain_store;
+pub mod channel;
+pub mod coin_selection;
+pub mod coinjoin;
+pub mod crypto;
+pub mod explorer;
+pub mod genesis;
+pub mod hex;
+pub mod mempool;
+pub mod merkle;
+pub mod policy;
+pub mod script;
+pub mod store;
+pub mod swap;
+pub mod transaction;
+pub mod u256;
+pub mod utxo;
+pub mod wallet;
+
+use ring::error::Unspecified;
+use std::error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug, PartialEq)]
+pub enum Error{
+    InvalidNumberOfKeyPairs(String),
+    InvalidNumberOfSpentOutputs(String),
+    SerializationError(String),
+    InvalidAddress,
+    MalformedAddress(transaction::AddressError),
+    InvalidTxAmount,
+    CryptographyError,
+    InvalidGenesis,
+    HeaderAndBodyHashMismatch,
+    HeadAndTailHashMismatch,
+    InvalidHeaderHash,
+    InvalidDifficulty,
+    MaxDifficultyExceeded,
+    NonceExhausted,
+    InvalidHeight,
+    TooManyInputForCoinbaseTx,
+    InvalidCoinbaseAmount,
+    HashIsTooHigh,
+    UtxoNotFound,
+    NotEnoughTokens,
+    DoubleSpend,
+    InsufficientFeeBump,
+    ImmatureCoinbase,
+    InvalidScript,
+    InvalidSigHashType,
+    DuplicateTransaction,
+    DuplicateBlock,
+    BlockTooLarge,
+    UnknownParent,
+    InvalidPassphrase,
+    InvalidBloomFilter,
+    InvalidChannelUpdate,
+    InsufficientFee,
+    TransactionTooLarge,
+    DustOutput,
+    IoError(String),
+}
+
+impl Error{
+    /// Whether the error is a transaction or a block breaking the consensus rules, rather
+    /// than a local failure: whoever sent it is at fault.
+    pub fn is_validation(&self) -> bool {
+        matches!(
+            *self,
+            Error::InvalidTxAmount
+            | Error::InvalidGenesis
+            | Error::HeaderAndBodyHashMismatch
+            | Error::HeadAndTailHashMismatch
+            | Error::InvalidHeaderHash
+            | Error::InvalidDifficulty
+            | Error::InvalidHeight
+            | Error::TooManyInputForCoinbaseTx
+            | Error::InvalidCoinbaseAmount
+            | Error::HashIsTooHigh
+            | Error::UtxoNotFound
+            | Error::DoubleSpend
+            | Error::ImmatureCoinbase
+            | Error::InvalidScript
+            | Error::DuplicateTransaction
+            | Error::DuplicateBlock
+            | Error::BlockTooLarge
+        )
+    }
+
+    /// Whether the error is a valid transaction refused by the relay policy of the node, or
+    /// by the conflict policy of its pool: other nodes may accept it, so whoever sent it is
+    /// not at fault.
+    pub fn is_policy(&self) -> bool {
+        matches!(
+            *self,
+            Error::InsufficientFee
+            | Error::TransactionTooLarge
+            | Error::DustOutput
+            | Error::InsufficientFeeBump
+        )
+    }
+}
+
+impl fmt::Display for Error{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::InvalidNumberOfKeyPairs(ref reason) => write!(f, "Invalid number of key pairs: {}", reason),
+            Error::InvalidNumberOfSpentOutputs(ref reason) => write!(f, "Invalid number of spent outputs: {}", reason),
+            Error::SerializationError(ref reason) => write!(f, "{}", reason),
+            Error::InvalidAddress => write!(f, "Invalid address"),
+            Error::MalformedAddress(ref err) => write!(f, "Malformed address: {:?}", err),
+            Error::InvalidTxAmount => write!(f, "Invalid transaction amount"),
+            Error::CryptographyError => write!(f, "Cryptography error"),
+            Error::InvalidGenesis => write!(f, "Invalid genesis block"),
+            Error::HeaderAndBodyHashMismatch => write!(f, "The header does not match the body"),
+            Error::HeadAndTailHashMismatch => write!(f, "The block does not extend its parent"),
+            Error::InvalidHeaderHash => write!(f, "Invalid header hash"),
+            Error::InvalidDifficulty => write!(f, "Invalid difficulty"),
+            Error::MaxDifficultyExceeded => write!(f, "Exceeded the maximum difficulty"),
+            Error::NonceExhausted => write!(f, "Every nonce was tried"),
+            Error::InvalidHeight => write!(f, "Invalid height"),
+            Error::TooManyInputForCoinbaseTx => write!(f, "Too many inputs for a coinbase transaction"),
+            Error::InvalidCoinbaseAmount => write!(f, "Invalid coinbase amount"),
+            Error::HashIsTooHigh => write!(f, "The hash is higher than the difficulty threshold"),
+            Error::UtxoNotFound => write!(f, "Unspent output not found"),
+            Error::NotEnoughTokens => write!(f, "Not enough tokens"),
+            Error::DoubleSpend => write!(f, "Double spend"),
+            Error::InsufficientFeeBump => write!(f, "Insufficient fee bump"),
+            Error::ImmatureCoinbase => write!(f, "Immature coinbase output"),
+            Error::InvalidScript => write!(f, "Invalid script"),
+            Error::InvalidSigHashType => write!(f, "The sighash type signs an output the transaction does not have"),
+            Error::DuplicateTransaction => write!(f, "Duplicate transaction"),
+            Error::DuplicateBlock => write!(f, "Duplicate block"),
+            Error::BlockTooLarge => write!(f, "The block exceeds the maximum block size"),
+            Error::UnknownParent => write!(f, "Unknown parent block"),
+            Error::InvalidPassphrase => write!(f, "Invalid passphrase"),
+            Error::InvalidBloomFilter => write!(f, "Invalid Bloom filter size or false positive rate"),
+            Error::InvalidChannelUpdate => write!(f, "Invalid payment channel update"),
+            Error::InsufficientFee => write!(f, "The fee rate is below the minimum relay fee rate"),
+            Error::TransactionTooLarge => write!(f, "The transaction exceeds the maximum transaction size"),
+            Error::DustOutput => write!(f, "An output is below the dust threshold"),
+            Error::IoError(ref reason) => write!(f, "I/O error: {}", reason),
+        }
+    }
+}
+
+impl error::Error for Error{}
+
+impl From<bincode::Error> for Error{
+    fn from(err: bincode::Error) -> Self {
+        Error::SerializationError(
+            format!("Could not properly serialize the transaction. Reason: {}", err)
+        )
+    }
+}
+
+impl From<Unspecified> for Error{
+    fn from(_: Unspecified) -> Self {
+        Error::CryptographyError
+    }
+}
+
+impl From<transaction::AddressError> for Error{
+    fn from(err: transaction::AddressError) -> Self {
+        Error::MalformedAddress(err)
+    }
+}
+
+impl From<io::Error> for Error{
+    fn from(err: io::Error) -> Self {
+        Error::IoError(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tells_validation_errors_from_local_ones() {
+        assert!(Error::DoubleSpend.is_validation());
+        assert!(Error::HashIsTooHigh.is_validation());
+        assert!(!Error::NotEnoughTokens.is_validation());
+        assert!(!Error::IoError("Disk full".to_owned()).is_validation());
+        assert_eq!("Double spend", Error::DoubleSpend.to_string());
+
+        assert!(Error::InsufficientFee.is_policy());
+        assert!(!Error::InsufficientFee.is_validation());
+        assert!(!Error::DoubleSpend.is_policy());
+    }
+}
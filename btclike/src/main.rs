@@ -1,25 +1,31 @@
 #[macro_use] extern crate log;
-extern crate env_logger;
-extern crate ring;
-extern crate untrusted;
-extern crate serde;
-#[macro_use] extern crate serde_derive;
 extern crate bincode;
+extern crate clap;
+extern crate env_logger;
+extern crate btclike_simulation as btclike;
 
-mod blockchain;
-mod crypto;
-mod transaction;
-mod wallet;
-
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use log::LevelFilter;
-use ring::error::Unspecified;
-use blockchain::Difficulty;
-use transaction::Address;
-use transaction::TxOut;
-use crypto::KeyPairGenerator;
-use crypto::Hash;
-use transaction::UtxoStore;
-use blockchain::Chain;
+use btclike::block_template::BlockTemplateBuilder;
+use btclike::chain_params::ChainParams;
+use btclike::crypto::Hash;
+use btclike::transaction::{Address, SignedTx, TxOut};
+use btclike::crypto::KeyPairGenerator;
+use btclike::explorer::ExplorerIndex;
+use btclike::blockchain::{Block, Body, Chain};
+use btclike::genesis::{self, GenesisConfig};
+use btclike::mempool::Mempool;
+use btclike::store::{self, BlockStore, FileBlockStore};
+use btclike::utxo::UtxoSet;
+use btclike::wallet::Wallet;
+use std::cmp::Reverse;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::process;
+
+/// The number of consecutive unused addresses after which a wallet stops looking for funds.
+const GAP_LIMIT: u32 = 20;
 
 fn main() {
     // Always print backtrace on panic.
@@ -30,61 +36,589 @@ fn main() {
         .filter_level(LevelFilter::Info)
         .init();
 
-    let key_pair_generator = KeyPairGenerator::new();
+    let matches = App::new("Bitcoin-like Blockchain")
+        .version("0.1")
+        .author("Pierre L. <pierre.larger@gmail.com>")
+        .about("Mines the blocks of a Bitcoin-like blockchain and pays from its wallets")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("wallet")
+                .about("Manages an encrypted wallet")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("new")
+                        .about("Creates a wallet and prints its first address.")
+                        .args(&wallet_args()),
+                )
+                .subcommand(
+                    SubCommand::with_name("address")
+                        .about("Derives a new address of the wallet and prints it.")
+                        .args(&wallet_args()),
+                )
+                .subcommand(
+                    SubCommand::with_name("send")
+                        .about("Pays an address with the confirmed outputs of the wallet. The payment waits in the data directory until the node mines it.")
+                        .args(&wallet_args())
+                        .arg(data_dir_arg().required(true))
+                        .arg(network_arg())
+                        .arg(
+                            Arg::with_name("to")
+                                .long("to")
+                                .value_name("ADDRESS")
+                                .help("The address to pay.")
+                                .required(true)
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("amount")
+                                .long("amount")
+                                .value_name("AMOUNT")
+                                .help("The amount to pay.")
+                                .required(true)
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("fee")
+                                .long("fee")
+                                .value_name("FEE")
+                                .help("The fees of the payment, collected by the miner. Defaults to 1.")
+                                .takes_value(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("node")
+                .about("Runs a mining node")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("run")
+                        .about("Mines blocks on top of the chain, with the pending payments.")
+                        .arg(data_dir_arg())
+                        .arg(
+                            Arg::with_name("wallet")
+                                .long("wallet")
+                                .value_name("WALLET_FILE")
+                                .help("Pays the coinbases to this encrypted wallet, created if it does not exist.")
+                                .takes_value(true),
+                        )
+                        .arg(passphrase_arg())
+                        .arg(network_arg())
+                        .arg(
+                            Arg::with_name("genesis")
+                                .long("genesis")
+                                .value_name("GENESIS_FILE")
+                                .help("Mines the genesis block defined by this TOML file: its timestamp, its premined outputs and its difficulty.")
+                                .conflicts_with("import_genesis")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("import_genesis")
+                                .long("import-genesis")
+                                .value_name("GENESIS_BLOCK_FILE")
+                                .help("Starts the chain from the genesis block exported by another run instead of mining one.")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("export_genesis")
+                                .long("export-genesis")
+                                .value_name("GENESIS_BLOCK_FILE")
+                                .help("Writes the genesis block of the chain to this file, for other runs to import.")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("explorer")
+                                .long("explorer")
+                                .help("Maintains the explorer index of the chain in the data directory.")
+                                .requires("data_dir"),
+                        )
+                        .arg(
+                            Arg::with_name("blocks")
+                                .short("b")
+                                .long("blocks")
+                                .value_name("NUMBER_OF_BLOCKS")
+                                .help("The number of blocks to mine on top of the chain.")
+                                .takes_value(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("tx")
+                .about("Inspects transactions")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("decode")
+                        .about("Prints the inputs and the outputs of a hexadecimal transaction, and checks its signatures against the chain of a data directory if any.")
+                        .arg(data_dir_arg())
+                        .arg(network_arg())
+                        .arg(Arg::with_name("hex").value_name("TRANSACTION_HEX").required(true)),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("chain")
+                .about("Exports and verifies chains")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("export")
+                        .about("Writes the chain of the data directory to a file.")
+                        .arg(data_dir_arg().required(true))
+                        .arg(network_arg())
+                        .arg(Arg::with_name("file").value_name("CHAIN_FILE").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("verify")
+                        .about("Verifies every block of a chain file and replays its transactions.")
+                        .arg(network_arg())
+                        .arg(Arg::with_name("file").value_name("CHAIN_FILE").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("history")
+                        .about("Prints the transactions funding or spending the outputs of an address.")
+                        .arg(data_dir_arg().required(true))
+                        .arg(Arg::with_name("address").value_name("ADDRESS").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("tx")
+                        .about("Prints a transaction of the chain, with the height of its block.")
+                        .arg(data_dir_arg().required(true))
+                        .arg(Arg::with_name("hash").value_name("TRANSACTION_HASH").required(true)),
+                )
+                .subcommand(
+                    SubCommand::with_name("wealth")
+                        .about("Prints how the coins of the chain are distributed among its addresses.")
+                        .arg(data_dir_arg().required(true))
+                        .arg(
+                            Arg::with_name("top")
+                                .long("top")
+                                .value_name("NUMBER_OF_ADDRESSES")
+                                .help("The number of richest addresses to print. Defaults to 10.")
+                                .takes_value(true),
+                        ),
+                ),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        ("wallet", Some(matches)) => match matches.subcommand() {
+            ("new", Some(matches)) => new_wallet(matches),
+            ("address", Some(matches)) => new_address(matches),
+            ("send", Some(matches)) => send(matches),
+            _ => unreachable!("A wallet subcommand is required"),
+        },
+        ("node", Some(matches)) => match matches.subcommand() {
+            ("run", Some(matches)) => run_node(matches),
+            _ => unreachable!("A node subcommand is required"),
+        },
+        ("tx", Some(matches)) => match matches.subcommand() {
+            ("decode", Some(matches)) => decode_transaction(matches),
+            _ => unreachable!("A tx subcommand is required"),
+        },
+        ("chain", Some(matches)) => match matches.subcommand() {
+            ("export", Some(matches)) => export_chain(matches),
+            ("verify", Some(matches)) => verify_chain(matches),
+            ("history", Some(matches)) => print_history(matches),
+            ("tx", Some(matches)) => print_chain_transaction(matches),
+            ("wealth", Some(matches)) => print_wealth(matches),
+            _ => unreachable!("A chain subcommand is required"),
+        },
+        _ => unreachable!("A subcommand is required"),
+    }
+}
+
+fn wallet_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("wallet")
+            .long("wallet")
+            .value_name("WALLET_FILE")
+            .help("The encrypted wallet file.")
+            .required(true)
+            .takes_value(true),
+        passphrase_arg(),
+    ]
+}
+
+fn passphrase_arg() -> Arg<'static, 'static> {
+    Arg::with_name("passphrase")
+        .long("passphrase")
+        .value_name("PASSPHRASE")
+        .help("The passphrase of the wallet.")
+        .takes_value(true)
+}
+
+fn data_dir_arg() -> Arg<'static, 'static> {
+    Arg::with_name("data_dir")
+        .long("data-dir")
+        .value_name("DATA_DIR")
+        .help("Persists the chain to this directory and resumes it on the next run.")
+        .takes_value(true)
+}
+
+fn network_arg() -> Arg<'static, 'static> {
+    Arg::with_name("network")
+        .long("network")
+        .value_name("NETWORK")
+        .help("The rules of the chain: main, testnet or regtest. Defaults to main.")
+        .possible_values(&["main", "testnet", "regtest"])
+        .takes_value(true)
+}
+
+fn network(matches: &ArgMatches) -> ChainParams {
+    ChainParams::by_name(matches.value_of("network").unwrap_or("main")).expect("Invalid network")
+}
 
-    let wallet = key_pair_generator.random_keypair().ok().unwrap();
-    let address = Address::from_pub_key(&wallet.pub_key());
+fn load_wallet(matches: &ArgMatches) -> Wallet {
+    Wallet::load(matches.value_of("wallet").unwrap(), matches.value_of("passphrase").unwrap_or(""))
+        .expect("Could not load the wallet")
+}
 
-    let mut difficulty = Difficulty::min_difficulty();
+fn save_wallet(matches: &ArgMatches, wallet: &Wallet) {
+    wallet.save(matches.value_of("wallet").unwrap(), matches.value_of("passphrase").unwrap_or(""))
+        .expect("Could not save the wallet");
+}
 
-    for _i in 0..4 {
-        difficulty.increase();
+fn new_wallet(matches: &ArgMatches) {
+    if Path::new(matches.value_of("wallet").unwrap()).exists() {
+        fail("The wallet file already exists");
     }
 
-    let chain = Chain::mine_new_genesis(difficulty, address).ok().unwrap();
+    let mut wallet = Wallet::new();
+    let address = wallet.new_address().expect("Could not create an address");
+    save_wallet(matches, &wallet);
+    println!("{}", address);
+}
 
-    chain.verify(chain.head_hash(), &EmptyUtxoStore{}).ok().unwrap();
-    info!("Hello world.");
+fn new_address(matches: &ArgMatches) {
+    let mut wallet = load_wallet(matches);
+    let address = wallet.new_address().expect("Could not create an address");
+    save_wallet(matches, &wallet);
+    println!("{}", address);
 }
 
-struct EmptyUtxoStore;
+fn send(matches: &ArgMatches) {
+    let params = network(matches);
+    let to_address: Address = match matches.value_of("to").unwrap().parse() {
+        Ok(to_address) => to_address,
+        Err(err) => fail(&format!("Invalid address: {:?}", err)),
+    };
+    let amount: u32 = match matches.value_of("amount").unwrap().parse() {
+        Ok(amount) => amount,
+        Err(err) => fail(&format!("Invalid amount: {}", err)),
+    };
+    let fees: u32 = match matches.value_of("fee").unwrap_or("1").parse() {
+        Ok(fees) => fees,
+        Err(err) => fail(&format!("Invalid fee: {}", err)),
+    };
+
+    let mut store = open_store(matches);
+    let (chain, utxo_set) = match store.load_chain(&params).expect("Could not load the stored chain") {
+        Some(resumed) => resumed,
+        None => fail("The data directory has no chain"),
+    };
+    let next_height = chain.height() + 1;
+    let mut pending = store.load_pending().expect("Could not load the pending payments");
+
+    let mut wallet = load_wallet(matches);
+    wallet.scan(&utxo_set, GAP_LIMIT).expect("Could not scan the unspent outputs");
+    // The outputs spent by the payments waiting to be mined are not spent again.
+    let pending_body = Body::new(TxOut::new(0, Address::from_hash(Hash::min())), pending.clone());
+    wallet.connect_block(&pending_body, next_height).expect("Could not apply the pending payments");
 
-impl UtxoStore for EmptyUtxoStore{
-    fn find(&self, _transaction_hash: &Hash, _txo_index: &u8) -> Option<&TxOut> {
-        None
+    let transaction = match wallet.new_transaction(amount, to_address.clone(), fees, &utxo_set) {
+        Ok(transaction) => transaction,
+        Err(err) => fail(&format!("Could not pay {} to {}: {}", amount, to_address, err)),
+    };
+    if let Err(err) = transaction.verify(&utxo_set, next_height, &params) {
+        fail(&format!("Invalid payment: {}", err));
     }
+
+    pending.push(transaction);
+    store.save_pending(&pending).expect("Could not save the payment");
+    // The change address was derived by the payment.
+    save_wallet(matches, &wallet);
+    info!("Payment of {} to {} pending, {} payment(s) waiting to be mined", amount, to_address, pending.len());
+    println!("{}", pending.last().unwrap().to_hex().expect("Could not serialize the payment"));
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Error{
-    InvalidNumberOfKeyPairs(String),
-    SerializationError(String),
-    InvalidAddress,
-    InvalidTxAmount,
-    CryptographyError,
-    InvalidGenesis,
-    HeaderAndBodyHashMismatch,
-    HeadAndTailHashMismatch,
-    InvalidHeaderHash,
-    InvalidDifficulty,
-    InvalidHeight,
-    TooManyInputForCoinbaseTx,
-    InvalidCoinbaseAmount,
-    HashIsTooHigh,
-    UtxoNotFound,
-    NotEnoughTokens,
+fn run_node(matches: &ArgMatches) {
+    let number_of_blocks: u32 = matches.value_of("blocks").unwrap_or("1")
+        .parse().expect("Invalid number of blocks");
+    let params = network(matches);
+
+    let mut store = matches.value_of("data_dir").map(|data_dir| {
+        FileBlockStore::open(data_dir).expect("Could not open the data directory")
+    });
+
+    let passphrase = matches.value_of("passphrase").unwrap_or("");
+    let mut wallet = matches.value_of("wallet").map(|wallet_file| {
+        if Path::new(wallet_file).exists() {
+            Wallet::load(wallet_file, passphrase).expect("Could not load the wallet")
+        } else {
+            Wallet::new()
+        }
+    });
+
+    let resumed = match store {
+        Some(ref mut store) => store.load_chain(&params).expect("Could not load the stored chain"),
+        None => None,
+    };
+
+    let (mut chain, mut utxo_set) = match resumed {
+        Some((chain, utxo_set)) => {
+            info!("Resumed a chain with height {}", chain.height());
+            (chain, utxo_set)
+        },
+        None => {
+            let chain = match matches.value_of("import_genesis") {
+                Some(genesis_file) => {
+                    let chain = genesis::import_genesis(genesis_file, &params)
+                        .expect("Could not import the genesis block");
+                    info!("Imported a genesis block of the {} network", params.name());
+                    chain
+                },
+                None => {
+                    let genesis = match matches.value_of("genesis") {
+                        Some(genesis_file) => GenesisConfig::load(genesis_file)
+                            .expect("Could not load the genesis definition"),
+                        None => GenesisConfig::default(),
+                    };
+                    let chain = Chain::mine_new_genesis(&params, &genesis, coinbase_address(&mut wallet))
+                        .ok().unwrap();
+                    info!("Mined a new genesis block on the {} network", params.name());
+                    chain
+                },
+            };
+            let mut utxo_set = UtxoSet::new();
+            utxo_set.apply(chain.head().body(), chain.height()).ok().unwrap();
+
+            if let Some(ref mut store) = store {
+                store.append(chain.head()).expect("Could not store the genesis block");
+            }
+
+            (chain, utxo_set)
+        },
+    };
+
+    if let Some(genesis_file) = matches.value_of("export_genesis") {
+        genesis::export_genesis(&chain, genesis_file).expect("Could not export the genesis block");
+    }
+
+    let mut explorer_index = match store {
+        Some(ref mut store) if matches.is_present("explorer") => {
+            Some(ExplorerIndex::load_or_rebuild(store).expect("Could not load the explorer index"))
+        },
+        _ => None,
+    };
+
+    let mut mempool = Mempool::new().with_params(params.clone());
+    if let Some(ref mut store) = store {
+        for transaction in store.load_pending().expect("Could not load the pending payments") {
+            if let Err(err) = mempool.add(transaction, &utxo_set, chain.height() + 1) {
+                warn!("Dropped a pending payment: {}", err);
+            }
+        }
+    }
+
+    for _i in 0..number_of_blocks {
+        let coinbase_address = coinbase_address(&mut wallet);
+        let template = BlockTemplateBuilder::new(&params, coinbase_address.clone())
+            .build(&mempool, chain.height() + 1)
+            .expect("Could not build the block template");
+        let fees = *template.fees();
+        let transactions = template.into_body().transactions().to_vec();
+        for transaction in &transactions {
+            mempool.remove(&transaction.hash().ok().unwrap());
+        }
+
+        chain = Chain::mine_next_block(chain, &params, transactions, fees, coinbase_address).ok().unwrap();
+        utxo_set.apply(chain.head().body(), chain.height()).ok().unwrap();
+
+        if let Some(ref mut store) = store {
+            store.append(chain.head()).expect("Could not store the block");
+        }
+        if let Some(ref mut explorer_index) = explorer_index {
+            explorer_index.connect_block(chain.head()).expect("Could not index the block");
+        }
+    }
+
+    if let Some(ref mut store) = store {
+        store.save_utxo_set(&utxo_set, chain.head_hash()).expect("Could not store the UTXO set");
+        let pending: Vec<_> = mempool.entries().map(|entry| entry.transaction().clone()).collect();
+        store.save_pending(&pending).expect("Could not store the pending payments");
+        if let Some(ref explorer_index) = explorer_index {
+            store.save_explorer_index(explorer_index).expect("Could not store the explorer index");
+        }
+    }
+
+    if let (Some(wallet_file), Some(wallet)) = (matches.value_of("wallet"), wallet) {
+        wallet.save(wallet_file, passphrase).expect("Could not save the wallet");
+    }
+
+    info!("Chain height: {}, unspent outputs: {}, pending payments: {}", chain.height(), utxo_set.len(), mempool.len());
 }
 
-impl From<bincode::Error> for Error{
-    fn from(err: bincode::Error) -> Self {
-        Error::SerializationError(
-            format!("Could not properly serialize the transaction. Reason: {}", err)
-        )
+fn decode_transaction(matches: &ArgMatches) {
+    let transaction = match SignedTx::from_hex(matches.value_of("hex").unwrap()) {
+        Ok(transaction) => transaction,
+        Err(err) => fail(&format!("Malformed transaction: {}", err)),
+    };
+    // The spent outputs are needed to check the signatures, as part of the next block.
+    let utxo_set = matches.value_of("data_dir").map(|_data_dir| {
+        match open_store(matches).load_chain(&network(matches)).expect("Could not load the stored chain") {
+            Some((chain, utxo_set)) => (utxo_set, chain.height() + 1),
+            None => fail("The data directory has no chain"),
+        }
+    });
+
+    let hash = transaction.hash().expect("Could not hash the transaction");
+    println!("Transaction {}", hash);
+    println!("Inputs:");
+    for (index, tx_in) in transaction.input().iter().enumerate() {
+        let signature = match utxo_set {
+            Some((ref utxo_set, height)) => match transaction.verify_input(index, utxo_set, height) {
+                Ok(()) => "valid".to_string(),
+                Err(err) => format!("invalid ({})", err),
+            },
+            None => "not checked".to_string(),
+        };
+        println!(
+            "  #{} spends output {} of {}, unlock script of {} bytes, signature {}",
+            index,
+            tx_in.prev_tx_output_index(),
+            tx_in.prev_tx_hash(),
+            tx_in.unlock_script().as_bytes().len(),
+            signature
+        );
+    }
+    println!("Outputs:");
+    for (index, tx_out) in transaction.output().iter().enumerate() {
+        println!("  #{} pays {} to {}", index, tx_out.amount(), tx_out.address());
     }
+    let out_amount: u64 = transaction.output().iter().map(|tx_out| u64::from(*tx_out.amount())).sum();
+    println!("Total output amount: {}", out_amount);
 }
 
-impl From<Unspecified> for Error{
-    fn from(_: Unspecified) -> Self {
-        Error::CryptographyError
+fn export_chain(matches: &ArgMatches) {
+    let params = network(matches);
+    let chain = match open_store(matches).load_chain(&params).expect("Could not load the stored chain") {
+        Some((chain, _utxo_set)) => chain,
+        None => fail("The data directory has no chain"),
+    };
+
+    let chain_file = matches.value_of("file").unwrap();
+    fs::write(chain_file, bincode::serialize(&chain).expect("Could not serialize the chain"))
+        .expect("Could not write the chain file");
+    info!("Exported a chain with height {} to {}", chain.height(), chain_file);
+}
+
+fn verify_chain(matches: &ArgMatches) {
+    let params = network(matches);
+    let mut bytes = vec![];
+    File::open(matches.value_of("file").unwrap())
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .expect("Could not read the chain file");
+
+    // A chain is serialized as the sequence of its blocks.
+    let blocks: Vec<Block> = match bincode::deserialize(&bytes) {
+        Ok(blocks) => blocks,
+        Err(err) => fail(&format!("Malformed chain: {}", err)),
+    };
+    match store::replay(&blocks, &params) {
+        Ok(utxo_set) => println!(
+            "Valid chain of the {} network: height {}, {} unspent outputs",
+            params.name(),
+            blocks.len().saturating_sub(1),
+            utxo_set.len()
+        ),
+        Err(err) => fail(&format!("Invalid chain: {}", err)),
+    }
+}
+
+fn print_history(matches: &ArgMatches) {
+    let address: Address = matches.value_of("address").unwrap().parse().expect("Invalid address");
+    let index = load_explorer_index(matches);
+
+    println!("History of {}:", address);
+    for entry in index.get_history(&address) {
+        println!(
+            "  block {}: {} received {}, spent {}",
+            entry.height(),
+            entry.tx_hash(),
+            entry.received(),
+            entry.spent()
+        );
+    }
+    println!("Balance: {}", index.balance(&address));
+}
+
+fn print_chain_transaction(matches: &ArgMatches) {
+    let hash: Hash = match matches.value_of("hash").unwrap().parse() {
+        Ok(hash) => hash,
+        Err(_err) => fail("Invalid transaction hash"),
+    };
+    let index = load_explorer_index(matches);
+    let transaction = match index.get_tx(&hash) {
+        Some(transaction) => transaction,
+        None => fail("The chain has no such transaction"),
+    };
+
+    let kind = if transaction.is_coinbase() { "Coinbase transaction" } else { "Transaction" };
+    println!("{} {}, mined in block {}", kind, hash, transaction.height());
+    println!("Inputs:");
+    for (index, (prev_tx_hash, prev_tx_output_index)) in transaction.inputs().iter().enumerate() {
+        println!("  #{} spends output {} of {}", index, prev_tx_output_index, prev_tx_hash);
     }
-}
\ No newline at end of file
+    println!("Outputs:");
+    for (index, tx_out) in transaction.outputs().iter().enumerate() {
+        println!("  #{} pays {} to {}", index, tx_out.amount(), tx_out.address());
+    }
+}
+
+fn print_wealth(matches: &ArgMatches) {
+    let top: usize = matches.value_of("top").unwrap_or("10")
+        .parse().expect("Invalid number of addresses");
+    let index = load_explorer_index(matches);
+
+    let mut balances: Vec<_> = index.balances().filter(|&(_address, balance)| balance > 0).collect();
+    balances.sort_by_key(|&(_address, balance)| Reverse(balance));
+    let total: u64 = balances.iter().map(|&(_address, balance)| balance).sum();
+
+    println!(
+        "{} coins held by {} addresses, {} transactions",
+        total,
+        balances.len(),
+        index.transaction_count()
+    );
+    for &(address, balance) in balances.iter().take(top) {
+        println!("  {} holds {} ({:.2}%)", address, balance, balance as f64 * 100.0 / total as f64);
+    }
+}
+
+/// The explorer index of the data directory, brought up to date with its blocks.
+fn load_explorer_index(matches: &ArgMatches) -> ExplorerIndex {
+    let mut store = open_store(matches);
+    if store.blocks().expect("Could not read the stored blocks").is_empty() {
+        fail("The data directory has no chain");
+    }
+    ExplorerIndex::load_or_rebuild(&mut store).expect("Could not load the explorer index")
+}
+
+fn open_store(matches: &ArgMatches) -> FileBlockStore {
+    FileBlockStore::open(matches.value_of("data_dir").unwrap()).expect("Could not open the data directory")
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn coinbase_address(wallet: &mut Option<Wallet>) -> Address {
+    match *wallet {
+        Some(ref mut wallet) => wallet.new_address().expect("Could not create an address"),
+        None => random_address(),
+    }
+}
+
+fn random_address() -> Address {
+    let key_pair_generator = KeyPairGenerator::new();
+    let key_pair = key_pair_generator.random_keypair().ok().unwrap();
+    Address::from_pub_key(&key_pair.pub_key())
+}
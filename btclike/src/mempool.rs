@@ -0,0 +1,429 @@
+use bincode;
+use chain_params::ChainParams;
+use crypto::Hash;
+use policy::{PolicyRejections, RelayPolicy};
+use Error;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::collections::hash_map::Values;
+use transaction::SignedTx;
+use transaction::UtxoStore;
+
+/// The fees paid per kilobyte of transactions, the unit of the fee rates of the pool and of
+/// the blocks. Empty transactions are considered to be one byte long.
+pub fn fee_rate(fees: u32, size: usize) -> u64 {
+    fees as u64 * 1000 / size.max(1) as u64
+}
+
+/// A transaction waiting to be included in a block, along with the data used to prioritize it.
+pub struct MempoolEntry {
+    transaction: SignedTx,
+    hash: Hash,
+    fees: u32,
+    size: usize,
+}
+
+impl MempoolEntry {
+    pub fn transaction(&self) -> &SignedTx {
+        &self.transaction
+    }
+
+    pub fn hash(&self) -> &Hash {
+        &self.hash
+    }
+
+    pub fn fees(&self) -> &u32 {
+        &self.fees
+    }
+
+    /// The size of the serialized transaction, in bytes.
+    pub fn size(&self) -> &usize {
+        &self.size
+    }
+
+    /// The fees per kilobyte of the transaction, rounded down.
+    pub fn fee_rate(&self) -> u64 {
+        fee_rate(self.fees, self.size)
+    }
+
+    /// Compares the fees per byte of both entries without losing precision.
+    fn cmp_fee_rate(&self, other: &MempoolEntry) -> Ordering {
+        let fee_rate = self.fees as u64 * other.size as u64;
+        let other_fee_rate = other.fees as u64 * self.size as u64;
+        fee_rate.cmp(&other_fee_rate)
+    }
+}
+
+/// How the pool handles a transaction spending an output already spent by a pending one.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ConflictPolicy {
+    /// The pending transactions are kept and the new one is rejected.
+    #[default]
+    FirstSeen,
+    /// The new transaction replaces the pending ones it conflicts with if its fees exceed
+    /// their total fees by at least the given bump.
+    ReplaceByFee { min_fee_bump: u32 },
+}
+
+/// The pool of the valid transactions that are not confirmed yet.
+/// Every transaction of the pool is valid against the same UTXO set and no two of them spend
+/// the same output, so any subset of the pool can be included in the next block.
+#[derive(Default)]
+pub struct Mempool {
+    entries: HashMap<Hash, MempoolEntry>,
+    /// The hash of the pending transaction spending each output.
+    spent_outputs: HashMap<(Hash, u8), Hash>,
+    conflict_policy: ConflictPolicy,
+    /// The rules of the network the transactions are verified against, the main one by default.
+    params: ChainParams,
+    /// The rules the valid transactions must also follow to enter the pool.
+    relay_policy: RelayPolicy,
+    double_spend_attempts: u64,
+    replacements: u64,
+    policy_rejections: PolicyRejections,
+}
+
+impl Mempool {
+    pub fn new() -> Mempool {
+        Mempool::default()
+    }
+
+    pub fn with_conflict_policy(mut self, conflict_policy: ConflictPolicy) -> Mempool {
+        self.conflict_policy = conflict_policy;
+        self
+    }
+
+    pub fn with_params(mut self, params: ChainParams) -> Mempool {
+        self.params = params;
+        self
+    }
+
+    pub fn with_relay_policy(mut self, relay_policy: RelayPolicy) -> Mempool {
+        self.relay_policy = relay_policy;
+        self
+    }
+
+    /// Verifies the transaction against the UTXO set, as part of the block at the given height,
+    /// and adds it to the pool. Fails if the transaction is invalid, refused by the relay policy
+    /// of the pool or already known.
+    /// A transaction spending an output already spent by pending transactions is a double
+    /// spend attempt, handled according to the conflict policy of the pool.
+    pub fn add<S>(&mut self, transaction: SignedTx, utxo_store: &S, height: u32) -> Result<Hash, Error>
+        where S: UtxoStore
+    {
+        let hash = transaction.hash()?;
+        if self.entries.contains_key(&hash) {
+            return Err(Error::DuplicateTransaction);
+        }
+
+        let fees = transaction.verify(utxo_store, height, &self.params)?;
+        let size = bincode::serialize(&transaction)?.len();
+        if let Err(err) = self.relay_policy.check(&transaction, fees, size) {
+            self.policy_rejections.record(&err);
+            return Err(err);
+        }
+
+        let mut outputs = vec![];
+        let mut conflicts = vec![];
+        for tx_in in transaction.input() {
+            let output = (tx_in.prev_tx_hash().clone(), *tx_in.prev_tx_output_index());
+            if outputs.contains(&output) {
+                return Err(Error::DoubleSpend);
+            }
+            if let Some(conflict) = self.spent_outputs.get(&output) {
+                if !conflicts.contains(conflict) {
+                    conflicts.push(conflict.clone());
+                }
+            }
+            outputs.push(output);
+        }
+
+        if !conflicts.is_empty() {
+            self.double_spend_attempts += 1;
+
+            match self.conflict_policy {
+                ConflictPolicy::FirstSeen => return Err(Error::DoubleSpend),
+                ConflictPolicy::ReplaceByFee { min_fee_bump } => {
+                    let conflicting_fees: u64 = conflicts.iter()
+                        .map(|conflict| self.entries[conflict].fees as u64)
+                        .sum();
+                    if (fees as u64) < conflicting_fees + min_fee_bump as u64 {
+                        return Err(Error::InsufficientFeeBump);
+                    }
+
+                    for conflict in conflicts {
+                        self.remove(&conflict);
+                    }
+                    self.replacements += 1;
+                },
+            }
+        }
+
+        for output in outputs {
+            self.spent_outputs.insert(output, hash.clone());
+        }
+        self.entries.insert(hash.clone(), MempoolEntry{
+            transaction,
+            hash: hash.clone(),
+            fees,
+            size,
+        });
+
+        Ok(hash)
+    }
+
+    pub fn remove(&mut self, hash: &Hash) -> Option<SignedTx> {
+        let entry = self.entries.remove(hash)?;
+
+        for tx_in in entry.transaction.input() {
+            let output = (tx_in.prev_tx_hash().clone(), *tx_in.prev_tx_output_index());
+            self.spent_outputs.remove(&output);
+        }
+
+        Some(entry.transaction)
+    }
+
+    /// Drops the transactions that are not valid anymore against the new UTXO set, as part of
+    /// the block at the given height, typically because they were confirmed by a new block or
+    /// conflict with one of its transactions. The relay policy was checked when they entered
+    /// the pool and is not checked again.
+    pub fn update<S>(&mut self, utxo_store: &S, height: u32)
+        where S: UtxoStore
+    {
+        let invalid: Vec<Hash> = self.entries.values()
+            .filter(|entry| entry.transaction.verify(utxo_store, height, &self.params).is_err())
+            .map(|entry| entry.hash.clone())
+            .collect();
+
+        for hash in invalid {
+            self.remove(&hash);
+        }
+    }
+
+    /// Returns up to `max_count` transactions, the ones with the highest fees per byte first.
+    pub fn select_for_block(&self, max_count: usize) -> Vec<&MempoolEntry> {
+        let mut entries: Vec<&MempoolEntry> = self.entries.values().collect();
+        entries.sort_by(|one, other| {
+            other.cmp_fee_rate(one).then_with(|| one.hash.cmp(&other.hash))
+        });
+        entries.truncate(max_count);
+        entries
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&MempoolEntry> {
+        self.entries.get(hash)
+    }
+
+    /// Every pending transaction, in no particular order.
+    pub fn entries(&self) -> Values<'_, Hash, MempoolEntry> {
+        self.entries.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn conflict_policy(&self) -> ConflictPolicy {
+        self.conflict_policy
+    }
+
+    pub fn relay_policy(&self) -> &RelayPolicy {
+        &self.relay_policy
+    }
+
+    /// The number of transactions that spent an output already spent by pending ones,
+    /// whether they were accepted or not.
+    pub fn double_spend_attempts(&self) -> u64 {
+        self.double_spend_attempts
+    }
+
+    /// The number of transactions accepted in place of the pending ones they conflicted with.
+    pub fn replacements(&self) -> u64 {
+        self.replacements
+    }
+
+    /// The number of valid transactions refused by the relay policy, by rule.
+    pub fn policy_rejections(&self) -> PolicyRejections {
+        self.policy_rejections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blockchain::{Body, COINBASE_AMOUNT, COINBASE_MATURITY};
+    use transaction::TxOut;
+    use utxo::UtxoSet;
+    use wallet::Wallet;
+
+    /// The height of the next block, once the coinbase outputs of the funded wallets are mature.
+    const NEXT_HEIGHT: u32 = COINBASE_MATURITY + 3;
+
+    #[test]
+    fn accepts_valid_transactions_once() {
+        let (mut wallets, utxo_set) = funded_wallets(1);
+        let mut mempool = Mempool::new();
+
+        let transaction = new_payment(&mut wallets[0], 100, 5, &utxo_set);
+        let hash = mempool.add(transaction.clone(), &utxo_set, NEXT_HEIGHT).unwrap();
+        assert!(mempool.contains(&hash));
+        assert_eq!(&5, mempool.get(&hash).unwrap().fees());
+
+        assert_eq!(Error::DuplicateTransaction, mempool.add(transaction, &utxo_set, NEXT_HEIGHT).err().unwrap());
+        assert_eq!(1, mempool.len());
+    }
+
+    #[test]
+    fn rejects_invalid_transactions() {
+        let (mut wallets, utxo_set) = funded_wallets(1);
+        let mut mempool = Mempool::new();
+
+        let transaction = new_payment(&mut wallets[0], 100, 5, &utxo_set);
+        assert_eq!(Error::UtxoNotFound, mempool.add(transaction, &UtxoSet::new(), NEXT_HEIGHT).err().unwrap());
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn refuses_transactions_against_the_relay_policy() {
+        let (mut wallets, utxo_set) = funded_wallets(1);
+        let transaction = new_payment(&mut wallets[0], 100, 5, &utxo_set);
+        let size = bincode::serialize(&transaction).unwrap().len();
+
+        let policies = vec![
+            (RelayPolicy::new().with_min_relay_fee_rate(fee_rate(5, size) + 1), Error::InsufficientFee),
+            (RelayPolicy::new().with_max_tx_size(size - 1), Error::TransactionTooLarge),
+            (RelayPolicy::new().with_dust_threshold(101), Error::DustOutput),
+        ];
+        let mut policy_rejections = PolicyRejections::default();
+        for (relay_policy, error) in policies {
+            let mut mempool = Mempool::new().with_relay_policy(relay_policy);
+            assert_eq!(error, mempool.add(transaction.clone(), &utxo_set, NEXT_HEIGHT).err().unwrap());
+            assert!(mempool.is_empty());
+            assert_eq!(1, mempool.policy_rejections().total());
+            policy_rejections = policy_rejections + mempool.policy_rejections();
+        }
+        assert_eq!(PolicyRejections { insufficient_fee: 1, too_large: 1, dust: 1 }, policy_rejections);
+
+        let mut mempool = Mempool::new().with_relay_policy(
+            RelayPolicy::new().with_min_relay_fee_rate(fee_rate(5, size)).with_dust_threshold(100)
+        );
+        mempool.add(transaction.clone(), &utxo_set, NEXT_HEIGHT).unwrap();
+
+        // The blocks follow the consensus rules only, whatever the policies refuse.
+        let params = ChainParams::main();
+        let coinbase_tx_out = TxOut::new(params.block_reward(NEXT_HEIGHT) + 5, wallets[0].new_address().unwrap());
+        Body::new(coinbase_tx_out, vec![transaction]).verify(&utxo_set, NEXT_HEIGHT, &params).unwrap();
+    }
+
+    #[test]
+    fn rejects_double_spends() {
+        let (mut wallets, utxo_set) = funded_wallets(1);
+        let mut mempool = Mempool::new();
+
+        let transaction = new_payment(&mut wallets[0], 100, 5, &utxo_set);
+        let conflicting_transaction = new_payment(&mut wallets[0], 200, 5, &utxo_set);
+        let hash = mempool.add(transaction, &utxo_set, NEXT_HEIGHT).unwrap();
+        assert_eq!(
+            Error::DoubleSpend,
+            mempool.add(conflicting_transaction.clone(), &utxo_set, NEXT_HEIGHT).err().unwrap()
+        );
+
+        assert_eq!(1, mempool.double_spend_attempts());
+        assert_eq!(0, mempool.replacements());
+
+        // Once the first one is gone, the conflicting one is acceptable.
+        mempool.remove(&hash).unwrap();
+        mempool.add(conflicting_transaction, &utxo_set, NEXT_HEIGHT).unwrap();
+        assert_eq!(1, mempool.double_spend_attempts());
+    }
+
+    #[test]
+    fn replaces_transactions_paying_enough_fees() {
+        let (mut wallets, utxo_set) = funded_wallets(1);
+        let mut mempool = Mempool::new()
+            .with_conflict_policy(ConflictPolicy::ReplaceByFee { min_fee_bump: 5 });
+
+        let transaction = new_payment(&mut wallets[0], 100, 5, &utxo_set);
+        let hash = mempool.add(transaction, &utxo_set, NEXT_HEIGHT).unwrap();
+
+        let low_fee_transaction = new_payment(&mut wallets[0], 100, 9, &utxo_set);
+        assert_eq!(
+            Error::InsufficientFeeBump,
+            mempool.add(low_fee_transaction, &utxo_set, NEXT_HEIGHT).err().unwrap()
+        );
+        assert!(mempool.contains(&hash));
+
+        let replacement = new_payment(&mut wallets[0], 100, 10, &utxo_set);
+        let replacement_hash = mempool.add(replacement, &utxo_set, NEXT_HEIGHT).unwrap();
+        assert!(!mempool.contains(&hash));
+        assert!(mempool.contains(&replacement_hash));
+        assert_eq!(1, mempool.len());
+
+        assert_eq!(2, mempool.double_spend_attempts());
+        assert_eq!(1, mempool.replacements());
+    }
+
+    #[test]
+    fn selects_the_highest_fee_rates_first() {
+        let (mut wallets, utxo_set) = funded_wallets(3);
+        let mut mempool = Mempool::new();
+
+        for (wallet, fees) in wallets.iter_mut().zip(&[5, 20, 10]) {
+            let transaction = new_payment(wallet, 100, *fees, &utxo_set);
+            mempool.add(transaction, &utxo_set, NEXT_HEIGHT).unwrap();
+        }
+
+        let selected_fees: Vec<u32> = mempool.select_for_block(2).iter()
+            .map(|entry| *entry.fees())
+            .collect();
+        assert_eq!(vec![20, 10], selected_fees);
+    }
+
+    #[test]
+    fn drops_confirmed_transactions() {
+        let (mut wallets, mut utxo_set) = funded_wallets(2);
+        let mut mempool = Mempool::new();
+
+        let confirmed_transaction = new_payment(&mut wallets[0], 100, 0, &utxo_set);
+        let confirmed_hash = mempool.add(confirmed_transaction.clone(), &utxo_set, NEXT_HEIGHT).unwrap();
+        let pending_transaction = new_payment(&mut wallets[1], 100, 0, &utxo_set);
+        let pending_hash = mempool.add(pending_transaction, &utxo_set, NEXT_HEIGHT).unwrap();
+
+        let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT, wallets[0].new_address().unwrap());
+        utxo_set.apply(&Body::new(coinbase_tx_out, vec![confirmed_transaction]), NEXT_HEIGHT).unwrap();
+        mempool.update(&utxo_set, NEXT_HEIGHT + 1);
+
+        assert!(!mempool.contains(&confirmed_hash));
+        assert!(mempool.contains(&pending_hash));
+    }
+
+    /// Returns the given number of wallets, each of them owning a single coinbase output.
+    fn funded_wallets(number_of_wallets: usize) -> (Vec<Wallet>, UtxoSet) {
+        let mut wallets = vec![];
+        let mut utxo_set = UtxoSet::new();
+
+        for height in 0..number_of_wallets {
+            let mut wallet = Wallet::new();
+            let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT, wallet.new_address().unwrap());
+            let body = Body::new(coinbase_tx_out, vec![]);
+            utxo_set.apply(&body, height as u32).unwrap();
+            wallet.connect_block(&body, height as u32).unwrap();
+            wallets.push(wallet);
+        }
+
+        (wallets, utxo_set)
+    }
+
+    fn new_payment(wallet: &mut Wallet, amount: u32, fees: u32, utxo_set: &UtxoSet) -> SignedTx {
+        let to_address = wallet.new_address().unwrap();
+        wallet.new_transaction(amount, to_address, fees, utxo_set).unwrap()
+    }
+}
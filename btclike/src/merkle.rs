@@ -0,0 +1,143 @@
+use crypto::hash;
+use crypto::Hash;
+
+/// A binary hash tree over the transactions of a body. When a level has an odd number of
+/// nodes, the last one is paired with itself, like in Bitcoin.
+pub struct MerkleTree {
+    /// From the leaves up to the root.
+    levels: Vec<Vec<Hash>>,
+}
+
+impl MerkleTree {
+    /// The root of a tree without any leaf is `Hash::min()`.
+    pub fn new(leaves: Vec<Hash>) -> MerkleTree {
+        let mut levels = vec![leaves];
+
+        while levels[levels.len() - 1].len() > 1 {
+            let level = {
+                let previous_level = &levels[levels.len() - 1];
+                previous_level
+                    .chunks(2)
+                    .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+                    .collect()
+            };
+            levels.push(level);
+        }
+
+        MerkleTree { levels }
+    }
+
+    pub fn root(&self) -> Hash {
+        self.levels[self.levels.len() - 1]
+            .first()
+            .cloned()
+            .unwrap_or_else(Hash::min)
+    }
+
+    pub fn leaves(&self) -> &[Hash] {
+        &self.levels[0]
+    }
+
+    /// The proof that the leaf at the given index is part of the tree, if there is one.
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        if index >= self.leaves().len() {
+            return None;
+        }
+
+        let mut siblings = vec![];
+        let mut level_index = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling_index = level_index ^ 1;
+            siblings.push(level.get(sibling_index).unwrap_or(&level[level_index]).clone());
+            level_index /= 2;
+        }
+
+        Some(MerkleProof { index, siblings })
+    }
+}
+
+/// The hashes needed to compute the root of a tree from one of its leaves.
+#[derive(Clone)]
+pub struct MerkleProof {
+    /// The position of the leaf, which tells on which side each sibling goes.
+    index: usize,
+    /// From the sibling of the leaf up to the child of the root.
+    siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn verify(&self, leaf: &Hash, root: &Hash) -> bool {
+        let mut computed = leaf.clone();
+        let mut index = self.index;
+
+        for sibling in &self.siblings {
+            computed = if index & 1 == 0 {
+                hash_pair(&computed, sibling)
+            } else {
+                hash_pair(sibling, &computed)
+            };
+            index /= 2;
+        }
+
+        index == 0 && &computed == root
+    }
+}
+
+fn hash_pair(left: &Hash, right: &Hash) -> Hash {
+    let mut bytes = Vec::with_capacity(left.as_ref().len() + right.as_ref().len());
+    bytes.extend_from_slice(left.as_ref());
+    bytes.extend_from_slice(right.as_ref());
+    hash(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_prove_every_leaf() {
+        for leaf_count in 1..10 {
+            let tree = MerkleTree::new(leaves(leaf_count));
+            let root = tree.root();
+
+            for (index, leaf) in tree.leaves().iter().enumerate() {
+                assert!(tree.proof(index).unwrap().verify(leaf, &root));
+            }
+
+            assert!(tree.proof(leaf_count as usize).is_none());
+        }
+    }
+
+    #[test]
+    fn a_single_leaf_is_the_root() {
+        let tree = MerkleTree::new(leaves(1));
+        assert!(tree.root() == hash(&[0]));
+        assert!(MerkleTree::new(vec![]).root() == Hash::min());
+    }
+
+    #[test]
+    fn rejects_invalid_proofs() {
+        let tree = MerkleTree::new(leaves(5));
+        let root = tree.root();
+        let proof = tree.proof(2).unwrap();
+
+        assert!(!proof.verify(&hash(&[3]), &root));
+        assert!(!proof.verify(&hash(&[2]), &MerkleTree::new(leaves(4)).root()));
+
+        let mut wrong_index = proof.clone();
+        wrong_index.index = 3;
+        assert!(!wrong_index.verify(&hash(&[2]), &root));
+
+        let mut wrong_sibling = proof;
+        wrong_sibling.siblings[0] = hash(&[42]);
+        assert!(!wrong_sibling.verify(&hash(&[2]), &root));
+    }
+
+    fn leaves(count: u8) -> Vec<Hash> {
+        (0..count).map(|index| hash(&[index])).collect()
+    }
+}
@@ -0,0 +1,127 @@
+//! The rules a node applies to the transactions it relays, on top of the consensus ones.
+//!
+//! `SignedTx::verify` and `Block::verify` check the consensus rules: every node applies the
+//! same ones, and a block breaking them is rejected by all. The relay policy is local to a
+//! node: it only decides which unconfirmed transactions the node keeps in its pool and passes
+//! on, so nodes with different policies still accept the same blocks, including the ones
+//! confirming transactions their own policy refused.
+
+use mempool::fee_rate;
+use std::ops::Add;
+use transaction::SignedTx;
+use Error;
+
+/// The largest serialized transaction relayed by default, in bytes: a tenth of the default
+/// maximum block size.
+pub const DEFAULT_MAX_TX_SIZE: usize = 100_000;
+
+/// The number of valid transactions a pool refused, by rule of its relay policy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PolicyRejections {
+    pub insufficient_fee: u64,
+    pub too_large: u64,
+    pub dust: u64,
+}
+
+impl PolicyRejections {
+    /// Counts the error if it is one of the relay policy.
+    pub fn record(&mut self, err: &Error) {
+        match *err {
+            Error::InsufficientFee => self.insufficient_fee += 1,
+            Error::TransactionTooLarge => self.too_large += 1,
+            Error::DustOutput => self.dust += 1,
+            _ => {}
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.insufficient_fee + self.too_large + self.dust
+    }
+}
+
+impl Add for PolicyRejections {
+    type Output = PolicyRejections;
+
+    fn add(self, other: PolicyRejections) -> PolicyRejections {
+        PolicyRejections {
+            insufficient_fee: self.insufficient_fee + other.insufficient_fee,
+            too_large: self.too_large + other.too_large,
+            dust: self.dust + other.dust,
+        }
+    }
+}
+
+/// Which valid transactions a node accepts in its pool. By default, any transaction but the
+/// ones larger than `DEFAULT_MAX_TX_SIZE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RelayPolicy {
+    /// The lowest fees per kilobyte relayed.
+    min_relay_fee_rate: u64,
+    max_tx_size: usize,
+    /// The outputs of a lower amount are not worth the fees of spending them.
+    dust_threshold: u32,
+}
+
+impl Default for RelayPolicy {
+    fn default() -> RelayPolicy {
+        RelayPolicy::new()
+    }
+}
+
+impl RelayPolicy {
+    pub fn new() -> RelayPolicy {
+        RelayPolicy {
+            min_relay_fee_rate: 0,
+            max_tx_size: DEFAULT_MAX_TX_SIZE,
+            dust_threshold: 0,
+        }
+    }
+
+    /// The fees per kilobyte a transaction must pay at least. Default: 0
+    pub fn with_min_relay_fee_rate(mut self, min_relay_fee_rate: u64) -> RelayPolicy {
+        self.min_relay_fee_rate = min_relay_fee_rate;
+        self
+    }
+
+    /// Default: `DEFAULT_MAX_TX_SIZE`
+    pub fn with_max_tx_size(mut self, max_tx_size: usize) -> RelayPolicy {
+        self.max_tx_size = max_tx_size;
+        self
+    }
+
+    /// The amount every output must reach at least. Default: 0
+    pub fn with_dust_threshold(mut self, dust_threshold: u32) -> RelayPolicy {
+        self.dust_threshold = dust_threshold;
+        self
+    }
+
+    pub fn min_relay_fee_rate(&self) -> u64 {
+        self.min_relay_fee_rate
+    }
+
+    pub fn max_tx_size(&self) -> usize {
+        self.max_tx_size
+    }
+
+    pub fn dust_threshold(&self) -> u32 {
+        self.dust_threshold
+    }
+
+    /// Checks a transaction already verified against the consensus rules, given its fees and
+    /// its serialized size in bytes.
+    pub fn check(&self, transaction: &SignedTx, fees: u32, size: usize) -> Result<(), Error> {
+        if size > self.max_tx_size {
+            return Err(Error::TransactionTooLarge);
+        }
+
+        if fee_rate(fees, size) < self.min_relay_fee_rate {
+            return Err(Error::InsufficientFee);
+        }
+
+        if transaction.output().iter().any(|tx_out| *tx_out.amount() < self.dust_threshold) {
+            return Err(Error::DustOutput);
+        }
+
+        Ok(())
+    }
+}
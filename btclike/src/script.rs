@@ -0,0 +1,621 @@
+use crypto::hash;
+use crypto::Hash;
+use crypto::PubKey;
+use crypto::Signature;
+use crypto::SignatureScheme;
+use transaction::Address;
+use Error;
+
+/// The largest number of bytes a push opcode can carry: a byte from 0 to this value pushes
+/// that many following bytes to the stack.
+const MAX_SHORT_PUSH_LEN: usize = 75;
+/// Pushes the number of bytes given by the next byte.
+const PUSH_DATA: u8 = 0x4c;
+/// The largest number of bytes a single push can add to the stack.
+pub const MAX_PUSH_LEN: usize = 255;
+
+/// The opcodes of the scripts, the pushes excepted.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// Duplicates the top of the stack.
+    Dup = 0x76,
+    /// Replaces the top of the stack with its hash.
+    Hash = 0xa8,
+    /// Pops two elements and fails unless they are equal.
+    EqualVerify = 0x88,
+    /// Pops a public key, then a signature, and fails unless the signature of the transaction
+    /// is valid. Pushes `true` otherwise.
+    CheckSig = 0xac,
+    /// Pops a count `n`, `n` addresses and a count `m`, then `m` pairs of a public key and a
+    /// signature. Fails unless each public key matches a distinct address and signed the
+    /// transaction. Pushes `true` otherwise.
+    CheckMultiSig = 0xae,
+    /// Pops a condition, `true` or the empty data for false, and runs the opcodes up to the
+    /// matching `Else` or `EndIf` only if it holds.
+    If = 0x63,
+    /// Runs the opcodes up to the matching `EndIf` only if the ones after the `If` did not run.
+    Else = 0x67,
+    EndIf = 0x68,
+    /// Pops a height, as 4 little-endian bytes, and fails unless the transaction is part of a
+    /// block at this height or above. Unlike Bitcoin, the lock time is not a field of the
+    /// transaction: the height of the block is checked directly.
+    CheckLockTimeVerify = 0xb1,
+}
+
+impl Op {
+    fn from_byte(byte: u8) -> Option<Op> {
+        match byte {
+            0x76 => Some(Op::Dup),
+            0xa8 => Some(Op::Hash),
+            0x88 => Some(Op::EqualVerify),
+            0xac => Some(Op::CheckSig),
+            0xae => Some(Op::CheckMultiSig),
+            0x63 => Some(Op::If),
+            0x67 => Some(Op::Else),
+            0x68 => Some(Op::EndIf),
+            0xb1 => Some(Op::CheckLockTimeVerify),
+            _ => None,
+        }
+    }
+}
+
+const TRUE: &[u8] = &[1];
+
+/// A program run to spend an output: the unlocking script of the input, which may only push
+/// data, runs first, then the locking script of the output runs on the resulting stack.
+/// The output is spent if both succeed and leave nothing but `true` on the stack.
+///
+/// An output paying to the hash of a redeem script is the exception: the last element pushed
+/// by the unlocking script must be a script with this hash, and this redeem script runs
+/// instead of the locking script.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct Script(Vec<u8>);
+
+impl Script {
+    pub fn new() -> Script {
+        Script(vec![])
+    }
+
+    /// Panics if the data is longer than `MAX_PUSH_LEN`.
+    pub fn with_push(mut self, data: &[u8]) -> Script {
+        assert!(data.len() <= MAX_PUSH_LEN, "Pushed data is too long");
+        if data.len() > MAX_SHORT_PUSH_LEN {
+            self.0.push(PUSH_DATA);
+        }
+        self.0.push(data.len() as u8);
+        self.0.extend_from_slice(data);
+        self
+    }
+
+    pub fn with_op(mut self, op: Op) -> Script {
+        self.0.push(op as u8);
+        self
+    }
+
+    /// Requires a signature of the key pair matching a public key address, or the redeem
+    /// script of a script address along with what it requires.
+    pub fn pay_to_address(address: &Address) -> Script {
+        match *address {
+            Address::PubKey(ref pub_key_hash) => Script::new()
+                .with_op(Op::Dup)
+                .with_op(Op::Hash)
+                .with_push(pub_key_hash.as_ref())
+                .with_op(Op::EqualVerify)
+                .with_op(Op::CheckSig),
+            Address::Script(ref script_hash) => Script::new()
+                .with_op(Op::Hash)
+                .with_push(script_hash.as_ref())
+                .with_op(Op::EqualVerify),
+        }
+    }
+
+    /// Requires signatures of `required` distinct key pairs among the ones matching the
+    /// addresses. Panics if there are more than 255 addresses.
+    pub fn multisig(required: u8, addresses: &[Address]) -> Script {
+        assert!(addresses.len() <= u8::MAX as usize, "Too many addresses");
+
+        let mut script = Script::new().with_push(&[required]);
+        for address in addresses {
+            script = script.with_push(address.as_hash().as_ref());
+        }
+
+        script
+            .with_push(&[addresses.len() as u8])
+            .with_op(Op::CheckMultiSig)
+    }
+
+    /// A hash time lock: the recipient spends the outputs with a signature and the secret
+    /// matching the hash, the refund address with a signature only, from the lock height on.
+    /// Both must be public key addresses.
+    ///
+    /// The unlocking script pushes the signature, the secret and `true` to claim the outputs,
+    /// the signature and false to take them back.
+    pub fn hash_time_lock(
+        secret_hash: &Hash,
+        recipient: &Address,
+        refund_address: &Address,
+        lock_height: u32,
+    ) -> Script {
+        Script::new()
+            .with_op(Op::If)
+            .with_op(Op::Hash)
+            .with_push(secret_hash.as_ref())
+            .with_op(Op::EqualVerify)
+            .with_op(Op::Dup)
+            .with_op(Op::Hash)
+            .with_push(recipient.as_hash().as_ref())
+            .with_op(Op::Else)
+            .with_push(&lock_height.to_le_bytes())
+            .with_op(Op::CheckLockTimeVerify)
+            .with_op(Op::Dup)
+            .with_op(Op::Hash)
+            .with_push(refund_address.as_hash().as_ref())
+            .with_op(Op::EndIf)
+            .with_op(Op::EqualVerify)
+            .with_op(Op::CheckSig)
+    }
+
+    /// The funding output of a payment channel: the signatures of both the payer and the
+    /// payee spend it, the one of the payer alone from the expiry height on. Both must be
+    /// public key addresses.
+    ///
+    /// The unlocking script pushes both signatures and `true`, or the signature of the payer
+    /// and false.
+    pub fn payment_channel(payer: &Address, payee: &Address, expiry_height: u32) -> Script {
+        let mut script = Script::new().with_op(Op::If);
+        script.0.extend_from_slice(Script::multisig(2, &[payer.clone(), payee.clone()]).as_bytes());
+        script
+            .with_op(Op::Else)
+            .with_push(&expiry_height.to_le_bytes())
+            .with_op(Op::CheckLockTimeVerify)
+            .with_op(Op::Dup)
+            .with_op(Op::Hash)
+            .with_push(payer.as_hash().as_ref())
+            .with_op(Op::EqualVerify)
+            .with_op(Op::CheckSig)
+            .with_op(Op::EndIf)
+    }
+
+    /// Pushes the condition an `If` pops: `true`, or the empty data for false.
+    pub fn with_condition(self, condition: bool) -> Script {
+        if condition {
+            self.with_push(TRUE)
+        } else {
+            self.with_push(&[])
+        }
+    }
+
+    /// The unlocking script of the outputs requiring the given signatures.
+    pub fn signatures(signatures: &[(PubKey, Signature)]) -> Script {
+        signatures.iter().fold(Script::new(), |script, (pub_key, signature)| {
+            script.with_push(signature.as_bytes()).with_push(pub_key.as_bytes())
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The data pushed by a script that only pushes data, such as an unlocking script. None if
+    /// it runs any other opcode or is malformed.
+    pub fn pushes(&self) -> Option<Vec<Vec<u8>>> {
+        let mut pushes = vec![];
+        let mut position = 0;
+        while position < self.0.len() {
+            let (data, next) = self.push_at(position).ok()??;
+            pushes.push(data.to_vec());
+            position = next;
+        }
+        Some(pushes)
+    }
+
+    /// The address the outputs locked by this script are known by: the one they are sent to
+    /// for a `pay_to_address` script, the script address of this script otherwise.
+    pub fn address(&self) -> Address {
+        let bytes = &self.0;
+        let is_pay_to_pub_key = bytes.len() == 37
+            && bytes[0] == Op::Dup as u8
+            && bytes[1] == Op::Hash as u8
+            && bytes[2] == 32
+            && bytes[35] == Op::EqualVerify as u8
+            && bytes[36] == Op::CheckSig as u8;
+
+        if is_pay_to_pub_key {
+            if let Some(pub_key_hash) = Hash::from_bytes(&bytes[3..35]) {
+                return Address::PubKey(pub_key_hash);
+            }
+        }
+
+        match self.script_hash() {
+            Some(script_hash) => Address::Script(script_hash),
+            None => Address::from_script(self),
+        }
+    }
+
+    /// The hash of the redeem script, if this script pays to a script address.
+    pub fn script_hash(&self) -> Option<Hash> {
+        let bytes = &self.0;
+        let is_pay_to_script_hash = bytes.len() == 35
+            && bytes[0] == Op::Hash as u8
+            && bytes[1] == 32
+            && bytes[34] == Op::EqualVerify as u8;
+
+        if is_pay_to_script_hash {
+            Hash::from_bytes(&bytes[2..34])
+        } else {
+            None
+        }
+    }
+
+    /// The data pushed by the byte at the given position and the position after it, none if
+    /// this byte is an opcode.
+    fn push_at(&self, position: usize) -> Result<Option<(&[u8], usize)>, Error> {
+        let byte = self.0[position];
+        if byte as usize > MAX_SHORT_PUSH_LEN && byte != PUSH_DATA {
+            return Ok(None);
+        }
+
+        let mut start = position + 1;
+        let mut len = byte as usize;
+        if byte == PUSH_DATA {
+            len = *self.0.get(start).ok_or(Error::InvalidScript)? as usize;
+            start += 1;
+        }
+
+        let end = start + len;
+        if end > self.0.len() {
+            return Err(Error::InvalidScript);
+        }
+        Ok(Some((&self.0[start..end], end)))
+    }
+
+    fn execute(
+        &self,
+        stack: &mut Vec<Vec<u8>>,
+        context: &Context,
+        push_only: bool,
+    ) -> Result<(), Error> {
+        // Whether each of the enclosing `If` branches runs.
+        let mut branches: Vec<bool> = vec![];
+        let mut position = 0;
+        while position < self.0.len() {
+            let running = branches.iter().all(|runs| *runs);
+
+            if let Some((data, next)) = self.push_at(position)? {
+                if running {
+                    stack.push(data.to_vec());
+                }
+                position = next;
+                continue;
+            }
+
+            let byte = self.0[position];
+            position += 1;
+            let op = match Op::from_byte(byte) {
+                Some(op) if !push_only => op,
+                _ => return Err(Error::InvalidScript),
+            };
+
+            match op {
+                Op::If => {
+                    let runs = running && pop_condition(stack)?;
+                    branches.push(runs);
+                },
+                Op::Else => {
+                    let runs = branches.last_mut().ok_or(Error::InvalidScript)?;
+                    *runs = !*runs;
+                },
+                Op::EndIf => {
+                    branches.pop().ok_or(Error::InvalidScript)?;
+                },
+                _ if !running => {},
+                Op::Dup => {
+                    let top = stack.last().ok_or(Error::InvalidScript)?.clone();
+                    stack.push(top);
+                },
+                Op::Hash => {
+                    let top = pop(stack)?;
+                    stack.push(hash(&top).as_ref().to_vec());
+                },
+                Op::EqualVerify => {
+                    if pop(stack)? != pop(stack)? {
+                        return Err(Error::InvalidScript);
+                    }
+                },
+                Op::CheckSig => {
+                    let (pub_key, signature) = pop_signature(stack)?;
+                    context.scheme.verify(&pub_key, context.tx_bytes, &signature)?;
+                    stack.push(TRUE.to_vec());
+                },
+                Op::CheckMultiSig => {
+                    let address_count = pop_count(stack)?;
+                    let mut addresses = vec![];
+                    for _i in 0..address_count {
+                        let address_hash = Hash::from_bytes(&pop(stack)?).ok_or(Error::InvalidScript)?;
+                        addresses.push(Address::from_hash(address_hash));
+                    }
+
+                    let required = pop_count(stack)?;
+                    if required == 0 || required > address_count {
+                        return Err(Error::InvalidScript);
+                    }
+
+                    let mut signed = vec![false; addresses.len()];
+                    for _i in 0..required {
+                        let (pub_key, signature) = pop_signature(stack)?;
+                        let address = Address::from_pub_key(&pub_key);
+                        let position = addresses.iter()
+                            .enumerate()
+                            .position(|(index, candidate)| !signed[index] && candidate == &address)
+                            .ok_or(Error::InvalidScript)?;
+                        signed[position] = true;
+
+                        context.scheme.verify(&pub_key, context.tx_bytes, &signature)?;
+                    }
+
+                    stack.push(TRUE.to_vec());
+                },
+                Op::CheckLockTimeVerify => {
+                    let lock_height = match pop(stack)?.as_slice() {
+                        [a, b, c, d] => u32::from_le_bytes([*a, *b, *c, *d]),
+                        _ => return Err(Error::InvalidScript),
+                    };
+                    if context.height < lock_height {
+                        return Err(Error::InvalidScript);
+                    }
+                },
+            }
+        }
+
+        if branches.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidScript)
+        }
+    }
+}
+
+/// What the scripts of an input check the spending transaction against.
+struct Context<'a> {
+    /// The message the signatures of the input sign, see `SigHasher`.
+    tx_bytes: &'a [u8],
+    /// The height of the block the transaction is part of.
+    height: u32,
+    scheme: &'a dyn SignatureScheme,
+}
+
+/// Runs the unlocking script of an input, then the locking script of the output it spends,
+/// against the message the signatures of the input must sign with the given scheme, as part
+/// of the block at the given height.
+pub fn verify(
+    unlock_script: &Script,
+    lock_script: &Script,
+    tx_bytes: &[u8],
+    height: u32,
+    scheme: &dyn SignatureScheme,
+) -> Result<(), Error> {
+    let context = Context {
+        tx_bytes,
+        height,
+        scheme,
+    };
+    let mut stack = vec![];
+    unlock_script.execute(&mut stack, &context, true)?;
+
+    match lock_script.script_hash() {
+        Some(script_hash) => {
+            let redeem_script = Script(pop(&mut stack)?);
+            if hash(redeem_script.as_bytes()) != script_hash {
+                return Err(Error::InvalidScript);
+            }
+
+            redeem_script.execute(&mut stack, &context, false)?;
+        },
+        None => lock_script.execute(&mut stack, &context, false)?,
+    }
+
+    if stack.len() == 1 && stack[0] == TRUE {
+        Ok(())
+    } else {
+        Err(Error::InvalidScript)
+    }
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, Error> {
+    stack.pop().ok_or(Error::InvalidScript)
+}
+
+fn pop_condition(stack: &mut Vec<Vec<u8>>) -> Result<bool, Error> {
+    match pop(stack)?.as_slice() {
+        [] => Ok(false),
+        TRUE => Ok(true),
+        _ => Err(Error::InvalidScript),
+    }
+}
+
+fn pop_count(stack: &mut Vec<Vec<u8>>) -> Result<u8, Error> {
+    match pop(stack)?.as_slice() {
+        [count] => Ok(*count),
+        _ => Err(Error::InvalidScript),
+    }
+}
+
+fn pop_signature(stack: &mut Vec<Vec<u8>>) -> Result<(PubKey, Signature), Error> {
+    let pub_key = PubKey::from_bytes(&pop(stack)?).ok_or(Error::InvalidScript)?;
+    let signature = Signature::from_bytes(&pop(stack)?).ok_or(Error::InvalidScript)?;
+    Ok((pub_key, signature))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::{Ed25519, KeyPair, Secp256k1, SignatureAlgorithm};
+    use crypto::KeyPairGenerator;
+
+    const TX_BYTES: &[u8] = b"transaction";
+
+    #[test]
+    fn can_spend_pay_to_address_outputs() {
+        let key_pair = random_key_pair();
+        let lock_script = Script::pay_to_address(&Address::from_pub_key(&key_pair.pub_key()));
+
+        verify(&signatures(&[&key_pair]), &lock_script, TX_BYTES, 0, &Ed25519).ok().unwrap();
+
+        // Another key pair, or a signature of another transaction.
+        assert!(verify(&signatures(&[&random_key_pair()]), &lock_script, TX_BYTES, 0, &Ed25519).is_err());
+        assert!(verify(&signatures(&[&key_pair]), &lock_script, b"other transaction", 0, &Ed25519).is_err());
+
+        // Nothing must be left on the stack.
+        assert_eq!(
+            Error::InvalidScript,
+            verify(&signatures(&[&key_pair, &key_pair]), &lock_script, TX_BYTES, 0, &Ed25519).err().unwrap()
+        );
+        assert_eq!(Error::InvalidScript, verify(&Script::new(), &lock_script, TX_BYTES, 0, &Ed25519).err().unwrap());
+    }
+
+    #[test]
+    fn can_spend_multisig_outputs() {
+        let key_pairs = [random_key_pair(), random_key_pair(), random_key_pair()];
+        let addresses: Vec<Address> = key_pairs.iter()
+            .map(|key_pair| Address::from_pub_key(&key_pair.pub_key()))
+            .collect();
+        let lock_script = Script::multisig(2, &addresses);
+
+        verify(&signatures(&[&key_pairs[0], &key_pairs[2]]), &lock_script, TX_BYTES, 0, &Ed25519).ok().unwrap();
+        verify(&signatures(&[&key_pairs[2], &key_pairs[1]]), &lock_script, TX_BYTES, 0, &Ed25519).ok().unwrap();
+
+        assert!(verify(&signatures(&[&key_pairs[0]]), &lock_script, TX_BYTES, 0, &Ed25519).is_err());
+        assert!(verify(&signatures(&[&key_pairs[0], &key_pairs[0]]), &lock_script, TX_BYTES, 0, &Ed25519).is_err());
+        assert!(verify(
+            &signatures(&[&key_pairs[0], &random_key_pair()]), &lock_script, TX_BYTES, 0, &Ed25519
+        ).is_err());
+
+        // A condition requiring no signature, or more than it lists, cannot be met.
+        assert!(verify(&Script::new(), &Script::multisig(0, &addresses), TX_BYTES, 0, &Ed25519).is_err());
+        let too_many = Script::multisig(2, &addresses[..1]);
+        assert!(verify(&signatures(&[&key_pairs[0], &key_pairs[0]]), &too_many, TX_BYTES, 0, &Ed25519).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_scripts() {
+        let key_pair = random_key_pair();
+        let lock_script = Script::pay_to_address(&Address::from_pub_key(&key_pair.pub_key()));
+
+        // The unlocking script may only push data.
+        let unlock_script = signatures(&[&key_pair]).with_op(Op::Dup);
+        assert!(verify(&unlock_script, &lock_script, TX_BYTES, 0, &Ed25519).is_err());
+
+        // Unknown opcodes and truncated pushes.
+        assert!(verify(&Script::new(), &Script(vec![1, 1, 0xff]), TX_BYTES, 0, &Ed25519).is_err());
+        assert!(verify(&Script::new(), &Script(vec![2, 1]), TX_BYTES, 0, &Ed25519).is_err());
+
+        // A lone `true` is enough, which makes the outputs locked by it spendable by anyone.
+        verify(&Script::new(), &Script::new().with_push(TRUE), TX_BYTES, 0, &Ed25519).ok().unwrap();
+    }
+
+    #[test]
+    fn knows_the_address_of_the_outputs() {
+        let address = Address::from_pub_key(&random_key_pair().pub_key());
+        assert!(address == Script::pay_to_address(&address).address());
+
+        let multisig = Script::multisig(1, ::std::slice::from_ref(&address));
+        assert!(address != multisig.address());
+        assert!(Address::from_script(&multisig) == multisig.address());
+
+        // Paying to the hash of the script is another way to lock outputs with it.
+        let pay_to_script_hash = Script::pay_to_address(&multisig.address());
+        assert!(multisig.address() == pay_to_script_hash.address());
+        assert!(pay_to_script_hash.script_hash().unwrap() == *multisig.address().as_hash());
+        assert!(multisig.script_hash().is_none());
+    }
+
+    #[test]
+    fn can_spend_pay_to_script_hash_outputs() {
+        let key_pairs = [random_key_pair(), random_key_pair(), random_key_pair()];
+        let addresses: Vec<Address> = key_pairs.iter()
+            .map(|key_pair| Address::from_pub_key(&key_pair.pub_key()))
+            .collect();
+        let redeem_script = Script::multisig(2, &addresses);
+        assert!(redeem_script.as_bytes().len() > MAX_SHORT_PUSH_LEN);
+        let lock_script = Script::pay_to_address(&Address::from_script(&redeem_script));
+
+        let unlock_script = signatures(&[&key_pairs[0], &key_pairs[1]]);
+        verify(&unlock_script.clone().with_push(redeem_script.as_bytes()), &lock_script, TX_BYTES, 0, &Ed25519)
+            .ok().unwrap();
+
+        // The redeem script must be revealed, must match the hash and its conditions must be met.
+        assert!(verify(&unlock_script, &lock_script, TX_BYTES, 0, &Ed25519).is_err());
+        let other_redeem_script = Script::multisig(1, &addresses);
+        assert!(verify(
+            &unlock_script.clone().with_push(other_redeem_script.as_bytes()), &lock_script, TX_BYTES, 0, &Ed25519
+        ).is_err());
+        assert!(verify(
+            &signatures(&[&key_pairs[0]]).with_push(redeem_script.as_bytes()), &lock_script, TX_BYTES, 0, &Ed25519
+        ).is_err());
+    }
+
+    #[test]
+    fn can_spend_hash_time_locked_outputs() {
+        let recipient = random_key_pair();
+        let refund = random_key_pair();
+        let secret = b"secret";
+        let lock_script = Script::hash_time_lock(
+            &hash(secret),
+            &Address::from_pub_key(&recipient.pub_key()),
+            &Address::from_pub_key(&refund.pub_key()),
+            10,
+        );
+        let claim = |key_pair: &KeyPair, secret: &[u8]| signatures(&[key_pair]).with_push(secret).with_condition(true);
+        let take_back = |key_pair: &KeyPair| signatures(&[key_pair]).with_condition(false);
+
+        // The recipient claims the outputs with the secret, at any height.
+        verify(&claim(&recipient, secret), &lock_script, TX_BYTES, 0, &Ed25519).ok().unwrap();
+        assert!(verify(&claim(&recipient, b"guess"), &lock_script, TX_BYTES, 0, &Ed25519).is_err());
+        assert!(verify(&claim(&refund, secret), &lock_script, TX_BYTES, 0, &Ed25519).is_err());
+
+        // The refund address takes them back from the lock height on.
+        assert!(verify(&take_back(&refund), &lock_script, TX_BYTES, 9, &Ed25519).is_err());
+        verify(&take_back(&refund), &lock_script, TX_BYTES, 10, &Ed25519).ok().unwrap();
+        assert!(verify(&take_back(&recipient), &lock_script, TX_BYTES, 10, &Ed25519).is_err());
+
+        // The condition must be `true` or empty, and the branches must be closed.
+        let other_condition = signatures(&[&refund]).with_push(&[2]);
+        assert!(verify(&other_condition, &lock_script, TX_BYTES, 10, &Ed25519).is_err());
+        let unclosed = Script::new().with_op(Op::If).with_push(TRUE);
+        assert!(verify(&Script::new().with_condition(true), &unclosed, TX_BYTES, 0, &Ed25519).is_err());
+
+        let pushes = Script::new().with_push(secret).with_condition(true).pushes();
+        assert_eq!(Some(vec![secret.to_vec(), TRUE.to_vec()]), pushes);
+        assert_eq!(None, lock_script.pushes());
+    }
+
+    #[test]
+    fn verifies_signatures_with_the_given_scheme() {
+        let key_pair_generator = KeyPairGenerator::new()
+            .with_signature_algorithm(SignatureAlgorithm::Secp256k1);
+        let key_pairs: Vec<KeyPair> = (0..2)
+            .map(|_i| key_pair_generator.random_keypair().unwrap())
+            .collect();
+        let lock_script = Script::pay_to_address(&Address::from_pub_key(&key_pairs[0].pub_key()));
+
+        verify(&signatures(&[&key_pairs[0]]), &lock_script, TX_BYTES, 0, &Secp256k1).ok().unwrap();
+        assert!(verify(&signatures(&[&key_pairs[1]]), &lock_script, TX_BYTES, 0, &Secp256k1).is_err());
+        assert!(verify(&signatures(&[&key_pairs[0]]), &lock_script, TX_BYTES, 0, &Ed25519).is_err());
+
+        // Ed25519 signatures are not valid secp256k1 ones.
+        let ed25519_key_pair = random_key_pair();
+        let lock_script = Script::pay_to_address(&Address::from_pub_key(&ed25519_key_pair.pub_key()));
+        assert!(verify(&signatures(&[&ed25519_key_pair]), &lock_script, TX_BYTES, 0, &Secp256k1).is_err());
+    }
+
+    fn signatures(key_pairs: &[&KeyPair]) -> Script {
+        let signatures: Vec<(PubKey, Signature)> = key_pairs.iter()
+            .map(|key_pair| (key_pair.pub_key(), key_pair.sign(TX_BYTES)))
+            .collect();
+        Script::signatures(&signatures)
+    }
+
+    fn random_key_pair() -> KeyPair {
+        KeyPairGenerator::new().random_keypair().ok().unwrap()
+    }
+}
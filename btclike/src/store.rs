@@ -0,0 +1,458 @@
+use bincode;
+use blockchain::{Block, Chain};
+use chain_params::ChainParams;
+use crypto::Hash;
+use explorer::ExplorerIndex;
+use Error;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use transaction::SignedTx;
+use utxo::UtxoSet;
+
+const BLOCKS_FILE: &str = "blocks.dat";
+const INDEX_FILE: &str = "index.dat";
+const UTXO_SET_FILE: &str = "utxo.dat";
+const PENDING_FILE: &str = "pending.dat";
+const EXPLORER_INDEX_FILE: &str = "explorer.dat";
+
+/// The length of a block record header: the length of the serialized block, as a little-endian u32.
+const RECORD_HEADER_LEN: u64 = 4;
+/// The length of an index entry: a block hash followed by its offset, as a little-endian u64.
+const INDEX_ENTRY_LEN: usize = 32 + 8;
+
+/// Persists the blocks of a chain and a snapshot of its UTXO set, so that a node can resume
+/// after a restart.
+pub trait BlockStore {
+    /// Appends a block on top of the stored ones.
+    fn append(&mut self, block: &Block) -> Result<(), Error>;
+
+    /// The stored blocks, in the order they were appended.
+    fn blocks(&mut self) -> Result<Vec<Block>, Error>;
+
+    fn get(&mut self, hash: &Hash) -> Result<Option<Block>, Error>;
+
+    /// Replaces the UTXO set snapshot. `tip_hash` is the hash of the head of the chain the
+    /// UTXO set is the result of.
+    fn save_utxo_set(&mut self, utxo_set: &UtxoSet, tip_hash: &Hash) -> Result<(), Error>;
+
+    fn load_utxo_set(&mut self) -> Result<Option<(UtxoSet, Hash)>, Error>;
+
+    /// Replaces the transactions waiting to be mined.
+    fn save_pending(&mut self, transactions: &[SignedTx]) -> Result<(), Error>;
+
+    /// The transactions waiting to be mined, in the order they were saved.
+    fn load_pending(&mut self) -> Result<Vec<SignedTx>, Error>;
+
+    /// Replaces the explorer index of the stored blocks.
+    fn save_explorer_index(&mut self, index: &ExplorerIndex) -> Result<(), Error>;
+
+    /// The explorer index saved last, if any. It may not cover the blocks appended since.
+    fn load_explorer_index(&mut self) -> Result<Option<ExplorerIndex>, Error>;
+
+    /// Rebuilds the stored chain along with its UTXO set, if any block was stored.
+    /// The UTXO set snapshot is used if it matches the head of the chain, otherwise every
+    /// block is verified against the rules of the given network and its transactions are
+    /// replayed.
+    fn load_chain(&mut self, params: &ChainParams) -> Result<Option<(Chain, UtxoSet)>, Error> {
+        let blocks = self.blocks()?;
+        let snapshot = self.load_utxo_set()?;
+
+        let utxo_set = match (blocks.last(), snapshot) {
+            (None, _) => return Ok(None),
+            (Some(head), Some((utxo_set, tip_hash))) if head.header().hash() == &tip_hash => utxo_set,
+            (Some(_head), _) => replay(&blocks, params)?,
+        };
+
+        Ok(Chain::from_blocks(blocks).map(|chain| (chain, utxo_set)))
+    }
+}
+
+/// Verifies the blocks, genesis first, and applies their transactions to a new UTXO set.
+pub fn replay(blocks: &[Block], params: &ChainParams) -> Result<UtxoSet, Error> {
+    let mut utxo_set = UtxoSet::new();
+    let mut previous_hash: Option<&Hash> = None;
+
+    for block in blocks {
+        if let Some(previous_hash) = previous_hash {
+            if block.header().previous_block_hash() != previous_hash {
+                return Err(Error::HeadAndTailHashMismatch);
+            }
+        }
+
+        block.verify(&utxo_set, params)?;
+        utxo_set.apply(block.body(), *block.header().height())?;
+        previous_hash = Some(block.header().hash());
+    }
+
+    Ok(utxo_set)
+}
+
+/// Stores the blocks in an append-only file, each one prefixed with its length, along with an
+/// append-only index of their offsets by hash.
+///
+/// On opening, a block partially written by an interrupted append is discarded and the blocks
+/// missing from the index are indexed again.
+pub struct FileBlockStore {
+    directory: PathBuf,
+    blocks_file: File,
+    index_file: File,
+    /// Block offsets, by hash.
+    index: HashMap<Hash, u64>,
+    /// Block offsets, in order.
+    offsets: Vec<u64>,
+}
+
+impl FileBlockStore {
+    /// Opens the store of the given directory, creating it if necessary.
+    pub fn open<P: AsRef<Path>>(directory: P) -> Result<FileBlockStore, Error> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+
+        let mut store = FileBlockStore {
+            blocks_file: open_append_only(&directory.join(BLOCKS_FILE))?,
+            index_file: open_append_only(&directory.join(INDEX_FILE))?,
+            directory,
+            index: HashMap::new(),
+            offsets: vec![],
+        };
+
+        store.recover()?;
+        Ok(store)
+    }
+
+    /// Loads the index, then scans the blocks file from the last indexed block to index the
+    /// blocks that were appended but not indexed, and to drop a truncated block.
+    fn recover(&mut self) -> Result<(), Error> {
+        let blocks_len = self.blocks_file.metadata()?.len();
+
+        let mut index_bytes = vec![];
+        self.index_file.seek(SeekFrom::Start(0))?;
+        self.index_file.read_to_end(&mut index_bytes)?;
+
+        let mut valid_index_len = 0;
+        for entry in index_bytes.chunks(INDEX_ENTRY_LEN) {
+            if entry.len() < INDEX_ENTRY_LEN {
+                break;
+            }
+
+            let hash: Hash = bincode::deserialize(&entry[..32])?;
+            let offset: u64 = bincode::deserialize(&entry[32..])?;
+            if offset >= blocks_len {
+                break;
+            }
+
+            self.index.insert(hash, offset);
+            self.offsets.push(offset);
+            valid_index_len += INDEX_ENTRY_LEN as u64;
+        }
+
+        while let Some(last_offset) = self.offsets.last().cloned() {
+            if self.read_complete_block(last_offset, blocks_len)?.is_some() {
+                break;
+            }
+
+            self.offsets.pop();
+            self.index.retain(|_hash, offset| *offset != last_offset);
+            valid_index_len -= INDEX_ENTRY_LEN as u64;
+        }
+        self.index_file.set_len(valid_index_len)?;
+
+        let mut offset = match self.offsets.last() {
+            Some(last_offset) => *last_offset + RECORD_HEADER_LEN + self.record_len(*last_offset)?,
+            None => 0,
+        };
+
+        while let Some(block) = self.read_complete_block(offset, blocks_len)? {
+            let record_len = RECORD_HEADER_LEN + self.record_len(offset)?;
+            self.write_index_entry(block.header().hash(), offset)?;
+            offset += record_len;
+        }
+
+        // Anything after the last complete block is the remainder of an interrupted append.
+        self.blocks_file.set_len(offset)?;
+        Ok(())
+    }
+
+    /// Reads the block at the given offset, if it was completely written.
+    fn read_complete_block(&mut self, offset: u64, blocks_len: u64) -> Result<Option<Block>, Error> {
+        if offset + RECORD_HEADER_LEN > blocks_len {
+            return Ok(None);
+        }
+
+        let record_len = self.record_len(offset)?;
+        if offset + RECORD_HEADER_LEN + record_len > blocks_len {
+            return Ok(None);
+        }
+
+        match self.read_block(offset) {
+            Ok(block) => Ok(Some(block)),
+            Err(Error::SerializationError(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn record_len(&mut self, offset: u64) -> Result<u64, Error> {
+        let mut len_bytes = [0u8; RECORD_HEADER_LEN as usize];
+        self.blocks_file.seek(SeekFrom::Start(offset))?;
+        self.blocks_file.read_exact(&mut len_bytes)?;
+        Ok(u64::from(bincode::deserialize::<u32>(&len_bytes)?))
+    }
+
+    fn read_block(&mut self, offset: u64) -> Result<Block, Error> {
+        let mut block_bytes = vec![0u8; self.record_len(offset)? as usize];
+        self.blocks_file.read_exact(&mut block_bytes)?;
+        Ok(bincode::deserialize(&block_bytes)?)
+    }
+
+    fn write_index_entry(&mut self, hash: &Hash, offset: u64) -> Result<(), Error> {
+        let mut entry = bincode::serialize(hash)?;
+        entry.extend(bincode::serialize(&offset)?);
+        self.index_file.write_all(&entry)?;
+
+        self.index.insert(hash.clone(), offset);
+        self.offsets.push(offset);
+        Ok(())
+    }
+}
+
+impl BlockStore for FileBlockStore {
+    fn append(&mut self, block: &Block) -> Result<(), Error> {
+        let block_bytes = bincode::serialize(block)?;
+        let mut record = bincode::serialize(&(block_bytes.len() as u32))?;
+        record.extend(block_bytes);
+
+        let offset = self.blocks_file.seek(SeekFrom::End(0))?;
+        self.blocks_file.write_all(&record)?;
+        self.blocks_file.sync_data()?;
+
+        self.write_index_entry(block.header().hash(), offset)
+    }
+
+    fn blocks(&mut self) -> Result<Vec<Block>, Error> {
+        let offsets = self.offsets.clone();
+        offsets.into_iter().map(|offset| self.read_block(offset)).collect()
+    }
+
+    fn get(&mut self, hash: &Hash) -> Result<Option<Block>, Error> {
+        match self.index.get(hash).cloned() {
+            Some(offset) => self.read_block(offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn save_utxo_set(&mut self, utxo_set: &UtxoSet, tip_hash: &Hash) -> Result<(), Error> {
+        write_atomically(&self.directory.join(UTXO_SET_FILE), &bincode::serialize(&(tip_hash, utxo_set))?)
+    }
+
+    fn load_utxo_set(&mut self) -> Result<Option<(UtxoSet, Hash)>, Error> {
+        let path = self.directory.join(UTXO_SET_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let mut bytes = vec![];
+        File::open(path)?.read_to_end(&mut bytes)?;
+        let (tip_hash, utxo_set): (Hash, UtxoSet) = bincode::deserialize(&bytes)?;
+        Ok(Some((utxo_set, tip_hash)))
+    }
+
+    fn save_pending(&mut self, transactions: &[SignedTx]) -> Result<(), Error> {
+        write_atomically(&self.directory.join(PENDING_FILE), &bincode::serialize(transactions)?)
+    }
+
+    fn load_pending(&mut self) -> Result<Vec<SignedTx>, Error> {
+        let path = self.directory.join(PENDING_FILE);
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut bytes = vec![];
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Ok(bincode::deserialize(&bytes)?)
+    }
+
+    fn save_explorer_index(&mut self, index: &ExplorerIndex) -> Result<(), Error> {
+        write_atomically(&self.directory.join(EXPLORER_INDEX_FILE), &bincode::serialize(index)?)
+    }
+
+    fn load_explorer_index(&mut self) -> Result<Option<ExplorerIndex>, Error> {
+        let path = self.directory.join(EXPLORER_INDEX_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let mut bytes = vec![];
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Ok(Some(bincode::deserialize(&bytes)?))
+    }
+}
+
+fn open_append_only(path: &Path) -> Result<File, Error> {
+    Ok(OpenOptions::new().read(true).append(true).create(true).open(path)?)
+}
+
+/// Writes the file aside then renames it, so that a crash never leaves a partial one.
+fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    let temporary_path = path.with_extension("tmp");
+
+    let mut file = File::create(&temporary_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    fs::rename(temporary_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use genesis::GenesisConfig;
+    use std::env;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use transaction::UtxoStore;
+    use wallet::Wallet;
+
+    static TEST_DIRECTORIES: AtomicUsize = AtomicUsize::new(0);
+
+    #[test]
+    fn can_resume_a_chain() {
+        let directory = test_directory();
+        let (chain, utxo_set) = {
+            let mut store = FileBlockStore::open(&directory).unwrap();
+            assert!(store.load_chain(&ChainParams::main()).unwrap().is_none());
+            mine_and_store(&mut store, 3)
+        };
+
+        let mut store = FileBlockStore::open(&directory).unwrap();
+        let (resumed_chain, resumed_utxo_set) = store.load_chain(&ChainParams::main()).unwrap().unwrap();
+        assert!(chain.head_hash() == resumed_chain.head_hash());
+        assert_eq!(3, resumed_chain.height());
+        assert_eq!(utxo_set.len(), resumed_utxo_set.len());
+
+        let coinbase_hash = chain.head().body().coinbase_tx().hash().unwrap();
+        assert!(resumed_utxo_set.find(&coinbase_hash, &0).is_some());
+        assert!(store.get(chain.head_hash()).unwrap().is_some());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn replays_the_blocks_without_snapshot() {
+        let directory = test_directory();
+        let (chain, utxo_set) = {
+            let mut store = FileBlockStore::open(&directory).unwrap();
+            mine_and_store(&mut store, 2)
+        };
+        fs::remove_file(directory.join(UTXO_SET_FILE)).unwrap();
+        fs::remove_file(directory.join(INDEX_FILE)).unwrap();
+
+        let mut store = FileBlockStore::open(&directory).unwrap();
+        let (resumed_chain, resumed_utxo_set) = store.load_chain(&ChainParams::main()).unwrap().unwrap();
+        assert!(chain.head_hash() == resumed_chain.head_hash());
+        assert_eq!(utxo_set.len(), resumed_utxo_set.len());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn drops_a_partially_written_block() {
+        let directory = test_directory();
+        let (chain, _utxo_set) = {
+            let mut store = FileBlockStore::open(&directory).unwrap();
+            mine_and_store(&mut store, 2)
+        };
+
+        let blocks_path = directory.join(BLOCKS_FILE);
+        let blocks_len = fs::metadata(&blocks_path).unwrap().len();
+        OpenOptions::new().write(true).open(&blocks_path).unwrap()
+            .set_len(blocks_len - 1).unwrap();
+
+        let mut store = FileBlockStore::open(&directory).unwrap();
+        let blocks = store.blocks().unwrap();
+        assert_eq!(2, blocks.len());
+        assert!(store.get(chain.head_hash()).unwrap().is_none());
+
+        // The snapshot does not match the head anymore: the blocks are replayed.
+        let (resumed_chain, _utxo_set) = store.load_chain(&ChainParams::main()).unwrap().unwrap();
+        assert_eq!(1, resumed_chain.height());
+
+        // The store can be appended to again.
+        let coinbase_address = Wallet::new().new_address().unwrap();
+        let next_chain =
+            Chain::mine_next_block(resumed_chain, &ChainParams::main(), vec![], 0, coinbase_address).unwrap();
+        store.append(next_chain.head()).unwrap();
+        let mut store = FileBlockStore::open(&directory).unwrap();
+        assert_eq!(3, store.blocks().unwrap().len());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn keeps_the_pending_transactions() {
+        let directory = test_directory();
+        let mut store = FileBlockStore::open(&directory).unwrap();
+        assert!(store.load_pending().unwrap().is_empty());
+
+        let mut wallet = Wallet::new();
+        let address = wallet.new_address().unwrap();
+        let genesis = GenesisConfig::new(1).with_premine(address.clone(), 100);
+        let chain = Chain::mine_new_genesis(&ChainParams::regtest(), &genesis, address.clone()).unwrap();
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.apply(chain.head().body(), 0).unwrap();
+        wallet.connect_block(chain.head().body(), 0).unwrap();
+        let transaction = wallet.new_transaction(10, address, 1, &utxo_set).unwrap();
+        store.save_pending(::std::slice::from_ref(&transaction)).unwrap();
+
+        let mut store = FileBlockStore::open(&directory).unwrap();
+        let pending = store.load_pending().unwrap();
+        assert_eq!(1, pending.len());
+        assert!(transaction.hash().unwrap() == pending[0].hash().unwrap());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn brings_the_explorer_index_up_to_date() {
+        let directory = test_directory();
+        let mut store = FileBlockStore::open(&directory).unwrap();
+        let (chain, _utxo_set) = mine_and_store(&mut store, 1);
+        let blocks = store.blocks().unwrap();
+        store.save_explorer_index(&ExplorerIndex::from_blocks(&blocks[..1]).unwrap()).unwrap();
+
+        let mut store = FileBlockStore::open(&directory).unwrap();
+        let index = ExplorerIndex::load_or_rebuild(&mut store).unwrap();
+        assert!(index.tip_hash() == Some(chain.head_hash()));
+        assert_eq!(2, index.transaction_count());
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    fn mine_and_store(store: &mut FileBlockStore, height: u32) -> (Chain, UtxoSet) {
+        let mut wallet = Wallet::new();
+        let coinbase_address = wallet.new_address().unwrap();
+        let mut chain = Chain::mine_new_genesis(&ChainParams::main(), &GenesisConfig::default(), coinbase_address)
+            .unwrap();
+        let mut utxo_set = UtxoSet::new();
+        utxo_set.apply(chain.head().body(), chain.height()).unwrap();
+        store.append(chain.head()).unwrap();
+
+        while chain.height() < height {
+            let coinbase_address = wallet.new_address().unwrap();
+            chain = Chain::mine_next_block(chain, &ChainParams::main(), vec![], 0, coinbase_address).unwrap();
+            utxo_set.apply(chain.head().body(), chain.height()).unwrap();
+            store.append(chain.head()).unwrap();
+        }
+
+        store.save_utxo_set(&utxo_set, chain.head_hash()).unwrap();
+        (chain, utxo_set)
+    }
+
+    fn test_directory() -> PathBuf {
+        env::temp_dir().join(format!(
+            "btclike-store-{}-{}",
+            ::std::process::id(),
+            TEST_DIRECTORIES.fetch_add(1, Ordering::Relaxed)
+        ))
+    }
+}
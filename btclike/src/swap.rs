@@ -0,0 +1,222 @@
+use crypto::Hash;
+use transaction::Address;
+use transaction::HashTimeLock;
+
+/// The two hash time locks of an atomic swap of coins of one chain for coins of another one.
+///
+/// The initiator picks a secret and locks its coins on the first chain to the participant.
+/// Once they are confirmed, the participant locks its coins on the second chain to the
+/// initiator, with the hash of the same secret. The initiator claims them, which reveals the
+/// secret on the second chain, and the participant uses it to claim the coins of the first
+/// chain: either both payments happen, or each side takes its coins back once its lock
+/// expires.
+///
+/// The lock of the participant must expire well before the one of the initiator: the
+/// participant needs time to claim the coins of the first chain once the secret is revealed,
+/// possibly at the last moment. The heights of both chains are not comparable though, so the
+/// margin is left to the caller.
+pub struct AtomicSwap {
+    initiator_lock: HashTimeLock,
+    participant_lock: HashTimeLock,
+}
+
+impl AtomicSwap {
+    /// The swap of the coins of the first chain of the initiator for the coins of the second
+    /// chain of the participant, each side receiving the coins and taking its own back with
+    /// the given address.
+    pub fn new(
+        secret_hash: Hash,
+        initiator: Address,
+        participant: Address,
+        initiator_lock_height: u32,
+        participant_lock_height: u32,
+    ) -> AtomicSwap {
+        AtomicSwap {
+            initiator_lock: HashTimeLock::new(
+                secret_hash.clone(),
+                participant.clone(),
+                initiator.clone(),
+                initiator_lock_height,
+            ),
+            participant_lock: HashTimeLock::new(secret_hash, initiator, participant, participant_lock_height),
+        }
+    }
+
+    /// The lock of the coins of the initiator, on the first chain.
+    pub fn initiator_lock(&self) -> &HashTimeLock {
+        &self.initiator_lock
+    }
+
+    /// The lock of the coins of the participant, on the second chain.
+    pub fn participant_lock(&self) -> &HashTimeLock {
+        &self.participant_lock
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use blockchain::Body;
+    use chain_params::ChainParams;
+    use crypto::hash;
+    use transaction::{SignedTx, TxOut};
+    use utxo::UtxoSet;
+    use wallet::{self, Wallet};
+    use Error;
+
+    const SECRET: &[u8] = b"secret of the swap";
+
+    /// A chain of its own, its coinbases burnt once the first one funded a wallet, for the
+    /// tests of the protocols built on top of the chains.
+    pub struct TestChain {
+        params: ChainParams,
+        pub utxo_set: UtxoSet,
+        /// The height of the next block.
+        pub height: u32,
+    }
+
+    impl TestChain {
+        pub fn new(params: ChainParams, wallet: &mut Wallet) -> TestChain {
+            let mut utxo_set = UtxoSet::new();
+            let body = Body::new(TxOut::new(params.block_reward(0), wallet.new_address().unwrap()), vec![]);
+            utxo_set.apply(&body, 0).unwrap();
+            wallet.connect_block(&body, 0).unwrap();
+
+            // The next block is the first one the coinbase output can be spent in.
+            let height = params.coinbase_maturity();
+            TestChain {
+                params,
+                utxo_set,
+                height,
+            }
+        }
+
+        pub fn mine(&mut self, transactions: Vec<SignedTx>) -> Result<(), Error> {
+            let mut fees = 0;
+            for transaction in &transactions {
+                fees += transaction.verify(&self.utxo_set, self.height, &self.params)?;
+            }
+            let coinbase_amount = self.params.block_reward(self.height) + fees;
+            // A burn address of its own, for the coinbase transactions not to share a hash.
+            let burn_address = Address::from_hash(hash(&self.height.to_le_bytes()));
+            let coinbase_tx_out = TxOut::new(coinbase_amount, burn_address);
+            let body = Body::new(coinbase_tx_out, transactions);
+            body.verify(&self.utxo_set, self.height, &self.params)?;
+            self.utxo_set.apply(&body, self.height)?;
+            self.height += 1;
+            Ok(())
+        }
+
+        pub fn mine_until(&mut self, height: u32) {
+            while self.height < height {
+                self.mine(vec![]).unwrap();
+            }
+        }
+    }
+
+    /// Both sides locked their coins: 600 of the first chain by the initiator, 900 of the
+    /// second one by the participant.
+    fn locked_swap() -> (AtomicSwap, Wallet, TestChain, Wallet, TestChain) {
+        let mut initiator = Wallet::new();
+        let mut participant = Wallet::new();
+        let mut first_chain = TestChain::new(ChainParams::main(), &mut initiator);
+        let mut second_chain = TestChain::new(ChainParams::testnet(), &mut participant);
+
+        let swap = AtomicSwap::new(
+            hash(SECRET),
+            initiator.new_address().unwrap(),
+            participant.new_address().unwrap(),
+            first_chain.height + 20,
+            second_chain.height + 10,
+        );
+
+        let address = swap.initiator_lock().address();
+        let lock = initiator.new_transaction(600, address, 10, &first_chain.utxo_set).unwrap();
+        first_chain.mine(vec![lock]).unwrap();
+        let address = swap.participant_lock().address();
+        let lock = participant.new_transaction(900, address, 10, &second_chain.utxo_set).unwrap();
+        second_chain.mine(vec![lock]).unwrap();
+
+        (swap, initiator, first_chain, participant, second_chain)
+    }
+
+    #[test]
+    fn swaps_coins_across_two_chains() {
+        let (swap, mut initiator, mut first_chain, mut participant, mut second_chain) = locked_swap();
+
+        // Only the secret claims the coins.
+        let to_address = initiator.new_address().unwrap();
+        let guess = initiator
+            .claim_hash_time_lock(swap.participant_lock(), b"guess", to_address.clone(), 10, &second_chain.utxo_set)
+            .unwrap();
+        assert_eq!(Error::InvalidScript, second_chain.mine(vec![guess]).err().unwrap());
+
+        let claim = initiator
+            .claim_hash_time_lock(swap.participant_lock(), SECRET, to_address.clone(), 10, &second_chain.utxo_set)
+            .unwrap();
+        second_chain.mine(vec![claim.clone()]).unwrap();
+
+        // The claim revealed the secret to the participant.
+        let secret = swap.participant_lock().find_secret(&claim).unwrap();
+        assert_eq!(SECRET, secret.as_slice());
+        let participant_address = participant.new_address().unwrap();
+        let claim = participant.claim_hash_time_lock(
+            swap.initiator_lock(),
+            &secret,
+            participant_address.clone(),
+            10,
+            &first_chain.utxo_set,
+        );
+        first_chain.mine(vec![claim.unwrap()]).unwrap();
+
+        assert_eq!(&890, wallet::UtxoStore::find_for_address(&second_chain.utxo_set, &to_address)[0].amount());
+        assert_eq!(&590, wallet::UtxoStore::find_for_address(&first_chain.utxo_set, &participant_address)[0].amount());
+
+        // Nothing is left to take back.
+        second_chain.mine_until(swap.participant_lock().lock_height());
+        let refund_address = participant.new_address().unwrap();
+        assert_eq!(
+            Error::NotEnoughTokens,
+            participant
+                .refund_hash_time_lock(swap.participant_lock(), refund_address, 10, &second_chain.utxo_set)
+                .err()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn refunds_the_swap_once_the_locks_expire() {
+        let (swap, mut initiator, mut first_chain, mut participant, mut second_chain) = locked_swap();
+
+        // The initiator never claims: the participant takes its coins back once its lock
+        // expired, and only then.
+        let refund_address = participant.new_address().unwrap();
+        let refund = participant
+            .refund_hash_time_lock(swap.participant_lock(), refund_address.clone(), 10, &second_chain.utxo_set)
+            .unwrap();
+        assert_eq!(Error::InvalidScript, second_chain.mine(vec![refund.clone()]).err().unwrap());
+        second_chain.mine_until(swap.participant_lock().lock_height());
+        second_chain.mine(vec![refund]).unwrap();
+        assert_eq!(&890, wallet::UtxoStore::find_for_address(&second_chain.utxo_set, &refund_address)[0].amount());
+
+        // Only the refund address can take the coins back.
+        let initiator_address = initiator.new_address().unwrap();
+        assert_eq!(
+            Error::InvalidAddress,
+            participant
+                .refund_hash_time_lock(swap.initiator_lock(), initiator_address.clone(), 10, &first_chain.utxo_set)
+                .err()
+                .unwrap()
+        );
+
+        // The lock of the initiator expires later.
+        first_chain.mine_until(swap.participant_lock().lock_height());
+        let refund = initiator
+            .refund_hash_time_lock(swap.initiator_lock(), initiator_address.clone(), 10, &first_chain.utxo_set)
+            .unwrap();
+        assert_eq!(Error::InvalidScript, first_chain.mine(vec![refund.clone()]).err().unwrap());
+        first_chain.mine_until(swap.initiator_lock().lock_height());
+        first_chain.mine(vec![refund]).unwrap();
+        assert_eq!(&590, wallet::UtxoStore::find_for_address(&first_chain.utxo_set, &initiator_address)[0].amount());
+    }
+}
@@ -1,30 +1,215 @@
+use base58::{self, DecodingError};
 use crypto::Hash;
 use crypto::PubKey;
 use crypto::Signature;
 use crypto::KeyPair;
+use crypto::SignatureAlgorithm;
 use crypto::hash;
 use bincode;
+use chain_params::ChainParams;
+use hex;
+use script;
+use script::Script;
 use Error;
-
-#[derive(Serialize, Clone, PartialEq, Eq, Hash)]
-pub struct Address(Hash);
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    /// The hash of a public key. The outputs sent to it are spent with a signature of the
+    /// matching key pair.
+    PubKey(Hash),
+    /// The hash of a redeem script. The outputs sent to it are spent by revealing the script
+    /// and meeting its conditions.
+    Script(Hash),
+}
 
 impl Address{
     pub fn from_pub_key(pub_key: &PubKey) -> Address{
-        Address(hash(&pub_key.as_bytes()))
+        Address::PubKey(hash(pub_key.as_bytes()))
+    }
+
+    pub fn from_script(redeem_script: &Script) -> Address{
+        Address::Script(hash(redeem_script.as_bytes()))
+    }
+
+    /// Builds an address no key pair is known to control. Any output sent to it is burnt.
+    pub fn from_hash(hash: Hash) -> Address{
+        Address::PubKey(hash)
+    }
+
+    pub fn as_hash(&self) -> &Hash {
+        match *self {
+            Address::PubKey(ref hash) | Address::Script(ref hash) => hash,
+        }
+    }
+
+    fn version(&self) -> u8 {
+        match *self {
+            Address::PubKey(_) => PUB_KEY_ADDRESS_VERSION,
+            Address::Script(_) => SCRIPT_ADDRESS_VERSION,
+        }
+    }
+}
+
+/// The version bytes of the text form of the addresses, the ones of Bitcoin's mainnet. The
+/// hashes are longer than Bitcoin's though, so the addresses do not start with the same
+/// characters.
+const PUB_KEY_ADDRESS_VERSION: u8 = 0x00;
+const SCRIPT_ADDRESS_VERSION: u8 = 0x05;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AddressError {
+    Encoding(DecodingError),
+    UnknownVersion(u8),
+    /// The payload is not a hash.
+    InvalidLength,
+}
+
+impl From<DecodingError> for AddressError {
+    fn from(err: DecodingError) -> Self {
+        AddressError::Encoding(err)
+    }
+}
+
+/// Writes the address in Base58Check, to be exchanged as text.
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", base58::encode_check(self.version(), self.as_hash().as_ref()))
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(encoded: &str) -> Result<Address, AddressError> {
+        let (version, payload) = base58::decode_check(encoded)?;
+        let hash = Hash::from_bytes(&payload).ok_or(AddressError::InvalidLength)?;
+
+        match version {
+            PUB_KEY_ADDRESS_VERSION => Ok(Address::PubKey(hash)),
+            SCRIPT_ADDRESS_VERSION => Ok(Address::Script(hash)),
+            _ => Err(AddressError::UnknownVersion(version)),
+        }
     }
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct RawTxIn{
     pub prev_tx_hash: Hash,
     pub prev_tx_output_index: u8,
 }
 
-#[derive(Serialize, Clone)]
+/// An m-of-n condition: `required` distinct key pairs among the ones matching the addresses
+/// must sign the spending transaction.
+/// A condition requiring no signature, or more signatures than it lists addresses, cannot be
+/// met: any output locked by it is burnt.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MultiSig {
+    required: u8,
+    addresses: Vec<Address>,
+}
+
+impl MultiSig {
+    pub fn new(required: u8, addresses: Vec<Address>) -> MultiSig {
+        MultiSig {
+            required,
+            addresses,
+        }
+    }
+
+    pub fn required(&self) -> u8 {
+        self.required
+    }
+
+    pub fn addresses(&self) -> &[Address] {
+        &self.addresses
+    }
+
+    pub fn script(&self) -> Script {
+        Script::multisig(self.required, &self.addresses)
+    }
+
+    /// The address of the outputs paying to the hash of the condition. The outputs locked by
+    /// the condition itself are known by this address as well, so that the co-signers can
+    /// find both kinds.
+    pub fn address(&self) -> Address {
+        Address::from_script(&self.script())
+    }
+}
+
+/// A hash time lock, the building block of atomic swaps: the recipient spends the outputs it
+/// locks by revealing a secret matching the hash, while the refund address takes them back
+/// once the chain reaches the lock height, if the recipient did not spend them before.
+/// Both must be public key addresses.
+#[derive(Clone, PartialEq, Eq)]
+pub struct HashTimeLock {
+    secret_hash: Hash,
+    recipient: Address,
+    refund_address: Address,
+    lock_height: u32,
+}
+
+impl HashTimeLock {
+    pub fn new(secret_hash: Hash, recipient: Address, refund_address: Address, lock_height: u32) -> HashTimeLock {
+        HashTimeLock {
+            secret_hash,
+            recipient,
+            refund_address,
+            lock_height,
+        }
+    }
+
+    pub fn secret_hash(&self) -> &Hash {
+        &self.secret_hash
+    }
+
+    pub fn recipient(&self) -> &Address {
+        &self.recipient
+    }
+
+    pub fn refund_address(&self) -> &Address {
+        &self.refund_address
+    }
+
+    /// The height of the first block the refund address can take the outputs back in.
+    pub fn lock_height(&self) -> u32 {
+        self.lock_height
+    }
+
+    pub fn script(&self) -> Script {
+        Script::hash_time_lock(&self.secret_hash, &self.recipient, &self.refund_address, self.lock_height)
+    }
+
+    /// The address of the outputs paying to the hash of the lock, like the one of a
+    /// multi-signature condition.
+    pub fn address(&self) -> Address {
+        Address::from_script(&self.script())
+    }
+
+    /// The secret revealed by a transaction claiming the outputs, found among the data pushed
+    /// by its inputs: the other side of a swap learns it this way.
+    pub fn find_secret(&self, transaction: &SignedTx) -> Option<Vec<u8>> {
+        transaction.input.iter()
+            .filter_map(|tx_in| tx_in.unlock_script.pushes())
+            .flatten()
+            .find(|data| hash(data).constant_time_eq(&self.secret_hash))
+    }
+}
+
+/// How an input spends an output locked by a `HashTimeLock`.
+#[derive(Clone)]
+pub enum HashTimeLockBranch {
+    /// The recipient reveals the secret.
+    Claim(Vec<u8>),
+    /// The refund address takes the output back.
+    Refund,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct TxOut{
     amount: u32,
-    to_address: Address,
+    lock_script: Script,
 }
 
 impl TxOut {
@@ -32,9 +217,21 @@ impl TxOut {
         amount: u32,
         to_address: Address,
     ) -> TxOut {
+        TxOut::from_script(amount, Script::pay_to_address(&to_address))
+    }
+
+    pub fn new_multisig(amount: u32, multisig: &MultiSig) -> TxOut {
+        TxOut::from_script(amount, multisig.script())
+    }
+
+    pub fn new_hash_time_lock(amount: u32, hash_time_lock: &HashTimeLock) -> TxOut {
+        TxOut::from_script(amount, hash_time_lock.script())
+    }
+
+    pub fn from_script(amount: u32, lock_script: Script) -> TxOut {
         TxOut{
             amount,
-            to_address,
+            lock_script,
         }
     }
 
@@ -42,38 +239,149 @@ impl TxOut {
         &self.amount
     }
 
-    pub fn to_address(&self) -> &Address{
-        &self.to_address
+    pub fn lock_script(&self) -> &Script {
+        &self.lock_script
+    }
+
+    /// The address the output is known by: the one it is sent to, or the hash of its
+    /// locking script, as if it was a redeem script.
+    pub fn address(&self) -> Address {
+        self.lock_script.address()
     }
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct RawTx {
     pub input: Vec<RawTxIn>,
     pub output: Vec<TxOut>,
 }
 
-#[derive(Serialize, Clone)]
+/// The tag the signature messages start with, so that they can never be mistaken for a
+/// serialized transaction or any other hashed data.
+const SIGHASH_TAG: &[u8] = b"btclike sighash v1";
+
+/// Which parts of the transaction the signatures of an input commit to, like Bitcoin's
+/// sighash flags. The others can change without invalidating them, which lets several parties
+/// build a transaction together.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SigHashType {
+    /// Every input and every output.
+    #[default]
+    All,
+    /// Every input, and the output of the same index as the input only: the other outputs
+    /// are left to the other signers.
+    Single,
+    /// This input and every output: anyone can add inputs, to fund the outputs together as
+    /// in a crowdfunding.
+    AllAnyoneCanPay,
+    /// This input and the output of the same index only, as the parties of a coinjoin each
+    /// paying themselves.
+    SingleAnyoneCanPay,
+}
+
+impl SigHashType {
+    /// Whether the signatures leave out the other inputs.
+    pub fn anyone_can_pay(&self) -> bool {
+        matches!(*self, SigHashType::AllAnyoneCanPay | SigHashType::SingleAnyoneCanPay)
+    }
+
+    /// Whether the signatures commit to the output of the same index as the input only.
+    pub fn single(&self) -> bool {
+        matches!(*self, SigHashType::Single | SigHashType::SingleAnyoneCanPay)
+    }
+}
+
+/// Computes the messages the signatures of the inputs of a transaction sign, in the manner of
+/// SegWit: the message of an input commits to its sighash type, to the outpoints spent and
+/// to the amounts and the locking scripts of the outputs spent, to the outputs created and,
+/// unless anyone can pay, to the index of the input signed. A signature thus holds for the
+/// known amounts it spends, whatever else the other signers put in their unlocking scripts.
+pub struct SigHasher {
+    /// The hash of every outpoint spent, then of every output spent, then of every output.
+    prevouts: Hash,
+    spent_outputs: Hash,
+    outputs: Hash,
+    /// The hashes of each outpoint and of the output it spends, for the inputs signed alone.
+    input_hashes: Vec<(Hash, Hash)>,
+    /// The hash of each output, for the inputs signing the output of their index alone.
+    output_hashes: Vec<Hash>,
+}
+
+impl SigHasher {
+    /// Fails unless the outputs spent match the inputs of the transaction, in order.
+    pub fn new(raw_tx: &RawTx, spent_outputs: &[&TxOut]) -> Result<SigHasher, Error> {
+        if raw_tx.input.len() != spent_outputs.len() {
+            return Err(Error::InvalidNumberOfSpentOutputs(
+                format!("Expected {} spent outputs, got {}", raw_tx.input.len(), spent_outputs.len())
+            ));
+        }
+
+        let input_hashes = raw_tx.input.iter()
+            .zip(spent_outputs)
+            .map(|(raw_tx_in, spent_output)| {
+                Ok((hash(&bincode::serialize(raw_tx_in)?), hash(&bincode::serialize(spent_output)?)))
+            })
+            .collect::<Result<Vec<(Hash, Hash)>, Error>>()?;
+        let output_hashes = raw_tx.output.iter()
+            .map(|tx_out| Ok(hash(&bincode::serialize(tx_out)?)))
+            .collect::<Result<Vec<Hash>, Error>>()?;
+
+        Ok(SigHasher {
+            prevouts: hash(&bincode::serialize(&raw_tx.input)?),
+            spent_outputs: hash(&bincode::serialize(spent_outputs)?),
+            outputs: hash(&bincode::serialize(&raw_tx.output)?),
+            input_hashes,
+            output_hashes,
+        })
+    }
+
+    /// The message the signatures of the given input sign with the given sighash type, none
+    /// if it signs a single output the transaction does not have. Panics if the transaction
+    /// has no such input.
+    pub fn message(&self, input_index: usize, sighash_type: SigHashType) -> Option<Hash> {
+        let (prevouts, spent_outputs) = if sighash_type.anyone_can_pay() {
+            self.input_hashes[input_index].clone()
+        } else {
+            (self.prevouts.clone(), self.spent_outputs.clone())
+        };
+        let outputs = if sighash_type.single() {
+            self.output_hashes.get(input_index)?
+        } else {
+            &self.outputs
+        };
+
+        let mut preimage = SIGHASH_TAG.to_vec();
+        preimage.push(sighash_type as u8);
+        preimage.extend_from_slice(prevouts.as_ref());
+        preimage.extend_from_slice(spent_outputs.as_ref());
+        preimage.extend_from_slice(outputs.as_ref());
+        // The inputs added by others may shift the index of the input, its outpoint tells it.
+        if !sighash_type.anyone_can_pay() {
+            preimage.extend_from_slice(&(input_index as u32).to_le_bytes());
+        }
+        Some(hash(&preimage))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SignedTxIn{
     prev_tx_hash: Hash,
     prev_tx_output_index: u8,
-    tx_signature: Signature,
-    sig_public_key: PubKey,
+    sighash_type: SigHashType,
+    unlock_script: Script,
 }
 
 impl SignedTxIn{
-    fn from_raw_tx_in(raw_tx_in: RawTxIn, serialized_tx: &[u8], key_pair: &KeyPair)
-                      -> SignedTxIn
-    {
-        let signature = key_pair.sign(&serialized_tx);
-        let pub_key = key_pair.pub_key();
-
-        SignedTxIn{
-            prev_tx_output_index: raw_tx_in.prev_tx_output_index,
-            prev_tx_hash: raw_tx_in.prev_tx_hash,
-            tx_signature: signature,
-            sig_public_key: pub_key,
-        }
+    pub fn prev_tx_hash(&self) -> &Hash {
+        &self.prev_tx_hash
+    }
+
+    pub fn prev_tx_output_index(&self) -> &u8 {
+        &self.prev_tx_output_index
+    }
+
+    pub fn sighash_type(&self) -> SigHashType {
+        self.sighash_type
     }
 
     fn clone_without_signature(&self) -> RawTxIn {
@@ -83,47 +391,90 @@ impl SignedTxIn{
         }
     }
 
-    fn verify_signature(&self, tx_bytes: &[u8]) -> Result<(), Error> {
-        self.sig_public_key.verify_signature(tx_bytes, &self.tx_signature)
-            .map_err(|err|{
-                Error::from(err)
-            })
+    pub fn unlock_script(&self) -> &Script {
+        &self.unlock_script
     }
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SignedTx {
     input: Vec<SignedTxIn>,
     output: Vec<TxOut>,
 }
 
 impl SignedTx {
-    pub fn from_raw_tx(raw_tx: RawTx, key_pairs: Vec<&KeyPair>)
+    /// Signs each input of the transaction, spending the given output, with the given key pair.
+    pub fn from_raw_tx(raw_tx: RawTx, spent_outputs: &[&TxOut], key_pairs: Vec<&KeyPair>)
                    -> Result<SignedTx, Error>
     {
-        let serialized = bincode::serialize(&raw_tx)?;
-
-        let mut raw_input = raw_tx.input;
-        let output = raw_tx.output;
-
-        if raw_input.len() != key_pairs.len() {
+        if raw_tx.input.len() != key_pairs.len() {
             return Err(Error::InvalidNumberOfKeyPairs(
-                format!("Expected {} key pairs, got {}", raw_input.len(), key_pairs.len()))
+                format!("Expected {} key pairs, got {}", raw_tx.input.len(), key_pairs.len()))
             );
         }
 
-        let mut signed_input = vec![];
-        for key_pair in key_pairs {
-            let raw_tx_in = raw_input.pop().unwrap();
-
-            let signed_tx_in = SignedTxIn::from_raw_tx_in(raw_tx_in, &serialized, key_pair);
-            signed_input.push(signed_tx_in);
+        let mut partially_signed_tx = PartiallySignedTx::new(raw_tx, spent_outputs)?;
+        for (input_index, key_pair) in key_pairs.into_iter().enumerate() {
+            partially_signed_tx.sign(input_index, key_pair);
         }
 
-        Ok(SignedTx {
-            input: signed_input,
+        Ok(partially_signed_tx.into_signed_tx())
+    }
+
+    /// Assembles inputs signed apart, possibly for other transactions, with the given outputs:
+    /// the contributions to a crowdfunding, signed with `SigHashType::AllAnyoneCanPay` for the
+    /// same outputs, or the inputs of a coinjoin, each signed with
+    /// `SigHashType::SingleAnyoneCanPay` for the output of its index. The signatures
+    /// committing to other inputs or outputs do not hold anymore.
+    pub fn from_parts(input: Vec<SignedTxIn>, output: Vec<TxOut>) -> SignedTx {
+        SignedTx {
+            input,
             output,
-        })
+        }
+    }
+
+    pub fn input(&self) -> &[SignedTxIn] {
+        &self.input
+    }
+
+    pub fn output(&self) -> &[TxOut] {
+        &self.output
+    }
+
+    pub fn hash(&self) -> Result<Hash, Error> {
+        let serialized = bincode::serialize(&self)?;
+        Ok(hash(&serialized))
+    }
+
+    /// The serialized transaction, in hexadecimal.
+    pub fn to_hex(&self) -> Result<String, Error> {
+        Ok(hex::encode(&bincode::serialize(&self)?))
+    }
+
+    pub fn from_hex(encoded: &str) -> Result<SignedTx, Error> {
+        let bytes = hex::decode(encoded)
+            .map_err(|err| Error::SerializationError(format!("Invalid hexadecimal transaction: {:?}", err)))?;
+        Ok(bincode::deserialize(&bytes)?)
+    }
+
+    /// Verifies the unlock script of the given input alone against the output it spends, as
+    /// part of the block at the given height, which tells which signatures are invalid when
+    /// the transaction is. Panics if the transaction has no such input.
+    pub fn verify_input<S>(&self, input_index: usize, utxo_store: &S, height: u32) -> Result<(), Error>
+    where
+        S: UtxoStore,
+    {
+        // The message commits to every output spent, not only the one of the input.
+        let prev_tx_outs = self.input.iter()
+            .map(|tx_in| utxo_store.find(&tx_in.prev_tx_hash, &tx_in.prev_tx_output_index).ok_or(Error::UtxoNotFound))
+            .collect::<Result<Vec<&TxOut>, Error>>()?;
+
+        let tx_in = &self.input[input_index];
+        let message = SigHasher::new(&self.clone_without_signatures(), &prev_tx_outs)?
+            .message(input_index, tx_in.sighash_type)
+            .ok_or(Error::InvalidScript)?;
+        let scheme = utxo_store.signature_algorithm().scheme();
+        script::verify(&tx_in.unlock_script, &prev_tx_outs[input_index].lock_script, message.as_ref(), height, scheme)
     }
 
     fn clone_without_signatures(&self) -> RawTx {
@@ -140,13 +491,22 @@ impl SignedTx {
         }
     }
 
-    pub fn verify<S>(&self, utxo_store: &S) -> Result<u32, Error>
+    /// Verifies the transaction against the consensus rules, as part of the block at the given
+    /// height of the given network, returning its fees. Whether a node relays it is up to its
+    /// `RelayPolicy`.
+    pub fn verify<S>(&self, utxo_store: &S, height: u32, params: &ChainParams) -> Result<u32, Error>
     where
         S: UtxoStore,
     {
         let mut prev_tx_outs = vec![];
 
         for tx_in in &self.input {
+            if let Some(coinbase_height) = utxo_store.coinbase_height(&tx_in.prev_tx_hash) {
+                if height < coinbase_height.saturating_add(params.coinbase_maturity()) {
+                    return Err(Error::ImmatureCoinbase);
+                }
+            }
+
             if let Some(prev_tx_out) = utxo_store.find(
                 &tx_in.prev_tx_hash,
                 &tx_in.prev_tx_output_index
@@ -157,51 +517,211 @@ impl SignedTx {
             }
         }
 
-        let mut in_amount = 0;
-        prev_tx_outs.iter().for_each(|tx_out|{
-            in_amount += tx_out.amount;
-        });
+        // The amounts of a transaction cannot add up to more coins than a u32 can count.
+        let in_amount = prev_tx_outs.iter()
+            .try_fold(0u32, |amount, tx_out| amount.checked_add(tx_out.amount))
+            .ok_or(Error::InvalidTxAmount)?;
+        let out_amount = self.output.iter()
+            .try_fold(0u32, |amount, tx_out| amount.checked_add(tx_out.amount))
+            .ok_or(Error::InvalidTxAmount)?;
+
+        let fees = in_amount.checked_sub(out_amount).ok_or(Error::InvalidTxAmount)?;
 
-        let mut out_amount = 0;
-        self.output.iter().for_each(|tx_out|{
-            out_amount += tx_out.amount;
-        });
+        let sig_hasher = SigHasher::new(&self.clone_without_signatures(), &prev_tx_outs)?;
 
-        if in_amount < out_amount {
-            return Err(Error::InvalidTxAmount);
+        let scheme = utxo_store.signature_algorithm().scheme();
+        for (i, prev_tx_out) in prev_tx_outs.iter().enumerate() {
+            let message = sig_hasher.message(i, self.input[i].sighash_type).ok_or(Error::InvalidScript)?;
+            script::verify(&self.input[i].unlock_script, &prev_tx_out.lock_script, message.as_ref(), height, scheme)?
         }
 
-        let fees = in_amount - out_amount;
+        Ok(fees)
+    }
+}
 
-        let raw_next_tx = self.clone_without_signatures();
-        let serialized = bincode::serialize(&raw_next_tx)?;
+/// A transaction collecting the signatures of its inputs, possibly from several wallets
+/// when it spends multi-signature outputs.
+pub struct PartiallySignedTx {
+    raw_tx: RawTx,
+    sig_hasher: SigHasher,
+    sighash_types: Vec<SigHashType>,
+    signatures: Vec<Vec<(PubKey, Signature)>>,
+    redeem_scripts: Vec<Option<Script>>,
+    /// The secrets revealed to claim the outputs locked by a hash time lock.
+    secrets: Vec<Option<Vec<u8>>>,
+    /// The conditions choosing the branch of the scripts spent.
+    conditions: Vec<Option<bool>>,
+}
 
-        for (i, prev_tx_out) in prev_tx_outs.iter().enumerate() {
-            let tx_in = &self.input[i];
-            let address = Address::from_pub_key(&tx_in.sig_public_key);
+impl PartiallySignedTx {
+    /// The transaction spending the given outputs, one per input in the same order, which the
+    /// signatures commit to.
+    pub fn new(raw_tx: RawTx, spent_outputs: &[&TxOut]) -> Result<PartiallySignedTx, Error> {
+        let sig_hasher = SigHasher::new(&raw_tx, spent_outputs)?;
+        let sighash_types = vec![SigHashType::All; raw_tx.input.len()];
+        let signatures = vec![vec![]; raw_tx.input.len()];
+        let redeem_scripts = vec![None; raw_tx.input.len()];
+        let secrets = vec![None; raw_tx.input.len()];
+        let conditions = vec![None; raw_tx.input.len()];
+
+        Ok(PartiallySignedTx {
+            raw_tx,
+            sig_hasher,
+            sighash_types,
+            signatures,
+            redeem_scripts,
+            secrets,
+            conditions,
+        })
+    }
 
-            if address != prev_tx_out.to_address {
-                return Err(Error::InvalidAddress);
-            }
+    pub fn raw_tx(&self) -> &RawTx {
+        &self.raw_tx
+    }
+
+    /// Adds the signature of the key pair to the given input.
+    /// Panics if the transaction has no such input.
+    pub fn sign(&mut self, input_index: usize, key_pair: &KeyPair) {
+        let signature = key_pair.sign(self.message(input_index).as_ref());
+        self.signatures[input_index].push((key_pair.pub_key(), signature));
+    }
 
-            tx_in.verify_signature(&serialized)?
+    /// Adds a signature made with the given scheme by another party, which must sign the
+    /// message of the input. Panics if the transaction has no such input.
+    pub fn add_signature(
+        &mut self,
+        input_index: usize,
+        pub_key: PubKey,
+        signature: Signature,
+        signature_algorithm: SignatureAlgorithm,
+    ) -> Result<(), Error> {
+        signature_algorithm.scheme().verify(&pub_key, self.message(input_index).as_ref(), &signature)?;
+        self.signatures[input_index].push((pub_key, signature));
+        Ok(())
+    }
+
+    /// Chooses which parts of the transaction the signatures of the given input commit to,
+    /// `SigHashType::All` by default. The signatures of the input so far signed another
+    /// message and are dropped. Fails if the input would sign an output the transaction does
+    /// not have. Panics if the transaction has no such input.
+    pub fn set_sighash_type(&mut self, input_index: usize, sighash_type: SigHashType) -> Result<(), Error> {
+        if sighash_type.single() && input_index >= self.raw_tx.output.len() {
+            return Err(Error::InvalidSigHashType);
         }
 
-        Ok(fees)
+        self.sighash_types[input_index] = sighash_type;
+        self.signatures[input_index].clear();
+        Ok(())
+    }
+
+    /// The message the signatures of the given input sign.
+    fn message(&self, input_index: usize) -> Hash {
+        self.sig_hasher.message(input_index, self.sighash_types[input_index])
+            .expect("The sighash types are checked when set")
+    }
+
+    /// The signatures of the given input so far, in the order they were added.
+    pub fn signatures(&self, input_index: usize) -> &[(PubKey, Signature)] {
+        &self.signatures[input_index]
+    }
+
+    /// Whether the key pair matching the address already signed the given input.
+    pub fn is_signed_by(&self, input_index: usize, address: &Address) -> bool {
+        self.signatures[input_index].iter()
+            .any(|(pub_key, _signature)| &Address::from_pub_key(pub_key) == address)
+    }
+
+    pub fn signature_count(&self, input_index: usize) -> usize {
+        self.signatures[input_index].len()
+    }
+
+    /// Reveals the redeem script of the given input, which spends an output sent to the
+    /// hash of the script. Panics if the transaction has no such input.
+    pub fn set_redeem_script(&mut self, input_index: usize, redeem_script: Script) {
+        self.redeem_scripts[input_index] = Some(redeem_script);
+    }
+
+    /// Chooses the branch of the hash time lock the given input spends. Panics if the
+    /// transaction has no such input.
+    pub fn set_hash_time_lock_branch(&mut self, input_index: usize, branch: HashTimeLockBranch) {
+        match branch {
+            HashTimeLockBranch::Claim(secret) => {
+                self.secrets[input_index] = Some(secret);
+                self.set_condition(input_index, true);
+            },
+            HashTimeLockBranch::Refund => {
+                self.secrets[input_index] = None;
+                self.set_condition(input_index, false);
+            },
+        }
+    }
+
+    /// Pushes the condition the `If` of the script spent pops, after the signatures of the
+    /// given input. Panics if the transaction has no such input.
+    pub fn set_condition(&mut self, input_index: usize, condition: bool) {
+        self.conditions[input_index] = Some(condition);
+    }
+
+    pub fn into_signed_tx(self) -> SignedTx {
+        // The inputs must keep their order: the messages the signatures sign commit to their index.
+        let input = self.raw_tx.input.into_iter()
+            .zip(self.sighash_types)
+            .zip(self.signatures)
+            .zip(self.redeem_scripts)
+            .zip(self.secrets)
+            .zip(self.conditions)
+            .map(|(((((raw_tx_in, sighash_type), signatures), redeem_script), secret), condition)| {
+                let mut unlock_script = Script::signatures(&signatures);
+                if let Some(secret) = secret {
+                    unlock_script = unlock_script.with_push(&secret);
+                }
+                if let Some(condition) = condition {
+                    unlock_script = unlock_script.with_condition(condition);
+                }
+                if let Some(redeem_script) = redeem_script {
+                    unlock_script = unlock_script.with_push(redeem_script.as_bytes());
+                }
+
+                SignedTxIn {
+                    prev_tx_hash: raw_tx_in.prev_tx_hash,
+                    prev_tx_output_index: raw_tx_in.prev_tx_output_index,
+                    sighash_type,
+                    unlock_script,
+                }
+            })
+            .collect();
+
+        SignedTx {
+            input,
+            output: self.raw_tx.output,
+        }
     }
 }
 
 pub trait UtxoStore {
     fn find(&self, transaction_hash: &Hash, txo_index: &u8) -> Option<&TxOut>;
+    /// The height of the block that created the given transaction, if it is a coinbase one.
+    fn coinbase_height(&self, transaction_hash: &Hash) -> Option<u32>;
+    /// The scheme the transactions spending the outputs must be signed with.
+    fn signature_algorithm(&self) -> SignatureAlgorithm;
 }
 
-#[derive(Serialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct CoinbaseTx(pub TxOut);
 
+impl CoinbaseTx {
+    pub fn hash(&self) -> Result<Hash, Error> {
+        let serialized = bincode::serialize(&self)?;
+        Ok(hash(&serialized))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chain_params::ChainParams;
     use crypto::KeyPairGenerator;
+    use proptest::prelude::*;
 
     #[test]
     fn can_sign_and_verify_transactions() {
@@ -215,17 +735,14 @@ mod tests {
             prev_tx_hash: Hash::min(),
         };
 
-        let next_output = TxOut{
-            amount: initial_amount,
-            to_address: next_address(&key_pair_generator),
-        };
+        let next_output = TxOut::new(initial_amount, next_address(&key_pair_generator));
 
         let next_tx = RawTx {
             input: vec![next_input],
             output: vec![next_output],
         };
 
-        let signed_tx = SignedTx::from_raw_tx(next_tx,
+        let signed_tx = SignedTx::from_raw_tx(next_tx, &[&prev_output],
                                               vec![&prev_to_keypair]).ok().unwrap();
 
         verify(signed_tx, prev_output).ok().unwrap();
@@ -243,22 +760,41 @@ mod tests {
             prev_tx_hash: Hash::min(),
         };
 
-        let next_output = TxOut{
-            amount: initial_amount + 1,
-            to_address: next_address(&key_pair_generator),
-        };
+        let next_output = TxOut::new(initial_amount + 1, next_address(&key_pair_generator));
 
         let next_tx = RawTx {
             input: vec![next_input],
             output: vec![next_output],
         };
 
-        let signed_tx = SignedTx::from_raw_tx(next_tx,
+        let signed_tx = SignedTx::from_raw_tx(next_tx, &[&prev_output],
                                               vec![&prev_to_keypair]).ok().unwrap();
 
         verify(signed_tx, prev_output).err().unwrap();
     }
 
+    #[test]
+    fn rejects_amounts_overflowing() {
+        let key_pair_generator = KeyPairGenerator::new();
+        let (prev_to_keypair, prev_output) = prev_context(&key_pair_generator, u32::MAX);
+
+        // Wrapping around, the outputs would add up to less than the input.
+        let next_tx = RawTx {
+            input: vec![RawTxIn{
+                prev_tx_output_index: 0,
+                prev_tx_hash: Hash::min(),
+            }],
+            output: vec![
+                TxOut::new(u32::MAX, next_address(&key_pair_generator)),
+                TxOut::new(1, next_address(&key_pair_generator)),
+            ],
+        };
+
+        let signed_tx = SignedTx::from_raw_tx(next_tx, &[&prev_output], vec![&prev_to_keypair]).ok().unwrap();
+
+        assert_eq!(Error::InvalidTxAmount, verify(signed_tx, prev_output).err().unwrap());
+    }
+
     #[test]
     fn rejects_invalid_pub_key() {
         let key_pair_generator = KeyPairGenerator::new();
@@ -271,21 +807,20 @@ mod tests {
             prev_tx_hash: Hash::min(),
         };
 
-        let next_output = TxOut{
-            amount: 10,
-            to_address: next_address(&key_pair_generator),
-        };
+        let next_output = TxOut::new(10, next_address(&key_pair_generator));
 
         let next_tx = RawTx {
             input: vec![next_input],
             output: vec![next_output],
         };
 
-        let mut signed_tx = SignedTx::from_raw_tx(next_tx,
+        let message = SigHasher::new(&next_tx, &[&prev_output]).ok().unwrap().message(0, SigHashType::All).unwrap();
+        let signature = prev_to_keypair.sign(message.as_ref());
+        let mut signed_tx = SignedTx::from_raw_tx(next_tx, &[&prev_output],
                                                   vec![&prev_to_keypair]).ok().unwrap();
 
         let invalid_key_pair = key_pair_generator.random_keypair().ok().unwrap();
-        signed_tx.input[0].sig_public_key = invalid_key_pair.pub_key();
+        signed_tx.input[0].unlock_script = Script::signatures(&[(invalid_key_pair.pub_key(), signature)]);
 
         verify(signed_tx, prev_output).err().unwrap();
     }
@@ -302,10 +837,7 @@ mod tests {
             prev_tx_hash: Hash::min(),
         };
 
-        let next_output = TxOut{
-            amount: 10,
-            to_address: next_address(&key_pair_generator),
-        };
+        let next_output = TxOut::new(10, next_address(&key_pair_generator));
 
         let next_tx = RawTx {
             input: vec![next_input],
@@ -313,40 +845,354 @@ mod tests {
         };
 
         let invalid_key_pair = key_pair_generator.random_keypair().ok().unwrap();
-        let signed_tx = SignedTx::from_raw_tx(next_tx,
+        let signed_tx = SignedTx::from_raw_tx(next_tx, &[&prev_output],
                                               vec![&invalid_key_pair]).ok().unwrap();
 
         verify(signed_tx, prev_output).err().unwrap();
     }
 
+    #[test]
+    fn mutations_invalidate_the_signatures() {
+        let key_pair_generator = KeyPairGenerator::new();
+        let (prev_to_keypair, prev_output) = prev_context(&key_pair_generator, 10);
+
+        let raw_tx = RawTx {
+            input: (0..2)
+                .map(|index| RawTxIn{
+                    prev_tx_output_index: index,
+                    prev_tx_hash: Hash::min(),
+                })
+                .collect(),
+            output: vec![TxOut::new(15, next_address(&key_pair_generator))],
+        };
+        let signed_tx = SignedTx::from_raw_tx(raw_tx, &[&prev_output, &prev_output],
+                                              vec![&prev_to_keypair, &prev_to_keypair]).ok().unwrap();
+        verify(signed_tx.clone(), prev_output.clone()).ok().unwrap();
+
+        // The signatures of the same key on two inputs cannot be swapped.
+        let mut swapped = signed_tx.clone();
+        swapped.input[0].unlock_script = signed_tx.input[1].unlock_script.clone();
+        swapped.input[1].unlock_script = signed_tx.input[0].unlock_script.clone();
+        assert_eq!(Error::CryptographyError, verify(swapped, prev_output.clone()).err().unwrap());
+
+        let mut other_outpoint = signed_tx.clone();
+        other_outpoint.input[1].prev_tx_output_index = 2;
+        assert_eq!(Error::CryptographyError, verify(other_outpoint, prev_output.clone()).err().unwrap());
+
+        // The amount of the last output, right before its locking script of 37 bytes.
+        let mut bytes = bincode::serialize(&signed_tx).ok().unwrap();
+        let amount_offset = bytes.len() - (37 + 8 + 4);
+        bytes[amount_offset] -= 1;
+        let mutated: SignedTx = bincode::deserialize(&bytes).ok().unwrap();
+        assert_eq!(14, *mutated.output()[0].amount());
+        assert_eq!(Error::CryptographyError, verify(mutated, prev_output.clone()).err().unwrap());
+
+        // The signers commit to the amounts they spend, not only to the outputs they spend.
+        let other_amount = TxOut::from_script(11, prev_output.lock_script().clone());
+        assert_eq!(Error::CryptographyError, verify(signed_tx, other_amount).err().unwrap());
+    }
+
+    #[test]
+    fn sighash_types_leave_out_parts_of_the_transaction() {
+        let key_pair_generator = KeyPairGenerator::new();
+        let (first_key_pair, first_output) = prev_context(&key_pair_generator, 10);
+        let (second_key_pair, second_output) = prev_context(&key_pair_generator, 20);
+        let utxo_store = TxOutsUtxoStore(vec![first_output.clone(), second_output.clone()]);
+        let params = ChainParams::main();
+        let input = |index: u8| RawTxIn{
+            prev_tx_output_index: index,
+            prev_tx_hash: Hash::min(),
+        };
+
+        // Each backer of a crowdfunding signs its own input for the goal alone.
+        let goal = TxOut::new(30, next_address(&key_pair_generator));
+        let contribute = |index: u8, key_pair: &KeyPair, spent_output: &TxOut, sighash_type: SigHashType| {
+            let raw_tx = RawTx {
+                input: vec![input(index)],
+                output: vec![goal.clone()],
+            };
+            let mut transaction = PartiallySignedTx::new(raw_tx, &[spent_output]).ok().unwrap();
+            transaction.set_sighash_type(0, sighash_type).ok().unwrap();
+            transaction.sign(0, key_pair);
+            transaction.into_signed_tx().input[0].clone()
+        };
+        let crowdfunding = |sighash_type: SigHashType| SignedTx::from_parts(
+            vec![
+                contribute(0, &first_key_pair, &first_output, sighash_type),
+                contribute(1, &second_key_pair, &second_output, sighash_type),
+            ],
+            vec![goal.clone()],
+        );
+        crowdfunding(SigHashType::AllAnyoneCanPay).verify(&utxo_store, 0, &params).ok().unwrap();
+        let all_inputs_signed = crowdfunding(SigHashType::All);
+        assert_eq!(Error::CryptographyError, all_inputs_signed.verify(&utxo_store, 0, &params).err().unwrap());
+
+        let raw_tx = RawTx {
+            input: vec![input(0), input(1)],
+            output: vec![goal.clone()],
+        };
+        let mut transaction = PartiallySignedTx::new(raw_tx, &[&first_output, &second_output]).ok().unwrap();
+        assert_eq!(Err(Error::InvalidSigHashType), transaction.set_sighash_type(1, SigHashType::Single));
+
+        // The signer of a single output leaves the others to the other signers.
+        let raw_tx = RawTx {
+            input: vec![input(0)],
+            output: vec![
+                TxOut::new(5, next_address(&key_pair_generator)),
+                TxOut::new(4, next_address(&key_pair_generator)),
+            ],
+        };
+        let mut transaction = PartiallySignedTx::new(raw_tx, &[&first_output]).ok().unwrap();
+        transaction.set_sighash_type(0, SigHashType::Single).ok().unwrap();
+        transaction.sign(0, &first_key_pair);
+        let signed_tx = transaction.into_signed_tx();
+        assert_eq!(SigHashType::Single, signed_tx.input()[0].sighash_type());
+
+        let mut other_output = signed_tx.clone();
+        other_output.output[1] = TxOut::new(3, next_address(&key_pair_generator));
+        other_output.verify(&utxo_store, 0, &params).ok().unwrap();
+        let mut other_signed_output = signed_tx.clone();
+        other_signed_output.output[0] = TxOut::new(4, next_address(&key_pair_generator));
+        assert_eq!(Error::CryptographyError, other_signed_output.verify(&utxo_store, 0, &params).err().unwrap());
+
+        // The signatures commit to their sighash type as well.
+        let mut other_sighash_type = signed_tx;
+        other_sighash_type.input[0].sighash_type = SigHashType::All;
+        assert_eq!(Error::CryptographyError, other_sighash_type.verify(&utxo_store, 0, &params).err().unwrap());
+    }
+
+    #[test]
+    fn can_verify_multisig_inputs() {
+        let key_pair_generator = KeyPairGenerator::new();
+        let key_pairs: Vec<KeyPair> = (0..3)
+            .map(|_i| key_pair_generator.random_keypair().ok().unwrap())
+            .collect();
+        let addresses = key_pairs.iter()
+            .map(|key_pair| Address::from_pub_key(&key_pair.pub_key()))
+            .collect();
+        let prev_output = TxOut::new_multisig(10, &MultiSig::new(2, addresses));
+
+        let sign = |signers: &[usize]| {
+            let mut transaction = PartiallySignedTx::new(RawTx {
+                input: vec![RawTxIn{
+                    prev_tx_output_index: 0,
+                    prev_tx_hash: Hash::min(),
+                }],
+                output: vec![TxOut::new(10, next_address(&key_pair_generator))],
+            }, &[&prev_output]).ok().unwrap();
+            for signer in signers {
+                transaction.sign(0, &key_pairs[*signer]);
+            }
+            transaction.into_signed_tx()
+        };
+
+        verify(sign(&[0, 2]), prev_output.clone()).ok().unwrap();
+        assert_eq!(Error::InvalidScript, verify(sign(&[1]), prev_output.clone()).err().unwrap());
+        assert_eq!(Error::InvalidScript, verify(sign(&[0, 1, 2]), prev_output).err().unwrap());
+    }
+
+    #[test]
+    fn can_exchange_addresses_as_text() {
+        let pub_key_address = Address::from_hash(Hash::min());
+        let encoded = pub_key_address.to_string();
+        assert_eq!("1111111111111111111111111111111112m1s9K", encoded);
+        assert!(pub_key_address == encoded.parse().unwrap());
+
+        let key_pair_generator = KeyPairGenerator::new();
+        let script_address = MultiSig::new(1, vec![next_address(&key_pair_generator)]).address();
+        let encoded = script_address.to_string();
+        // The same hash as a public key address is another address.
+        assert!(Address::from_hash(script_address.as_hash().clone()).to_string() != encoded);
+        assert!(script_address == encoded.parse().unwrap());
+
+        assert_eq!(
+            Err(AddressError::Encoding(DecodingError::InvalidChecksum)),
+            "1111111111111111111111111111111112m1s9L".parse::<Address>().map(|_address| ())
+        );
+        assert_eq!(
+            Err(AddressError::InvalidLength),
+            "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa".parse::<Address>().map(|_address| ())
+        );
+        assert_eq!(
+            Err(AddressError::UnknownVersion(0x6f)),
+            base58::encode_check(0x6f, Hash::min().as_ref()).parse::<Address>().map(|_address| ())
+        );
+    }
+
     fn next_address(key_pair_generator: &KeyPairGenerator) -> Address {
         let next_to_keypair = key_pair_generator.random_keypair().ok().unwrap();
         let next_to_pub_key = next_to_keypair.pub_key();
-        let next_to_addr = Address::from_pub_key(&next_to_pub_key);
-        next_to_addr
+        Address::from_pub_key(&next_to_pub_key)
     }
 
     fn prev_context(key_pair_generator: &KeyPairGenerator, amount: u32) -> (KeyPair, TxOut) {
         let prev_to_keypair = key_pair_generator.random_keypair().ok().unwrap();
         let prev_to_pub_key = prev_to_keypair.pub_key();
         let prev_to_addr = Address::from_pub_key(&prev_to_pub_key);
-        let prev_output = TxOut {
-            amount,
-            to_address: prev_to_addr,
-        };
+        let prev_output = TxOut::new(amount, prev_to_addr);
         (prev_to_keypair, prev_output)
     }
 
-    struct SingleEntryUtxoStore(TxOut);
+    /// The outputs of a single transaction, by index.
+    struct TxOutsUtxoStore(Vec<TxOut>);
+
+    impl UtxoStore for TxOutsUtxoStore{
+        fn find(&self, _transaction_hash: &Hash, txo_index: &u8) -> Option<&TxOut> {
+            self.0.get(*txo_index as usize)
+        }
+
+        fn coinbase_height(&self, _transaction_hash: &Hash) -> Option<u32> {
+            None
+        }
+
+        fn signature_algorithm(&self) -> SignatureAlgorithm {
+            SignatureAlgorithm::Ed25519
+        }
+    }
+
+    struct SingleEntryUtxoStore(TxOut, SignatureAlgorithm);
 
     impl UtxoStore for SingleEntryUtxoStore{
         fn find(&self, _transaction_hash: &Hash, _txo_index: &u8) -> Option<&TxOut> {
             Some(&self.0)
         }
+
+        fn coinbase_height(&self, _transaction_hash: &Hash) -> Option<u32> {
+            None
+        }
+
+        fn signature_algorithm(&self) -> SignatureAlgorithm {
+            self.1
+        }
+    }
+
+    #[test]
+    fn can_deserialize_transactions() {
+        let key_pair_generator = KeyPairGenerator::new();
+        let (prev_to_keypair, prev_output) = prev_context(&key_pair_generator, 10);
+
+        let next_tx = RawTx {
+            input: vec![RawTxIn{
+                prev_tx_output_index: 0,
+                prev_tx_hash: Hash::min(),
+            }],
+            output: vec![TxOut::new(10, next_address(&key_pair_generator))],
+        };
+
+        let signed_tx = SignedTx::from_raw_tx(next_tx, &[&prev_output], vec![&prev_to_keypair]).ok().unwrap();
+        let serialized = bincode::serialize(&signed_tx).ok().unwrap();
+        let deserialized: SignedTx = bincode::deserialize(&serialized).ok().unwrap();
+
+        assert!(signed_tx.hash().ok().unwrap() == deserialized.hash().ok().unwrap());
+        verify(deserialized, prev_output).ok().unwrap();
+
+        assert!(bincode::deserialize::<SignedTx>(&serialized[..serialized.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn can_exchange_transactions_in_hex() {
+        let key_pair_generator = KeyPairGenerator::new();
+        let (prev_to_keypair, prev_output) = prev_context(&key_pair_generator, 10);
+        let next_tx = RawTx {
+            input: vec![RawTxIn{
+                prev_tx_output_index: 0,
+                prev_tx_hash: Hash::min(),
+            }],
+            output: vec![TxOut::new(10, next_address(&key_pair_generator))],
+        };
+        let signed_tx = SignedTx::from_raw_tx(next_tx, &[&prev_output], vec![&prev_to_keypair]).ok().unwrap();
+
+        let encoded = signed_tx.to_hex().unwrap();
+        let decoded = SignedTx::from_hex(&encoded.to_uppercase()).unwrap();
+        assert!(signed_tx.hash().unwrap() == decoded.hash().unwrap());
+        assert!(SignedTx::from_hex(&encoded[..encoded.len() - 2]).is_err());
+        assert!(SignedTx::from_hex("xyz").is_err());
+
+        let store = SingleEntryUtxoStore(prev_output, SignatureAlgorithm::Ed25519);
+        decoded.verify_input(0, &store, 0).unwrap();
+        let other_output = TxOut::new(10, next_address(&key_pair_generator));
+        let other_store = SingleEntryUtxoStore(other_output, SignatureAlgorithm::Ed25519);
+        assert!(decoded.verify_input(0, &other_store, 0).is_err());
+    }
+
+    #[test]
+    fn wire_format_is_stable() {
+        // Little-endian integers, then the locking script as a vector of bytes: the opcodes
+        // around the push of the 32 bytes of the address hash.
+        let tx_out = TxOut::new(258, Address::from_hash(Hash::min()));
+        let mut expected = vec![2u8, 1, 0, 0, 37, 0, 0, 0, 0, 0, 0, 0, 0x76, 0xa8, 32];
+        expected.extend_from_slice(&[0u8; 32]);
+        expected.extend_from_slice(&[0x88, 0xac]);
+        assert_eq!(expected, bincode::serialize(&tx_out).ok().unwrap());
+
+        // Vectors are prefixed with their length as a little-endian u64.
+        let raw_tx = RawTx {
+            input: vec![],
+            output: vec![tx_out],
+        };
+        let serialized = bincode::serialize(&raw_tx).ok().unwrap();
+        assert_eq!(8 + 8 + 4 + 8 + 37, serialized.len());
+        assert_eq!(&[1u8, 0, 0, 0, 0, 0, 0, 0], &serialized[8..16]);
     }
 
     fn verify(transaction: SignedTx, utxo: TxOut) -> Result<u32, Error> {
-        transaction.verify(&SingleEntryUtxoStore(utxo))?;
+        transaction.verify(&SingleEntryUtxoStore(utxo, SignatureAlgorithm::Ed25519), 0, &ChainParams::main())?;
         Ok(0)
     }
+
+    fn signature_algorithm() -> impl Strategy<Value = SignatureAlgorithm> {
+        prop_oneof![Just(SignatureAlgorithm::Ed25519), Just(SignatureAlgorithm::Secp256k1)]
+    }
+
+    /// A transaction spending a single output to the given amounts, signed with the key the
+    /// output is locked to, along with that output.
+    fn signed_payment(signature_algorithm: SignatureAlgorithm, amounts: &[u16], fees: u16) -> (SignedTx, TxOut) {
+        let key_pair_generator = KeyPairGenerator::new().with_signature_algorithm(signature_algorithm);
+        let in_amount = amounts.iter().map(|amount| *amount as u32).sum::<u32>() + fees as u32;
+        let (prev_to_keypair, prev_output) = prev_context(&key_pair_generator, in_amount);
+        let raw_tx = RawTx {
+            input: vec![RawTxIn{
+                prev_tx_output_index: 0,
+                prev_tx_hash: Hash::min(),
+            }],
+            output: amounts.iter()
+                .map(|amount| TxOut::new(*amount as u32, next_address(&key_pair_generator)))
+                .collect(),
+        };
+        (SignedTx::from_raw_tx(raw_tx, &[&prev_output], vec![&prev_to_keypair]).ok().unwrap(), prev_output)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn signed_transactions_verify(
+            signature_algorithm in signature_algorithm(),
+            amounts in prop::collection::vec(any::<u16>(), 0..4),
+            fees in any::<u16>(),
+        ) {
+            let (signed_tx, prev_output) = signed_payment(signature_algorithm, &amounts, fees);
+            let utxo_store = SingleEntryUtxoStore(prev_output, signature_algorithm);
+            prop_assert_eq!(Ok(fees as u32), signed_tx.verify(&utxo_store, 0, &ChainParams::main()));
+        }
+
+        #[test]
+        fn mutated_transactions_do_not_verify(
+            signature_algorithm in signature_algorithm(),
+            amounts in prop::collection::vec(any::<u16>(), 1..4),
+            index in any::<prop::sample::Index>(),
+            mask in 1..=255u8,
+        ) {
+            let (signed_tx, prev_output) = signed_payment(signature_algorithm, &amounts, 0);
+            let mut serialized = bincode::serialize(&signed_tx).ok().unwrap();
+            let index = index.index(serialized.len());
+            serialized[index] ^= mask;
+
+            // Either the bytes do not make a transaction anymore, or not one that verifies.
+            if let Ok(mutated) = bincode::deserialize::<SignedTx>(&serialized) {
+                let utxo_store = SingleEntryUtxoStore(prev_output, signature_algorithm);
+                prop_assert!(mutated.verify(&utxo_store, 0, &ChainParams::main()).is_err());
+            }
+        }
+    }
 }
\ No newline at end of file
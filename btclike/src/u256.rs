@@ -0,0 +1,266 @@
+//! An unsigned 256 bits integer, large enough for the thresholds of the proof of work and
+//! the total work of a chain.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Div, Not, Sub};
+
+const BYTES_LEN: usize = 32;
+const LIMBS_LEN: usize = 4;
+
+/// Stored as four 64 bits limbs, the least significant first.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct U256([u64; LIMBS_LEN]);
+
+impl U256 {
+    pub fn zero() -> U256 {
+        U256([0; LIMBS_LEN])
+    }
+
+    pub fn one() -> U256 {
+        U256::from(1u64)
+    }
+
+    pub fn max_value() -> U256 {
+        U256([u64::MAX; LIMBS_LEN])
+    }
+
+    pub fn from_be_bytes(bytes: &[u8; BYTES_LEN]) -> U256 {
+        let mut limbs = [0u64; LIMBS_LEN];
+        for (index, limb) in limbs.iter_mut().enumerate() {
+            let mut limb_bytes = [0u8; 8];
+            let end = BYTES_LEN - 8 * index;
+            limb_bytes.copy_from_slice(&bytes[end - 8..end]);
+            *limb = u64::from_be_bytes(limb_bytes);
+        }
+        U256(limbs)
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; BYTES_LEN] {
+        let mut bytes = [0u8; BYTES_LEN];
+        for (index, limb) in self.0.iter().enumerate() {
+            let end = BYTES_LEN - 8 * index;
+            bytes[end - 8..end].copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|limb| *limb == 0)
+    }
+
+    pub fn checked_add(&self, other: &U256) -> Option<U256> {
+        let mut sum = [0u64; LIMBS_LEN];
+        let mut carry = false;
+        for (index, limb) in sum.iter_mut().enumerate() {
+            let (partial, first_carry) = self.0[index].overflowing_add(other.0[index]);
+            let (partial, second_carry) = partial.overflowing_add(carry as u64);
+            *limb = partial;
+            carry = first_carry || second_carry;
+        }
+
+        if carry {
+            None
+        } else {
+            Some(U256(sum))
+        }
+    }
+
+    pub fn saturating_add(&self, other: &U256) -> U256 {
+        self.checked_add(other).unwrap_or_else(U256::max_value)
+    }
+
+    pub fn checked_sub(&self, other: &U256) -> Option<U256> {
+        let mut difference = [0u64; LIMBS_LEN];
+        let mut borrow = false;
+        for (index, limb) in difference.iter_mut().enumerate() {
+            let (partial, first_borrow) = self.0[index].overflowing_sub(other.0[index]);
+            let (partial, second_borrow) = partial.overflowing_sub(borrow as u64);
+            *limb = partial;
+            borrow = first_borrow || second_borrow;
+        }
+
+        if borrow {
+            None
+        } else {
+            Some(U256(difference))
+        }
+    }
+
+    pub fn checked_mul_u64(&self, factor: u64) -> Option<U256> {
+        let mut product = [0u64; LIMBS_LEN];
+        let mut carry = 0u128;
+        for (index, limb) in product.iter_mut().enumerate() {
+            carry += self.0[index] as u128 * factor as u128;
+            *limb = carry as u64;
+            carry >>= 64;
+        }
+
+        if carry == 0 {
+            Some(U256(product))
+        } else {
+            None
+        }
+    }
+
+    /// Panics if the divisor is zero.
+    pub fn div_u64(&self, divisor: u64) -> U256 {
+        assert!(divisor != 0, "Division by zero");
+
+        let mut quotient = [0u64; LIMBS_LEN];
+        let mut remainder = 0u128;
+        for index in (0..LIMBS_LEN).rev() {
+            remainder = (remainder << 64) | self.0[index] as u128;
+            quotient[index] = (remainder / divisor as u128) as u64;
+            remainder %= divisor as u128;
+        }
+        U256(quotient)
+    }
+
+    /// The number of significant bits.
+    pub fn bits(&self) -> usize {
+        for index in (0..LIMBS_LEN).rev() {
+            if self.0[index] != 0 {
+                return 64 * index + 64 - self.0[index].leading_zeros() as usize;
+            }
+        }
+        0
+    }
+
+    fn bit(&self, index: usize) -> bool {
+        self.0[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    fn set_bit(&mut self, index: usize) {
+        self.0[index / 64] |= 1 << (index % 64);
+    }
+
+    fn shl_one(&self) -> U256 {
+        let mut shifted = [0u64; LIMBS_LEN];
+        let mut carry = 0u64;
+        for (index, limb) in shifted.iter_mut().enumerate() {
+            *limb = (self.0[index] << 1) | carry;
+            carry = self.0[index] >> 63;
+        }
+        U256(shifted)
+    }
+}
+
+impl From<u64> for U256 {
+    fn from(value: u64) -> U256 {
+        U256([value, 0, 0, 0])
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &U256) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &U256) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Panics on overflow, like the primitive integers in debug builds.
+impl Add for U256 {
+    type Output = U256;
+
+    fn add(self, other: U256) -> U256 {
+        self.checked_add(&other).expect("U256 addition overflow")
+    }
+}
+
+/// Panics on underflow.
+impl Sub for U256 {
+    type Output = U256;
+
+    fn sub(self, other: U256) -> U256 {
+        self.checked_sub(&other).expect("U256 subtraction underflow")
+    }
+}
+
+/// A long division, bit by bit. Panics if the divisor is zero.
+impl Div for U256 {
+    type Output = U256;
+
+    fn div(self, divisor: U256) -> U256 {
+        assert!(!divisor.is_zero(), "Division by zero");
+
+        let mut quotient = U256::zero();
+        let mut remainder = U256::zero();
+        for index in (0..self.bits()).rev() {
+            remainder = remainder.shl_one();
+            if self.bit(index) {
+                remainder.0[0] |= 1;
+            }
+
+            if remainder >= divisor {
+                remainder = remainder - divisor;
+                quotient.set_bit(index);
+            }
+        }
+        quotient
+    }
+}
+
+impl Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> U256 {
+        let mut complement = self.0;
+        for limb in complement.iter_mut() {
+            *limb = !*limb;
+        }
+        U256(complement)
+    }
+}
+
+/// Hexadecimal, the most significant digits first.
+impl fmt::Debug for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x")?;
+        for limb in self.0.iter().rev() {
+            write!(f, "{:016x}", limb)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_from_and_to_bytes() {
+        let mut bytes = [0u8; BYTES_LEN];
+        bytes[0] = 0x12;
+        bytes[31] = 0x34;
+        let number = U256::from_be_bytes(&bytes);
+
+        assert_eq!(U256([0x34, 0, 0, 0x1200_0000_0000_0000]), number);
+        assert_eq!(bytes, number.to_be_bytes());
+        assert_eq!(253, number.bits());
+    }
+
+    #[test]
+    fn computes() {
+        let max = U256::max_value();
+        assert_eq!(None, max.checked_add(&U256::one()));
+        assert_eq!(max, max.saturating_add(&U256::one()));
+        assert_eq!(U256([0, 1, 0, 0]), U256::from(u64::MAX) + U256::one());
+        assert_eq!(U256::from(u64::MAX), U256([0, 1, 0, 0]) - U256::one());
+        assert_eq!(None, U256::zero().checked_sub(&U256::one()));
+
+        assert_eq!(Some(U256([0, 3, 0, 0])), U256([0x8000_0000_0000_0000, 1, 0, 0]).checked_mul_u64(2));
+        assert_eq!(None, max.checked_mul_u64(2));
+        assert_eq!(U256([0x8000_0000_0000_0000, 1, 0, 0]), U256([0, 3, 0, 0]).div_u64(2));
+
+        assert_eq!(U256::from(7), U256::from(23) / U256::from(3));
+        assert_eq!(U256([0, 1, 0, 0]), max / U256([u64::MAX, u64::MAX, u64::MAX, 0]));
+        assert_eq!(U256::zero(), !max);
+        assert!(U256([0, 0, 0, 1]) > U256([u64::MAX, u64::MAX, u64::MAX, 0]));
+    }
+}
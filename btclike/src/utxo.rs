@@ -0,0 +1,421 @@
+use blockchain::Body;
+use crypto::Hash;
+use crypto::SignatureAlgorithm;
+use Error;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use transaction::Address;
+use transaction::TxOut;
+use transaction::UtxoStore;
+use wallet;
+use wallet::TxOutReference;
+
+/// The changes made to a `UtxoSet` by a block, required to roll the block back.
+pub struct BlockUndo {
+    /// The outputs spent by the block, to be restored.
+    spent: Vec<(Hash, u8, TxOut)>,
+    /// The outputs created by the block, to be removed.
+    created: Vec<(Hash, u8)>,
+    /// The heights of the coinbase transactions whose output was spent by the block.
+    spent_coinbases: Vec<(Hash, u32)>,
+}
+
+/// The set of the unspent transaction outputs of a chain.
+/// Outputs are indexed both by transaction hash, for the transaction verification,
+/// and by address, for the wallets.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UtxoSet {
+    utxos: HashMap<Hash, HashMap<u8, TxOut>>,
+    references: HashMap<Address, Vec<TxOutReference>>,
+    /// The heights of the coinbase transactions with an unspent output, for the maturity rule.
+    coinbases: HashMap<Hash, u32>,
+    signature_algorithm: SignatureAlgorithm,
+}
+
+impl UtxoSet {
+    pub fn new() -> UtxoSet {
+        UtxoSet{
+            utxos: HashMap::new(),
+            references: HashMap::new(),
+            coinbases: HashMap::new(),
+            signature_algorithm: SignatureAlgorithm::default(),
+        }
+    }
+
+    /// Selects the scheme the transactions spending the outputs must be signed with, Ed25519
+    /// by default. Every node of a chain must select the same one.
+    pub fn with_signature_algorithm(mut self, signature_algorithm: SignatureAlgorithm) -> UtxoSet {
+        self.signature_algorithm = signature_algorithm;
+        self
+    }
+
+    /// Spends the inputs of the transactions of the body of the block at the given height and
+    /// adds their outputs, including the coinbase one, to the set. Returns what is needed to
+    /// roll the block back.
+    /// Fails without modifying the set if an input is not part of it or is spent twice, or
+    /// if a transaction of the body has the same hash as an unspent one.
+    /// This does not verify the signatures: the body is expected to have been verified first.
+    pub fn apply(&mut self, body: &Body, height: u32) -> Result<BlockUndo, Error> {
+        let mut spent = HashSet::new();
+        for transaction in body.transactions() {
+            for tx_in in transaction.input() {
+                let outpoint = (tx_in.prev_tx_hash(), tx_in.prev_tx_output_index());
+
+                if self.find(outpoint.0, outpoint.1).is_none() || !spent.insert(outpoint) {
+                    return Err(Error::UtxoNotFound);
+                }
+            }
+        }
+
+        let mut created = vec![];
+        for transaction in body.transactions() {
+            created.push((transaction.hash()?, transaction.output()));
+        }
+        let coinbase_hash = body.coinbase_tx().hash()?;
+        let coinbase_tx_out = &body.coinbase_tx().0;
+        created.push((coinbase_hash.clone(), ::std::slice::from_ref(coinbase_tx_out)));
+
+        // Overwriting an unspent output would make the rollback lose it.
+        for (tx_hash, _output) in &created {
+            if self.utxos.contains_key(tx_hash) {
+                return Err(Error::DuplicateTransaction);
+            }
+        }
+
+        let mut undo = BlockUndo {
+            spent: vec![],
+            created: vec![],
+            spent_coinbases: vec![],
+        };
+
+        for (tx_hash, tx_out_index) in spent {
+            if let Some(coinbase_height) = self.coinbases.get(tx_hash) {
+                undo.spent_coinbases.push((tx_hash.clone(), *coinbase_height));
+            }
+
+            if let Some(tx_out) = self.remove(tx_hash, tx_out_index) {
+                undo.spent.push((tx_hash.clone(), *tx_out_index, tx_out));
+            }
+        }
+
+        for (tx_hash, output) in created {
+            for (tx_out_index, tx_out) in output.iter().enumerate() {
+                self.insert(tx_hash.clone(), tx_out_index as u8, tx_out.clone());
+                undo.created.push((tx_hash.clone(), tx_out_index as u8));
+            }
+        }
+        self.coinbases.insert(coinbase_hash, height);
+
+        Ok(undo)
+    }
+
+    /// Reverts the changes made by a block: removes the outputs it created and restores the
+    /// ones it spent. Blocks must be rolled back in the reverse order of their application.
+    /// Fails without modifying the set if an output created by the block is not unspent.
+    pub fn rollback(&mut self, undo: &BlockUndo) -> Result<(), Error> {
+        for (tx_hash, tx_out_index) in &undo.created {
+            if self.find(tx_hash, tx_out_index).is_none() {
+                return Err(Error::UtxoNotFound);
+            }
+        }
+
+        for (tx_hash, tx_out_index) in &undo.created {
+            self.remove(tx_hash, tx_out_index);
+        }
+
+        for (tx_hash, tx_out_index, tx_out) in &undo.spent {
+            self.insert(tx_hash.clone(), *tx_out_index, tx_out.clone());
+        }
+
+        for (tx_hash, coinbase_height) in &undo.spent_coinbases {
+            self.coinbases.insert(tx_hash.clone(), *coinbase_height);
+        }
+
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.utxos.values().map(|outputs| outputs.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.utxos.is_empty()
+    }
+
+    fn insert(&mut self, tx_hash: Hash, tx_out_index: u8, tx_out: TxOut) {
+        self.references.entry(tx_out.address())
+            .or_default()
+            .push(TxOutReference::new(tx_hash.clone(), tx_out_index, *tx_out.amount()));
+
+        self.utxos.entry(tx_hash)
+            .or_default()
+            .insert(tx_out_index, tx_out);
+    }
+
+    fn remove(&mut self, tx_hash: &Hash, tx_out_index: &u8) -> Option<TxOut> {
+        let removed = match self.utxos.get_mut(tx_hash) {
+            Some(outputs) => {
+                let removed = outputs.remove(tx_out_index);
+                if outputs.is_empty() {
+                    self.utxos.remove(tx_hash);
+                    self.coinbases.remove(tx_hash);
+                }
+                removed
+            },
+            None => None,
+        };
+
+        if let Some(ref tx_out) = removed {
+            let address = tx_out.address();
+            let now_empty = match self.references.get_mut(&address) {
+                Some(references) => {
+                    references.retain(|reference| {
+                        reference.tx_hash() != tx_hash || reference.tx_out_index() != tx_out_index
+                    });
+                    references.is_empty()
+                },
+                None => false,
+            };
+
+            if now_empty {
+                self.references.remove(&address);
+            }
+        }
+
+        removed
+    }
+}
+
+impl Default for UtxoSet {
+    fn default() -> Self {
+        UtxoSet::new()
+    }
+}
+
+impl UtxoStore for UtxoSet {
+    fn find(&self, transaction_hash: &Hash, txo_index: &u8) -> Option<&TxOut> {
+        self.utxos.get(transaction_hash)
+            .and_then(|outputs| outputs.get(txo_index))
+    }
+
+    fn coinbase_height(&self, transaction_hash: &Hash) -> Option<u32> {
+        self.coinbases.get(transaction_hash).cloned()
+    }
+
+    fn signature_algorithm(&self) -> SignatureAlgorithm {
+        self.signature_algorithm
+    }
+}
+
+impl wallet::UtxoStore for UtxoSet {
+    fn find_for_address(&self, address: &Address) -> &[TxOutReference] {
+        self.references.get(address)
+            .map_or(&[], |references| references.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chain_params::ChainParams;
+    use blockchain::{COINBASE_AMOUNT, COINBASE_MATURITY};
+    use transaction::SignedTx;
+    use wallet::Wallet;
+
+    #[test]
+    fn can_spend_and_create_outputs() {
+        let mut wallet = Wallet::new();
+        let mut utxo_set = UtxoSet::new();
+
+        let coinbase_body = Body::new(TxOut::new(COINBASE_AMOUNT, wallet.new_address().unwrap()), vec![]);
+        utxo_set.apply(&coinbase_body, 0).unwrap();
+        wallet.connect_block(&coinbase_body, 0).unwrap();
+        assert_eq!(1, utxo_set.len());
+
+        let to_address = wallet.new_address().unwrap();
+        let transaction = wallet.new_transaction(600, to_address.clone(), 10, &utxo_set).unwrap();
+        let fees = transaction.verify(&utxo_set, COINBASE_MATURITY, &ChainParams::main()).unwrap();
+        let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT + fees, wallet.new_address().unwrap());
+        let body = Body::new(coinbase_tx_out, vec![transaction.clone()]);
+        body.verify(&utxo_set, COINBASE_MATURITY, &ChainParams::main()).unwrap();
+        utxo_set.apply(&body, COINBASE_MATURITY).unwrap();
+
+        // The coinbase output was spent, the change, the payment and the new coinbase are unspent.
+        assert_eq!(3, utxo_set.len());
+        assert!(utxo_set.find(&coinbase_body.coinbase_tx().hash().unwrap(), &0).is_none());
+        assert_eq!(&600, utxo_set.find(&transaction.hash().unwrap(), &1).unwrap().amount());
+        assert_eq!(&600, wallet::UtxoStore::find_for_address(&utxo_set, &to_address)[0].amount());
+    }
+
+    #[test]
+    fn verifies_signatures_with_the_scheme_of_the_set() {
+        let mut wallet = Wallet::new().with_signature_algorithm(SignatureAlgorithm::Secp256k1);
+        let mut utxo_set = UtxoSet::new().with_signature_algorithm(SignatureAlgorithm::Secp256k1);
+
+        let coinbase_body = Body::new(TxOut::new(COINBASE_AMOUNT, wallet.new_address().unwrap()), vec![]);
+        utxo_set.apply(&coinbase_body, 0).unwrap();
+        wallet.connect_block(&coinbase_body, 0).unwrap();
+
+        let to_address = wallet.new_address().unwrap();
+        let transaction = wallet.new_transaction(600, to_address, 10, &utxo_set).unwrap();
+        assert_eq!(10, transaction.verify(&utxo_set, COINBASE_MATURITY, &ChainParams::main()).unwrap());
+
+        let ed25519_utxo_set = utxo_set.clone().with_signature_algorithm(SignatureAlgorithm::Ed25519);
+        assert!(transaction.verify(&ed25519_utxo_set, COINBASE_MATURITY, &ChainParams::main()).is_err());
+    }
+
+    #[test]
+    fn can_spend_multiple_inputs() {
+        let mut wallet = Wallet::new();
+        let mut utxo_set = UtxoSet::new();
+
+        for height in 0..2 {
+            let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT, wallet.new_address().unwrap());
+            let body = Body::new(coinbase_tx_out, vec![]);
+            utxo_set.apply(&body, height).unwrap();
+            wallet.connect_block(&body, height).unwrap();
+        }
+
+        let to_address = wallet.new_address().unwrap();
+        let transaction = wallet.new_transaction(COINBASE_AMOUNT + 1, to_address, 0, &utxo_set).unwrap();
+        assert_eq!(2, transaction.input().len());
+        transaction.verify(&utxo_set, COINBASE_MATURITY + 1, &ChainParams::main()).unwrap();
+    }
+
+    #[test]
+    fn rejects_double_spends() {
+        let mut wallet = Wallet::new();
+        let mut utxo_set = UtxoSet::new();
+
+        let coinbase_body = Body::new(TxOut::new(COINBASE_AMOUNT, wallet.new_address().unwrap()), vec![]);
+        utxo_set.apply(&coinbase_body, 0).unwrap();
+        wallet.connect_block(&coinbase_body, 0).unwrap();
+
+        let to_address = wallet.new_address().unwrap();
+        let transaction = wallet.new_transaction(600, to_address.clone(), 0, &utxo_set).unwrap();
+        let conflicting_transaction = wallet.new_transaction(500, to_address, 0, &utxo_set).unwrap();
+
+        let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT, wallet.new_address().unwrap());
+        let body = Body::new(coinbase_tx_out.clone(), vec![transaction.clone(), conflicting_transaction]);
+        assert_eq!(Error::UtxoNotFound, utxo_set.apply(&body, 1).err().unwrap());
+        assert_eq!(1, utxo_set.len());
+
+        let body = Body::new(coinbase_tx_out, vec![transaction]);
+        utxo_set.apply(&body, 1).unwrap();
+        assert_eq!(Error::UtxoNotFound, utxo_set.apply(&body, 2).err().unwrap());
+    }
+
+    #[test]
+    fn rejects_duplicated_transactions() {
+        let mut wallet = Wallet::new();
+        let mut utxo_set = UtxoSet::new();
+
+        let coinbase_body = Body::new(TxOut::new(COINBASE_AMOUNT, wallet.new_address().unwrap()), vec![]);
+        utxo_set.apply(&coinbase_body, 0).unwrap();
+        assert_eq!(Error::DuplicateTransaction, utxo_set.apply(&coinbase_body, 1).err().unwrap());
+        assert_eq!(1, utxo_set.len());
+    }
+
+    #[test]
+    fn can_roll_back_a_block() {
+        let mut wallet = Wallet::new();
+        let mut utxo_set = UtxoSet::new();
+
+        let coinbase_address = wallet.new_address().unwrap();
+        let coinbase_body = Body::new(TxOut::new(COINBASE_AMOUNT, coinbase_address.clone()), vec![]);
+        let coinbase_hash = coinbase_body.coinbase_tx().hash().unwrap();
+        utxo_set.apply(&coinbase_body, 0).unwrap();
+        wallet.connect_block(&coinbase_body, 0).unwrap();
+
+        let (body, transaction) = payment_body(&mut wallet, &utxo_set);
+        let undo = utxo_set.apply(&body, COINBASE_MATURITY).unwrap();
+        assert!(utxo_set.find(&coinbase_hash, &0).is_none());
+        assert_eq!(None, utxo_set.coinbase_height(&coinbase_hash));
+        assert_eq!(Some(COINBASE_MATURITY), utxo_set.coinbase_height(&body.coinbase_tx().hash().unwrap()));
+
+        utxo_set.rollback(&undo).unwrap();
+        assert_eq!(1, utxo_set.len());
+        assert_eq!(Some(0), utxo_set.coinbase_height(&coinbase_hash));
+        assert_eq!(None, utxo_set.coinbase_height(&body.coinbase_tx().hash().unwrap()));
+        assert_eq!(&COINBASE_AMOUNT, utxo_set.find(&coinbase_hash, &0).unwrap().amount());
+        assert!(
+            &coinbase_hash
+                == wallet::UtxoStore::find_for_address(&utxo_set, &coinbase_address)[0].tx_hash()
+        );
+        assert!(utxo_set.find(&transaction.hash().unwrap(), &0).is_none());
+        assert!(utxo_set.find(&body.coinbase_tx().hash().unwrap(), &0).is_none());
+    }
+
+    #[test]
+    fn can_roll_back_a_reorganization() {
+        let mut wallet = Wallet::new();
+        let mut utxo_set = UtxoSet::new();
+
+        let coinbase_body = Body::new(TxOut::new(COINBASE_AMOUNT, wallet.new_address().unwrap()), vec![]);
+        let coinbase_hash = coinbase_body.coinbase_tx().hash().unwrap();
+        utxo_set.apply(&coinbase_body, 0).unwrap();
+        wallet.connect_block(&coinbase_body, 0).unwrap();
+
+        // The first branch: the second block spends an output of the first one.
+        let (first_body, first_transaction) = payment_body(&mut wallet, &utxo_set);
+        let first_undo = utxo_set.apply(&first_body, 1).unwrap();
+        wallet.connect_block(&first_body, 1).unwrap();
+        let first_len = utxo_set.len();
+        let (second_body, _transaction) = payment_body(&mut wallet, &utxo_set);
+        let second_undo = utxo_set.apply(&second_body, 2).unwrap();
+        wallet.connect_block(&second_body, 2).unwrap();
+        let second_len = utxo_set.len();
+
+        // Rolling back out of order fails and leaves the set untouched.
+        assert_eq!(Error::UtxoNotFound, utxo_set.rollback(&first_undo).err().unwrap());
+        assert_eq!(second_len, utxo_set.len());
+
+        utxo_set.rollback(&second_undo).unwrap();
+        wallet.disconnect_block(2);
+        assert_eq!(first_len, utxo_set.len());
+        utxo_set.rollback(&first_undo).unwrap();
+        wallet.disconnect_block(1);
+        assert_eq!(1, utxo_set.len());
+        assert_eq!(COINBASE_AMOUNT as u64, wallet.balance(&utxo_set));
+        assert!(utxo_set.find(&coinbase_hash, &0).is_some());
+        assert!(utxo_set.find(&first_transaction.hash().unwrap(), &0).is_none());
+
+        // The other branch spends the coinbase output differently.
+        let (other_body, other_transaction) = payment_body(&mut wallet, &utxo_set);
+        utxo_set.apply(&other_body, 1).unwrap();
+        assert!(utxo_set.find(&coinbase_hash, &0).is_none());
+        assert!(utxo_set.find(&other_transaction.hash().unwrap(), &0).is_some());
+        assert!(utxo_set.find(&first_transaction.hash().unwrap(), &0).is_none());
+    }
+
+    #[test]
+    fn rejects_immature_coinbase_spends() {
+        let mut wallet = Wallet::new();
+        let mut utxo_set = UtxoSet::new();
+
+        let coinbase_body = Body::new(TxOut::new(COINBASE_AMOUNT, wallet.new_address().unwrap()), vec![]);
+        utxo_set.apply(&coinbase_body, 5).unwrap();
+        wallet.connect_block(&coinbase_body, 5).unwrap();
+
+        let (body, transaction) = payment_body(&mut wallet, &utxo_set);
+        let params = ChainParams::main();
+        let immature_height = 5 + COINBASE_MATURITY - 1;
+        assert_eq!(Error::ImmatureCoinbase, transaction.verify(&utxo_set, immature_height, &params).err().unwrap());
+        assert_eq!(Error::ImmatureCoinbase, body.verify(&utxo_set, immature_height, &params).err().unwrap());
+        body.verify(&utxo_set, 5 + COINBASE_MATURITY, &ChainParams::main()).unwrap();
+
+        // The outputs of regular transactions can be spent right away.
+        utxo_set.apply(&body, 5 + COINBASE_MATURITY).unwrap();
+        wallet.connect_block(&body, 5 + COINBASE_MATURITY).unwrap();
+        let (_body, transaction) = payment_body(&mut wallet, &utxo_set);
+        transaction.verify(&utxo_set, 5 + COINBASE_MATURITY, &ChainParams::main()).unwrap();
+    }
+
+    /// Builds a body containing a payment of the wallet, with a coinbase to a new address.
+    fn payment_body(wallet: &mut Wallet, utxo_set: &UtxoSet) -> (Body, SignedTx) {
+        let to_address = wallet.new_address().unwrap();
+        let transaction = wallet.new_transaction(100, to_address, 0, utxo_set).unwrap();
+        let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT, wallet.new_address().unwrap());
+        (Body::new(coinbase_tx_out, vec![transaction.clone()]), transaction)
+    }
+}
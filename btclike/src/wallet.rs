@@ -1,27 +1,301 @@
+use bincode;
+use crypto::{self, ExtendedKey};
 use crypto::KeyPair;
 use crypto::KeyPairGenerator;
+use crypto::Seed;
+use crypto::SignatureAlgorithm;
+use blockchain::Body;
 use transaction::Address;
 use transaction::TxOut;
 use transaction::RawTxIn;
 use Error;
 use transaction::RawTx;
 use transaction::SignedTx;
+use transaction::MultiSig;
+use transaction::{HashTimeLock, HashTimeLockBranch};
+use transaction::PartiallySignedTx;
+use transaction;
 use crypto::Hash;
+use chain_store::BlockFees;
+use coin_selection::{CoinSelector, InOrder};
+use mempool::Mempool;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// The number of consecutive unused addresses after which a scan stops looking for funds.
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+/// The number of recent blocks considered to estimate fees.
+pub const FEE_ESTIMATION_BLOCKS: usize = 10;
+/// The size in bytes of a payment spending one output and sending the change back to the
+/// wallet, the most common transaction built by `new_transaction`.
+const ESTIMATED_PAYMENT_SIZE: u64 = 253;
 
 /// A naive implementation of a cryptocurrency wallet.
+///
+/// Every key is derived from a single seed: the key of the address number `i` is the hardened
+/// child `i` of the master key. Restoring the seed and scanning the UTXO set is enough to
+/// recover the funds of the wallet.
+///
+/// The wallet keeps track of its unspent outputs and of its history as the blocks are
+/// connected and disconnected, so that it does not have to look for its funds in the whole
+/// UTXO set.
+///
+/// Addresses of external keys can be imported as watch-only: their payments are tracked like
+/// the ones of the wallet, but they are never spent.
 pub struct Wallet{
     accounts: Vec<Account>,
-    generator: KeyPairGenerator,
+    watch_only: Vec<Address>,
+    /// The account or watch-only address of each address.
+    owners: HashMap<Address, Owner>,
+    /// The outputs sent to the addresses of the wallet and not spent yet, with their owner.
+    unspent: HashMap<(Hash, u8), (Owner, TxOutReference)>,
+    history: Vec<HistoryEntry>,
+    seed: Seed,
+    master_key: ExtendedKey,
+    signature_algorithm: SignatureAlgorithm,
 }
 
 impl Wallet {
+    /// Creates a wallet from a new random seed.
     pub fn new() -> Wallet{
+        let seed = KeyPairGenerator::new().random_seed().expect("Could not generate a seed");
+        Wallet::from_seed(seed)
+    }
+
+    /// Creates a wallet without any address. Use `scan` to find the addresses funded by a
+    /// previous wallet with the same seed.
+    pub fn from_seed(seed: Seed) -> Wallet {
+        let master_key = ExtendedKey::master(seed.as_bytes());
+
         Wallet{
             accounts: vec![],
-            generator: KeyPairGenerator::new(),
+            watch_only: vec![],
+            owners: HashMap::new(),
+            unspent: HashMap::new(),
+            history: vec![],
+            seed,
+            master_key,
+            signature_algorithm: SignatureAlgorithm::default(),
+        }
+    }
+
+    /// Selects the scheme of the keys of the wallet, Ed25519 by default. It must match the
+    /// one of the chain, and be selected before any address is derived: the existing
+    /// addresses keep their scheme.
+    pub fn with_signature_algorithm(mut self, signature_algorithm: SignatureAlgorithm) -> Wallet {
+        self.signature_algorithm = signature_algorithm;
+        self
+    }
+
+    /// The seed to back up in order to restore the wallet.
+    pub fn seed(&self) -> &Seed {
+        &self.seed
+    }
+
+    /// Saves the seed, the number of addresses and the watch-only addresses of the wallet to a
+    /// file, encrypted with a key derived from the passphrase. The previous content of the
+    /// file is replaced.
+    pub fn save<P: AsRef<Path>>(&self, path: P, passphrase: &str) -> Result<(), Error> {
+        let state = SavedWallet {
+            seed: self.seed.clone(),
+            address_count: self.accounts.len() as u32,
+            watch_only: self.watch_only.clone(),
+            signature_algorithm: self.signature_algorithm,
+        };
+        let encrypted = crypto::encrypt_with_passphrase(passphrase, &bincode::serialize(&state)?)?;
+
+        // Written aside then renamed, so that a crash never leaves a partial wallet.
+        let path = path.as_ref();
+        let temporary_path = path.with_extension("tmp");
+        let mut file = File::create(&temporary_path)?;
+        file.write_all(&encrypted)?;
+        file.sync_all()?;
+        fs::rename(temporary_path, path)?;
+        Ok(())
+    }
+
+    /// Restores a wallet saved with `save`, along with all the addresses it had derived or
+    /// imported. The history is not saved: the blocks must be connected again, or the UTXO
+    /// set scanned, before the funds can be spent.
+    pub fn load<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<Wallet, Error> {
+        let mut encrypted = vec![];
+        File::open(path)?.read_to_end(&mut encrypted)?;
+        let bytes = crypto::decrypt_with_passphrase(passphrase, &encrypted)
+            .map_err(|_| Error::InvalidPassphrase)?;
+        let state: SavedWallet = bincode::deserialize(&bytes)?;
+
+        let mut wallet = Wallet::from_seed(state.seed)
+            .with_signature_algorithm(state.signature_algorithm);
+        for _index in 0..state.address_count {
+            wallet.new_address()?;
+        }
+        for address in state.watch_only {
+            wallet.import_watch_only(address);
+        }
+        Ok(wallet)
+    }
+
+    /// Derives addresses until `gap_limit` consecutive ones are not funded, so the funds sent
+    /// to addresses of a previous wallet with the same seed can be spent again. The unspent
+    /// outputs found, including the ones of the watch-only addresses, are added to the wallet,
+    /// without history. Returns the number of funded derived addresses.
+    ///
+    /// Only unspent outputs are visible, so an address that received funds then spent all of
+    /// them counts as unused.
+    pub fn scan<S>(&mut self, utxo_store: &S, gap_limit: u32) -> Result<usize, Error>
+        where S: UtxoStore
+    {
+        let mut funded_accounts = 0;
+        let mut gap = 0;
+        let mut index = 0;
+        // The accounts derived past the known ones, kept only if a later one is funded.
+        let mut derived_accounts = vec![];
+
+        while gap < gap_limit {
+            let address = match self.accounts.get(index) {
+                Some(account) => account.address.clone(),
+                None => {
+                    let account = self.derive_account(index as u32)?;
+                    let address = account.address.clone();
+                    derived_accounts.push(account);
+                    address
+                },
+            };
+
+            let references = utxo_store.find_for_address(&address);
+            if references.is_empty() {
+                gap += 1;
+            } else {
+                for account in derived_accounts.drain(..) {
+                    self.push_account(account);
+                }
+                for reference in references {
+                    let outpoint = (reference.tx_hash.clone(), reference.tx_out_index);
+                    self.unspent.insert(outpoint, (Owner::Account(index), reference.clone()));
+                }
+
+                funded_accounts += 1;
+                gap = 0;
+            }
+            index += 1;
+        }
+
+        for (watch_only_index, address) in self.watch_only.iter().enumerate() {
+            for reference in utxo_store.find_for_address(address) {
+                let outpoint = (reference.tx_hash.clone(), reference.tx_out_index);
+                self.unspent.insert(outpoint, (Owner::WatchOnly(watch_only_index), reference.clone()));
+            }
+        }
+
+        Ok(funded_accounts)
+    }
+
+    /// Records the outputs of the block sent to the wallet and the outputs of the wallet the
+    /// block spends.
+    pub fn connect_block(&mut self, body: &Body, height: u32) -> Result<(), Error> {
+        for transaction in body.transactions() {
+            for tx_in in transaction.input() {
+                let outpoint = (tx_in.prev_tx_hash().clone(), *tx_in.prev_tx_output_index());
+
+                if let Some((owner, output)) = self.unspent.remove(&outpoint) {
+                    self.history.push(HistoryEntry {
+                        height,
+                        kind: HistoryKind::Spent,
+                        address: self.address_of(owner).clone(),
+                        output,
+                    });
+                }
+            }
+        }
+
+        let mut created = vec![];
+        for transaction in body.transactions() {
+            created.push((transaction.hash()?, transaction.output()));
+        }
+        let coinbase_tx_out = &body.coinbase_tx().0;
+        created.push((body.coinbase_tx().hash()?, ::std::slice::from_ref(coinbase_tx_out)));
+
+        for (tx_hash, output) in created {
+            for (tx_out_index, tx_out) in output.iter().enumerate() {
+                let address = tx_out.address();
+
+                if let Some(&owner) = self.owners.get(&address) {
+                    let output = TxOutReference::new(tx_hash.clone(), tx_out_index as u8, *tx_out.amount());
+                    self.unspent.insert((tx_hash.clone(), tx_out_index as u8), (owner, output.clone()));
+                    self.history.push(HistoryEntry {
+                        height,
+                        kind: HistoryKind::Funded,
+                        address,
+                        output,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reverts the changes made by the block at the given height. Blocks must be disconnected
+    /// in the reverse order of their connection.
+    pub fn disconnect_block(&mut self, height: u32) {
+        while self.history.last().is_some_and(|entry| entry.height == height) {
+            let entry = self.history.pop().expect("The history is not empty");
+            let outpoint = (entry.output.tx_hash.clone(), entry.output.tx_out_index);
+
+            match entry.kind {
+                HistoryKind::Funded => {
+                    self.unspent.remove(&outpoint);
+                },
+                HistoryKind::Spent => {
+                    let owner = self.owners[&entry.address];
+                    self.unspent.insert(outpoint, (owner, entry.output));
+                },
+            }
         }
     }
 
+    /// The outputs sent to and spent from the addresses of the wallet, watch-only ones
+    /// included, in block order.
+    pub fn history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+
+    /// Tracks the payments sent to an address of an external key. Only the blocks connected
+    /// afterwards, or a `scan`, reveal its funds. Importing an address the wallet already
+    /// tracks does nothing.
+    pub fn import_watch_only(&mut self, address: Address) {
+        if !self.owners.contains_key(&address) {
+            self.owners.insert(address.clone(), Owner::WatchOnly(self.watch_only.len()));
+            self.watch_only.push(address);
+        }
+    }
+
+    /// Whether the address was imported with `import_watch_only`.
+    pub fn is_watch_only(&self, address: &Address) -> bool {
+        self.owners.get(address).is_some_and(Owner::is_watch_only)
+    }
+
+    /// The total amount of the unspent outputs the wallet can spend. The outputs missing from
+    /// the UTXO store, typically because a block spending them was not connected to the
+    /// wallet yet, are not counted.
+    pub fn balance<S>(&self, utxo_store: &S) -> u64
+        where S: transaction::UtxoStore
+    {
+        self.unspent_balance(utxo_store, false)
+    }
+
+    /// The total amount of the unspent outputs sent to the watch-only addresses.
+    pub fn watch_only_balance<S>(&self, utxo_store: &S) -> u64
+        where S: transaction::UtxoStore
+    {
+        self.unspent_balance(utxo_store, true)
+    }
+
+    /// Builds a payment spending the unspent outputs of the wallet that are still part of the
+    /// UTXO store, the outputs of the oldest accounts first, and sending the change to a new
+    /// address.
     pub fn new_transaction<S>(
         &mut self,
         amount: u32,
@@ -29,65 +303,358 @@ impl Wallet {
         fees: u32,
         utxo_store: &S,
     ) -> Result<SignedTx, Error>
-        where S: UtxoStore
+        where S: transaction::UtxoStore
+    {
+        self.new_transaction_with(amount, to_address, fees, utxo_store, &InOrder)
+    }
+
+    /// Builds a payment spending the unspent outputs of the wallet chosen by the coin
+    /// selector among the ones still part of the UTXO store. The outputs of the watch-only
+    /// addresses are never candidates. The change, if any, is sent to a new address as the
+    /// first output.
+    pub fn new_transaction_with<S>(
+        &mut self,
+        amount: u32,
+        to_address: Address,
+        fees: u32,
+        utxo_store: &S,
+        coin_selector: &dyn CoinSelector,
+    ) -> Result<SignedTx, Error>
+        where S: transaction::UtxoStore
     {
-        let change_address = self.new_address()?;
+        let total_cost = amount.checked_add(fees).ok_or(Error::InvalidTxAmount)?;
+        let selection = self.select_inputs(total_cost, utxo_store, coin_selector)?;
 
-        let total_cost = amount + fees;
-        let mut collected_amount = 0u32;
+        let mut output = vec![];
+        let change = selection.amount - total_cost;
+        if change > 0 {
+            output.push(TxOut::new(change, self.new_address()?));
+        }
+        output.push(TxOut::new(amount, to_address));
 
-        let mut raw_tx_ins = vec![];
-        let mut key_pairs = vec![];
-        {
-            // PERFORMANCE We iterate over the accounts to collect the funds when
-            // it would have been more efficient to track the list of funded addresses.
-            let mut account_iter = self.accounts.iter();
-
-            while collected_amount < total_cost {
-                match account_iter.next() {
-                    Some(account) => {
-                        if let Some(utxo_reference) = utxo_store.find_for_address(&account.address) {
-                            let raw_tx_in = RawTxIn{
-                                prev_tx_hash: utxo_reference.tx_hash.clone(),
-                                prev_tx_output_index: utxo_reference.tx_out_index,
-                            };
-
-                            raw_tx_ins.push(raw_tx_in);
-                            key_pairs.push(&account.key_pair);
-                            collected_amount += utxo_reference.amount;
-                        }
-                    },
-                    None => {
-                        return Err(Error::NotEnoughTokens);
-                    }
+        let raw_tx = RawTx {
+            input: selection.inputs,
+            output,
+        };
+
+        let key_pairs = selection.account_indexes.into_iter()
+            .map(|account_index| &self.accounts[account_index].key_pair)
+            .collect();
+        SignedTx::from_raw_tx(raw_tx, &selection.spent_outputs, key_pairs)
+    }
+
+    /// Chooses unspent outputs of the wallet still part of the UTXO store worth at least the
+    /// given amount with the coin selector. The outputs of the watch-only addresses are never
+    /// candidates.
+    pub(crate) fn select_inputs<'s, S>(
+        &self,
+        amount: u32,
+        utxo_store: &'s S,
+        coin_selector: &dyn CoinSelector,
+    ) -> Result<Selection<'s>, Error>
+        where S: transaction::UtxoStore
+    {
+        // Sorted so that the candidates, and the selection of deterministic selectors, do not
+        // depend on the order of the map.
+        let mut unspent: Vec<(usize, &TxOutReference)> = self.unspent.values()
+            .filter_map(|(owner, output)| match owner {
+                Owner::Account(account_index) => Some((*account_index, output)),
+                Owner::WatchOnly(_) => None,
+            })
+            .filter(|(_account_index, output)| utxo_store.find(&output.tx_hash, &output.tx_out_index).is_some())
+            .collect();
+        unspent.sort_by(|one, other| {
+            (one.0, &one.1.tx_hash, one.1.tx_out_index).cmp(&(other.0, &other.1.tx_hash, other.1.tx_out_index))
+        });
+        let candidates: Vec<TxOutReference> = unspent.iter()
+            .map(|(_account_index, output)| (*output).clone())
+            .collect();
+
+        let indexes = coin_selector.select(&candidates, amount)
+            .ok_or(Error::NotEnoughTokens)?;
+
+        let mut selection = Selection {
+            inputs: vec![],
+            spent_outputs: vec![],
+            account_indexes: vec![],
+            amount: 0,
+        };
+        for index in indexes {
+            let (account_index, utxo_reference) = unspent[index];
+            selection.inputs.push(RawTxIn{
+                prev_tx_hash: utxo_reference.tx_hash.clone(),
+                prev_tx_output_index: utxo_reference.tx_out_index,
+            });
+            selection.spent_outputs.push(
+                utxo_store.find(&utxo_reference.tx_hash, &utxo_reference.tx_out_index).ok_or(Error::UtxoNotFound)?
+            );
+            selection.account_indexes.push(account_index);
+            selection.amount += utxo_reference.amount;
+        }
+        Ok(selection)
+    }
+
+    /// Builds a transaction spending an output locked by the multi-signature condition, or
+    /// sent to its hash, and sending the change to the hash of the condition. The transaction
+    /// is signed by the keys of the wallet the condition lists, the other co-signers complete
+    /// it with `co_sign`.
+    pub fn new_multisig_transaction<S>(
+        &self,
+        multisig: &MultiSig,
+        amount: u32,
+        to_address: Address,
+        fees: u32,
+        utxo_store: &S,
+    ) -> Result<PartiallySignedTx, Error>
+        where S: UtxoStore + transaction::UtxoStore
+    {
+        let total_cost = amount.checked_add(fees).ok_or(Error::InvalidTxAmount)?;
+        let utxo_reference = utxo_store.find_for_address(&multisig.address()).iter()
+            .find(|utxo_reference| utxo_reference.amount >= total_cost)
+            .ok_or(Error::NotEnoughTokens)?;
+        let tx_out = utxo_store.find(&utxo_reference.tx_hash, &utxo_reference.tx_out_index)
+            .ok_or(Error::UtxoNotFound)?;
+
+        let raw_tx = RawTx {
+            input: vec![RawTxIn{
+                prev_tx_hash: utxo_reference.tx_hash.clone(),
+                prev_tx_output_index: utxo_reference.tx_out_index,
+            }],
+            output: vec![
+                TxOut::new(utxo_reference.amount - total_cost, multisig.address()),
+                TxOut::new(amount, to_address),
+            ],
+        };
+
+        let mut transaction = PartiallySignedTx::new(raw_tx, &[tx_out])?;
+        if tx_out.lock_script().script_hash().is_some() {
+            transaction.set_redeem_script(0, multisig.script());
+        }
+        self.co_sign(&mut transaction, multisig);
+        Ok(transaction)
+    }
+
+    /// Signs every input of the transaction, expected to spend outputs locked by the
+    /// multi-signature condition, with the keys of the wallet the condition lists.
+    /// Inputs already signed by enough keys are left untouched.
+    pub fn co_sign(&self, transaction: &mut PartiallySignedTx, multisig: &MultiSig) {
+        for input_index in 0..transaction.raw_tx().input.len() {
+            for account in &self.accounts {
+                if transaction.signature_count(input_index) >= multisig.required() as usize {
+                    break;
+                }
+
+                if multisig.addresses().contains(&account.address)
+                    && !transaction.is_signed_by(input_index, &account.address) {
+                    transaction.sign(input_index, &account.key_pair);
+                }
+            }
+        }
+    }
+
+    /// Signs every input of the transaction spending an unspent output of the wallet, the
+    /// ones of a CoinJoin for instance, returning the number of inputs signed. Inputs already
+    /// signed by the wallet are left untouched.
+    pub fn sign_owned_inputs(&self, transaction: &mut PartiallySignedTx) -> usize {
+        let mut signed_inputs = 0;
+        for input_index in 0..transaction.raw_tx().input.len() {
+            let raw_tx_in = &transaction.raw_tx().input[input_index];
+            let output = (raw_tx_in.prev_tx_hash.clone(), raw_tx_in.prev_tx_output_index);
+            if let Some((Owner::Account(account_index), _utxo_reference)) = self.unspent.get(&output) {
+                let account = &self.accounts[*account_index];
+                if !transaction.is_signed_by(input_index, &account.address) {
+                    transaction.sign(input_index, &account.key_pair);
+                    signed_inputs += 1;
                 }
             }
         }
+        signed_inputs
+    }
+
+    /// Builds a transaction claiming an output locked by the hash time lock, or sent to its
+    /// hash, by revealing the secret. The recipient of the lock must be an address of the
+    /// wallet.
+    pub fn claim_hash_time_lock<S>(
+        &self,
+        hash_time_lock: &HashTimeLock,
+        secret: &[u8],
+        to_address: Address,
+        fees: u32,
+        utxo_store: &S,
+    ) -> Result<SignedTx, Error>
+        where S: UtxoStore + transaction::UtxoStore
+    {
+        let branch = HashTimeLockBranch::Claim(secret.to_vec());
+        self.spend_hash_time_lock(hash_time_lock, branch, hash_time_lock.recipient(), to_address, fees, utxo_store)
+    }
 
-        let change = collected_amount - total_cost;
-        let change_tx_out = TxOut::new(change, change_address);
+    /// Builds a transaction taking back an output locked by the hash time lock, or sent to
+    /// its hash, valid from the lock height on. The refund address of the lock must be an
+    /// address of the wallet.
+    pub fn refund_hash_time_lock<S>(
+        &self,
+        hash_time_lock: &HashTimeLock,
+        to_address: Address,
+        fees: u32,
+        utxo_store: &S,
+    ) -> Result<SignedTx, Error>
+        where S: UtxoStore + transaction::UtxoStore
+    {
+        let branch = HashTimeLockBranch::Refund;
+        self.spend_hash_time_lock(hash_time_lock, branch, hash_time_lock.refund_address(), to_address, fees, utxo_store)
+    }
 
-        let payment_tx_out = TxOut::new(amount, to_address);
+    /// Spends the whole output, the fees excepted, with the key of the given address.
+    fn spend_hash_time_lock<S>(
+        &self,
+        hash_time_lock: &HashTimeLock,
+        branch: HashTimeLockBranch,
+        signer: &Address,
+        to_address: Address,
+        fees: u32,
+        utxo_store: &S,
+    ) -> Result<SignedTx, Error>
+        where S: UtxoStore + transaction::UtxoStore
+    {
+        let utxo_reference = utxo_store.find_for_address(&hash_time_lock.address()).iter()
+            .find(|utxo_reference| utxo_reference.amount >= fees)
+            .ok_or(Error::NotEnoughTokens)?;
+        let tx_out = utxo_store.find(&utxo_reference.tx_hash, &utxo_reference.tx_out_index)
+            .ok_or(Error::UtxoNotFound)?;
 
         let raw_tx = RawTx {
-            input: raw_tx_ins,
-            output: vec![
-                change_tx_out,
-                payment_tx_out,
-            ],
+            input: vec![RawTxIn{
+                prev_tx_hash: utxo_reference.tx_hash.clone(),
+                prev_tx_output_index: utxo_reference.tx_out_index,
+            }],
+            output: vec![TxOut::new(utxo_reference.amount - fees, to_address)],
         };
 
-        SignedTx::from_raw_tx(raw_tx, key_pairs)
+        let mut transaction = PartiallySignedTx::new(raw_tx, &[tx_out])?;
+        transaction.set_hash_time_lock_branch(0, branch);
+        if tx_out.lock_script().script_hash().is_some() {
+            transaction.set_redeem_script(0, hash_time_lock.script());
+        }
+        self.sign_input(&mut transaction, 0, signer)?;
+        Ok(transaction.into_signed_tx())
+    }
+
+    /// Signs the given input of the transaction with the key of the given address, which must
+    /// be an address of the wallet, watch-only ones excepted. Panics if the transaction has
+    /// no such input.
+    pub fn sign_input(
+        &self,
+        transaction: &mut PartiallySignedTx,
+        input_index: usize,
+        address: &Address,
+    ) -> Result<(), Error> {
+        match self.owners.get(address) {
+            Some(Owner::Account(account_index)) => {
+                transaction.sign(input_index, &self.accounts[*account_index].key_pair);
+                Ok(())
+            },
+            _ => Err(Error::InvalidAddress),
+        }
+    }
+
+    /// Suggests the fees of a payment to be confirmed within the target number of blocks.
+    ///
+    /// The lower the target, the higher the fee rate chosen among the recent blocks. If the
+    /// pending transactions are more than the recent blocks could confirm in time, the
+    /// payment must also outbid the ones that would not make it.
+    pub fn estimate_fee<H>(&self, target_confirmations: u32, mempool: &Mempool, fee_history: &H) -> u32
+        where H: FeeHistory
+    {
+        let target_confirmations = target_confirmations.max(1) as usize;
+        let recent_block_fees = fee_history.recent_block_fees(FEE_ESTIMATION_BLOCKS);
+
+        let mut block_fee_rates: Vec<u64> = recent_block_fees.iter()
+            .map(BlockFees::fee_rate)
+            .collect();
+        block_fee_rates.sort_unstable_by(|one, other| other.cmp(one));
+        let rank = block_fee_rates.len().saturating_sub(1) * (target_confirmations - 1) / target_confirmations;
+        let history_fee_rate = block_fee_rates.get(rank).cloned().unwrap_or(0);
+
+        // Without recent transactions, nothing tells how many pending ones the next blocks
+        // will confirm, so the whole pool must be outbid.
+        let transaction_count: usize = recent_block_fees.iter()
+            .map(|block_fees| *block_fees.transaction_count())
+            .sum();
+        let transactions_per_block = transaction_count.div_ceil(recent_block_fees.len().max(1));
+        let capacity = target_confirmations * transactions_per_block;
+        let congestion_fee_rate = mempool.select_for_block(capacity + 1)
+            .get(capacity)
+            .map_or(0, |entry| entry.fee_rate() + 1);
+
+        let fee_rate = history_fee_rate.max(congestion_fee_rate);
+        let fees = (fee_rate * ESTIMATED_PAYMENT_SIZE).div_ceil(1000);
+        fees.min(u32::MAX as u64) as u32
     }
 
     pub fn new_address(&mut self) -> Result<Address, Error> {
-        let new_account = Account::new(self.generator.random_keypair()?);
+        let index = self.accounts.len() as u32;
+        let new_account = self.derive_account(index)?;
 
         let address = new_account.address.clone();
-        self.accounts.push(new_account);
+        self.push_account(new_account);
 
         Ok(address)
     }
+
+    fn push_account(&mut self, account: Account) {
+        self.owners.insert(account.address.clone(), Owner::Account(self.accounts.len()));
+        self.accounts.push(account);
+    }
+
+    fn address_of(&self, owner: Owner) -> &Address {
+        match owner {
+            Owner::Account(index) => &self.accounts[index].address,
+            Owner::WatchOnly(index) => &self.watch_only[index],
+        }
+    }
+
+    fn unspent_balance<S>(&self, utxo_store: &S, watch_only: bool) -> u64
+        where S: transaction::UtxoStore
+    {
+        self.unspent.values()
+            .filter(|(owner, _output)| owner.is_watch_only() == watch_only)
+            .filter(|(_owner, output)| utxo_store.find(&output.tx_hash, &output.tx_out_index).is_some())
+            .map(|(_owner, output)| output.amount as u64)
+            .sum()
+    }
+
+    fn derive_account(&self, index: u32) -> Result<Account, Error> {
+        let key_pair = self.master_key.derive_child(index).key_pair(self.signature_algorithm)?;
+        Ok(Account::new(key_pair))
+    }
+}
+
+impl Default for Wallet {
+    fn default() -> Self {
+        Wallet::new()
+    }
+}
+
+/// What a saved wallet needs to derive its keys again.
+#[derive(Serialize, Deserialize)]
+struct SavedWallet {
+    seed: Seed,
+    address_count: u32,
+    watch_only: Vec<Address>,
+    signature_algorithm: SignatureAlgorithm,
+}
+
+/// The index of the account or of the watch-only address an address belongs to.
+#[derive(Clone, Copy)]
+enum Owner {
+    Account(usize),
+    WatchOnly(usize),
+}
+
+impl Owner {
+    fn is_watch_only(&self) -> bool {
+        matches!(self, Owner::WatchOnly(_))
+    }
 }
 
 struct Account {
@@ -107,26 +674,105 @@ impl Account {
     }
 }
 
+/// The unspent outputs of a wallet chosen to fund a transaction.
+pub(crate) struct Selection<'a> {
+    pub inputs: Vec<RawTxIn>,
+    pub spent_outputs: Vec<&'a TxOut>,
+    /// The account owning each output spent.
+    pub account_indexes: Vec<usize>,
+    /// The total amount of the outputs spent.
+    pub amount: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct TxOutReference {
     tx_hash: Hash,
     tx_out_index: u8,
     amount: u32,
 }
 
+impl TxOutReference {
+    pub fn new(tx_hash: Hash, tx_out_index: u8, amount: u32) -> TxOutReference {
+        TxOutReference{
+            tx_hash,
+            tx_out_index,
+            amount,
+        }
+    }
+
+    pub fn tx_hash(&self) -> &Hash {
+        &self.tx_hash
+    }
+
+    pub fn tx_out_index(&self) -> &u8 {
+        &self.tx_out_index
+    }
+
+    pub fn amount(&self) -> &u32 {
+        &self.amount
+    }
+}
+
+/// Whether an output was sent to or spent from an address of the wallet.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HistoryKind {
+    Funded,
+    Spent,
+}
+
+/// An output sent to or spent from an address of the wallet by the block at some height.
+#[derive(Clone)]
+pub struct HistoryEntry {
+    height: u32,
+    kind: HistoryKind,
+    address: Address,
+    output: TxOutReference,
+}
+
+impl HistoryEntry {
+    pub fn height(&self) -> &u32 {
+        &self.height
+    }
+
+    pub fn kind(&self) -> HistoryKind {
+        self.kind
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    pub fn output(&self) -> &TxOutReference {
+        &self.output
+    }
+}
+
 pub trait UtxoStore {
-    fn find_for_address(&self, address: &Address) -> Option<&TxOutReference>;
+    /// Every unspent output sent to the address.
+    fn find_for_address(&self, address: &Address) -> &[TxOutReference];
+}
+
+pub trait FeeHistory {
+    /// The fees of up to `count` blocks of the best chain, the most recent first.
+    fn recent_block_fees(&self, count: usize) -> Vec<BlockFees>;
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chain_params::ChainParams;
+    use bincode;
+    use coin_selection::{BranchAndBound, LargestFirst};
+    use blockchain::{Body, COINBASE_AMOUNT};
+    use crypto::hash;
+    use std::env;
     use std::collections::HashMap;
     use transaction;
     use self::map_key_pair::PairHashMap;
 
     /// A basic UTXO store relying on hash maps.
     struct BasicUtxoStore{
-        utxos_from_address: HashMap<Address, TxOutReference>,
+        utxos_from_address: HashMap<Address, Vec<TxOutReference>>,
         utxos_from_tx_hash: PairHashMap<Hash, u8, TxOut>,
     }
 
@@ -139,19 +785,27 @@ mod tests {
         }
 
         fn push(&mut self, tx_hash: Hash, tx_out: TxOut, tx_out_index: u8){
-            self.utxos_from_address.insert(tx_out.to_address().clone(), TxOutReference{
-                tx_out_index: 0,
+            self.utxos_from_address.entry(tx_out.address()).or_default().push(TxOutReference{
+                tx_out_index,
                 tx_hash: tx_hash.clone(),
                 amount: *tx_out.amount(),
             });
 
             self.utxos_from_tx_hash.insert(tx_hash, tx_out_index, tx_out);
         }
+
+        /// Sends the output with the coinbase of a new block, connected to the wallet.
+        fn fund(&mut self, wallet: &mut Wallet, tx_out: TxOut, height: u32) -> Body {
+            let body = Body::new(tx_out.clone(), vec![]);
+            self.push(body.coinbase_tx().hash().unwrap(), tx_out, 0);
+            wallet.connect_block(&body, height).unwrap();
+            body
+        }
     }
 
     impl UtxoStore for BasicUtxoStore {
-        fn find_for_address(&self, address: &Address) -> Option<&TxOutReference> {
-            self.utxos_from_address.get(address)
+        fn find_for_address(&self, address: &Address) -> &[TxOutReference] {
+            self.utxos_from_address.get(address).map_or(&[], |references| references.as_slice())
         }
     }
 
@@ -159,6 +813,14 @@ mod tests {
         fn find(&self, transaction_hash: &Hash, txo_index: &u8) -> Option<&TxOut> {
             self.utxos_from_tx_hash.get(transaction_hash, txo_index)
         }
+
+        fn coinbase_height(&self, _transaction_hash: &Hash) -> Option<u32> {
+            None
+        }
+
+        fn signature_algorithm(&self) -> SignatureAlgorithm {
+            SignatureAlgorithm::Ed25519
+        }
     }
 
     #[test]
@@ -171,11 +833,244 @@ mod tests {
 
         let mut utxo_store = BasicUtxoStore::new();
 
-        let tx_out = TxOut::new(10, address_a);
-        utxo_store.push(Hash::min(), tx_out, 0);
+        utxo_store.fund(&mut wallet_a, TxOut::new(10, address_a), 0);
 
         let transaction = wallet_a.new_transaction(7, address_b, 2, &utxo_store).unwrap();
-        transaction.verify(&utxo_store).unwrap();
+        transaction.verify(&utxo_store, 0, &ChainParams::main()).unwrap();
+    }
+
+    #[test]
+    fn can_co_sign_multisig_transactions() {
+        let mut wallets: Vec<Wallet> = (0..3).map(|_i| Wallet::new()).collect();
+        let addresses = wallets.iter_mut()
+            .map(|wallet| wallet.new_address().unwrap())
+            .collect();
+        let multisig = MultiSig::new(2, addresses);
+        let to_address = Wallet::new().new_address().unwrap();
+
+        let mut utxo_store = BasicUtxoStore::new();
+        utxo_store.push(Hash::min(), TxOut::new_multisig(10, &multisig), 0);
+
+        let mut transaction = wallets[2]
+            .new_multisig_transaction(&multisig, 7, to_address, 2, &utxo_store)
+            .unwrap();
+        assert_eq!(1, transaction.signature_count(0));
+
+        // Signing twice with the same wallet does not help.
+        wallets[2].co_sign(&mut transaction, &multisig);
+        assert_eq!(1, transaction.signature_count(0));
+
+        wallets[0].co_sign(&mut transaction, &multisig);
+        assert_eq!(2, transaction.signature_count(0));
+        wallets[1].co_sign(&mut transaction, &multisig);
+        assert_eq!(2, transaction.signature_count(0));
+
+        let transaction = transaction.into_signed_tx();
+        assert_eq!(2, transaction.verify(&utxo_store, 0, &ChainParams::main()).unwrap());
+
+        // The change is sent to the hash of the condition, and can be redeemed as well.
+        let change = transaction.output()[0].clone();
+        assert!(change.lock_script().script_hash().is_some());
+        assert!(multisig.address() == change.address());
+        utxo_store.push(transaction.hash().unwrap(), change, 0);
+
+        let to_address = Wallet::new().new_address().unwrap();
+        let mut transaction = wallets[0]
+            .new_multisig_transaction(&multisig, 1, to_address, 0, &utxo_store)
+            .unwrap();
+        wallets[1].co_sign(&mut transaction, &multisig);
+        assert_eq!(0, transaction.into_signed_tx().verify(&utxo_store, 0, &ChainParams::main()).unwrap());
+    }
+
+    /// The fees of a few recent blocks, the most recent first.
+    struct BasicFeeHistory(Vec<BlockFees>);
+
+    impl FeeHistory for BasicFeeHistory {
+        fn recent_block_fees(&self, count: usize) -> Vec<BlockFees> {
+            self.0.iter().take(count).cloned().collect()
+        }
+    }
+
+    #[test]
+    fn estimates_fees_from_recent_blocks_and_mempool() {
+        let mut utxo_store = BasicUtxoStore::new();
+        let mut payments = vec![];
+        for fees in 1..7 {
+            let mut wallet = Wallet::new();
+            let tx_out = TxOut::new(100, wallet.new_address().unwrap());
+            utxo_store.fund(&mut wallet, tx_out, 0);
+            let to_address = Wallet::new().new_address().unwrap();
+            payments.push(wallet.new_transaction(10, to_address, fees * 10, &utxo_store).unwrap());
+        }
+
+        let wallet = Wallet::new();
+        let mut mempool = Mempool::new();
+        let mut fee_history = BasicFeeHistory(vec![]);
+        assert_eq!(0, wallet.estimate_fee(1, &mempool, &fee_history));
+
+        // Every recent block confirmed a single payment.
+        for (fees, payment) in (1..4).zip(&payments) {
+            let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT + fees * 10, Wallet::new().new_address().unwrap());
+            let body = Body::new(coinbase_tx_out, vec![payment.clone()]);
+            fee_history.0.insert(0, BlockFees::new(&body, 1, &ChainParams::main()).unwrap());
+        }
+        assert_eq!(30, wallet.estimate_fee(1, &mempool, &fee_history));
+        assert_eq!(20, wallet.estimate_fee(2, &mempool, &fee_history));
+
+        // A payment must outbid the pending ones that would be confirmed before it.
+        for payment in &payments[3..] {
+            mempool.add(payment.clone(), &utxo_store, 0).unwrap();
+        }
+        assert_eq!(50, wallet.estimate_fee(1, &mempool, &fee_history));
+        assert_eq!(20, wallet.estimate_fee(3, &mempool, &fee_history));
+    }
+
+    #[test]
+    fn restores_funded_addresses_from_the_seed() {
+        let mut wallet = Wallet::new();
+        let mut utxo_store = BasicUtxoStore::new();
+
+        let addresses: Vec<Address> = (0..DEFAULT_GAP_LIMIT + 5)
+            .map(|_index| wallet.new_address().unwrap())
+            .collect();
+        utxo_store.push(Hash::min(), TxOut::new(10, addresses[2].clone()), 0);
+        utxo_store.push(hash(b"other"), TxOut::new(20, addresses[DEFAULT_GAP_LIMIT as usize + 2].clone()), 0);
+
+        let seed: Seed = bincode::deserialize(&bincode::serialize(wallet.seed()).unwrap()).unwrap();
+        let mut restored_wallet = Wallet::from_seed(seed);
+        assert_eq!(2, restored_wallet.scan(&utxo_store, DEFAULT_GAP_LIMIT).unwrap());
+
+        // The next address follows the last funded one.
+        assert!(addresses[DEFAULT_GAP_LIMIT as usize + 3] == restored_wallet.new_address().unwrap());
+
+        let to_address = Wallet::new().new_address().unwrap();
+        restored_wallet.new_transaction(25, to_address, 0, &utxo_store).unwrap();
+    }
+
+    #[test]
+    fn saves_and_loads_encrypted_wallets() {
+        let mut wallet = Wallet::new();
+        let addresses: Vec<Address> = (0..3).map(|_index| wallet.new_address().unwrap()).collect();
+        let watched_address = Wallet::new().new_address().unwrap();
+        wallet.import_watch_only(watched_address.clone());
+        let path = env::temp_dir().join(format!("btclike-wallet-{}.dat", ::std::process::id()));
+
+        wallet.save(&path, "passphrase").unwrap();
+        assert_eq!(Error::InvalidPassphrase, Wallet::load(&path, "other passphrase").err().unwrap());
+
+        let mut loaded_wallet = Wallet::load(&path, "passphrase").unwrap();
+        assert!(wallet.seed() == loaded_wallet.seed());
+        assert!(wallet.new_address().unwrap() == loaded_wallet.new_address().unwrap());
+
+        // The funds of a loaded wallet are found again by scanning the UTXO set.
+        let mut utxo_store = BasicUtxoStore::new();
+        utxo_store.push(Hash::min(), TxOut::new(10, addresses[2].clone()), 0);
+        utxo_store.push(Hash::min(), TxOut::new(5, watched_address), 1);
+        let to_address = Wallet::new().new_address().unwrap();
+        assert!(loaded_wallet.new_transaction(7, to_address.clone(), 2, &utxo_store).is_err());
+        loaded_wallet.scan(&utxo_store, DEFAULT_GAP_LIMIT).unwrap();
+        assert_eq!(5, loaded_wallet.watch_only_balance(&utxo_store));
+        loaded_wallet.new_transaction(7, to_address, 2, &utxo_store).unwrap();
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn tracks_the_history_of_connected_blocks() {
+        let mut wallet = Wallet::new();
+        let mut utxo_store = BasicUtxoStore::new();
+        let address = wallet.new_address().unwrap();
+        utxo_store.fund(&mut wallet, TxOut::new(10, address.clone()), 0);
+        let other_body = utxo_store.fund(&mut wallet, TxOut::new(20, address.clone()), 1);
+        assert_eq!(30, wallet.balance(&utxo_store));
+
+        let to_address = Wallet::new().new_address().unwrap();
+        let transaction = wallet.new_transaction(25, to_address, 1, &utxo_store).unwrap();
+        let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT + 1, Wallet::new().new_address().unwrap());
+        let body = Body::new(coinbase_tx_out, vec![transaction.clone()]);
+        wallet.connect_block(&body, 2).unwrap();
+
+        // Both outputs were spent, the change was received.
+        let kinds: Vec<(u32, HistoryKind)> = wallet.history().iter()
+            .map(|entry| (*entry.height(), entry.kind()))
+            .collect();
+        assert_eq!(vec![
+            (0, HistoryKind::Funded),
+            (1, HistoryKind::Funded),
+            (2, HistoryKind::Spent),
+            (2, HistoryKind::Spent),
+            (2, HistoryKind::Funded),
+        ], kinds);
+        assert!(wallet.history()[4].output().tx_hash() == &transaction.hash().unwrap());
+        utxo_store.push(transaction.hash().unwrap(), transaction.output()[0].clone(), 0);
+        assert_eq!(4, wallet.balance(&utxo_store));
+
+        // Disconnecting the blocks restores the spent outputs.
+        wallet.disconnect_block(2);
+        assert_eq!(2, wallet.history().len());
+        assert_eq!(30, wallet.balance(&utxo_store));
+
+        wallet.disconnect_block(1);
+        assert_eq!(10, wallet.balance(&utxo_store));
+        wallet.connect_block(&other_body, 1).unwrap();
+        assert_eq!(30, wallet.balance(&utxo_store));
+    }
+
+    #[test]
+    fn tracks_watch_only_addresses_without_spending_them() {
+        let mut external_wallet = Wallet::new();
+        let external_address = external_wallet.new_address().unwrap();
+        let mut wallet = Wallet::new();
+        wallet.import_watch_only(external_address.clone());
+        assert!(wallet.is_watch_only(&external_address));
+
+        let mut utxo_store = BasicUtxoStore::new();
+        let funding_body = utxo_store.fund(&mut wallet, TxOut::new(50, external_address.clone()), 0);
+        external_wallet.connect_block(&funding_body, 0).unwrap();
+        assert_eq!(0, wallet.balance(&utxo_store));
+        assert_eq!(50, wallet.watch_only_balance(&utxo_store));
+
+        let to_address = Wallet::new().new_address().unwrap();
+        assert_eq!(
+            Error::NotEnoughTokens,
+            wallet.new_transaction(10, to_address.clone(), 1, &utxo_store).err().unwrap()
+        );
+
+        // The payments of the external key are part of the history.
+        let transaction = external_wallet.new_transaction(10, to_address, 1, &utxo_store).unwrap();
+        let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT + 1, Wallet::new().new_address().unwrap());
+        wallet.connect_block(&Body::new(coinbase_tx_out, vec![transaction]), 1).unwrap();
+        assert_eq!(HistoryKind::Spent, wallet.history()[1].kind());
+        assert!(wallet.history()[1].address() == &external_address);
+
+        wallet.disconnect_block(1);
+        assert_eq!(50, wallet.watch_only_balance(&utxo_store));
+    }
+
+    #[test]
+    fn selects_coins_with_the_given_strategy() {
+        let mut wallet = Wallet::new();
+        let mut utxo_store = BasicUtxoStore::new();
+        for (height, amount) in [10, 30, 5].iter().enumerate() {
+            let address = wallet.new_address().unwrap();
+            utxo_store.fund(&mut wallet, TxOut::new(*amount, address), height as u32);
+        }
+        let to_address = Wallet::new().new_address().unwrap();
+
+        // The oldest outputs are spent first by default.
+        let transaction = wallet.new_transaction(14, to_address.clone(), 1, &utxo_store).unwrap();
+        assert_eq!(2, transaction.input().len());
+        assert_eq!(&25, transaction.output()[0].amount());
+
+        let transaction = wallet.new_transaction_with(14, to_address.clone(), 1, &utxo_store, &LargestFirst).unwrap();
+        assert_eq!(1, transaction.input().len());
+        assert_eq!(&15, transaction.output()[0].amount());
+
+        // An exact match needs no change output.
+        let transaction = wallet.new_transaction_with(14, to_address, 1, &utxo_store, &BranchAndBound).unwrap();
+        assert_eq!(2, transaction.input().len());
+        assert_eq!(1, transaction.output().len());
+        assert_eq!(&14, transaction.output()[0].amount());
     }
 
     #[test]
@@ -190,6 +1085,28 @@ mod tests {
         wallet_a.new_transaction(7, address_b, 2, &utxo_store).err().unwrap();
     }
 
+    #[test]
+    fn cannot_create_transaction_if_the_fees_overflow_the_amount() {
+        let mut wallet_a = Wallet::new();
+        let mut wallet_b = Wallet::new();
+        let address_a = wallet_a.new_address().unwrap();
+        let address_b = wallet_b.new_address().unwrap();
+        let multisig = MultiSig::new(1, vec![address_a.clone()]);
+
+        let mut utxo_store = BasicUtxoStore::new();
+        utxo_store.fund(&mut wallet_a, TxOut::new(COINBASE_AMOUNT, address_a), 1);
+        utxo_store.push(Hash::min(), TxOut::new_multisig(10, &multisig), 0);
+
+        assert_eq!(
+            Error::InvalidTxAmount,
+            wallet_a.new_transaction(u32::MAX, address_b.clone(), 1, &utxo_store).err().unwrap()
+        );
+        assert_eq!(
+            Error::InvalidTxAmount,
+            wallet_a.new_multisig_transaction(&multisig, u32::MAX, address_b, 1, &utxo_store).err().unwrap()
+        );
+    }
+
     mod map_key_pair {
         use std::collections::HashMap;
         use std::hash::{Hash, Hasher};
@@ -208,30 +1125,30 @@ mod tests {
             fn b(&self) -> &B;
         }
 
-        impl<'a, A, B> Borrow<MapKeyPair<A, B> + 'a> for Pair<A, B>
+        impl<'a, A, B> Borrow<dyn MapKeyPair<A, B> + 'a> for Pair<A, B>
             where
                 A: Eq + Hash + 'a,
                 B: Eq + Hash + 'a,
         {
-            fn borrow(&self) -> &(MapKeyPair<A, B> + 'a) {
+            fn borrow(&self) -> &(dyn MapKeyPair<A, B> + 'a) {
                 self
             }
         }
 
-        impl<'a, A: Hash, B: Hash> Hash for (MapKeyPair<A, B> + 'a) {
+        impl<'a, A: Hash, B: Hash> Hash for dyn MapKeyPair<A, B> + 'a {
             fn hash<H: Hasher>(&self, state: &mut H) {
                 self.a().hash(state);
                 self.b().hash(state);
             }
         }
 
-        impl<'a, A: Eq, B: Eq> PartialEq for (MapKeyPair<A, B> + 'a) {
+        impl<'a, A: Eq, B: Eq> PartialEq for dyn MapKeyPair<A, B> + 'a {
             fn eq(&self, other: &Self) -> bool {
                 self.a() == other.a() && self.b() == other.b()
             }
         }
 
-        impl<'a, A: Eq, B: Eq> Eq for (MapKeyPair<A, B> + 'a) {}
+        impl<'a, A: Eq, B: Eq> Eq for dyn MapKeyPair<A, B> + 'a {}
 
         /// A hash map relying on a pair of keys.
         pub struct PairHashMap<A: Eq + Hash, B: Eq + Hash, V> {
@@ -244,7 +1161,7 @@ mod tests {
             }
 
             pub fn get(&self, a: &A, b: &B) -> Option<&V> {
-                self.map.get(&BorrowedPair(a, b) as &MapKeyPair<A, B>)
+                self.map.get(&BorrowedPair(a, b) as &dyn MapKeyPair<A, B>)
             }
 
             pub fn insert(&mut self, a: A, b: B, v: V) {
@@ -0,0 +1,22 @@
+//! Decodes a block along with a set of unspent outputs, verifies the block against them, then
+//! applies it to the set and rolls it back.
+
+#![no_main]
+
+extern crate bincode;
+extern crate btclike_simulation as btclike;
+extern crate libfuzzer_sys;
+
+use btclike::blockchain::Block;
+use btclike::chain_params::ChainParams;
+use btclike::utxo::UtxoSet;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((mut utxo_set, block)) = bincode::deserialize::<(UtxoSet, Block)>(data) {
+        let _ = block.verify(&utxo_set, &ChainParams::main());
+        if let Ok(undo) = utxo_set.apply(block.body(), *block.header().height()) {
+            let _ = utxo_set.rollback(&undo);
+        }
+    }
+});
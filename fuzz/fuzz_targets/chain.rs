@@ -0,0 +1,19 @@
+//! Decodes a chain of blocks and verifies it from its head down to its genesis block.
+
+#![no_main]
+
+extern crate bincode;
+extern crate btclike_simulation as btclike;
+extern crate libfuzzer_sys;
+
+use btclike::blockchain::Chain;
+use btclike::chain_params::ChainParams;
+use btclike::crypto::Hash;
+use btclike::utxo::UtxoSet;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(chain) = bincode::deserialize::<Chain>(data) {
+        let _ = chain.verify(&Hash::min(), &UtxoSet::new(), &ChainParams::main());
+    }
+});
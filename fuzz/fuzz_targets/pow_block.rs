@@ -0,0 +1,53 @@
+//! Expands a genesis chain of any difficulty with a block made of any fields, then keeps
+//! raising the difficulty and incrementing the nonce of the block, the arithmetic of the proof
+//! of work.
+
+#![no_main]
+
+extern crate btclike_simulation as btclike;
+extern crate libfuzzer_sys;
+extern crate pow_blockchain_simulation as pow;
+
+use btclike::blockchain::Body;
+use btclike::crypto::{self, Hasher};
+use btclike::transaction::{Address, TxOut};
+use btclike::u256::U256;
+use libfuzzer_sys::fuzz_target;
+use pow::blockchain::{Block, BlockBody, Difficulty, Hash, Nonce};
+use pow::Chain;
+use std::sync::Arc;
+
+fuzz_target!(|fields: (u32, [u8; 8], u32, [u8; 32], bool, [u8; 32], u32, u8)| {
+    let (node_id, nonce, extra_nonce, threshold, extends_genesis, previous_hash, height, increments) = fields;
+    let mut difficulty = Difficulty::from_threshold(U256::from_be_bytes(&threshold));
+    let genesis_chain = Arc::new(Chain::init_new(difficulty.clone()));
+
+    let coinbase_address = Address::from_hash(crypto::hash(&node_id.to_le_bytes()));
+    let body = Body::new(TxOut::new(genesis_chain.params().block_reward(height), coinbase_address), vec![]);
+    let body = Arc::new(BlockBody::new(body).expect("Could not build the block body"));
+    let previous_hash = if extends_genesis {
+        genesis_chain.head().hash().clone()
+    } else {
+        Hash::from_bytes(previous_hash)
+    };
+    let mut nonce = Nonce::from_bytes(nonce);
+    let block = Block::new(
+        node_id,
+        nonce.clone(),
+        extra_nonce,
+        genesis_chain.head().difficulty(),
+        Hasher::default(),
+        previous_hash,
+        height,
+        &body,
+    );
+    if let Ok(chain) = Chain::expand(&genesis_chain, block) {
+        let _ = chain.validate();
+    }
+
+    for _increment in 0..increments {
+        let _ = difficulty.increase();
+        let _ = difficulty.work();
+        let _ = nonce.increment();
+    }
+});
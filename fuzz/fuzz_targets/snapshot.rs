@@ -0,0 +1,13 @@
+//! Loads a chain from a snapshot, which validates every block and replays the transactions.
+
+#![no_main]
+
+extern crate libfuzzer_sys;
+extern crate pow_blockchain_simulation as pow;
+
+use libfuzzer_sys::fuzz_target;
+use pow::Chain;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Chain::decode_snapshot(data);
+});
@@ -0,0 +1,49 @@
+//! Decodes a signed transaction, along with the output its inputs spend, and verifies it with
+//! both signature schemes.
+
+#![no_main]
+
+extern crate bincode;
+extern crate btclike_simulation as btclike;
+extern crate libfuzzer_sys;
+
+use btclike::chain_params::ChainParams;
+use btclike::crypto::{Hash, SignatureAlgorithm};
+use btclike::transaction::{SignedTx, TxOut, UtxoStore};
+use libfuzzer_sys::fuzz_target;
+
+/// Finds the same output whatever the input, possibly a coinbase one.
+struct AnyOutput {
+    output: TxOut,
+    coinbase_height: Option<u32>,
+    signature_algorithm: SignatureAlgorithm,
+}
+
+impl UtxoStore for AnyOutput {
+    fn find(&self, _transaction_hash: &Hash, _txo_index: &u8) -> Option<&TxOut> {
+        Some(&self.output)
+    }
+
+    fn coinbase_height(&self, _transaction_hash: &Hash) -> Option<u32> {
+        self.coinbase_height
+    }
+
+    fn signature_algorithm(&self) -> SignatureAlgorithm {
+        self.signature_algorithm
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let decoded = bincode::deserialize::<(TxOut, Option<u32>, u32, SignedTx)>(data);
+    if let Ok((output, coinbase_height, height, transaction)) = decoded {
+        let _ = transaction.hash();
+        for signature_algorithm in [SignatureAlgorithm::Ed25519, SignatureAlgorithm::Secp256k1] {
+            let utxo_store = AnyOutput {
+                output: output.clone(),
+                coinbase_height,
+                signature_algorithm,
+            };
+            let _ = transaction.verify(&utxo_store, height, &ChainParams::main());
+        }
+    }
+});
@@ -0,0 +1,137 @@
+//! The time of a network. The latency of the messages, the timeouts, and the sleeps and
+//! intervals of the nodes are all timers of `tokio::time`, driven by the runtimes the clock of
+//! the network builds, sized by its `RuntimeConfig`: the wall clock, or a virtual one for the
+//! tests.
+
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::runtime::{Builder, Runtime};
+
+/// How the runtimes of a network use the threads of the host.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RuntimeConfig {
+    worker_threads: Option<usize>,
+    max_blocking_threads: Option<usize>,
+    shards: Option<usize>,
+}
+
+impl RuntimeConfig {
+    pub fn new() -> RuntimeConfig {
+        RuntimeConfig::default()
+    }
+
+    /// The threads running the tasks of the nodes. Default: one per core
+    pub fn with_worker_threads(mut self, worker_threads: usize) -> Self {
+        self.worker_threads = Some(worker_threads);
+        self
+    }
+
+    /// The threads running the blocking tasks of the nodes, such as the validation of the
+    /// chains, apart from their event loops. Default: 512
+    pub fn with_max_blocking_threads(mut self, max_blocking_threads: usize) -> Self {
+        self.max_blocking_threads = Some(max_blocking_threads);
+        self
+    }
+
+    /// Spreads the nodes by address over the given number of single threaded runtimes, each on
+    /// its own thread, rather than running them all on the runtime of the network: a busy node
+    /// only slows down the nodes of its shard. The runtime of the network keeps the scenario.
+    /// Default: none
+    pub fn with_shards(mut self, shards: usize) -> Self {
+        self.shards = Some(shards);
+        self
+    }
+
+    pub fn shards(&self) -> Option<usize> {
+        self.shards
+    }
+}
+
+/// Builds the runtimes running the nodes of a network, whose timers follow this clock.
+pub trait Clock: Send + Sync {
+    fn runtime(&self, config: &RuntimeConfig) -> Runtime;
+
+    /// The runtime of a shard of the nodes, run on its own thread. None if the clock only drives
+    /// the runtime of the network, which then runs every node.
+    fn shard_runtime(&self, config: &RuntimeConfig) -> Option<Runtime>;
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn runtime(&self, config: &RuntimeConfig) -> Runtime {
+        (**self).runtime(config)
+    }
+
+    fn shard_runtime(&self, config: &RuntimeConfig) -> Option<Runtime> {
+        (**self).shard_runtime(config)
+    }
+}
+
+/// The wall clock: the nodes run in real time, on every core unless told otherwise.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn runtime(&self, config: &RuntimeConfig) -> Runtime {
+        let mut builder = Builder::new_multi_thread();
+        if let Some(worker_threads) = config.worker_threads {
+            builder.worker_threads(worker_threads);
+        }
+        build(builder.enable_all(), config)
+    }
+
+    fn shard_runtime(&self, config: &RuntimeConfig) -> Option<Runtime> {
+        Some(build(Builder::new_current_thread().enable_all(), config))
+    }
+}
+
+/// A virtual clock, which jumps to the next timer as soon as every node waits: minutes of a
+/// scenario run in milliseconds, and since the nodes share a single thread, the timers always
+/// fire in the same order. The nodes busy computing hold the time back, so the ones that never
+/// wait, mining without delay for instance, stop it. The worker threads and the shards of the
+/// runtime configuration are ignored.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MockClock;
+
+impl Clock for MockClock {
+    fn runtime(&self, config: &RuntimeConfig) -> Runtime {
+        build(Builder::new_current_thread().enable_time().start_paused(true), config)
+    }
+
+    fn shard_runtime(&self, _config: &RuntimeConfig) -> Option<Runtime> {
+        None
+    }
+}
+
+fn build(builder: &mut Builder, config: &RuntimeConfig) -> Runtime {
+    if let Some(max_blocking_threads) = config.max_blocking_threads {
+        builder.max_blocking_threads(max_blocking_threads);
+    }
+    builder.build().expect("Could not start the runtime")
+}
+
+/// The current time of the clock of the network running the caller, the wall clock outside of
+/// a network. The nodes measure durations with it rather than with `Instant::now`, which would
+/// not follow a virtual clock.
+pub fn now() -> Instant {
+    tokio::time::Instant::now().into_std()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::time;
+
+    #[test]
+    fn skips_the_waits_with_a_virtual_clock() {
+        let real_start = Instant::now();
+        let elapsed = MockClock.runtime(&RuntimeConfig::new()).block_on(async {
+            let start = now();
+            time::sleep(Duration::from_secs(3600)).await;
+            now() - start
+        });
+
+        assert!(elapsed >= Duration::from_secs(3600));
+        assert!(real_start.elapsed() < Duration::from_secs(60));
+    }
+}
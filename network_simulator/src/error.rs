@@ -0,0 +1,59 @@
+use futures::channel::mpsc::{SendError, TrySendError};
+use std::error;
+use std::fmt;
+
+/// The failures of the simulated network.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The remote end of a connection, or the transport of a remote node, is gone.
+    Disconnected,
+    /// A connection is full, its receiver does not keep up with its sender.
+    Congested,
+    /// A transport was acknowledged a connection it did not initiate.
+    UnknownConnection(u32),
+    /// No response to the request with the given identifier was received in time.
+    Timeout(u64),
+    /// A scenario file could not be read.
+    InvalidScenario(String),
+    /// A trace file could not be written or read.
+    InvalidTrace(String),
+    /// An address book file could not be written or read.
+    InvalidAddressBook(String),
+    /// A latency matrix file, or the mapping of the nodes to its regions, could not be read.
+    InvalidLatencyMatrix(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Disconnected => write!(f, "Disconnected"),
+            Error::Congested => write!(f, "Congested"),
+            Error::UnknownConnection(address_id) => {
+                write!(f, "Unknown connection to acknowledge from {}", address_id)
+            }
+            Error::Timeout(request_id) => write!(f, "Request {} timed out", request_id),
+            Error::InvalidScenario(ref reason) => write!(f, "Invalid scenario: {}", reason),
+            Error::InvalidTrace(ref reason) => write!(f, "Invalid trace: {}", reason),
+            Error::InvalidAddressBook(ref reason) => write!(f, "Invalid address book: {}", reason),
+            Error::InvalidLatencyMatrix(ref reason) => write!(f, "Invalid latency matrix: {}", reason),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<SendError> for Error {
+    fn from(err: SendError) -> Self {
+        if err.is_full() {
+            Error::Congested
+        } else {
+            Error::Disconnected
+        }
+    }
+}
+
+impl<M> From<TrySendError<M>> for Error {
+    fn from(err: TrySendError<M>) -> Self {
+        Error::from(err.into_send_error())
+    }
+}
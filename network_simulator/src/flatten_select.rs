@@ -1,49 +1,48 @@
-use futures::{self, Async, Poll};
-use futures::{Sink, Stream};
+use futures::stream::{Fuse, SelectAll};
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
 /// A combinator used to flatten a stream-of-streams into one long stream of
 /// elements.
-/// This differs from tokio's flatten implementation in that it polls the
-/// streams in a round-robin semi-concurrent fashion whereas tokio's implementation
-/// will poll the same stream as long as it is ready. The only way the FlattenSelect
-/// will poll twice the same stream in a row is if it is the only one that is ready
-/// at that time.
+/// This differs from the futures' flatten implementation in that it polls the
+/// streams concurrently whereas the futures' implementation exhausts a stream
+/// before polling the next one.
 ///
-/// This implementation has room for improvement, especially performance-wise.
+/// The children are held in a `SelectAll`: each of them registers its own waker,
+/// so only the children that were woken are polled again, and a child yielding an
+/// item goes back at the end of the queue, so that a busy child does not starve
+/// the others.
 #[derive(Debug)]
 #[must_use = "streams do nothing unless polled"]
 pub struct FlattenSelect<S>
 where
     S: Stream,
 {
-    stream: S,
-    still_has_children: bool,
-    children: Vec<S::Item>,
-    last_polled_index: usize,
+    stream: Fuse<S>,
+    children: SelectAll<S::Item>,
 }
 
 pub fn new<S>(s: S) -> FlattenSelect<S>
 where
     S: Stream,
-    S::Item: Stream,
-    <S::Item as Stream>::Error: From<S::Error>,
+    S::Item: Stream + Unpin,
 {
     FlattenSelect {
-        stream: s,
-        still_has_children: true,
-        children: vec![],
-        last_polled_index: 0,
+        stream: s.fuse(),
+        children: SelectAll::new(),
     }
 }
 
-// Directly copied from tokio's flatten implementation.
-// Required in cases like chaining stream operators
-#[allow(dead_code)]
-impl<S: Stream> FlattenSelect<S> {
+impl<S> FlattenSelect<S>
+where
+    S: Stream,
+    S::Item: Stream + Unpin,
+{
     /// Acquires a reference to the underlying stream that this combinator is
     /// pulling from.
     pub fn get_ref(&self) -> &S {
-        &self.stream
+        self.stream.get_ref()
     }
 
     /// Acquires a mutable reference to the underlying stream that this
@@ -52,112 +51,104 @@ impl<S: Stream> FlattenSelect<S> {
     /// Note that care must be taken to avoid tampering with the state of the
     /// stream which may otherwise confuse this combinator.
     pub fn get_mut(&mut self) -> &mut S {
-        &mut self.stream
+        self.stream.get_mut()
+    }
+
+    /// The number of children still yielding elements.
+    pub fn children(&self) -> usize {
+        self.children.len()
     }
 
     /// Consumes this combinator, returning the underlying stream.
     ///
-    /// Note that this may discard intermediate state of this combinator, so
-    /// care should be taken to avoid losing resources when this is called.
+    /// Note that this discards the children not exhausted yet.
     pub fn into_inner(self) -> S {
-        self.stream
+        self.stream.into_inner()
     }
 }
 
-// Directly copied from tokio's flatten implementation.
-// Forwarding impl of Sink from the underlying stream
-impl<S> Sink for FlattenSelect<S>
+impl<S> Stream for FlattenSelect<S>
 where
-    S: Sink + Stream,
+    S: Stream + Unpin,
+    S::Item: Stream + Unpin,
 {
-    type SinkItem = S::SinkItem;
-    type SinkError = S::SinkError;
+    type Item = <S::Item as Stream>::Item;
 
-    fn start_send(&mut self, item: S::SinkItem) -> futures::StartSend<S::SinkItem, S::SinkError> {
-        self.stream.start_send(item)
-    }
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        // Take every child available, the stream is only woken again when a new one comes.
+        while let Poll::Ready(Some(child)) = self.stream.poll_next_unpin(cx) {
+            self.children.push(child);
+        }
 
-    fn poll_complete(&mut self) -> Poll<(), S::SinkError> {
-        self.stream.poll_complete()
+        match self.children.poll_next_unpin(cx) {
+            Poll::Ready(Some(item)) => Poll::Ready(Some(item)),
+            // No child left, and none to come.
+            Poll::Ready(None) if self.stream.is_done() => Poll::Ready(None),
+            _other => Poll::Pending,
+        }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::mpsc;
+    use futures::executor::block_on;
+    use futures::stream;
+    use futures::task::{self, ArcWake};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn ends_when_every_child_ends() {
+        let children = stream::iter(vec![stream::iter(vec![1, 2]), stream::iter(vec![]), stream::iter(vec![3])]);
 
-    fn close(&mut self) -> Poll<(), S::SinkError> {
-        self.stream.close()
+        let mut items = block_on(new(children).collect::<Vec<u32>>());
+        items.sort();
+
+        assert_eq!(vec![1, 2, 3], items);
     }
-}
 
-impl<S> Stream for FlattenSelect<S>
-where
-    S: Stream,
-    S::Item: Stream,
-    <S::Item as Stream>::Error: From<S::Error>,
-{
-    type Item = <S::Item as Stream>::Item;
-    type Error = <S::Item as Stream>::Error;
-
-    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        if self.still_has_children {
-            match self.stream.poll() {
-                Ok(Async::Ready(Some(e))) => {
-                    self.children.push(e);
-                }
-                Ok(Async::Ready(None)) => {
-                    self.still_has_children = false;
-                }
-                Err(err) => {
-                    return Err(From::from(err));
-                }
-                _other => {}
-            }
+    #[test]
+    fn polls_the_children_in_turn() {
+        let children = stream::iter(vec![stream::repeat(1), stream::repeat(2), stream::repeat(3)]);
+
+        let items = block_on(new(children).take(30).collect::<Vec<u32>>());
+
+        for child in 1..=3 {
+            assert_eq!(10, items.iter().filter(|&&item| item == child).count());
         }
+    }
+
+    struct Flag(AtomicBool);
 
-        let children_len = self.children.len();
-
-        if !self.still_has_children && children_len == 0 {
-            return Ok(Async::Ready(None));
-        } else if children_len > 0 {
-            let range_start = self.last_polled_index + 1;
-            let range_end = range_start + children_len - 1;
-
-            let mut to_remove = vec![];
-            for index in range_start..range_end {
-                let index = index % children_len;
-                self.last_polled_index = index;
-
-                let mut child = &mut self.children[index];
-
-                match child.poll() {
-                    Ok(Async::Ready(None)) => {
-                        to_remove.push(index);
-                    }
-                    Ok(Async::Ready(Some(item))) => {
-                        self.last_polled_index = index;
-                        return Ok(Async::Ready(Some(item)));
-                    }
-                    Err(err) => {
-                        return Err(err);
-                    }
-                    _other => {}
-                }
-            }
-
-            // Remove the items from the highest index to the lowest. This avoids re-adjusting the
-            // indexes of the item to remove at every iteration. Leads to O(n*log n) in the worst case
-            // instead of O(n^2)
-            to_remove.sort();
-            let _: () = to_remove
-                .iter()
-                .rev()
-                .map(|index_to_remove| {
-                    if self.last_polled_index > *index_to_remove {
-                        self.last_polled_index -= 1;
-                    }
-
-                    self.children.remove(*index_to_remove);
-                })
-                .collect();
+    impl ArcWake for Flag {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.store(true, Ordering::SeqCst);
         }
+    }
 
-        Ok(Async::NotReady) // No child was ready, consider this stream "not ready".
+    #[test]
+    fn is_woken_by_new_children_and_their_items() {
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = task::waker(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        let (children_sender, children_receiver) = mpsc::unbounded();
+        let mut flatten_select = new(children_receiver);
+
+        assert_eq!(Poll::Pending, flatten_select.poll_next_unpin(&mut cx));
+        let (child_sender, child_receiver) = mpsc::unbounded();
+        children_sender.unbounded_send(child_receiver).unwrap();
+        assert!(flag.0.swap(false, Ordering::SeqCst));
+
+        assert_eq!(Poll::Pending, flatten_select.poll_next_unpin(&mut cx));
+        assert_eq!(1, flatten_select.children());
+        child_sender.unbounded_send(42).unwrap();
+        assert!(flag.0.swap(false, Ordering::SeqCst));
+        assert_eq!(Poll::Ready(Some(42)), flatten_select.poll_next_unpin(&mut cx));
+
+        drop(child_sender);
+        drop(children_sender);
+        assert_eq!(Poll::Ready(None), flatten_select.poll_next_unpin(&mut cx));
     }
 }
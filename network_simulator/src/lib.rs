@@ -2,8 +2,12 @@ extern crate futures;
 #[macro_use]
 extern crate log;
 extern crate rand;
+extern crate serde;
 extern crate tokio;
-extern crate tokio_timer;
+extern crate toml;
 
+pub mod clock;
+pub mod error;
 pub mod flatten_select;
 pub mod network;
+pub mod simulation;
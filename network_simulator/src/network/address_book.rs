@@ -0,0 +1,87 @@
+//! The peers the nodes of a network know from a previous run.
+//!
+//! A node that restarts reconnects to the peers it knew rather than asking the seeds for new
+//! ones. The address book of a network, written once it has run, gives the nodes of the next
+//! one the peers they initiated connections to, by address:
+//!
+//! ```toml
+//! peers = [[3, 7], [0, 5], []]
+//! ```
+//!
+//! Some of these addresses may be stale by then: the nodes may be gone, or now behind a NAT.
+//! They are dropped when the network is built, and the nodes draw peers from their topology
+//! instead.
+
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AddressBook {
+    /// The peers of each node, by address.
+    peers: Vec<Vec<u32>>,
+}
+
+impl AddressBook {
+    /// An empty address book, where the nodes know no peer.
+    pub fn new() -> AddressBook {
+        AddressBook::default()
+    }
+
+    /// The address book where each node, by address, knows the given peers.
+    pub fn from_peers(peers: Vec<Vec<u32>>) -> AddressBook {
+        AddressBook { peers }
+    }
+
+    /// The peers the node with the given address knows, none if it is not in the book.
+    pub fn peers_of(&self, address_id: u32) -> &[u32] {
+        self.peers.get(address_id as usize).map_or(&[], Vec::as_slice)
+    }
+
+    /// The number of nodes in the book.
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    pub fn from_toml(toml: &str) -> Result<AddressBook, Error> {
+        toml::from_str(toml).map_err(|err| Error::InvalidAddressBook(err.to_string()))
+    }
+
+    pub fn to_toml(&self) -> Result<String, Error> {
+        toml::to_string(self).map_err(|err| Error::InvalidAddressBook(err.to_string()))
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<AddressBook, Error> {
+        let toml = fs::read_to_string(path).map_err(|err| Error::InvalidAddressBook(err.to_string()))?;
+        AddressBook::from_toml(&toml)
+    }
+
+    /// Writes the address book to a file, overwriting it.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        fs::write(path, self.to_toml()?).map_err(|err| Error::InvalidAddressBook(err.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_address_book_it_writes() {
+        let address_book = AddressBook::from_peers(vec![vec![3, 7], vec![], vec![0]]);
+        let toml = address_book.to_toml().unwrap();
+        assert_eq!(address_book, AddressBook::from_toml(&toml).unwrap());
+        assert_eq!(&[3, 7], address_book.peers_of(0));
+        // A node missing from the book knows no peer.
+        assert!(address_book.peers_of(3).is_empty());
+
+        assert!(AddressBook::from_toml("peers = [[-1]]").is_err());
+        assert!(AddressBook::from_toml("peer = [[1]]").is_err());
+    }
+}
@@ -0,0 +1,200 @@
+//! Measures the bytes the messages would take on the wire, before and after compression, for
+//! each connection of a network. The channels of the transport carry the messages as they
+//! are, so a `WireEncoder` gives the bytes a real transport would serialize.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// How the messages are serialized to be sent.
+pub trait WireEncoder<M>: Send + Sync {
+    /// The bytes of the message, none for the messages left out of the measure.
+    fn encode(&self, message: &M) -> Option<Vec<u8>>;
+}
+
+/// How the serialized messages are compressed before they are sent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    /// Snappy, in its raw format, as it favours speed over ratio like most transports do.
+    #[cfg(feature = "compression")]
+    Snappy,
+}
+
+impl Compression {
+    /// The size of the given bytes once compressed.
+    pub fn compressed_len(self, bytes: &[u8]) -> usize {
+        match self {
+            Compression::None => bytes.len(),
+            #[cfg(feature = "compression")]
+            Compression::Snappy => snap::raw::Encoder::new()
+                .compress_vec(bytes)
+                .map(|compressed| compressed.len())
+                .unwrap_or(bytes.len()),
+        }
+    }
+}
+
+/// The messages sent on a connection and their bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConnectionBytes {
+    pub messages: u64,
+    /// The bytes once serialized.
+    pub raw_bytes: u64,
+    /// The bytes once serialized then compressed, what the connection actually carries.
+    pub compressed_bytes: u64,
+}
+
+impl ConnectionBytes {
+    /// The share of the raw bytes saved by the compression, in percent. None if no byte was
+    /// sent.
+    pub fn savings(&self) -> Option<f64> {
+        if self.raw_bytes == 0 {
+            None
+        } else {
+            Some(100.0 * (1.0 - self.compressed_bytes as f64 / self.raw_bytes as f64))
+        }
+    }
+
+    fn add(&mut self, other: &ConnectionBytes) {
+        self.messages += other.messages;
+        self.raw_bytes += other.raw_bytes;
+        self.compressed_bytes += other.compressed_bytes;
+    }
+}
+
+/// Counts the bytes sent on every connection of a network, by sender then receiver, shared
+/// by every connection like the send metrics. A message is counted when it is sent, even if
+/// it is dropped later on by a congested connection.
+pub struct BandwidthMeter<M> {
+    encoder: Arc<dyn WireEncoder<M>>,
+    compression: Compression,
+    connections: Arc<Mutex<BTreeMap<(u32, u32), ConnectionBytes>>>,
+}
+
+impl<M> Clone for BandwidthMeter<M> {
+    fn clone(&self) -> Self {
+        BandwidthMeter {
+            encoder: self.encoder.clone(),
+            compression: self.compression,
+            connections: self.connections.clone(),
+        }
+    }
+}
+
+impl<M> fmt::Debug for BandwidthMeter<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BandwidthMeter")
+            .field("compression", &self.compression)
+            .field("total", &self.total())
+            .finish()
+    }
+}
+
+impl<M> BandwidthMeter<M> {
+    /// Counts the messages as serialized by the given encoder, without compression.
+    pub fn new<E: WireEncoder<M> + 'static>(encoder: E) -> BandwidthMeter<M> {
+        BandwidthMeter {
+            encoder: Arc::new(encoder),
+            compression: Compression::None,
+            connections: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// How the serialized messages are compressed. Default: `Compression::None`
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// The bytes sent by the given node to the given peer so far.
+    pub fn connection(&self, sender_id: u32, receiver_id: u32) -> ConnectionBytes {
+        self.state().get(&(sender_id, receiver_id)).copied().unwrap_or_default()
+    }
+
+    /// The bytes sent so far on every connection a message was sent on, by sender then
+    /// receiver.
+    pub fn connections(&self) -> Vec<((u32, u32), ConnectionBytes)> {
+        self.state().iter().map(|(connection, bytes)| (*connection, *bytes)).collect()
+    }
+
+    /// The bytes sent so far on every connection.
+    pub fn total(&self) -> ConnectionBytes {
+        let mut total = ConnectionBytes::default();
+        for bytes in self.state().values() {
+            total.add(bytes);
+        }
+        total
+    }
+
+    pub(crate) fn measure(&self, sender_id: u32, receiver_id: u32, message: &M) {
+        let raw = match self.encoder.encode(message) {
+            Some(raw) => raw,
+            None => return,
+        };
+        let bytes = ConnectionBytes {
+            messages: 1,
+            raw_bytes: raw.len() as u64,
+            compressed_bytes: self.compression.compressed_len(&raw) as u64,
+        };
+        self.state().entry((sender_id, receiver_id)).or_default().add(&bytes);
+    }
+
+    fn state(&self) -> MutexGuard<'_, BTreeMap<(u32, u32), ConnectionBytes>> {
+        self.connections.lock().expect("Poisoned bandwidth meter")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Repeats the message as many times as its value, leaving the zeros out.
+    struct RepeatingEncoder;
+
+    impl WireEncoder<u8> for RepeatingEncoder {
+        fn encode(&self, message: &u8) -> Option<Vec<u8>> {
+            if *message == 0 {
+                None
+            } else {
+                Some(vec![*message; *message as usize])
+            }
+        }
+    }
+
+    #[test]
+    fn counts_the_bytes_of_each_connection() {
+        let meter = BandwidthMeter::new(RepeatingEncoder);
+        meter.measure(0, 1, &3);
+        meter.measure(0, 1, &4);
+        meter.measure(1, 0, &5);
+        meter.measure(1, 2, &0);
+
+        let expected = ConnectionBytes {
+            messages: 2,
+            raw_bytes: 7,
+            compressed_bytes: 7,
+        };
+        assert_eq!(expected, meter.connection(0, 1));
+        assert_eq!(ConnectionBytes::default(), meter.connection(1, 2));
+        assert_eq!(vec![(0, 1), (1, 0)], meter.connections().iter().map(|(c, _)| *c).collect::<Vec<_>>());
+        assert_eq!(12, meter.total().raw_bytes);
+        assert_eq!(Some(0.0), meter.total().savings());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compresses_the_repeated_bytes() {
+        let meter = BandwidthMeter::new(RepeatingEncoder).with_compression(Compression::Snappy);
+        meter.measure(0, 1, &200);
+
+        let bytes = meter.connection(0, 1);
+        assert_eq!(200, bytes.raw_bytes);
+        assert!(bytes.compressed_bytes < 20, "{:?}", bytes);
+        assert!(bytes.savings().unwrap() > 90.0);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::network::latency_matrix::LatencyMatrix;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// The conditions of the simulated network, shared by every connection of a network and
+/// changed while it runs, by a scenario for instance.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkConditions {
+    inner: Arc<RwLock<ConditionsState>>,
+}
+
+#[derive(Debug, Default)]
+struct ConditionsState {
+    /// The group of each node of a partitioned network, none if the network is whole.
+    groups: Option<HashMap<u32, usize>>,
+    /// The delay before a message sent is received.
+    latency: Duration,
+    /// The latencies between the regions of the nodes, added to the latency, if any.
+    latency_matrix: Option<Arc<LatencyMatrix>>,
+}
+
+impl NetworkConditions {
+    pub fn new() -> NetworkConditions {
+        NetworkConditions::default()
+    }
+
+    /// Splits the network: the nodes of different groups cannot reach each other anymore. The
+    /// nodes of no group form one more group.
+    pub fn partition(&self, groups: &[Vec<u32>]) {
+        let groups = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(group, node_ids)| node_ids.iter().map(move |node_id| (*node_id, group)))
+            .collect();
+
+        self.inner.write().expect("Poisoned network conditions").groups = Some(groups);
+    }
+
+    /// Ends the partition, if any.
+    pub fn heal(&self) {
+        self.inner.write().expect("Poisoned network conditions").groups = None;
+    }
+
+    pub fn set_latency(&self, latency: Duration) {
+        self.inner.write().expect("Poisoned network conditions").latency = latency;
+    }
+
+    pub fn latency(&self) -> Duration {
+        self.inner.read().expect("Poisoned network conditions").latency
+    }
+
+    /// Spreads the nodes over the regions of the matrix: their messages take the latency
+    /// between their regions, on top of the latency of the network.
+    pub fn set_latency_matrix(&self, latency_matrix: LatencyMatrix) {
+        self.inner.write().expect("Poisoned network conditions").latency_matrix = Some(Arc::new(latency_matrix));
+    }
+
+    /// The delay before a message of the node is received by the given peer.
+    pub fn latency_between(&self, node_id: u32, peer_id: u32) -> Duration {
+        let state = self.inner.read().expect("Poisoned network conditions");
+        match state.latency_matrix {
+            Some(ref latency_matrix) => state.latency + latency_matrix.latency(node_id, peer_id),
+            None => state.latency,
+        }
+    }
+
+    /// Whether the messages of a node reach the given peer.
+    pub fn can_reach(&self, node_id: u32, peer_id: u32) -> bool {
+        match self.inner.read().expect("Poisoned network conditions").groups {
+            Some(ref groups) => groups.get(&node_id) == groups.get(&peer_id),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partitions_the_network_in_groups() {
+        let conditions = NetworkConditions::new();
+        assert!(conditions.can_reach(0, 2));
+
+        conditions.partition(&[vec![0, 1], vec![2, 3]]);
+        assert!(conditions.can_reach(0, 1));
+        assert!(!conditions.can_reach(0, 2));
+        assert!(!conditions.can_reach(3, 4));
+        // The nodes of no group are a group of their own.
+        assert!(conditions.can_reach(4, 5));
+
+        conditions.heal();
+        assert!(conditions.can_reach(0, 2));
+    }
+
+    #[test]
+    fn adds_the_latency_between_the_regions() {
+        let conditions = NetworkConditions::new();
+        conditions.set_latency(Duration::from_millis(10));
+        assert_eq!(Duration::from_millis(10), conditions.latency_between(0, 1));
+
+        let latency_matrix = LatencyMatrix::from_csv(",us-east,eu-west\nus-east,10,80\neu-west,80,8\n").unwrap();
+        conditions.set_latency_matrix(latency_matrix);
+        assert_eq!(Duration::from_millis(50), conditions.latency_between(0, 1));
+        assert_eq!(Duration::from_millis(14), conditions.latency_between(1, 3));
+        assert_eq!(Duration::from_millis(10), conditions.latency());
+    }
+}
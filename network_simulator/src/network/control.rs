@@ -0,0 +1,175 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::Waker;
+
+/// Pauses a running network to look at its nodes one message at a time, shared by every
+/// connection and node of a network like its conditions.
+///
+/// While the network is paused, the nodes do not handle any event, and the messages sent are
+/// held back instead of being received. The messages sent before the pause are held once
+/// their latency passes. `step` then delivers the held messages one at a time, in the order
+/// they were sent, and lets their receiver handle them, until `resume` delivers the remaining
+/// ones and lets every node run again. The timers of the nodes keep running: the events they
+/// cause are handled once the node runs again.
+#[derive(Clone, Default)]
+pub struct Controller {
+    inner: Arc<Mutex<ControllerState>>,
+}
+
+#[derive(Default)]
+struct ControllerState {
+    paused: bool,
+    held: VecDeque<HeldMessage>,
+    /// The nodes allowed to run once despite the pause, to handle a message stepped to them.
+    steps: HashSet<u32>,
+    /// The tasks of the paused nodes, woken up when they may run again.
+    wakers: HashMap<u32, Waker>,
+}
+
+struct HeldMessage {
+    delivery: Delivery,
+    deliver: Box<dyn FnOnce() + Send>,
+}
+
+/// A message delivered by `Controller::step`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Delivery {
+    pub sender_id: u32,
+    pub receiver_id: u32,
+}
+
+impl Controller {
+    pub fn new() -> Controller {
+        Controller::default()
+    }
+
+    /// Stops the nodes once they finish handling their current event.
+    pub fn pause(&self) {
+        self.state().paused = true;
+    }
+
+    /// Delivers the held messages, then lets every node run again.
+    pub fn resume(&self) {
+        let (held, wakers) = {
+            let mut state = self.state();
+            state.paused = false;
+            state.steps.clear();
+            (state.held.drain(..).collect::<Vec<_>>(), state.wakers.drain().collect::<Vec<_>>())
+        };
+
+        for message in held {
+            (message.deliver)();
+        }
+        for (_node_id, waker) in wakers {
+            waker.wake();
+        }
+    }
+
+    /// Delivers the oldest held message and lets its receiver handle it, if any.
+    pub fn step(&self) -> Option<Delivery> {
+        let (message, waker) = {
+            let mut state = self.state();
+            let message = state.held.pop_front()?;
+            let receiver_id = message.delivery.receiver_id;
+            state.steps.insert(receiver_id);
+            (message, state.wakers.remove(&receiver_id))
+        };
+
+        (message.deliver)();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        Some(message.delivery)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state().paused
+    }
+
+    /// The messages waiting for a step or for the network to resume.
+    pub fn held_messages(&self) -> usize {
+        self.state().held.len()
+    }
+
+    /// Holds the message back while the network is paused, delivers it at once otherwise.
+    pub(crate) fn deliver<D>(&self, sender_id: u32, receiver_id: u32, deliver: D)
+    where
+        D: FnOnce() + Send + 'static,
+    {
+        {
+            let mut state = self.state();
+            if state.paused {
+                let delivery = Delivery { sender_id, receiver_id };
+                state.held.push_back(HeldMessage {
+                    delivery,
+                    deliver: Box::new(deliver),
+                });
+                return;
+            }
+        }
+        deliver();
+    }
+
+    /// Whether the given node may handle its events. If not, its task is woken up once it may.
+    pub(crate) fn may_run(&self, node_id: u32, waker: &Waker) -> bool {
+        let mut state = self.state();
+        if !state.paused || state.steps.remove(&node_id) {
+            return true;
+        }
+        state.wakers.insert(node_id, waker.clone());
+        false
+    }
+
+    fn state(&self) -> MutexGuard<'_, ControllerState> {
+        self.inner.lock().expect("Poisoned controller")
+    }
+}
+
+impl fmt::Debug for Controller {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = self.state();
+        f.debug_struct("Controller")
+            .field("paused", &state.paused)
+            .field("held_messages", &state.held.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::noop_waker;
+
+    #[test]
+    fn steps_the_held_messages_in_order() {
+        let controller = Controller::new();
+        let delivered = Arc::new(Mutex::new(vec![]));
+        let deliver = |message: u32| {
+            let delivered = delivered.clone();
+            move || delivered.lock().unwrap().push(message)
+        };
+        let waker = noop_waker();
+
+        controller.deliver(0, 1, deliver(0));
+        controller.pause();
+        controller.deliver(1, 2, deliver(1));
+        controller.deliver(2, 3, deliver(2));
+        controller.deliver(3, 1, deliver(3));
+        assert_eq!(vec![0], *delivered.lock().unwrap());
+        assert_eq!(3, controller.held_messages());
+        assert!(!controller.may_run(2, &waker));
+
+        assert_eq!(Some(Delivery { sender_id: 1, receiver_id: 2 }), controller.step());
+        assert_eq!(vec![0, 1], *delivered.lock().unwrap());
+        // The receiver handles the message, then waits again.
+        assert!(controller.may_run(2, &waker));
+        assert!(!controller.may_run(2, &waker));
+        assert!(!controller.may_run(3, &waker));
+
+        controller.resume();
+        assert_eq!(vec![0, 1, 2, 3], *delivered.lock().unwrap());
+        assert_eq!(None, controller.step());
+        assert!(controller.may_run(3, &waker));
+    }
+}
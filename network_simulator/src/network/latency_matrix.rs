@@ -0,0 +1,171 @@
+//! The latencies between the regions the nodes are spread over, so that the delays of the
+//! messages follow a geography rather than a single latency. The matrix is read from a CSV
+//! file of the round trip times between the regions, in milliseconds, as measured between
+//! cloud regions for instance:
+//!
+//! ```csv
+//! ,us-east,eu-west,ap-south
+//! us-east,10,80,200
+//! eu-west,80,8,120
+//! ap-south,200,120,12
+//! ```
+//!
+//! A message takes half the round trip time between the regions of its sender and receiver.
+//! The nodes are assigned to the regions by address, in turn, unless a mapping of the nodes
+//! to their regions is given, also in CSV, one node per line:
+//!
+//! ```csv
+//! 0,eu-west
+//! 1,ap-south
+//! ```
+
+use crate::error::Error;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LatencyMatrix {
+    regions: Vec<String>,
+    /// The one way latencies between the regions, by index: half the round trip times.
+    latencies: Vec<Vec<Duration>>,
+    /// The region of each node of the mapping, by address.
+    node_regions: HashMap<u32, usize>,
+}
+
+impl LatencyMatrix {
+    pub fn from_csv(csv: &str) -> Result<LatencyMatrix, Error> {
+        let mut lines = csv_lines(csv);
+        let header = lines.next().ok_or_else(|| invalid("Empty matrix".to_owned()))?;
+        let regions: Vec<String> = header.iter().skip(1).map(|region| region.to_string()).collect();
+        if regions.is_empty() || !header[0].is_empty() {
+            return Err(invalid("The first line must name the regions, after an empty cell".to_owned()));
+        }
+
+        let mut latencies = vec![];
+        for (index, cells) in lines.enumerate() {
+            let region = regions.get(index).ok_or_else(|| invalid(format!("Unexpected row: {}", cells[0])))?;
+            if cells[0] != region || cells.len() != regions.len() + 1 {
+                return Err(invalid(format!("Expected the row of {} with {} cells", region, regions.len())));
+            }
+            let row = cells[1..]
+                .iter()
+                .map(|cell| one_way_latency(cell).ok_or_else(|| invalid(format!("Invalid round trip time: {}", cell))))
+                .collect::<Result<Vec<Duration>, Error>>()?;
+            latencies.push(row);
+        }
+        if latencies.len() != regions.len() {
+            return Err(invalid(format!("Expected {} rows, found {}", regions.len(), latencies.len())));
+        }
+
+        Ok(LatencyMatrix {
+            regions,
+            latencies,
+            node_regions: HashMap::new(),
+        })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<LatencyMatrix, Error> {
+        let csv = fs::read_to_string(path).map_err(|err| invalid(err.to_string()))?;
+        LatencyMatrix::from_csv(&csv)
+    }
+
+    /// Assigns the nodes of the CSV mapping to their regions, the others staying assigned in
+    /// turn.
+    pub fn with_mapping_csv(mut self, csv: &str) -> Result<LatencyMatrix, Error> {
+        for cells in csv_lines(csv) {
+            let (address_id, region) = match cells[..] {
+                [address_id, region] => (address_id, region),
+                _ => return Err(invalid(format!("Expected a node and its region: {}", cells.join(",")))),
+            };
+            let address_id: u32 = address_id
+                .parse()
+                .map_err(|_err| invalid(format!("Invalid node: {}", address_id)))?;
+            let region_index = self
+                .regions
+                .iter()
+                .position(|known_region| known_region == region)
+                .ok_or_else(|| invalid(format!("Unknown region: {}", region)))?;
+            self.node_regions.insert(address_id, region_index);
+        }
+        Ok(self)
+    }
+
+    pub fn with_mapping_file<P: AsRef<Path>>(self, path: P) -> Result<LatencyMatrix, Error> {
+        let csv = fs::read_to_string(path).map_err(|err| invalid(err.to_string()))?;
+        self.with_mapping_csv(&csv)
+    }
+
+    pub fn regions(&self) -> &[String] {
+        &self.regions
+    }
+
+    /// The index of the region of the node with the given address.
+    pub fn region_of(&self, address_id: u32) -> usize {
+        match self.node_regions.get(&address_id) {
+            Some(region_index) => *region_index,
+            None => address_id as usize % self.regions.len(),
+        }
+    }
+
+    /// How long a message of the node takes to reach the given peer.
+    pub fn latency(&self, node_id: u32, peer_id: u32) -> Duration {
+        self.latencies[self.region_of(node_id)][self.region_of(peer_id)]
+    }
+}
+
+/// Half the round trip time, given in milliseconds.
+fn one_way_latency(round_trip_millis: &str) -> Option<Duration> {
+    let round_trip_millis: f64 = round_trip_millis.parse().ok()?;
+    Duration::try_from_secs_f64(round_trip_millis / 2000.0).ok()
+}
+
+/// The cells of the lines that are not blank, trimmed.
+fn csv_lines(csv: &str) -> impl Iterator<Item = Vec<&str>> {
+    csv.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.split(',').map(str::trim).collect())
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidLatencyMatrix(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MATRIX: &str = "
+        ,us-east,eu-west,ap-south
+        us-east,10,80,200
+        eu-west,80,8,120
+        ap-south,200,120,12
+    ";
+
+    #[test]
+    fn reads_the_latencies_between_the_regions() {
+        let matrix = LatencyMatrix::from_csv(MATRIX).unwrap();
+        assert_eq!(&["us-east", "eu-west", "ap-south"], matrix.regions());
+        // In turn.
+        assert_eq!(2, matrix.region_of(5));
+        assert_eq!(Duration::from_millis(40), matrix.latency(0, 4));
+        assert_eq!(Duration::from_millis(5), matrix.latency(0, 3));
+
+        let matrix = matrix.with_mapping_csv("0,ap-south\n4,ap-south\n").unwrap();
+        assert_eq!((2, 2, 1), (matrix.region_of(0), matrix.region_of(4), matrix.region_of(1)));
+        assert_eq!(Duration::from_millis(6), matrix.latency(0, 4));
+        assert!(matrix.clone().with_mapping_csv("0,sa-east").is_err());
+        assert!(matrix.clone().with_mapping_csv("0").is_err());
+        // A large address costs no more than a small one.
+        let matrix = matrix.with_mapping_csv("4000000000,ap-south").unwrap();
+        assert_eq!(2, matrix.region_of(4_000_000_000));
+        assert!(matrix.with_mapping_csv("4294967296,eu-west").is_err());
+
+        assert!(LatencyMatrix::from_csv("").is_err());
+        assert!(LatencyMatrix::from_csv(",us-east,eu-west\nus-east,10,80\n").is_err());
+        assert!(LatencyMatrix::from_csv(",us-east,eu-west\neu-west,80,8\nus-east,10,80\n").is_err());
+        assert!(LatencyMatrix::from_csv(",us-east\nus-east,-10\n").is_err());
+        assert!(LatencyMatrix::from_csv(",us-east\nus-east,10,20\n").is_err());
+    }
+}
@@ -1,44 +1,183 @@
-use futures::{stream, Future, Stream};
-use network::transport::MPSCAddress;
-pub use network::transport::MPSCConnection;
-use network::transport::MPSCTransport;
-use rand::{self, Rng};
-use std::collections::HashSet;
+use crate::clock::{self, Clock, RuntimeConfig, SystemClock};
+use crate::error::Error;
+pub use crate::network::address_book::AddressBook;
+pub use crate::network::bandwidth::{BandwidthMeter, Compression, ConnectionBytes, WireEncoder};
+pub use crate::network::conditions::NetworkConditions;
+pub use crate::network::control::{Controller, Delivery};
+pub use crate::network::latency_matrix::LatencyMatrix;
+pub use crate::network::multiplex::{
+    demultiplex, open_channels, ChannelId, ChannelMessage, ChannelReceiver, ChannelSender, Multiplexed,
+};
+pub use crate::network::scenario::Scenario;
+pub use crate::network::tcp::TcpBridge;
+pub use crate::network::trace::{Recorder, Trace, TraceCodec, TraceRecord};
+use crate::network::transport::MPSCAddress;
+pub use crate::network::transport::MPSCConnection;
+use crate::network::transport::{MPSCTransport, DEFAULT_CHANNEL_CAPACITY};
+pub use crate::network::transport::{ConnectionEvent, ConnectionSender, SendMetrics};
+use futures::channel::oneshot;
+use futures::{future, Future, Stream};
+use rand::{self, Rng, SeedableRng, StdRng};
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
-use std::ops::Add;
-use std::time::{Duration, Instant};
-use tokio;
-use tokio_timer::Delay;
+use std::panic;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Poll;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tokio::runtime::{Handle, Runtime};
+use tokio::task::AbortHandle;
+use tokio::time;
+
+/// How often the condition ending a network early is checked.
+const CONDITION_CHECK_INTERVAL: Duration = Duration::from_millis(10);
 
 pub trait Node<M> {
-    fn run<S>(self, connection_stream: S) -> Box<Future<Item = (), Error = ()> + Send>
+    /// What the node returns when it stops before the end of the simulation: its final chain
+    /// or its last metrics for instance, that `Network::run_until_with_outputs` collects.
+    type Output: Send + 'static;
+
+    /// Runs the node given the stream of its connections being opened and closed.
+    fn run<S>(self, connection_stream: S) -> Pin<Box<dyn Future<Output = Self::Output> + Send>>
     where
-        S: Stream<Item = MPSCConnection<M>, Error = ()> + Send + 'static;
+        S: Stream<Item = Result<ConnectionEvent<M>, Error>> + Send + Unpin + 'static;
 }
 
+pub mod address_book;
+pub mod bandwidth;
+pub mod conditions;
+pub mod control;
+pub mod latency_matrix;
+pub mod multiplex;
+pub mod rpc;
+pub mod scenario;
+pub mod tcp;
+pub mod trace;
 pub mod transport;
 
+/// How the nodes choose the peers they initiate connections to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Topology {
+    /// Random peers. The same seed gives the same connections.
+    Random { seed: Option<u64> },
+    /// The next nodes by address, the last ones connecting to the first ones.
+    Ring,
+    /// Random peers, for nodes spread over independent chains by address, see `chain_of`. The
+    /// peers are on the same chain, but for the given fraction of the connections, whose peers
+    /// are on other chains. The same seed gives the same connections.
+    MultiChain {
+        chains: u32,
+        cross_chain_fraction: f64,
+        seed: Option<u64>,
+    },
+}
+
+impl Topology {
+    /// The chain of the node with the given address, the first one for a single chain.
+    pub fn chain_of(&self, address_id: u32) -> u32 {
+        match *self {
+            Topology::MultiChain { chains, .. } => address_id % chains.max(1),
+            _ => 0,
+        }
+    }
+}
+
 pub struct Network<M>
 where
     M: Clone + Send + 'static,
 {
     transports: Vec<MPSCTransport<M>>,
+    /// Whether each node is behind a NAT, by address.
+    behind_nat: Vec<bool>,
+    /// The addresses of the address book the nodes could not reconnect to.
+    stale_addresses: u32,
+    send_metrics: SendMetrics,
+    conditions: NetworkConditions,
+    controller: Controller,
+    recorder: Option<Recorder<M>>,
+    /// The connections to the nodes of other processes, if this one only hosts some of them.
+    bridge: Option<TcpBridge<M>>,
+    scenario: Scenario,
+    clock: Arc<dyn Clock>,
+    runtime_config: RuntimeConfig,
 }
 
 impl<M> Network<M>
 where
     M: Clone + Send + 'static,
 {
-    pub fn new(size: u32, initiated_connections_per_node: u8) -> Network<M>
-    where
-        M: Clone + Send + 'static,
-    {
+    pub fn new(size: u32, initiated_connections_per_node: u8) -> Network<M> {
+        Network::from_topology(size, initiated_connections_per_node, Topology::Random { seed: None })
+    }
+
+    /// A network whose nodes initiate connections to the peers chosen by the given topology.
+    pub fn from_topology(size: u32, initiated_connections_per_node: u8, topology: Topology) -> Network<M> {
+        Network::from_topology_behind_nat(size, initiated_connections_per_node, topology, 0.0)
+    }
+
+    /// Same as `from_topology`, the given fraction of the nodes, drawn at random, being behind
+    /// a NAT: they initiate their connections like the others, but cannot accept any. Every
+    /// connection is then initiated to a reachable node, which form the backbone of the
+    /// network. The seed of the topology draws the same nodes. At least one node stays
+    /// reachable, whatever the fraction.
+    pub fn from_topology_behind_nat(
+        size: u32,
+        initiated_connections_per_node: u8,
+        topology: Topology,
+        nat_fraction: f64,
+    ) -> Network<M> {
+        Network::from_address_book(
+            size,
+            initiated_connections_per_node,
+            topology,
+            nat_fraction,
+            &AddressBook::new(),
+        )
+    }
+
+    /// Same as `from_topology_behind_nat`, the nodes first reconnecting to the peers the
+    /// address book gives them, then initiating the connections left to the peers chosen by
+    /// the topology. The stale addresses are dropped: the nodes gone, behind a NAT or already
+    /// connected, and the ones beyond the connections to initiate.
+    pub fn from_address_book(
+        size: u32,
+        initiated_connections_per_node: u8,
+        topology: Topology,
+        nat_fraction: f64,
+        address_book: &AddressBook,
+    ) -> Network<M> {
+        let mut rng: StdRng = match topology {
+            Topology::Random { seed: Some(seed) } | Topology::MultiChain { seed: Some(seed), .. } => {
+                SeedableRng::from_seed(&[seed as usize][..])
+            }
+            _ => SeedableRng::from_seed(&[rand::thread_rng().gen::<usize>()][..]),
+        };
         let mut transports = vec![];
         let mut addresses = vec![];
         let mut defined_connections = BiSet::new();
+        let mut stale_addresses = 0;
+        let send_metrics = SendMetrics::new();
+        let conditions = NetworkConditions::new();
+        let controller = Controller::new();
+
+        // Drawn only if any, for the other connections of a seed to stay the same.
+        let mut behind_nat = vec![false; size as usize];
+        let nat_nodes = ((size as f64 * nat_fraction).round() as usize).min((size as usize).saturating_sub(1));
+        if nat_nodes > 0 {
+            let mut address_ids: Vec<usize> = (0..size as usize).collect();
+            for i in 0..nat_nodes {
+                let drawn = rng.gen_range(i, address_ids.len());
+                address_ids.swap(i, drawn);
+                behind_nat[address_ids[i]] = true;
+            }
+        }
 
         for i in 0..size {
-            let node = MPSCTransport::new(i);
+            let node = MPSCTransport::new(i)
+                .with_send_metrics(send_metrics.clone())
+                .with_conditions(conditions.clone())
+                .with_controller(controller.clone());
             addresses.push(node.address().clone());
             transports.push(node);
         }
@@ -50,66 +189,374 @@ where
             for candidate in &addresses {
                 let candidate_address_id = *candidate.id();
                 if node_address_id != candidate_address_id
+                    && !behind_nat[candidate_address_id as usize]
                     && !defined_connections.contains(node_address_id, candidate_address_id)
                 {
                     candidate_addresses.push(candidate.clone());
                 }
             }
 
-            for _i in 0u8..initiated_connections_per_node {
-                let pool_not_empty = !candidate_addresses.is_empty();
-                if pool_not_empty {
-                    let seed_index = transports.random_different_address(&candidate_addresses);
+            let mut reconnections = 0u8;
+            for known_peer in address_book.peers_of(node_address_id) {
+                let known_index = candidate_addresses.iter().position(|candidate| candidate.id() == known_peer);
+                match known_index {
+                    Some(known_index) if reconnections < initiated_connections_per_node => {
+                        let seed_address = candidate_addresses.remove(known_index);
+                        defined_connections.insert(*seed_address.id(), node_address_id);
+                        transports.include_seed(seed_address);
+                        reconnections += 1;
+                    }
+                    _ => stale_addresses += 1,
+                }
+            }
 
-                    let seed_address = candidate_addresses.remove(seed_index);
-                    defined_connections.insert(*seed_address.id(), node_address_id);
-                    transports.include_seed(seed_address);
+            for _i in reconnections..initiated_connections_per_node {
+                let seed_index = if candidate_addresses.is_empty() {
+                    None
                 } else {
-                    debug!("Empty pool.");
+                    match topology {
+                        Topology::Random { .. } => Some(rng.gen_range(0, candidate_addresses.len())),
+                        Topology::Ring => Some(next_address(node_address_id, size, &candidate_addresses)),
+                        Topology::MultiChain {
+                            cross_chain_fraction, ..
+                        } => {
+                            let cross_chain = rng.gen::<f64>() < cross_chain_fraction;
+                            let node_chain = topology.chain_of(node_address_id);
+                            let pool: Vec<usize> = (0..candidate_addresses.len())
+                                .filter(|index| {
+                                    let candidate_chain = topology.chain_of(*candidate_addresses[*index].id());
+                                    (candidate_chain != node_chain) == cross_chain
+                                })
+                                .collect();
+                            if pool.is_empty() {
+                                None
+                            } else {
+                                Some(pool[rng.gen_range(0, pool.len())])
+                            }
+                        }
+                    }
+                };
+
+                match seed_index {
+                    Some(seed_index) => {
+                        let seed_address = candidate_addresses.remove(seed_index);
+                        defined_connections.insert(*seed_address.id(), node_address_id);
+                        transports.include_seed(seed_address);
+                    }
+                    None => debug!("Empty pool."),
                 }
             }
         }
 
-        Network { transports }
+        Network {
+            transports,
+            behind_nat,
+            stale_addresses,
+            send_metrics,
+            conditions,
+            controller,
+            recorder: None,
+            bridge: None,
+            scenario: Scenario::new(),
+            clock: Arc::new(SystemClock),
+            runtime_config: RuntimeConfig::new(),
+        }
+    }
+
+    /// How many messages each connection buffers before its sender is congested.
+    pub fn with_channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.transports = self
+            .transports
+            .into_iter()
+            .map(|transport| transport.with_channel_capacity(channel_capacity))
+            .collect();
+        self
+    }
+
+    /// Records the messages delivered while the network runs, see the `trace` module.
+    pub fn with_recorder(mut self, recorder: Recorder<M>) -> Self {
+        self.transports = self
+            .transports
+            .into_iter()
+            .map(|transport| transport.with_recorder(recorder.clone()))
+            .collect();
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Counts the bytes the connections send, see the `bandwidth` module.
+    pub fn with_bandwidth_meter(mut self, bandwidth: BandwidthMeter<M>) -> Self {
+        self.transports = self
+            .transports
+            .into_iter()
+            .map(|transport| transport.with_bandwidth_meter(bandwidth.clone()))
+            .collect();
+        self
+    }
+
+    /// Only runs the nodes the bridge hosts, connected to the ones of the other processes
+    /// through it, see the `tcp` module. The factory still creates every node, in the order of
+    /// their addresses, and the others are dropped right away. The other processes must build
+    /// the same topology, from the same seed.
+    pub fn with_tcp_bridge(mut self, mut bridge: TcpBridge<M>) -> Self {
+        for transport in &mut self.transports {
+            if bridge.hosts(*transport.address().id()) {
+                transport.redirect_seeds(|seed| {
+                    if bridge.hosts(*seed.id()) {
+                        None
+                    } else {
+                        Some(bridge.proxy(*seed.id()))
+                    }
+                });
+            }
+        }
+        self.bridge = Some(bridge);
+        self
+    }
+
+    /// The events to apply to the network while it runs. The nodes are killed by address,
+    /// which is the order they are created in by the factory given to `run`.
+    pub fn with_scenario(mut self, scenario: Scenario) -> Self {
+        self.scenario = scenario;
+        self
+    }
+
+    /// The clock driving the timers of the network and of its nodes. Default: the wall clock
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// How the runtimes of the network use the threads of the host. Default: the default of
+    /// the clock
+    pub fn with_runtime_config(mut self, runtime_config: RuntimeConfig) -> Self {
+        self.runtime_config = runtime_config;
+        self
+    }
+
+    /// The congested sends of every connection of the network, to be read once it has run.
+    pub fn send_metrics(&self) -> SendMetrics {
+        self.send_metrics.clone()
+    }
+
+    /// The number of connections of each node, by address: the ones it initiates and the ones
+    /// its peers initiate to it.
+    pub fn degrees(&self) -> Vec<u32> {
+        let mut degrees = vec![0; self.transports.len()];
+        for transport in &self.transports {
+            for seed in transport.seeds() {
+                degrees[*transport.address().id() as usize] += 1;
+                degrees[*seed.id() as usize] += 1;
+            }
+        }
+        degrees
     }
 
-    pub fn run<N, F>(self, node_factory: F, for_duration: Duration)
+    /// Whether each node is behind a NAT, by address: none of its connections were initiated
+    /// by its peers.
+    pub fn behind_nat(&self) -> Vec<bool> {
+        self.behind_nat.clone()
+    }
+
+    /// The peers each node initiates its connections to, to build the next network from.
+    pub fn address_book(&self) -> AddressBook {
+        let peers = self
+            .transports
+            .iter()
+            .map(|transport| transport.seeds().iter().map(|seed| *seed.id()).collect())
+            .collect();
+        AddressBook::from_peers(peers)
+    }
+
+    /// The addresses of the address book the network was built from that the nodes did not
+    /// reconnect to.
+    pub fn stale_addresses(&self) -> u32 {
+        self.stale_addresses
+    }
+
+    /// The conditions every connection of the network follows, which can be changed while
+    /// it runs.
+    pub fn conditions(&self) -> NetworkConditions {
+        self.conditions.clone()
+    }
+
+    /// Pauses, steps and resumes the nodes and the messages of the network while it runs.
+    pub fn controller(&self) -> Controller {
+        self.controller.clone()
+    }
+
+    /// Runs every node until the given duration passes, and returns how long they ran by the
+    /// clock of the network. The panics of the nodes are propagated once they all stopped.
+    pub fn run<N, F>(self, node_factory: F, for_duration: Duration) -> Duration
     where
         N: Node<M> + Sync + Send + 'static,
         F: Fn() -> N + Send + 'static,
     {
-        let nodes = self.transports;
-        let nodes_future = stream::iter_ok(nodes).for_each(move |transport| {
-            debug!("Starting a new node.");
+        self.run_until(node_factory, for_duration, || false)
+    }
 
-            let node_future = node_factory().run(transport.run());
-            tokio::spawn(with_timeout(node_future, for_duration))
-        });
+    /// Same as `run`, but stops every node as soon as the given condition holds, which is
+    /// checked every few milliseconds.
+    pub fn run_until<N, F, C>(self, node_factory: F, for_duration: Duration, condition: C) -> Duration
+    where
+        N: Node<M> + Sync + Send + 'static,
+        F: Fn() -> N + Send + 'static,
+        C: FnMut() -> bool + Send + 'static,
+    {
+        let (duration, _outputs) = self.run_until_with_outputs(node_factory, for_duration, condition);
+        duration
+    }
 
-        tokio::run(nodes_future);
+    /// Same as `run_until`, but also returns the outputs of the nodes, by address. A node
+    /// still running at the end, or stopped by the condition or the scenario, has none.
+    pub fn run_until_with_outputs<N, F, C>(
+        self,
+        node_factory: F,
+        for_duration: Duration,
+        mut condition: C,
+    ) -> (Duration, Vec<Option<N::Output>>)
+    where
+        N: Node<M> + Sync + Send + 'static,
+        F: Fn() -> N + Send + 'static,
+        C: FnMut() -> bool + Send + 'static,
+    {
+        let runtime = self.clock.runtime(&self.runtime_config);
+        let shards: Vec<Shard> = (0..self.runtime_config.shards().unwrap_or(0))
+            .map_while(|_shard| self.clock.shard_runtime(&self.runtime_config))
+            .map(Shard::start)
+            .collect();
+        let scenario = self.scenario;
+        let conditions = self.conditions;
+        let controller = self.controller;
+        let recorder = self.recorder;
+        // The nodes this process runs, all of them without a bridge.
+        let hosted = self.bridge.as_ref().map(TcpBridge::hosted);
+        let bridge = self.bridge;
+        runtime.block_on(async move {
+            let start = clock::now();
+            if let Some(recorder) = recorder {
+                recorder.start();
+            }
+            if let Some(bridge) = bridge {
+                let hosted_addresses: HashMap<u32, MPSCAddress<M>> = self
+                    .transports
+                    .iter()
+                    .filter(|transport| bridge.hosts(*transport.address().id()))
+                    .map(|transport| (*transport.address().id(), transport.address().clone()))
+                    .collect();
+                let channel_capacity = self
+                    .transports
+                    .first()
+                    .map_or(DEFAULT_CHANNEL_CAPACITY, MPSCTransport::channel_capacity);
+                tokio::spawn(bridge.serve(hosted_addresses, channel_capacity));
+            }
+            let handles: Vec<_> = self
+                .transports
+                .into_iter()
+                .enumerate()
+                .map(|(index, transport)| {
+                    let node_id = *transport.address().id();
+                    let node = node_factory();
+                    if !hosted.as_ref().is_none_or(|hosted| hosted.contains(&node_id)) {
+                        // Another process runs it.
+                        return tokio::spawn(async { None });
+                    }
+                    debug!("Starting a new node.");
+
+                    let mut node_future = node.run(Box::pin(transport.run()));
+                    let controller = controller.clone();
+                    // A paused node is not polled until it may run again.
+                    let node_future = future::poll_fn(move |cx| {
+                        if controller.may_run(node_id, cx.waker()) {
+                            node_future.as_mut().poll(cx)
+                        } else {
+                            Poll::Pending
+                        }
+                    });
+                    let node_task = async move {
+                        // A node stops at the end of the simulation, if not before.
+                        tokio::time::timeout(for_duration, node_future).await.ok()
+                    };
+                    match shards.get(index % shards.len().max(1)) {
+                        Some(shard) => shard.handle.spawn(node_task),
+                        None => tokio::spawn(node_task),
+                    }
+                })
+                .collect();
+
+            let abort_handles: Vec<AbortHandle> = handles.iter().map(|handle| handle.abort_handle()).collect();
+            // The scenario is dropped with the runtime if it outlasts the nodes.
+            tokio::spawn(scenario.play(conditions, abort_handles.clone()));
+            tokio::spawn(async move {
+                let mut checks = time::interval(CONDITION_CHECK_INTERVAL);
+                loop {
+                    checks.tick().await;
+                    if condition() {
+                        for node in &abort_handles {
+                            node.abort();
+                        }
+                        return;
+                    }
+                }
+            });
+
+            let mut outputs = Vec::with_capacity(handles.len());
+            for handle in handles {
+                // A node killed by the scenario is cancelled.
+                match handle.await {
+                    Ok(output) => outputs.push(output),
+                    Err(err) if err.is_panic() => panic::resume_unwind(err.into_panic()),
+                    Err(_cancelled) => outputs.push(None),
+                }
+            }
+            (clock::now() - start, outputs)
+        })
     }
 }
 
-fn with_timeout<F>(future: F, timeout: Duration) -> impl Future<Item = (), Error = ()>
-where
-    F: Future<Item = (), Error = ()>,
-{
-    let delay_future =
-        Delay::new(Instant::now().add(timeout)).map_err(|err| panic!("Timer error: {}", err));
+/// A runtime running some of the nodes of a network on its own thread, until it is dropped.
+struct Shard {
+    handle: Handle,
+    stop: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Shard {
+    fn start(runtime: Runtime) -> Shard {
+        let handle = runtime.handle().clone();
+        let (stop, stopped) = oneshot::channel();
+        let thread = thread::spawn(move || {
+            let _ = runtime.block_on(stopped);
+        });
 
-    future.select(delay_future).map(|_| {}).map_err(|_| {})
+        Shard {
+            handle,
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
 }
 
-impl<M> MPSCTransport<M>
-where
-    M: Clone + Send + 'static,
-{
-    fn random_different_address(&self, pool: &[MPSCAddress<M>]) -> usize {
-        let mut rng = rand::thread_rng();
-        rng.gen_range(0, pool.len())
+impl Drop for Shard {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
     }
 }
 
+/// The index of the address following the given one in a ring of the given size.
+fn next_address<M>(address_id: u32, size: u32, pool: &[MPSCAddress<M>]) -> usize {
+    pool.iter()
+        .enumerate()
+        .min_by_key(|(_index, candidate)| (*candidate.id() + size - address_id) % size)
+        .map(|(index, _candidate)| index)
+        .expect("The pool is not empty")
+}
+
 /// A very naive HashSet for tuples.
 /// May not be the most efficient because 'contains' method instantiate a new tuple, requiring owned items.
 struct BiSet<T>
@@ -149,9 +596,11 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
-    use futures::Future;
+    use futures::executor::block_on;
+    use futures::{future, StreamExt};
+    use crate::clock::MockClock;
     use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
-    use std::sync::Arc;
+    use std::sync::{Arc, Mutex};
 
     #[derive(Clone, Debug)]
     pub struct Message {}
@@ -163,32 +612,34 @@ mod tests {
     }
 
     impl Node<Message> for TestNode {
-        fn run<S>(self, connection_stream: S) -> Box<Future<Item = (), Error = ()> + Send>
+        type Output = ();
+
+        fn run<S>(self, mut connection_stream: S) -> Pin<Box<dyn Future<Output = ()> + Send>>
         where
-            S: Stream<Item = MPSCConnection<Message>, Error = ()> + Send + 'static,
+            S: Stream<Item = Result<ConnectionEvent<Message>, Error>> + Send + Unpin + 'static,
         {
             self.notified_of_start.store(true, Ordering::Relaxed);
 
-            let connection_future = connection_stream.for_each(move |connection| {
-                self.connections_established.fetch_add(1, Ordering::Relaxed);
-                let received_messages = self.received_messages.clone();
-                let (sender, receiver) = connection.split();
+            Box::pin(async move {
+                while let Some(event) = connection_stream.next().await {
+                    let connection = match event.expect("The transport failed") {
+                        ConnectionEvent::Opened(connection) => connection,
+                        ConnectionEvent::Closed(_peer_id) => continue,
+                    };
+                    self.connections_established.fetch_add(1, Ordering::Relaxed);
+                    let received_messages = self.received_messages.clone();
+                    let (mut sender, receiver) = connection.split();
 
-                // Send one message per connection received for each node.
-                if let Err(_err) = &sender.unbounded_send(Message {}) {
-                    panic!()
-                }
+                    // Send one message per connection received for each node.
+                    sender.try_send(Message {}).expect("Could not send a message");
 
-                let reception = receiver
-                    .into_future().map(move |(_message, _tail)| {
+                    tokio::spawn(async move {
+                        let (_message, _tail) = receiver.into_future().await;
                         received_messages.fetch_add(1, Ordering::Relaxed);
                         drop(sender); // This will drop the connection too.
-                    })
-                    .map_err(|_| panic!());
-                tokio::spawn(reception)
-            });
-
-            Box::new(connection_future)
+                    });
+                }
+            })
         }
     }
 
@@ -200,8 +651,21 @@ mod tests {
         new_network_test(256, 4);
     }
 
+    #[test]
+    fn runs_the_nodes_on_shards() {
+        let runtime_config = RuntimeConfig::new()
+            .with_worker_threads(2)
+            .with_max_blocking_threads(2)
+            .with_shards(3);
+        sized_network_test(16, 2, runtime_config);
+    }
+
     fn new_network_test(network_size: u32, initiated_connections: u8) {
-        let network = Network::new(network_size, initiated_connections);
+        sized_network_test(network_size, initiated_connections, RuntimeConfig::new());
+    }
+
+    fn sized_network_test(network_size: u32, initiated_connections: u8, runtime_config: RuntimeConfig) {
+        let network = Network::new(network_size, initiated_connections).with_runtime_config(runtime_config);
 
         let global_number_of_received_messages = Arc::new(AtomicUsize::new(0));
         let notified_of_start = Arc::new(AtomicBool::new(false));
@@ -230,4 +694,333 @@ mod tests {
         );
         assert!(notified_of_start.load(Ordering::Relaxed));
     }
+
+    /// Returns the id of its first peer, if it stops at all.
+    struct FirstPeerNode {
+        stops: bool,
+    }
+
+    impl Node<Message> for FirstPeerNode {
+        type Output = u32;
+
+        fn run<S>(self, mut connection_stream: S) -> Pin<Box<dyn Future<Output = u32> + Send>>
+        where
+            S: Stream<Item = Result<ConnectionEvent<Message>, Error>> + Send + Unpin + 'static,
+        {
+            Box::pin(async move {
+                if !self.stops {
+                    return future::pending().await;
+                }
+
+                loop {
+                    let event = connection_stream.next().await.expect("The transport stopped");
+                    if let ConnectionEvent::Opened(connection) = event.expect("The transport failed") {
+                        return connection.peer_id();
+                    }
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn collects_the_outputs_of_the_nodes_that_stopped() {
+        let network: Network<Message> = Network::from_topology(4, 1, Topology::Ring).with_clock(MockClock);
+        let started = AtomicUsize::new(0);
+        let (_duration, outputs) = network.run_until_with_outputs(
+            move || FirstPeerNode { stops: started.fetch_add(1, Ordering::Relaxed).is_multiple_of(2) },
+            Duration::from_secs(5),
+            || false,
+        );
+
+        assert_eq!(4, outputs.len());
+        for (address, output) in outputs.into_iter().enumerate() {
+            if address.is_multiple_of(2) {
+                assert_ne!(Some(address as u32), output);
+                assert!(output.is_some());
+            } else {
+                assert_eq!(None, output);
+            }
+        }
+    }
+
+    #[test]
+    fn steps_the_messages_held_while_paused() {
+        let network = Network::from_topology(4, 1, Topology::Ring).with_clock(MockClock);
+        network.conditions().set_latency(Duration::from_secs(1));
+        let controller = network.controller();
+        let received_messages = Arc::new(AtomicUsize::new(0));
+        let received_messages_clone = received_messages.clone();
+        let checked_messages = received_messages.clone();
+        // The messages received after each step, once the messages sent are all held.
+        let steps = Arc::new(Mutex::new(vec![]));
+        let steps_clone = steps.clone();
+
+        let start = clock::now();
+        network.run_until(
+            move || TestNode {
+                received_messages: received_messages_clone.clone(),
+                notified_of_start: Arc::new(AtomicBool::new(false)),
+                connections_established: Arc::new(AtomicUsize::new(0)),
+            },
+            Duration::from_secs(60),
+            move || {
+                let elapsed = clock::now() - start;
+                if elapsed >= Duration::from_millis(500) && !controller.is_paused() {
+                    controller.pause();
+                } else if elapsed >= Duration::from_millis(1500) {
+                    let mut steps = steps_clone.lock().unwrap();
+                    steps.push((controller.held_messages(), checked_messages.load(Ordering::Relaxed)));
+                    if controller.step().is_none() {
+                        controller.resume();
+                        return true;
+                    }
+                }
+                false
+            },
+        );
+
+        // Each message is received at its step, none before.
+        let expected_steps: Vec<_> = (0..8).map(|step| (8 - step, step)).collect();
+        assert_eq!(expected_steps, steps.lock().unwrap()[..8]);
+        assert_eq!(8, received_messages.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn builds_the_connections_of_a_topology() {
+        let seeds = |network: Network<Message>| -> Vec<Vec<u32>> {
+            network
+                .transports
+                .iter()
+                .map(|transport| transport.seeds().iter().map(|address| *address.id()).collect())
+                .collect()
+        };
+
+        let ring = Network::from_topology(4, 2, Topology::Ring);
+        assert_eq!(vec![3, 3, 3, 3], ring.degrees());
+        assert_eq!(vec![vec![1, 2], vec![2, 3], vec![3], vec![0]], seeds(ring));
+        assert_eq!(vec![2, 2, 2, 2], Network::<Message>::from_topology(4, 1, Topology::Ring).degrees());
+
+        let topology = Topology::Random { seed: Some(42) };
+        assert_eq!(
+            seeds(Network::from_topology(16, 3, topology)),
+            seeds(Network::from_topology(16, 3, topology))
+        );
+
+        let separate_chains = Topology::MultiChain {
+            chains: 2,
+            cross_chain_fraction: 0.0,
+            seed: Some(42),
+        };
+        for (node_id, node_seeds) in seeds(Network::from_topology(16, 3, separate_chains)).iter().enumerate() {
+            assert_eq!(3, node_seeds.len());
+            assert!(node_seeds.iter().all(|seed| seed % 2 == node_id as u32 % 2), "{:?}", node_seeds);
+        }
+        let bridged_chains = Topology::MultiChain {
+            chains: 2,
+            cross_chain_fraction: 1.0,
+            seed: Some(42),
+        };
+        for (node_id, node_seeds) in seeds(Network::from_topology(16, 1, bridged_chains)).iter().enumerate() {
+            assert!(node_seeds.iter().all(|seed| seed % 2 != node_id as u32 % 2), "{:?}", node_seeds);
+        }
+        let partly_bridged_chains = Topology::MultiChain {
+            chains: 4,
+            cross_chain_fraction: 0.2,
+            seed: Some(42),
+        };
+        let network_seeds = seeds(Network::from_topology(256, 4, partly_bridged_chains));
+        let connections: Vec<(u32, u32)> = network_seeds
+            .iter()
+            .enumerate()
+            .flat_map(|(node_id, node_seeds)| node_seeds.iter().map(move |seed| (node_id as u32, *seed)))
+            .collect();
+        let cross_chain_connections = connections
+            .iter()
+            .filter(|(node_id, seed)| partly_bridged_chains.chain_of(*node_id) != partly_bridged_chains.chain_of(*seed))
+            .count();
+        let cross_chain_fraction = cross_chain_connections as f64 / connections.len() as f64;
+        assert!((0.15..0.25).contains(&cross_chain_fraction), "{}", cross_chain_fraction);
+        assert_eq!(1, bridged_chains.chain_of(5));
+        assert_eq!(0, Topology::Ring.chain_of(5));
+    }
+
+    #[test]
+    fn puts_some_nodes_behind_a_nat() {
+        let seeds = |network: Network<Message>| -> Vec<Vec<u32>> {
+            network
+                .transports
+                .iter()
+                .map(|transport| transport.seeds().iter().map(|address| *address.id()).collect())
+                .collect()
+        };
+        let topology = Topology::Random { seed: Some(42) };
+
+        // The nodes behind a NAT only initiate connections, to the reachable nodes.
+        let network = Network::<Message>::from_topology_behind_nat(16, 2, topology, 0.5);
+        let behind_nat = network.behind_nat();
+        assert_eq!(8, behind_nat.iter().filter(|nat| **nat).count());
+        let degrees = network.degrees();
+        for (node_id, node_seeds) in seeds(network).iter().enumerate() {
+            assert!(node_seeds.iter().all(|seed| !behind_nat[*seed as usize]), "{:?}", node_seeds);
+            if behind_nat[node_id] {
+                assert_eq!(2, degrees[node_id]);
+            }
+        }
+        // The same seed draws the same nodes, and no NAT keeps the connections of the seed.
+        assert_eq!(behind_nat, Network::<Message>::from_topology_behind_nat(16, 2, topology, 0.5).behind_nat());
+        assert_eq!(
+            seeds(Network::from_topology(16, 3, topology)),
+            seeds(Network::from_topology_behind_nat(16, 3, topology, 0.0))
+        );
+        // In a ring, to the next reachable node.
+        let ring = Network::<Message>::from_topology_behind_nat(4, 1, Topology::Ring, 0.5);
+        let behind_nat = ring.behind_nat();
+        for (node_id, node_seeds) in seeds(ring).iter().enumerate() {
+            let next_reachable = (1..4).map(|step| (node_id + step) % 4).find(|peer| !behind_nat[*peer]).unwrap();
+            if behind_nat[node_id] {
+                assert_eq!(&vec![next_reachable as u32], node_seeds);
+            }
+        }
+        // A fraction rounding to every node still keeps one reachable.
+        let network = Network::<Message>::from_topology_behind_nat(10, 2, Topology::Ring, 0.96);
+        let behind_nat = network.behind_nat();
+        assert_eq!(9, behind_nat.iter().filter(|nat| **nat).count());
+        let reachable = behind_nat.iter().position(|nat| !nat).unwrap() as u32;
+        for (node_id, node_seeds) in seeds(network).iter().enumerate() {
+            if node_id as u32 != reachable {
+                assert_eq!(&vec![reachable], node_seeds);
+            }
+        }
+    }
+
+    #[test]
+    fn reconnects_to_the_peers_of_the_address_book() {
+        let network = Network::<Message>::from_topology(16, 3, Topology::Random { seed: Some(7) });
+        let address_book = network.address_book();
+        assert_eq!(16, address_book.len());
+        assert_eq!(3, address_book.peers_of(0).len());
+
+        // Whatever the seed of the topology.
+        let topology = Topology::Random { seed: Some(8) };
+        let restarted = Network::<Message>::from_address_book(16, 3, topology, 0.0, &address_book);
+        assert_eq!(0, restarted.stale_addresses());
+        assert_eq!(address_book, restarted.address_book());
+
+        // The nodes gone are replaced by peers drawn from the topology.
+        let shrunk = Network::<Message>::from_address_book(12, 3, topology, 0.0, &address_book);
+        let gone_peers = (0..12).flat_map(|node_id| address_book.peers_of(node_id)).filter(|peer| **peer >= 12).count();
+        // A peer drawn instead may be one that also knew the node.
+        assert!(shrunk.stale_addresses() >= gone_peers as u32);
+        let shrunk_address_book = shrunk.address_book();
+        let connected = |node_id: u32, peer: u32| {
+            let initiated = shrunk_address_book.peers_of(node_id).contains(&peer);
+            initiated || shrunk_address_book.peers_of(peer).contains(&node_id)
+        };
+        for node_id in 0..12 {
+            let mut known_peers = address_book.peers_of(node_id).iter().filter(|peer| **peer < 12);
+            assert!(known_peers.all(|peer| connected(node_id, *peer)), "{:?}", address_book.peers_of(node_id));
+            assert!(shrunk_address_book.peers_of(node_id).iter().all(|seed| *seed < 12));
+        }
+
+        // Nor itself, nor a peer it is connected to already, nor more peers than the connections
+        // to initiate.
+        let address_book = AddressBook::from_peers(vec![vec![0, 1, 1], vec![], vec![0, 1, 3], vec![0, 1, 2]]);
+        let ring = Network::<Message>::from_address_book(4, 2, Topology::Ring, 0.0, &address_book);
+        assert_eq!(6, ring.stale_addresses());
+        let expected = AddressBook::from_peers(vec![vec![1, 2], vec![2, 3], vec![3], vec![0]]);
+        assert_eq!(expected, ring.address_book());
+    }
+
+    #[test]
+    fn drops_messages_sent_on_a_congested_connection() {
+        let metrics = SendMetrics::new();
+        let transport = MPSCTransport::new(0)
+            .with_channel_capacity(1)
+            .with_send_metrics(metrics.clone());
+        let mut seeding_transport = MPSCTransport::new(1)
+            .with_channel_capacity(1)
+            .with_send_metrics(metrics.clone());
+        seeding_transport.include_seed(transport.address().clone());
+
+        let (_seed_connection, connection) = connect(transport, seeding_transport);
+        let (mut sender, _receiver) = connection.split();
+
+        // The capacity of a channel is its buffer plus one message per sender.
+        assert_eq!(Ok(()), sender.try_send(Message {}));
+        assert_eq!(Ok(()), sender.try_send(Message {}));
+        assert_eq!(Err(Error::Congested), sender.try_send(Message {}));
+        assert_eq!(1, metrics.dropped());
+        assert_eq!(0, metrics.delayed());
+    }
+
+    #[test]
+    fn loses_the_messages_sent_across_a_partition() {
+        let metrics = SendMetrics::new();
+        let conditions = NetworkConditions::new();
+        let transport = MPSCTransport::new(0)
+            .with_send_metrics(metrics.clone())
+            .with_conditions(conditions.clone());
+        let mut seeding_transport = MPSCTransport::new(1)
+            .with_send_metrics(metrics.clone())
+            .with_conditions(conditions.clone());
+        seeding_transport.include_seed(transport.address().clone());
+
+        let (seed_connection, connection) = connect(transport, seeding_transport);
+        let (mut sender, _receiver) = connection.split();
+        let (_seed_sender, mut seed_receiver) = seed_connection.split();
+
+        conditions.partition(&[vec![0], vec![1]]);
+        assert_eq!(Ok(()), sender.try_send(Message {}));
+        assert!(seed_receiver.try_recv().is_err());
+        assert_eq!(1, metrics.lost());
+
+        conditions.heal();
+        assert_eq!(Ok(()), sender.try_send(Message {}));
+        assert!(seed_receiver.try_recv().is_ok());
+    }
+
+    #[test]
+    fn notifies_peers_of_disconnections() {
+        let transport = MPSCTransport::new(0);
+        let mut seeding_transport = MPSCTransport::new(1);
+        seeding_transport.include_seed(transport.address().clone());
+        let mut seed_events = Box::pin(transport.run());
+        let mut seeding_events = Box::pin(seeding_transport.run());
+
+        let (seed_event, seeding_event) =
+            block_on(future::join(seed_events.next(), seeding_events.next()));
+        let seed_connection = opened(seed_event);
+        let seeding_connection = opened(seeding_event);
+        assert_eq!(1, seed_connection.peer_id());
+        assert_eq!(0, seeding_connection.peer_id());
+        assert!(seed_connection.is_inbound());
+        assert!(!seeding_connection.is_inbound());
+
+        let (sender, _receiver) = seeding_connection.split();
+        assert_eq!(Ok(()), sender.disconnect());
+
+        match block_on(seed_events.next()) {
+            Some(Ok(ConnectionEvent::Closed(peer_id))) => assert_eq!(1, peer_id),
+            _ => panic!("Expected the connection to be closed"),
+        }
+    }
+
+    /// Connects a seeding transport to its seed and returns the connections of both.
+    fn connect(
+        transport: MPSCTransport<Message>,
+        seeding_transport: MPSCTransport<Message>,
+    ) -> (MPSCConnection<Message>, MPSCConnection<Message>) {
+        // The seed acknowledges the connection before the seeding transport yields it.
+        let mut seed_events = Box::pin(transport.run());
+        let mut events = Box::pin(seeding_transport.run());
+        let (seed_event, event) = block_on(future::join(seed_events.next(), events.next()));
+        (opened(seed_event), opened(event))
+    }
+
+    fn opened(event: Option<Result<ConnectionEvent<Message>, Error>>) -> MPSCConnection<Message> {
+        match event {
+            Some(Ok(ConnectionEvent::Opened(connection))) => connection,
+            _ => panic!("Expected a new connection"),
+        }
+    }
 }
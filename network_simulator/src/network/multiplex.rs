@@ -0,0 +1,244 @@
+//! Several protocols sharing the connections of a network.
+//!
+//! The messages of a network are of a single type. To speak two protocols over the same
+//! connections, a node sends `Multiplexed` messages: the sender wraps the messages of each
+//! protocol in their variant, and `demultiplex` splits the receiver of a connection into one
+//! stream per protocol, so that each can be handled by its own task. Multiplexing more than
+//! two protocols nests them, `Multiplexed<A, Multiplexed<B, C>>` for instance.
+//!
+//! The messages of both protocols still share the queue of the connection, and are handled in
+//! the order they were received. `open_channels` gives each protocol a logical channel of its
+//! own instead, identified by a `ChannelId`: the messages received are queued by channel, so
+//! that a protocol whose handler does not keep up, the relay of large blocks for instance,
+//! only drops its own messages and does not hold back the pings sent after them.
+
+use crate::error::Error;
+use crate::network::transport::{ConnectionSender, MPSCConnection};
+use futures::channel::mpsc::{self, Receiver, UnboundedReceiver};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Multiplexed<A, B> {
+    First(A),
+    Second(B),
+}
+
+/// Routes the messages of the stream to the stream of their protocol, from a task of their
+/// own. Both streams end with the given one. The messages of a protocol whose stream was
+/// dropped are dropped as well.
+pub fn demultiplex<A, B, S>(stream: S) -> (UnboundedReceiver<A>, UnboundedReceiver<B>)
+where
+    A: Send + 'static,
+    B: Send + 'static,
+    S: Stream<Item = Multiplexed<A, B>> + Send + Unpin + 'static,
+{
+    let (first_sender, first_receiver) = mpsc::unbounded();
+    let (second_sender, second_receiver) = mpsc::unbounded();
+    let mut stream = stream;
+    tokio::spawn(async move {
+        while let Some(message) = stream.next().await {
+            let sent = match message {
+                Multiplexed::First(message) => first_sender.unbounded_send(message).is_ok(),
+                Multiplexed::Second(message) => second_sender.unbounded_send(message).is_ok(),
+            };
+
+            if !sent && first_sender.is_closed() && second_sender.is_closed() {
+                return;
+            }
+        }
+    });
+
+    (first_receiver, second_receiver)
+}
+
+/// The identifier of a logical channel of a connection, one per protocol.
+pub type ChannelId = u8;
+
+/// A message sent on one of the logical channels of a connection.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelMessage<M> {
+    pub channel: ChannelId,
+    pub message: M,
+}
+
+/// Sends the messages of a logical channel over the connection it shares with the others.
+#[derive(Debug)]
+pub struct ChannelSender<M> {
+    channel: ChannelId,
+    sender: ConnectionSender<ChannelMessage<M>>,
+}
+
+impl<M> Clone for ChannelSender<M> {
+    fn clone(&self) -> Self {
+        ChannelSender {
+            channel: self.channel,
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<M> ChannelSender<M> {
+    pub fn channel(&self) -> ChannelId {
+        self.channel
+    }
+
+    /// The address of the peer at the other end of the connection.
+    pub fn peer_id(&self) -> u32 {
+        self.sender.peer_id()
+    }
+
+    /// Sends the message on the channel, see `ConnectionSender::try_send`: the connection
+    /// itself is shared with the other channels.
+    pub fn try_send(&mut self, message: M) -> Result<(), Error>
+    where
+        M: Clone + Send + 'static,
+    {
+        let channel = self.channel;
+        self.sender.try_send(ChannelMessage { channel, message })
+    }
+}
+
+/// The messages received on a logical channel, queued apart from the ones of the others.
+#[derive(Debug)]
+pub struct ChannelReceiver<M> {
+    channel: ChannelId,
+    receiver: Receiver<M>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl<M> ChannelReceiver<M> {
+    pub fn channel(&self) -> ChannelId {
+        self.channel
+    }
+
+    /// The messages of the channel dropped because its queue was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<M> Stream for ChannelReceiver<M> {
+    type Item = M;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<M>> {
+        Pin::new(&mut self.get_mut().receiver).poll_next(cx)
+    }
+}
+
+/// Splits the connection into the given logical channels, in the same order. A task routes the
+/// messages received to the queue of their channel, which holds the given capacity and one more
+/// message, like the channels of futures: a message received while the queue of its channel is
+/// full is dropped and counted, without holding back the other channels. The messages of the
+/// channels not opened are dropped too. Both ends of a connection should open the same
+/// channels.
+pub fn open_channels<M>(
+    connection: MPSCConnection<ChannelMessage<M>>,
+    channels: &[ChannelId],
+    capacity: usize,
+) -> Vec<(ChannelSender<M>, ChannelReceiver<M>)>
+where
+    M: Send + 'static,
+{
+    let (sender, mut receiver) = connection.split();
+    let mut queues = HashMap::new();
+    let mut opened = Vec::with_capacity(channels.len());
+    for &channel in channels {
+        let (queue_sender, queue_receiver) = mpsc::channel(capacity);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        queues.insert(channel, (queue_sender, dropped.clone()));
+        opened.push((
+            ChannelSender { channel, sender: sender.clone() },
+            ChannelReceiver { channel, receiver: queue_receiver, dropped },
+        ));
+    }
+
+    tokio::spawn(async move {
+        while let Some(ChannelMessage { channel, message }) = receiver.next().await {
+            if let Some((queue, dropped)) = queues.get_mut(&channel) {
+                match queue.try_send(message) {
+                    Ok(()) => {}
+                    Err(err) if err.is_full() => {
+                        dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(_disconnected) => {
+                        queues.remove(&channel);
+                    }
+                }
+            }
+
+            // Every channel was dropped by the node.
+            if queues.is_empty() {
+                return;
+            }
+        }
+    });
+
+    opened
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, MockClock, RuntimeConfig};
+    use crate::network::transport::{ConnectionEvent, MPSCTransport};
+    use futures::{future, stream};
+
+    #[test]
+    fn routes_the_messages_to_their_protocol() {
+        MockClock.runtime(&RuntimeConfig::new()).block_on(async move {
+            let messages = vec![
+                Multiplexed::First(1u32),
+                Multiplexed::Second("ping"),
+                Multiplexed::First(2),
+                Multiplexed::Second("pong"),
+            ];
+            let (first, second) = demultiplex(stream::iter(messages));
+
+            assert_eq!(vec![1, 2], first.collect::<Vec<u32>>().await);
+            assert_eq!(vec!["ping", "pong"], second.collect::<Vec<&str>>().await);
+        });
+    }
+
+    const BLOCKS: ChannelId = 0;
+    const PINGS: ChannelId = 1;
+
+    #[test]
+    fn queues_the_messages_of_each_channel_apart() {
+        MockClock.runtime(&RuntimeConfig::new()).block_on(async move {
+            let transport = MPSCTransport::new(0);
+            let mut seeding_transport = MPSCTransport::new(1);
+            seeding_transport.include_seed(transport.address().clone());
+            let mut seed_events = Box::pin(transport.run());
+            let mut events = Box::pin(seeding_transport.run());
+            let connections = match future::join(seed_events.next(), events.next()).await {
+                (Some(Ok(ConnectionEvent::Opened(seed))), Some(Ok(ConnectionEvent::Opened(seeding)))) => {
+                    (seed, seeding)
+                }
+                _ => panic!("Expected a new connection"),
+            };
+            let mut receiving = open_channels(connections.0, &[BLOCKS, PINGS], 0).into_iter();
+            let mut sending = open_channels(connections.1, &[BLOCKS, PINGS], 0).into_iter();
+            let (mut block_sender, _block_receiver) = sending.next().unwrap();
+            let (mut ping_sender, _ping_receiver) = sending.next().unwrap();
+            let (_block_sender, mut block_receiver) = receiving.next().unwrap();
+            let (_ping_sender, mut ping_receiver) = receiving.next().unwrap();
+
+            // The blocks are not handled yet: their queue only holds the first one.
+            for block in ["first block", "second block", "third block"] {
+                block_sender.try_send(block).unwrap();
+            }
+            ping_sender.try_send("ping").unwrap();
+
+            assert_eq!(Some("ping"), ping_receiver.next().await);
+            assert_eq!(2, block_receiver.dropped());
+            assert_eq!(0, ping_receiver.dropped());
+            assert_eq!(Some("first block"), block_receiver.next().await);
+        });
+    }
+}
@@ -0,0 +1,222 @@
+//! Requests and responses over the connections of the network. A request is tagged with an
+//! identifier that its response carries back, so that a node can wait for the response to
+//! each of its requests, whatever the order they are answered in.
+
+use crate::error::Error;
+use crate::network::transport::ConnectionSender;
+use futures::channel::oneshot;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::time::{self, Sleep};
+
+pub type RequestId = u64;
+
+#[derive(Clone, Debug)]
+pub struct Request<T> {
+    id: RequestId,
+    body: T,
+}
+
+impl<T> Request<T> {
+    pub fn id(&self) -> RequestId {
+        self.id
+    }
+
+    pub fn body(&self) -> &T {
+        &self.body
+    }
+
+    /// The response to this request.
+    pub fn respond<R>(&self, body: R) -> Response<R> {
+        Response { id: self.id, body }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Response<T> {
+    id: RequestId,
+    body: T,
+}
+
+impl<T> Response<T> {
+    pub fn id(&self) -> RequestId {
+        self.id
+    }
+
+    pub fn body(&self) -> &T {
+        &self.body
+    }
+}
+
+struct PendingRequests<T> {
+    next_id: RequestId,
+    senders: HashMap<RequestId, oneshot::Sender<T>>,
+}
+
+/// Sends requests expecting responses of type `T` and matches the responses received with
+/// them. The clones of a client share its pending requests.
+pub struct RpcClient<T> {
+    pending: Arc<Mutex<PendingRequests<T>>>,
+    timeout: Duration,
+}
+
+impl<T> Clone for RpcClient<T> {
+    fn clone(&self) -> Self {
+        RpcClient {
+            pending: self.pending.clone(),
+            timeout: self.timeout,
+        }
+    }
+}
+
+impl<T> RpcClient<T> {
+    /// A client giving up on the requests not answered within the given timeout.
+    pub fn new(timeout: Duration) -> RpcClient<T> {
+        RpcClient {
+            pending: Arc::new(Mutex::new(PendingRequests {
+                next_id: 0,
+                senders: HashMap::new(),
+            })),
+            timeout,
+        }
+    }
+
+    /// Sends a request through the given connection, wrapped in a message by `into_message`.
+    /// Fails right away if the request could not be sent.
+    pub fn call<M, B, F>(
+        &self,
+        sender: &mut ConnectionSender<M>,
+        body: B,
+        into_message: F,
+    ) -> Result<ResponseFuture<T>, Error>
+    where
+        M: Clone + Send + 'static,
+        F: FnOnce(Request<B>) -> M,
+    {
+        let (request, response) = self.request(body);
+        sender.try_send(into_message(request))?;
+        Ok(response)
+    }
+
+    /// A new request and the future of its response, for the requests not sent with `call`.
+    /// Like `call`, must be called from a Tokio runtime, whose timer times the request out.
+    pub fn request<B>(&self, body: B) -> (Request<B>, ResponseFuture<T>) {
+        let (sender, receiver) = oneshot::channel();
+
+        let mut pending = self.pending.lock().expect("Poisoned RPC client");
+        let id = pending.next_id;
+        pending.next_id = pending.next_id.wrapping_add(1);
+        pending.senders.insert(id, sender);
+
+        let response = ResponseFuture {
+            id,
+            receiver,
+            deadline: Box::pin(time::sleep(self.timeout)),
+            pending: self.pending.clone(),
+        };
+        (Request { id, body }, response)
+    }
+
+    /// Completes the request answered by the given response. Returns false if the response
+    /// matches no pending request, for instance because the request timed out.
+    pub fn receive(&self, response: Response<T>) -> bool {
+        let sender = self
+            .pending
+            .lock()
+            .expect("Poisoned RPC client")
+            .senders
+            .remove(&response.id);
+
+        match sender {
+            Some(sender) => sender.send(response.body).is_ok(),
+            None => false,
+        }
+    }
+
+    /// The number of requests waiting for a response.
+    pub fn pending(&self) -> usize {
+        self.pending.lock().expect("Poisoned RPC client").senders.len()
+    }
+}
+
+/// Yields the response to a request, or fails with `Error::Timeout` if it is not received in
+/// time. Dropping it abandons the request.
+pub struct ResponseFuture<T> {
+    id: RequestId,
+    receiver: oneshot::Receiver<T>,
+    deadline: Pin<Box<Sleep>>,
+    pending: Arc<Mutex<PendingRequests<T>>>,
+}
+
+impl<T> ResponseFuture<T> {
+    pub fn id(&self) -> RequestId {
+        self.id
+    }
+}
+
+impl<T> Future for ResponseFuture<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, Error>> {
+        match Pin::new(&mut self.receiver).poll(cx) {
+            Poll::Ready(Ok(body)) => return Poll::Ready(Ok(body)),
+            // Only a dropped future removes its pending request without answering it.
+            Poll::Ready(Err(_canceled)) => return Poll::Ready(Err(Error::Disconnected)),
+            Poll::Pending => {}
+        }
+
+        match self.deadline.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Error::Timeout(self.id))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for ResponseFuture<T> {
+    fn drop(&mut self) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.senders.remove(&self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::runtime::Runtime;
+
+    #[test]
+    fn matches_responses_with_requests() {
+        let client: RpcClient<u32> = RpcClient::new(Duration::from_secs(5));
+        let runtime = Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let (first_request, first_response) = client.request("first");
+        let (second_request, second_response) = client.request("second");
+        assert_eq!(2, client.pending());
+
+        // Answered in the reverse order.
+        assert!(client.receive(second_request.respond(2)));
+        assert!(client.receive(first_request.respond(1)));
+        assert!(!client.receive(first_request.respond(1)));
+        assert_eq!(0, client.pending());
+
+        assert_eq!(Ok(1), runtime.block_on(first_response));
+        assert_eq!(Ok(2), runtime.block_on(second_response));
+    }
+
+    #[test]
+    fn times_out_unanswered_requests() {
+        let client: RpcClient<u32> = RpcClient::new(Duration::from_millis(10));
+        let runtime = Runtime::new().unwrap();
+        let _guard = runtime.enter();
+        let (request, response) = client.request(());
+
+        assert_eq!(Err(Error::Timeout(request.id())), runtime.block_on(response));
+        assert_eq!(0, client.pending());
+        assert!(!client.receive(request.respond(1)));
+    }
+}
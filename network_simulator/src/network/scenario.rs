@@ -0,0 +1,181 @@
+//! Timed events changing a network while it runs, so that an experiment is described by a
+//! file rather than coded. A scenario is written in TOML:
+//!
+//! ```toml
+//! [[events]]
+//! at_secs = 10
+//! action = "partition"
+//! groups = [[0, 1, 2, 3], [4, 5, 6, 7]]
+//!
+//! [[events]]
+//! at_secs = 20
+//! action = "kill"
+//! node = 5
+//!
+//! [[events]]
+//! at_secs = 25
+//! action = "set_latency"
+//! millis = 500
+//!
+//! [[events]]
+//! at_secs = 30
+//! action = "heal"
+//! ```
+
+use crate::error::Error;
+use crate::network::conditions::NetworkConditions;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tokio::task::AbortHandle;
+use tokio::time::{self, Instant};
+
+/// What happens to the network at some point of a scenario.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    /// Splits the network in the given groups of nodes, see `NetworkConditions::partition`.
+    Partition { groups: Vec<Vec<u32>> },
+    /// Ends the partition.
+    Heal,
+    /// Stops the node with the given address, as if it crashed: its peers only notice it when
+    /// sending it a message.
+    Kill { node: u32 },
+    /// Delays every message sent from now on by the given number of milliseconds.
+    SetLatency { millis: u64 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimedEvent {
+    /// When the event happens, from the start of the network.
+    pub at: Duration,
+    pub action: Action,
+}
+
+#[derive(Deserialize)]
+struct RawTimedEvent {
+    at_secs: f64,
+    #[serde(flatten)]
+    action: Action,
+}
+
+#[derive(Deserialize)]
+struct RawScenario {
+    #[serde(default)]
+    events: Vec<RawTimedEvent>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scenario {
+    events: Vec<TimedEvent>,
+}
+
+impl Scenario {
+    pub fn new() -> Scenario {
+        Scenario::default()
+    }
+
+    /// Adds an event happening at the given time.
+    pub fn with_event(mut self, at: Duration, action: Action) -> Scenario {
+        self.events.push(TimedEvent { at, action });
+        self
+    }
+
+    pub fn from_toml(toml: &str) -> Result<Scenario, Error> {
+        let raw_scenario: RawScenario =
+            toml::from_str(toml).map_err(|err| Error::InvalidScenario(err.to_string()))?;
+
+        raw_scenario
+            .events
+            .into_iter()
+            .try_fold(Scenario::new(), |scenario, raw_event| {
+                let at = Duration::try_from_secs_f64(raw_event.at_secs).map_err(|_err| {
+                    Error::InvalidScenario(format!("Invalid event time: {}", raw_event.at_secs))
+                })?;
+                Ok(scenario.with_event(at, raw_event.action))
+            })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Scenario, Error> {
+        let toml = fs::read_to_string(path).map_err(|err| Error::InvalidScenario(err.to_string()))?;
+        Scenario::from_toml(&toml)
+    }
+
+    /// The events, in the order they happen.
+    pub fn events(&self) -> Vec<&TimedEvent> {
+        let mut events: Vec<&TimedEvent> = self.events.iter().collect();
+        events.sort_by_key(|event| event.at);
+        events
+    }
+
+    /// Applies the events when their time comes. The tasks of the nodes are indexed by
+    /// address.
+    pub(crate) async fn play(self, conditions: NetworkConditions, nodes: Vec<AbortHandle>) {
+        let start = Instant::now();
+
+        for event in self.events() {
+            time::sleep_until(start + event.at).await;
+            info!("Scenario at {:?}: {:?}", event.at, event.action);
+
+            match event.action {
+                Action::Partition { ref groups } => conditions.partition(groups),
+                Action::Heal => conditions.heal(),
+                Action::Kill { node } => match nodes.get(node as usize) {
+                    Some(node) => node.abort(),
+                    None => warn!("No node {} to kill", node),
+                },
+                Action::SetLatency { millis } => conditions.set_latency(Duration::from_millis(millis)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_scenario_file() {
+        let scenario = Scenario::from_toml(
+            r#"
+            [[events]]
+            at_secs = 20
+            action = "kill"
+            node = 5
+
+            [[events]]
+            at_secs = 10
+            action = "partition"
+            groups = [[0, 1], [2, 3]]
+
+            [[events]]
+            at_secs = 25.5
+            action = "set_latency"
+            millis = 500
+            "#,
+        )
+        .unwrap();
+
+        let expected = Scenario::new()
+            .with_event(Duration::from_secs(20), Action::Kill { node: 5 })
+            .with_event(
+                Duration::from_secs(10),
+                Action::Partition {
+                    groups: vec![vec![0, 1], vec![2, 3]],
+                },
+            )
+            .with_event(Duration::from_millis(25500), Action::SetLatency { millis: 500 });
+        assert_eq!(expected, scenario);
+        assert_eq!(Duration::from_secs(10), scenario.events()[0].at);
+    }
+
+    #[test]
+    fn rejects_invalid_events() {
+        let unknown_action = "[[events]]\nat_secs = 1\naction = \"explode\"";
+        assert!(Scenario::from_toml(unknown_action).is_err());
+
+        let negative_time = "[[events]]\nat_secs = -1\naction = \"heal\"";
+        assert!(Scenario::from_toml(negative_time).is_err());
+    }
+}
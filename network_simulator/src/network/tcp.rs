@@ -0,0 +1,476 @@
+//! Connections between the nodes of a network spread over several processes.
+//!
+//! Every process builds the same network, from the same topology and seed, but only runs the
+//! nodes of its own range of addresses. A `TcpBridge` given to the network carries their
+//! connections to the nodes of the other processes over TCP: a node initiating a connection to
+//! a node of another process reaches a proxy, which opens a TCP connection to the bridge of
+//! that process, and the bridge opens the connection to its node on behalf of the initiator.
+//! Both nodes then see an `MPSCConnection` like any other, closed when either of them closes
+//! it or when the TCP connection breaks.
+//!
+//! The messages are serialized by a `TraceCodec`, the one writing the trace files, and sent as
+//! frames of their length followed by their payload. The messages the codec leaves out only
+//! reach the nodes of the same process. The conditions of the network, latency included, still
+//! apply on top of the ones of the real connections, by the sender.
+
+use crate::network::trace::TraceCodec;
+use crate::network::transport::{MPSCAddress, TransportMessage};
+use futures::channel::mpsc::{self, Receiver, Sender, UnboundedReceiver};
+use futures::channel::oneshot;
+use futures::future;
+use futures::{Future, StreamExt};
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// The largest frame accepted, so that a corrupted length does not allocate gigabytes.
+const MAX_FRAME_LEN: u32 = 64 * 1024 * 1024;
+
+/// Carries the connections between the nodes hosted by this process and the ones of the other
+/// processes, see the module documentation.
+pub struct TcpBridge<M> {
+    listener: StdTcpListener,
+    hosted: Range<u32>,
+    /// The addresses of the bridges of the other processes, by the range of nodes they host.
+    remote_nodes: Vec<(Range<u32>, SocketAddr)>,
+    codec: Arc<dyn TraceCodec<M> + Send + Sync>,
+    /// The proxies of the nodes of other processes the hosted nodes connect to, and their
+    /// connection requests, by address.
+    proxies: HashMap<u32, (MPSCAddress<M>, UnboundedReceiver<TransportMessage<M>>)>,
+}
+
+impl<M> TcpBridge<M>
+where
+    M: Clone + Send + 'static,
+{
+    /// Listens on the given address for the connections of the other processes to the nodes of
+    /// the given range, the ones this process hosts. Port 0 picks any free port, see
+    /// `local_addr`.
+    pub fn bind<C>(address: SocketAddr, hosted: Range<u32>, codec: C) -> io::Result<TcpBridge<M>>
+    where
+        C: TraceCodec<M> + Send + Sync + 'static,
+    {
+        let listener = StdTcpListener::bind(address)?;
+        // Required to hand it over to Tokio.
+        listener.set_nonblocking(true)?;
+        Ok(TcpBridge {
+            listener,
+            hosted,
+            remote_nodes: vec![],
+            codec: Arc::new(codec),
+            proxies: HashMap::new(),
+        })
+    }
+
+    /// The address the other processes connect to, for the orchestrator to give it to them.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// The addresses of the nodes hosted by this process.
+    pub fn hosted(&self) -> Range<u32> {
+        self.hosted.clone()
+    }
+
+    /// The nodes of the given range are hosted by the process whose bridge listens on the
+    /// given address.
+    pub fn with_remote_nodes(mut self, nodes: Range<u32>, address: SocketAddr) -> Self {
+        self.remote_nodes.push((nodes, address));
+        self
+    }
+
+    /// Whether the node with the given address runs in this process.
+    pub fn hosts(&self, address_id: u32) -> bool {
+        self.hosted.contains(&address_id)
+    }
+
+    /// The address standing for the given node of another process, for the hosted nodes to
+    /// connect to it.
+    pub(crate) fn proxy(&mut self, address_id: u32) -> MPSCAddress<M> {
+        // Every hosted node connecting to the node shares the same proxy.
+        let (address, _requests) = self
+            .proxies
+            .entry(address_id)
+            .or_insert_with(|| MPSCAddress::proxy(address_id));
+        address.clone()
+    }
+
+    /// Carries the connections of the hosted nodes, given their addresses, until the runtime
+    /// stops. The connections buffer the given number of messages, like the ones of the
+    /// network.
+    pub(crate) fn serve(
+        self,
+        addresses: HashMap<u32, MPSCAddress<M>>,
+        channel_capacity: usize,
+    ) -> impl Future<Output = ()> + Send {
+        let TcpBridge {
+            listener,
+            remote_nodes,
+            codec,
+            proxies,
+            ..
+        } = self;
+
+        async move {
+            for (address_id, (_address, requests)) in proxies {
+                match remote_nodes.iter().find(|(nodes, _address)| nodes.contains(&address_id)) {
+                    Some((_nodes, socket_address)) => {
+                        let proxy = Proxy {
+                            address_id,
+                            socket_address: *socket_address,
+                            codec: codec.clone(),
+                            channel_capacity,
+                        };
+                        tokio::spawn(proxy.run(requests));
+                    }
+                    None => warn!("No process hosts the node {}", address_id),
+                }
+            }
+
+            let listener = match TcpListener::from_std(listener) {
+                Ok(listener) => listener,
+                Err(err) => {
+                    error!("Could not listen for the nodes of the other processes: {}", err);
+                    return;
+                }
+            };
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _peer_address)) => {
+                        tokio::spawn(accept(stream, addresses.clone(), codec.clone(), channel_capacity));
+                    }
+                    Err(err) => debug!("Could not accept a connection from another process: {}", err),
+                }
+            }
+        }
+    }
+}
+
+/// Stands for a node of another process, opening a TCP connection to it for each hosted node
+/// connecting to it.
+struct Proxy<M> {
+    address_id: u32,
+    socket_address: SocketAddr,
+    codec: Arc<dyn TraceCodec<M> + Send + Sync>,
+    channel_capacity: usize,
+}
+
+impl<M> Proxy<M>
+where
+    M: Clone + Send + 'static,
+{
+    async fn run(self, mut requests: UnboundedReceiver<TransportMessage<M>>) {
+        // Dropped when their node closes the connection.
+        let mut closings = HashMap::new();
+        while let Some(request) = requests.next().await {
+            match request {
+                TransportMessage::Init(node_address, node_sender) => {
+                    let (closing, closed) = oneshot::channel::<()>();
+                    closings.insert(*node_address.id(), closing);
+                    let closed = async move {
+                        let _ = closed.await;
+                    };
+                    tokio::spawn(self.open(node_address, node_sender, closed));
+                }
+                TransportMessage::Disconnect(node_id) => {
+                    closings.remove(&node_id);
+                }
+                TransportMessage::Ack(node_id, _sender) => {
+                    debug!("Unexpected ack from {} to the proxy of {}", node_id, self.address_id)
+                }
+            }
+        }
+    }
+
+    /// Connects the node to the one of the other process, then relays their messages.
+    fn open<F>(&self, node_address: MPSCAddress<M>, node_sender: Sender<M>, closed: F) -> impl Future<Output = ()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (peer_id, socket_address) = (self.address_id, self.socket_address);
+        let (codec, channel_capacity) = (self.codec.clone(), self.channel_capacity);
+        async move {
+            let node_id = *node_address.id();
+            let mut stream = match TcpStream::connect(socket_address).await {
+                Ok(stream) => stream,
+                Err(err) => {
+                    debug!("Could not connect {} to {} at {}: {}", node_id, peer_id, socket_address, err);
+                    return;
+                }
+            };
+            let _ = stream.set_nodelay(true);
+            if let Err(err) = write_frame(&mut stream, &handshake(node_id, peer_id)).await {
+                debug!("Could not connect {} to {} at {}: {}", node_id, peer_id, socket_address, err);
+                return;
+            }
+
+            let (peer_sender, peer_receiver) = mpsc::channel(channel_capacity);
+            if node_address.notify(TransportMessage::Ack(peer_id, peer_sender)).is_err() {
+                return;
+            }
+            if let Closed::ByPeer = relay(stream, node_sender, peer_receiver, closed, codec).await {
+                let _ = node_address.notify(TransportMessage::Disconnect(peer_id));
+            }
+        }
+    }
+}
+
+/// Opens the connection another process asks for to the hosted node it names, on behalf of
+/// its own node, then relays their messages.
+async fn accept<M>(
+    mut stream: TcpStream,
+    addresses: HashMap<u32, MPSCAddress<M>>,
+    codec: Arc<dyn TraceCodec<M> + Send + Sync>,
+    channel_capacity: usize,
+) where
+    M: Clone + Send + 'static,
+{
+    let _ = stream.set_nodelay(true);
+    let (peer_id, node_id) = match read_frame(&mut stream).await {
+        Ok(Some(frame)) if frame.len() == HANDSHAKE_LEN => (
+            u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]),
+            u32::from_be_bytes([frame[4], frame[5], frame[6], frame[7]]),
+        ),
+        _ => {
+            debug!("Invalid handshake from another process");
+            return;
+        }
+    };
+    let node_address = match addresses.get(&node_id) {
+        Some(node_address) => node_address,
+        None => {
+            debug!("No node {} in this process for {} to connect to", node_id, peer_id);
+            return;
+        }
+    };
+
+    let (peer_address, mut requests) = MPSCAddress::proxy(peer_id);
+    let (peer_sender, peer_receiver) = mpsc::channel(channel_capacity);
+    if node_address.notify(TransportMessage::Init(peer_address, peer_sender)).is_err() {
+        return;
+    }
+    let node_sender = match requests.next().await {
+        Some(TransportMessage::Ack(_node_id, node_sender)) => node_sender,
+        _ => return,
+    };
+    let closed = async move {
+        while let Some(request) = requests.next().await {
+            if let TransportMessage::Disconnect(_node_id) = request {
+                return;
+            }
+        }
+    };
+    if let Closed::ByPeer = relay(stream, node_sender, peer_receiver, closed, codec).await {
+        let _ = node_address.notify(TransportMessage::Disconnect(peer_id));
+    }
+}
+
+/// Which end closed a relayed connection.
+enum Closed {
+    ByNode,
+    ByPeer,
+}
+
+/// Relays the messages of the TCP connection to the hosted node and the ones the node sends
+/// to the TCP connection, until either end closes it. The messages the node does not keep up
+/// with are dropped, like the ones of a congested connection.
+async fn relay<M, F>(
+    stream: TcpStream,
+    mut node_sender: Sender<M>,
+    mut node_receiver: Receiver<M>,
+    closed: F,
+    codec: Arc<dyn TraceCodec<M> + Send + Sync>,
+) -> Closed
+where
+    M: Send + 'static,
+    F: Future<Output = ()> + Send + 'static,
+{
+    let (mut reader, mut writer) = stream.into_split();
+    let decoder = codec.clone();
+    let receiving = async move {
+        while let Ok(Some(frame)) = read_frame(&mut reader).await {
+            match decoder.decode(&frame) {
+                Ok(message) => {
+                    if let Err(err) = node_sender.try_send(message) {
+                        if err.is_disconnected() {
+                            return Closed::ByNode;
+                        }
+                    }
+                }
+                Err(err) => {
+                    warn!("Could not decode a message from another process: {}", err);
+                    break;
+                }
+            }
+        }
+        Closed::ByPeer
+    };
+    let sending = async move {
+        while let Some(message) = node_receiver.next().await {
+            if let Some(frame) = codec.encode(&message) {
+                if write_frame(&mut writer, &frame).await.is_err() {
+                    return Closed::ByPeer;
+                }
+            }
+        }
+        Closed::ByNode
+    };
+    let closing = async move {
+        closed.await;
+        Closed::ByNode
+    };
+
+    let ends: Vec<Pin<Box<dyn Future<Output = Closed> + Send>>> =
+        vec![Box::pin(receiving), Box::pin(sending), Box::pin(closing)];
+    // Dropping the TCP connection closes it for the other process as well.
+    let (closed_by, _index, _others) = future::select_all(ends).await;
+    closed_by
+}
+
+/// The first frame of a connection: the address of the initiating node, then the one of the
+/// node it connects to.
+fn handshake(node_id: u32, peer_id: u32) -> Vec<u8> {
+    let mut frame = node_id.to_be_bytes().to_vec();
+    frame.extend(peer_id.to_be_bytes());
+    frame
+}
+
+const HANDSHAKE_LEN: usize = 8;
+
+async fn write_frame<W>(writer: &mut W, payload: &[u8]) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let len = u32::try_from(payload.len())
+        .ok()
+        .filter(|len| *len <= MAX_FRAME_LEN)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Frame too large"))?;
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend(len.to_be_bytes());
+    frame.extend(payload);
+    writer.write_all(&frame).await
+}
+
+/// The payload of the next frame, none once the connection is closed.
+async fn read_frame<R>(reader: &mut R) -> io::Result<Option<Vec<u8>>>
+where
+    R: AsyncRead + Unpin,
+{
+    let len = match reader.read_u32().await {
+        Ok(len) => len,
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Frame of {} bytes", len)));
+    }
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload).await?;
+    Ok(Some(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::network::{ConnectionEvent, Network, Node, Topology};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    struct IdCodec;
+
+    impl TraceCodec<u32> for IdCodec {
+        fn encode(&self, message: &u32) -> Option<Vec<u8>> {
+            Some(message.to_be_bytes().to_vec())
+        }
+
+        fn decode(&self, payload: &[u8]) -> Result<u32, Error> {
+            payload
+                .try_into()
+                .map(u32::from_be_bytes)
+                .map_err(|_err| Error::InvalidTrace(format!("Invalid id of {} bytes", payload.len())))
+        }
+    }
+
+    /// Sends its id to each of its peers, and records the ids it receives.
+    struct GreetingNode {
+        id: u32,
+        greetings: Arc<Mutex<Vec<(u32, u32)>>>,
+    }
+
+    impl Node<u32> for GreetingNode {
+        type Output = ();
+
+        fn run<S>(self, mut connection_stream: S) -> Pin<Box<dyn Future<Output = ()> + Send>>
+        where
+            S: futures::Stream<Item = Result<ConnectionEvent<u32>, Error>> + Send + Unpin + 'static,
+        {
+            Box::pin(async move {
+                while let Some(event) = connection_stream.next().await {
+                    if let ConnectionEvent::Opened(connection) = event.expect("The transport failed") {
+                        let (mut sender, mut receiver) = connection.split();
+                        sender.try_send(self.id).expect("Could not send the greeting");
+                        let (id, greetings) = (self.id, self.greetings.clone());
+                        tokio::spawn(async move {
+                            if let Some(peer_id) = receiver.next().await {
+                                greetings.lock().unwrap().push((id, peer_id));
+                            }
+                            drop(sender);
+                        });
+                    }
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn connects_the_nodes_of_several_processes() {
+        let greetings = Arc::new(Mutex::new(vec![]));
+        let hosted = [0..2, 2..4];
+        let bridges: Vec<TcpBridge<u32>> = hosted
+            .iter()
+            .map(|nodes| TcpBridge::bind("127.0.0.1:0".parse().unwrap(), nodes.clone(), IdCodec).unwrap())
+            .collect();
+        let addresses: Vec<SocketAddr> = bridges.iter().map(|bridge| bridge.local_addr().unwrap()).collect();
+
+        // Each half of the ring runs in a process of its own, here a thread.
+        let processes: Vec<_> = bridges
+            .into_iter()
+            .enumerate()
+            .map(|(index, bridge)| {
+                let other = 1 - index;
+                let bridge = bridge.with_remote_nodes(hosted[other].clone(), addresses[other]);
+                let (node_greetings, greetings) = (greetings.clone(), greetings.clone());
+                thread::spawn(move || {
+                    let next_id = AtomicU32::new(0);
+                    Network::from_topology(4, 1, Topology::Ring).with_tcp_bridge(bridge).run_until(
+                        move || GreetingNode {
+                            id: next_id.fetch_add(1, Ordering::Relaxed),
+                            greetings: node_greetings.clone(),
+                        },
+                        Duration::from_secs(10),
+                        move || greetings.lock().unwrap().len() == 8,
+                    )
+                })
+            })
+            .collect();
+        for process in processes {
+            process.join().unwrap();
+        }
+
+        // Every node greeted both of its neighbours, whatever their process.
+        let mut greetings = greetings.lock().unwrap().clone();
+        greetings.sort();
+        assert_eq!(
+            vec![(0, 1), (0, 3), (1, 0), (1, 2), (2, 1), (2, 3), (3, 0), (3, 2)],
+            greetings
+        );
+    }
+}
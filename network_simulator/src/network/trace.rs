@@ -0,0 +1,365 @@
+//! Records the messages delivered while a network runs, to replay the ones a node received
+//! and see what it does with them, alone and deterministically. A trace is written to a text
+//! file, one message per line: when it was delivered in microseconds from the start of the
+//! network, its sender, its receiver, the hash of its payload and the payload in hexadecimal,
+//! encoded by a `TraceCodec`.
+
+use crate::clock::{self, Clock, MockClock, RuntimeConfig};
+use crate::error::Error;
+use crate::network::transport::MPSCTransport;
+use crate::network::{ConnectionEvent, Node};
+use futures::{SinkExt, StreamExt};
+use std::collections::BTreeSet;
+use std::fmt::{self, Write};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::time;
+
+/// The first line of a trace file, changed whenever its format changes.
+const TRACE_HEADER: &str = "netsim-trace 1";
+
+/// A message delivered to a node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TraceRecord<M> {
+    /// When the message was delivered, from the start of the network.
+    pub at: Duration,
+    pub sender_id: u32,
+    pub receiver_id: u32,
+    pub message: M,
+}
+
+impl<M> TraceRecord<M> {
+    /// The hash of the encoded message, none if it is not traced.
+    pub fn payload_hash<C: TraceCodec<M>>(&self, codec: &C) -> Option<u64> {
+        codec.encode(&self.message).map(|payload| payload_hash(&payload))
+    }
+}
+
+/// How the messages of a trace are written to its file and read back.
+pub trait TraceCodec<M> {
+    /// The payload of the message, none for the messages left out of the trace files.
+    fn encode(&self, message: &M) -> Option<Vec<u8>>;
+
+    fn decode(&self, payload: &[u8]) -> Result<M, Error>;
+}
+
+/// Records the messages delivered by the connections of a network, when they are delivered.
+pub struct Recorder<M> {
+    inner: Arc<Mutex<RecorderState<M>>>,
+}
+
+struct RecorderState<M> {
+    /// When the network started, by its clock.
+    start: Option<Instant>,
+    records: Vec<TraceRecord<M>>,
+}
+
+impl<M> Clone for Recorder<M> {
+    fn clone(&self) -> Self {
+        Recorder {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<M> Default for Recorder<M> {
+    fn default() -> Self {
+        Recorder {
+            inner: Arc::new(Mutex::new(RecorderState {
+                start: None,
+                records: vec![],
+            })),
+        }
+    }
+}
+
+impl<M> fmt::Debug for Recorder<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Recorder").field("records", &self.state().records.len()).finish()
+    }
+}
+
+impl<M> Recorder<M> {
+    pub fn new() -> Recorder<M> {
+        Recorder::default()
+    }
+
+    /// The messages delivered so far, in the order they were delivered.
+    pub fn trace(&self) -> Trace<M>
+    where
+        M: Clone,
+    {
+        Trace::new(self.state().records.clone())
+    }
+
+    /// Times the messages from now on, by the clock of the network running the caller.
+    pub(crate) fn start(&self) {
+        self.state().start = Some(clock::now());
+    }
+
+    pub(crate) fn record(&self, sender_id: u32, receiver_id: u32, message: M) {
+        let now = clock::now();
+        let mut state = self.state();
+        let at = state
+            .start
+            .map(|start| now.saturating_duration_since(start))
+            .unwrap_or_default();
+        state.records.push(TraceRecord {
+            at,
+            sender_id,
+            receiver_id,
+            message,
+        });
+    }
+
+    fn state(&self) -> MutexGuard<'_, RecorderState<M>> {
+        self.inner.lock().expect("Poisoned recorder")
+    }
+}
+
+/// The messages delivered while a network ran, in the order they were delivered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trace<M> {
+    records: Vec<TraceRecord<M>>,
+}
+
+impl<M> Trace<M> {
+    pub fn new(records: Vec<TraceRecord<M>>) -> Trace<M> {
+        Trace { records }
+    }
+
+    pub fn records(&self) -> &[TraceRecord<M>] {
+        &self.records
+    }
+
+    /// The messages delivered to the given node.
+    pub fn received_by(&self, node_id: u32) -> impl Iterator<Item = &TraceRecord<M>> {
+        self.records.iter().filter(move |record| record.receiver_id == node_id)
+    }
+
+    /// The messages the given node sent.
+    pub fn sent_by(&self, node_id: u32) -> impl Iterator<Item = &TraceRecord<M>> {
+        self.records.iter().filter(move |record| record.sender_id == node_id)
+    }
+
+    /// Writes the messages the codec encodes to a file, overwriting it.
+    pub fn write<P: AsRef<Path>, C: TraceCodec<M>>(&self, path: P, codec: &C) -> Result<(), Error> {
+        let mut file = String::from(TRACE_HEADER);
+        file.push('\n');
+        for record in &self.records {
+            let payload = match codec.encode(&record.message) {
+                Some(payload) => payload,
+                None => continue,
+            };
+            let _ = write!(
+                file,
+                "{} {} {} {:016x} ",
+                record.at.as_micros(),
+                record.sender_id,
+                record.receiver_id,
+                payload_hash(&payload)
+            );
+            for byte in payload {
+                let _ = write!(file, "{:02x}", byte);
+            }
+            file.push('\n');
+        }
+
+        fs::write(path, file).map_err(|err| Error::InvalidTrace(err.to_string()))
+    }
+
+    /// Reads a trace file, rejecting the payloads that do not match their hash.
+    pub fn read<P: AsRef<Path>, C: TraceCodec<M>>(path: P, codec: &C) -> Result<Trace<M>, Error> {
+        let file = fs::read_to_string(path).map_err(|err| Error::InvalidTrace(err.to_string()))?;
+        let mut lines = file.lines();
+        if lines.next() != Some(TRACE_HEADER) {
+            return Err(Error::InvalidTrace("Not a trace file".to_owned()));
+        }
+
+        let records = lines.enumerate().map(|(index, line)| {
+            read_record(line, codec).map_err(|reason| Error::InvalidTrace(format!("Line {}: {}", index + 2, reason)))
+        });
+        Ok(Trace::new(records.collect::<Result<_, _>>()?))
+    }
+}
+
+impl<M> Trace<M>
+where
+    M: Clone + Send + 'static,
+{
+    /// Runs the given node alone, connected to puppet peers sending it the messages it received
+    /// from them in this trace, at the same times, until the given duration passes. Returns the
+    /// messages the node sent them. The node runs on a virtual clock, so that a replay gives the
+    /// same result every time, unless the node itself is random.
+    pub fn replay<N>(&self, node_id: u32, node: N, for_duration: Duration) -> Trace<M>
+    where
+        N: Node<M> + Send + 'static,
+    {
+        let recorder = Recorder::new();
+        let mut transport = MPSCTransport::new(node_id).with_recorder(recorder.clone());
+        let peer_ids: BTreeSet<u32> = self
+            .received_by(node_id)
+            .map(|record| record.sender_id)
+            .chain(self.sent_by(node_id).map(|record| record.receiver_id))
+            .collect();
+        let peers: Vec<_> = peer_ids
+            .into_iter()
+            .map(|peer_id| {
+                let peer = MPSCTransport::new(peer_id);
+                transport.include_seed(peer.address().clone());
+                let messages: Vec<_> = self
+                    .received_by(node_id)
+                    .filter(|record| record.sender_id == peer_id)
+                    .map(|record| (record.at, record.message.clone()))
+                    .collect();
+                (peer, messages)
+            })
+            .collect();
+
+        let started_recorder = recorder.clone();
+        MockClock.runtime(&RuntimeConfig::new()).block_on(async move {
+            started_recorder.start();
+            let start = time::Instant::now();
+            for (peer, messages) in peers {
+                tokio::spawn(async move {
+                    let mut connection_stream = Box::pin(peer.run());
+                    let connection = match connection_stream.next().await {
+                        Some(Ok(ConnectionEvent::Opened(connection))) => connection,
+                        _ => return,
+                    };
+                    let (mut sender, mut receiver) = connection.split();
+                    tokio::spawn(async move { while receiver.next().await.is_some() {} });
+                    for (at, message) in messages {
+                        time::sleep_until(start + at).await;
+                        if sender.send(message).await.is_err() {
+                            return;
+                        }
+                    }
+                    // The connection stays open until the end of the replay.
+                    while connection_stream.next().await.is_some() {}
+                });
+            }
+
+            let _ = time::timeout(for_duration, node.run(Box::pin(transport.run()))).await;
+        });
+
+        Trace::new(recorder.trace().sent_by(node_id).cloned().collect())
+    }
+}
+
+/// The FNV-1a hash of a payload, which does not change across builds.
+fn payload_hash(payload: &[u8]) -> u64 {
+    payload.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn read_record<M, C: TraceCodec<M>>(line: &str, codec: &C) -> Result<TraceRecord<M>, String> {
+    let fields: Vec<&str> = line.split(' ').collect();
+    if fields.len() != 5 {
+        return Err(format!("Expected 5 fields, got {}", fields.len()));
+    }
+    let number = |field: &str| field.parse::<u64>().map_err(|err| format!("Invalid number {}: {}", field, err));
+    let id = |field: &str| field.parse::<u32>().map_err(|err| format!("Invalid node {}: {}", field, err));
+
+    let expected_hash = u64::from_str_radix(fields[3], 16).map_err(|err| format!("Invalid hash: {}", err))?;
+    let payload = (0..fields[4].len())
+        .step_by(2)
+        .map(|index| fields[4].get(index..index + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| "Invalid payload".to_owned())?;
+    if payload_hash(&payload) != expected_hash {
+        return Err("The payload does not match its hash".to_owned());
+    }
+
+    Ok(TraceRecord {
+        at: Duration::from_micros(number(fields[0])?),
+        sender_id: id(fields[1])?,
+        receiver_id: id(fields[2])?,
+        message: codec.decode(&payload).map_err(|err| err.to_string())?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{Network, Topology};
+    use futures::{Future, Stream};
+    use std::env;
+    use std::pin::Pin;
+
+    /// Traces the even numbers only.
+    struct EvenCodec;
+
+    impl TraceCodec<u32> for EvenCodec {
+        fn encode(&self, message: &u32) -> Option<Vec<u8>> {
+            Some(message.to_be_bytes().to_vec()).filter(|_payload| message.is_multiple_of(2))
+        }
+
+        fn decode(&self, payload: &[u8]) -> Result<u32, Error> {
+            let bytes = payload.try_into().map_err(|_err| Error::InvalidTrace("Not a number".to_owned()))?;
+            Ok(u32::from_be_bytes(bytes))
+        }
+    }
+
+    /// Sends 1 to its peers, then the double of every number it receives under 16.
+    struct DoublingNode;
+
+    impl Node<u32> for DoublingNode {
+        type Output = ();
+
+        fn run<S>(self, mut connection_stream: S) -> Pin<Box<dyn Future<Output = ()> + Send>>
+        where
+            S: Stream<Item = Result<ConnectionEvent<u32>, Error>> + Send + Unpin + 'static,
+        {
+            Box::pin(async move {
+                while let Some(Ok(event)) = connection_stream.next().await {
+                    if let ConnectionEvent::Opened(connection) = event {
+                        let (mut sender, mut receiver) = connection.split();
+                        tokio::spawn(async move {
+                            let _ = sender.try_send(1);
+                            while let Some(number) = receiver.next().await {
+                                if number < 16 {
+                                    let _ = sender.try_send(number * 2);
+                                }
+                            }
+                        });
+                    }
+                }
+            })
+        }
+    }
+
+    #[test]
+    fn replays_the_messages_a_node_received() {
+        let recorder = Recorder::new();
+        let network = Network::from_topology(2, 1, Topology::Ring)
+            .with_clock(MockClock)
+            .with_recorder(recorder.clone());
+        network.conditions().set_latency(Duration::from_millis(10));
+        network.run(|| DoublingNode, Duration::from_secs(1));
+
+        let trace = recorder.trace();
+        let received: Vec<_> = trace.received_by(1).map(|record| record.message).collect();
+        assert_eq!(vec![1, 2, 4, 8, 16], received);
+        assert_eq!(Duration::from_millis(10), trace.records()[0].at);
+
+        let path = env::temp_dir().join(format!("netsim-{}.trace", std::process::id()));
+        trace.write(&path, &EvenCodec).unwrap();
+        let read_trace = Trace::read(&path, &EvenCodec).unwrap();
+        fs::remove_file(&path).unwrap();
+        let read_received: Vec<_> = read_trace.received_by(1).map(|record| record.message).collect();
+        assert_eq!(vec![2, 4, 8, 16], read_received);
+
+        // Node 1 answers the same numbers, without the latency of the network.
+        let replayed = trace.replay(1, DoublingNode, Duration::from_secs(1));
+        let sent: Vec<_> = replayed.records().iter().map(|record| (record.at, record.message)).collect();
+        let expected_sent: Vec<_> = trace
+            .sent_by(1)
+            .map(|record| (record.at - Duration::from_millis(10), record.message))
+            .collect();
+        assert_eq!(expected_sent, sent);
+    }
+}
@@ -1,21 +1,53 @@
-use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
-use futures::Stream;
-use std::collections::HashMap;
+use crate::error::Error;
+use crate::network::bandwidth::BandwidthMeter;
+use crate::network::conditions::NetworkConditions;
+use crate::network::control::Controller;
+use crate::network::trace::Recorder;
+use futures::channel::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use futures::{future, Sink, Stream, StreamExt};
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// How many messages a connection buffers before its sender is congested.
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 64;
 
 #[derive(Debug)]
-enum TransportMessage<M> {
-    Init(MPSCAddress<M>, UnboundedSender<M>),
-    Ack(u32, UnboundedSender<M>),
+pub(crate) enum TransportMessage<M> {
+    Init(MPSCAddress<M>, Sender<M>),
+    Ack(u32, Sender<M>),
+    /// The node with the given address closed its connection.
+    Disconnect(u32),
+}
+
+/// What happens to the connections of a transport.
+pub enum ConnectionEvent<M> {
+    Opened(MPSCConnection<M>),
+    /// The peer with the given address closed the connection. Its receiver may still yield
+    /// the messages sent before.
+    Closed(u32),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct MPSCAddress<M> {
     transport_sender: UnboundedSender<TransportMessage<M>>,
     id: u32, // Necessary for PartialEq
 }
 
+impl<M> Clone for MPSCAddress<M> {
+    fn clone(&self) -> Self {
+        MPSCAddress {
+            transport_sender: self.transport_sender.clone(),
+            id: self.id,
+        }
+    }
+}
+
 impl<M> Eq for MPSCAddress<M> {}
 
 impl<M> PartialEq for MPSCAddress<M> {
@@ -31,22 +63,303 @@ impl<M> Hash for MPSCAddress<M> {
 }
 
 impl<M> MPSCAddress<M> {
+    /// An address whose connection requests are handled by the returned receiver instead of a
+    /// transport, to stand for a node of another process.
+    pub(crate) fn proxy(id: u32) -> (MPSCAddress<M>, UnboundedReceiver<TransportMessage<M>>) {
+        let (transport_sender, transport_receiver) = mpsc::unbounded();
+        (MPSCAddress { transport_sender, id }, transport_receiver)
+    }
+
     pub fn id(&self) -> &u32 {
         &self.id
     }
+
+    /// Sends the message to the transport of this address, or fails if it is gone.
+    pub(crate) fn notify(&self, message: TransportMessage<M>) -> Result<(), Error> {
+        send(&self.transport_sender, message)
+    }
+}
+
+/// Counts the messages that could not be sent right away because the connection was full,
+/// and the ones lost to a partition, shared by every connection of a network.
+#[derive(Clone, Debug, Default)]
+pub struct SendMetrics {
+    dropped: Arc<AtomicUsize>,
+    delayed: Arc<AtomicUsize>,
+    lost: Arc<AtomicUsize>,
+}
+
+impl SendMetrics {
+    pub fn new() -> SendMetrics {
+        SendMetrics::default()
+    }
+
+    /// The messages given up by `ConnectionSender::try_send`.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// The messages the `Sink` of a `ConnectionSender` had to wait for.
+    pub fn delayed(&self) -> usize {
+        self.delayed.load(Ordering::Relaxed)
+    }
+
+    /// The messages sent to a peer on the other side of a partition.
+    pub fn lost(&self) -> usize {
+        self.lost.load(Ordering::Relaxed)
+    }
+}
+
+/// The sending half of a connection, bounded so that a slow node slows its peers down
+/// instead of accumulating their messages.
+///
+/// Either try to send and give up if the connection is congested, or use it as a `Sink`
+/// to wait for some room.
+///
+/// The messages follow the conditions of the network: the ones sent across a partition are
+/// lost, and with a latency, a task delivers them once it passed. A connection still full by
+/// then drops them. The messages sent while the network is paused are held back by its
+/// controller.
+#[derive(Debug)]
+pub struct ConnectionSender<M> {
+    inner: Sender<M>,
+    metrics: SendMetrics,
+    conditions: NetworkConditions,
+    controller: Controller,
+    recorder: Option<Recorder<M>>,
+    bandwidth: Option<BandwidthMeter<M>>,
+    /// The address of the peer, which receives the messages.
+    peer_address: MPSCAddress<M>,
+    /// The address of the node sending the messages.
+    address_id: u32,
+    /// Whether the `Sink` is waiting for some room, so that a delayed message is counted once.
+    waiting: bool,
+}
+
+impl<M> Clone for ConnectionSender<M> {
+    fn clone(&self) -> Self {
+        ConnectionSender {
+            inner: self.inner.clone(),
+            metrics: self.metrics.clone(),
+            conditions: self.conditions.clone(),
+            controller: self.controller.clone(),
+            recorder: self.recorder.clone(),
+            bandwidth: self.bandwidth.clone(),
+            peer_address: self.peer_address.clone(),
+            address_id: self.address_id,
+            waiting: false,
+        }
+    }
+}
+
+impl<M> ConnectionSender<M> {
+    fn new(inner: Sender<M>, shared: &SharedState<M>, peer_address: MPSCAddress<M>, address_id: u32) -> Self {
+        ConnectionSender {
+            inner,
+            metrics: shared.metrics.clone(),
+            conditions: shared.conditions.clone(),
+            controller: shared.controller.clone(),
+            recorder: shared.recorder.clone(),
+            bandwidth: shared.bandwidth.clone(),
+            peer_address,
+            address_id,
+            waiting: false,
+        }
+    }
+
+    /// The address of the peer at the other end of the connection.
+    pub fn peer_id(&self) -> u32 {
+        self.peer_address.id
+    }
+
+    /// Closes the connection: the peer is notified with a `ConnectionEvent::Closed`. The
+    /// node should then drop the receiver of the connection, and the peer its own sender.
+    pub fn disconnect(self) -> Result<(), Error> {
+        send(
+            &self.peer_address.transport_sender,
+            TransportMessage::Disconnect(self.address_id),
+        )
+    }
+
+    /// Sends a message if the connection has room for it. Otherwise, the message is dropped
+    /// and the send fails with `Error::Congested`.
+    ///
+    /// With a latency, must be called from a Tokio runtime, which delivers the message. While
+    /// the network is paused, the message is held back, so the send succeeds.
+    pub fn try_send(&mut self, message: M) -> Result<(), Error>
+    where
+        M: Clone + Send + 'static,
+    {
+        if !self.reaches_peer() {
+            return Ok(());
+        }
+        self.measure(&message);
+
+        let latency = self.conditions.latency_between(self.address_id, self.peer_address.id);
+        if latency > Duration::ZERO || self.controller.is_paused() {
+            return self.deliver_later(message, latency);
+        }
+
+        let recorded = self.recorded(&message);
+        self.inner.try_send(message).map_err(|err| {
+            if err.is_full() {
+                self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                Error::Congested
+            } else {
+                Error::Disconnected
+            }
+        })?;
+        self.record(recorded);
+        Ok(())
+    }
+
+    /// Whether the connection has room for a message. If not, the current task is woken up
+    /// once it does.
+    pub fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        let poll = self.inner.poll_ready(cx).map_err(Error::from);
+        if poll.is_pending() && !self.waiting {
+            self.metrics.delayed.fetch_add(1, Ordering::Relaxed);
+        }
+        self.waiting = poll.is_pending();
+        poll
+    }
+
+    /// Whether the peer is on the same side of the partition, if any. Counts the lost
+    /// messages otherwise.
+    fn reaches_peer(&self) -> bool {
+        let reaches_peer = self.conditions.can_reach(self.address_id, self.peer_address.id);
+        if !reaches_peer {
+            self.metrics.lost.fetch_add(1, Ordering::Relaxed);
+        }
+        reaches_peer
+    }
+
+    /// Counts the bytes of the message, if the network measures its bandwidth.
+    fn measure(&self, message: &M) {
+        if let Some(ref bandwidth) = self.bandwidth {
+            bandwidth.measure(self.address_id, self.peer_address.id, message);
+        }
+    }
+
+    /// Sends the message once the latency passed, through the controller of the network.
+    /// Messages sent close together may be received in a different order.
+    fn deliver_later(&self, message: M, latency: Duration) -> Result<(), Error>
+    where
+        M: Clone + Send + 'static,
+    {
+        if self.inner.is_closed() {
+            return Err(Error::Disconnected);
+        }
+
+        let mut inner = self.inner.clone();
+        let metrics = self.metrics.clone();
+        let recorded = self.recorded(&message);
+        let (address_id, peer_id) = (self.address_id, self.peer_address.id);
+        let deliver = move || match inner.try_send(message) {
+            Ok(()) => {
+                if let Some((recorder, message)) = recorded {
+                    recorder.record(address_id, peer_id, message);
+                }
+            }
+            Err(err) => {
+                if err.is_full() {
+                    metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        };
+        let controller = self.controller.clone();
+        if latency.is_zero() {
+            controller.deliver(address_id, peer_id, deliver);
+        } else {
+            tokio::spawn(async move {
+                tokio::time::sleep(latency).await;
+                controller.deliver(address_id, peer_id, deliver);
+            });
+        }
+        Ok(())
+    }
+
+    /// A copy of the message to record once it is delivered, if the network records a trace.
+    fn recorded(&self, message: &M) -> Option<(Recorder<M>, M)>
+    where
+        M: Clone,
+    {
+        self.recorder.clone().map(|recorder| (recorder, message.clone()))
+    }
+
+    fn record(&self, recorded: Option<(Recorder<M>, M)>) {
+        if let Some((recorder, message)) = recorded {
+            recorder.record(self.address_id, self.peer_address.id, message);
+        }
+    }
+}
+
+impl<M: Clone + Send + 'static> Sink<M> for ConnectionSender<M> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        self.get_mut().poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: M) -> Result<(), Error> {
+        let sender = self.get_mut();
+        if !sender.reaches_peer() {
+            return Ok(());
+        }
+        sender.measure(&message);
+
+        let latency = sender.conditions.latency_between(sender.address_id, sender.peer_address.id);
+        if latency > Duration::ZERO || sender.controller.is_paused() {
+            return sender.deliver_later(message, latency);
+        }
+
+        let recorded = sender.recorded(&message);
+        sender.inner.start_send(message).map_err(Error::from)?;
+        sender.record(recorded);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx).map_err(Error::from)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx).map_err(Error::from)
+    }
 }
 
 pub struct MPSCConnection<M> {
-    sender: UnboundedSender<M>,
-    receiver: UnboundedReceiver<M>,
+    sender: ConnectionSender<M>,
+    receiver: Receiver<M>,
+    /// Whether the peer initiated the connection.
+    inbound: bool,
 }
 
 impl<M> MPSCConnection<M> {
-    pub fn split(self) -> (UnboundedSender<M>, UnboundedReceiver<M>) {
+    /// The address of the peer at the other end of the connection.
+    pub fn peer_id(&self) -> u32 {
+        self.sender.peer_id()
+    }
+
+    /// Whether the peer initiated the connection, rather than this node.
+    pub fn is_inbound(&self) -> bool {
+        self.inbound
+    }
+
+    pub fn split(self) -> (ConnectionSender<M>, Receiver<M>) {
         (self.sender, self.receiver)
     }
 }
 
+/// What the connections of a transport share with every connection of the network.
+struct SharedState<M> {
+    metrics: SendMetrics,
+    conditions: NetworkConditions,
+    controller: Controller,
+    recorder: Option<Recorder<M>>,
+    bandwidth: Option<BandwidthMeter<M>>,
+}
+
 pub struct MPSCTransport<M>
 where
     M: Clone + Send,
@@ -54,6 +367,8 @@ where
     address: MPSCAddress<M>,
     transport_receiver: UnboundedReceiver<TransportMessage<M>>,
     seeds: Vec<MPSCAddress<M>>,
+    channel_capacity: usize,
+    shared: SharedState<M>,
 }
 
 impl<M> MPSCTransport<M>
@@ -72,9 +387,53 @@ where
             address,
             transport_receiver: channel_receiver,
             seeds: vec![],
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            shared: SharedState {
+                metrics: SendMetrics::new(),
+                conditions: NetworkConditions::new(),
+                controller: Controller::new(),
+                recorder: None,
+                bandwidth: None,
+            },
         }
     }
 
+    /// How many messages each connection of this transport buffers.
+    pub fn with_channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.channel_capacity = channel_capacity;
+        self
+    }
+
+    /// Where the connections of this transport count their congested sends.
+    pub fn with_send_metrics(mut self, metrics: SendMetrics) -> Self {
+        self.shared.metrics = metrics;
+        self
+    }
+
+    /// The conditions the connections of this transport follow.
+    pub fn with_conditions(mut self, conditions: NetworkConditions) -> Self {
+        self.shared.conditions = conditions;
+        self
+    }
+
+    /// The controller pausing the connections of this transport.
+    pub fn with_controller(mut self, controller: Controller) -> Self {
+        self.shared.controller = controller;
+        self
+    }
+
+    /// Where the connections of this transport record the messages they deliver.
+    pub fn with_recorder(mut self, recorder: Recorder<M>) -> Self {
+        self.shared.recorder = Some(recorder);
+        self
+    }
+
+    /// Where the connections of this transport count the bytes they send.
+    pub fn with_bandwidth_meter(mut self, bandwidth: BandwidthMeter<M>) -> Self {
+        self.shared.bandwidth = Some(bandwidth);
+        self
+    }
+
     pub fn address(&self) -> &MPSCAddress<M> {
         &self.address
     }
@@ -83,21 +442,53 @@ where
         self.seeds.push(address);
     }
 
-    pub fn run(self) -> impl Stream<Item = MPSCConnection<M>, Error = ()> {
+    /// The addresses this transport initiates connections to.
+    pub fn seeds(&self) -> &[MPSCAddress<M>] {
+        &self.seeds
+    }
+
+    /// Replaces the seeds the given function redirects, to the proxies of the nodes of other
+    /// processes for instance.
+    pub(crate) fn redirect_seeds<F>(&mut self, mut redirect: F)
+    where
+        F: FnMut(&MPSCAddress<M>) -> Option<MPSCAddress<M>>,
+    {
+        for seed in &mut self.seeds {
+            if let Some(redirected) = redirect(seed) {
+                *seed = redirected;
+            }
+        }
+    }
+
+    pub(crate) fn channel_capacity(&self) -> usize {
+        self.channel_capacity
+    }
+
+    /// The connections to the seeds and from the nodes that have this one as a seed, then
+    /// their closing by the peers. A seed that is already gone is skipped. The stream fails if
+    /// the transport is acknowledged a connection it did not initiate, which would be a bug
+    /// of the transport itself.
+    pub fn run(self) -> impl Stream<Item = Result<ConnectionEvent<M>, Error>> {
         let self_address = self.address;
         let self_address_id = self_address.id;
+        let channel_capacity = self.channel_capacity;
+        let shared = self.shared;
         let mut connections = HashMap::new();
+        let mut open_connections = HashSet::new();
 
-        for remote_address in &self.seeds {
-            let (connection_sender, connection_receiver): (
-                UnboundedSender<M>,
-                UnboundedReceiver<M>,
-            ) = mpsc::unbounded::<M>();
-            connections.insert(remote_address.id, connection_receiver);
-
+        for remote_address in self.seeds {
+            let (connection_sender, connection_receiver) = mpsc::channel(channel_capacity);
             let init_message = TransportMessage::Init(self_address.clone(), connection_sender);
 
-            send_or_panic(&remote_address.transport_sender, init_message);
+            match send(&remote_address.transport_sender, init_message) {
+                Ok(()) => {
+                    connections.insert(remote_address.id, (remote_address, connection_receiver));
+                }
+                Err(err) => debug!(
+                    "Could not connect {} to {}: {}",
+                    &self_address_id, &remote_address.id, err
+                ),
+            }
         }
 
         self.transport_receiver
@@ -108,38 +499,69 @@ where
                         &remote_address.id, &self_address_id
                     );
 
-                    let (connection_sender, connection_receiver): (
-                        UnboundedSender<M>,
-                        UnboundedReceiver<M>,
-                    ) = mpsc::unbounded::<M>();
-
-                    let connection = MPSCConnection {
-                        sender: remote_connection_sender,
-                        receiver: connection_receiver,
-                    };
+                    let (connection_sender, connection_receiver) = mpsc::channel(channel_capacity);
 
                     let ack_message = TransportMessage::Ack(self_address_id, connection_sender);
-                    send_or_panic(&remote_address.transport_sender, ack_message);
-
-                    connection
+                    match send(&remote_address.transport_sender, ack_message) {
+                        Ok(()) => {
+                            open_connections.insert(remote_address.id);
+                            let sender = ConnectionSender::new(
+                                remote_connection_sender,
+                                &shared,
+                                remote_address,
+                                self_address_id,
+                            );
+                            Ok(Some(ConnectionEvent::Opened(MPSCConnection {
+                                sender,
+                                receiver: connection_receiver,
+                                inbound: true,
+                            })))
+                        }
+                        Err(err) => {
+                            debug!(
+                                "Could not acknowledge the connection from {} to {}: {}",
+                                &remote_address.id, &self_address_id, err
+                            );
+                            Ok(None)
+                        }
+                    }
                 }
                 TransportMessage::Ack(address_id, sender) => {
                     debug!(
                         "Ack connection from {} to {}",
                         &self_address_id, &address_id
                     );
-                    if let Some(receiver) = connections.remove(&address_id) {
-                        MPSCConnection { sender, receiver }
+                    match connections.remove(&address_id) {
+                        Some((remote_address, receiver)) => {
+                            open_connections.insert(address_id);
+                            let sender = ConnectionSender::new(sender, &shared, remote_address, self_address_id);
+                            Ok(Some(ConnectionEvent::Opened(MPSCConnection {
+                                sender,
+                                receiver,
+                                inbound: false,
+                            })))
+                        }
+                        None => Err(Error::UnknownConnection(address_id)),
+                    }
+                }
+                TransportMessage::Disconnect(address_id) => {
+                    debug!(
+                        "Disconnection from {} to {}",
+                        &address_id, &self_address_id
+                    );
+                    // A peer may disconnect more than once through the clones of its sender.
+                    if open_connections.remove(&address_id) {
+                        Ok(Some(ConnectionEvent::Closed(address_id)))
                     } else {
-                        panic!("Could not find the connection to acknowledge.")
+                        Ok(None)
                     }
                 }
             })
+            .filter_map(|event| future::ready(event.transpose()))
     }
 }
 
-pub fn send_or_panic<M>(sender: &UnboundedSender<M>, message: M) {
-    if let Err(_err) = sender.unbounded_send(message) {
-        panic!("{}", _err)
-    }
+/// Sends a message through an unbounded channel, or fails if the receiver is gone.
+pub fn send<M>(sender: &UnboundedSender<M>, message: M) -> Result<(), Error> {
+    sender.unbounded_send(message).map_err(Error::from)
 }
@@ -0,0 +1,474 @@
+//! A simulation gathers a network, the nodes it runs and what is measured while they run:
+//!
+//! ```no_run
+//! # use network_simulator::network::Node;
+//! # use network_simulator::simulation::{LogSink, SimulationBuilder};
+//! # use std::time::Duration;
+//! # fn simulate<N: Node<()> + Send + Sync + 'static>(node: fn() -> N) {
+//! let report = SimulationBuilder::new(64, node)
+//!     .with_connections(3)
+//!     .with_latency(Duration::from_millis(20))
+//!     .with_duration(Duration::from_secs(10))
+//!     .with_metrics_sink(LogSink)
+//!     .build()
+//!     .run();
+//! # }
+//! ```
+
+use crate::clock::{Clock, RuntimeConfig, SystemClock};
+use crate::network::{
+    AddressBook, BandwidthMeter, Controller, LatencyMatrix, Network, NetworkConditions, Node, Recorder, Scenario,
+    SendMetrics, TcpBridge, Topology,
+};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a simulation runs, unless told otherwise.
+const DEFAULT_DURATION: Duration = Duration::from_secs(30);
+
+/// What was measured during a simulation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SimulationReport {
+    pub network_size: u32,
+    /// How long the nodes actually ran, by the clock of the network.
+    pub elapsed: Duration,
+    /// The messages given up on congested connections.
+    pub dropped_messages: usize,
+    /// The messages that had to wait for some room on congested connections.
+    pub delayed_messages: usize,
+    /// The messages sent across a partition.
+    pub lost_messages: usize,
+    /// The number of connections of each node, by address.
+    pub degrees: Vec<u32>,
+    /// Whether each node is behind a NAT, by address.
+    pub behind_nat: Vec<bool>,
+    /// The peers each node initiated its connections to, to restart the nodes with.
+    pub address_book: AddressBook,
+    /// The addresses of the address book the simulation started from that the nodes did not
+    /// reconnect to.
+    pub stale_addresses: u32,
+}
+
+/// Receives the report of a simulation once it has run.
+pub trait MetricsSink: Send {
+    fn record(&mut self, report: &SimulationReport);
+}
+
+/// Logs the report of a simulation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogSink;
+
+impl MetricsSink for LogSink {
+    fn record(&mut self, report: &SimulationReport) {
+        info!(
+            "{} nodes ran for {:?}. Congested connections: {} dropped messages, {} delayed messages, {} lost to partitions",
+            report.network_size,
+            report.elapsed,
+            report.dropped_messages,
+            report.delayed_messages,
+            report.lost_messages
+        );
+    }
+}
+
+/// Configures a simulation, every option but the size of the network and the node factory
+/// having a default value.
+pub struct SimulationBuilder<M, F> {
+    network_size: u32,
+    node_factory: F,
+    initiated_connections_per_node: u8,
+    topology: Topology,
+    nat_fraction: f64,
+    address_book: AddressBook,
+    channel_capacity: Option<usize>,
+    latency: Duration,
+    latency_matrix: Option<LatencyMatrix>,
+    scenario: Scenario,
+    duration: Duration,
+    clock: Arc<dyn Clock>,
+    runtime_config: RuntimeConfig,
+    recorder: Option<Recorder<M>>,
+    bandwidth: Option<BandwidthMeter<M>>,
+    bridge: Option<TcpBridge<M>>,
+    metrics_sinks: Vec<Box<dyn MetricsSink>>,
+    _messages: PhantomData<fn() -> M>,
+}
+
+impl<M, F> SimulationBuilder<M, F>
+where
+    M: Clone + Send + 'static,
+{
+    /// A simulation of the given number of nodes, created by the factory in the order of
+    /// their addresses.
+    pub fn new(network_size: u32, node_factory: F) -> SimulationBuilder<M, F> {
+        SimulationBuilder {
+            network_size,
+            node_factory,
+            initiated_connections_per_node: 3,
+            topology: Topology::Random { seed: None },
+            nat_fraction: 0.0,
+            address_book: AddressBook::new(),
+            channel_capacity: None,
+            latency: Duration::from_millis(0),
+            latency_matrix: None,
+            scenario: Scenario::new(),
+            duration: DEFAULT_DURATION,
+            clock: Arc::new(SystemClock),
+            runtime_config: RuntimeConfig::new(),
+            recorder: None,
+            bandwidth: None,
+            bridge: None,
+            metrics_sinks: vec![],
+            _messages: PhantomData,
+        }
+    }
+
+    /// How many connections each node initiates. Default: 3
+    pub fn with_connections(mut self, initiated_connections_per_node: u8) -> Self {
+        self.initiated_connections_per_node = initiated_connections_per_node;
+        self
+    }
+
+    /// Default: random peers, with a random seed.
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    /// The fraction of the nodes behind a NAT, see `Network::from_topology_behind_nat`.
+    /// Default: none
+    pub fn with_nat_fraction(mut self, nat_fraction: f64) -> Self {
+        self.nat_fraction = nat_fraction;
+        self
+    }
+
+    /// The peers the nodes knew in a previous simulation, which they reconnect to, see
+    /// `Network::from_address_book`. Default: none
+    pub fn with_address_book(mut self, address_book: AddressBook) -> Self {
+        self.address_book = address_book;
+        self
+    }
+
+    /// See `Network::with_channel_capacity`.
+    pub fn with_channel_capacity(mut self, channel_capacity: usize) -> Self {
+        self.channel_capacity = Some(channel_capacity);
+        self
+    }
+
+    /// The delay before every message sent is received, until a scenario changes it.
+    /// Default: none
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Spreads the nodes over the regions of the matrix, whose latencies add up to the one of
+    /// the network, see `NetworkConditions::set_latency_matrix`. Default: none
+    pub fn with_latency_matrix(mut self, latency_matrix: LatencyMatrix) -> Self {
+        self.latency_matrix = Some(latency_matrix);
+        self
+    }
+
+    /// See `Network::with_scenario`.
+    pub fn with_scenario(mut self, scenario: Scenario) -> Self {
+        self.scenario = scenario;
+        self
+    }
+
+    /// How long the nodes run. Default: 30 seconds
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// See `Network::with_clock`.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// See `Network::with_runtime_config`.
+    pub fn with_runtime_config(mut self, runtime_config: RuntimeConfig) -> Self {
+        self.runtime_config = runtime_config;
+        self
+    }
+
+    /// See `Network::with_recorder`.
+    pub fn with_recorder(mut self, recorder: Recorder<M>) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// See `Network::with_bandwidth_meter`.
+    pub fn with_bandwidth_meter(mut self, bandwidth: BandwidthMeter<M>) -> Self {
+        self.bandwidth = Some(bandwidth);
+        self
+    }
+
+    /// See `Network::with_tcp_bridge`.
+    pub fn with_tcp_bridge(mut self, bridge: TcpBridge<M>) -> Self {
+        self.bridge = Some(bridge);
+        self
+    }
+
+    /// Adds a sink to give the report to, in the order they are added.
+    pub fn with_metrics_sink<S: MetricsSink + 'static>(mut self, metrics_sink: S) -> Self {
+        self.metrics_sinks.push(Box::new(metrics_sink));
+        self
+    }
+
+    /// Sets up the network, the nodes are only created when the simulation runs.
+    pub fn build(self) -> Simulation<M, F> {
+        let mut network = Network::from_address_book(
+            self.network_size,
+            self.initiated_connections_per_node,
+            self.topology,
+            self.nat_fraction,
+            &self.address_book,
+        )
+        .with_scenario(self.scenario)
+        .with_clock(self.clock)
+        .with_runtime_config(self.runtime_config);
+        if let Some(channel_capacity) = self.channel_capacity {
+            network = network.with_channel_capacity(channel_capacity);
+        }
+        if let Some(recorder) = self.recorder {
+            network = network.with_recorder(recorder);
+        }
+        if let Some(bandwidth) = self.bandwidth {
+            network = network.with_bandwidth_meter(bandwidth);
+        }
+        if let Some(bridge) = self.bridge {
+            network = network.with_tcp_bridge(bridge);
+        }
+        network.conditions().set_latency(self.latency);
+        if let Some(latency_matrix) = self.latency_matrix {
+            network.conditions().set_latency_matrix(latency_matrix);
+        }
+
+        Simulation {
+            network_size: self.network_size,
+            network,
+            node_factory: self.node_factory,
+            duration: self.duration,
+            metrics_sinks: self.metrics_sinks,
+        }
+    }
+}
+
+/// A network ready to run, see `SimulationBuilder`.
+pub struct Simulation<M, F>
+where
+    M: Clone + Send + 'static,
+{
+    network_size: u32,
+    network: Network<M>,
+    node_factory: F,
+    duration: Duration,
+    metrics_sinks: Vec<Box<dyn MetricsSink>>,
+}
+
+impl<M, F> Simulation<M, F>
+where
+    M: Clone + Send + 'static,
+{
+    /// The conditions of the network, which can be changed while it runs.
+    pub fn conditions(&self) -> NetworkConditions {
+        self.network.conditions()
+    }
+
+    /// Pauses, steps and resumes the nodes while they run, see `Controller`.
+    pub fn controller(&self) -> Controller {
+        self.network.controller()
+    }
+
+    /// The congested sends of the network, counted while it runs.
+    pub fn send_metrics(&self) -> SendMetrics {
+        self.network.send_metrics()
+    }
+
+    /// Runs the nodes for the duration of the simulation, then gives its report to every
+    /// metrics sink. The panics of the nodes are propagated.
+    pub fn run<N>(self) -> SimulationReport
+    where
+        N: Node<M> + Sync + Send + 'static,
+        F: Fn() -> N + Send + 'static,
+    {
+        self.run_until(|| false)
+    }
+
+    /// Same as `run`, but stops the nodes as soon as the given condition holds, if it does
+    /// before the end of the duration of the simulation.
+    pub fn run_until<N, C>(self, condition: C) -> SimulationReport
+    where
+        N: Node<M> + Sync + Send + 'static,
+        F: Fn() -> N + Send + 'static,
+        C: FnMut() -> bool + Send + 'static,
+    {
+        let (report, _outputs) = self.run_until_with_outputs(condition);
+        report
+    }
+
+    /// Same as `run_until`, but also returns the outputs of the nodes that stopped on their
+    /// own, by address, see `Network::run_until_with_outputs`.
+    pub fn run_until_with_outputs<N, C>(self, condition: C) -> (SimulationReport, Vec<Option<N::Output>>)
+    where
+        N: Node<M> + Sync + Send + 'static,
+        F: Fn() -> N + Send + 'static,
+        C: FnMut() -> bool + Send + 'static,
+    {
+        let send_metrics = self.network.send_metrics();
+        let degrees = self.network.degrees();
+        let behind_nat = self.network.behind_nat();
+        let address_book = self.network.address_book();
+        let stale_addresses = self.network.stale_addresses();
+        let (elapsed, outputs) = self.network.run_until_with_outputs(self.node_factory, self.duration, condition);
+
+        let report = SimulationReport {
+            network_size: self.network_size,
+            elapsed,
+            dropped_messages: send_metrics.dropped(),
+            delayed_messages: send_metrics.delayed(),
+            lost_messages: send_metrics.lost(),
+            degrees,
+            behind_nat,
+            address_book,
+            stale_addresses,
+        };
+        for mut metrics_sink in self.metrics_sinks {
+            metrics_sink.record(&report);
+        }
+        (report, outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::error::Error;
+    use crate::network::{ConnectionEvent, WireEncoder};
+    use futures::{Future, Stream, StreamExt};
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    /// Sends a message on every connection, then ignores them.
+    struct GreetingNode;
+
+    impl Node<()> for GreetingNode {
+        type Output = ();
+
+        fn run<S>(self, mut connection_stream: S) -> Pin<Box<dyn Future<Output = ()> + Send>>
+        where
+            S: Stream<Item = Result<ConnectionEvent<()>, Error>> + Send + Unpin + 'static,
+        {
+            Box::pin(async move {
+                let mut connections = vec![];
+                while let Some(Ok(event)) = connection_stream.next().await {
+                    if let ConnectionEvent::Opened(connection) = event {
+                        let (mut sender, receiver) = connection.split();
+                        let _ = sender.try_send(());
+                        connections.push((sender, receiver));
+                    }
+                }
+            })
+        }
+    }
+
+    /// Sends every greeting as a 16 bytes frame.
+    struct FrameEncoder;
+
+    impl WireEncoder<()> for FrameEncoder {
+        fn encode(&self, _message: &()) -> Option<Vec<u8>> {
+            Some(vec![0; 16])
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingSink {
+        reports: Arc<Mutex<Vec<SimulationReport>>>,
+    }
+
+    impl MetricsSink for RecordingSink {
+        fn record(&mut self, report: &SimulationReport) {
+            self.reports.lock().unwrap().push(report.clone());
+        }
+    }
+
+    #[test]
+    fn gives_the_report_to_every_metrics_sink() {
+        let sink = RecordingSink::default();
+        let simulation = SimulationBuilder::new(4, || GreetingNode)
+            .with_topology(Topology::Ring)
+            .with_connections(1)
+            .with_duration(Duration::from_millis(200))
+            .with_metrics_sink(sink.clone())
+            .with_metrics_sink(sink.clone())
+            .build();
+        // Every message sent is lost.
+        simulation.conditions().partition(&[vec![0], vec![1], vec![2], vec![3]]);
+
+        let report = simulation.run();
+
+        assert_eq!(4, report.network_size);
+        assert!(report.elapsed >= Duration::from_millis(200));
+        assert_eq!(8, report.lost_messages);
+        assert_eq!(vec![2, 2, 2, 2], report.degrees);
+        assert_eq!(vec![false; 4], report.behind_nat);
+        assert_eq!(AddressBook::from_peers(vec![vec![1], vec![2], vec![3], vec![0]]), report.address_book);
+        assert_eq!(0, report.stale_addresses);
+        assert_eq!(vec![report.clone(), report], *sink.reports.lock().unwrap());
+    }
+
+    #[test]
+    fn stops_the_nodes_once_the_condition_holds() {
+        let start = Instant::now();
+        let simulation = SimulationBuilder::new(4, || GreetingNode)
+            .with_duration(Duration::from_secs(30))
+            .build();
+
+        let report = simulation.run_until(move || start.elapsed() >= Duration::from_millis(100));
+
+        assert!(report.elapsed >= Duration::from_millis(100));
+        assert!(report.elapsed < Duration::from_secs(30));
+    }
+
+    #[test]
+    fn runs_a_long_simulation_at_once_with_a_virtual_clock() {
+        let real_start = Instant::now();
+        let simulation = SimulationBuilder::new(4, || GreetingNode)
+            .with_latency(Duration::from_secs(1))
+            .with_duration(Duration::from_secs(3600))
+            .with_clock(MockClock)
+            .build();
+
+        let report = simulation.run();
+
+        assert!(report.elapsed >= Duration::from_secs(3600));
+        assert!(real_start.elapsed() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn counts_the_bytes_sent_on_each_connection() {
+        let bandwidth = BandwidthMeter::new(FrameEncoder);
+        let simulation = SimulationBuilder::new(4, || GreetingNode)
+            .with_topology(Topology::Ring)
+            .with_connections(1)
+            .with_duration(Duration::from_millis(200))
+            .with_clock(MockClock)
+            .with_bandwidth_meter(bandwidth.clone())
+            .build();
+        // The greetings lost to the partition are not sent.
+        simulation.conditions().partition(&[vec![0, 1, 2], vec![3]]);
+
+        simulation.run();
+
+        assert_eq!(16, bandwidth.connection(0, 1).raw_bytes);
+        assert_eq!(16, bandwidth.connection(1, 0).raw_bytes);
+        assert_eq!(0, bandwidth.connection(3, 0).messages);
+        assert_eq!(4, bandwidth.total().messages);
+        assert_eq!(64, bandwidth.total().compressed_bytes);
+    }
+}
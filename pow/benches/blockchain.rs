@@ -0,0 +1,198 @@
+//! Benchmarks of the hot paths of the simulation: the proof of work hash, the validation of
+//! blocks, chains and transactions, and the propagation of a chain across a small network.
+//!
+//! ```sh
+//! cargo bench -p pow_blockchain_simulation
+//! ```
+
+extern crate btclike_simulation as btclike;
+extern crate criterion;
+extern crate pow_blockchain_simulation as pow;
+
+use btclike::blockchain::{Body, COINBASE_MATURITY};
+use btclike::chain_params::ChainParams;
+use btclike::crypto::{self, Hasher, SignatureAlgorithm};
+use btclike::transaction::{Address, SignedTx, TxOut};
+use btclike::utxo::UtxoSet;
+use btclike::wallet::Wallet;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, SamplingMode};
+use pow::blockchain::{hash_header, Block, BlockBody, Difficulty, MetricsBus, NodeMetric, Nonce};
+use pow::{Chain, Network, PowNode};
+use ring::digest::SHA256_OUTPUT_LEN;
+use std::collections::HashSet;
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Long enough for the nodes not to mine or pay while a chain propagates.
+const IDLE_DELAY: Duration = Duration::from_secs(3600);
+/// How long the network runs for each propagation, once the chain reached every node.
+const PROPAGATION_DURATION: Duration = Duration::from_millis(500);
+
+/// The body of a block at the given height, with the coinbase transaction alone.
+fn empty_body(coinbase_address: Address, height: u32) -> Arc<BlockBody> {
+    let coinbase = TxOut::new(ChainParams::main().block_reward(height), coinbase_address);
+    Arc::new(BlockBody::new(Body::new(coinbase, vec![])).expect("Could not build the block body"))
+}
+
+/// Any hash meets the minimum difficulty.
+fn expand(chain: &Arc<Chain>, body: &Arc<BlockBody>) -> Arc<Chain> {
+    let block = Block::new(
+        0,
+        Nonce::new(),
+        0,
+        chain.head().difficulty(),
+        chain.head().hasher(),
+        chain.head().hash().clone(),
+        chain.height() + 1,
+        body,
+    );
+    Chain::expand(chain, block).expect("Invalid block")
+}
+
+/// Expands the chain with empty blocks, paying every coinbase to another address.
+fn expand_by(mut chain: Arc<Chain>, blocks: u32) -> Arc<Chain> {
+    for _i in 0..blocks {
+        let height = chain.height() + 1;
+        let coinbase_address = Address::from_hash(crypto::hash(&height.to_le_bytes()));
+        chain = expand(&chain, &empty_body(coinbase_address, height));
+    }
+    chain
+}
+
+fn min_difficulty_chain() -> Arc<Chain> {
+    Arc::new(Chain::init_new(Difficulty::min_difficulty()))
+}
+
+fn hash(c: &mut Criterion) {
+    let difficulty = Difficulty::min_difficulty();
+    let nonce = Nonce::new();
+    let previous_hash = [0u8; SHA256_OUTPUT_LEN];
+    let body_hash = [0u8; SHA256_OUTPUT_LEN];
+
+    let mut group = c.benchmark_group("Hash::new");
+    for hasher in [Hasher::Sha256, Hasher::DoubleSha256, Hasher::Sha512Trunc256] {
+        group.bench_with_input(BenchmarkId::from_parameter(format!("{:?}", hasher)), &hasher, |b, hasher| {
+            b.iter(|| hash_header(0, &nonce, 0, &difficulty, *hasher, 1, &previous_hash, black_box(&body_hash)))
+        });
+    }
+    group.finish();
+}
+
+fn block_validation(c: &mut Criterion) {
+    let chain = expand_by(min_difficulty_chain(), 1);
+    c.bench_function("Block::validate", |b| b.iter(|| chain.head().validate().unwrap()));
+}
+
+fn chain_validation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Chain::validate");
+    for height in [10, 100, 1000] {
+        let chain = expand_by(min_difficulty_chain(), height);
+        group.bench_with_input(BenchmarkId::from_parameter(height), &chain, |b, chain| {
+            b.iter(|| chain.validate().unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// A payment spending a mature coinbase output, along with the outputs it may spend and the
+/// height of the block that would confirm it.
+fn payment(signature_algorithm: SignatureAlgorithm) -> (SignedTx, UtxoSet, u32) {
+    let mut wallet = Wallet::new().with_signature_algorithm(signature_algorithm);
+    let genesis_chain = Arc::new(
+        Chain::init_new(Difficulty::min_difficulty()).with_signature_algorithm(signature_algorithm),
+    );
+    let coinbase_body = empty_body(wallet.new_address().unwrap(), 1);
+    let chain = expand(&genesis_chain, &coinbase_body);
+    wallet.connect_block(coinbase_body.body(), chain.height()).unwrap();
+    let chain = expand_by(chain, COINBASE_MATURITY);
+    let utxo_set = chain.validate().unwrap();
+
+    let to_address = wallet.new_address().unwrap();
+    let transaction = wallet.new_transaction(100, to_address, 0, &utxo_set).unwrap();
+    (transaction, utxo_set, chain.height() + 1)
+}
+
+fn transaction_verification(c: &mut Criterion) {
+    let mut group = c.benchmark_group("SignedTx::verify");
+    for signature_algorithm in [SignatureAlgorithm::Ed25519, SignatureAlgorithm::Secp256k1] {
+        let (transaction, utxo_set, height) = payment(signature_algorithm);
+        let params = ChainParams::main();
+        group.bench_function(format!("{:?}", signature_algorithm), |b| {
+            b.iter(|| transaction.verify(&utxo_set, height, &params).unwrap())
+        });
+    }
+    group.finish();
+}
+
+/// Runs a network where only the first node knows the chain, and measures how long it takes
+/// for every other node to adopt it.
+fn propagate(genesis_chain: &Arc<Chain>, chain: &Arc<Chain>, network_size: u32) -> Duration {
+    let mut metrics_bus = MetricsBus::new();
+    let metrics = metrics_bus.subscribe();
+    let (genesis_chain, chain_height, chain) = (genesis_chain.clone(), chain.height(), chain.clone());
+    let node_id = AtomicUsize::new(0);
+
+    let start = Instant::now();
+    let network = thread::spawn(move || {
+        Network::new(network_size, 3).run(
+            move || {
+                let node_id = node_id.fetch_add(1, Ordering::Relaxed) as u32;
+                let node_chain = if node_id == 0 { chain.clone() } else { genesis_chain.clone() };
+                PowNode::new(node_id, node_chain, IDLE_DELAY, IDLE_DELAY).with_metrics_bus(metrics_bus.clone())
+            },
+            PROPAGATION_DURATION,
+        )
+    });
+
+    let mut up_to_date_nodes = HashSet::new();
+    for metric in metrics.iter() {
+        if let NodeMetric::ChainHeight { node_id, height } = metric {
+            if height == chain_height {
+                up_to_date_nodes.insert(node_id);
+                if up_to_date_nodes.len() == network_size as usize - 1 {
+                    break;
+                }
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+
+    network.join().expect("The network panicked");
+    elapsed
+}
+
+fn propagation(c: &mut Criterion) {
+    let genesis_chain = min_difficulty_chain();
+    let chain = expand_by(genesis_chain.clone(), 100);
+
+    // Every run lasts as long as the network, whatever the time measured.
+    let mut group = c.benchmark_group("propagation");
+    group
+        .sampling_mode(SamplingMode::Flat)
+        .sample_size(10)
+        .warm_up_time(Duration::from_millis(1))
+        .measurement_time(Duration::from_millis(1));
+    for network_size in [8, 32] {
+        group.bench_with_input(BenchmarkId::new("100_blocks", network_size), &network_size, |b, network_size| {
+            b.iter_custom(|iterations| {
+                (0..iterations)
+                    .map(|_iteration| propagate(&genesis_chain, &chain, *network_size))
+                    .sum()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    hash,
+    block_validation,
+    chain_validation,
+    transaction_verification,
+    propagation
+);
+criterion_main!(benches);
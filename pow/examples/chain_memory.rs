@@ -0,0 +1,74 @@
+//! Measures the memory taken by a long chain: builds one block after the other with the minimum
+//! difficulty, then reports the bytes still allocated once the chain is complete.
+//!
+//! ```sh
+//! cargo run --release -p pow_blockchain_simulation --example chain_memory -- 1000000
+//! ```
+
+extern crate btclike_simulation as btclike;
+extern crate pow_blockchain_simulation as pow;
+
+use btclike::blockchain::{Body, COINBASE_AMOUNT};
+use btclike::crypto;
+use btclike::transaction::{Address, TxOut};
+use pow::blockchain::{Block, BlockBody, Difficulty, Nonce};
+use pow::Chain;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Counts the bytes currently allocated.
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn main() {
+    let blocks: u32 = env::args()
+        .nth(1)
+        .map(|blocks| blocks.parse().expect("Invalid number of blocks"))
+        .unwrap_or(1_000_000);
+
+    let allocated_before = ALLOCATED.load(Ordering::Relaxed);
+    let mut chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+    for height in 1..=blocks {
+        // Every coinbase pays another address, so that the transactions differ.
+        let coinbase_address = Address::from_hash(crypto::hash(&height.to_le_bytes()));
+        let body = BlockBody::new(Body::new(TxOut::new(COINBASE_AMOUNT, coinbase_address), vec![]))
+            .expect("Could not build the block body");
+        let block = Block::new(
+            0,
+            Nonce::new(),
+            0,
+            chain.head().difficulty(),
+            chain.head().hasher(),
+            chain.head().hash().clone(),
+            height,
+            &Arc::new(body),
+        );
+        chain = Chain::expand(&chain, block).expect("Any hash meets the minimum difficulty");
+    }
+    let allocated = ALLOCATED.load(Ordering::Relaxed) - allocated_before;
+
+    println!(
+        "{} blocks: {} MiB, {} bytes per block",
+        chain.height(),
+        allocated / (1024 * 1024),
+        allocated / blocks.max(1) as usize
+    );
+}
@@ -0,0 +1,89 @@
+extern crate clap;
+extern crate env_logger;
+#[macro_use]
+extern crate log;
+extern crate pow_blockchain_simulation as pow;
+
+use clap::{App, Arg};
+use log::LevelFilter;
+use pow::orchestrator::{Orchestrator, WorkerReport, MAX_PROCESSES};
+use std::env;
+use std::process;
+
+fn main() {
+    // Always print backtrace on panic.
+    ::std::env::set_var("RUST_BACKTRACE", "1");
+
+    env_logger::Builder::from_default_env()
+        .default_format_module_path(false)
+        .filter_level(LevelFilter::Info)
+        .init();
+
+    let matches = App::new("Proof-of-Work Blockchain Network Orchestrator")
+        .version("0.1")
+        .author("Pierre L. <pierre.larger@gmail.com>")
+        .about("Spreads a Proof-of-Work blockchain network simulation over several processes")
+        .arg(
+            Arg::with_name("processes")
+                .short("p")
+                .long("processes")
+                .value_name("PROCESSES")
+                .help("The number of processes the nodes are shared by. Default: 2")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("simulation_flags")
+                .value_name("SIMULATION_FLAGS")
+                .help("The flags of the simulation, after --, given to every process: -- -n 20000 --seed 7")
+                .multiple(true),
+        )
+        .get_matches();
+
+    let processes = match matches.value_of("processes").map(str::parse) {
+        None => 2,
+        Some(Ok(processes)) if (1..=MAX_PROCESSES).contains(&processes) => processes,
+        Some(_) => panic!("Invalid number of processes, expected [1-{}]", MAX_PROCESSES),
+    };
+    let simulation_flags = matches
+        .values_of("simulation_flags")
+        .into_iter()
+        .flatten()
+        .map(str::to_owned);
+    // The simulation binary is built along with this one.
+    let program = env::current_exe()
+        .expect("Could not locate the orchestrator")
+        .with_file_name(format!("pow_blockchain_simulation{}", env::consts::EXE_SUFFIX));
+
+    match Orchestrator::new(program, processes).with_args(simulation_flags).run() {
+        Ok(reports) => log_reports(&reports),
+        Err(err) => {
+            error!("{}", err);
+            process::exit(1);
+        }
+    }
+}
+
+fn log_reports(reports: &[WorkerReport]) {
+    info!(
+        "{} workers ran {} nodes. Congested connections: {} dropped messages, {} delayed messages, {} lost to partitions",
+        reports.len(),
+        reports.iter().map(|report| report.nodes.len()).sum::<usize>(),
+        reports.iter().map(|report| report.dropped_messages).sum::<usize>(),
+        reports.iter().map(|report| report.delayed_messages).sum::<usize>(),
+        reports.iter().map(|report| report.lost_messages).sum::<usize>()
+    );
+    for (index, report) in reports.iter().enumerate() {
+        info!(
+            "Worker #{}: nodes {:?}, strongest chain of height {}, head {}",
+            index, report.nodes, report.height, report.head
+        );
+    }
+    if let Some(strongest) = reports.iter().max_by_key(|report| report.height) {
+        info!(
+            "Consensus: {} of the {} workers on the strongest chain, of height {}",
+            reports.iter().filter(|report| report.head == strongest.head).count(),
+            reports.len(),
+            strongest.height
+        );
+    }
+}
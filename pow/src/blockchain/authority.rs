@@ -0,0 +1,111 @@
+use crate::blockchain::BlockHeader;
+use crate::error::{ChainError, Error};
+use btclike::crypto::{KeyPair, KeyPairGenerator, PubKey, Signature, SignatureAlgorithm};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+
+/// The nodes allowed to produce the blocks of a proof of authority chain, with the public keys
+/// their blocks are signed with. The blocks of the other nodes are rejected, however much work
+/// they carry.
+#[derive(Clone)]
+pub struct Authorities {
+    signature_algorithm: SignatureAlgorithm,
+    pub_keys: HashMap<u32, PubKey>,
+}
+
+impl Authorities {
+    pub fn new(signature_algorithm: SignatureAlgorithm) -> Authorities {
+        Authorities {
+            signature_algorithm,
+            pub_keys: HashMap::new(),
+        }
+    }
+
+    /// Generates a key pair for each of the given nodes, which become the authorities. Returns
+    /// the key pairs the nodes sign their blocks with, by node id.
+    pub fn generate<I: IntoIterator<Item = u32>>(
+        node_ids: I,
+        signature_algorithm: SignatureAlgorithm,
+    ) -> Result<(Authorities, HashMap<u32, Arc<KeyPair>>), Error> {
+        let key_pair_generator = KeyPairGenerator::new().with_signature_algorithm(signature_algorithm);
+        let mut authorities = Authorities::new(signature_algorithm);
+        let mut key_pairs = HashMap::new();
+        for node_id in node_ids {
+            let key_pair = key_pair_generator
+                .random_keypair()
+                .map_err(|_err| Error::Internal(format!("Could not generate the key pair of #{:05}", node_id)))?;
+            authorities.insert(node_id, key_pair.pub_key());
+            key_pairs.insert(node_id, Arc::new(key_pair));
+        }
+        Ok((authorities, key_pairs))
+    }
+
+    /// Allows the node to produce blocks signed with the key.
+    pub fn insert(&mut self, node_id: u32, pub_key: PubKey) {
+        self.pub_keys.insert(node_id, pub_key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pub_keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pub_keys.is_empty()
+    }
+
+    /// Checks that the block was produced by an authority, which signed its hash.
+    pub fn verify(&self, header: &BlockHeader) -> Result<(), ChainError> {
+        self.verify_signed(header.node_id, header.hash.as_ref(), header.signature.as_ref())
+    }
+
+    /// Checks that the message was signed by the given authority.
+    pub fn verify_signed(
+        &self,
+        node_id: u32,
+        message: &[u8],
+        signature: Option<&Signature>,
+    ) -> Result<(), ChainError> {
+        let pub_key = self.pub_keys.get(&node_id).ok_or(ChainError::UnknownProducer)?;
+        let signature = signature.ok_or(ChainError::MissingSignature)?;
+        self.signature_algorithm
+            .scheme()
+            .verify(pub_key, message, signature)
+            .map_err(|_err| ChainError::InvalidSignature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{Block, Chain, Difficulty, Nonce};
+
+    #[test]
+    fn only_accepts_the_blocks_signed_by_their_producer() {
+        let (authorities, key_pairs) = Authorities::generate(0..2, SignatureAlgorithm::Ed25519).unwrap();
+        let chain = Chain::init_new(Difficulty::min_difficulty());
+        let block = |node_id| {
+            Block::new(
+                node_id,
+                Nonce::new(),
+                0,
+                chain.head().difficulty(),
+                chain.head().hasher(),
+                chain.head().hash().clone(),
+                1,
+                chain.head().body(),
+            )
+        };
+
+        assert_eq!(Ok(()), authorities.verify(&block(0).sign(&key_pairs[&0]).header()));
+        assert_eq!(Err(ChainError::MissingSignature), authorities.verify(&block(1).header()));
+        assert_eq!(
+            Err(ChainError::InvalidSignature),
+            authorities.verify(&block(1).sign(&key_pairs[&0]).header())
+        );
+        assert_eq!(
+            Err(ChainError::UnknownProducer),
+            authorities.verify(&block(2).sign(&key_pairs[&0]).header())
+        );
+    }
+}
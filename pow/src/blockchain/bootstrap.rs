@@ -0,0 +1,130 @@
+use crate::blockchain::NodeMetric;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// Measures how long the honest nodes take to validate the chain they start from, and then to
+/// reach consensus: to adopt a first chain from a peer. The nodes are built one after the
+/// other, so the slower the bootstrap, the later the last ones join the network.
+pub struct BootstrapStats {
+    honest_nodes: Range<u32>,
+    nodes: HashMap<u32, NodeBootstrap>,
+}
+
+struct NodeBootstrap {
+    bootstrap: Duration,
+    /// When the node started to bootstrap.
+    joined_at: Instant,
+    /// The time from then until it adopted a first chain from a peer, if it did.
+    time_to_consensus: Option<Duration>,
+}
+
+impl BootstrapStats {
+    pub fn new(honest_nodes: Range<u32>) -> BootstrapStats {
+        BootstrapStats {
+            honest_nodes,
+            nodes: HashMap::new(),
+        }
+    }
+
+    /// Records a metric published by a node now.
+    pub fn record(&mut self, metric: &NodeMetric) {
+        self.record_at(metric, Instant::now());
+    }
+
+    fn record_at(&mut self, metric: &NodeMetric, at: Instant) {
+        match *metric {
+            NodeMetric::NodeBootstrapped {
+                node_id, bootstrap_ms, ..
+            } if self.honest_nodes.contains(&node_id) => {
+                let bootstrap = Duration::from_secs_f64(bootstrap_ms / 1000.0);
+                self.nodes.insert(
+                    node_id,
+                    NodeBootstrap {
+                        bootstrap,
+                        joined_at: at.checked_sub(bootstrap).unwrap_or(at),
+                        time_to_consensus: None,
+                    },
+                );
+            }
+            NodeMetric::BlockPropagated { node_id, .. } => {
+                if let Some(node) = self.nodes.get_mut(&node_id) {
+                    if node.time_to_consensus.is_none() {
+                        node.time_to_consensus = Some(at.duration_since(node.joined_at));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The number of honest nodes that bootstrapped.
+    pub fn nodes(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// The number of them that adopted a chain from a peer.
+    pub fn synced_nodes(&self) -> usize {
+        self.nodes.values().filter(|node| node.time_to_consensus.is_some()).count()
+    }
+
+    /// The time the nodes took to bootstrap, at the given percentile.
+    pub fn bootstrap_percentile(&self, percentage: u8) -> Option<Duration> {
+        percentile(self.nodes.values().map(|node| node.bootstrap).collect(), percentage)
+    }
+
+    /// The time the synced nodes took to adopt a chain from a peer once they started to
+    /// bootstrap, at the given percentile.
+    pub fn time_to_consensus_percentile(&self, percentage: u8) -> Option<Duration> {
+        percentile(
+            self.nodes.values().filter_map(|node| node.time_to_consensus).collect(),
+            percentage,
+        )
+    }
+}
+
+pub(crate) fn percentile(mut durations: Vec<Duration>, percentage: u8) -> Option<Duration> {
+    if durations.is_empty() {
+        return None;
+    }
+
+    durations.sort();
+    let rank = (durations.len() - 1) * percentage.min(100) as usize / 100;
+    Some(durations[rank])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_the_time_to_consensus_from_the_start_of_the_bootstrap() {
+        let mut stats = BootstrapStats::new(1..4);
+        let start = Instant::now();
+        let bootstrapped = |node_id, bootstrap_ms| NodeMetric::NodeBootstrapped {
+            node_id,
+            height: 100,
+            bootstrap_ms,
+        };
+        let propagated = |node_id| NodeMetric::BlockPropagated {
+            node_id,
+            peer_id: 0,
+            height: 101,
+            hash: "00".to_owned(),
+        };
+
+        stats.record_at(&bootstrapped(0, 10.0), start);
+        stats.record_at(&bootstrapped(1, 100.0), start + Duration::from_millis(100));
+        stats.record_at(&bootstrapped(2, 200.0), start + Duration::from_millis(300));
+        stats.record_at(&propagated(1), start + Duration::from_millis(400));
+        stats.record_at(&propagated(2), start + Duration::from_millis(500));
+        stats.record_at(&propagated(1), start + Duration::from_millis(600));
+
+        assert_eq!(2, stats.nodes());
+        assert_eq!(2, stats.synced_nodes());
+        assert_eq!(Some(Duration::from_millis(100)), stats.bootstrap_percentile(0));
+        assert_eq!(Some(Duration::from_millis(200)), stats.bootstrap_percentile(100));
+        assert_eq!(Some(Duration::from_millis(400)), stats.time_to_consensus_percentile(0));
+        assert_eq!(Some(Duration::from_millis(400)), stats.time_to_consensus_percentile(100));
+    }
+}
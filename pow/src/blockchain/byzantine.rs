@@ -0,0 +1,289 @@
+use crate::blockchain::miner::interval_stream;
+use crate::blockchain::pow::Nonce;
+use crate::blockchain::{Block, BlockBody, Chain, Difficulty, Message, NodeLogger};
+use crate::error::Error;
+use btclike::crypto::Hash;
+use btclike::genesis::GenesisConfig;
+use futures::channel::mpsc::Receiver;
+use futures::stream::SelectAll;
+use futures::{future, Future, Stream, StreamExt};
+use netsim::clock;
+use netsim::network::{ConnectionEvent, ConnectionSender, Node};
+use ring::digest::SHA256_OUTPUT_LEN;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The maximum number of hashes tried to give a forged block a valid proof of work. Past it,
+/// the block is sent anyway, and rejected for its hash instead.
+const MAX_FORGING_ATTEMPTS: u32 = 1 << 20;
+
+/// The ways a byzantine node breaks the consensus rules, in turn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Forgery {
+    /// A block whose hash does not match its fields.
+    BadHash,
+    /// A block mined with an easier difficulty than the one of its parent.
+    WrongDifficulty,
+    /// A block skipping a height.
+    BrokenHeightLink,
+}
+
+impl Forgery {
+    fn next(self) -> Forgery {
+        match self {
+            Forgery::BadHash => Forgery::WrongDifficulty,
+            Forgery::WrongDifficulty => Forgery::BrokenHeightLink,
+            Forgery::BrokenHeightLink => Forgery::BadHash,
+        }
+    }
+}
+
+/// The events that can happen in a byzantine node.
+enum ByzantineNodeEvent {
+    Peer(ConnectionSender<Message>),
+    /// The peer with the given address closed its connection.
+    PeerClosed(u32),
+    Chain(Arc<Chain>),
+    /// A peer pings this node, which answers through the given sender.
+    Ping(Instant, ConnectionSender<Message>),
+    ForgeryAttempt,
+}
+
+/// A node that does not mine, and regularly sends its peers a chain whose head block breaks
+/// the consensus rules, on top of the strongest chain it received. The proof of work of the
+/// forged blocks is valid whenever it can be found quickly, so that the honest nodes reject
+/// them for the intended reason.
+pub struct ByzantineNode {
+    node_id: u32,
+    chain: Arc<Chain>,
+    forgery_delay: Duration,
+    next_forgery: Forgery,
+    nonce: Nonce,
+    /// The body of every forged block, which pays the coinbase to an address nobody controls.
+    body: Arc<BlockBody>,
+    logger: NodeLogger,
+}
+
+impl ByzantineNode {
+    pub fn new(node_id: u32, genesis_chain: Arc<Chain>, forgery_delay: Duration) -> ByzantineNode {
+        ByzantineNode {
+            node_id,
+            chain: genesis_chain,
+            forgery_delay,
+            next_forgery: Forgery::BadHash,
+            nonce: Nonce::new(),
+            body: Arc::new(BlockBody::genesis(&GenesisConfig::default())),
+            logger: NodeLogger::new(node_id, clock::now()),
+        }
+    }
+
+    /// Tags the log lines of this node with the given logger, from the start of the simulation.
+    pub fn with_logger(mut self, logger: NodeLogger) -> ByzantineNode {
+        self.logger = logger;
+        self
+    }
+
+    /// Builds the next forged chain, on top of the strongest known one.
+    fn forge(&mut self) -> Arc<Chain> {
+        let forgery = self.next_forgery;
+        self.next_forgery = forgery.next();
+
+        let difficulty = self.chain.head().difficulty().clone();
+        let height = self.chain.height() + 1;
+        let block = match forgery {
+            Forgery::BadHash => Block {
+                hash: Hash::from([0; SHA256_OUTPUT_LEN]),
+                ..self.mine(&difficulty, height, 1)
+            },
+            Forgery::WrongDifficulty => {
+                self.mine(&Arc::new(Difficulty::min_difficulty()), height, MAX_FORGING_ATTEMPTS)
+            }
+            Forgery::BrokenHeightLink => self.mine(&difficulty, height + 1, MAX_FORGING_ATTEMPTS),
+        };
+
+        self.logger.debug(
+            "forgery",
+            format_args!("Forged a block: {:?}, {}, height {}", forgery, block.hash(), block.height),
+        );
+        Arc::new(Chain::unvalidated_expand(&self.chain, block))
+    }
+
+    /// Tries the next nonces until the hash of the block on top of the known chain does not
+    /// exceed the difficulty, or until the attempts are exhausted.
+    fn mine(&mut self, difficulty: &Arc<Difficulty>, height: u32, attempts: u32) -> Block {
+        let head = self.chain.head();
+        let mut attempt = 0;
+        loop {
+            if self.nonce.increment().is_err() {
+                self.nonce = Nonce::new();
+            }
+            let block = Block::new(
+                self.node_id,
+                self.nonce.clone(),
+                0,
+                difficulty,
+                head.hasher(),
+                head.hash().clone(),
+                height,
+                &self.body,
+            );
+
+            attempt += 1;
+            if block.hash().is_below(&difficulty.threshold().to_be_bytes()) || attempt >= attempts {
+                return block;
+            }
+        }
+    }
+
+    /// Keeps the chain if it is stronger than the known one. Only its head is checked, which
+    /// is enough to tell the chains forged by the other byzantine nodes.
+    fn receive_chain(&mut self, chain: Arc<Chain>) {
+        let valid_head = chain
+            .tail()
+            .is_some_and(|tail| chain.validate_link(tail.head(), chain.head()).is_ok());
+
+        if valid_head && chain.stronger_than(&self.chain) {
+            self.chain = chain;
+        }
+    }
+
+    /// Sends a forged chain to every peer, once the known chain has a first block to forge on
+    /// top of.
+    fn send_forgery(&mut self, peers: &mut Vec<ConnectionSender<Message>>) {
+        if self.chain.height() == 0 {
+            return;
+        }
+
+        let forged_chain = self.forge();
+        peers.retain_mut(|peer| match peer.try_send(Message::Chain(forged_chain.clone())) {
+            Err(netsim::error::Error::Disconnected) => false,
+            // A congested peer gets the next forgery.
+            _ => true,
+        });
+    }
+
+    /// Handles the events of the peers and the forgery attempts one at a time.
+    async fn route<S>(&mut self, mut connection_stream: S) -> Result<(), Error>
+    where
+        S: Stream<Item = Result<ConnectionEvent<Message>, netsim::error::Error>> + Unpin,
+    {
+        let mut forgery_attempts = Box::pin(interval_stream(self.forgery_delay));
+        // The messages of every peer, polled in turn.
+        let mut receptions = SelectAll::new();
+        let mut peers = vec![];
+
+        loop {
+            let node_event = tokio::select! {
+                Some(event) = connection_stream.next() => match event? {
+                    ConnectionEvent::Opened(connection) => {
+                        self.logger.debug("peer", format_args!("Connection received."));
+                        let (sender, receiver) = connection.split();
+                        receptions.push(byzantine_reception(receiver, sender.clone()));
+                        ByzantineNodeEvent::Peer(sender)
+                    }
+                    ConnectionEvent::Closed(peer_id) => ByzantineNodeEvent::PeerClosed(peer_id),
+                },
+                Some(byzantine_node_event) = receptions.next() => byzantine_node_event,
+                Some(_instant) = forgery_attempts.next() => ByzantineNodeEvent::ForgeryAttempt,
+                else => return Ok(()),
+            };
+
+            match node_event {
+                ByzantineNodeEvent::Peer(sender) => peers.push(sender),
+                ByzantineNodeEvent::PeerClosed(peer_id) => peers.retain(|sender| sender.peer_id() != peer_id),
+                ByzantineNodeEvent::Chain(chain) => self.receive_chain(chain),
+                ByzantineNodeEvent::Ping(sent_at, mut sender) => {
+                    if let Err(err) = sender.try_send(Message::Pong(sent_at)) {
+                        self.logger.debug("ping", format_args!("Pong not sent: {}", err));
+                    }
+                }
+                ByzantineNodeEvent::ForgeryAttempt => self.send_forgery(&mut peers),
+            }
+        }
+    }
+}
+
+/// The events caused by the messages of a peer. Its pings are answered through the given
+/// sender, its other requests are ignored.
+fn byzantine_reception(
+    receiver: Receiver<Message>,
+    reply_sender: ConnectionSender<Message>,
+) -> impl Stream<Item = ByzantineNodeEvent> + Send + Unpin {
+    receiver.filter_map(move |message| {
+        future::ready(match message {
+            Message::Chain(chain) => Some(ByzantineNodeEvent::Chain(chain)),
+            Message::Ping(sent_at) => Some(ByzantineNodeEvent::Ping(sent_at, reply_sender.clone())),
+            Message::ProofRequest(_)
+            | Message::Proof(_)
+            | Message::Pong(_)
+            | Message::SendCompact
+            | Message::CompactBlock(_)
+            | Message::GetBlockTransactions(..)
+            | Message::BlockTransactions(..)
+            | Message::GetChain
+            | Message::RequestedChain(_)
+            | Message::Inventory(_)
+            | Message::GetTransactions(_)
+            | Message::Transactions(_)
+            | Message::FilterLoad(_)
+            | Message::Vote(_) => None,
+        })
+    })
+}
+
+impl Node<Message> for ByzantineNode {
+    type Output = ();
+
+    fn run<S>(mut self, connection_stream: S) -> Pin<Box<dyn Future<Output = ()> + Send>>
+    where
+        S: Stream<Item = Result<ConnectionEvent<Message>, netsim::error::Error>> + Send + Unpin + 'static,
+    {
+        Box::pin(async move {
+            if let Err(err) = self.route(connection_stream).await {
+                self.logger.error("node", format_args!("Stopped: {}", err));
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ChainError;
+    use btclike::blockchain::Body;
+    use btclike::crypto;
+    use btclike::transaction::{Address, TxOut};
+
+    #[test]
+    fn forges_chains_breaking_each_rule_in_turn() {
+        let mut difficulty = Difficulty::min_difficulty();
+        for _i in 0..4 {
+            difficulty.increase().unwrap();
+        }
+        let genesis_chain = Arc::new(Chain::init_new(difficulty));
+        let mut node = ByzantineNode::new(1, genesis_chain.clone(), Duration::from_secs(1));
+        // The burn address was paid by the genesis block already.
+        let reward = genesis_chain.params().block_reward(1);
+        let coinbase_tx_out = TxOut::new(reward, Address::from_hash(crypto::hash(b"Surcouf")));
+        node.body = Arc::new(BlockBody::new(Body::new(coinbase_tx_out, vec![])).unwrap());
+        let first_block = node.mine(&genesis_chain.head().difficulty().clone(), 1, MAX_FORGING_ATTEMPTS);
+        let chain = Chain::expand(&genesis_chain, first_block).unwrap();
+        node.receive_chain(chain.clone());
+        let utxo_set = chain.validate().unwrap();
+
+        for expected_error in [
+            ChainError::InvalidHash,
+            ChainError::InvalidDifficulty,
+            ChainError::HeightMismatch,
+            ChainError::InvalidHash,
+        ] {
+            let forged_chain = node.forge();
+            assert_eq!(Err(expected_error), forged_chain.validate_from(&chain, &utxo_set).map(|_| ()));
+            assert!(expected_error.is_forged());
+            // The other byzantine nodes do not fool it either.
+            node.receive_chain(forged_chain);
+            assert!(Arc::ptr_eq(&chain, &node.chain));
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use crate::blockchain::pow::{hash_header, Nonce};
+use crate::blockchain::Difficulty;
+use btclike::crypto::Hasher;
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::hint;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The highest difficulty factor accepted.
+pub const MAX_DIFFICULTY_FACTOR: u8 = 224;
+/// How long the hash rate of the host is measured.
+const BENCHMARK_DURATION: Duration = Duration::from_millis(200);
+
+/// How the difficulty of a simulation is chosen: either given, or calibrated so that blocks
+/// are mined approximately every block interval on this host.
+///
+/// Parsed from a difficulty factor, or from one of the presets: `fast`, `normal` and `slow`
+/// blocks, mined every second, ten seconds and minute.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "RawDifficultySetting")]
+pub enum DifficultySetting {
+    /// The number of times the minimum difficulty is doubled.
+    Factor(u8),
+    BlockInterval(Duration),
+}
+
+impl DifficultySetting {
+    /// The difficulty factor of the setting, calibrated for the given miners if needed.
+    pub fn difficulty_factor(&self, hasher: Hasher, miners: u32, mining_attempt_delay: Duration) -> u8 {
+        match *self {
+            DifficultySetting::Factor(difficulty_factor) => difficulty_factor,
+            DifficultySetting::BlockInterval(block_interval) => {
+                calibrate(hasher, miners, mining_attempt_delay, block_interval)
+            }
+        }
+    }
+}
+
+impl FromStr for DifficultySetting {
+    type Err = String;
+
+    fn from_str(raw_setting: &str) -> Result<DifficultySetting, String> {
+        match raw_setting {
+            "fast" => Ok(DifficultySetting::BlockInterval(Duration::from_secs(1))),
+            "normal" => Ok(DifficultySetting::BlockInterval(Duration::from_secs(10))),
+            "slow" => Ok(DifficultySetting::BlockInterval(Duration::from_secs(60))),
+            raw_factor => match raw_factor.parse() {
+                Ok(factor) if factor <= MAX_DIFFICULTY_FACTOR => Ok(DifficultySetting::Factor(factor)),
+                _ => Err(format!(
+                    "Invalid difficulty {}, expected [0-{}], fast, normal or slow",
+                    raw_setting, MAX_DIFFICULTY_FACTOR
+                )),
+            },
+        }
+    }
+}
+
+/// A difficulty factor or a preset, in a configuration file.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawDifficultySetting {
+    Factor(u64),
+    Preset(String),
+}
+
+impl TryFrom<RawDifficultySetting> for DifficultySetting {
+    type Error = String;
+
+    fn try_from(raw_setting: RawDifficultySetting) -> Result<DifficultySetting, String> {
+        match raw_setting {
+            RawDifficultySetting::Factor(factor) => factor.to_string().parse(),
+            RawDifficultySetting::Preset(preset) => preset.parse(),
+        }
+    }
+}
+
+/// The difficulty factor giving approximately the given block interval on this host. Every
+/// miner attempts a hash per mining attempt delay, unless the host cannot keep up with them.
+pub fn calibrate(hasher: Hasher, miners: u32, mining_attempt_delay: Duration, block_interval: Duration) -> u8 {
+    let miners_hash_rate = f64::from(miners) / mining_attempt_delay.as_secs_f64();
+    let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+    let host_hash_rate = host_hash_rate(hasher) * threads as f64;
+    let hash_rate = miners_hash_rate.min(host_hash_rate);
+    let difficulty_factor = difficulty_factor(hash_rate, block_interval);
+
+    info!(
+        "Calibration: {:.0} hashes/s for the miners, {:.0} hashes/s for the host, difficulty factor {} for a block every {:?}",
+        miners_hash_rate, host_hash_rate, difficulty_factor, block_interval
+    );
+    difficulty_factor
+}
+
+/// The difficulty factor for which the given hash rate mines a block approximately every
+/// block interval, the one whose expected number of hashes per block is the closest.
+pub fn difficulty_factor(hash_rate: f64, block_interval: Duration) -> u8 {
+    let hashes_per_block = (hash_rate * block_interval.as_secs_f64()).clamp(1.0, f64::MAX).log2();
+    let mut difficulty = Difficulty::min_difficulty();
+    let mut best_factor = 0;
+    let mut best_distance = (work_log2(&difficulty) - hashes_per_block).abs();
+
+    for factor in 1..=MAX_DIFFICULTY_FACTOR {
+        if difficulty.increase().is_err() {
+            break;
+        }
+
+        let distance = (work_log2(&difficulty) - hashes_per_block).abs();
+        if distance < best_distance {
+            best_factor = factor;
+            best_distance = distance;
+        }
+    }
+
+    best_factor
+}
+
+/// The binary logarithm of the expected number of hashes needed to mine a block.
+fn work_log2(difficulty: &Difficulty) -> f64 {
+    difficulty
+        .work()
+        .to_be_bytes()
+        .iter()
+        .fold(0.0, |work, byte| work * 256.0 + f64::from(*byte))
+        .log2()
+}
+
+/// How many proof of work hashes a single thread of this host computes per second.
+pub fn host_hash_rate(hasher: Hasher) -> f64 {
+    let difficulty = Difficulty::min_difficulty();
+    let previous_hash = [0u8; 32];
+    let body_hash = [0u8; 32];
+    let mut nonce = Nonce::new();
+    let mut hashes = 0u64;
+
+    let start = Instant::now();
+    while start.elapsed() < BENCHMARK_DURATION {
+        if nonce.increment().is_err() {
+            nonce = Nonce::new();
+        }
+
+        hint::black_box(hash_header(0, &nonce, 0, &difficulty, hasher, 1, &previous_hash, &body_hash));
+        hashes += 1;
+    }
+
+    hashes as f64 / start.elapsed().as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_the_difficulty_with_the_hashes_per_block() {
+        assert_eq!(0, difficulty_factor(0.5, Duration::from_secs(1)));
+        assert_eq!(6, difficulty_factor(64.0, Duration::from_secs(1)));
+        assert_eq!(7, difficulty_factor(64.0, Duration::from_secs(2)));
+        // The factor 8 halves the threshold twice.
+        assert_eq!(9, difficulty_factor(1024.0, Duration::from_secs(1)));
+        assert_eq!(MAX_DIFFICULTY_FACTOR, difficulty_factor(f64::MAX, Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn parses_factors_and_presets() {
+        assert_eq!(Ok(DifficultySetting::Factor(15)), "15".parse());
+        assert_eq!(
+            Ok(DifficultySetting::BlockInterval(Duration::from_secs(10))),
+            "normal".parse()
+        );
+        assert!("225".parse::<DifficultySetting>().is_err());
+        assert!("hard".parse::<DifficultySetting>().is_err());
+    }
+}
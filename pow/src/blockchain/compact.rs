@@ -0,0 +1,251 @@
+use crate::blockchain::{Block, BlockBody, BlockHeader};
+use btclike::blockchain::Body;
+use btclike::crypto::{self, Hash};
+use btclike::mempool::Mempool;
+use btclike::transaction::{SignedTx, TxOut};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The number of bytes of the transaction hashes kept as short ids.
+pub const SHORT_ID_LEN: usize = 6;
+
+const COMPACT_ERROR_SERIALIZATION: &str = "Could not serialize the transactions";
+const COMPACT_ERROR_TRANSACTION_COUNT: &str = "Unexpected number of transactions";
+const COMPACT_ERROR_MISSING_TRANSACTIONS: &str = "Missing transactions";
+const COMPACT_ERROR_RECONSTRUCTION: &str = "The transactions do not match the header";
+
+/// The first bytes of the hash of a transaction. Unlike Bitcoin's, they are not salted: the
+/// nodes of the simulation do not try to collide them.
+pub type ShortId = [u8; SHORT_ID_LEN];
+
+fn short_id(hash: &crypto::Hash) -> ShortId {
+    let mut short_id = [0u8; SHORT_ID_LEN];
+    short_id.copy_from_slice(&hash.as_ref()[..SHORT_ID_LEN]);
+    short_id
+}
+
+fn serialized_size(transaction: &SignedTx) -> Result<usize, &'static str> {
+    bincode::serialized_size(transaction)
+        .map(|size| size as usize)
+        .map_err(|_| COMPACT_ERROR_SERIALIZATION)
+}
+
+/// The size of the given transactions sent to a peer, in bytes.
+pub fn transactions_size(transactions: &[SignedTx]) -> Result<usize, &'static str> {
+    transactions.iter().map(serialized_size).sum()
+}
+
+/// A block announced by its header, its coinbase and the short ids of its other transactions,
+/// which the peers find in their mempools, as in Bitcoin's BIP152.
+pub struct CompactBlock {
+    header: BlockHeader,
+    coinbase_tx_out: TxOut,
+    short_ids: Vec<ShortId>,
+}
+
+impl CompactBlock {
+    pub fn new(block: &Block) -> Result<CompactBlock, &'static str> {
+        let body = block.body().body();
+        let short_ids = body
+            .transactions()
+            .iter()
+            .map(|transaction| transaction.hash().map(|hash| short_id(&hash)))
+            .collect::<Result<Vec<ShortId>, _>>()
+            .map_err(|_| COMPACT_ERROR_SERIALIZATION)?;
+
+        Ok(CompactBlock {
+            header: block.header(),
+            coinbase_tx_out: body.coinbase_tx().0.clone(),
+            short_ids,
+        })
+    }
+
+    pub fn hash(&self) -> &Hash {
+        self.header.hash()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.header.height()
+    }
+
+    pub fn previous_block_hash(&self) -> &Hash {
+        &self.header.previous_block_hash
+    }
+
+    /// The size of the compact block sent to a peer, in bytes.
+    pub fn size(&self) -> usize {
+        let coinbase_size = bincode::serialized_size(&self.coinbase_tx_out).unwrap_or_default() as usize;
+        self.header.size() + coinbase_size + self.short_ids.len() * SHORT_ID_LEN
+    }
+}
+
+/// A compact block along with the transactions found so far, in the order of the block.
+pub struct PartialBlock {
+    compact_block: Arc<CompactBlock>,
+    /// The address of the peer that sent the compact block, and is asked for the missing
+    /// transactions.
+    peer_id: u32,
+    transactions: Vec<Option<SignedTx>>,
+}
+
+impl PartialBlock {
+    /// Finds the transactions of the compact block in the mempool, by short id.
+    pub fn new(peer_id: u32, compact_block: Arc<CompactBlock>, mempool: &Mempool) -> PartialBlock {
+        let pending: HashMap<ShortId, &SignedTx> = mempool
+            .entries()
+            .map(|entry| (short_id(entry.hash()), entry.transaction()))
+            .collect();
+        let transactions = compact_block
+            .short_ids
+            .iter()
+            .map(|short_id| pending.get(short_id).map(|transaction| (*transaction).clone()))
+            .collect();
+
+        PartialBlock {
+            compact_block,
+            peer_id,
+            transactions,
+        }
+    }
+
+    pub fn compact_block(&self) -> &Arc<CompactBlock> {
+        &self.compact_block
+    }
+
+    pub fn peer_id(&self) -> u32 {
+        self.peer_id
+    }
+
+    /// The indexes of the transactions that are still missing, coinbase excluded.
+    pub fn missing(&self) -> Vec<u32> {
+        self.transactions
+            .iter()
+            .enumerate()
+            .filter(|(_index, transaction)| transaction.is_none())
+            .map(|(index, _transaction)| index as u32)
+            .collect()
+    }
+
+    /// Adds the missing transactions, in the order of their indexes.
+    pub fn fill(&mut self, transactions: Vec<SignedTx>) -> Result<(), &'static str> {
+        let mut missing: Vec<&mut Option<SignedTx>> =
+            self.transactions.iter_mut().filter(|transaction| transaction.is_none()).collect();
+        if missing.len() != transactions.len() {
+            return Err(COMPACT_ERROR_TRANSACTION_COUNT);
+        }
+
+        for (slot, transaction) in missing.iter_mut().zip(transactions) {
+            **slot = Some(transaction);
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the block. Fails if transactions are missing, or if the ones found do not
+    /// match the header, which happens when short ids collide.
+    pub fn block(self) -> Result<Block, &'static str> {
+        let transactions = self
+            .transactions
+            .into_iter()
+            .collect::<Option<Vec<SignedTx>>>()
+            .ok_or(COMPACT_ERROR_MISSING_TRANSACTIONS)?;
+        let header = &self.compact_block.header;
+        let body = Body::new(self.compact_block.coinbase_tx_out.clone(), transactions);
+        let body = Arc::new(BlockBody::new(body)?);
+
+        let mut block = Block::new(
+            header.node_id,
+            header.nonce.clone(),
+            header.extra_nonce,
+            &header.difficulty,
+            header.hasher,
+            header.previous_block_hash.clone(),
+            header.height,
+            &body,
+        );
+        // The signature is not part of the hash input, it is checked with the chain.
+        block.signature = header.signature.clone();
+        if block.hash() == header.hash() {
+            Ok(block)
+        } else {
+            Err(COMPACT_ERROR_RECONSTRUCTION)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::pow::Nonce;
+    use crate::blockchain::{Chain, Difficulty};
+    use btclike::blockchain::COINBASE_MATURITY;
+    use btclike::transaction::Address;
+    use btclike::wallet::Wallet;
+
+    fn next_block(chain: &Arc<Chain>, nonce: &mut Nonce, body: Body) -> Block {
+        nonce.increment().unwrap();
+        Block::new(
+            1,
+            nonce.clone(),
+            0,
+            chain.head().difficulty(),
+            chain.head().hasher(),
+            chain.head().hash().clone(),
+            chain.height() + 1,
+            &Arc::new(BlockBody::new(body).unwrap()),
+        )
+    }
+
+    #[test]
+    fn rebuilds_a_block_from_the_mempool_and_the_missing_transactions() {
+        let mut chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let mut nonce = Nonce::new();
+        let burn_address = Address::from_hash(crypto::hash(b"Surcouf"));
+        // Every wallet gets a coinbase, so that their payments do not conflict.
+        let mut wallets = [Wallet::new(), Wallet::new(), Wallet::new()];
+        for wallet in wallets.iter_mut() {
+            let reward = chain.params().block_reward(chain.height() + 1);
+            let coinbase_tx_out = TxOut::new(reward, wallet.new_address().unwrap());
+            let block = next_block(&chain, &mut nonce, Body::new(coinbase_tx_out, vec![]));
+            wallet.connect_block(block.body().body(), chain.height() + 1).unwrap();
+            chain = Chain::expand(&chain, block).unwrap();
+        }
+        // Identical coinbases would have the same hash.
+        let mut miner = Wallet::new();
+        for _i in 0..COINBASE_MATURITY {
+            let reward = chain.params().block_reward(chain.height() + 1);
+            let coinbase_tx_out = TxOut::new(reward, miner.new_address().unwrap());
+            let block = next_block(&chain, &mut nonce, Body::new(coinbase_tx_out, vec![]));
+            chain = Chain::expand(&chain, block).unwrap();
+        }
+
+        let utxo_set = chain.validate().unwrap();
+        let height = chain.height() + 1;
+        let mut mempool = Mempool::new();
+        let mut transactions = vec![];
+        for wallet in wallets.iter_mut() {
+            let transaction = wallet.new_transaction(10, burn_address.clone(), 1, &utxo_set).unwrap();
+            mempool.add(transaction.clone(), &utxo_set, height).unwrap();
+            transactions.push(transaction);
+        }
+        let coinbase_tx_out = TxOut::new(chain.params().block_reward(height) + 3, burn_address);
+        let block = next_block(&chain, &mut nonce, Body::new(coinbase_tx_out, transactions.clone()));
+        let compact_block = Arc::new(CompactBlock::new(&block).unwrap());
+        assert!(compact_block.size() < block.size());
+
+        // The second payment never reached this mempool.
+        mempool.remove(&transactions[1].hash().unwrap());
+        let mut partial_block = PartialBlock::new(2, compact_block.clone(), &mempool);
+        assert_eq!(vec![1], partial_block.missing());
+        assert!(partial_block.fill(vec![]).is_err());
+        partial_block.fill(vec![transactions[1].clone()]).unwrap();
+        let rebuilt_block = partial_block.block().unwrap();
+        assert_eq!(block.hash(), rebuilt_block.hash());
+        let rebuilt_chain = Chain::expand(&chain, rebuilt_block).unwrap();
+        rebuilt_chain.validate_from(&chain, &utxo_set).unwrap();
+
+        // Other transactions do not make the same block.
+        let mut partial_block = PartialBlock::new(2, compact_block, &Mempool::new());
+        partial_block.fill(vec![transactions[2].clone(); 3]).unwrap();
+        assert_eq!(Err(COMPACT_ERROR_RECONSTRUCTION), partial_block.block().map(|_| ()));
+    }
+}
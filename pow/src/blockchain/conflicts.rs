@@ -0,0 +1,106 @@
+use btclike::mempool::Mempool;
+use btclike::policy::PolicyRejections;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// The double spend attempts, replacements and policy rejections of a mempool.
+type MempoolCounters = (u64, u64, PolicyRejections);
+
+/// Gathers the double spend counters of the mempools of all the nodes of a network, to measure
+/// how often conflicting payments race during a run, along with the payments their relay
+/// policies refused.
+#[derive(Clone, Default)]
+pub struct DoubleSpendCounter {
+    /// The latest counters reported by each node.
+    inner: Arc<Mutex<HashMap<u32, MempoolCounters>>>,
+}
+
+impl DoubleSpendCounter {
+    pub fn new() -> DoubleSpendCounter {
+        DoubleSpendCounter::default()
+    }
+
+    /// Records the current counters of the mempool of a node, replacing the previous ones.
+    pub fn report(&self, node_id: u32, mempool: &Mempool) {
+        self.inner
+            .lock()
+            .expect("Poisoned double spend counter")
+            .insert(
+                node_id,
+                (mempool.double_spend_attempts(), mempool.replacements(), mempool.policy_rejections()),
+            );
+    }
+
+    /// The total number of double spend attempts observed by the nodes.
+    pub fn attempts(&self) -> u64 {
+        self.inner
+            .lock()
+            .expect("Poisoned double spend counter")
+            .values()
+            .map(|counters| counters.0)
+            .sum()
+    }
+
+    /// The total number of transactions that replaced conflicting ones.
+    pub fn replacements(&self) -> u64 {
+        self.inner
+            .lock()
+            .expect("Poisoned double spend counter")
+            .values()
+            .map(|counters| counters.1)
+            .sum()
+    }
+
+    /// The total number of valid payments the relay policies of the nodes refused, by rule.
+    pub fn policy_rejections(&self) -> PolicyRejections {
+        self.inner
+            .lock()
+            .expect("Poisoned double spend counter")
+            .values()
+            .fold(PolicyRejections::default(), |total, counters| total + counters.2)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btclike::blockchain::{Body, COINBASE_AMOUNT, COINBASE_MATURITY};
+    use btclike::mempool::ConflictPolicy;
+    use btclike::policy::RelayPolicy;
+    use btclike::transaction::TxOut;
+    use btclike::utxo::UtxoSet;
+    use btclike::wallet::Wallet;
+
+    #[test]
+    fn sums_the_latest_counters_of_every_node() {
+        let mut wallet = Wallet::new();
+        let mut utxo_set = UtxoSet::new();
+        let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT, wallet.new_address().unwrap());
+        let coinbase_body = Body::new(coinbase_tx_out, vec![]);
+        utxo_set.apply(&coinbase_body, 0).unwrap();
+        wallet.connect_block(&coinbase_body, 0).unwrap();
+
+        let counter = DoubleSpendCounter::new();
+        let mut first_seen_mempool = Mempool::new();
+        let mut rbf_mempool =
+            Mempool::new().with_conflict_policy(ConflictPolicy::ReplaceByFee { min_fee_bump: 1 });
+        let mut dust_mempool = Mempool::new().with_relay_policy(RelayPolicy::new().with_dust_threshold(11));
+
+        for fees in 1..4 {
+            let to_address = wallet.new_address().unwrap();
+            let tx = wallet.new_transaction(10, to_address, fees, &utxo_set).unwrap();
+            let _ = first_seen_mempool.add(tx.clone(), &utxo_set, COINBASE_MATURITY);
+            let _ = rbf_mempool.add(tx.clone(), &utxo_set, COINBASE_MATURITY);
+            let _ = dust_mempool.add(tx, &utxo_set, COINBASE_MATURITY);
+
+            counter.report(0, &first_seen_mempool);
+            counter.report(1, &rbf_mempool);
+            counter.report(2, &dust_mempool);
+        }
+
+        assert_eq!(4, counter.attempts());
+        assert_eq!(2, counter.replacements());
+        assert_eq!(3, counter.policy_rejections().dust);
+        assert_eq!(3, counter.policy_rejections().total());
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::{Duration, Instant};
+
+/// The anchors a node protects from eviction, unless told otherwise.
+pub const DEFAULT_ANCHORS: usize = 2;
+
+/// Which peer a node drops when it has more connections than allowed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EvictionPolicy {
+    /// The peer connected for the longest time.
+    Oldest,
+    /// The peer with the highest round trip time. The peers not measured yet are kept, unless
+    /// none is measured: the oldest one is dropped then.
+    WorstLatency,
+}
+
+/// A connection of a node, as seen by its eviction policy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerConnection {
+    pub peer_id: u32,
+    /// Whether the peer initiated the connection.
+    pub inbound: bool,
+    /// Whether the connection is one of the first ones the node initiated, never evicted.
+    pub anchor: bool,
+    pub opened_at: Instant,
+    /// The smoothed round trip time of the connection, none until it is measured.
+    pub round_trip_time: Option<Duration>,
+}
+
+/// How many connections a node keeps in each direction, and which peers it evicts once it
+/// has too many. No limit by default.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConnectionLimits {
+    max_inbound: Option<usize>,
+    max_outbound: Option<usize>,
+    eviction_policy: EvictionPolicy,
+    anchors: usize,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> ConnectionLimits {
+        ConnectionLimits::new()
+    }
+}
+
+impl ConnectionLimits {
+    pub fn new() -> ConnectionLimits {
+        ConnectionLimits {
+            max_inbound: None,
+            max_outbound: None,
+            eviction_policy: EvictionPolicy::Oldest,
+            anchors: DEFAULT_ANCHORS,
+        }
+    }
+
+    /// The connections the peers initiate a node keeps at most.
+    pub fn with_max_inbound(mut self, max_inbound: usize) -> ConnectionLimits {
+        self.max_inbound = Some(max_inbound);
+        self
+    }
+
+    /// The connections a node initiates it keeps at most.
+    pub fn with_max_outbound(mut self, max_outbound: usize) -> ConnectionLimits {
+        self.max_outbound = Some(max_outbound);
+        self
+    }
+
+    /// Default: the oldest peer
+    pub fn with_eviction_policy(mut self, eviction_policy: EvictionPolicy) -> ConnectionLimits {
+        self.eviction_policy = eviction_policy;
+        self
+    }
+
+    /// The first connections a node initiates that are never evicted, so that peers
+    /// connecting at will cannot surround it. Default: 2
+    pub fn with_anchors(mut self, anchors: usize) -> ConnectionLimits {
+        self.anchors = anchors;
+        self
+    }
+
+    pub fn anchors(&self) -> usize {
+        self.anchors
+    }
+
+    /// The peer to evict once the new connection is opened, if the connections in its
+    /// direction exceed the limit: one of the given ones in the same direction, but the
+    /// anchors, or the new one if they all are anchors.
+    pub fn evict(&self, connections: &[PeerConnection], new_connection: &PeerConnection) -> Option<u32> {
+        let max_connections = if new_connection.inbound { self.max_inbound } else { self.max_outbound }?;
+        let same_direction = connections.iter().filter(|connection| connection.inbound == new_connection.inbound);
+        if same_direction.clone().count() < max_connections {
+            return None;
+        }
+
+        let candidates: Vec<&PeerConnection> = same_direction.filter(|connection| !connection.anchor).collect();
+        let oldest = candidates.iter().min_by_key(|connection| connection.opened_at);
+        let evicted = match self.eviction_policy {
+            EvictionPolicy::Oldest => oldest,
+            EvictionPolicy::WorstLatency => candidates
+                .iter()
+                .filter(|connection| connection.round_trip_time.is_some())
+                .max_by_key(|connection| connection.round_trip_time)
+                .or(oldest),
+        };
+        Some(evicted.map_or(new_connection.peer_id, |connection| connection.peer_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connection(peer_id: u32, inbound: bool, opened_at: Instant, round_trip_time_ms: Option<u64>) -> PeerConnection {
+        PeerConnection {
+            peer_id,
+            inbound,
+            anchor: false,
+            opened_at,
+            round_trip_time: round_trip_time_ms.map(Duration::from_millis),
+        }
+    }
+
+    #[test]
+    fn evicts_a_peer_once_the_limit_is_exceeded() {
+        let start = Instant::now();
+        let limits = ConnectionLimits::new().with_max_inbound(2);
+        let mut connections = vec![
+            connection(1, true, start, Some(50)),
+            connection(2, true, start + Duration::from_secs(1), Some(200)),
+            connection(3, false, start, None),
+        ];
+        let new_connection = connection(4, true, start + Duration::from_secs(2), None);
+
+        assert_eq!(None, limits.evict(&connections[..1], &new_connection));
+        assert_eq!(Some(1), limits.evict(&connections, &new_connection));
+        let worst_latency = limits.with_eviction_policy(EvictionPolicy::WorstLatency);
+        assert_eq!(Some(2), worst_latency.evict(&connections, &new_connection));
+        // Without a limit of outbound connections.
+        assert_eq!(None, limits.evict(&connections, &connection(5, false, start, None)));
+
+        // The anchors are kept, even if it means refusing the new peer.
+        connections[0].anchor = true;
+        assert_eq!(Some(2), limits.evict(&connections, &new_connection));
+        connections[1].anchor = true;
+        assert_eq!(Some(4), limits.evict(&connections, &new_connection));
+    }
+}
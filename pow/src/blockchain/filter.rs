@@ -0,0 +1,108 @@
+use crate::blockchain::NodeMetric;
+
+/// The default false positive rate of the Bloom filters of the light nodes.
+pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// How the Bloom filters of the light nodes sorted the transactions: how many the full nodes
+/// tested and sent, and how many of the ones the light nodes received were theirs.
+#[derive(Default)]
+pub struct FilterStats {
+    tested: u64,
+    matched: u64,
+    received: u64,
+    relevant: u64,
+}
+
+impl FilterStats {
+    pub fn new() -> FilterStats {
+        FilterStats::default()
+    }
+
+    pub fn record(&mut self, metric: &NodeMetric) {
+        match *metric {
+            NodeMetric::TransactionsFiltered { tested, matched, .. } => {
+                self.tested += tested as u64;
+                self.matched += matched as u64;
+            }
+            NodeMetric::FilteredTransactionsReceived {
+                transactions, relevant, ..
+            } => {
+                self.received += transactions as u64;
+                self.relevant += relevant as u64;
+            }
+            _ => {}
+        }
+    }
+
+    /// The transactions tested against the filters, once per light peer.
+    pub fn tested(&self) -> u64 {
+        self.tested
+    }
+
+    /// The tested transactions that matched, and were sent to the light peers.
+    pub fn matched(&self) -> u64 {
+        self.matched
+    }
+
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+
+    /// The received transactions paying the light nodes, the others being false positives.
+    pub fn relevant(&self) -> u64 {
+        self.relevant
+    }
+
+    /// The share of the transactions not paying the light nodes that matched their filters
+    /// anyway, in percent. Assumes that no matching transaction was lost on the way, none if
+    /// every transaction tested was relevant.
+    pub fn false_positive_rate(&self) -> Option<f64> {
+        let irrelevant = self.tested.saturating_sub(self.relevant);
+        if irrelevant == 0 {
+            None
+        } else {
+            Some(100.0 * self.matched.saturating_sub(self.relevant) as f64 / irrelevant as f64)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_the_false_positive_rate() {
+        let mut stats = FilterStats::new();
+        assert_eq!(None, stats.false_positive_rate());
+
+        for metric in [
+            NodeMetric::TransactionsFiltered {
+                node_id: 4,
+                tested: 60,
+                matched: 10,
+            },
+            NodeMetric::TransactionsFiltered {
+                node_id: 5,
+                tested: 50,
+                matched: 2,
+            },
+            NodeMetric::FilteredTransactionsReceived {
+                node_id: 0,
+                transactions: 12,
+                relevant: 10,
+            },
+            NodeMetric::TransactionsAnnounced {
+                node_id: 4,
+                transactions: 3,
+            },
+        ] {
+            stats.record(&metric);
+        }
+
+        assert_eq!(110, stats.tested());
+        assert_eq!(12, stats.matched());
+        assert_eq!(12, stats.received());
+        assert_eq!(10, stats.relevant());
+        assert_eq!(Some(2.0), stats.false_positive_rate());
+    }
+}
@@ -0,0 +1,310 @@
+use crate::blockchain::bootstrap::percentile;
+use crate::blockchain::{Authorities, NodeMetric};
+use crate::error::Error;
+use btclike::crypto::{Hash, KeyPair, Signature, SignatureAlgorithm};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The number of blocks on top of a block for it to be considered settled in proof of work,
+/// the depth the time to finality compares with.
+pub const CONFIRMATION_DEPTH: u32 = 6;
+
+const FINALITY_ERROR_UNKNOWN_VOTER: &str = "Unknown voter";
+const FINALITY_ERROR_INVALID_SIGNATURE: &str = "Invalid vote signature";
+const FINALITY_ERROR_NOT_A_CHECKPOINT: &str = "Vote for a height that is not a checkpoint";
+
+/// The vote of a voter for the block of its chain at a checkpoint height, signed with its key.
+#[derive(Clone)]
+pub struct CheckpointVote {
+    voter: u32,
+    height: u32,
+    block_hash: Hash,
+    signature: Signature,
+}
+
+impl CheckpointVote {
+    pub fn new(voter: u32, height: u32, block_hash: Hash, key_pair: &KeyPair) -> CheckpointVote {
+        let signature = key_pair.sign(&signed_bytes(height, &block_hash));
+        CheckpointVote {
+            voter,
+            height,
+            block_hash,
+            signature,
+        }
+    }
+
+    pub fn voter(&self) -> u32 {
+        self.voter
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn block_hash(&self) -> &Hash {
+        &self.block_hash
+    }
+}
+
+/// The height and the hash of the checkpoint, which the voters sign.
+fn signed_bytes(height: u32, block_hash: &Hash) -> Vec<u8> {
+    let mut bytes = height.to_le_bytes().to_vec();
+    bytes.extend_from_slice(block_hash.as_ref());
+    bytes
+}
+
+/// The nodes voting on the checkpoints of a chain, every given number of blocks, with their
+/// weights and the public keys their votes are signed with.
+pub struct FinalityVoters {
+    checkpoint_interval: u32,
+    /// The keys the voters sign their votes with.
+    authorities: Authorities,
+    weights: BTreeMap<u32, u64>,
+}
+
+impl FinalityVoters {
+    /// Generates a key pair for each of the given nodes, which vote with the given weights on
+    /// the checkpoints every `checkpoint_interval` blocks. Returns the key pairs the nodes sign
+    /// their votes with, by node id.
+    pub fn generate<I: IntoIterator<Item = (u32, u64)>>(
+        weights: I,
+        checkpoint_interval: u32,
+        signature_algorithm: SignatureAlgorithm,
+    ) -> Result<(FinalityVoters, HashMap<u32, Arc<KeyPair>>), Error> {
+        let weights: BTreeMap<u32, u64> = weights.into_iter().collect();
+        let (authorities, key_pairs) = Authorities::generate(weights.keys().copied(), signature_algorithm)?;
+        Ok((
+            FinalityVoters {
+                checkpoint_interval,
+                authorities,
+                weights,
+            },
+            key_pairs,
+        ))
+    }
+
+    pub fn checkpoint_interval(&self) -> u32 {
+        self.checkpoint_interval
+    }
+
+    pub fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    pub fn total_weight(&self) -> u64 {
+        self.weights.values().sum()
+    }
+
+    pub fn is_checkpoint(&self, height: u32) -> bool {
+        height > 0 && height.is_multiple_of(self.checkpoint_interval)
+    }
+
+    /// The highest checkpoint of a chain of the given height, none below the first one.
+    pub fn last_checkpoint(&self, height: u32) -> Option<u32> {
+        Some(height - height % self.checkpoint_interval).filter(|&checkpoint| checkpoint > 0)
+    }
+
+    /// Checks that the vote is for a checkpoint, and signed by a voter.
+    pub fn verify(&self, vote: &CheckpointVote) -> Result<(), &'static str> {
+        if !self.is_checkpoint(vote.height) {
+            return Err(FINALITY_ERROR_NOT_A_CHECKPOINT);
+        }
+        if !self.weights.contains_key(&vote.voter) {
+            return Err(FINALITY_ERROR_UNKNOWN_VOTER);
+        }
+        self.authorities
+            .verify_signed(vote.voter, &signed_bytes(vote.height, &vote.block_hash), Some(&vote.signature))
+            .map_err(|_err| FINALITY_ERROR_INVALID_SIGNATURE)
+    }
+}
+
+/// The votes a node received on the checkpoints, and the last checkpoint it finalized. A
+/// checkpoint is finalized once voters holding 2/3 of the weight voted for the same block.
+/// The finalized checkpoints are reported, they do not change the choice of the chain.
+pub struct FinalityGadget {
+    voters: Arc<FinalityVoters>,
+    /// The weight of the votes for each block at a checkpoint height, above the finalized one.
+    tallies: HashMap<(u32, Hash), u64>,
+    /// The checkpoint heights each voter voted for. Its other votes at the same heights are
+    /// ignored.
+    votes: HashSet<(u32, u32)>,
+    finalized_height: u32,
+}
+
+impl FinalityGadget {
+    pub fn new(voters: Arc<FinalityVoters>) -> FinalityGadget {
+        FinalityGadget {
+            voters,
+            tallies: HashMap::new(),
+            votes: HashSet::new(),
+            finalized_height: 0,
+        }
+    }
+
+    pub fn voters(&self) -> &FinalityVoters {
+        &self.voters
+    }
+
+    pub fn finalized_height(&self) -> u32 {
+        self.finalized_height
+    }
+
+    /// Counts a vote with the others for the same block. Returns whether the vote is new, to
+    /// be relayed, and the height of the checkpoint it finalized, if any. Fails if the vote is
+    /// not signed by a voter.
+    pub fn add(&mut self, vote: &CheckpointVote) -> Result<(bool, Option<u32>), &'static str> {
+        self.voters.verify(vote)?;
+        if vote.height <= self.finalized_height || !self.votes.insert((vote.voter, vote.height)) {
+            return Ok((false, None));
+        }
+
+        let weight = self.voters.weights.get(&vote.voter).copied().unwrap_or(0);
+        let tally = self.tallies.entry((vote.height, vote.block_hash.clone())).or_insert(0);
+        *tally += weight;
+        if 3 * *tally < 2 * self.voters.total_weight() {
+            return Ok((true, None));
+        }
+
+        self.finalized_height = vote.height;
+        let finalized_height = self.finalized_height;
+        self.tallies.retain(|(height, _hash), _tally| *height > finalized_height);
+        self.votes.retain(|(_voter, height)| *height > finalized_height);
+        Ok((true, Some(finalized_height)))
+    }
+}
+
+/// Measures how long the checkpoints took to be finalized by the nodes once mined, compared
+/// with the time they took to reach the confirmation depth.
+pub struct FinalityStats {
+    checkpoint_interval: u32,
+    /// When the first block of each height was mined.
+    first_mined: HashMap<u32, Instant>,
+    /// When each checkpoint block was mined, with its height, by hash.
+    checkpoints: HashMap<String, (u32, Instant)>,
+    /// How long after being mined each node finalized each checkpoint.
+    times_to_finality: Vec<Duration>,
+    finalized_checkpoints: HashSet<String>,
+}
+
+impl FinalityStats {
+    pub fn new(checkpoint_interval: u32) -> FinalityStats {
+        FinalityStats {
+            checkpoint_interval,
+            first_mined: HashMap::new(),
+            checkpoints: HashMap::new(),
+            times_to_finality: vec![],
+            finalized_checkpoints: HashSet::new(),
+        }
+    }
+
+    pub fn record(&mut self, metric: &NodeMetric) {
+        match *metric {
+            NodeMetric::BlockMined { height, ref hash, .. } => {
+                let now = Instant::now();
+                self.first_mined.entry(height).or_insert(now);
+                if height.is_multiple_of(self.checkpoint_interval) {
+                    self.checkpoints.insert(hash.clone(), (height, now));
+                }
+            }
+            NodeMetric::CheckpointFinalized { ref hash, .. } => {
+                if let Some(&(_height, mined_at)) = self.checkpoints.get(hash) {
+                    self.times_to_finality.push(mined_at.elapsed());
+                }
+                self.finalized_checkpoints.insert(hash.clone());
+            }
+            _ => {}
+        }
+    }
+
+    pub fn mined_checkpoints(&self) -> u64 {
+        self.checkpoints.len() as u64
+    }
+
+    /// The checkpoints at least one node finalized.
+    pub fn finalized_checkpoints(&self) -> u64 {
+        self.finalized_checkpoints.len() as u64
+    }
+
+    /// The time the given percentage of the finalizations of the checkpoints by the nodes
+    /// did not exceed, since the checkpoints were mined.
+    pub fn time_to_finality_percentile(&self, percentage: u8) -> Option<Duration> {
+        percentile(self.times_to_finality.clone(), percentage)
+    }
+
+    /// The time the given percentage of the checkpoints took to be followed by a block at the
+    /// confirmation depth, on any chain.
+    pub fn time_to_depth_percentile(&self, percentage: u8) -> Option<Duration> {
+        percentile(
+            self.checkpoints
+                .values()
+                .filter_map(|&(height, mined_at)| {
+                    let confirmed_at = self.first_mined.get(&(height + CONFIRMATION_DEPTH))?;
+                    confirmed_at.checked_duration_since(mined_at)
+                })
+                .collect(),
+            percentage,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btclike::crypto;
+
+    fn hash(seed: u32) -> Hash {
+        Hash::from(*crypto::hash(&seed.to_le_bytes()).as_ref())
+    }
+
+    #[test]
+    fn finalizes_the_checkpoints_two_thirds_of_the_weight_voted_for() {
+        let (voters, key_pairs) =
+            FinalityVoters::generate(vec![(0, 1), (1, 1), (2, 2)], 5, SignatureAlgorithm::Ed25519).unwrap();
+        let mut gadget = FinalityGadget::new(Arc::new(voters));
+        let vote = |voter, height, block| CheckpointVote::new(voter, height, hash(block), &key_pairs[&voter]);
+
+        assert!(gadget.add(&vote(0, 4, 0)).is_err());
+        let forged_vote = CheckpointVote {
+            voter: 1,
+            ..vote(0, 5, 0)
+        };
+        assert!(gadget.add(&forged_vote).is_err());
+        assert_eq!(Ok((true, None)), gadget.add(&vote(0, 5, 0)));
+        assert_eq!(Ok((false, None)), gadget.add(&vote(0, 5, 1)));
+        assert_eq!(Ok((true, None)), gadget.add(&vote(1, 5, 1)));
+        // Half of the weight, then two thirds.
+        assert_eq!(Ok((true, None)), gadget.add(&vote(1, 10, 2)));
+        assert_eq!(Ok((true, Some(10))), gadget.add(&vote(2, 10, 2)));
+        assert_eq!(Ok((false, None)), gadget.add(&vote(2, 5, 0)));
+        assert_eq!(10, gadget.finalized_height());
+    }
+
+    #[test]
+    fn measures_the_time_to_finality_of_the_checkpoints() {
+        let mut stats = FinalityStats::new(2);
+        let mined = |height, hash: &str| NodeMetric::BlockMined {
+            node_id: 0,
+            height,
+            hash: hash.to_owned(),
+            reward: 0,
+        };
+        for height in 1..=(2 + CONFIRMATION_DEPTH) {
+            stats.record(&mined(height, &format!("block {}", height)));
+        }
+        stats.record(&NodeMetric::CheckpointFinalized {
+            node_id: 1,
+            height: 2,
+            hash: "block 2".to_owned(),
+        });
+
+        assert_eq!(CONFIRMATION_DEPTH as u64 / 2 + 1, stats.mined_checkpoints());
+        assert_eq!(1, stats.finalized_checkpoints());
+        assert!(stats.time_to_finality_percentile(100).is_some());
+        assert!(stats.time_to_depth_percentile(100).is_some());
+    }
+}
@@ -0,0 +1,102 @@
+use crate::blockchain::{NodeMetric, SeenSet};
+use btclike::crypto::Hash;
+
+/// The default number of transactions a node remembers having seen.
+pub const DEFAULT_SEEN_TRANSACTIONS: usize = 10_000;
+
+/// The hashes of the last transactions a node saw, so that it neither asks for them twice nor
+/// announces them twice, which would make every transaction echo through the network forever.
+/// Past the capacity, the oldest ones are forgotten: they are confirmed or dropped by then.
+pub type SeenTransactions = SeenSet<Hash>;
+
+/// How the transactions spread through the network: how many were announced, how many of the
+/// announcements were new to the nodes, how many of the transactions fetched were valid, and
+/// how many valid ones the relay policies of the nodes refused.
+#[derive(Default)]
+pub struct GossipStats {
+    announced: u64,
+    received_announcements: u64,
+    unknown_announcements: u64,
+    fetched: u64,
+    accepted: u64,
+    refused: u64,
+}
+
+impl GossipStats {
+    pub fn new() -> GossipStats {
+        GossipStats::default()
+    }
+
+    pub fn record(&mut self, metric: &NodeMetric) {
+        match *metric {
+            NodeMetric::TransactionsAnnounced { transactions, .. } => self.announced += transactions as u64,
+            NodeMetric::InventoryReceived {
+                transactions, unknown, ..
+            } => {
+                self.received_announcements += transactions as u64;
+                self.unknown_announcements += unknown as u64;
+            }
+            NodeMetric::TransactionsReceived {
+                transactions,
+                accepted,
+                refused,
+                ..
+            } => {
+                self.fetched += transactions as u64;
+                self.accepted += accepted as u64;
+                self.refused += refused as u64;
+            }
+            _ => {}
+        }
+    }
+
+    /// The transactions announced to the peers, once per peer.
+    pub fn announced(&self) -> u64 {
+        self.announced
+    }
+
+    pub fn received_announcements(&self) -> u64 {
+        self.received_announcements
+    }
+
+    /// The announcements of transactions the nodes did not see before, which they asked for.
+    pub fn unknown_announcements(&self) -> u64 {
+        self.unknown_announcements
+    }
+
+    pub fn fetched(&self) -> u64 {
+        self.fetched
+    }
+
+    /// The fetched transactions the mempools accepted.
+    pub fn accepted(&self) -> u64 {
+        self.accepted
+    }
+
+    /// The valid fetched transactions the relay policies of the mempools refused.
+    pub fn refused(&self) -> u64 {
+        self.refused
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btclike::crypto;
+
+    #[test]
+    fn forgets_the_oldest_transactions_past_the_capacity() {
+        let mut seen_transactions = SeenTransactions::new(2);
+        let hashes: Vec<Hash> = [b"one", b"two", b"six"].iter().map(|data| crypto::hash(*data)).collect();
+
+        assert!(seen_transactions.insert(&hashes[0]));
+        assert!(!seen_transactions.insert(&hashes[0]));
+        assert!(seen_transactions.insert(&hashes[1]));
+        assert!(seen_transactions.insert(&hashes[2]));
+
+        assert_eq!(2, seen_transactions.len());
+        assert!(!seen_transactions.contains(&hashes[0]));
+        assert!(seen_transactions.contains(&hashes[1]));
+        assert!(seen_transactions.insert(&hashes[0]));
+    }
+}
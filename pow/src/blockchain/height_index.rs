@@ -0,0 +1,156 @@
+use crate::blockchain::{Block, Chain};
+use btclike::crypto::Hash;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// The heights of the blocks of a single chain, by hash, along with the blocks by height. A
+/// node keeps one for its own chain, so that it can tell whether a block is part of it, and
+/// find where another chain forks from it, without walking its chain down to the genesis block.
+pub struct HeightIndex {
+    heights: HashMap<Hash, u32>,
+    blocks: Vec<Arc<Block>>,
+    /// The blocks lower than this height were pruned.
+    pruned_height: u32,
+}
+
+impl HeightIndex {
+    pub fn new(chain: &Chain) -> HeightIndex {
+        let mut index = HeightIndex {
+            heights: HashMap::new(),
+            blocks: vec![],
+            pruned_height: 0,
+        };
+        index.update(chain);
+        index
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.heights.contains_key(hash)
+    }
+
+    pub fn height_of(&self, hash: &Hash) -> Option<u32> {
+        self.heights.get(hash).copied()
+    }
+
+    pub fn block_at(&self, height: u32) -> Option<&Arc<Block>> {
+        self.blocks.get(height as usize)
+    }
+
+    /// The blocks of the given chain after the last block it has in common with the indexed
+    /// one, head first, and the height of that common block. None if the chains do not start
+    /// from the same genesis block.
+    pub fn fork(&self, chain: &Chain) -> (Vec<Arc<Block>>, Option<u32>) {
+        let mut new_blocks = vec![];
+        for block in chain.blocks() {
+            if let Some(height) = self.height_of(block.hash()) {
+                return (new_blocks, Some(height));
+            }
+            new_blocks.push(block);
+        }
+
+        (new_blocks, None)
+    }
+
+    /// Indexes the given chain instead of the current one. Returns the heights of the blocks
+    /// that are not part of the chain anymore, and the blocks added, head first.
+    pub fn update(&mut self, chain: &Chain) -> (Range<u32>, Vec<Arc<Block>>) {
+        let (new_blocks, fork_height) = self.fork(chain);
+        let kept = fork_height.map_or(0, |height| height + 1);
+        let removed = kept..self.blocks.len() as u32;
+
+        for block in self.blocks.drain(kept as usize..) {
+            self.heights.remove(block.hash());
+        }
+        for block in new_blocks.iter().rev() {
+            self.heights.insert(block.hash().clone(), block.height);
+            self.blocks.push(block.clone());
+        }
+        self.pruned_height = self.pruned_height.min(kept);
+
+        (removed, new_blocks)
+    }
+
+    /// Replaces the blocks lower than the given height by the same blocks without their
+    /// transactions but the coinbase one. Returns the number of blocks pruned.
+    pub fn prune(&mut self, below_height: u32) -> u32 {
+        let below_height = below_height.min(self.blocks.len() as u32);
+        if below_height <= self.pruned_height {
+            return 0;
+        }
+
+        for block in &mut self.blocks[self.pruned_height as usize..below_height as usize] {
+            *block = Arc::new(block.pruned());
+        }
+        let pruned_blocks = below_height - self.pruned_height;
+        self.pruned_height = below_height;
+        pruned_blocks
+    }
+
+    /// The height below which the blocks were pruned.
+    pub fn pruned_height(&self) -> u32 {
+        self.pruned_height
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::tests::expand;
+    use crate::blockchain::Difficulty;
+    use btclike::crypto::Hasher;
+
+    #[test]
+    fn finds_where_a_chain_forks() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let fork = expand(&expand(&genesis_chain, 0), 1);
+        let chain = expand(&fork, 2);
+        let other_chain = expand(&expand(&fork, 3), 4);
+        let index = HeightIndex::new(&chain);
+
+        assert!(index.contains(fork.head().hash()));
+        assert_eq!(Some(3), index.height_of(chain.head().hash()));
+        assert!(!index.contains(other_chain.head().hash()));
+
+        let (new_blocks, fork_height) = index.fork(&other_chain);
+        assert_eq!(Some(2), fork_height);
+        let new_heights: Vec<u32> = new_blocks.iter().map(|block| block.height).collect();
+        assert_eq!(vec![4, 3], new_heights);
+
+        let other_genesis_chain =
+            Arc::new(Chain::init_new(Difficulty::min_difficulty()).with_hasher(Hasher::DoubleSha256));
+        assert_eq!(None, index.fork(&other_genesis_chain).1);
+    }
+
+    #[test]
+    fn switches_to_another_chain() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let fork = expand(&genesis_chain, 0);
+        let chain = expand(&expand(&fork, 1), 2);
+        let other_chain = expand(&fork, 3);
+        let mut index = HeightIndex::new(&chain);
+
+        let (removed, added) = index.update(&other_chain);
+
+        assert_eq!(2..4, removed);
+        assert_eq!(1, added.len());
+        assert!(Arc::ptr_eq(other_chain.head(), index.block_at(2).unwrap()));
+        assert!(index.block_at(3).is_none());
+        assert!(!index.contains(chain.head().hash()));
+        assert!(index.contains(genesis_chain.head().hash()));
+    }
+
+    #[test]
+    fn prunes_the_lower_blocks() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let chain = expand(&expand(&expand(&genesis_chain, 0), 1), 2);
+        let mut index = HeightIndex::new(&chain);
+
+        assert_eq!(2, index.prune(2));
+        assert_eq!(0, index.prune(1));
+        assert_eq!(2, index.prune(10));
+        assert_eq!(4, index.pruned_height());
+        assert!(index.block_at(3).unwrap().body().is_pruned());
+        assert!(index.contains(chain.head().hash()));
+    }
+}
@@ -0,0 +1,150 @@
+use crate::blockchain::Block;
+use btclike::crypto::Hash;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashSet};
+use std::hash;
+use std::mem;
+use std::sync::{Arc, RwLock};
+
+/// The blocks of every chain starting from the same genesis block, by hash. The chains only
+/// reference their head block and find the others here by following the hashes of the
+/// previous blocks, so that a block is stored once whatever the number of chains and forks
+/// that contain it.
+///
+/// Blocks are only added once validated, and never removed: the stale ones are kept along
+/// with those of the strongest chain. Their bodies may be pruned though.
+#[derive(Clone, Default)]
+pub struct BlockIndex {
+    blocks: Arc<RwLock<IndexedBlocks>>,
+}
+
+#[derive(Default)]
+struct IndexedBlocks {
+    by_hash: HashSet<IndexedBlock>,
+    /// The hashes of the blocks whose bodies were not pruned yet, by height.
+    unpruned: BTreeMap<u32, Vec<Hash>>,
+}
+
+impl BlockIndex {
+    pub fn new() -> BlockIndex {
+        BlockIndex::default()
+    }
+
+    pub fn insert(&self, block: &Arc<Block>) {
+        let mut blocks = self.blocks.write().expect("Poisoned block index");
+        if blocks.by_hash.insert(IndexedBlock(block.clone())) {
+            blocks.unpruned.entry(block.height).or_default().push(block.hash.clone());
+        }
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<Arc<Block>> {
+        self.blocks
+            .read()
+            .expect("Poisoned block index")
+            .by_hash
+            .get(hash)
+            .map(|indexed_block| indexed_block.0.clone())
+    }
+
+    /// Replaces the blocks lower than the given height by the same blocks without their
+    /// transactions but the coinbase one. The chains still find them, but cannot validate
+    /// them anymore. Returns the number of bodies pruned, and their size in bytes.
+    pub fn prune(&self, below_height: u32) -> (u32, u64) {
+        let mut blocks = self.blocks.write().expect("Poisoned block index");
+        let kept = blocks.unpruned.split_off(&below_height);
+        let pruned = mem::replace(&mut blocks.unpruned, kept);
+
+        let mut pruned_bodies = 0;
+        let mut pruned_bytes = 0;
+        for hash in pruned.into_values().flatten() {
+            if let Some(IndexedBlock(block)) = blocks.by_hash.take(&hash) {
+                pruned_bodies += 1;
+                pruned_bytes += block.body.size as u64;
+                blocks.by_hash.insert(IndexedBlock(Arc::new(block.pruned())));
+            }
+        }
+        (pruned_bodies, pruned_bytes)
+    }
+
+    /// The block the given one extends, none for a genesis block or if it is unknown.
+    pub fn parent(&self, block: &Block) -> Option<Arc<Block>> {
+        if block.height == 0 {
+            None
+        } else {
+            self.get(&block.previous_block_hash)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.read().expect("Poisoned block index").by_hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A block looked up by its hash.
+struct IndexedBlock(Arc<Block>);
+
+impl Borrow<Hash> for IndexedBlock {
+    fn borrow(&self) -> &Hash {
+        &self.0.hash
+    }
+}
+
+impl PartialEq for IndexedBlock {
+    fn eq(&self, other: &IndexedBlock) -> bool {
+        self.0.hash == other.0.hash
+    }
+}
+
+impl Eq for IndexedBlock {}
+
+impl hash::Hash for IndexedBlock {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.0.hash.hash(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::tests::expand;
+    use crate::blockchain::{Chain, Difficulty};
+    use crate::error::ChainError;
+
+    #[test]
+    fn stores_the_common_blocks_of_the_forks_once() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let fork = expand(&genesis_chain, 0);
+        let chain = expand(&fork, 1);
+        let other_chain = expand(&fork, 2);
+
+        // The genesis block, the common block and the two heads.
+        assert_eq!(4, genesis_chain.index.len());
+        let parent = chain.index.parent(chain.head()).unwrap();
+        assert!(Arc::ptr_eq(fork.head(), &parent));
+        assert!(Arc::ptr_eq(&parent, &other_chain.index.parent(other_chain.head()).unwrap()));
+        assert!(chain.index.parent(genesis_chain.head()).is_none());
+    }
+
+    #[test]
+    fn prunes_the_bodies_of_the_lower_blocks() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let fork = expand(&genesis_chain, 0);
+        let chain = expand(&expand(&fork, 1), 2);
+        let other_chain = expand(&fork, 3);
+
+        // Every block but the head of the chain, the stale one included.
+        assert_eq!(4, chain.index.prune(3).0);
+        assert_eq!(0, chain.index.prune(3).0);
+        assert_eq!(5, chain.index.len());
+
+        let stale_block = other_chain.index.get(other_chain.head().hash()).unwrap();
+        assert!(!Arc::ptr_eq(other_chain.head(), &stale_block));
+        assert!(stale_block.body().is_pruned());
+        assert!(!chain.index.get(chain.head().hash()).unwrap().body().is_pruned());
+        assert_eq!(Err(ChainError::Pruned), chain.validate().map(|_| ()));
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The weight of a new measure in the smoothed round trip time, the one of TCP (RFC 6298).
+const SMOOTHING_FACTOR: u32 = 8;
+
+/// Smooths the round trip times measured on a connection, so that a single late pong does not
+/// make a peer look slow.
+pub fn smoothed_round_trip_time(previous: Option<Duration>, measured: Duration) -> Duration {
+    match previous {
+        Some(previous) => (previous * (SMOOTHING_FACTOR - 1) + measured) / SMOOTHING_FACTOR,
+        None => measured,
+    }
+}
+
+/// Gathers the round trip times measured by all the nodes of a network to each of their peers.
+#[derive(Clone, Default)]
+pub struct LatencyStats {
+    /// The latest smoothed round trip time of each connection, by node and peer address.
+    inner: Arc<Mutex<HashMap<(u32, u32), Duration>>>,
+}
+
+impl LatencyStats {
+    pub fn new() -> LatencyStats {
+        LatencyStats::default()
+    }
+
+    /// Records the smoothed round trip time of a node to one of its peers, replacing the
+    /// previous one.
+    pub fn report(&self, node_id: u32, peer_id: u32, round_trip_time: Duration) {
+        self.inner
+            .lock()
+            .expect("Poisoned latency stats")
+            .insert((node_id, peer_id), round_trip_time);
+    }
+
+    /// Forgets a closed connection.
+    pub fn remove(&self, node_id: u32, peer_id: u32) {
+        self.inner
+            .lock()
+            .expect("Poisoned latency stats")
+            .remove(&(node_id, peer_id));
+    }
+
+    /// The number of connections with a measured round trip time.
+    pub fn len(&self) -> usize {
+        self.inner.lock().expect("Poisoned latency stats").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The average round trip time of the connections, none if none was measured.
+    pub fn average(&self) -> Option<Duration> {
+        let inner = self.inner.lock().expect("Poisoned latency stats");
+        if inner.is_empty() {
+            None
+        } else {
+            Some(inner.values().sum::<Duration>() / inner.len() as u32)
+        }
+    }
+
+    /// The round trip time of the slowest connection, none if none was measured.
+    pub fn max(&self) -> Option<Duration> {
+        self.inner
+            .lock()
+            .expect("Poisoned latency stats")
+            .values()
+            .max()
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smooths_round_trip_times() {
+        let first = smoothed_round_trip_time(None, Duration::from_millis(80));
+        assert_eq!(Duration::from_millis(80), first);
+
+        let second = smoothed_round_trip_time(Some(first), Duration::from_millis(160));
+        assert_eq!(Duration::from_millis(90), second);
+    }
+
+    #[test]
+    fn aggregates_the_connections_of_every_node() {
+        let stats = LatencyStats::new();
+        assert_eq!(None, stats.average());
+
+        stats.report(0, 1, Duration::from_millis(10));
+        stats.report(0, 2, Duration::from_millis(50));
+        stats.report(1, 0, Duration::from_millis(20));
+        stats.report(1, 0, Duration::from_millis(30));
+        assert_eq!(3, stats.len());
+        assert_eq!(Some(Duration::from_millis(30)), stats.average());
+        assert_eq!(Some(Duration::from_millis(50)), stats.max());
+
+        stats.remove(0, 2);
+        assert_eq!(Some(Duration::from_millis(30)), stats.max());
+    }
+}
@@ -0,0 +1,585 @@
+use crate::blockchain::miner::interval_stream;
+use crate::blockchain::{
+    Authorities, BlockHeader, Chain, Message, MetricsBus, NodeLogger, NodeMetric, ProofRequest, ProofResponse,
+    ValidatorRegistry,
+};
+use crate::error::{ChainError, Error};
+use btclike::bloom::BloomFilter;
+use btclike::crypto::{self, Hash};
+use btclike::merkle::MerkleProof;
+use btclike::transaction::{Address, SignedTx};
+use futures::channel::mpsc::Receiver;
+use futures::stream::{FuturesUnordered, SelectAll};
+use futures::future::BoxFuture;
+use futures::{Future, FutureExt, Stream, StreamExt};
+use netsim::clock;
+use netsim::network::rpc::RpcClient;
+use netsim::network::{ConnectionEvent, ConnectionSender, Node};
+use rand::{self, Rng};
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const PROOF_ERROR_UNKNOWN_BLOCK: &str = "Unknown block";
+const PROOF_ERROR_INVALID_BRANCH: &str = "Invalid Merkle branch";
+
+/// Proves that a transaction is part of a block: the Merkle branch links the transaction hash
+/// to the Merkle root included in the block header.
+pub struct TransactionProof {
+    block_hash: Hash,
+    height: u32,
+    transaction_hash: crypto::Hash,
+    merkle_proof: MerkleProof,
+}
+
+impl TransactionProof {
+    /// Builds the proof requested by a light client from the given chain. The transaction index
+    /// wraps around the number of transactions of the block, the coinbase one being the first.
+    /// None if the block is not part of the chain, or if its body was pruned.
+    pub fn new(chain: &Chain, request: &ProofRequest) -> Option<TransactionProof> {
+        let block = chain.block_at(request.height())?;
+        if block.hash() != request.block_hash() || block.body().is_pruned() {
+            return None;
+        }
+
+        let tree = block.body().body().merkle_tree().ok()?;
+        let index = request.transaction_index() % tree.leaves().len();
+
+        Some(TransactionProof {
+            block_hash: block.hash().clone(),
+            height: request.height(),
+            transaction_hash: tree.leaves()[index].clone(),
+            merkle_proof: tree.proof(index)?,
+        })
+    }
+
+    pub fn block_hash(&self) -> &Hash {
+        &self.block_hash
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn transaction_hash(&self) -> &crypto::Hash {
+        &self.transaction_hash
+    }
+}
+
+/// Follows the strongest chain by only keeping and validating the block headers. The
+/// transactions are never replayed: their inclusion in a block is checked with the Merkle
+/// branches served by the full nodes.
+pub struct LightClient {
+    /// The headers of the strongest known chain, indexed by height.
+    headers: Vec<BlockHeader>,
+    /// The producers of the blocks, if the chain is a proof of authority one.
+    authorities: Option<Arc<Authorities>>,
+    /// The validators of the blocks, if the chain is a proof of stake one.
+    validators: Option<Arc<ValidatorRegistry>>,
+}
+
+impl LightClient {
+    pub fn new(genesis_chain: &Chain) -> LightClient {
+        let genesis_block = genesis_chain
+            .block_at(0)
+            .expect("A chain always has a genesis block");
+
+        LightClient {
+            headers: vec![genesis_block.header()],
+            authorities: genesis_chain.authorities.clone(),
+            validators: genesis_chain.validators.clone(),
+        }
+    }
+
+    pub fn height(&self) -> u32 {
+        (self.headers.len() - 1) as u32
+    }
+
+    pub fn header(&self, height: u32) -> Option<&BlockHeader> {
+        self.headers.get(height as usize)
+    }
+
+    /// Adopts the headers of the given chain if it is stronger than the known one.
+    /// Only the headers of the blocks the client does not know yet are validated.
+    /// Returns whether the headers were adopted.
+    pub fn update(&mut self, chain: &Chain) -> Result<bool, ChainError> {
+        if chain.height() <= self.height() {
+            return Ok(false);
+        }
+
+        let mut new_headers = vec![];
+        let mut parents = chain.blocks().skip(1);
+        let mut header = chain.head().header();
+        loop {
+            if let Some(known_header) = self.header(header.height()) {
+                if known_header.hash() == header.hash() {
+                    break;
+                }
+            }
+
+            match parents.next().map(|parent| parent.header()) {
+                Some(parent_header) => {
+                    self.validate_link(&parent_header, &header)?;
+                    new_headers.push(header);
+                    header = parent_header;
+                }
+                None => return Err(ChainError::InvalidGenesis),
+            }
+        }
+
+        self.headers.truncate(header.height() as usize + 1);
+        self.headers.extend(new_headers.into_iter().rev());
+        Ok(true)
+    }
+
+    /// Checks that the transaction of the proof is part of a block of the known chain.
+    pub fn verify_transaction(&self, proof: &TransactionProof) -> Result<(), &'static str> {
+        let header = match self.header(proof.height) {
+            Some(header) if header.hash() == &proof.block_hash => header,
+            _ => return Err(PROOF_ERROR_UNKNOWN_BLOCK),
+        };
+
+        if proof
+            .merkle_proof
+            .verify(&proof.transaction_hash, header.merkle_root())
+        {
+            Ok(())
+        } else {
+            Err(PROOF_ERROR_INVALID_BRANCH)
+        }
+    }
+
+    fn validate_link(&self, parent: &BlockHeader, header: &BlockHeader) -> Result<(), ChainError> {
+        header.validate()?;
+        if let Some(ref authorities) = self.authorities {
+            authorities.verify(header)?;
+        }
+        if let Some(ref validators) = self.validators {
+            validators.verify(header)?;
+        }
+
+        if header.height != parent.height + 1 {
+            Err(ChainError::HeightMismatch)
+        } else if header.previous_block_hash != parent.hash {
+            Err(ChainError::HashMismatch)
+        } else if header.difficulty != parent.difficulty {
+            Err(ChainError::InvalidDifficulty)
+        } else if header.hasher != parent.hasher {
+            Err(ChainError::InvalidHasher)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The events that can happen in a light node.
+enum LightNodeEvent {
+    Peer(ConnectionSender<Message>),
+    /// The peer with the given address closed its connection.
+    PeerClosed(u32),
+    Message(Message),
+    /// A peer pings this node, which answers through the given sender.
+    Ping(Instant, ConnectionSender<Message>),
+    ProofAttempt,
+    /// The answer to a proof request, or why there is none.
+    ProofResponse(ProofAttempt, Result<ProofResponse, netsim::error::Error>),
+}
+
+/// A proof request, along with the peers it was sent to.
+struct ProofAttempt {
+    request: ProofRequest,
+    tried_peers: Vec<u32>,
+}
+
+/// The response to the last peer a proof attempt was sent to.
+type ProofResponseFuture = BoxFuture<'static, (ProofAttempt, Result<ProofResponse, netsim::error::Error>)>;
+
+/// A node that neither mines nor relays chains. It follows the headers of the chains sent by
+/// its peers and regularly asks one of them for a proof that a random transaction was confirmed.
+pub struct LightNode {
+    node_id: u32,
+    client: LightClient,
+    proof_attempt_delay: Duration,
+    rpc: RpcClient<ProofResponse>,
+    /// The addresses of the payments this node wants to hear about.
+    watched_addresses: HashSet<Address>,
+    /// The filter of the watched addresses loaded by the peers, none to get no transaction.
+    bloom_filter: Option<Arc<BloomFilter>>,
+    metrics_bus: Option<MetricsBus>,
+    logger: NodeLogger,
+}
+
+impl LightNode {
+    pub fn new(node_id: u32, genesis_chain: &Chain, proof_attempt_delay: Duration) -> LightNode {
+        LightNode {
+            node_id,
+            client: LightClient::new(genesis_chain),
+            proof_attempt_delay,
+            // A proof is expected before the next attempt.
+            rpc: RpcClient::new(proof_attempt_delay),
+            watched_addresses: HashSet::new(),
+            bloom_filter: None,
+            metrics_bus: None,
+            logger: NodeLogger::new(node_id, clock::now()),
+        }
+    }
+
+    /// Loads a Bloom filter of the given addresses on every peer, so that they send the pending
+    /// transactions paying them, along with others at the given false positive rate.
+    pub fn with_bloom_filter(
+        mut self,
+        addresses: Vec<Address>,
+        false_positive_rate: f64,
+    ) -> Result<LightNode, btclike::Error> {
+        let mut bloom_filter = BloomFilter::new(addresses.len(), false_positive_rate)?;
+        for address in &addresses {
+            bloom_filter.insert_address(address);
+        }
+        self.watched_addresses = addresses.into_iter().collect();
+        self.bloom_filter = Some(Arc::new(bloom_filter));
+        Ok(self)
+    }
+
+    /// Publishes the filtered transactions this node receives on the given bus.
+    pub fn with_metrics_bus(mut self, metrics_bus: MetricsBus) -> LightNode {
+        self.metrics_bus = Some(metrics_bus);
+        self
+    }
+
+    /// Tags the log lines of this node with the given logger, from the start of the simulation.
+    pub fn with_logger(mut self, logger: NodeLogger) -> LightNode {
+        self.logger = logger;
+        self
+    }
+
+    /// Sends a proof request for a random transaction of a random known block to a random peer.
+    /// Returns the future of the response, if the request could be sent.
+    fn request_proof(
+        &self,
+        peers: &mut Vec<ConnectionSender<Message>>,
+    ) -> Option<ProofResponseFuture> {
+        if self.client.height() == 0 {
+            return None;
+        }
+
+        let mut rng = rand::thread_rng();
+        let height = rng.gen_range(1, self.client.height() + 1);
+        let block_hash = self.client.header(height).expect("Known height").hash().clone();
+        let request = ProofRequest::new(block_hash, height, rng.gen::<u8>() as usize);
+        self.send_proof_request(request, vec![], peers)
+    }
+
+    /// Sends the proof request to a random peer it was not sent to yet. Returns the future of
+    /// the response, along with the peers tried, if the request could be sent.
+    fn send_proof_request(
+        &self,
+        request: ProofRequest,
+        mut tried_peers: Vec<u32>,
+        peers: &mut Vec<ConnectionSender<Message>>,
+    ) -> Option<ProofResponseFuture> {
+        let untried_peers: Vec<usize> = (0..peers.len())
+            .filter(|index| !tried_peers.contains(&peers[*index].peer_id()))
+            .collect();
+        let peer_index = *rand::thread_rng().choose(&untried_peers)?;
+        let peer_id = peers[peer_index].peer_id();
+
+        match self.rpc.call(&mut peers[peer_index], request.clone(), Message::ProofRequest) {
+            Ok(response) => {
+                tried_peers.push(peer_id);
+                let attempt = ProofAttempt { request, tried_peers };
+                Some(response.map(move |response| (attempt, response)).boxed())
+            }
+            Err(netsim::error::Error::Congested) => {
+                self.logger.debug("proof", format_args!("Congested peer, proof request dropped."));
+                None
+            }
+            Err(err) => {
+                self.logger.debug("peer", format_args!("Peer lost: {}", err));
+                peers.remove(peer_index);
+                None
+            }
+        }
+    }
+
+    fn handle_message(&mut self, message: Message) {
+        match message {
+            Message::Chain(chain) => match self.client.update(&chain) {
+                Ok(true) => self.logger.debug(
+                    "chain",
+                    format_args!("New header chain with height: {}", self.client.height()),
+                ),
+                Ok(false) => {}
+                Err(err) => self.logger.error("chain", format_args!("Invalid header chain: {}", err)),
+            },
+            Message::Proof(response) => {
+                if !self.rpc.receive(response) {
+                    self.logger.debug("proof", format_args!("Received a proof too late."));
+                }
+            }
+            // Light nodes cannot serve proofs.
+            Message::ProofRequest(_request) => {}
+            Message::Transactions(transactions) => self.receive_filtered_transactions(&transactions),
+            // Light nodes do not serve filters.
+            Message::FilterLoad(_bloom_filter) => {}
+            // The pings are answered on reception, and light nodes do not ping.
+            Message::Ping(_sent_at) | Message::Pong(_sent_at) => {}
+            // Light nodes never ask for compact blocks nor transactions by hash, and do not keep any.
+            Message::SendCompact
+            | Message::CompactBlock(_)
+            | Message::GetBlockTransactions(..)
+            | Message::BlockTransactions(..)
+            | Message::GetChain
+            | Message::RequestedChain(_)
+            | Message::Inventory(_)
+            | Message::GetTransactions(_) => {}
+            // Light nodes do not follow the finality of the checkpoints.
+            Message::Vote(_vote) => {}
+        }
+    }
+
+    /// Tells the transactions paying the watched addresses from the false positives of the
+    /// filter.
+    fn receive_filtered_transactions(&self, transactions: &[SignedTx]) {
+        let relevant = transactions
+            .iter()
+            .filter(|transaction| {
+                transaction
+                    .output()
+                    .iter()
+                    .any(|tx_out| self.watched_addresses.contains(&tx_out.address()))
+            })
+            .count();
+        self.logger.debug(
+            "filter",
+            format_args!("Received {} filtered transactions, {} of them relevant", transactions.len(), relevant),
+        );
+
+        if let Some(ref metrics_bus) = self.metrics_bus {
+            metrics_bus.publish(NodeMetric::FilteredTransactionsReceived {
+                node_id: self.node_id,
+                transactions: transactions.len() as u32,
+                relevant: relevant as u32,
+            });
+        }
+    }
+
+    /// Verifies the proof received. A peer that pruned the block is not asked again: the request
+    /// is sent to another peer, whose response future is returned.
+    fn handle_proof_response(
+        &self,
+        attempt: ProofAttempt,
+        response: Result<ProofResponse, netsim::error::Error>,
+        peers: &mut Vec<ConnectionSender<Message>>,
+    ) -> Option<ProofResponseFuture> {
+        match response {
+            Ok(ProofResponse::Proof(proof)) => match self.client.verify_transaction(&proof) {
+                Ok(()) => self.logger.info(
+                    "proof",
+                    format_args!("Verified a transaction of block {}, height {}", proof.block_hash(), proof.height()),
+                ),
+                // The block may have been reorganized out of the chain in the meantime.
+                Err(err) => self.logger.debug("proof", format_args!("Could not verify a transaction: {}", err)),
+            },
+            // The block is not part of the chain of the peer, or not anymore.
+            Ok(ProofResponse::UnknownBlock) => self.logger.debug(
+                "proof",
+                format_args!("The peer could not prove a transaction."),
+            ),
+            Ok(ProofResponse::Pruned) => {
+                self.logger.debug("proof", format_args!("The peer pruned the block, asking another one."));
+                return self.send_proof_request(attempt.request, attempt.tried_peers, peers);
+            }
+            Err(err) => self.logger.debug("proof", format_args!("Proof request failed: {}", err)),
+        }
+        None
+    }
+
+    /// Handles the events of the peers and the proof attempts one at a time.
+    async fn route<S>(&mut self, mut connection_stream: S) -> Result<(), Error>
+    where
+        S: Stream<Item = Result<ConnectionEvent<Message>, netsim::error::Error>> + Unpin,
+    {
+        let mut proof_attempts = Box::pin(interval_stream(self.proof_attempt_delay));
+        // The messages of every peer, polled in turn.
+        let mut receptions = SelectAll::new();
+        let mut proof_responses = FuturesUnordered::new();
+        let mut peers = vec![];
+
+        loop {
+            let node_event = tokio::select! {
+                Some(event) = connection_stream.next() => match event? {
+                    ConnectionEvent::Opened(connection) => {
+                        self.logger.debug("peer", format_args!("Connection received."));
+                        let (sender, receiver) = connection.split();
+                        receptions.push(light_reception(receiver, sender.clone()));
+                        LightNodeEvent::Peer(sender)
+                    }
+                    ConnectionEvent::Closed(peer_id) => LightNodeEvent::PeerClosed(peer_id),
+                },
+                Some(light_node_event) = receptions.next() => light_node_event,
+                Some(_instant) = proof_attempts.next() => LightNodeEvent::ProofAttempt,
+                Some((attempt, response)) = proof_responses.next() => LightNodeEvent::ProofResponse(attempt, response),
+                else => return Ok(()),
+            };
+
+            match node_event {
+                LightNodeEvent::Peer(mut sender) => {
+                    if let Some(ref bloom_filter) = self.bloom_filter {
+                        if let Err(err) = sender.try_send(Message::FilterLoad(bloom_filter.clone())) {
+                            self.logger.debug("filter", format_args!("Filter not loaded: {}", err));
+                        }
+                    }
+                    peers.push(sender);
+                }
+                LightNodeEvent::PeerClosed(peer_id) => peers.retain(|sender| sender.peer_id() != peer_id),
+                LightNodeEvent::Message(message) => self.handle_message(message),
+                LightNodeEvent::Ping(sent_at, mut sender) => {
+                    if let Err(err) = sender.try_send(Message::Pong(sent_at)) {
+                        self.logger.debug("ping", format_args!("Pong not sent: {}", err));
+                    }
+                }
+                LightNodeEvent::ProofAttempt => {
+                    if let Some(response) = self.request_proof(&mut peers) {
+                        proof_responses.push(response);
+                    }
+                }
+                LightNodeEvent::ProofResponse(attempt, response) => {
+                    if let Some(response) = self.handle_proof_response(attempt, response, &mut peers) {
+                        proof_responses.push(response);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The events caused by the messages of a peer. Its pings are answered through the given sender.
+fn light_reception(
+    receiver: Receiver<Message>,
+    reply_sender: ConnectionSender<Message>,
+) -> impl Stream<Item = LightNodeEvent> + Send + Unpin {
+    receiver.map(move |message| match message {
+        Message::Ping(sent_at) => LightNodeEvent::Ping(sent_at, reply_sender.clone()),
+        message => LightNodeEvent::Message(message),
+    })
+}
+
+impl Node<Message> for LightNode {
+    type Output = ();
+
+    fn run<S>(mut self, connection_stream: S) -> Pin<Box<dyn Future<Output = ()> + Send>>
+    where
+        S: Stream<Item = Result<ConnectionEvent<Message>, netsim::error::Error>> + Send + Unpin + 'static,
+    {
+        Box::pin(async move {
+            if let Err(err) = self.route(connection_stream).await {
+                self.logger.error("node", format_args!("Stopped: {}", err));
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{Block, BlockBody, Difficulty};
+    use crate::blockchain::pow::Nonce;
+    use btclike::blockchain::{Body, COINBASE_AMOUNT};
+    use btclike::transaction::TxOut;
+    use btclike::wallet::Wallet;
+    use std::sync::Arc;
+
+    #[test]
+    fn follows_the_strongest_header_chain() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let mut client = LightClient::new(&genesis_chain);
+        let mut nonce = Nonce::new();
+
+        let chain = mine_blocks(genesis_chain.clone(), 3, &mut nonce);
+        assert!(client.update(&chain).unwrap());
+        assert_eq!(3, client.height());
+        assert!(!client.update(&chain).unwrap());
+
+        // A stronger fork from the first block replaces the last headers.
+        let fork = mine_blocks(sub_chain(&chain, 1), 3, &mut nonce);
+        assert!(client.update(&fork).unwrap());
+        assert_eq!(4, client.height());
+        assert!(client.header(4).unwrap().hash() == fork.head().hash());
+        assert!(client.header(1).unwrap().hash() == chain.block_at(1).unwrap().hash());
+
+        let other_genesis_chain = Arc::new(Chain::init_new({
+            let mut difficulty = Difficulty::min_difficulty();
+            difficulty.increase().unwrap();
+            difficulty
+        }));
+        let other_chain = mine_blocks(other_genesis_chain, 5, &mut nonce);
+        assert_eq!(ChainError::InvalidGenesis, client.update(&other_chain).err().unwrap());
+    }
+
+    #[test]
+    fn verifies_transaction_proofs() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let mut client = LightClient::new(&genesis_chain);
+        let chain = mine_blocks(genesis_chain, 2, &mut Nonce::new());
+        client.update(&chain).unwrap();
+
+        let block_hash = chain.head().hash().clone();
+        let request = ProofRequest::new(block_hash.clone(), 2, 0);
+        let proof = TransactionProof::new(&chain, &request).unwrap();
+        assert!(client.verify_transaction(&proof).is_ok());
+
+        let coinbase_hash = chain.head().body().body().coinbase_tx().hash().unwrap();
+        assert!(proof.transaction_hash() == &coinbase_hash);
+
+        let mut forged_proof = TransactionProof::new(&chain, &request).unwrap();
+        forged_proof.transaction_hash = crypto::hash(b"Garneray");
+        assert_eq!(PROOF_ERROR_INVALID_BRANCH, client.verify_transaction(&forged_proof).err().unwrap());
+
+        let mut unknown_block_proof = TransactionProof::new(&chain, &request).unwrap();
+        unknown_block_proof.height = 1;
+        assert_eq!(PROOF_ERROR_UNKNOWN_BLOCK, client.verify_transaction(&unknown_block_proof).err().unwrap());
+
+        assert!(TransactionProof::new(&chain, &ProofRequest::new(block_hash, 1, 0)).is_none());
+
+        let parent_hash = chain.block_at(1).unwrap().hash().clone();
+        assert!(TransactionProof::new(&chain, &ProofRequest::new(parent_hash.clone(), 1, 0)).is_some());
+        chain.index.prune(2);
+        assert!(TransactionProof::new(&chain, &ProofRequest::new(parent_hash, 1, 0)).is_none());
+    }
+
+    fn sub_chain(chain: &Arc<Chain>, height: u32) -> Arc<Chain> {
+        let mut chain = chain.clone();
+        while chain.height() > height {
+            chain = chain.tail().unwrap();
+        }
+        chain
+    }
+
+    /// Mines empty blocks, with a different coinbase address each.
+    fn mine_blocks(mut chain: Arc<Chain>, count: u32, nonce: &mut Nonce) -> Arc<Chain> {
+        let mut wallet = Wallet::new();
+        let target_height = chain.height() + count;
+
+        while chain.height() < target_height {
+            let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT, wallet.new_address().unwrap());
+            let body = Arc::new(BlockBody::new(Body::new(coinbase_tx_out, vec![])).unwrap());
+            nonce.increment().unwrap();
+            let block = Block::new(
+                1,
+                nonce.clone(),
+                0,
+                &chain.head().difficulty,
+                chain.head().hasher,
+                chain.head().hash().clone(),
+                chain.height() + 1,
+                &body,
+            );
+            if let Ok(mined_chain) = Chain::expand(&chain, block) {
+                chain = mined_chain;
+            }
+        }
+
+        chain
+    }
+}
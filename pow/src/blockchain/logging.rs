@@ -0,0 +1,152 @@
+use log::Level;
+use netsim::clock;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A log line of a node, kept by a log capture.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogLine {
+    pub level: Level,
+    /// What the line is about, `chain` or `peer` for instance.
+    pub event: &'static str,
+    /// The time from the start of the simulation.
+    pub time: Duration,
+    pub message: String,
+}
+
+/// Keeps the log lines of the nodes in memory, by node, for the tests to check what the nodes
+/// did once the simulation ran. Its clones share the lines. Every level is kept, whatever the
+/// level of the logs.
+#[derive(Clone, Default)]
+pub struct LogCapture {
+    lines: Arc<Mutex<HashMap<u32, Vec<LogLine>>>>,
+}
+
+impl LogCapture {
+    pub fn new() -> LogCapture {
+        LogCapture::default()
+    }
+
+    /// The lines the given node logged, the oldest first.
+    pub fn lines(&self, node_id: u32) -> Vec<LogLine> {
+        self.lines
+            .lock()
+            .expect("A node panicked while logging")
+            .get(&node_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The ids of the nodes that logged at least one line, sorted.
+    pub fn nodes(&self) -> Vec<u32> {
+        let mut nodes: Vec<u32> = self.lines.lock().expect("A node panicked while logging").keys().copied().collect();
+        nodes.sort_unstable();
+        nodes
+    }
+
+    fn push(&self, node_id: u32, line: LogLine) {
+        self.lines
+            .lock()
+            .expect("A node panicked while logging")
+            .entry(node_id)
+            .or_default()
+            .push(line);
+    }
+}
+
+/// Tags the log lines of a node with its id, the event they are about and the simulation time,
+/// so that the lines of thousands of concurrent nodes can be told apart and filtered:
+///
+/// ```text
+/// [#00012    1.532s chain] Known chain, height: 3
+/// ```
+#[derive(Clone)]
+pub struct NodeLogger {
+    node_id: u32,
+    /// The start of the simulation.
+    start: Instant,
+    capture: Option<LogCapture>,
+}
+
+impl NodeLogger {
+    pub fn new(node_id: u32, start: Instant) -> NodeLogger {
+        NodeLogger {
+            node_id,
+            start,
+            capture: None,
+        }
+    }
+
+    /// Also keeps the lines of the node in the given capture.
+    pub fn with_capture(mut self, capture: LogCapture) -> NodeLogger {
+        self.capture = Some(capture);
+        self
+    }
+
+    pub fn log(&self, level: Level, event: &'static str, message: fmt::Arguments) {
+        let time = clock::now().saturating_duration_since(self.start);
+        log!(
+            level,
+            "[#{:05} {:>9.3}s {}] {}",
+            self.node_id,
+            time.as_secs_f64(),
+            event,
+            message
+        );
+        if let Some(ref capture) = self.capture {
+            capture.push(
+                self.node_id,
+                LogLine {
+                    level,
+                    event,
+                    time,
+                    message: message.to_string(),
+                },
+            );
+        }
+    }
+
+    pub fn error(&self, event: &'static str, message: fmt::Arguments) {
+        self.log(Level::Error, event, message);
+    }
+
+    pub fn info(&self, event: &'static str, message: fmt::Arguments) {
+        self.log(Level::Info, event, message);
+    }
+
+    pub fn debug(&self, event: &'static str, message: fmt::Arguments) {
+        self.log(Level::Debug, event, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captures_the_lines_of_each_node() {
+        let capture = LogCapture::new();
+        let start = Instant::now();
+        let logger = NodeLogger::new(3, start).with_capture(capture.clone());
+        let other_logger = NodeLogger::new(5, start).with_capture(capture.clone());
+        let uncaptured_logger = NodeLogger::new(7, start);
+
+        logger.debug("chain", format_args!("Known chain, height: {}", 2));
+        other_logger.error("chain", format_args!("Invalid chain: {}", "bad nonce"));
+        logger.info("peer", format_args!("Banned peer #{:05}", 5));
+        uncaptured_logger.info("peer", format_args!("New peer"));
+
+        assert_eq!(vec![3, 5], capture.nodes());
+        let lines = capture.lines(3);
+        assert_eq!(2, lines.len());
+        assert_eq!(Level::Debug, lines[0].level);
+        assert_eq!("chain", lines[0].event);
+        assert_eq!("Known chain, height: 2", lines[0].message);
+        assert_eq!("Banned peer #00005", lines[1].message);
+        assert!(lines[0].time <= lines[1].time);
+        assert_eq!(Level::Error, capture.lines(5)[0].level);
+        assert!(capture.lines(7).is_empty());
+    }
+}
@@ -0,0 +1,91 @@
+use crate::blockchain::{Chain, CheckpointVote, CompactBlock, TransactionProof};
+use btclike::bloom::BloomFilter;
+use btclike::crypto::{self, Hash};
+use btclike::transaction::SignedTx;
+use netsim::network::rpc::{Request, Response};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// The messages exchanged by the nodes of the network.
+#[derive(Clone)]
+pub enum Message {
+    /// The strongest chain known by the sender.
+    Chain(Arc<Chain>),
+    /// Sent by light nodes to full nodes.
+    ProofRequest(Request<ProofRequest>),
+    /// The answer of a full node to a `ProofRequest`.
+    Proof(Response<ProofResponse>),
+    /// Sent regularly to measure the round trip time of a connection, with the instant it
+    /// was sent at.
+    Ping(Instant),
+    /// The answer to a `Ping`, echoing its instant back.
+    Pong(Instant),
+    /// Asks a full node to announce the blocks extending the chain it sent last as compact
+    /// blocks.
+    SendCompact,
+    /// A block extending the chain the sender sent last, without the transactions the
+    /// receiver is expected to know.
+    CompactBlock(Arc<CompactBlock>),
+    /// Asks for the transactions of a compact block missing from the mempool, by index.
+    GetBlockTransactions(Hash, Vec<u32>),
+    /// The answer to a `GetBlockTransactions`, in the order of the indexes.
+    BlockTransactions(Hash, Vec<SignedTx>),
+    /// Asks for the strongest chain known by the receiver, when a compact block could not be
+    /// connected.
+    GetChain,
+    /// The answer to a `GetChain`, which may be a chain the sender sent already.
+    RequestedChain(Arc<Chain>),
+    /// Announces pending transactions by hash. The receiver asks for the ones it did not see
+    /// with a `GetTransactions`.
+    Inventory(Vec<crypto::Hash>),
+    /// Asks for pending transactions by hash.
+    GetTransactions(Vec<crypto::Hash>),
+    /// The answer to a `GetTransactions`, without the transactions that left the mempool.
+    Transactions(Vec<SignedTx>),
+    /// Sent by light nodes to full nodes, which only send them the new transactions matching
+    /// the filter from then on.
+    FilterLoad(Arc<BloomFilter>),
+    /// The vote of a voter for a checkpoint, relayed by the nodes that did not count it yet.
+    Vote(Arc<CheckpointVote>),
+}
+
+/// The answer of a full node to a `ProofRequest`.
+#[derive(Clone)]
+pub enum ProofResponse {
+    Proof(Arc<TransactionProof>),
+    /// The block is not part of the chain of the full node, or not anymore.
+    UnknownBlock,
+    /// The full node dropped the transactions of the block, another one may still keep them.
+    Pruned,
+}
+
+/// Asks a full node for a transaction of a block along with the Merkle branch proving it is
+/// part of the block.
+#[derive(Clone)]
+pub struct ProofRequest {
+    block_hash: Hash,
+    height: u32,
+    transaction_index: usize,
+}
+
+impl ProofRequest {
+    pub fn new(block_hash: Hash, height: u32, transaction_index: usize) -> ProofRequest {
+        ProofRequest {
+            block_hash,
+            height,
+            transaction_index,
+        }
+    }
+
+    pub fn block_hash(&self) -> &Hash {
+        &self.block_hash
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn transaction_index(&self) -> usize {
+        self.transaction_index
+    }
+}
@@ -0,0 +1,200 @@
+use crate::blockchain::Misbehavior;
+use crate::error::ChainError;
+use serde::Serialize;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// What a node tells the observers of the network about itself while it runs. Serialized as
+/// JSON objects tagged by their `event`, `block_mined` for instance.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum NodeMetric {
+    /// The node adopted a chain of the given height.
+    ChainHeight { node_id: u32, height: u32 },
+    /// The node received a chain as high as its own, but with a different head.
+    Fork { node_id: u32 },
+    /// The node received a message from a peer.
+    MessageReceived { node_id: u32 },
+    /// The node mined a block, in hexadecimal, whose coinbase collected the given reward, fees
+    /// included.
+    BlockMined {
+        node_id: u32,
+        height: u32,
+        hash: String,
+        reward: u32,
+    },
+    /// The node learned of a block from the given peer, in hexadecimal: the block is part of a
+    /// chain received from the peer, and was not part of the chain of the node. Published
+    /// whether the node adopts the chain or not.
+    BlockReceived {
+        node_id: u32,
+        peer_id: u32,
+        height: u32,
+        hash: String,
+    },
+    /// The node dropped a chain the given peer sent, already received from another peer: it
+    /// was neither validated nor handled again.
+    ChainDuplicate { node_id: u32, peer_id: u32 },
+    /// The node dropped a chain the given peer sent, grown from another genesis block: the
+    /// peer is on another chain of the network.
+    ForeignChain { node_id: u32, peer_id: u32 },
+    /// The node rejected a chain the given peer sent, for the given reason.
+    ChainRejected {
+        node_id: u32,
+        peer_id: u32,
+        error: ChainError,
+    },
+    /// The node adopted a chain received from the given peer, whose head is the given block.
+    BlockPropagated {
+        node_id: u32,
+        peer_id: u32,
+        height: u32,
+        hash: String,
+    },
+    /// The node measured the smoothed round trip time of its connection to the given peer.
+    LinkLatency {
+        node_id: u32,
+        peer_id: u32,
+        round_trip_time_ms: f64,
+    },
+    /// The given peer misbehaved, which added to its score.
+    PeerMisbehaved {
+        node_id: u32,
+        peer_id: u32,
+        misbehavior: Misbehavior,
+    },
+    /// The node dropped a message of the given peer, which exceeded its rate limit.
+    MessageThrottled { node_id: u32, peer_id: u32 },
+    /// The node banned the given peer for its misbehavior.
+    PeerBanned { node_id: u32, peer_id: u32 },
+    /// The node closed the connection of the given peer to stay within its connection limits,
+    /// the peer being the new one if it refused it.
+    PeerEvicted { node_id: u32, peer_id: u32 },
+    /// The node sent the given number of bytes to relay blocks to a peer, where the whole
+    /// blocks would have taken the given number of full block bytes.
+    BlockRelayed {
+        node_id: u32,
+        bytes: u64,
+        full_block_bytes: u64,
+    },
+    /// The node rebuilt a compact block, after asking for the given number of transactions
+    /// missing from its mempool.
+    CompactBlockReconstructed { node_id: u32, missing_transactions: u32 },
+    /// The node could not connect a compact block, and asked for the whole chain instead.
+    CompactBlockFallback { node_id: u32 },
+    /// The node announced the given number of transactions to a peer.
+    TransactionsAnnounced { node_id: u32, transactions: u32 },
+    /// A peer announced transactions to the node, which asked for the unknown ones.
+    InventoryReceived {
+        node_id: u32,
+        transactions: u32,
+        unknown: u32,
+    },
+    /// A peer sent transactions to the node, whose mempool accepted the given number of them
+    /// and refused the given number of valid ones, by policy.
+    TransactionsReceived {
+        node_id: u32,
+        transactions: u32,
+        accepted: u32,
+        refused: u32,
+    },
+    /// The node tested new transactions against the Bloom filter of a light peer, and sent it
+    /// the given number that matched.
+    TransactionsFiltered { node_id: u32, tested: u32, matched: u32 },
+    /// The light node received transactions matching its Bloom filter, the given number of
+    /// which pay one of its addresses.
+    FilteredTransactionsReceived {
+        node_id: u32,
+        transactions: u32,
+        relevant: u32,
+    },
+    /// The node pruned the bodies of the given number of blocks of its chain, and the bodies
+    /// every node pruned were dropped from the shared block store.
+    BlocksPruned {
+        node_id: u32,
+        blocks: u32,
+        dropped_bodies: u32,
+        dropped_bytes: u64,
+    },
+    /// The node could not serve a proof, the body of the block being pruned.
+    ProofRequestPruned { node_id: u32 },
+    /// The node validated the chain of the given height it starts from, or loaded it from a
+    /// UTXO snapshot, in the given time.
+    NodeBootstrapped {
+        node_id: u32,
+        height: u32,
+        bootstrap_ms: f64,
+    },
+    /// The node observed the given validator sign two blocks on top of the same parent, and
+    /// burnt its stake.
+    ValidatorSlashed { node_id: u32, validator: u32, stake: u64 },
+    /// The node received votes from 2/3 of the voting weight for the block at the given
+    /// checkpoint height, in hexadecimal.
+    CheckpointFinalized { node_id: u32, height: u32, hash: String },
+}
+
+impl NodeMetric {
+    /// The height of the block or of the chain the metric is about, if any.
+    pub fn height(&self) -> Option<u32> {
+        match *self {
+            NodeMetric::ChainHeight { height, .. }
+            | NodeMetric::BlockMined { height, .. }
+            | NodeMetric::BlockReceived { height, .. }
+            | NodeMetric::BlockPropagated { height, .. }
+            | NodeMetric::CheckpointFinalized { height, .. } => Some(height),
+            _ => None,
+        }
+    }
+}
+
+/// Carries the metrics of all the nodes of a network to their observers, a dashboard for
+/// instance. The metrics are lost once an observer is gone, or if there is none.
+#[derive(Clone, Default)]
+pub struct MetricsBus {
+    subscribers: Vec<Sender<NodeMetric>>,
+}
+
+impl MetricsBus {
+    pub fn new() -> MetricsBus {
+        MetricsBus::default()
+    }
+
+    /// Receives the metrics published on the clones of the bus made from now on. The receiver
+    /// ends when they are all dropped.
+    pub fn subscribe(&mut self) -> Receiver<NodeMetric> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    pub fn publish(&self, metric: NodeMetric) {
+        for subscriber in &self.subscribers {
+            // The subscriber may not be watching anymore.
+            let _ = subscriber.send(metric.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gives_the_metrics_to_every_subscriber() {
+        let mut bus = MetricsBus::new();
+        let receiver = bus.subscribe();
+        let other_receiver = bus.subscribe();
+        let other_bus = bus.clone();
+
+        bus.publish(NodeMetric::Fork { node_id: 1 });
+        other_bus.publish(NodeMetric::ChainHeight { node_id: 2, height: 3 });
+        drop(bus);
+        drop(other_bus);
+
+        let expected = vec![
+            NodeMetric::Fork { node_id: 1 },
+            NodeMetric::ChainHeight { node_id: 2, height: 3 },
+        ];
+        assert_eq!(expected, receiver.iter().collect::<Vec<NodeMetric>>());
+        assert_eq!(expected, other_receiver.iter().collect::<Vec<NodeMetric>>());
+    }
+}
@@ -1,85 +1,146 @@
-use blockchain::{pow::Nonce, Block, Chain};
-use futures::sync::mpsc::{self, UnboundedSender};
-use futures::Stream;
-use std::ops::Add;
+use crate::blockchain::{pow::Nonce, Block, BlockBody, Chain, HashRegistry};
+use crate::error::Error;
+use btclike::crypto::KeyPair;
+use futures::channel::mpsc::{self, UnboundedSender};
+use futures::{future, stream, Stream, StreamExt};
+use netsim::network::transport::send;
+use std::pin::Pin;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio_timer::Interval;
+use tokio::time;
+
+/// The instants of the mining attempts.
+type Attempts = Pin<Box<dyn Stream<Item = Instant> + Send>>;
 
 struct MiningState {
     chain: Arc<Chain>,
+    body: Arc<BlockBody>,
     nonce: Nonce,
+    extra_nonce: u32,
     node_id: u32,
+    hash_registry: Option<HashRegistry>,
+    /// The key the blocks are signed with, in a proof of authority or proof of stake chain.
+    producer_key: Option<Arc<KeyPair>>,
+    /// Whether the producer signed a block on top of the chain already: another one would
+    /// conflict with it.
+    signed: bool,
 }
 
 impl MiningState {
-    pub fn new(node_id: u32, chain: Arc<Chain>) -> MiningState {
+    pub fn new(
+        node_id: u32,
+        chain: Arc<Chain>,
+        body: Arc<BlockBody>,
+        hash_registry: Option<HashRegistry>,
+        producer_key: Option<Arc<KeyPair>>,
+    ) -> MiningState {
         MiningState {
             chain,
+            body,
             nonce: Nonce::new(),
+            extra_nonce: 0,
             node_id,
+            hash_registry,
+            producer_key,
+            signed: false,
         }
     }
 }
 
+/// What the node tells its miner while it runs.
+pub enum MiningUpdate {
+    /// The body to mine on top of the chain.
+    Chain(Arc<Chain>, Arc<BlockBody>),
+    /// The new delay between two mining attempts, none to stop mining.
+    AttemptDelay(Option<Duration>),
+}
+
 #[derive(Clone)]
 pub struct MiningStateUpdater {
-    sender: UnboundedSender<Arc<Chain>>,
+    sender: UnboundedSender<MiningUpdate>,
 }
 
 impl MiningStateUpdater {
-    pub fn new(sender: UnboundedSender<Arc<Chain>>) -> MiningStateUpdater {
+    pub fn new(sender: UnboundedSender<MiningUpdate>) -> MiningStateUpdater {
         MiningStateUpdater { sender }
     }
 
-    pub fn mine_new_chain(&self, new_chain: Arc<Chain>) {
-        if let Err(_err) = self.sender.unbounded_send(new_chain) {
-            panic!("Could not notify of new chain: {}", _err)
-        }
+    /// Makes the miner mine the given body on top of the given chain.
+    /// Also used to update the body when the chain did not change.
+    pub fn mine_new_chain(&self, new_chain: Arc<Chain>, body: Arc<BlockBody>) -> Result<(), Error> {
+        send(&self.sender, MiningUpdate::Chain(new_chain, body))
+            .map_err(|err| Error::Internal(format!("Could not notify of new chain: {}", err)))
+    }
+
+    /// Changes the delay between two mining attempts, the hash rate of the miner, from the
+    /// next attempt on. None stops the miner until the next change.
+    pub fn set_attempt_delay(&self, attempt_delay: Option<Duration>) -> Result<(), Error> {
+        send(&self.sender, MiningUpdate::AttemptDelay(attempt_delay))
+            .map_err(|err| Error::Internal(format!("Could not change the mining delay: {}", err)))
     }
 }
 
 pub fn mining_stream(
     node_id: u32,
     chain: Arc<Chain>,
+    body: Arc<BlockBody>,
     attempt_delay: Duration,
+    hash_registry: Option<HashRegistry>,
+    producer_key: Option<Arc<KeyPair>>,
 ) -> (
-    impl Stream<Item = Arc<Chain>, Error = ()>,
+    impl Stream<Item = Arc<Chain>>,
     MiningStateUpdater,
 ) {
     let (updater_sender, updater_receiver) = mpsc::unbounded();
 
-    let mut state = MiningState::new(node_id, chain);
+    let state = MiningState::new(node_id, chain, body, hash_registry, producer_key);
 
     let mining_state_updater = MiningStateUpdater::new(updater_sender);
 
-    let mining_stream = updater_receiver
-        // Merging both streams avoids the need of locking on the state by doing everything sequentially.
-        .map(|chain_update|{Some(chain_update)})
-        .select(interval_stream(attempt_delay).map(|_instant|{None}))
-        // Now we can mine or update the state.
-        .map(move |chain_update_option|{
-            if let Some(chain_update) = chain_update_option{
-                if chain_update.stronger_than(&state.chain) {
-                    state.chain = chain_update.clone();
-                    state.nonce = Nonce::new();
-                }
-
-                None
-
-            } else {
-                match mine(&mut state){
-                    MiningResult::Success(mined_new_chain) => {
-                        Some(mined_new_chain)
+    // Merging the updates and the attempts avoids the need of locking on the state by doing
+    // everything sequentially. The attempts stop while there is no delay.
+    let attempts: Option<Attempts> = Some(Box::pin(interval_stream(attempt_delay)));
+    let mining_stream = stream::unfold(
+        (state, updater_receiver, attempts),
+        |(mut state, mut updater_receiver, mut attempts)| async move {
+            loop {
+                let next_attempt = async {
+                    match attempts {
+                        Some(ref mut attempts) => attempts.next().await,
+                        None => future::pending().await,
                     }
-                    MiningResult::Failure => {
-                        None
+                };
+                tokio::select! {
+                    Some(update) = updater_receiver.next() => match update {
+                        MiningUpdate::Chain(chain_update, body_update) => {
+                            // A body update comes with a chain at least as strong as the current
+                            // one, anything weaker is an outdated update.
+                            if !state.chain.stronger_than(&chain_update) {
+                                if state.chain.head().hash() != chain_update.head().hash() {
+                                    state.signed = false;
+                                }
+                                state.chain = chain_update;
+                                state.body = body_update;
+                                state.nonce = Nonce::new();
+                                state.extra_nonce = 0;
+                            }
+                        }
+                        MiningUpdate::AttemptDelay(attempt_delay) => {
+                            attempts = attempt_delay
+                                .map(|attempt_delay| Box::pin(interval_stream(attempt_delay)) as Attempts);
+                        }
+                    },
+                    Some(_instant) = next_attempt => {
+                        // Only the mined blocks are returned.
+                        if let MiningResult::Success(mined_new_chain) = mine(&mut state) {
+                            return Some((mined_new_chain, (state, updater_receiver, attempts)));
+                        }
                     }
+                    else => return None,
                 }
             }
-        })
-        // Filter it so only the mined blocks are returned.
-        .filter_map(|chain_option|{ chain_option });
+        },
+    );
 
     (mining_stream, mining_state_updater)
 }
@@ -89,10 +150,13 @@ pub fn mining_stream(
 /// # Arguments
 ///
 /// `interval_duration`: the duration of the interval between two yielded items.
-fn interval_stream(interval_duration: Duration) -> impl Stream<Item = Instant, Error = ()> {
-    let start_instant = Instant::now().add(interval_duration);
-    Interval::new(start_instant, interval_duration)
-        .map_err(|timer_err| panic!("Timer error: {}", timer_err))
+pub(crate) fn interval_stream(interval_duration: Duration) -> impl Stream<Item = Instant> {
+    let start_instant = time::Instant::now() + interval_duration;
+    let interval = time::interval_at(start_instant, interval_duration);
+    stream::unfold(interval, |mut interval| async move {
+        let instant = interval.tick().await;
+        Some((instant.into_std(), interval))
+    })
 }
 
 enum MiningResult {
@@ -101,7 +165,21 @@ enum MiningResult {
 }
 
 fn mine(state: &mut MiningState) -> MiningResult {
-    state.nonce.increment();
+    if state.signed {
+        return MiningResult::Failure;
+    }
+    // Only the leader elected on top of the chain produces the next block of a proof of stake
+    // chain.
+    if let Some(validators) = state.chain.validators() {
+        if validators.leader(state.chain.head().hash()) != Some(state.node_id) {
+            return MiningResult::Failure;
+        }
+    }
+    if state.nonce.increment().is_err() {
+        // Every nonce was tried, the extra nonce gives as many new ones.
+        state.nonce = Nonce::new();
+        state.extra_nonce = state.extra_nonce.wrapping_add(1);
+    }
 
     let head_hash = state.chain.head().hash().clone();
     let difficulty = &state.chain.head().difficulty;
@@ -109,18 +187,31 @@ fn mine(state: &mut MiningState) -> MiningResult {
     let block = Block::new(
         state.node_id,
         state.nonce.clone(),
+        state.extra_nonce,
         difficulty,
+        state.chain.head().hasher,
         head_hash,
         new_height,
+        &state.body,
     );
+    // Only the blocks meeting the difficulty are worth signing.
+    let block = match state.producer_key {
+        Some(ref producer_key) if block.hash().is_below(&difficulty.threshold().to_be_bytes()) => block.sign(producer_key),
+        _ => block,
+    };
 
     match Chain::expand(&state.chain, block) {
         Ok(mined_chain) => {
+            if let Some(ref hash_registry) = state.hash_registry {
+                hash_registry.record(mined_chain.head());
+            }
+
             debug!(
                 "[N#{}] Mined a new block with height: {}",
                 state.node_id,
                 mined_chain.height()
             );
+            state.signed = state.producer_key.is_some();
             MiningResult::Success(mined_chain)
         }
         Err(err) => {
@@ -0,0 +1,110 @@
+use crate::blockchain::{Misbehavior, NodeMetric};
+use std::collections::BTreeMap;
+
+/// Counts, from the metrics of the nodes, the misbehavior of each peer and how many nodes
+/// banned it for it.
+#[derive(Default)]
+pub struct MisbehaviorStats {
+    /// The misbehavior of every misbehaving peer, by address.
+    peers: BTreeMap<u32, PeerMisbehavior>,
+}
+
+/// The misbehavior of a peer, as seen by all the nodes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PeerMisbehavior {
+    pub invalid_chains: u64,
+    pub spam: u64,
+    pub stale_data: u64,
+    pub bans: u32,
+}
+
+impl MisbehaviorStats {
+    pub fn new() -> MisbehaviorStats {
+        MisbehaviorStats::default()
+    }
+
+    pub fn record(&mut self, metric: &NodeMetric) {
+        match *metric {
+            NodeMetric::PeerMisbehaved {
+                peer_id, misbehavior, ..
+            } => {
+                let peer = self.peers.entry(peer_id).or_default();
+                match misbehavior {
+                    Misbehavior::InvalidChain => peer.invalid_chains += 1,
+                    Misbehavior::Spam => peer.spam += 1,
+                    Misbehavior::StaleData => peer.stale_data += 1,
+                }
+            }
+            NodeMetric::PeerBanned { peer_id, .. } => {
+                self.peers.entry(peer_id).or_default().bans += 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// The misbehavior of all the peers.
+    pub fn total(&self) -> PeerMisbehavior {
+        self.peers.values().fold(PeerMisbehavior::default(), |total, peer| PeerMisbehavior {
+            invalid_chains: total.invalid_chains + peer.invalid_chains,
+            spam: total.spam + peer.spam,
+            stale_data: total.stale_data + peer.stale_data,
+            bans: total.bans + peer.bans,
+        })
+    }
+
+    /// The misbehaving peers, by address.
+    pub fn peers(&self) -> impl Iterator<Item = (u32, PeerMisbehavior)> + '_ {
+        self.peers.iter().map(|(peer_id, misbehavior)| (*peer_id, *misbehavior))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn misbehaved(node_id: u32, peer_id: u32, misbehavior: Misbehavior) -> NodeMetric {
+        NodeMetric::PeerMisbehaved {
+            node_id,
+            peer_id,
+            misbehavior,
+        }
+    }
+
+    #[test]
+    fn counts_the_misbehavior_of_every_peer() {
+        let mut stats = MisbehaviorStats::new();
+        stats.record(&misbehaved(0, 7, Misbehavior::InvalidChain));
+        stats.record(&misbehaved(1, 7, Misbehavior::InvalidChain));
+        stats.record(&misbehaved(1, 7, Misbehavior::StaleData));
+        stats.record(&misbehaved(1, 3, Misbehavior::Spam));
+        stats.record(&NodeMetric::PeerBanned { node_id: 1, peer_id: 7 });
+        stats.record(&NodeMetric::Fork { node_id: 1 });
+
+        let expected_total = PeerMisbehavior {
+            invalid_chains: 2,
+            spam: 1,
+            stale_data: 1,
+            bans: 1,
+        };
+        assert_eq!(expected_total, stats.total());
+        let expected = vec![
+            (
+                3,
+                PeerMisbehavior {
+                    spam: 1,
+                    ..PeerMisbehavior::default()
+                },
+            ),
+            (
+                7,
+                PeerMisbehavior {
+                    invalid_chains: 2,
+                    stale_data: 1,
+                    bans: 1,
+                    ..PeerMisbehavior::default()
+                },
+            ),
+        ];
+        assert_eq!(expected, stats.peers().collect::<Vec<_>>());
+    }
+}
@@ -1,14 +1,172 @@
+mod authority;
+mod bootstrap;
+mod byzantine;
+mod calibration;
+mod compact;
+mod conflicts;
+mod connections;
+mod filter;
+mod finality;
+mod gossip;
+mod height_index;
+mod index;
+mod latency;
+mod light;
+mod logging;
+mod message;
+mod metrics;
 mod miner;
+mod misbehavior;
 mod node;
 mod pow;
+mod propagation;
+mod pruning;
+mod rate_limit;
+mod registry;
+mod relay;
+mod rewards;
+mod scoring;
+mod seen;
+mod shock;
+mod snapshot;
+mod split;
+mod stale;
+mod sybil;
+mod throughput;
+mod trace;
+mod validators;
+mod warm_up;
 
-pub use self::miner::{mining_stream, MiningStateUpdater};
-pub use self::node::PowNode;
-pub use self::pow::Difficulty;
-use blockchain::pow::{Hash, Nonce};
+pub use self::authority::Authorities;
+pub use self::bootstrap::BootstrapStats;
+pub use self::byzantine::{ByzantineNode, Forgery};
+pub use self::calibration::{DifficultySetting, MAX_DIFFICULTY_FACTOR};
+pub use self::compact::{CompactBlock, PartialBlock};
+pub use self::conflicts::DoubleSpendCounter;
+pub use self::connections::{ConnectionLimits, EvictionPolicy, PeerConnection, DEFAULT_ANCHORS};
+pub use self::finality::{CheckpointVote, FinalityGadget, FinalityStats, FinalityVoters, CONFIRMATION_DEPTH};
+pub use self::filter::{FilterStats, DEFAULT_FALSE_POSITIVE_RATE};
+pub use self::gossip::{GossipStats, SeenTransactions, DEFAULT_SEEN_TRANSACTIONS};
+pub use self::height_index::HeightIndex;
+pub use self::index::BlockIndex;
+pub use self::latency::LatencyStats;
+pub use self::logging::{LogCapture, LogLine, NodeLogger};
+pub use self::light::{LightClient, LightNode, TransactionProof};
+pub use self::message::{Message, ProofRequest, ProofResponse};
+pub use self::metrics::{MetricsBus, NodeMetric};
+pub use self::miner::{mining_stream, MiningStateUpdater, MiningUpdate};
+pub use self::misbehavior::{MisbehaviorStats, PeerMisbehavior};
+pub use self::node::{PowNode, SimulationNode};
+pub use self::pow::{hash_header, Nonce};
+pub use btclike::blockchain::Difficulty;
+pub use btclike::crypto::Hash;
+pub use self::propagation::PropagationStats;
+pub use self::pruning::{PruningHorizon, PruningStats, UndoLog, MIN_PRUNE_DEPTH};
+pub use self::rate_limit::TokenBucket;
+pub use self::registry::HashRegistry;
+pub use self::relay::{BlockEncoder, RelayStats};
+pub use self::rewards::RewardStats;
+pub use self::scoring::{BanPolicy, Misbehavior, PeerScores, DEFAULT_BAN_DURATION, DEFAULT_BAN_THRESHOLD};
+pub use self::seen::SeenSet;
+pub use self::shock::{average_hashrate, BlockIntervalStats, HashrateShock, PhaseIntervals};
+pub use self::snapshot::{NodeChains, StrongestChain};
+pub use self::split::{competing_heads, CompetingHead, SplitDetector, SplitEvent, SPLIT_SAMPLE_INTERVAL};
+pub use self::stale::{DegreeStaleBlocks, StaleStats};
+pub use self::sybil::SybilAdversary;
+pub use self::throughput::ThroughputStats;
+pub use self::trace::ChainCodec;
+pub use self::validators::{ValidatorRegistry, ValidatorStats};
+pub use self::warm_up::WarmUp;
+use btclike::blockchain::{Body, COINBASE_AMOUNT};
+use btclike::chain_params::ChainParams;
+use btclike::genesis::GenesisConfig;
+use btclike::crypto::{self, Hasher, KeyPair, Signature, SignatureAlgorithm};
+use btclike::transaction::{Address, TxOut};
+use btclike::u256::U256;
+use btclike::utxo::UtxoSet;
 use ring::digest::SHA256_OUTPUT_LEN;
+use crate::error::ChainError;
+use std::iter;
 use std::sync::Arc;
-use std::u32::MAX as U32_MAX;
+
+/// The transactions of a block along with the root of their Merkle tree. The root is computed
+/// once so that mining attempts do not have to hash the transactions over and over.
+pub struct BlockBody {
+    body: Body,
+    hash: crypto::Hash,
+    /// The size of the serialized transactions, in bytes.
+    size: usize,
+    /// Whether the transactions but the coinbase one were dropped.
+    pruned: bool,
+}
+
+const BODY_ERROR_SERIALIZATION: &str = "Could not serialize the body";
+
+impl BlockBody {
+    pub fn new(body: Body) -> Result<BlockBody, &'static str> {
+        let hash = body.merkle_root().map_err(|_| BODY_ERROR_SERIALIZATION)?;
+        let size = bincode::serialized_size(&body).map_err(|_| BODY_ERROR_SERIALIZATION)? as usize;
+        Ok(BlockBody {
+            body,
+            hash,
+            size,
+            pruned: false,
+        })
+    }
+
+    /// The same body with the coinbase transaction alone. The Merkle root and the size are the
+    /// ones of the whole body.
+    fn pruned(&self) -> BlockBody {
+        BlockBody {
+            body: Body::new(self.body.coinbase_tx().0.clone(), vec![]),
+            hash: self.hash.clone(),
+            size: self.size,
+            pruned: true,
+        }
+    }
+
+    /// The body of a block only known by its header, from a UTXO snapshot: even the coinbase
+    /// transaction is unknown, replaced by an empty payment to an address nobody controls.
+    fn header_only(hash: crypto::Hash) -> BlockBody {
+        let burn_address = Address::from_hash(crypto::Hash::min());
+        BlockBody {
+            body: Body::new(TxOut::new(0, burn_address), vec![]),
+            hash,
+            size: 0,
+            pruned: true,
+        }
+    }
+
+    /// The genesis body pays the coinbase to an address nobody controls, along with the
+    /// premined outputs of the definition.
+    fn genesis(genesis: &GenesisConfig) -> BlockBody {
+        let burn_address = Address::from_hash(crypto::Hash::min());
+        let body = genesis
+            .body(TxOut::new(COINBASE_AMOUNT, burn_address))
+            .expect(BODY_ERROR_SERIALIZATION);
+        BlockBody::new(body).expect(BODY_ERROR_SERIALIZATION)
+    }
+
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    pub fn hash(&self) -> &crypto::Hash {
+        &self.hash
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_pruned(&self) -> bool {
+        self.pruned
+    }
+
+    fn hash_bytes(&self) -> &[u8; SHA256_OUTPUT_LEN] {
+        self.hash.as_ref()
+    }
+}
 
 pub struct Block {
     /// in order to protect these fields to being tampered with, all of them
@@ -22,9 +180,15 @@ pub struct Block {
     /// The nonce field enables a node to produce a different hash for every
     /// mining attempt.
     nonce: Nonce,
+    /// Changed by the miner once it tried every nonce, like the extra nonce of
+    /// Bitcoin's coinbase transactions, so that it can keep producing new hashes.
+    extra_nonce: u32,
     /// For a block to be added to the chain and accepted by the other nodes,
     /// its hash must be inferior to the difficulty threshold.
     difficulty: Arc<Difficulty>,
+    /// The hash function of the proof of work. Like the difficulty, it is set by the
+    /// genesis block and every block must keep the one of its parent.
+    hasher: Hasher,
     /// By including the hash of the previous block among the input of the hash
     /// of the current block, it links it to the previous one, making the chain
     /// more secure.
@@ -34,182 +198,627 @@ pub struct Block {
     /// different blocks. It has other benefits, like helping identifying a block
     /// or preventing us from having to count all the blocks one by one.
     height: u32,
+    /// The transactions confirmed by this block. Only the Merkle root of the
+    /// transactions is part of the hash input, the body itself is checked against it.
+    body: Arc<BlockBody>,
+    /// In a proof of authority or proof of stake chain, the signature of the hash by the node
+    /// that produced the block. It is not part of the hash input, which it signs.
+    signature: Option<Signature>,
 }
 
-const HEAD_ERROR_INVALID_HASH: &str = "Invalid hash";
-const HEAD_ERROR_HASH_HIGHER_THAN_DIFFICULTY: &str = "Hash higher than difficulty";
+/// The size of the fields of a block other than its transactions, in bytes: the hashes, the
+/// node id, the nonces, the difficulty, the hash function, the height and the Merkle root.
+pub const BLOCK_HEADER_SIZE: usize = 3 * SHA256_OUTPUT_LEN + 4 + 8 + 4 + SHA256_OUTPUT_LEN + 1 + 4;
 
 impl Block {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         node_id: u32,
         nonce: Nonce,
+        extra_nonce: u32,
         difficulty: &Arc<Difficulty>,
+        hasher: Hasher,
         previous_block_hash: Hash,
         height: u32,
+        body: &Arc<BlockBody>,
     ) -> Block {
-        let hash = Hash::new(
+        let hash = hash_header(
             node_id,
             &nonce,
+            extra_nonce,
             difficulty,
+            hasher,
             height,
-            previous_block_hash.bytes(),
+            previous_block_hash.as_ref(),
+            body.hash_bytes(),
         );
         Block {
             node_id,
             nonce,
+            extra_nonce,
             hash,
             difficulty: difficulty.clone(),
+            hasher,
             height,
             previous_block_hash,
+            body: body.clone(),
+            signature: None,
         }
     }
 
-    /// The genesis block is the first block of the chain. It is the same for all nodes.
-    pub fn genesis_block(difficulty: Arc<Difficulty>) -> Block {
+    /// Signs the hash of the block with the key of its producer, for a proof of authority or
+    /// proof of stake chain.
+    fn sign(mut self, key_pair: &KeyPair) -> Block {
+        self.signature = Some(key_pair.sign(self.hash.as_ref()));
+        self
+    }
+
+    /// The genesis block is the first block of the chain. It is the same for all the nodes
+    /// sharing its definition. It has no parent: the previous block hash of its hash input
+    /// commits to the timestamp of the definition instead.
+    pub fn genesis_block(difficulty: Arc<Difficulty>, hasher: Hasher, genesis: &GenesisConfig) -> Block {
         let nonce = Nonce::new();
-        let genesis_node_id = U32_MAX;
+        let genesis_node_id = u32::MAX;
         let height = 0;
-        let hash = Hash::new(
+        let body = BlockBody::genesis(genesis);
+        let hash = hash_header(
             genesis_node_id,
             &nonce,
+            0,
             &difficulty,
+            hasher,
             height,
-            &[0u8; SHA256_OUTPUT_LEN],
+            genesis.previous_block_hash().as_ref(),
+            body.hash_bytes(),
         );
         Block {
             node_id: genesis_node_id,
             nonce,
+            extra_nonce: 0,
             difficulty,
+            hasher,
             previous_block_hash: hash.clone(),
             height,
             hash,
+            body: Arc::new(body),
+            signature: None,
+        }
+    }
+
+    /// Checks that the hash matches the fields and that it does not exceed the difficulty threshold.
+    pub fn validate(&self) -> Result<(), ChainError> {
+        self.header().validate()?;
+        self.validate_body_hash()
+    }
+
+    /// The fields of the block without the transactions.
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            hash: self.hash.clone(),
+            node_id: self.node_id,
+            nonce: self.nonce.clone(),
+            extra_nonce: self.extra_nonce,
+            difficulty: self.difficulty.clone(),
+            hasher: self.hasher,
+            previous_block_hash: self.previous_block_hash.clone(),
+            height: self.height,
+            merkle_root: self.body.hash().clone(),
+            signature: self.signature.clone(),
+        }
+    }
+
+    /// The same block without its transactions but the coinbase one. Its header is the same,
+    /// but it cannot be validated nor replayed anymore.
+    fn pruned(&self) -> Block {
+        Block {
+            hash: self.hash.clone(),
+            node_id: self.node_id,
+            nonce: self.nonce.clone(),
+            extra_nonce: self.extra_nonce,
+            difficulty: self.difficulty.clone(),
+            hasher: self.hasher,
+            previous_block_hash: self.previous_block_hash.clone(),
+            height: self.height,
+            body: Arc::new(self.body.pruned()),
+            signature: self.signature.clone(),
         }
     }
 
+    /// Checks that the transactions match the Merkle root included in the block hash input.
+    fn validate_body_hash(&self) -> Result<(), ChainError> {
+        if self.body.pruned {
+            return Err(ChainError::Pruned);
+        }
+        match self.body.body.merkle_root() {
+            Ok(ref hash) if hash == self.body.hash() => Ok(()),
+            _ => Err(ChainError::InvalidBodyHash),
+        }
+    }
+
+    pub fn hash(&self) -> &Hash {
+        &self.hash
+    }
+
+    pub fn difficulty(&self) -> &Arc<Difficulty> {
+        &self.difficulty
+    }
+
+    pub fn hasher(&self) -> Hasher {
+        self.hasher
+    }
+
+    pub fn body(&self) -> &Arc<BlockBody> {
+        &self.body
+    }
+
+    /// The size of the block sent whole to a peer, in bytes.
+    pub fn size(&self) -> usize {
+        BLOCK_HEADER_SIZE + signature_size(&self.signature) + self.body.size
+    }
+}
+
+/// The fields of a block that are part of its hash input, where the transactions are only
+/// represented by their Merkle root. This is all a light client needs to check the proof of work.
+#[derive(Clone)]
+pub struct BlockHeader {
+    hash: Hash,
+    node_id: u32,
+    nonce: Nonce,
+    extra_nonce: u32,
+    difficulty: Arc<Difficulty>,
+    hasher: Hasher,
+    previous_block_hash: Hash,
+    height: u32,
+    merkle_root: crypto::Hash,
+    signature: Option<Signature>,
+}
+
+impl BlockHeader {
     /// Checks that the hash matches the fields and that it does not exceed the difficulty threshold.
-    pub fn validate(&self) -> Result<(), &'static str> {
-        if self.hash.less_than(&self.difficulty) {
-            let hash = Hash::new(
+    pub fn validate(&self) -> Result<(), ChainError> {
+        if self.hash.is_below(&self.difficulty.threshold().to_be_bytes()) {
+            let hash = hash_header(
                 self.node_id,
                 &self.nonce,
+                self.extra_nonce,
                 &self.difficulty,
+                self.hasher,
                 self.height,
-                &self.previous_block_hash.bytes(),
+                self.previous_block_hash.as_ref(),
+                self.merkle_root.as_ref(),
             );
 
             if hash.eq(&self.hash) {
                 Ok(())
             } else {
-                Err(HEAD_ERROR_INVALID_HASH)
+                Err(ChainError::InvalidHash)
             }
         } else {
-            Err(HEAD_ERROR_HASH_HIGHER_THAN_DIFFICULTY)
+            Err(ChainError::HashHigherThanDifficulty)
         }
     }
 
     pub fn hash(&self) -> &Hash {
         &self.hash
     }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn merkle_root(&self) -> &crypto::Hash {
+        &self.merkle_root
+    }
+
+    /// The size of the header sent to a peer, in bytes.
+    pub fn size(&self) -> usize {
+        BLOCK_HEADER_SIZE + signature_size(&self.signature)
+    }
 }
 
-pub struct Chain {
-    head: Block,
-    tail: Option<Arc<Chain>>,
+fn signature_size(signature: &Option<Signature>) -> usize {
+    signature.as_ref().map_or(0, |signature| signature.as_bytes().len())
 }
 
-const CHAIN_ERROR_HASH_MISMATCH: &str = "Hash mismatch";
-const CHAIN_ERROR_HEIGHT_MISMATCH: &str = "Height mismatch";
-const CHAIN_ERROR_INVALID_GENESIS: &str = "Invalid genesis";
-const CHAIN_ERROR_INVALID_DIFFICULTY: &str = "Invalid difficulty";
+/// A chain is a reference to its head block: the other blocks are found in the index shared
+/// by every chain expanding the same genesis block.
+pub struct Chain {
+    head: Arc<Block>,
+    index: BlockIndex,
+    /// The scheme the transactions of the chain are signed with, chosen with the genesis block.
+    signature_algorithm: SignatureAlgorithm,
+    /// The total work of the blocks of the chain, genesis included.
+    work: U256,
+    /// The producers of the blocks of a proof of authority chain, chosen with the genesis
+    /// block. None for a proof of work chain, whose blocks anyone can mine.
+    authorities: Option<Arc<Authorities>>,
+    /// The validators of a proof of stake chain and their stakes, chosen with the genesis
+    /// block. None for the other chains.
+    validators: Option<Arc<ValidatorRegistry>>,
+    /// The rules of the network of the chain, chosen with the genesis block.
+    params: Arc<ChainParams>,
+    /// The definition the genesis block was built from.
+    genesis: Arc<GenesisConfig>,
+}
 
 impl Chain {
     pub fn init_new(difficulty: Difficulty) -> Chain {
+        let work = difficulty.work();
+        let genesis_block = Block::genesis_block(Arc::new(difficulty), Hasher::default(), &GenesisConfig::default());
+        Chain::genesis(genesis_block, work)
+    }
+
+    fn genesis(genesis_block: Block, work: U256) -> Chain {
+        let head = Arc::new(genesis_block);
+        let index = BlockIndex::new();
+        index.insert(&head);
+        Chain {
+            head,
+            index,
+            signature_algorithm: SignatureAlgorithm::default(),
+            work,
+            authorities: None,
+            validators: None,
+            params: Arc::new(ChainParams::main()),
+            genesis: Arc::new(GenesisConfig::default()),
+        }
+    }
+
+    /// Selects the hash function of the proof of work of a new chain, SHA-256 by default.
+    /// The genesis block is built again with it, so this is only meant for a chain made of
+    /// the genesis block alone.
+    pub fn with_hasher(self, hasher: Hasher) -> Chain {
+        let genesis_block = Block::genesis_block(self.head.difficulty.clone(), hasher, &self.genesis);
+        let chain = Chain::genesis(genesis_block, self.work).with_signature_algorithm(self.signature_algorithm);
+        Chain {
+            authorities: self.authorities,
+            validators: self.validators,
+            params: self.params,
+            genesis: self.genesis,
+            ..chain
+        }
+    }
+
+    /// Builds the genesis block of a new chain from the given definition, with its timestamp
+    /// and its premined outputs. The difficulty of the definition is ignored, the one of the
+    /// chain is kept. Like `with_hasher`, this is only meant for a chain made of the genesis
+    /// block alone.
+    pub fn with_genesis(self, genesis: GenesisConfig) -> Chain {
+        let genesis_block = Block::genesis_block(self.head.difficulty.clone(), self.head.hasher, &genesis);
+        let chain = Chain::genesis(genesis_block, self.work).with_signature_algorithm(self.signature_algorithm);
         Chain {
-            head: Block::genesis_block(Arc::new(difficulty)),
-            tail: None,
+            authorities: self.authorities,
+            validators: self.validators,
+            params: self.params,
+            genesis: Arc::new(genesis),
+            ..chain
         }
     }
 
+    /// The definition of the genesis block of the chain.
+    pub fn genesis_config(&self) -> &GenesisConfig {
+        &self.genesis
+    }
+
+    /// Selects the signature scheme of a new chain, Ed25519 by default. The chains expanding
+    /// it inherit the scheme.
+    pub fn with_signature_algorithm(mut self, signature_algorithm: SignatureAlgorithm) -> Chain {
+        self.signature_algorithm = signature_algorithm;
+        self
+    }
+
+    /// Makes a new chain a proof of authority one: only the given authorities can produce its
+    /// blocks, which they sign. The chains expanding it inherit the authorities.
+    pub fn with_authorities(mut self, authorities: Authorities) -> Chain {
+        self.authorities = Some(Arc::new(authorities));
+        self
+    }
+
+    /// Makes a new chain a proof of stake one: the block on top of each parent can only be
+    /// produced by the validator elected for it, which signs it. The chains expanding it
+    /// inherit the validators.
+    pub fn with_validators(mut self, validators: ValidatorRegistry) -> Chain {
+        self.validators = Some(Arc::new(validators));
+        self
+    }
+
+    pub fn validators(&self) -> Option<&Arc<ValidatorRegistry>> {
+        self.validators.as_ref()
+    }
+
+    /// Selects the network of a new chain, the main one by default: its coinbase schedule, its
+    /// coinbase maturity and its maximum block size. The chains expanding it inherit it.
+    pub fn with_params(mut self, params: ChainParams) -> Chain {
+        self.params = Arc::new(params);
+        self
+    }
+
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
     /// Creates a new chain by adding a block to an existing chain.
     /// Will fail if the block is invalid or the hashes do not match.
-    pub fn expand(chain: &Arc<Chain>, block: Block) -> Result<Arc<Chain>, &'static str> {
+    pub fn expand(chain: &Arc<Chain>, block: Block) -> Result<Arc<Chain>, ChainError> {
+        let new_chain = Chain::unvalidated_expand(chain, block);
+
+        chain.validate_link(&chain.head, &new_chain.head)?;
+        new_chain.index.insert(&new_chain.head);
+        Ok(Arc::new(new_chain))
+    }
+
+    /// Same as `expand`, for a block whose body is unknown: only its header is checked.
+    fn expand_header(chain: &Arc<Chain>, block: Block) -> Result<Arc<Chain>, ChainError> {
         let new_chain = Chain::unvalidated_expand(chain, block);
 
-        new_chain.validate_head()?;
+        chain.validate_header_link(&chain.head, &new_chain.head)?;
+        new_chain.index.insert(&new_chain.head);
         Ok(Arc::new(new_chain))
     }
 
     /// Creates a new chain by adding a block to an existing chain.
     /// Will succeed even if the block is invalid or the hashes do not match.
+    /// The block is not indexed, so only this chain contains it.
     fn unvalidated_expand(chain: &Arc<Chain>, block: Block) -> Chain {
         Chain {
-            head: block,
-            tail: Some(chain.clone()),
+            work: chain.work.saturating_add(&block.difficulty.work()),
+            head: Arc::new(block),
+            index: chain.index.clone(),
+            signature_algorithm: chain.signature_algorithm,
+            authorities: chain.authorities.clone(),
+            validators: chain.validators.clone(),
+            params: chain.params.clone(),
+            genesis: chain.genesis.clone(),
         }
     }
 
     /// The head of the chain is the block at the top of it.
-    pub fn head(&self) -> &Block {
+    pub fn head(&self) -> &Arc<Block> {
         &self.head
     }
 
+    /// The chain without its head block, none for the genesis block alone.
+    pub fn tail(&self) -> Option<Arc<Chain>> {
+        self.index.parent(&self.head).map(|parent| {
+            Arc::new(Chain {
+                work: self.work.checked_sub(&self.head.difficulty.work()).unwrap_or_else(U256::zero),
+                head: parent,
+                index: self.index.clone(),
+                signature_algorithm: self.signature_algorithm,
+                authorities: self.authorities.clone(),
+                validators: self.validators.clone(),
+                params: self.params.clone(),
+                genesis: self.genesis.clone(),
+            })
+        })
+    }
+
+    /// The blocks of the chain, from the head to the genesis block.
+    pub fn blocks(&self) -> impl Iterator<Item = Arc<Block>> + '_ {
+        iter::successors(Some(self.head.clone()), move |block| self.index.parent(block))
+    }
+
     /// The height of the chain is the number of blocks composing the chain.
     /// It is the same that the height of the head block.
     pub fn height(&self) -> u32 {
         self.head.height
     }
 
+    pub fn signature_algorithm(&self) -> SignatureAlgorithm {
+        self.signature_algorithm
+    }
+
+    pub fn work(&self) -> U256 {
+        self.work
+    }
+
+    /// Like in the Bitcoin network, the strongest chain is the chain with the most work. With
+    /// a constant difficulty, it is the longest one.
     pub fn stronger_than(&self, other: &Chain) -> bool {
-        // Since this is a constant difficulty simulation, the strongest chain is the longest.
-        // This is not the case with a dynamic difficulty like in the Bitcoin network where the
-        // strongest chain is the chain with the most work.
-        self.height() > other.height()
+        self.work > other.work
+    }
+
+    /// The block of this chain at the given height, if any.
+    pub fn block_at(&self, height: u32) -> Option<Arc<Block>> {
+        self.blocks()
+            .find(|block| block.height <= height)
+            .filter(|block| block.height == height)
     }
 
-    fn hashes_match(chain: &Arc<Chain>, block: &Block) -> bool {
-        chain.head.hash.eq(&block.previous_block_hash)
+    /// The blocks of this chain and of the other one that come after their last common block,
+    /// head first. Chains starting from different genesis blocks do not have any common block.
+    pub fn diverging_blocks(&self, other: &Chain) -> (Vec<Arc<Block>>, Vec<Arc<Block>>) {
+        let mut own_blocks = vec![];
+        let mut other_blocks = vec![];
+        let mut own_block = Some(self.head.clone());
+        let mut other_block = Some(other.head.clone());
+
+        loop {
+            let (advance_own, advance_other) = match (&own_block, &other_block) {
+                (Some(own), Some(other)) => {
+                    if own.height == other.height && own.hash() == other.hash() {
+                        break;
+                    }
+                    (own.height >= other.height, other.height >= own.height)
+                }
+                (Some(_own), None) => (true, false),
+                (None, Some(_other)) => (false, true),
+                (None, None) => break,
+            };
+
+            if advance_own {
+                let own = own_block.take().expect("The chain is not exhausted");
+                own_block = self.index.parent(&own);
+                own_blocks.push(own);
+            }
+            if advance_other {
+                let other_head = other_block.take().expect("The chain is not exhausted");
+                other_block = other.index.parent(&other_head);
+                other_blocks.push(other_head);
+            }
+        }
+
+        (own_blocks, other_blocks)
     }
 
-    /// Checks that the chain is valid from head to tail and that it starts from the genesis block.
+    /// Checks that the chain is valid from head to tail and that it starts from the genesis block,
+    /// then replays the transactions from the genesis block to the head.
+    /// Returns the resulting set of unspent transaction outputs.
     /// The current implementation is not the most efficient but is efficient enough
     /// for this simulation.
-    pub fn validate(&self) -> Result<(), &'static str> {
-        if let Err(err) = self.validate_head() {
-            return Err(err);
+    pub fn validate(&self) -> Result<UtxoSet, ChainError> {
+        let mut blocks = vec![];
+        let mut block = self.head.clone();
+        while block.height > 0 {
+            let parent = self.index.parent(&block).ok_or(ChainError::HashMismatch)?;
+            self.validate_link(&parent, &block)?;
+            blocks.push(block);
+            block = parent;
         }
 
-        if let Some(ref tail) = self.tail {
-            Chain::validate(tail)
-        } else if self.head
+        let genesis = block;
+        if !genesis
             .hash()
-            .eq(Block::genesis_block(self.head.difficulty.clone()).hash())
+            .eq(Block::genesis_block(genesis.difficulty.clone(), genesis.hasher, &self.genesis).hash())
         {
-            Ok(())
-        } else {
-            Err(CHAIN_ERROR_INVALID_GENESIS)
-        }
-    }
-
-    fn validate_head(&self) -> Result<(), &'static str> {
-        if let Some(ref tail) = self.tail {
-            match self.head.validate() {
-                Ok(()) => {
-                    if self.height() == tail.height() + 1 {
-                        if Chain::hashes_match(tail, &self.head) {
-                            if tail.head.difficulty.eq(&self.head.difficulty) {
-                                Ok(())
-                            } else {
-                                Err(CHAIN_ERROR_INVALID_DIFFICULTY)
-                            }
-                        } else {
-                            Err(CHAIN_ERROR_HASH_MISMATCH)
-                        }
-                    } else {
-                        Err(CHAIN_ERROR_HEIGHT_MISMATCH)
-                    }
+            return Err(ChainError::InvalidGenesis);
+        }
+        genesis.validate_body_hash()?;
+        blocks.push(genesis);
+
+        let mut utxo_set = UtxoSet::new().with_signature_algorithm(self.signature_algorithm);
+        Chain::replay(&blocks, &mut utxo_set, &self.params)?;
+        Ok(utxo_set)
+    }
+
+    /// Same as `validate` but if this chain extends `known_chain`, only the blocks on top of
+    /// it are checked and their transactions are replayed from `known_utxo_set`, which must
+    /// be the result of the validation of `known_chain`.
+    pub fn validate_from(
+        &self,
+        known_chain: &Chain,
+        known_utxo_set: &UtxoSet,
+    ) -> Result<UtxoSet, ChainError> {
+        // Chains signed with another scheme do not share the same genesis.
+        if self.signature_algorithm != known_chain.signature_algorithm {
+            return Err(ChainError::InvalidGenesis);
+        }
+        // Every block keeps the hash function of its parent, checked below.
+        if self.head.hasher != known_chain.head.hasher {
+            return Err(ChainError::InvalidHasher);
+        }
+
+        let mut new_blocks = vec![];
+        let mut block = self.head.clone();
+        while block.height > known_chain.height() {
+            match self.index.parent(&block) {
+                Some(parent) => {
+                    self.validate_link(&parent, &block)?;
+                    new_blocks.push(block);
+                    block = parent;
                 }
-                Err(err) => Err(err),
+                None => break,
             }
+        }
+
+        if block.height == known_chain.height() && block.hash() == known_chain.head.hash() {
+            let mut utxo_set = known_utxo_set.clone();
+            Chain::replay(&new_blocks, &mut utxo_set, &self.params)?;
+            Ok(utxo_set)
+        } else {
+            self.validate()
+        }
+    }
+
+    /// Same as `validate_from`, for a node that only keeps the bodies of the last blocks of
+    /// `known_chain`: its unspent outputs are rolled back to the last block it has in common
+    /// with this chain with the given undo data, then the blocks of this chain are replayed.
+    /// Fails with `ChainError::Pruned` if the fork is deeper than the undo data. Returns the
+    /// unspent outputs and the undo data of this chain.
+    pub fn validate_from_fork(
+        &self,
+        known_chain: &Chain,
+        known_utxo_set: &UtxoSet,
+        known_undo_log: &UndoLog,
+    ) -> Result<(UtxoSet, UndoLog), ChainError> {
+        if self.signature_algorithm != known_chain.signature_algorithm {
+            return Err(ChainError::InvalidGenesis);
+        }
+        if self.head.hasher != known_chain.head.hasher {
+            return Err(ChainError::InvalidHasher);
+        }
+
+        let (new_blocks, rolled_back_blocks) = self.diverging_blocks(known_chain);
+        if new_blocks.last().is_some_and(|block| block.height == 0) {
+            return Err(ChainError::InvalidGenesis);
+        }
+        if rolled_back_blocks.len() > known_undo_log.len() {
+            return Err(ChainError::Pruned);
+        }
+
+        let mut utxo_set = known_utxo_set.clone();
+        let mut undo_log = known_undo_log.clone();
+        for _block in &rolled_back_blocks {
+            // Undo data not matching the known chain cannot tell whether this one is valid either.
+            let undo = undo_log.pop().ok_or(ChainError::Pruned)?;
+            utxo_set.rollback(&undo).map_err(|_| ChainError::Pruned)?;
+        }
+
+        for block in new_blocks.iter().rev() {
+            let parent = self.index.parent(block).ok_or(ChainError::HashMismatch)?;
+            self.validate_link(&parent, block)?;
+            let body = block.body.body();
+            let undo = body
+                .verify(&utxo_set, block.height, &self.params)
+                .and_then(|()| utxo_set.apply(body, block.height))
+                .map_err(|_| ChainError::InvalidTransactions)?;
+            undo_log.push(undo);
+        }
+
+        Ok((utxo_set, undo_log))
+    }
+
+    /// Verifies and applies the transactions of the given blocks, from the last one to the first.
+    fn replay(blocks: &[Arc<Block>], utxo_set: &mut UtxoSet, params: &ChainParams) -> Result<(), ChainError> {
+        for block in blocks.iter().rev() {
+            let body = block.body.body();
+            body.verify(utxo_set, block.height, params)
+                .and_then(|()| utxo_set.apply(body, block.height).map(|_undo| ()))
+                .map_err(|_| ChainError::InvalidTransactions)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the block is valid and that it extends the parent one.
+    fn validate_link(&self, parent: &Block, block: &Block) -> Result<(), ChainError> {
+        self.validate_header_link(parent, block)?;
+        if block.body.size() > self.params.max_block_size() {
+            return Err(ChainError::BlockTooLarge);
+        }
+        block.validate_body_hash()
+    }
+
+    /// Same as `validate_link`, without the body of the block. The block of a proof of
+    /// authority chain must be signed by an authority as well, and the block of a proof of
+    /// stake chain by the validator elected for its parent.
+    fn validate_header_link(&self, parent: &Block, block: &Block) -> Result<(), ChainError> {
+        let header = block.header();
+        header.validate()?;
+        if let Some(ref authorities) = self.authorities {
+            authorities.verify(&header)?;
+        }
+        if let Some(ref validators) = self.validators {
+            validators.verify(&header)?;
+        }
+        if block.height != parent.height + 1 {
+            Err(ChainError::HeightMismatch)
+        } else if !parent.hash.eq(&block.previous_block_hash) {
+            Err(ChainError::HashMismatch)
+        } else if !parent.difficulty.eq(&block.difficulty) {
+            Err(ChainError::InvalidDifficulty)
+        } else if parent.hasher != block.hasher {
+            Err(ChainError::InvalidHasher)
         } else {
             Ok(())
         }
@@ -219,12 +828,27 @@ impl Chain {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use btclike::blockchain::COINBASE_MATURITY;
+    use btclike::transaction::SignedTx;
+    use btclike::transaction::UtxoStore;
+    use btclike::wallet::Wallet;
+    use proptest::prelude::*;
+    use proptest::sample::Index;
 
     fn decapitate(chain: Arc<Chain>) -> (Option<Arc<Chain>>, Block) {
-        match Arc::try_unwrap(chain) {
-            Ok(chain) => (chain.tail, chain.head),
-            Err(_err) => panic!(),
-        }
+        let tail = chain.tail();
+        let head = chain.head();
+        let block = Block::new(
+            head.node_id,
+            head.nonce.clone(),
+            head.extra_nonce,
+            &head.difficulty,
+            head.hasher,
+            head.previous_block_hash.clone(),
+            head.height,
+            &head.body,
+        );
+        (tail, block)
     }
 
     #[test]
@@ -235,6 +859,8 @@ mod tests {
 
         assert!(chain.validate().is_ok());
         assert_eq!(5, chain.height());
+        // Half of the hashes are below the threshold: each block is worth two hashes.
+        assert_eq!(U256::from(12), chain.work());
     }
 
     #[test]
@@ -250,19 +876,265 @@ mod tests {
         assert!(Chain::unvalidated_expand(&chain, block).validate().is_err());
     }
 
+    #[test]
+    fn cannot_forge_hasher() {
+        let (_nonce, mut block, chain) = init_decapitated_chain();
+        block.hasher = Hasher::DoubleSha256;
+        assert!(Chain::expand(&chain, block).is_err());
+
+        let (_nonce, mut block, chain) = init_decapitated_chain();
+        block.hasher = Hasher::DoubleSha256;
+        assert!(Chain::unvalidated_expand(&chain, block).validate().is_err());
+    }
+
+    #[test]
+    fn can_mine_with_another_hasher() {
+        let (sha256_chain, node_id, mut nonce) = init_chain();
+        let mut difficulty = Difficulty::min_difficulty();
+        difficulty.increase().unwrap();
+        let chain = Arc::new(Chain::init_new(difficulty).with_hasher(Hasher::DoubleSha256));
+
+        let chain = mine_5_blocks(chain, node_id, &mut nonce);
+
+        assert!(chain.validate().is_ok());
+        assert!(Hasher::DoubleSha256 == chain.head().hasher);
+        // Chains hashed with another function cannot extend each other.
+        let sha256_utxo_set = sha256_chain.validate().unwrap();
+        assert!(chain.validate_from(&sha256_chain, &sha256_utxo_set).is_err());
+    }
+
     #[test]
     fn cannot_forge_nonce() {
         let (mut nonce, mut block, chain) = init_decapitated_chain();
-        nonce.increment();
+        nonce.increment().unwrap();
         block.nonce = nonce;
         assert!(Chain::expand(&chain, block).is_err());
 
         let (mut nonce, mut block, chain) = init_decapitated_chain();
-        nonce.increment();
+        nonce.increment().unwrap();
         block.nonce = nonce;
         assert!(Chain::unvalidated_expand(&chain, block).validate().is_err());
     }
 
+    #[test]
+    fn can_confirm_transactions() {
+        let (mut chain, node_id, mut nonce) = init_chain();
+        let mut wallet = Wallet::new();
+
+        let coinbase_body = body(wallet.new_address().unwrap(), vec![], 0);
+        chain = mine_next_block(chain, node_id, &mut nonce, &coinbase_body);
+        wallet.connect_block(coinbase_body.body(), chain.height()).unwrap();
+        chain = mine_until_mature(chain, node_id, &mut nonce);
+        let utxo_set = chain.validate().unwrap();
+
+        let to_address = wallet.new_address().unwrap();
+        let transaction = wallet
+            .new_transaction(100, to_address, 5, &utxo_set)
+            .unwrap();
+        let transaction_body = body(wallet.new_address().unwrap(), vec![transaction.clone()], 5);
+        chain = mine_next_block(chain, node_id, &mut nonce, &transaction_body);
+
+        let utxo_set = chain.validate().unwrap();
+        assert_eq!(1 + COINBASE_MATURITY, chain.height());
+        assert_eq!(
+            &100,
+            utxo_set
+                .find(&transaction.hash().unwrap(), &1)
+                .unwrap()
+                .amount()
+        );
+    }
+
+    #[test]
+    fn can_spend_the_premined_outputs_of_a_genesis_definition() {
+        let (chain, node_id, mut nonce) = init_chain();
+        let mut wallet = Wallet::new();
+        // The premined outputs change the genesis block.
+        let genesis = GenesisConfig::new(1231006505).with_premine(wallet.new_address().unwrap(), 500);
+        let premined_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()).with_genesis(genesis));
+        assert!(chain.head().hash() != premined_chain.head().hash());
+        // Another timestamp makes another genesis block.
+        let other_chain = Chain::init_new(Difficulty::min_difficulty()).with_genesis(GenesisConfig::new(1));
+        assert!(chain.head().hash() != other_chain.head().hash());
+
+        let utxo_set = premined_chain.validate().unwrap();
+        wallet.connect_block(premined_chain.head().body().body(), 0).unwrap();
+        let to_address = wallet.new_address().unwrap();
+        let transaction = wallet.new_transaction(100, to_address, 5, &utxo_set).unwrap();
+        let transaction_body = body(wallet.new_address().unwrap(), vec![transaction], 5);
+        let premined_chain = mine_next_block(premined_chain, node_id, &mut nonce, &transaction_body);
+        assert!(premined_chain.validate().is_ok());
+    }
+
+    #[test]
+    fn can_validate_from_a_known_chain() {
+        let (mut chain, node_id, mut nonce) = init_chain();
+        let mut wallet = Wallet::new();
+
+        let coinbase_body = body(wallet.new_address().unwrap(), vec![], 0);
+        chain = mine_next_block(chain, node_id, &mut nonce, &coinbase_body);
+        wallet.connect_block(coinbase_body.body(), chain.height()).unwrap();
+        chain = mine_until_mature(chain, node_id, &mut nonce);
+        let known_chain = chain.clone();
+        let known_utxo_set = known_chain.validate().unwrap();
+
+        let to_address = wallet.new_address().unwrap();
+        let transaction = wallet
+            .new_transaction(100, to_address, 0, &known_utxo_set)
+            .unwrap();
+        let transaction_body = body(wallet.new_address().unwrap(), vec![transaction], 0);
+        chain = mine_next_block(chain, node_id, &mut nonce, &transaction_body);
+
+        let utxo_set = chain.validate_from(&known_chain, &known_utxo_set).unwrap();
+        assert_eq!(chain.validate().unwrap().len(), utxo_set.len());
+
+        // The known chain does not match the UTXO set, but it is not a tail of this chain.
+        let (other_chain, _node_id, _nonce) = init_chain();
+        let other_chain = mine_5_blocks(other_chain, node_id, &mut nonce);
+        let utxo_set = chain.validate_from(&other_chain, &UtxoSet::new()).unwrap();
+        assert_eq!(chain.validate().unwrap().len(), utxo_set.len());
+    }
+
+    #[test]
+    fn cannot_forge_transactions() {
+        let (mut chain, node_id, mut nonce) = init_chain();
+        let mut wallet = Wallet::new();
+
+        let coinbase_body = body(wallet.new_address().unwrap(), vec![], 0);
+        chain = mine_next_block(chain, node_id, &mut nonce, &coinbase_body);
+        wallet.connect_block(coinbase_body.body(), chain.height()).unwrap();
+        let utxo_set = chain.validate().unwrap();
+
+        // The coinbase amount does not match the fees.
+        let invalid_body = body(wallet.new_address().unwrap(), vec![], 1);
+        let invalid_chain = mine_next_block(chain.clone(), node_id, &mut nonce, &invalid_body);
+        assert!(invalid_chain.validate().is_err());
+
+        // The coinbase output is spent before being mature.
+        let to_address = wallet.new_address().unwrap();
+        let transaction = wallet
+            .new_transaction(100, to_address, 0, &utxo_set)
+            .unwrap();
+        let immature_body = body(wallet.new_address().unwrap(), vec![transaction.clone()], 0);
+        let invalid_chain = mine_next_block(chain.clone(), node_id, &mut nonce, &immature_body);
+        assert!(invalid_chain.validate().is_err());
+
+        // The same output is spent twice.
+        chain = mine_until_mature(chain, node_id, &mut nonce);
+        let double_spend_body = body(
+            wallet.new_address().unwrap(),
+            vec![transaction.clone(), transaction],
+            0,
+        );
+        let invalid_chain = mine_next_block(chain, node_id, &mut nonce, &double_spend_body);
+        assert!(invalid_chain.validate().is_err());
+    }
+
+    #[test]
+    fn finds_the_diverging_blocks_of_a_fork() {
+        let (chain, node_id, mut nonce) = init_chain();
+        let fork = mine_5_blocks(chain, node_id, &mut nonce);
+        let mut wallet = Wallet::new();
+        let mut own_chain = fork.clone();
+        for _i in 0..3 {
+            let body = body(wallet.new_address().unwrap(), vec![], 0);
+            own_chain = mine_next_block(own_chain, node_id, &mut nonce, &body);
+        }
+        let mut other_chain = fork.clone();
+        for _i in 0..2 {
+            let body = body(wallet.new_address().unwrap(), vec![], 0);
+            other_chain = mine_next_block(other_chain, node_id + 1, &mut nonce, &body);
+        }
+
+        let (own_blocks, other_blocks) = own_chain.diverging_blocks(&other_chain);
+        let own_heights: Vec<u32> = own_blocks.iter().map(|block| block.height).collect();
+        let other_heights: Vec<u32> = other_blocks.iter().map(|block| block.height).collect();
+        assert_eq!(vec![8, 7, 6], own_heights);
+        assert_eq!(vec![7, 6], other_heights);
+        assert!(other_blocks[0].hash() == other_chain.head().hash());
+
+        let (own_blocks, fork_blocks) = own_chain.diverging_blocks(&fork);
+        assert_eq!(3, own_blocks.len());
+        assert!(fork_blocks.is_empty());
+    }
+
+    #[test]
+    fn can_validate_a_fork_from_the_undo_log() {
+        let (chain, node_id, mut nonce) = init_chain();
+        let fork = mine_5_blocks(chain, node_id, &mut nonce);
+        let fork_utxo_set = fork.validate().unwrap();
+        let mut wallet = Wallet::new();
+        let mut own_chain = fork.clone();
+        for _i in 0..2 {
+            let body = body(wallet.new_address().unwrap(), vec![], 0);
+            own_chain = mine_next_block(own_chain, node_id, &mut nonce, &body);
+        }
+        let (utxo_set, undo_log) = own_chain
+            .validate_from_fork(&fork, &fork_utxo_set, &UndoLog::new(MIN_PRUNE_DEPTH))
+            .unwrap();
+        assert_eq!(2, undo_log.len());
+
+        // The fork rolls the two blocks of the known chain back.
+        let mut other_chain = fork;
+        for _i in 0..3 {
+            let body = body(wallet.new_address().unwrap(), vec![], 0);
+            other_chain = mine_next_block(other_chain, node_id + 1, &mut nonce, &body);
+        }
+        let (other_utxo_set, other_undo_log) = other_chain.validate_from_fork(&own_chain, &utxo_set, &undo_log).unwrap();
+        assert_eq!(other_chain.validate().unwrap().len(), other_utxo_set.len());
+        assert_eq!(3, other_undo_log.len());
+
+        // The undo data of a single block cannot roll both back.
+        let mut shallow_undo_log = undo_log.clone();
+        shallow_undo_log.pop();
+        assert_eq!(
+            Err(ChainError::Pruned),
+            other_chain.validate_from_fork(&own_chain, &utxo_set, &shallow_undo_log).map(|_| ())
+        );
+        // The fork may be valid: the peer that sent it is not to blame.
+        assert!(!ChainError::Pruned.is_forged());
+    }
+
+    #[test]
+    fn cannot_forge_body() {
+        let (mut nonce, mut block, chain) = init_decapitated_chain();
+        nonce.increment().unwrap();
+        block.body = Arc::new(BlockBody {
+            body: Body::new(TxOut::new(COINBASE_AMOUNT + 1, burn_address()), vec![]),
+            hash: block.body.hash().clone(),
+            size: block.body.size,
+            pruned: false,
+        });
+        assert!(Chain::expand(&chain, block).is_err());
+    }
+
+    #[test]
+    fn cannot_forge_producer() {
+        let (authorities, key_pairs) = Authorities::generate(0..1, SignatureAlgorithm::Ed25519).unwrap();
+        let chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()).with_authorities(authorities));
+        let body = body(Wallet::new().new_address().unwrap(), vec![], 0);
+        let block = |node_id| {
+            Block::new(
+                node_id,
+                Nonce::new(),
+                0,
+                &chain.head.difficulty,
+                chain.head.hasher,
+                chain.head.hash.clone(),
+                1,
+                &body,
+            )
+        };
+
+        assert!(Chain::expand(&chain, block(0)).is_err());
+        assert!(Chain::expand(&chain, block(1).sign(&key_pairs[&0])).is_err());
+        let signed_chain = Chain::expand(&chain, block(0).sign(&key_pairs[&0])).unwrap();
+        assert!(signed_chain.validate().is_ok());
+        // A proof of work chain ignores the signatures.
+        let pow_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        assert!(Chain::expand(&pow_chain, block(1)).is_ok());
+    }
+
     fn init_decapitated_chain() -> (Nonce, Block, Arc<Chain>) {
         let (mut chain, node_id, mut nonce) = init_chain();
         chain = mine_5_blocks(chain, node_id, &mut nonce);
@@ -271,35 +1143,238 @@ mod tests {
         (nonce, block, chain)
     }
 
-    fn try_to_mine_next_block(chain: Arc<Chain>, node_id: u32, nonce: &mut Nonce) -> Arc<Chain> {
-        nonce.increment();
+    fn try_to_mine_next_block(
+        chain: Arc<Chain>,
+        node_id: u32,
+        nonce: &mut Nonce,
+        body: &Arc<BlockBody>,
+    ) -> Result<Arc<Chain>, Arc<Chain>> {
+        nonce.increment().unwrap();
         let block = Block::new(
             node_id,
             nonce.clone(),
+            0,
             &chain.head().difficulty,
+            chain.head().hasher,
             chain.head().hash().clone(),
             chain.height() + 1,
+            body,
         );
 
-        match Chain::expand(&chain, block) {
-            Ok(chain) => chain,
-            Err(_err) => chain,
+        Chain::expand(&chain, block).map_err(|_err| chain)
+    }
+
+    /// Mines the next block without checking the transactions, only the block hash.
+    fn mine_next_block(
+        mut chain: Arc<Chain>,
+        node_id: u32,
+        nonce: &mut Nonce,
+        body: &Arc<BlockBody>,
+    ) -> Arc<Chain> {
+        loop {
+            match try_to_mine_next_block(chain, node_id, nonce, body) {
+                Ok(mined_chain) => return mined_chain,
+                Err(same_chain) => chain = same_chain,
+            }
         }
     }
 
     fn mine_5_blocks(mut chain: Arc<Chain>, node_id: u32, nonce: &mut Nonce) -> Arc<Chain> {
-        loop {
-            chain = try_to_mine_next_block(chain, node_id, nonce);
+        let mut wallet = Wallet::new();
+        while chain.height() < 5 {
+            // A coinbase must not have the same hash as an unspent one.
+            let body = body(wallet.new_address().unwrap(), vec![], 0);
+            chain = mine_next_block(chain, node_id, nonce, &body);
+        }
+
+        chain
+    }
+
+    /// Mines empty blocks until the coinbase output of the head block can be spent.
+    fn mine_until_mature(mut chain: Arc<Chain>, node_id: u32, nonce: &mut Nonce) -> Arc<Chain> {
+        let mut wallet = Wallet::new();
+        let mature_height = chain.height() + COINBASE_MATURITY - 1;
+        while chain.height() < mature_height {
+            let body = body(wallet.new_address().unwrap(), vec![], 0);
+            chain = mine_next_block(chain, node_id, nonce, &body);
+        }
 
-            if chain.height() == 5 {
-                return chain;
+        chain
+    }
+
+    fn body(coinbase_address: Address, transactions: Vec<SignedTx>, fees: u32) -> Arc<BlockBody> {
+        let coinbase_tx_out = TxOut::new(COINBASE_AMOUNT + fees, coinbase_address);
+        Arc::new(BlockBody::new(Body::new(coinbase_tx_out, transactions)).unwrap())
+    }
+
+    fn burn_address() -> Address {
+        Address::from_hash(crypto::Hash::min())
+    }
+
+    /// Expands the chain with an empty block. All but one in 65,536 hashes meet the minimum
+    /// difficulty, and every miner gets a coinbase address of its own, so that the coinbase
+    /// transactions differ.
+    pub(super) fn expand(chain: &Arc<Chain>, node_id: u32) -> Arc<Chain> {
+        let coinbase_address = Address::from_hash(crypto::hash(&node_id.to_le_bytes()));
+        let body = body(coinbase_address, vec![], 0);
+        let block = Block::new(
+            node_id,
+            Nonce::from_bytes([node_id as u8; 8]),
+            node_id,
+            &chain.head().difficulty,
+            chain.head().hasher,
+            chain.head().hash().clone(),
+            chain.height() + 1,
+            &body,
+        );
+        Chain::expand(chain, block).unwrap()
+    }
+
+    /// The length of the fields of a header that can be mutated, in the order `mutate_header`
+    /// reads them: the hash function cannot, `cannot_forge_hasher` covers it.
+    const HEADER_LEN: usize = 32 + 4 + 8 + 4 + DIFFICULTY_LEN + 32 + 4 + 32;
+    /// The difficulty as its compact bits.
+    const DIFFICULTY_LEN: usize = 4;
+
+    fn take<'a>(fields: &mut &'a [u8], len: usize) -> &'a [u8] {
+        let (field, rest) = fields.split_at(len);
+        *fields = rest;
+        field
+    }
+
+    /// The block with one byte of its header XORed with the mask, none if the compact bits of
+    /// its difficulty do not encode one anymore.
+    fn mutate_header(block: &Block, index: usize, mask: u8) -> Option<Block> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+        bytes.extend_from_slice(block.hash.as_ref());
+        bytes.extend_from_slice(&block.node_id.to_le_bytes());
+        bytes.extend_from_slice(block.nonce.bytes());
+        bytes.extend_from_slice(&block.extra_nonce.to_le_bytes());
+        bytes.extend_from_slice(&block.difficulty.to_compact().to_le_bytes());
+        bytes.extend_from_slice(block.previous_block_hash.as_ref());
+        bytes.extend_from_slice(&block.height.to_le_bytes());
+        bytes.extend_from_slice(block.body.hash().as_ref());
+        bytes[index] ^= mask;
+
+        let mut fields = bytes.as_slice();
+        let hash = Hash::from_bytes(take(&mut fields, 32)).unwrap();
+        let node_id = u32::from_le_bytes(take(&mut fields, 4).try_into().unwrap());
+        let nonce = Nonce::from_bytes(take(&mut fields, 8).try_into().unwrap());
+        let extra_nonce = u32::from_le_bytes(take(&mut fields, 4).try_into().unwrap());
+        let compact_difficulty = u32::from_le_bytes(take(&mut fields, DIFFICULTY_LEN).try_into().unwrap());
+        let difficulty = Difficulty::from_compact(compact_difficulty).ok()?;
+        let previous_block_hash = Hash::from_bytes(take(&mut fields, 32)).unwrap();
+        let height = u32::from_le_bytes(take(&mut fields, 4).try_into().unwrap());
+        let merkle_root = crypto::Hash::from_bytes(take(&mut fields, 32)).unwrap();
+        Some(Block {
+            hash,
+            node_id,
+            nonce,
+            extra_nonce,
+            difficulty: Arc::new(difficulty),
+            hasher: block.hasher,
+            previous_block_hash,
+            height,
+            body: Arc::new(BlockBody {
+                body: block.body.body.clone(),
+                hash: merkle_root,
+                size: block.body.size,
+                pruned: false,
+            }),
+            signature: block.signature.clone(),
+        })
+    }
+
+    /// The block with the given transactions instead of its own, but still their Merkle root.
+    fn with_body(block: &Block, body: Body) -> Block {
+        Block {
+            hash: block.hash.clone(),
+            node_id: block.node_id,
+            nonce: block.nonce.clone(),
+            extra_nonce: block.extra_nonce,
+            difficulty: block.difficulty.clone(),
+            hasher: block.hasher,
+            previous_block_hash: block.previous_block_hash.clone(),
+            height: block.height,
+            body: Arc::new(BlockBody {
+                body,
+                hash: block.body.hash.clone(),
+                size: block.body.size,
+                pruned: false,
+            }),
+            signature: block.signature.clone(),
+        }
+    }
+
+    /// The last block the chains have in common.
+    fn fork_hash(chain: &Chain, diverging_blocks: &[Arc<Block>]) -> Hash {
+        match diverging_blocks.last() {
+            Some(block) => block.previous_block_hash.clone(),
+            None => chain.head().hash().clone(),
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn mutated_headers_are_rejected(index in 0..HEADER_LEN, mask in 1..=u8::MAX) {
+            let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+            let chain = expand(&genesis_chain, 0);
+            let block = chain.head();
+
+            // Either the bytes do not make a difficulty anymore, or not the one of the chain.
+            if let Some(mutated_block) = mutate_header(block, index, mask) {
+                prop_assert!(Chain::expand(&genesis_chain, mutated_block).is_err());
+                let mutated_block = mutate_header(block, index, mask).unwrap();
+                prop_assert!(Chain::unvalidated_expand(&genesis_chain, mutated_block).validate().is_err());
             }
         }
+
+        #[test]
+        fn mutated_bodies_are_rejected(index in any::<Index>(), mask in 1..=u8::MAX) {
+            let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+            let chain = expand(&genesis_chain, 0);
+            let block = chain.head();
+            let mut serialized = bincode::serialize(block.body.body()).unwrap();
+            let index = index.index(serialized.len());
+            serialized[index] ^= mask;
+
+            // Either the bytes do not make a body anymore, or not the one of the Merkle root.
+            if let Ok(body) = bincode::deserialize::<Body>(&serialized) {
+                prop_assert!(Chain::expand(&genesis_chain, with_body(block, body)).is_err());
+            }
+        }
+
+        /// Every block expands a random block of the tree, be it a head or not.
+        #[test]
+        fn trees_of_valid_blocks_make_valid_chains(
+            parents in prop::collection::vec(any::<Index>(), 1..32),
+            chain_indexes in (any::<Index>(), any::<Index>()),
+        ) {
+            let mut chains = vec![Arc::new(Chain::init_new(Difficulty::min_difficulty()))];
+            for (node_id, parent) in parents.iter().enumerate() {
+                let parent = chains[parent.index(chains.len())].clone();
+                chains.push(expand(&parent, node_id as u32));
+            }
+
+            for chain in &chains {
+                prop_assert!(chain.validate().is_ok());
+                let blocks: Vec<u32> = chain.blocks().map(|block| block.height).collect();
+                prop_assert_eq!((0..=chain.height()).rev().collect::<Vec<u32>>(), blocks);
+                prop_assert_eq!(U256::from(chain.height() as u64 + 1), chain.work());
+            }
+
+            let chain = &chains[chain_indexes.0.index(chains.len())];
+            let other_chain = &chains[chain_indexes.1.index(chains.len())];
+            let (own_blocks, other_blocks) = chain.diverging_blocks(other_chain);
+            prop_assert_eq!(fork_hash(chain, &own_blocks), fork_hash(other_chain, &other_blocks));
+            let fork_height = chain.height() - own_blocks.len() as u32;
+            prop_assert_eq!(fork_height, other_chain.height() - other_blocks.len() as u32);
+        }
     }
 
     fn init_chain() -> (Arc<Chain>, u32, Nonce) {
         let mut difficulty = Difficulty::min_difficulty();
-        difficulty.increase();
+        difficulty.increase().unwrap();
         let chain = Chain::init_new(difficulty);
         let chain = Arc::new(chain);
         let node_id = 1;
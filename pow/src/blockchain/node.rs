@@ -1,17 +1,109 @@
-use blockchain::{mining_stream, Chain, MiningStateUpdater};
-use futures::sync::mpsc::UnboundedSender;
-use futures::{self, future, Future, Stream};
-use netsim::flatten_select;
-use netsim::network::{MPSCConnection, Node};
+use crate::blockchain::compact::transactions_size;
+use crate::blockchain::latency::smoothed_round_trip_time;
+use crate::blockchain::miner::interval_stream;
+use crate::blockchain::{
+    mining_stream, BanPolicy, Block, BlockBody, Chain, CheckpointVote, CompactBlock, ConnectionLimits,
+    DoubleSpendCounter, FinalityGadget, FinalityVoters, HashRegistry, HeightIndex, LatencyStats, MetricsBus,
+    MiningStateUpdater, Misbehavior, NodeChains, NodeLogger, NodeMetric, PartialBlock, PeerConnection, PeerScores,
+    PruningHorizon, SeenSet, SeenTransactions, StrongestChain, SybilAdversary, ThroughputStats, TokenBucket, UndoLog,
+    ValidatorRegistry, DEFAULT_SEEN_TRANSACTIONS,
+};
+use crate::blockchain::{ByzantineNode, LightNode, Message, ProofRequest, ProofResponse, TransactionProof};
+use crate::error::{ChainError, Error};
+use btclike::block_template::BlockTemplateBuilder;
+use btclike::blockchain::COINBASE_AMOUNT;
+use btclike::bloom::BloomFilter;
+use btclike::crypto::{self, Hash, KeyPair};
+use btclike::mempool::{ConflictPolicy, Mempool};
+use btclike::policy::RelayPolicy;
+use btclike::transaction::{Address, SignedTx};
+use btclike::utxo::UtxoSet;
+use btclike::wallet::Wallet;
+use futures::channel::mpsc::Receiver;
+use futures::stream::{self, AbortHandle, FuturesUnordered, SelectAll};
+use futures::{future, Future, Stream, StreamExt};
+use netsim::clock;
+use netsim::network::rpc::Request;
+use netsim::network::{ConnectionEvent, ConnectionSender, Node};
+use rand::{self, Rng};
+use ring::digest::SHA256_OUTPUT_LEN;
+use std::collections::HashMap;
+use std::iter;
+use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tokio::task::{self, JoinHandle};
+use tokio::time;
+
+/// The maximum fees of the random payments sent by the nodes.
+const MAX_PAYMENT_FEES: u32 = 10;
+/// The maximum number of transactions in the blocks mined by the nodes.
+const MAX_BLOCK_TRANSACTIONS: usize = 100;
+/// The number of chains whose head a node remembers, so that it does not validate them again:
+/// many more than the chains its peers send it while one is validated.
+const SEEN_CHAINS: usize = 1_000;
+/// The default delay between two pings of the peers.
+const PING_DELAY: Duration = Duration::from_secs(1);
+
+/// The unspent outputs and the undo data of a chain a peer sent, or why it is invalid.
+type ChainValidation = Result<(UtxoSet, Option<UndoLog>), ChainError>;
 
 /// Contains a sink to the peer and information about the peer state.
 #[derive(Clone)]
 pub struct Peer {
-    sender: UnboundedSender<Arc<Chain>>,
+    sender: ConnectionSender<Message>,
+    /// The strongest chain the peer is known to have: the last one sent to it, or the last one
+    /// it sent, whichever is stronger.
     last_known_chain: Arc<Chain>,
     is_closed: bool,
+    /// The smoothed round trip time of the connection, none until the first pong.
+    round_trip_time: Option<Duration>,
+    /// The last chain the peer sent, none until it does.
+    last_received_chain: Option<Arc<Chain>>,
+    /// Whether the peer asked for compact blocks.
+    compact_relay: bool,
+    /// The filter of a light peer, which only gets the transactions matching it.
+    bloom_filter: Option<Arc<BloomFilter>>,
+    /// Whether the peer initiated the connection.
+    inbound: bool,
+    /// Whether the connection is one of the first ones this node initiated, never evicted.
+    anchor: bool,
+    opened_at: Instant,
+    /// Stops the stream of the messages of the peer.
+    reception: AbortHandle,
+}
+
+impl Peer {
+    fn connection(&self) -> PeerConnection {
+        PeerConnection {
+            peer_id: self.sender.peer_id(),
+            inbound: self.inbound,
+            anchor: self.anchor,
+            opened_at: self.opened_at,
+            round_trip_time: self.round_trip_time,
+        }
+    }
+
+    /// Closes the connection. The messages the peer sent that were not handled yet are dropped,
+    /// like the ones it could still send.
+    fn disconnect(self) -> Result<(), netsim::error::Error> {
+        self.reception.abort();
+        self.sender.disconnect()
+    }
+
+    /// Remembers that the peer has the chain, unless it is known to have a stronger one.
+    fn learn(&mut self, chain: &Arc<Chain>) {
+        if chain.stronger_than(&self.last_known_chain) {
+            self.last_known_chain = chain.clone();
+        }
+    }
+
+    /// Whether the chain is news to the peer: its head is not the one of the chain the peer is
+    /// known to have, and it is not weaker than that chain, which the peer would drop as stale.
+    /// A chain of the same height with another head is news.
+    fn misses(&self, chain: &Chain) -> bool {
+        chain.head().hash() != self.last_known_chain.head().hash() && !self.last_known_chain.stronger_than(chain)
+    }
 }
 
 /// Represents the events that can happen in a Proof of Work
@@ -20,44 +112,454 @@ pub struct Peer {
 /// concurrency issues, locking and lifetime management.
 pub enum NodeEvent {
     Peer(Peer),
+    /// The peer with the given address closed its connection.
+    PeerClosed(u32),
     MinedChain(Arc<Chain>),
-    ChainRemoteUpdate(Arc<Chain>),
+    /// The peer with the given address sent a chain.
+    ChainRemoteUpdate(u32, Arc<Chain>),
+    /// A worker validated the chain the peer with the given address sent.
+    ChainValidated(u32, Arc<Chain>, ChainValidation),
+    /// A light node asks for a proof, which is sent back through the given sender.
+    ProofRequest(Request<ProofRequest>, ConnectionSender<Message>),
+    PaymentAttempt,
+    PingAttempt,
+    /// A peer pings this node, which answers through the given sender.
+    Ping(Instant, ConnectionSender<Message>),
+    /// The peer with the given address answered a ping sent at the given instant.
+    Pong(u32, Instant),
+    /// The peer with the given address asked for compact blocks.
+    CompactRelayRequested(u32),
+    /// The peer with the given address sent a compact block.
+    CompactBlock(u32, Arc<CompactBlock>),
+    /// A peer asks for transactions of a block, which are sent back through the given sender.
+    BlockTransactionsRequest(Hash, Vec<u32>, ConnectionSender<Message>),
+    /// The peer with the given address sent the missing transactions of a compact block.
+    BlockTransactions(u32, Hash, Vec<SignedTx>),
+    /// The peer with the given address asks for the chain of this node.
+    ChainRequest(u32),
+    /// The peer with the given address answered a chain request.
+    RequestedChain(u32, Arc<Chain>),
+    /// The peer with the given address announced transactions.
+    Inventory(u32, Vec<crypto::Hash>),
+    /// A peer asks for pending transactions, which are sent back through the given sender.
+    TransactionsRequest(Vec<crypto::Hash>, ConnectionSender<Message>),
+    /// The peer with the given address sent transactions.
+    Transactions(u32, Vec<SignedTx>),
+    /// The light peer with the given address sent the filter of the transactions it wants.
+    FilterLoaded(u32, Arc<BloomFilter>),
+    /// The peer with the given address relayed a vote for a checkpoint.
+    Vote(u32, Arc<CheckpointVote>),
+    /// A hash rate shock changed the delay between two mining attempts, none to stop mining.
+    HashrateShock(Option<Duration>),
+}
+
+impl NodeEvent {
+    /// The address of the peer whose message caused the event, if any.
+    fn sender_id(&self) -> Option<u32> {
+        match *self {
+            NodeEvent::ChainRemoteUpdate(peer_id, _)
+            | NodeEvent::Pong(peer_id, _)
+            | NodeEvent::CompactRelayRequested(peer_id)
+            | NodeEvent::CompactBlock(peer_id, _)
+            | NodeEvent::BlockTransactions(peer_id, ..)
+            | NodeEvent::ChainRequest(peer_id)
+            | NodeEvent::RequestedChain(peer_id, _)
+            | NodeEvent::Inventory(peer_id, _)
+            | NodeEvent::Transactions(peer_id, _)
+            | NodeEvent::FilterLoaded(peer_id, _)
+            | NodeEvent::Vote(peer_id, _) => Some(peer_id),
+            NodeEvent::ProofRequest(_, ref sender)
+            | NodeEvent::Ping(_, ref sender)
+            | NodeEvent::BlockTransactionsRequest(_, _, ref sender)
+            | NodeEvent::TransactionsRequest(_, ref sender) => Some(sender.peer_id()),
+            _ => None,
+        }
+    }
 }
 
 pub struct PowNode {
     node_id: u32,
     mining_attempt_delay: Duration,
+    /// When the delay between two mining attempts changes, from the start of the node, and
+    /// the new delays, none to stop mining.
+    hashrate_shocks: Vec<(Duration, Option<Duration>)>,
+    payment_attempt_delay: Duration,
+    ping_delay: Duration,
     chain: Arc<Chain>,
+    /// The heights of the blocks of `chain`.
+    heights: HeightIndex,
+    /// The unspent transaction outputs of `chain`.
+    utxo_set: UtxoSet,
+    /// Holds the keys of the coinbase outputs and of the payments received by this node.
+    wallet: Wallet,
+    coinbase_address: Address,
+    /// The payments of this node that were not confirmed yet.
+    mempool: Mempool,
+    hash_registry: Option<HashRegistry>,
+    /// The key this node signs its blocks with, if it is an authority of a proof of authority
+    /// chain. The blocks of the others are rejected.
+    producer_key: Option<Arc<KeyPair>>,
+    /// The validators of a proof of stake chain, slashed as this node observes them sign
+    /// conflicting blocks. None for the other chains.
+    validators: Option<ValidatorRegistry>,
+    /// The votes on the checkpoints this node counts, if it follows their finality.
+    finality: Option<FinalityGadget>,
+    /// The key this node signs its votes with, if it is a voter.
+    voter_key: Option<Arc<KeyPair>>,
+    /// The last checkpoint height this node voted for.
+    voted_height: u32,
+    double_spend_counter: Option<DoubleSpendCounter>,
+    latency_stats: Option<LatencyStats>,
+    metrics_bus: Option<MetricsBus>,
+    logger: NodeLogger,
+    strongest_chain: Option<StrongestChain>,
+    node_chains: Option<NodeChains>,
+    throughput_stats: Option<ThroughputStats>,
+    /// The adversary this node is one of the sybils of, none for an honest node.
+    sybil_adversary: Option<SybilAdversary>,
+    /// The misbehavior scores of the peers, and the ones banned for it.
+    peer_scores: PeerScores,
+    /// The messages per second handled from each peer, none for no limit.
+    inbound_rate: Option<u32>,
+    /// The rate limits of the peers, by address.
+    rate_limiters: HashMap<u32, TokenBucket>,
+    /// How many peers this node keeps in each direction, and which ones it evicts beyond.
+    connection_limits: ConnectionLimits,
+    /// The connections this node initiated so far, the first ones being its anchors.
+    outbound_connections: usize,
+    /// Whether this node asks its peers for compact blocks.
+    compact_relay: bool,
+    /// The compact blocks waiting for the transactions missing from the mempool, by hash.
+    partial_blocks: HashMap<Hash, PartialBlock>,
+    /// Whether this node announces its payments and the ones it receives to its peers.
+    transaction_gossip: bool,
+    /// The transactions announced to this node or by it, which it does not ask for again.
+    seen_transactions: SeenTransactions,
+    /// The addresses paid half of the time, instead of the miner of the head block.
+    payees: Arc<Vec<Address>>,
+    /// The number of blocks whose bodies this node keeps, none to keep them all.
+    prune_depth: Option<u32>,
+    /// The undo data of the last blocks of `chain`, kept to follow the forks by a node that
+    /// cannot replay the chain from the genesis block. None for the others.
+    undo_log: Option<UndoLog>,
+    /// The heights every node pruned the shared blocks below.
+    pruning_horizon: PruningHorizon,
+    /// The validations of the chains of the peers, run by the blocking workers so that a long
+    /// one does not hold the other events back.
+    validations: FuturesUnordered<JoinHandle<(u32, Arc<Chain>, ChainValidation)>>,
+    /// The hashes of the head blocks of the last chains received, validated or being validated,
+    /// which are neither validated nor handled twice when other peers send them too.
+    seen_chains: SeenSet<Hash>,
 }
 
 impl PowNode {
-    pub fn new(node_id: u32, genesis_chain: Arc<Chain>, mining_attempt_delay: Duration) -> PowNode {
+    pub fn new(
+        node_id: u32,
+        genesis_chain: Arc<Chain>,
+        mining_attempt_delay: Duration,
+        payment_attempt_delay: Duration,
+    ) -> PowNode {
+        let utxo_set = genesis_chain.validate().expect("Invalid genesis chain");
+        PowNode::init(
+            node_id,
+            genesis_chain,
+            utxo_set,
+            None,
+            mining_attempt_delay,
+            payment_attempt_delay,
+        )
+    }
+
+    /// Same as `new`, for a chain loaded from a UTXO snapshot along with its unspent outputs,
+    /// which are trusted: only the blocks extending the chain are validated. The forks are
+    /// followed by rolling back the unspent outputs, down to the head of the snapshot.
+    pub fn from_utxo_set(
+        node_id: u32,
+        genesis_chain: Arc<Chain>,
+        utxo_set: UtxoSet,
+        mining_attempt_delay: Duration,
+        payment_attempt_delay: Duration,
+    ) -> PowNode {
+        PowNode::init(
+            node_id,
+            genesis_chain,
+            utxo_set,
+            // The blocks the node validates are as many as it can roll back.
+            Some(UndoLog::new(u32::MAX)),
+            mining_attempt_delay,
+            payment_attempt_delay,
+        )
+    }
+
+    fn init(
+        node_id: u32,
+        genesis_chain: Arc<Chain>,
+        utxo_set: UtxoSet,
+        undo_log: Option<UndoLog>,
+        mining_attempt_delay: Duration,
+        payment_attempt_delay: Duration,
+    ) -> PowNode {
+        let mut wallet = Wallet::new().with_signature_algorithm(genesis_chain.signature_algorithm());
+        let coinbase_address = wallet.new_address().expect("Could not create an address");
+        let heights = HeightIndex::new(&genesis_chain);
+        for height in 0..=genesis_chain.height() {
+            let block = heights.block_at(height).expect("Missing block");
+            wallet
+                .connect_block(block.body().body(), height)
+                .expect("Could not connect a block to the wallet");
+        }
+
+        let validators = genesis_chain.validators().map(|validators| (**validators).clone());
+        let mempool = Mempool::new().with_params(genesis_chain.params().clone());
         PowNode {
             node_id,
             chain: genesis_chain,
+            heights,
             mining_attempt_delay,
+            hashrate_shocks: vec![],
+            payment_attempt_delay,
+            ping_delay: PING_DELAY,
+            utxo_set,
+            wallet,
+            coinbase_address,
+            mempool,
+            hash_registry: None,
+            producer_key: None,
+            validators,
+            finality: None,
+            voter_key: None,
+            voted_height: 0,
+            double_spend_counter: None,
+            latency_stats: None,
+            metrics_bus: None,
+            logger: NodeLogger::new(node_id, clock::now()),
+            strongest_chain: None,
+            node_chains: None,
+            throughput_stats: None,
+            sybil_adversary: None,
+            peer_scores: PeerScores::new(BanPolicy::new()),
+            inbound_rate: None,
+            rate_limiters: HashMap::new(),
+            connection_limits: ConnectionLimits::new(),
+            outbound_connections: 0,
+            compact_relay: false,
+            partial_blocks: HashMap::new(),
+            transaction_gossip: false,
+            seen_transactions: SeenTransactions::new(DEFAULT_SEEN_TRANSACTIONS),
+            payees: Arc::new(vec![]),
+            prune_depth: None,
+            undo_log,
+            pruning_horizon: PruningHorizon::new(iter::empty()),
+            validations: FuturesUnordered::new(),
+            seen_chains: SeenSet::new(SEEN_CHAINS),
+        }
+    }
+
+    /// Records every block mined by this node in the given registry.
+    pub fn with_hash_registry(mut self, hash_registry: HashRegistry) -> PowNode {
+        self.hash_registry = Some(hash_registry);
+        self
+    }
+
+    /// Makes this node an authority of a proof of authority chain, or a validator of a proof of stake
+    /// chain, signing its blocks with the key.
+    pub fn with_producer_key(mut self, producer_key: Arc<KeyPair>) -> PowNode {
+        self.producer_key = Some(producer_key);
+        self
+    }
+
+    /// Makes this node count the votes on the checkpoints, and report the ones it finalizes.
+    /// It votes as well if it has a key.
+    pub fn with_finality(mut self, voters: Arc<FinalityVoters>, voter_key: Option<Arc<KeyPair>>) -> PowNode {
+        self.finality = Some(FinalityGadget::new(voters));
+        self.voter_key = voter_key;
+        self
+    }
+
+    /// Changes the delay between two mining attempts of this node at the given time from its
+    /// start, none to stop mining.
+    pub fn with_hashrate_shock(mut self, at: Duration, mining_attempt_delay: Option<Duration>) -> PowNode {
+        self.hashrate_shocks.push((at, mining_attempt_delay));
+        self
+    }
+
+    /// Decides how the mempool handles payments conflicting with pending ones.
+    pub fn with_conflict_policy(mut self, conflict_policy: ConflictPolicy) -> PowNode {
+        self.mempool = Mempool::new()
+            .with_conflict_policy(conflict_policy)
+            .with_relay_policy(*self.mempool.relay_policy())
+            .with_params(self.chain.params().clone());
+        self
+    }
+
+    /// Decides which valid payments the mempool accepts and relays. The blocks are accepted
+    /// whatever their transactions, as long as they follow the consensus rules.
+    pub fn with_relay_policy(mut self, relay_policy: RelayPolicy) -> PowNode {
+        self.mempool = Mempool::new()
+            .with_conflict_policy(self.mempool.conflict_policy())
+            .with_relay_policy(relay_policy)
+            .with_params(self.chain.params().clone());
+        self
+    }
+
+    /// Reports the double spend counters of the mempool to the given counter.
+    pub fn with_double_spend_counter(mut self, double_spend_counter: DoubleSpendCounter) -> PowNode {
+        self.double_spend_counter = Some(double_spend_counter);
+        self
+    }
+
+    /// Sets the delay between two pings of the peers, which measure their round trip times.
+    pub fn with_ping_delay(mut self, ping_delay: Duration) -> PowNode {
+        self.ping_delay = ping_delay;
+        self
+    }
+
+    /// Reports the round trip times measured to the peers to the given stats.
+    pub fn with_latency_stats(mut self, latency_stats: LatencyStats) -> PowNode {
+        self.latency_stats = Some(latency_stats);
+        self
+    }
+
+    /// Publishes the chain heights, forks and messages of this node on the given bus.
+    pub fn with_metrics_bus(mut self, metrics_bus: MetricsBus) -> PowNode {
+        self.metrics_bus = Some(metrics_bus);
+        self
+    }
+
+    /// Tags the log lines of this node with the given logger, from the start of the simulation.
+    pub fn with_logger(mut self, logger: NodeLogger) -> PowNode {
+        self.logger = logger;
+        self
+    }
+
+    /// Reports every chain this node adopts to the given tracker.
+    pub fn with_strongest_chain(mut self, strongest_chain: StrongestChain) -> PowNode {
+        self.strongest_chain = Some(strongest_chain);
+        self
+    }
+
+    /// Reports every chain this node adopts to the given tracker of the chains of the nodes.
+    pub fn with_node_chains(mut self, node_chains: NodeChains) -> PowNode {
+        self.node_chains = Some(node_chains);
+        self
+    }
+
+    /// Makes this node a load generator: its payments, their confirmations and its mempool
+    /// backlog are reported to the given stats. The rate of the payments is the one of the
+    /// payment attempts.
+    pub fn with_throughput_stats(mut self, throughput_stats: ThroughputStats) -> PowNode {
+        self.throughput_stats = Some(throughput_stats);
+        self
+    }
+
+    /// Makes this node one of the sybils of the given adversary: the blocks it mines are
+    /// dropped, and it relays the chains to the honest nodes late or never.
+    pub fn with_sybil_adversary(mut self, sybil_adversary: SybilAdversary) -> PowNode {
+        self.sybil_adversary = Some(sybil_adversary);
+        self
+    }
+
+    /// Decides when and for how long this node bans its misbehaving peers.
+    pub fn with_ban_policy(mut self, ban_policy: BanPolicy) -> PowNode {
+        self.peer_scores = PeerScores::new(ban_policy);
+        self
+    }
+
+    /// Drops the messages of every peer beyond the given number per second, after a burst of
+    /// as many, so that a flooding peer cannot starve the others.
+    pub fn with_inbound_rate(mut self, inbound_rate: u32) -> PowNode {
+        self.inbound_rate = Some(inbound_rate);
+        self
+    }
+
+    /// Evicts a peer whenever a new connection exceeds the limit of its direction.
+    pub fn with_connection_limits(mut self, connection_limits: ConnectionLimits) -> PowNode {
+        self.connection_limits = connection_limits;
+        self
+    }
+
+    /// Asks the peers to announce their new blocks as compact blocks, rebuilt from the mempool.
+    /// The blocks are announced the same way to the peers asking for it, whatever this setting.
+    pub fn with_compact_relay(mut self) -> PowNode {
+        self.compact_relay = true;
+        self
+    }
+
+    /// Announces the payments of this node and the ones it receives to its peers, which ask for
+    /// the ones they did not see, so that every miner can confirm them. The transactions
+    /// announced by the peers are asked for whatever this setting.
+    pub fn with_transaction_gossip(mut self) -> PowNode {
+        self.transaction_gossip = true;
+        self
+    }
+
+    /// Pays the given addresses half of the time, the ones of light nodes for instance, and the
+    /// miner of the head block the other half.
+    pub fn with_payees(mut self, payees: Arc<Vec<Address>>) -> PowNode {
+        self.payees = payees;
+        self
+    }
+
+    /// Only keeps the bodies of the last blocks of the chain, but the coinbase transactions: the
+    /// forks deeper than that cannot be followed anymore, and the proofs of the older blocks
+    /// are not served. The bodies are dropped from the blocks shared by the nodes once every
+    /// node of the given horizon pruned them.
+    pub fn with_pruning(mut self, prune_depth: u32, pruning_horizon: PruningHorizon) -> PowNode {
+        self.prune_depth = Some(prune_depth);
+        self.undo_log = Some(UndoLog::new(prune_depth));
+        self.pruning_horizon = pruning_horizon;
+        self
+    }
+
+    fn publish(&self, metric: NodeMetric) {
+        if let Some(ref metrics_bus) = self.metrics_bus {
+            metrics_bus.publish(metric);
         }
     }
 
     /// Propagates the new chain to peers and to the mining stream.
     /// The propagation only happens if the update is a stronger chain
     /// than the known one of either the peer or the mining stream.
+    /// The peers with the lowest round trip times get the chain first.
     fn propagate(
         &mut self,
         chain: Arc<Chain>,
+        utxo_set: UtxoSet,
+        undo_log: Option<UndoLog>,
         peers: &mut Vec<Peer>,
         mining_state_updater: &MiningStateUpdater,
-    ) {
+    ) -> Result<(), Error> {
         let chain_height = chain.height();
+        // Built once for all the peers asking for compact blocks.
+        let compact_block = if peers.iter().any(|peer| peer.compact_relay) {
+            let compact_block = CompactBlock::new(chain.head())
+                .map_err(|err| Error::Internal(format!("Could not build a compact block: {}", err)))?;
+            Some(Arc::new(compact_block))
+        } else {
+            None
+        };
 
+        // The peers not measured yet come last.
+        peers.sort_by_key(|peer| peer.round_trip_time.unwrap_or(Duration::MAX));
         peers.iter_mut().for_each(|peer| {
-            if chain.stronger_than(&peer.last_known_chain) {
-                match &peer.sender.unbounded_send(chain.clone()) {
+            // The peer that sent the chain, or a stronger one, does not get it back.
+            if peer.misses(&chain) {
+                let (message, bytes, full_block_bytes) = relay_message(peer, &chain, compact_block.as_ref());
+                match self.relay(&mut peer.sender, message) {
                     Ok(()) => {
-                        peer.last_known_chain = chain.clone();
+                        peer.learn(&chain);
+                        self.publish(NodeMetric::BlockRelayed {
+                            node_id: self.node_id,
+                            bytes,
+                            full_block_bytes,
+                        });
+                    }
+                    // The peer still does not know the chain, it will get the next one.
+                    Err(netsim::error::Error::Congested) => {
+                        self.logger.debug("relay", format_args!("Congested peer, chain dropped."));
                     }
                     Err(err) => {
-                        info!("Lost connection: {}", err);
+                        self.logger.info("peer", format_args!("Lost connection: {}", err));
                         peer.is_closed = true;
                     }
                 }
@@ -67,98 +569,1560 @@ impl PowNode {
         peers.retain(|peer| !peer.is_closed);
 
         if chain.stronger_than(&self.chain) {
-            mining_state_updater.mine_new_chain(chain.clone());
+            self.update_wallet(&chain)?;
             self.chain = chain;
-            debug!(
-                "[#{:05}]  New chain with height: {}",
-                self.node_id, chain_height
-            );
+            self.utxo_set = utxo_set;
+            if undo_log.is_some() {
+                self.undo_log = undo_log;
+            }
+            self.prune();
+            self.vote(peers);
+
+            // Confirmed payments, as well as the ones that conflict with the new chain, are
+            // not valid anymore.
+            self.mempool.update(&self.utxo_set, self.chain.height() + 1);
+            if let Some(ref throughput_stats) = self.throughput_stats {
+                throughput_stats.report_backlog(self.node_id, self.mempool.len());
+            }
+            self.coinbase_address = self.wallet
+                .new_address()
+                .map_err(|err| Error::Internal(format!("Could not create an address: {}", err)))?;
+
+            mining_state_updater.mine_new_chain(self.chain.clone(), self.block_body()?)?;
+            let chain = &self.chain;
+            self.partial_blocks
+                .retain(|_hash, partial_block| parent_chain(chain, partial_block.compact_block()).is_some());
+            if let Some(ref strongest_chain) = self.strongest_chain {
+                strongest_chain.report(&self.chain);
+            }
+            if let Some(ref node_chains) = self.node_chains {
+                node_chains.report(self.node_id, &self.chain);
+            }
+            self.publish(NodeMetric::ChainHeight {
+                node_id: self.node_id,
+                height: chain_height,
+            });
+            self.logger.debug("chain", format_args!("New chain with height: {}", chain_height));
         } else if chain_height == self.chain.height() {
             let new_hash = chain.head.hash();
             let current_hash = self.chain.head.hash();
 
             if new_hash != current_hash {
-                info!(
-                    "[#{:05}] Natural fork detected: {:?} <> {:?}",
-                    self.node_id, new_hash, current_hash
-                );
+                self.logger.info("fork", format_args!("Natural fork detected: {} <> {}", new_hash, current_hash));
+                self.publish(NodeMetric::Fork { node_id: self.node_id });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends the message relaying a chain to a peer, unless this node is a sybil delaying or
+    /// withholding it. A delayed chain is sent even if a stronger one was sent in the
+    /// meantime, the peer then ignores it.
+    fn relay(&self, sender: &mut ConnectionSender<Message>, message: Message) -> Result<(), netsim::error::Error> {
+        let relay_delay = match self.sybil_adversary {
+            Some(ref sybil_adversary) => sybil_adversary.relay_delay(sender.peer_id()),
+            None => Some(Duration::ZERO),
+        };
+
+        match relay_delay {
+            Some(relay_delay) if relay_delay.is_zero() => sender.try_send(message),
+            Some(relay_delay) => {
+                let mut sender = sender.clone();
+                tokio::spawn(async move {
+                    time::sleep(relay_delay).await;
+                    // Like any other chain, it is lost if the peer is gone or congested by then.
+                    let _ = sender.try_send(message);
+                });
+                Ok(())
+            }
+            // The peer is still considered aware of the chain, so that it is not tried again.
+            None => Ok(()),
+        }
+    }
+
+    /// Indexes the new chain, then disconnects from the wallet the blocks of the current chain
+    /// that the new one does not contain and connects the blocks of the new chain, oldest first.
+    fn update_wallet(&mut self, new_chain: &Chain) -> Result<(), Error> {
+        let (disconnected, connected) = self.heights.update(new_chain);
+
+        for height in disconnected.rev() {
+            self.wallet.disconnect_block(height);
+        }
+
+        for block in connected.iter().rev() {
+            // The chain is valid, the wallet must accept its blocks.
+            self.wallet
+                .connect_block(block.body().body(), block.height)
+                .map_err(|err| Error::Internal(format!("Could not connect a block to the wallet: {}", err)))?;
+
+            if let Some(ref throughput_stats) = self.throughput_stats {
+                for transaction in block.body().body().transactions() {
+                    let hash = transaction
+                        .hash()
+                        .map_err(|err| Error::Internal(format!("Could not hash a transaction: {}", err)))?;
+                    throughput_stats.confirm(&hash);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates the chain then propagates it.
+    fn validate_and_propagate(
+        &mut self,
+        chain: Arc<Chain>,
+        peers: &mut Vec<Peer>,
+        mining_state_updater: &MiningStateUpdater,
+    ) -> Result<(), Error> {
+        // A chain this one contains was validated already, and is weaker.
+        if self.heights.contains(chain.head().hash()) {
+            self.logger.debug("chain", format_args!("Known chain, height: {}", chain.height()));
+            return Ok(());
+        }
+
+        let (utxo_set, undo_log) = match self.undo_log {
+            Some(ref undo_log) => {
+                let (utxo_set, undo_log) = chain.validate_from_fork(&self.chain, &self.utxo_set, undo_log)?;
+                (utxo_set, Some(undo_log))
+            }
+            None => (chain.validate_from(&self.chain, &self.utxo_set)?, None),
+        };
+        self.propagate(chain, utxo_set, undo_log, peers, mining_state_updater)
+    }
+
+    /// Prunes the blocks of the chain deeper than the prune depth, then the shared blocks
+    /// every node pruned.
+    fn prune(&mut self) {
+        let prune_depth = match self.prune_depth {
+            Some(prune_depth) => prune_depth,
+            None => return,
+        };
+
+        let below_height = self.chain.height().saturating_sub(prune_depth) + 1;
+        let blocks = self.heights.prune(below_height);
+        if blocks == 0 {
+            return;
+        }
+
+        let shared_height = self.pruning_horizon.report(self.node_id, self.heights.pruned_height());
+        let (dropped_bodies, dropped_bytes) = self.chain.index.prune(shared_height);
+        self.logger.debug(
+            "pruning",
+            format_args!("Pruned {} blocks below height {}", blocks, self.heights.pruned_height()),
+        );
+        self.publish(NodeMetric::BlocksPruned {
+            node_id: self.node_id,
+            blocks,
+            dropped_bodies,
+            dropped_bytes,
+        });
+    }
+
+    /// Whether the rate limit of the peer whose message caused the event lets it through. The
+    /// events that are not caused by a message always are.
+    fn admit(&mut self, node_event: &NodeEvent) -> bool {
+        let (inbound_rate, peer_id) = match (self.inbound_rate, node_event.sender_id()) {
+            (Some(inbound_rate), Some(peer_id)) => (inbound_rate, peer_id),
+            _ => return true,
+        };
+
+        let now = clock::now();
+        let admitted = self
+            .rate_limiters
+            .entry(peer_id)
+            .or_insert_with(|| TokenBucket::new(inbound_rate, now))
+            .try_take(now);
+        if !admitted {
+            self.logger.debug("rate_limit", format_args!("Throttled peer #{:05}, message dropped.", peer_id));
+            self.publish(NodeMetric::MessageThrottled {
+                node_id: self.node_id,
+                peer_id,
+            });
+        }
+        admitted
+    }
+
+    /// Remembers the chain as the last one the peer sent, unless it is the same as the last
+    /// one or a weaker one, which is a misbehavior.
+    fn receive_from(&self, peer_id: u32, chain: &Arc<Chain>, peers: &mut [Peer]) -> Option<Misbehavior> {
+        let peer = peers.iter_mut().find(|peer| peer.sender.peer_id() == peer_id)?;
+        match peer.last_received_chain {
+            Some(ref last_chain) if last_chain.head().hash() == chain.head().hash() => Some(Misbehavior::Spam),
+            Some(ref last_chain) if !chain.stronger_than(last_chain) => Some(Misbehavior::StaleData),
+            _ => {
+                peer.last_received_chain = Some(chain.clone());
+                None
+            }
+        }
+    }
+
+    /// Whether the chain grows from another genesis block than the chain of this node, which
+    /// cannot adopt it. The peer is not penalized, it may be on another chain of the network.
+    fn is_foreign(&self, peer_id: u32, chain: &Chain) -> bool {
+        let foreign = chain.genesis_config() != self.chain.genesis_config();
+        if foreign {
+            self.logger.debug("chain", format_args!("Foreign chain, height: {}", chain.height()));
+            self.publish(NodeMetric::ForeignChain {
+                node_id: self.node_id,
+                peer_id,
+            });
+        }
+        foreign
+    }
+
+    /// Hands a chain the peer sent, or made of a compact block it sent, over to a worker that
+    /// validates it against the chain of this node. The validated chain comes back as a
+    /// `ChainValidated` event.
+    fn receive_chain(&mut self, peer_id: u32, chain: Arc<Chain>, peers: &mut [Peer]) {
+        if let Some(peer) = peers.iter_mut().find(|peer| peer.sender.peer_id() == peer_id) {
+            peer.learn(&chain);
+        }
+        self.observe_producer(&chain);
+        // A chain this one contains was validated already, and is weaker.
+        if self.heights.contains(chain.head().hash()) {
+            self.logger.debug("chain", format_args!("Known chain, height: {}", chain.height()));
+            return;
+        }
+        // Another peer sent it first.
+        if !self.seen_chains.insert(chain.head().hash()) {
+            self.logger.debug("chain", format_args!("Duplicate chain, height: {}", chain.height()));
+            self.publish(NodeMetric::ChainDuplicate {
+                node_id: self.node_id,
+                peer_id,
+            });
+            return;
+        }
+        // The blocks this node did not know of, down to the fork point.
+        if self.metrics_bus.is_some() {
+            for block in chain.blocks().take_while(|block| !self.heights.contains(block.hash())) {
+                self.publish(NodeMetric::BlockReceived {
+                    node_id: self.node_id,
+                    peer_id,
+                    height: block.height,
+                    hash: block.hash().to_string(),
+                });
+            }
+        }
+
+        let known_chain = self.chain.clone();
+        let known_utxo_set = self.utxo_set.clone();
+        let known_undo_log = self.undo_log.clone();
+        self.validations.push(task::spawn_blocking(move || {
+            // The unspent outputs and the undo data only depend on the validated chain, they
+            // hold even if this node adopted another one in the meantime.
+            let validation = match known_undo_log {
+                Some(ref undo_log) => chain
+                    .validate_from_fork(&known_chain, &known_utxo_set, undo_log)
+                    .map(|(utxo_set, undo_log)| (utxo_set, Some(undo_log))),
+                None => chain.validate_from(&known_chain, &known_utxo_set).map(|utxo_set| (utxo_set, None)),
+            };
+            (peer_id, chain, validation)
+        }));
+    }
+
+    /// Slashes the validator that produced the head block of the chain if it signed another
+    /// block on top of the same parent.
+    fn observe_producer(&mut self, chain: &Chain) {
+        let header = chain.head().header();
+        let slashed_stake = match self.validators {
+            Some(ref mut validators) => validators.observe(&header),
+            None => None,
+        };
+        if let Some(stake) = slashed_stake {
+            let validator = chain.head().node_id;
+            self.logger.info("slashing", format_args!("Slashed validator #{:05}, stake: {}", validator, stake));
+            self.publish(NodeMetric::ValidatorSlashed {
+                node_id: self.node_id,
+                validator,
+                stake,
+            });
+        }
+    }
+
+    /// Propagates a chain of the peer once validated. The peer is penalized if the chain is
+    /// invalid.
+    fn receive_validated_chain(
+        &mut self,
+        peer_id: u32,
+        chain: Arc<Chain>,
+        validation: ChainValidation,
+        peers: &mut Vec<Peer>,
+        mining_state_updater: &MiningStateUpdater,
+    ) -> Result<(), Error> {
+        // Adopted in the meantime, or part of a stronger chain that was.
+        if self.heights.contains(chain.head().hash()) {
+            return Ok(());
+        }
+
+        let previous_chain = self.chain.clone();
+        let result = validation
+            .map_err(Error::Validation)
+            .and_then(|(utxo_set, undo_log)| self.propagate(chain, utxo_set, undo_log, peers, mining_state_updater));
+        match result {
+            Err(Error::Validation(err)) => self.reject(peer_id, err, peers),
+            Err(Error::Ledger(_)) => self.penalize(peer_id, Misbehavior::InvalidChain, peers),
+            _ => {}
+        }
+        if !Arc::ptr_eq(&previous_chain, &self.chain) {
+            self.publish(NodeMetric::BlockPropagated {
+                node_id: self.node_id,
+                peer_id,
+                height: self.chain.height(),
+                hash: self.chain.head().hash().to_string(),
+            });
+        }
+        result
+    }
+
+    /// Rebuilds the compact block from the mempool, or asks the peer for the transactions
+    /// missing from it. A block that does not extend the chain of this node, nor its tail, is
+    /// asked for as part of the whole chain of the peer, unless it is no higher than the chain
+    /// of this node: every block has the difficulty of the genesis one, so the chain of the
+    /// peer would not be stronger.
+    fn receive_compact_block(
+        &mut self,
+        peer_id: u32,
+        compact_block: Arc<CompactBlock>,
+        peers: &mut Vec<Peer>,
+    ) -> Result<(), Error> {
+        let hash = compact_block.hash().clone();
+        if self.heights.contains(&hash) || self.partial_blocks.contains_key(&hash) {
+            return Ok(());
+        }
+        if parent_chain(&self.chain, &compact_block).is_none() {
+            if compact_block.height() > self.chain.height() {
+                self.request_chain(peer_id, peers);
+            }
+            return Ok(());
+        }
+
+        let partial_block = PartialBlock::new(peer_id, compact_block, &self.mempool);
+        let missing = partial_block.missing();
+        if missing.is_empty() {
+            return self.connect(partial_block, 0, peers);
+        }
+
+        let bytes = (SHA256_OUTPUT_LEN + 4 * missing.len()) as u64;
+        if let Some(peer) = peers.iter_mut().find(|peer| peer.sender.peer_id() == peer_id) {
+            match peer.sender.try_send(Message::GetBlockTransactions(hash.clone(), missing)) {
+                Ok(()) => {
+                    self.partial_blocks.insert(hash, partial_block);
+                    self.publish(NodeMetric::BlockRelayed {
+                        node_id: self.node_id,
+                        bytes,
+                        full_block_bytes: 0,
+                    });
+                }
+                // The next chain of the peer will tell the block.
+                Err(err) => self.logger.debug("transaction", format_args!("Transactions not requested: {}", err)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Completes the compact block the peer was asked the transactions of.
+    fn receive_block_transactions(
+        &mut self,
+        peer_id: u32,
+        hash: &Hash,
+        transactions: Vec<SignedTx>,
+        peers: &mut Vec<Peer>,
+    ) -> Result<(), Error> {
+        // The block may be connected or dropped already.
+        let mut partial_block = match self.partial_blocks.remove(hash) {
+            Some(partial_block) if partial_block.peer_id() == peer_id => partial_block,
+            Some(partial_block) => {
+                self.partial_blocks.insert(hash.clone(), partial_block);
+                return Ok(());
+            }
+            None => return Ok(()),
+        };
+
+        let missing_transactions = partial_block.missing().len() as u32;
+        match partial_block.fill(transactions) {
+            Ok(()) => self.connect(partial_block, missing_transactions, peers),
+            Err(reason) => {
+                self.logger.debug("compact_block", format_args!("Compact block not rebuilt: {}", reason));
+                self.request_chain(peer_id, peers);
+                Ok(())
+            }
+        }
+    }
+
+    /// Rebuilds the block of a complete compact block, and adopts the chain it makes if it is
+    /// the strongest. Short ids colliding with the ones of other transactions make the block
+    /// unusable, the whole chain of the peer is asked for instead.
+    fn connect(
+        &mut self,
+        partial_block: PartialBlock,
+        missing_transactions: u32,
+        peers: &mut Vec<Peer>,
+    ) -> Result<(), Error> {
+        let peer_id = partial_block.peer_id();
+        let parent_chain = match parent_chain(&self.chain, partial_block.compact_block()) {
+            Some(parent_chain) => parent_chain,
+            None => {
+                self.request_chain(peer_id, peers);
+                return Ok(());
+            }
+        };
+
+        let block = match partial_block.block() {
+            Ok(block) => block,
+            Err(reason) => {
+                self.logger.debug("compact_block", format_args!("Compact block not rebuilt: {}", reason));
+                self.request_chain(peer_id, peers);
+                return Ok(());
+            }
+        };
+        self.publish(NodeMetric::CompactBlockReconstructed {
+            node_id: self.node_id,
+            missing_transactions,
+        });
+
+        match Chain::expand(&parent_chain, block) {
+            Ok(chain) => {
+                self.receive_chain(peer_id, chain, peers);
+                Ok(())
+            }
+            Err(err) => {
+                self.reject(peer_id, err, peers);
+                Err(Error::Validation(err))
+            }
+        }
+    }
+
+    /// Asks the peer for its whole chain, in place of a compact block this node cannot connect.
+    fn request_chain(&self, peer_id: u32, peers: &mut [Peer]) {
+        self.publish(NodeMetric::CompactBlockFallback { node_id: self.node_id });
+        if let Some(peer) = peers.iter_mut().find(|peer| peer.sender.peer_id() == peer_id) {
+            if let Err(err) = peer.sender.try_send(Message::GetChain) {
+                self.logger.debug("relay", format_args!("Chain not requested: {}", err));
+            }
+        }
+    }
+
+    /// Answers a peer with the chain of this node. Its blocks after the last chain the peer
+    /// sent are the ones it is expected to miss, or the head block alone if it sent none.
+    fn send_requested_chain(&self, peer_id: u32, peers: &mut [Peer]) {
+        if let Some(peer) = peers.iter_mut().find(|peer| peer.sender.peer_id() == peer_id) {
+            let bytes = match peer.last_received_chain {
+                Some(ref known_chain) => blocks_size(&self.chain.diverging_blocks(known_chain).0),
+                None => self.chain.head().size() as u64,
+            };
+            match peer.sender.try_send(Message::RequestedChain(self.chain.clone())) {
+                Ok(()) => {
+                    peer.learn(&self.chain);
+                    self.publish(NodeMetric::BlockRelayed {
+                        node_id: self.node_id,
+                        bytes,
+                        full_block_bytes: 0,
+                    })
+                }
+                Err(err) => self.logger.debug("relay", format_args!("Chain not sent: {}", err)),
+            }
+        }
+    }
+
+    /// Answers a peer with the transactions it asked for, if the block is part of the chain.
+    fn send_block_transactions(&self, hash: &Hash, indexes: &[u32], sender: &mut ConnectionSender<Message>) {
+        let block = match self.heights.height_of(hash).and_then(|height| self.heights.block_at(height)) {
+            Some(block) => block,
+            None => {
+                self.logger.debug("transaction", format_args!("Transactions of an unknown block asked for."));
+                return;
+            }
+        };
+
+        let block_transactions = block.body().body().transactions();
+        let transactions: Vec<SignedTx> = indexes
+            .iter()
+            .filter_map(|index| block_transactions.get(*index as usize).cloned())
+            .collect();
+        let bytes = (SHA256_OUTPUT_LEN + transactions_size(&transactions).unwrap_or_default()) as u64;
+        match sender.try_send(Message::BlockTransactions(hash.clone(), transactions)) {
+            Ok(()) => self.publish(NodeMetric::BlockRelayed {
+                node_id: self.node_id,
+                bytes,
+                full_block_bytes: 0,
+            }),
+            Err(err) => self.logger.debug("transaction", format_args!("Transactions not sent: {}", err)),
+        }
+    }
+
+    /// Adds the misbehavior to the score of the peer. Once the score reaches the threshold,
+    /// the peer is banned: the connection is closed, and the ones it opens are closed at once
+    /// until the ban is over.
+    fn penalize(&mut self, peer_id: u32, misbehavior: Misbehavior, peers: &mut Vec<Peer>) {
+        self.publish(NodeMetric::PeerMisbehaved {
+            node_id: self.node_id,
+            peer_id,
+            misbehavior,
+        });
+        if !self.peer_scores.penalize(peer_id, misbehavior, clock::now()) {
+            return;
+        }
+
+        self.logger.info("ban", format_args!("Banned peer #{:05} for {:?}", peer_id, misbehavior));
+        self.remove_peer(peer_id, peers);
+        self.publish(NodeMetric::PeerBanned {
+            node_id: self.node_id,
+            peer_id,
+        });
+    }
+
+    /// Closes the connection of the peer to make room for a new one.
+    fn evict(&mut self, peer_id: u32, peers: &mut Vec<Peer>) {
+        self.logger.debug("peer", format_args!("Evicted peer #{:05}", peer_id));
+        self.remove_peer(peer_id, peers);
+        self.publish(NodeMetric::PeerEvicted {
+            node_id: self.node_id,
+            peer_id,
+        });
+    }
+
+    /// Closes the connection of the peer this node drops, and forgets it.
+    fn remove_peer(&mut self, peer_id: u32, peers: &mut Vec<Peer>) {
+        if let Some(index) = peers.iter().position(|peer| peer.sender.peer_id() == peer_id) {
+            let peer = peers.remove(index);
+            if let Err(err) = peer.disconnect() {
+                self.logger.debug("peer", format_args!("Peer lost: {}", err));
+            }
+        }
+        self.forget(peer_id);
+    }
+
+    /// Drops what this node keeps about a peer once its connection is closed.
+    fn forget(&mut self, peer_id: u32) {
+        self.rate_limiters.remove(&peer_id);
+        self.partial_blocks.retain(|_hash, partial_block| partial_block.peer_id() != peer_id);
+        if let Some(ref latency_stats) = self.latency_stats {
+            latency_stats.remove(self.node_id, peer_id);
+        }
+    }
+
+    /// Publishes why the chain of the peer was rejected, and penalizes the peer if the chain
+    /// was forged. A fork deeper than the blocks this node validated may be valid, it cannot
+    /// tell.
+    fn reject(&mut self, peer_id: u32, err: ChainError, peers: &mut Vec<Peer>) {
+        self.publish(NodeMetric::ChainRejected {
+            node_id: self.node_id,
+            peer_id,
+            error: err,
+        });
+        if err.is_forged() {
+            self.penalize(peer_id, Misbehavior::InvalidChain, peers);
+        }
+    }
+
+    /// Logs the failures caused by the peers or by the network, which the node recovers
+    /// from. Only its internal errors stop the node.
+    fn recover(&self, result: Result<(), Error>) -> Result<(), Error> {
+        match result {
+            Err(Error::Validation(ChainError::Pruned)) => {
+                self.logger.debug("chain", format_args!("Chain forking below the blocks validated."));
+                Ok(())
+            }
+            Err(Error::Validation(reason)) => {
+                self.logger.error("chain", format_args!("Invalid chain: {}", reason));
+                Ok(())
+            }
+            Err(Error::Ledger(err)) => {
+                self.logger.error("chain", format_args!("Invalid chain: {}", err));
+                Ok(())
+            }
+            Err(Error::Network(err)) => {
+                self.logger.debug("peer", format_args!("Peer lost: {}", err));
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Makes the wallet send a random amount to the miner of the head block, which may
+    /// be this very node. The payment will be included in the next blocks mined by this node.
+    /// Returns the hash of the payment, none if it failed.
+    fn try_new_payment(&mut self) -> Option<crypto::Hash> {
+        let mut rng = rand::thread_rng();
+        let amount = rng.gen_range(1, COINBASE_AMOUNT);
+        let fees = rng.gen_range(0, MAX_PAYMENT_FEES);
+        let to_address = match rng.choose(&self.payees) {
+            Some(payee) if rng.gen() => payee.clone(),
+            _ => self.chain.head().body().body().coinbase_tx().0.address(),
+        };
+
+        let result = self.wallet
+            .new_transaction(amount, to_address, fees, &self.utxo_set)
+            .and_then(|transaction| self.mempool.add(transaction, &self.utxo_set, self.chain.height() + 1));
+
+        if let Some(ref double_spend_counter) = self.double_spend_counter {
+            double_spend_counter.report(self.node_id, &self.mempool);
+        }
+        if let Some(ref throughput_stats) = self.throughput_stats {
+            match result {
+                Ok(ref hash) => throughput_stats.submit(hash.clone()),
+                Err(_) => throughput_stats.reject(),
+            }
+            throughput_stats.report_backlog(self.node_id, self.mempool.len());
+        }
+
+        match result {
+            Ok(hash) => {
+                self.logger.debug("payment", format_args!("New payment of {}", amount));
+                Some(hash)
+            }
+            Err(err) => {
+                self.logger.debug("payment", format_args!("Could not pay {}: {:?}", amount, err));
+                None
+            }
+        }
+    }
+
+    /// Votes for the last checkpoint of the chain, if this node is a voter that did not vote
+    /// for it yet.
+    fn vote(&mut self, peers: &mut [Peer]) {
+        let (finality, voter_key) = match (&self.finality, &self.voter_key) {
+            (Some(finality), Some(voter_key)) => (finality, voter_key),
+            _ => return,
+        };
+        let checkpoint_height = match finality.voters().last_checkpoint(self.chain.height()) {
+            Some(checkpoint_height) if checkpoint_height > self.voted_height => checkpoint_height,
+            _ => return,
+        };
+        let block_hash = match self.heights.block_at(checkpoint_height) {
+            Some(block) => block.hash().clone(),
+            None => return,
+        };
+
+        let vote = CheckpointVote::new(self.node_id, checkpoint_height, block_hash, voter_key);
+        self.voted_height = checkpoint_height;
+        self.receive_vote(None, Arc::new(vote), peers);
+    }
+
+    /// Counts the vote, then relays it to every peer but the one it comes from, if any, unless
+    /// it was counted already. Reports the checkpoint it finalizes.
+    fn receive_vote(&mut self, origin: Option<u32>, vote: Arc<CheckpointVote>, peers: &mut [Peer]) {
+        let finality = match self.finality {
+            Some(ref mut finality) => finality,
+            None => return,
+        };
+        let finalized_height = match finality.add(&vote) {
+            Ok((true, finalized_height)) => finalized_height,
+            Ok((false, _finalized_height)) => return,
+            Err(err) => {
+                self.logger.debug("finality", format_args!("Vote of #{:05} dropped: {}", vote.voter(), err));
+                return;
+            }
+        };
+
+        for peer in peers.iter_mut().filter(|peer| Some(peer.sender.peer_id()) != origin) {
+            // The vote still reaches the peer through the other nodes.
+            if let Err(err) = peer.sender.try_send(Message::Vote(vote.clone())) {
+                self.logger.debug("finality", format_args!("Vote not relayed: {}", err));
+            }
+        }
+
+        if let Some(height) = finalized_height {
+            self.logger.info(
+                "finality",
+                format_args!("Finalized the checkpoint {}, height {}", vote.block_hash(), height),
+            );
+            self.publish(NodeMetric::CheckpointFinalized {
+                node_id: self.node_id,
+                height,
+                hash: vote.block_hash().to_string(),
+            });
+        }
+    }
+
+    /// Announces the new transactions of the mempool to every peer but the one they come from,
+    /// if any. A transaction enters the mempool once, so it is announced once. The light peers
+    /// that loaded a filter get the matching transactions instead, gossip or not.
+    fn announce(&mut self, hashes: Vec<crypto::Hash>, origin: Option<u32>, peers: &mut [Peer]) {
+        for hash in &hashes {
+            self.seen_transactions.insert(hash);
+        }
+        self.send_filtered_transactions(&hashes, peers);
+        if !self.transaction_gossip {
+            return;
+        }
+
+        for peer in peers
+            .iter_mut()
+            .filter(|peer| Some(peer.sender.peer_id()) != origin && peer.bloom_filter.is_none())
+        {
+            match peer.sender.try_send(Message::Inventory(hashes.clone())) {
+                Ok(()) => self.publish(NodeMetric::TransactionsAnnounced {
+                    node_id: self.node_id,
+                    transactions: hashes.len() as u32,
+                }),
+                // The transactions still reach the peer through the other nodes, or in a block.
+                Err(err) => self.logger.debug("transaction", format_args!("Inventory not sent: {}", err)),
+            }
+        }
+    }
+
+    /// Sends the given transactions of the mempool matching the filters of the light peers.
+    fn send_filtered_transactions(&self, hashes: &[crypto::Hash], peers: &mut [Peer]) {
+        let transactions: Vec<&SignedTx> = hashes
+            .iter()
+            .filter_map(|hash| self.mempool.get(hash))
+            .map(|entry| entry.transaction())
+            .collect();
+
+        for peer in peers.iter_mut() {
+            let matching: Vec<SignedTx> = match peer.bloom_filter {
+                Some(ref bloom_filter) => transactions
+                    .iter()
+                    .filter(|transaction| bloom_filter.matches(transaction))
+                    .map(|transaction| (*transaction).clone())
+                    .collect(),
+                None => continue,
+            };
+            self.publish(NodeMetric::TransactionsFiltered {
+                node_id: self.node_id,
+                tested: transactions.len() as u32,
+                matched: matching.len() as u32,
+            });
+            if matching.is_empty() {
+                continue;
+            }
+
+            if let Err(err) = peer.sender.try_send(Message::Transactions(matching)) {
+                self.logger.debug("transaction", format_args!("Filtered transactions not sent: {}", err));
+            }
+        }
+    }
+
+    /// Asks the peer for the transactions it announced that this node did not see. They are
+    /// seen from now on: if the peer does not send them, this node gets them in a block.
+    fn receive_inventory(&mut self, peer_id: u32, hashes: Vec<crypto::Hash>, peers: &mut [Peer]) {
+        let transactions = hashes.len() as u32;
+        let unknown: Vec<crypto::Hash> = hashes
+            .into_iter()
+            .filter(|hash| !self.mempool.contains(hash) && self.seen_transactions.insert(hash))
+            .collect();
+        self.publish(NodeMetric::InventoryReceived {
+            node_id: self.node_id,
+            transactions,
+            unknown: unknown.len() as u32,
+        });
+        if unknown.is_empty() {
+            return;
+        }
+
+        if let Some(peer) = peers.iter_mut().find(|peer| peer.sender.peer_id() == peer_id) {
+            if let Err(err) = peer.sender.try_send(Message::GetTransactions(unknown)) {
+                self.logger.debug("transaction", format_args!("Transactions not requested: {}", err));
+            }
+        }
+    }
+
+    /// Adds the transactions the peer sent to the mempool, announces the accepted ones to the
+    /// other peers and mines them.
+    fn receive_transactions(
+        &mut self,
+        peer_id: u32,
+        transactions: Vec<SignedTx>,
+        peers: &mut [Peer],
+        mining_state_updater: &MiningStateUpdater,
+    ) -> Result<(), Error> {
+        let received = transactions.len() as u32;
+        let mut accepted = vec![];
+        let mut refused = 0;
+        for transaction in transactions {
+            // Confirmed or conflicting in the meantime, most likely. The peer is not at fault
+            // for relaying what the policy of this node refuses.
+            match self.mempool.add(transaction, &self.utxo_set, self.chain.height() + 1) {
+                Ok(hash) => accepted.push(hash),
+                Err(err) => {
+                    if err.is_policy() {
+                        refused += 1;
+                    }
+                    self.logger.debug("transaction", format_args!("Transaction rejected: {:?}", err))
+                }
+            }
+        }
+
+        if let Some(ref double_spend_counter) = self.double_spend_counter {
+            double_spend_counter.report(self.node_id, &self.mempool);
+        }
+        if let Some(ref throughput_stats) = self.throughput_stats {
+            throughput_stats.report_backlog(self.node_id, self.mempool.len());
+        }
+        self.publish(NodeMetric::TransactionsReceived {
+            node_id: self.node_id,
+            transactions: received,
+            accepted: accepted.len() as u32,
+            refused,
+        });
+        if accepted.is_empty() {
+            return Ok(());
+        }
+
+        self.announce(accepted, Some(peer_id), peers);
+        mining_state_updater.mine_new_chain(self.chain.clone(), self.block_body()?)
+    }
+
+    /// Answers a peer with the transactions it asked for that are still pending.
+    fn send_transactions(&self, hashes: &[crypto::Hash], sender: &mut ConnectionSender<Message>) {
+        let transactions = hashes
+            .iter()
+            .filter_map(|hash| self.mempool.get(hash))
+            .map(|entry| entry.transaction().clone())
+            .collect();
+        if let Err(err) = sender.try_send(Message::Transactions(transactions)) {
+            self.logger.debug("transaction", format_args!("Transactions not sent: {}", err));
+        }
+    }
+
+    /// Pings every peer, the pongs tell their round trip times.
+    fn ping(&self, peers: &mut Vec<Peer>) {
+        for peer in peers.iter_mut() {
+            match peer.sender.try_send(Message::Ping(clock::now())) {
+                Ok(()) => {}
+                // The round trip time is measured again on the next ping.
+                Err(netsim::error::Error::Congested) => {
+                    self.logger.debug("ping", format_args!("Congested peer, ping dropped."));
+                }
+                Err(err) => {
+                    self.logger.debug("peer", format_args!("Peer lost: {}", err));
+                    peer.is_closed = true;
+                }
             }
         }
+
+        peers.retain(|peer| !peer.is_closed);
+    }
+
+    /// Updates the round trip time of the peer that answered a ping sent at `sent_at`.
+    fn receive_pong(&self, peer_id: u32, sent_at: Instant, peers: &mut [Peer]) {
+        if let Some(peer) = peers.iter_mut().find(|peer| peer.sender.peer_id() == peer_id) {
+            let round_trip = clock::now().saturating_duration_since(sent_at);
+            let round_trip_time = smoothed_round_trip_time(peer.round_trip_time, round_trip);
+            peer.round_trip_time = Some(round_trip_time);
+
+            if let Some(ref latency_stats) = self.latency_stats {
+                latency_stats.report(self.node_id, peer_id, round_trip_time);
+            }
+            self.publish(NodeMetric::LinkLatency {
+                node_id: self.node_id,
+                peer_id,
+                round_trip_time_ms: round_trip_time.as_secs_f64() * 1000.0,
+            });
+        }
+    }
+
+    /// Answers a light node with the proof it asked for, if the block is part of the chain and
+    /// was not pruned.
+    fn send_proof(&self, request: &Request<ProofRequest>, sender: &mut ConnectionSender<Message>) {
+        let proof_request = request.body();
+        let response = match self.heights.block_at(proof_request.height()) {
+            Some(block) if block.hash() == proof_request.block_hash() && block.body().is_pruned() => {
+                self.publish(NodeMetric::ProofRequestPruned { node_id: self.node_id });
+                ProofResponse::Pruned
+            }
+            _ => match TransactionProof::new(&self.chain, proof_request) {
+                Some(proof) => ProofResponse::Proof(Arc::new(proof)),
+                None => ProofResponse::UnknownBlock,
+            },
+        };
+        if let Err(err) = sender.try_send(Message::Proof(request.respond(response))) {
+            self.logger.debug("proof", format_args!("Proof not sent: {}", err));
+        }
+    }
+
+    /// Builds the body of the next block: the pending payments with the highest fee rates
+    /// and the coinbase.
+    fn block_body(&self) -> Result<Arc<BlockBody>, Error> {
+        let template = BlockTemplateBuilder::new(self.chain.params(), self.coinbase_address.clone())
+            .with_max_transactions(MAX_BLOCK_TRANSACTIONS)
+            .build(&self.mempool, self.chain.height() + 1)
+            .map_err(|err| Error::Internal(format!("Could not build the block template: {}", err)))?;
+        BlockBody::new(template.into_body())
+            .map(Arc::new)
+            .map_err(|err| Error::Internal(format!("Could not build the block body: {}", err)))
     }
 }
 
-impl Node<Arc<Chain>> for PowNode {
-    fn run<S>(mut self, connection_stream: S) -> Box<Future<Item = (), Error = ()> + Send>
+impl PowNode {
+    /// Handles the events of the peers, of the miner and of the wallet one at a time, which
+    /// avoids concurrency issues, locking and complicated lifetime management. Stops on the
+    /// first error the node cannot recover from.
+    async fn route<S>(&mut self, mut connection_stream: S) -> Result<(), Error>
     where
-        S: Stream<Item = MPSCConnection<Arc<Chain>>, Error = ()> + Send + 'static,
+        S: Stream<Item = Result<ConnectionEvent<Message>, netsim::error::Error>> + Unpin,
     {
         // Start a mining stream.
         let (
             mining_stream, // This stream will yield valid blocks.
             updater,       // This provides a way to warn the miner that it should mine a new chain
-        ) = mining_stream(self.node_id, self.chain.clone(), self.mining_attempt_delay);
+        ) = mining_stream(
+            self.node_id,
+            self.chain.clone(),
+            self.block_body()?,
+            self.mining_attempt_delay,
+            self.hash_registry.clone(),
+            self.producer_key.clone(),
+        );
+        let mut mining_stream = Box::pin(mining_stream);
+        // The wallet regularly tries to send a payment.
+        let mut payment_attempts = Box::pin(interval_stream(self.payment_attempt_delay));
+        let mut ping_attempts = Box::pin(interval_stream(self.ping_delay));
+        let start = time::Instant::now();
+        // The shocks happen in turn, each with its new mining attempt delay.
+        let mut hashrate_shocks = self.hashrate_shocks.clone();
+        hashrate_shocks.sort_by_key(|&(at, _attempt_delay)| at);
+        let mut hashrate_shocks = Box::pin(stream::iter(hashrate_shocks).then(|(at, attempt_delay)| async move {
+            time::sleep_until(start + at).await;
+            attempt_delay
+        }));
 
-        let node_id = self.node_id;
         let genesis_chain = self.chain.clone();
-        let peer_stream = connection_stream.map(move |connection| {
-            debug!("[#{:05}] Connection received.", node_id);
-            let (sender, receiver) = connection.split();
-
-            let reception = receiver
-                .map(|chain| NodeEvent::ChainRemoteUpdate(chain))
-                .map_err(|_| panic!());
-
-            // Send a peer first, then every update received.
-            futures::stream::once(Ok(NodeEvent::Peer(Peer {
-                sender,
-                last_known_chain: genesis_chain.clone(),
-                is_closed: false,
-            }))).chain(reception)
-        });
-        // Flatten this stream so all incoming traffic is considered a single stream.
-        let peer_stream = flatten_select::new(peer_stream);
-
-        // Joining all these streams helps us avoid concurrency issues, the use of locking and
-        // complicated lifetime management.
+        // The messages of every peer, polled in turn.
+        let mut receptions = SelectAll::new();
         let mut peers = vec![];
-        let routing_future = peer_stream
-            .select(
-                // This merges the events coming from peers with the events of new mined nodes.
-                mining_stream.map(move |chain| NodeEvent::MinedChain(chain)),
-            )
-            .for_each(move |node_event| {
-                match node_event {
-                    NodeEvent::Peer(peer) => {
-                        match &peer.sender.unbounded_send(self.chain.clone()) {
-                            Ok(()) => {
-                                peers.push(peer);
-                                debug!("[#{:05}] New peer. Total: {}", self.node_id, peers.len());
-                            }
-                            Err(err) => {
-                                debug!("[#{:05}] Peer lost: {}", self.node_id, err);
-                            }
-                        }
+
+        loop {
+            let node_event = tokio::select! {
+                Some(event) = connection_stream.next() => match event? {
+                    ConnectionEvent::Opened(connection) => {
+                        self.logger.debug("peer", format_args!("Connection received."));
+                        let inbound = connection.is_inbound();
+                        let (sender, receiver) = connection.split();
+                        let (peer_reception, reception_handle) = stream::abortable(reception(receiver, sender.clone()));
+                        receptions.push(peer_reception);
+
+                        NodeEvent::Peer(Peer {
+                            sender,
+                            last_known_chain: genesis_chain.clone(),
+                            is_closed: false,
+                            round_trip_time: None,
+                            last_received_chain: None,
+                            compact_relay: false,
+                            bloom_filter: None,
+                            inbound,
+                            anchor: false,
+                            opened_at: clock::now(),
+                            reception: reception_handle,
+                        })
                     }
-                    NodeEvent::MinedChain(chain) => {
-                        info!(
-                            "[#{:05}] Mined a new block: {:?}, height {}",
-                            self.node_id,
-                            chain.head().hash(),
-                            chain.height()
-                        );
-                        self.propagate(chain, &mut peers, &updater);
+                    ConnectionEvent::Closed(peer_id) => NodeEvent::PeerClosed(peer_id),
+                },
+                Some(node_event) = receptions.next() => {
+                    self.publish(NodeMetric::MessageReceived { node_id: self.node_id });
+                    if !self.admit(&node_event) {
+                        continue;
                     }
-                    NodeEvent::ChainRemoteUpdate(chain) => match chain.validate() {
-                        Ok(()) => {
-                            self.propagate(chain, &mut peers, &updater);
-                        }
-                        Err(err) => error!("Invalid chain: {}", err),
-                    },
+                    node_event
+                }
+                Some(validated) = self.validations.next() => {
+                    let (peer_id, chain, validation) =
+                        validated.map_err(|err| Error::Internal(format!("Validation aborted: {}", err)))?;
+                    NodeEvent::ChainValidated(peer_id, chain, validation)
                 }
+                Some(chain) = mining_stream.next() => NodeEvent::MinedChain(chain),
+                Some(_instant) = payment_attempts.next() => NodeEvent::PaymentAttempt,
+                Some(_instant) = ping_attempts.next() => NodeEvent::PingAttempt,
+                Some(attempt_delay) = hashrate_shocks.next() => NodeEvent::HashrateShock(attempt_delay),
+                else => return Ok(()),
+            };
 
-                future::ok(())
-            });
+            let result = self.handle(node_event, &mut peers, &updater);
+            self.recover(result)?;
+        }
+    }
+
+    fn handle(
+        &mut self,
+        node_event: NodeEvent,
+        peers: &mut Vec<Peer>,
+        updater: &MiningStateUpdater,
+    ) -> Result<(), Error> {
+        match node_event {
+            NodeEvent::Peer(peer) if self.peer_scores.is_banned(peer.sender.peer_id(), clock::now()) => {
+                self.logger.debug("ban", format_args!("Banned peer #{:05} refused.", peer.sender.peer_id()));
+                // The peer may be gone already.
+                let _ = peer.disconnect();
+                Ok(())
+            }
+            NodeEvent::Peer(mut peer) => {
+                if !peer.inbound {
+                    peer.anchor = self.outbound_connections < self.connection_limits.anchors();
+                    self.outbound_connections += 1;
+                }
+                let connections: Vec<PeerConnection> = peers.iter().map(Peer::connection).collect();
+                if let Some(peer_id) = self.connection_limits.evict(&connections, &peer.connection()) {
+                    if peer_id == peer.sender.peer_id() {
+                        self.logger.debug("peer", format_args!("Peer #{:05} refused, no room left.", peer_id));
+                        let _ = peer.disconnect();
+                        self.publish(NodeMetric::PeerEvicted {
+                            node_id: self.node_id,
+                            peer_id,
+                        });
+                        return Ok(());
+                    }
+                    self.evict(peer_id, peers);
+                }
+                if self.compact_relay {
+                    // Without it, the peer keeps sending whole chains.
+                    let _ = peer.sender.try_send(Message::SendCompact);
+                }
+                let (message, _bytes, _full_block_bytes) = relay_message(&peer, &self.chain, None);
+                // A congested peer is still a peer, it will get the next chain.
+                match self.relay(&mut peer.sender, message) {
+                    Err(netsim::error::Error::Disconnected) => {
+                        Err(Error::from(netsim::error::Error::Disconnected))
+                    }
+                    _ => {
+                        peers.push(peer);
+                        self.logger.debug("peer", format_args!("New peer. Total: {}", peers.len()));
+                        Ok(())
+                    }
+                }
+            }
+            NodeEvent::PeerClosed(peer_id) => {
+                // The messages the peer sent before closing are dropped as well.
+                if let Some(index) = peers.iter().position(|peer| peer.sender.peer_id() == peer_id) {
+                    peers.remove(index).reception.abort();
+                }
+                self.forget(peer_id);
+                self.logger.debug("peer", format_args!("Peer closed. Total: {}", peers.len()));
+                Ok(())
+            }
+            // The sybils have no hash power of their own.
+            NodeEvent::MinedChain(_chain) if self.sybil_adversary.is_some() => Ok(()),
+            NodeEvent::MinedChain(chain) => {
+                self.logger.info(
+                    "mining",
+                    format_args!(
+                        "Mined a new block: {:?}, height {}, transactions {}",
+                        chain.head().hash(),
+                        chain.height(),
+                        chain.head().body().body().transactions().len()
+                    ),
+                );
+                self.publish(NodeMetric::BlockMined {
+                    node_id: self.node_id,
+                    height: chain.height(),
+                    hash: chain.head().hash().to_string(),
+                    reward: *chain.head().body().body().coinbase_tx().0.amount(),
+                });
+                self.validate_and_propagate(chain, peers, updater)
+            }
+            // The chains of a peer on another chain of a multi-chain network.
+            NodeEvent::ChainRemoteUpdate(peer_id, ref chain) | NodeEvent::RequestedChain(peer_id, ref chain)
+                if self.is_foreign(peer_id, chain) =>
+            {
+                Ok(())
+            }
+            // The chains the peer sent before it was banned.
+            NodeEvent::ChainRemoteUpdate(peer_id, _chain)
+                if self.peer_scores.is_banned(peer_id, clock::now()) =>
+            {
+                Ok(())
+            }
+            NodeEvent::ChainRemoteUpdate(peer_id, chain) => {
+                if let Some(misbehavior) = self.receive_from(peer_id, &chain, peers) {
+                    self.penalize(peer_id, misbehavior, peers);
+                }
+                self.receive_chain(peer_id, chain, peers);
+                Ok(())
+            }
+            NodeEvent::ChainValidated(peer_id, chain, validation) => {
+                self.receive_validated_chain(peer_id, chain, validation, peers, updater)
+            }
+            NodeEvent::CompactRelayRequested(peer_id) => {
+                if let Some(peer) = peers.iter_mut().find(|peer| peer.sender.peer_id() == peer_id) {
+                    peer.compact_relay = true;
+                }
+                Ok(())
+            }
+            // Like the chains, the compact blocks the peer sent before it was banned.
+            NodeEvent::CompactBlock(peer_id, _compact_block)
+                if self.peer_scores.is_banned(peer_id, clock::now()) =>
+            {
+                Ok(())
+            }
+            NodeEvent::CompactBlock(peer_id, compact_block) => {
+                self.receive_compact_block(peer_id, compact_block, peers)
+            }
+            NodeEvent::BlockTransactionsRequest(hash, indexes, mut sender) => {
+                self.send_block_transactions(&hash, &indexes, &mut sender);
+                Ok(())
+            }
+            NodeEvent::BlockTransactions(peer_id, hash, transactions) => {
+                self.receive_block_transactions(peer_id, &hash, transactions, peers)
+            }
+            NodeEvent::ChainRequest(peer_id) => {
+                self.send_requested_chain(peer_id, peers);
+                Ok(())
+            }
+            NodeEvent::RequestedChain(peer_id, _chain) if self.peer_scores.is_banned(peer_id, clock::now()) => {
+                Ok(())
+            }
+            // Unlike the relayed chains, the chain may be the one the peer sent last.
+            NodeEvent::RequestedChain(peer_id, chain) => {
+                self.receive_chain(peer_id, chain, peers);
+                Ok(())
+            }
+            NodeEvent::Inventory(peer_id, hashes) => {
+                self.receive_inventory(peer_id, hashes, peers);
+                Ok(())
+            }
+            NodeEvent::TransactionsRequest(hashes, mut sender) => {
+                self.send_transactions(&hashes, &mut sender);
+                Ok(())
+            }
+            NodeEvent::Transactions(peer_id, transactions) => {
+                self.receive_transactions(peer_id, transactions, peers, updater)
+            }
+            NodeEvent::FilterLoaded(peer_id, bloom_filter) => {
+                if let Some(peer) = peers.iter_mut().find(|peer| peer.sender.peer_id() == peer_id) {
+                    peer.bloom_filter = Some(bloom_filter);
+                }
+                Ok(())
+            }
+            NodeEvent::Vote(peer_id, vote) => {
+                self.receive_vote(Some(peer_id), vote, peers);
+                Ok(())
+            }
+            NodeEvent::ProofRequest(request, mut sender) => {
+                self.send_proof(&request, &mut sender);
+                Ok(())
+            }
+            NodeEvent::PaymentAttempt => match self.try_new_payment() {
+                Some(hash) => {
+                    self.announce(vec![hash], None, peers);
+                    self.block_body()
+                        .and_then(|body| updater.mine_new_chain(self.chain.clone(), body))
+                }
+                None => Ok(()),
+            },
+            NodeEvent::HashrateShock(attempt_delay) => {
+                self.logger.info("mining", format_args!("Hash rate shock, mining attempt delay: {:?}", attempt_delay));
+                updater.set_attempt_delay(attempt_delay)
+            }
+            NodeEvent::PingAttempt => {
+                self.ping(peers);
+                Ok(())
+            }
+            NodeEvent::Ping(sent_at, mut sender) => {
+                if let Err(err) = sender.try_send(Message::Pong(sent_at)) {
+                    self.logger.debug("ping", format_args!("Pong not sent: {}", err));
+                }
+                Ok(())
+            }
+            NodeEvent::Pong(peer_id, sent_at) => {
+                self.receive_pong(peer_id, sent_at, peers);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The events caused by the messages of a peer. Its proof requests and pings are answered
+/// through the given sender.
+fn reception(
+    receiver: Receiver<Message>,
+    reply_sender: ConnectionSender<Message>,
+) -> impl Stream<Item = NodeEvent> + Send + Unpin {
+    receiver.filter_map(move |message| {
+        future::ready(match message {
+            Message::Chain(chain) => Some(NodeEvent::ChainRemoteUpdate(reply_sender.peer_id(), chain)),
+            Message::ProofRequest(request) => Some(NodeEvent::ProofRequest(request, reply_sender.clone())),
+            // Full nodes do not ask for proofs.
+            Message::Proof(_response) => None,
+            Message::Ping(sent_at) => Some(NodeEvent::Ping(sent_at, reply_sender.clone())),
+            Message::Pong(sent_at) => Some(NodeEvent::Pong(reply_sender.peer_id(), sent_at)),
+            Message::SendCompact => Some(NodeEvent::CompactRelayRequested(reply_sender.peer_id())),
+            Message::CompactBlock(compact_block) => {
+                Some(NodeEvent::CompactBlock(reply_sender.peer_id(), compact_block))
+            }
+            Message::GetBlockTransactions(hash, indexes) => {
+                Some(NodeEvent::BlockTransactionsRequest(hash, indexes, reply_sender.clone()))
+            }
+            Message::BlockTransactions(hash, transactions) => {
+                Some(NodeEvent::BlockTransactions(reply_sender.peer_id(), hash, transactions))
+            }
+            Message::GetChain => Some(NodeEvent::ChainRequest(reply_sender.peer_id())),
+            Message::RequestedChain(chain) => Some(NodeEvent::RequestedChain(reply_sender.peer_id(), chain)),
+            Message::Inventory(hashes) => Some(NodeEvent::Inventory(reply_sender.peer_id(), hashes)),
+            Message::GetTransactions(hashes) => Some(NodeEvent::TransactionsRequest(hashes, reply_sender.clone())),
+            Message::Transactions(transactions) => {
+                Some(NodeEvent::Transactions(reply_sender.peer_id(), transactions))
+            }
+            Message::FilterLoad(bloom_filter) => Some(NodeEvent::FilterLoaded(reply_sender.peer_id(), bloom_filter)),
+            Message::Vote(vote) => Some(NodeEvent::Vote(reply_sender.peer_id(), vote)),
+        })
+    })
+}
+
+/// The size of the given blocks sent whole, in bytes.
+fn blocks_size(blocks: &[Arc<Block>]) -> u64 {
+    blocks.iter().map(|block| block.size() as u64).sum()
+}
+
+/// The message relaying the chain to the peer, along with its size and the size of the blocks
+/// the peer does not know yet, in bytes. The chain is relayed as the given compact block if the
+/// peer asked for them and the chain extends the last one it was sent by this single block,
+/// whole otherwise.
+fn relay_message(peer: &Peer, chain: &Arc<Chain>, compact_block: Option<&Arc<CompactBlock>>) -> (Message, u64, u64) {
+    let (new_blocks, _known_blocks) = chain.diverging_blocks(&peer.last_known_chain);
+    let full_block_bytes = blocks_size(&new_blocks);
+
+    match compact_block {
+        Some(compact_block)
+            if peer.compact_relay
+                && new_blocks.len() == 1
+                && compact_block.previous_block_hash() == peer.last_known_chain.head().hash() =>
+        {
+            (Message::CompactBlock(compact_block.clone()), compact_block.size() as u64, full_block_bytes)
+        }
+        _ => (Message::Chain(chain.clone()), full_block_bytes, full_block_bytes),
+    }
+}
+
+/// The chain the compact block extends: the given chain, or its tail for a block competing
+/// with its head. None if it extends neither.
+fn parent_chain(chain: &Arc<Chain>, compact_block: &CompactBlock) -> Option<Arc<Chain>> {
+    iter::successors(Some(chain.clone()), |chain| chain.tail())
+        .take(2)
+        .find(|chain| chain.head().hash() == compact_block.previous_block_hash())
+}
+
+impl Node<Message> for PowNode {
+    type Output = ();
+
+    fn run<S>(mut self, connection_stream: S) -> Pin<Box<dyn Future<Output = ()> + Send>>
+    where
+        S: Stream<Item = Result<ConnectionEvent<Message>, netsim::error::Error>> + Send + Unpin + 'static,
+    {
+        Box::pin(async move {
+            if let Err(err) = self.route(connection_stream).await {
+                self.logger.error("node", format_args!("Stopped: {}", err));
+            }
+        })
+    }
+}
+
+/// Lets full, light and byzantine nodes coexist in the same network.
+pub enum SimulationNode {
+    Full(Box<PowNode>),
+    Light(LightNode),
+    Byzantine(ByzantineNode),
+}
+
+impl Node<Message> for SimulationNode {
+    type Output = ();
+
+    fn run<S>(self, connection_stream: S) -> Pin<Box<dyn Future<Output = ()> + Send>>
+    where
+        S: Stream<Item = Result<ConnectionEvent<Message>, netsim::error::Error>> + Send + Unpin + 'static,
+    {
+        match self {
+            SimulationNode::Full(node) => (*node).run(connection_stream),
+            SimulationNode::Light(node) => node.run(connection_stream),
+            SimulationNode::Byzantine(node) => node.run(connection_stream),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::tests::expand;
+    use crate::blockchain::pow::Nonce;
+    use crate::blockchain::Difficulty;
+    use btclike::blockchain::{Body, COINBASE_MATURITY};
+    use btclike::genesis::GenesisConfig;
+    use btclike::transaction::TxOut;
+    use netsim::clock::{Clock, MockClock, RuntimeConfig};
+    use netsim::network::transport::MPSCTransport;
+    use std::sync::Mutex;
+
+    /// A peer of the node under test, which connects to it at the given instant, then sends
+    /// it the given messages at theirs whatever the node does with the connection.
+    struct ScriptedPeer {
+        connects_at: Duration,
+        messages: Vec<(Duration, Message)>,
+    }
+
+    impl ScriptedPeer {
+        fn new(connects_at: Duration) -> ScriptedPeer {
+            ScriptedPeer {
+                connects_at,
+                messages: vec![],
+            }
+        }
+
+        fn sending(mut self, at: Duration, message: Message) -> ScriptedPeer {
+            self.messages.push((at, message));
+            self
+        }
+    }
+
+    /// A node that neither mines nor pays, only handling what its peers send it.
+    fn idle_node(genesis_chain: &Arc<Chain>) -> PowNode {
+        PowNode::new(0, genesis_chain.clone(), Duration::from_millis(100), Duration::from_secs(3600))
+            .with_hashrate_shock(Duration::ZERO, None)
+    }
+
+    /// Runs the node for the given duration of virtual time with the scripted peers, numbered
+    /// from 1, and returns the chain it ended with, along with the messages each peer received.
+    fn run_with_peers(
+        node: PowNode,
+        peers: Vec<ScriptedPeer>,
+        for_duration: Duration,
+    ) -> (Arc<Chain>, Vec<Vec<Message>>) {
+        let node_chains = NodeChains::new(node.chain.clone());
+        let node = node.with_node_chains(node_chains.clone());
+        let node_transport = MPSCTransport::new(0);
+        let received: Vec<_> = peers.iter().map(|_peer| Arc::new(Mutex::new(vec![]))).collect();
+
+        let peer_received = received.clone();
+        MockClock.runtime(&RuntimeConfig::new()).block_on(async move {
+            let start = time::Instant::now();
+            for ((peer_id, peer), received) in (1..).zip(peers).zip(peer_received) {
+                let mut transport = MPSCTransport::new(peer_id);
+                transport.include_seed(node_transport.address().clone());
+                tokio::spawn(async move {
+                    time::sleep_until(start + peer.connects_at).await;
+                    let mut connection_stream = Box::pin(transport.run());
+                    let connection = match connection_stream.next().await {
+                        Some(Ok(ConnectionEvent::Opened(connection))) => connection,
+                        _ => return,
+                    };
+                    let (mut sender, mut receiver) = connection.split();
+                    tokio::spawn(async move {
+                        while let Some(message) = receiver.next().await {
+                            received.lock().unwrap().push(message);
+                        }
+                    });
+                    for (at, message) in peer.messages {
+                        time::sleep_until(start + at).await;
+                        let _ = sender.try_send(message);
+                    }
+                    while connection_stream.next().await.is_some() {}
+                });
+            }
+
+            let _ = time::timeout(for_duration, node.run(Box::pin(node_transport.run()))).await;
+        });
+
+        let received = received.iter().map(|received| received.lock().unwrap().clone()).collect();
+        (node_chains.get(0), received)
+    }
+
+    #[test]
+    fn ignores_the_chains_of_an_evicted_peer() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let chain = expand(&expand(&genesis_chain, 1), 2);
+        // The first peer keeps sending its chain once the second one took its place.
+        let peers = || {
+            vec![
+                ScriptedPeer::new(Duration::ZERO).sending(Duration::from_secs(2), Message::Chain(chain.clone())),
+                ScriptedPeer::new(Duration::from_secs(1)),
+            ]
+        };
+
+        let node = idle_node(&genesis_chain).with_connection_limits(ConnectionLimits::new().with_max_inbound(2));
+        let (node_chain, _received) = run_with_peers(node, peers(), Duration::from_secs(3));
+        assert_eq!(2, node_chain.height());
+
+        let node = idle_node(&genesis_chain).with_connection_limits(ConnectionLimits::new().with_max_inbound(1));
+        let (node_chain, _received) = run_with_peers(node, peers(), Duration::from_secs(3));
+        assert_eq!(0, node_chain.height());
+    }
+
+    /// Expands the chain with a block paying the coinbase and the fees to the address.
+    fn next_block(chain: &Arc<Chain>, node_id: u32, address: Address, transactions: Vec<SignedTx>) -> Arc<Chain> {
+        let height = chain.height() + 1;
+        let coinbase_tx_out = TxOut::new(chain.params().block_reward(height) + transactions.len() as u32, address);
+        let block = Block::new(
+            node_id,
+            Nonce::from_bytes([node_id as u8; 8]),
+            node_id,
+            chain.head().difficulty(),
+            chain.head().hasher(),
+            chain.head().hash().clone(),
+            height,
+            &Arc::new(BlockBody::new(Body::new(coinbase_tx_out, transactions)).unwrap()),
+        );
+        Chain::expand(chain, block).unwrap()
+    }
+
+    #[test]
+    fn rebuilds_the_compact_block_of_a_banned_peer_from_another_peer() {
+        let mut wallet = Wallet::new();
+        let mut chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        chain = next_block(&chain, 1, wallet.new_address().unwrap(), vec![]);
+        wallet.connect_block(chain.head().body().body(), 1).unwrap();
+        for node_id in 2..COINBASE_MATURITY + 2 {
+            chain = expand(&chain, node_id);
+        }
+        let utxo_set = chain.validate().unwrap();
+        // The payment never reached the mempool of the node, which asks for it.
+        let payment = wallet.new_transaction(10, Address::from_hash(Hash::min()), 1, &utxo_set).unwrap();
+        let paying_chain = next_block(&chain, 1, Address::from_hash(Hash::min()), vec![payment.clone()]);
+        let compact_block = Arc::new(CompactBlock::new(paying_chain.head()).unwrap());
+        let hash = paying_chain.head().hash().clone();
+
+        // The first peer announces the block, then gets banned for sending the same chain twice
+        // before it sent the payment.
+        let peers = vec![
+            ScriptedPeer::new(Duration::ZERO)
+                .sending(Duration::from_secs(1), Message::CompactBlock(compact_block.clone()))
+                .sending(Duration::from_secs(2), Message::Chain(chain.clone()))
+                .sending(Duration::from_secs(2), Message::Chain(chain.clone())),
+            ScriptedPeer::new(Duration::ZERO)
+                .sending(Duration::from_secs(3), Message::CompactBlock(compact_block))
+                .sending(Duration::from_secs(4), Message::BlockTransactions(hash, vec![payment])),
+        ];
+
+        let node = idle_node(&chain).with_ban_policy(BanPolicy::new().with_ban_threshold(1));
+        let (node_chain, received) = run_with_peers(node, peers, Duration::from_secs(5));
+        assert_eq!(paying_chain.head().hash(), node_chain.head().hash());
+        let requests = |received: &[Message]| {
+            received.iter().filter(|message| matches!(message, Message::GetBlockTransactions(..))).count()
+        };
+        assert_eq!(vec![1, 1], received.iter().map(|received| requests(received)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn ignores_the_chains_of_another_genesis_without_penalizing_the_peer() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let foreign_genesis_chain =
+            Arc::new(Chain::init_new(Difficulty::min_difficulty()).with_genesis(GenesisConfig::new(1)));
+        let foreign_chain = expand(&expand(&expand(&foreign_genesis_chain, 1), 2), 3);
+        let chain = expand(&expand(&genesis_chain, 1), 2);
+        // Were the foreign chains received like the others, sending one twice then a weaker
+        // chain would get the peer banned at the first penalty.
+        let peers = vec![ScriptedPeer::new(Duration::ZERO)
+            .sending(Duration::from_secs(1), Message::Chain(foreign_chain.clone()))
+            .sending(Duration::from_secs(2), Message::Chain(foreign_chain))
+            .sending(Duration::from_secs(3), Message::Chain(chain))];
+
+        let node = idle_node(&genesis_chain).with_ban_policy(BanPolicy::new().with_ban_threshold(1));
+        let (node_chain, _received) = run_with_peers(node, peers, Duration::from_secs(4));
+        assert_eq!(2, node_chain.height());
+        assert!(genesis_chain.genesis_config() == node_chain.genesis_config());
+    }
+
+    /// Hands the chains over to the validation workers of the node as if the given peer sent
+    /// them, then waits for the validations, in the order of the chains.
+    async fn validate(
+        node: &mut PowNode,
+        peer_id: u32,
+        chains: &[&Arc<Chain>],
+    ) -> Vec<(u32, Arc<Chain>, ChainValidation)> {
+        for chain in chains {
+            node.receive_chain(peer_id, (*chain).clone(), &mut []);
+        }
+        let mut validations = vec![];
+        while let Some(validation) = node.validations.next().await {
+            validations.push(validation.unwrap());
+        }
+        validations.sort_by_key(|(_peer_id, chain, _validation)| {
+            chains.iter().position(|known_chain| Arc::ptr_eq(known_chain, chain))
+        });
+        validations
+    }
+
+    #[test]
+    fn applies_a_late_validation_only_if_its_chain_is_stronger() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let weak_chain = expand(&genesis_chain, 1);
+        let strong_chain = expand(&expand(&genesis_chain, 2), 3);
+        let stronger_chain = expand(&strong_chain, 4);
+
+        MockClock.runtime(&RuntimeConfig::new()).block_on(async {
+            let mut node = idle_node(&genesis_chain);
+            let (_mining_stream, updater) = mining_stream(
+                node.node_id,
+                node.chain.clone(),
+                node.block_body().unwrap(),
+                node.mining_attempt_delay,
+                None,
+                None,
+            );
+            // The chains are validated against the genesis block, and the results come back once
+            // the node adopted the strong chain.
+            let mut validations = validate(&mut node, 1, &[&weak_chain, &strong_chain, &stronger_chain]).await;
+            let mut handle = |(peer_id, chain, validation)| {
+                node.handle(NodeEvent::ChainValidated(peer_id, chain, validation), &mut vec![], &updater)
+                    .unwrap();
+                node.chain.clone()
+            };
+            let stronger = validations.pop().unwrap();
+            let strong = validations.pop().unwrap();
+            let weak = validations.pop().unwrap();
+
+            assert!(Arc::ptr_eq(&strong_chain, &handle(strong)));
+            assert!(Arc::ptr_eq(&strong_chain, &handle(weak)));
+            assert!(Arc::ptr_eq(&stronger_chain, &handle(stronger)));
+        });
+    }
+
+    #[test]
+    fn validates_a_chain_received_twice_once() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let chain = expand(&genesis_chain, 1);
+
+        MockClock.runtime(&RuntimeConfig::new()).block_on(async {
+            let mut node = idle_node(&genesis_chain);
+            node.receive_chain(1, chain.clone(), &mut []);
+            // The second peer sends it while it is validated.
+            let validations = validate(&mut node, 2, &[&chain]).await;
+            assert_eq!(1, validations.len());
+            assert_eq!(1, validations[0].0);
+        });
+    }
+
+    /// The heads of the chains in the messages.
+    fn chain_heads(messages: &[Message]) -> Vec<Hash> {
+        messages
+            .iter()
+            .filter_map(|message| match message {
+                Message::Chain(chain) => Some(chain.head().hash().clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn does_not_send_a_chain_back_to_its_peer() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let chain = expand(&genesis_chain, 1);
+        let peers = vec![
+            ScriptedPeer::new(Duration::ZERO).sending(Duration::from_secs(1), Message::Chain(chain.clone())),
+            ScriptedPeer::new(Duration::ZERO),
+        ];
+
+        let (node_chain, received) = run_with_peers(idle_node(&genesis_chain), peers, Duration::from_secs(2));
+        assert!(Arc::ptr_eq(&chain, &node_chain));
+        assert!(!chain_heads(&received[0]).contains(chain.head().hash()));
+        assert!(chain_heads(&received[1]).contains(chain.head().hash()));
+    }
+
+    #[test]
+    fn relays_a_chain_of_the_same_height_with_another_head() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let chains = [expand(&genesis_chain, 1), expand(&genesis_chain, 2)];
+        // The node adopts the chain validated first, and the other peer is known to have a chain
+        // as strong.
+        let peers = chains
+            .iter()
+            .map(|chain| {
+                ScriptedPeer::new(Duration::ZERO).sending(Duration::from_secs(1), Message::Chain(chain.clone()))
+            })
+            .collect();
 
-        Box::new(routing_future)
+        let (node_chain, received) = run_with_peers(idle_node(&genesis_chain), peers, Duration::from_secs(2));
+        let adopted = chains.iter().position(|chain| Arc::ptr_eq(chain, &node_chain)).unwrap();
+        assert!(!chain_heads(&received[adopted]).contains(node_chain.head().hash()));
+        assert!(chain_heads(&received[1 - adopted]).contains(node_chain.head().hash()));
     }
 }
@@ -0,0 +1,280 @@
+use crate::blockchain::bootstrap::percentile;
+use crate::blockchain::NodeMetric;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// Measures, from the metrics of the nodes, how long the mined blocks take to reach the honest
+/// nodes and how often these nodes see forks. A node adopting several blocks at once, with a
+/// chain received from a peer, only counts as having adopted the head one, but learns of each
+/// of them.
+pub struct PropagationStats {
+    /// The addresses of the nodes whose blocks and adoptions are measured.
+    honest_nodes: Range<u32>,
+    /// The blocks mined by the honest nodes, by hash.
+    blocks: HashMap<String, BlockPropagation>,
+    forks: u64,
+}
+
+struct BlockPropagation {
+    height: u32,
+    miner: u32,
+    mined_at: Instant,
+    /// The time it took the block to be adopted by each node, the miner included.
+    adoption_delays: Vec<Duration>,
+    /// The time it took each node to first learn of the block, adopted or not, the miner
+    /// included.
+    learning_delays: HashMap<u32, Duration>,
+}
+
+/// How long a block took to reach the honest nodes, the first time each of them learned of it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockDelays {
+    pub hash: String,
+    pub height: u32,
+    /// The honest nodes that learned of the block, its miner included.
+    pub nodes: usize,
+    /// The time the block took to reach half of the honest nodes, none if it did not.
+    pub median: Option<Duration>,
+    pub p90: Option<Duration>,
+    pub p99: Option<Duration>,
+}
+
+impl PropagationStats {
+    pub fn new(honest_nodes: Range<u32>) -> PropagationStats {
+        PropagationStats {
+            honest_nodes,
+            blocks: HashMap::new(),
+            forks: 0,
+        }
+    }
+
+    /// Records a metric published by a node now.
+    pub fn record(&mut self, metric: &NodeMetric) {
+        self.record_at(metric, Instant::now());
+    }
+
+    fn record_at(&mut self, metric: &NodeMetric, at: Instant) {
+        match *metric {
+            NodeMetric::BlockMined {
+                node_id,
+                height,
+                ref hash,
+                ..
+            } if self.honest_nodes.contains(&node_id) => {
+                self.blocks.insert(
+                    hash.clone(),
+                    BlockPropagation {
+                        height,
+                        miner: node_id,
+                        mined_at: at,
+                        adoption_delays: vec![Duration::ZERO],
+                        learning_delays: HashMap::from([(node_id, Duration::ZERO)]),
+                    },
+                );
+            }
+            NodeMetric::BlockReceived { node_id, ref hash, .. } if self.honest_nodes.contains(&node_id) => {
+                if let Some(block) = self.blocks.get_mut(hash) {
+                    let delay = at.duration_since(block.mined_at);
+                    block.learning_delays.entry(node_id).or_insert(delay);
+                }
+            }
+            NodeMetric::BlockPropagated { node_id, ref hash, .. } if self.honest_nodes.contains(&node_id) => {
+                if let Some(block) = self.blocks.get_mut(hash) {
+                    block.adoption_delays.push(at.duration_since(block.mined_at));
+                }
+            }
+            NodeMetric::Fork { node_id } if self.honest_nodes.contains(&node_id) => {
+                self.forks += 1;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn blocks_mined(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn forks(&self) -> u64 {
+        self.forks
+    }
+
+    /// The number of forks seen by the honest nodes per block they mined.
+    pub fn fork_rate(&self) -> f64 {
+        if self.blocks.is_empty() {
+            0.0
+        } else {
+            self.forks as f64 / self.blocks.len() as f64
+        }
+    }
+
+    /// The number of blocks that reached the given percentage of the honest nodes.
+    pub fn blocks_reaching(&self, percentage: u8) -> usize {
+        self.times_to_reach(percentage).len()
+    }
+
+    /// The median time the blocks took to reach the given percentage of the honest nodes,
+    /// among the ones that did. None if none did.
+    pub fn median_time_to_reach(&self, percentage: u8) -> Option<Duration> {
+        let mut times = self.times_to_reach(percentage);
+        times.sort();
+        times.get(times.len() / 2).cloned()
+    }
+
+    /// The time each block mined by the honest nodes took to reach them, by height.
+    pub fn block_delays(&self) -> Vec<BlockDelays> {
+        let mut block_delays: Vec<BlockDelays> = self
+            .blocks
+            .iter()
+            .map(|(hash, block)| {
+                let mut delays: Vec<Duration> = block.learning_delays.values().copied().collect();
+                delays.sort();
+                let reach = |percentage| delays.get(self.nodes_needed(percentage) - 1).copied();
+                BlockDelays {
+                    hash: hash.clone(),
+                    height: block.height,
+                    nodes: delays.len(),
+                    median: reach(50),
+                    p90: reach(90),
+                    p99: reach(99),
+                }
+            })
+            .collect();
+        block_delays.sort_by(|a, b| (a.height, &a.hash).cmp(&(b.height, &b.hash)));
+        block_delays
+    }
+
+    /// The time the honest nodes took to learn of the blocks mined by the others, at the
+    /// given percentile of every such delay. None if no block reached any of them.
+    pub fn learning_delay_percentile(&self, percentage: u8) -> Option<Duration> {
+        let delays = self
+            .blocks
+            .values()
+            .flat_map(|block| {
+                block
+                    .learning_delays
+                    .iter()
+                    .filter(move |(&node_id, _delay)| node_id != block.miner)
+                    .map(|(_node_id, &delay)| delay)
+            })
+            .collect();
+        percentile(delays, percentage)
+    }
+
+    /// The number of honest nodes making the given percentage of them.
+    fn nodes_needed(&self, percentage: u8) -> usize {
+        (self.honest_nodes.len() * percentage.min(100) as usize).div_ceil(100).max(1)
+    }
+
+    fn times_to_reach(&self, percentage: u8) -> Vec<Duration> {
+        let needed = self.nodes_needed(percentage);
+
+        self.blocks
+            .values()
+            .filter(|block| block.adoption_delays.len() >= needed)
+            .map(|block| {
+                let mut delays = block.adoption_delays.clone();
+                delays.sort();
+                delays[needed - 1]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mined(node_id: u32, hash: &str) -> NodeMetric {
+        NodeMetric::BlockMined {
+            node_id,
+            height: 1,
+            hash: hash.to_owned(),
+            reward: 0,
+        }
+    }
+
+    fn received(node_id: u32, hash: &str) -> NodeMetric {
+        NodeMetric::BlockReceived {
+            node_id,
+            peer_id: 0,
+            height: 1,
+            hash: hash.to_owned(),
+        }
+    }
+
+    fn propagated(node_id: u32, hash: &str) -> NodeMetric {
+        NodeMetric::BlockPropagated {
+            node_id,
+            peer_id: 0,
+            height: 1,
+            hash: hash.to_owned(),
+        }
+    }
+
+    #[test]
+    fn measures_the_propagation_to_the_honest_nodes() {
+        // Nodes 4 and 5 are sybils.
+        let mut stats = PropagationStats::new(0..4);
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        stats.record_at(&mined(0, "a"), at(0));
+        stats.record_at(&propagated(1, "a"), at(10));
+        stats.record_at(&propagated(4, "a"), at(15));
+        stats.record_at(&propagated(2, "a"), at(30));
+        stats.record_at(&mined(1, "b"), at(40));
+        stats.record_at(&mined(5, "c"), at(40));
+        stats.record_at(&propagated(0, "b"), at(60));
+        stats.record_at(&NodeMetric::Fork { node_id: 2 }, at(60));
+        stats.record_at(&NodeMetric::Fork { node_id: 5 }, at(60));
+
+        assert_eq!(2, stats.blocks_mined());
+        assert_eq!(1, stats.forks());
+        assert_eq!(0.5, stats.fork_rate());
+        assert_eq!(2, stats.blocks_reaching(50));
+        assert_eq!(Some(Duration::from_millis(20)), stats.median_time_to_reach(50));
+        assert_eq!(1, stats.blocks_reaching(75));
+        assert_eq!(Some(Duration::from_millis(30)), stats.median_time_to_reach(75));
+        assert_eq!(None, stats.median_time_to_reach(100));
+    }
+
+    #[test]
+    fn measures_when_the_honest_nodes_learn_of_each_block() {
+        let mut stats = PropagationStats::new(0..4);
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        stats.record_at(&mined(0, "a"), at(0));
+        stats.record_at(&received(1, "a"), at(10));
+        stats.record_at(&received(1, "a"), at(15));
+        stats.record_at(&received(4, "a"), at(15));
+        stats.record_at(&received(2, "a"), at(20));
+        stats.record_at(&received(3, "a"), at(40));
+        stats.record_at(&mined(1, "b"), at(40));
+        stats.record_at(&received(0, "b"), at(50));
+
+        let block_delays = stats.block_delays();
+        assert_eq!(2, block_delays.len());
+        assert_eq!(
+            BlockDelays {
+                hash: "a".to_owned(),
+                height: 1,
+                nodes: 4,
+                median: Some(Duration::from_millis(10)),
+                p90: Some(Duration::from_millis(40)),
+                p99: Some(Duration::from_millis(40)),
+            },
+            block_delays[0]
+        );
+        assert_eq!(2, block_delays[1].nodes);
+        assert_eq!(Some(Duration::from_millis(10)), block_delays[1].median);
+        assert_eq!(None, block_delays[1].p90);
+
+        // The delays of the nodes learning of a block mined by another: 10, 20 and 40ms for the
+        // first block, 10ms for the second.
+        assert_eq!(Some(Duration::from_millis(10)), stats.learning_delay_percentile(50));
+        assert_eq!(Some(Duration::from_millis(20)), stats.learning_delay_percentile(90));
+        assert_eq!(Some(Duration::from_millis(40)), stats.learning_delay_percentile(100));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::blockchain::NodeMetric;
+use btclike::utxo::BlockUndo;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// The lowest number of blocks whose bodies a pruning node keeps, which is also the deepest
+/// fork it can follow.
+pub const MIN_PRUNE_DEPTH: u32 = 6;
+
+/// The changes the last blocks of a chain made to its unspent outputs, oldest first, so that a
+/// node that dropped the bodies of the older blocks can still roll its unspent outputs back to
+/// a fork, instead of replaying every block from the genesis one.
+#[derive(Clone)]
+pub struct UndoLog {
+    depth: usize,
+    undos: VecDeque<Arc<BlockUndo>>,
+}
+
+impl UndoLog {
+    pub fn new(depth: u32) -> UndoLog {
+        UndoLog {
+            depth: depth as usize,
+            undos: VecDeque::new(),
+        }
+    }
+
+    /// Adds the undo data of the new head block, forgetting the oldest past the depth.
+    pub fn push(&mut self, undo: BlockUndo) {
+        self.undos.push_back(Arc::new(undo));
+        if self.undos.len() > self.depth {
+            self.undos.pop_front();
+        }
+    }
+
+    /// Removes the undo data of the head block.
+    pub fn pop(&mut self) -> Option<Arc<BlockUndo>> {
+        self.undos.pop_back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.undos.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.undos.is_empty()
+    }
+}
+
+/// The heights below which the full nodes pruned the bodies of their blocks. The blocks are
+/// stored once for all the nodes of the simulation, so a body is only dropped from the store
+/// once every node pruned it: below the lowest of these heights.
+#[derive(Clone)]
+pub struct PruningHorizon {
+    heights: Arc<Mutex<HashMap<u32, u32>>>,
+}
+
+impl PruningHorizon {
+    /// Every given node keeps every body until it reports otherwise.
+    pub fn new<I: IntoIterator<Item = u32>>(node_ids: I) -> PruningHorizon {
+        PruningHorizon {
+            heights: Arc::new(Mutex::new(node_ids.into_iter().map(|node_id| (node_id, 0)).collect())),
+        }
+    }
+
+    /// Records the height below which the node pruned the bodies. Returns the height below
+    /// which every node did.
+    pub fn report(&self, node_id: u32, height: u32) -> u32 {
+        let mut heights = self.heights.lock().expect("Poisoned pruning horizon");
+        heights.insert(node_id, height);
+        heights.values().copied().min().unwrap_or(height)
+    }
+}
+
+/// The bodies the pruning nodes dropped, and the proofs they could not serve for it.
+#[derive(Default)]
+pub struct PruningStats {
+    pruned_blocks: u64,
+    dropped_bodies: u64,
+    dropped_bytes: u64,
+    pruned_proof_requests: u64,
+}
+
+impl PruningStats {
+    pub fn new() -> PruningStats {
+        PruningStats::default()
+    }
+
+    pub fn record(&mut self, metric: &NodeMetric) {
+        match *metric {
+            NodeMetric::BlocksPruned {
+                blocks,
+                dropped_bodies,
+                dropped_bytes,
+                ..
+            } => {
+                self.pruned_blocks += blocks as u64;
+                self.dropped_bodies += dropped_bodies as u64;
+                self.dropped_bytes += dropped_bytes;
+            }
+            NodeMetric::ProofRequestPruned { .. } => self.pruned_proof_requests += 1,
+            _ => {}
+        }
+    }
+
+    /// The blocks of their chains the nodes pruned, once per node.
+    pub fn pruned_blocks(&self) -> u64 {
+        self.pruned_blocks
+    }
+
+    /// The bodies dropped from the store shared by the nodes, once every node pruned them.
+    pub fn dropped_bodies(&self) -> u64 {
+        self.dropped_bodies
+    }
+
+    pub fn dropped_bytes(&self) -> u64 {
+        self.dropped_bytes
+    }
+
+    pub fn pruned_proof_requests(&self) -> u64 {
+        self.pruned_proof_requests
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_the_bodies_every_node_pruned() {
+        let pruning_horizon = PruningHorizon::new(3..6);
+        assert_eq!(0, pruning_horizon.report(3, 10));
+        assert_eq!(0, pruning_horizon.report(4, 12));
+        assert_eq!(9, pruning_horizon.report(5, 9));
+        assert_eq!(10, pruning_horizon.report(5, 15));
+    }
+}
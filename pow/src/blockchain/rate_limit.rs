@@ -0,0 +1,54 @@
+use std::time::Instant;
+
+/// Limits the messages of a peer to a rate, letting through bursts of up to one second of
+/// messages: every message takes a token, and the tokens come back at the rate.
+pub struct TokenBucket {
+    /// The tokens given back per second, which is also the capacity of the bucket.
+    rate: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket.
+    pub fn new(rate: u32, now: Instant) -> TokenBucket {
+        TokenBucket {
+            rate: rate as f64,
+            tokens: rate as f64,
+            refilled_at: now,
+        }
+    }
+
+    /// Takes a token if there is one left.
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn lets_a_burst_through_then_the_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(4, start);
+
+        assert_eq!(4, (0..10).filter(|_i| bucket.try_take(start)).count());
+        assert!(!bucket.try_take(start + Duration::from_millis(200)));
+        assert!(bucket.try_take(start + Duration::from_millis(250)));
+        // The bucket does not fill up beyond its capacity.
+        let later = start + Duration::from_secs(10);
+        assert_eq!(4, (0..10).filter(|_i| bucket.try_take(later)).count());
+    }
+}
@@ -0,0 +1,157 @@
+use crate::blockchain::pow::Nonce;
+use crate::blockchain::Block;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+/// The fields identifying the work of a miner: a node should never try the same nonce and extra
+/// nonce twice on top of the same parent.
+type MiningTuple = (u32, Nonce, u32, Vec<u8>);
+
+/// A debug oracle shared by all the nodes of a network. It records the hash of every block mined
+/// during a run and flags:
+/// * hash collisions: the same hash produced by blocks with different fields,
+/// * duplicated (node_id, nonce, extra nonce, parent) tuples: a node mining the same nonce twice on top of
+///   the same parent.
+///
+/// Both should never happen, this is a cross-check on the hashing and nonce management.
+#[derive(Clone, Default)]
+pub struct HashRegistry {
+    inner: Arc<Mutex<RegistryState>>,
+}
+
+#[derive(Default)]
+struct RegistryState {
+    /// The fields of every recorded block, including the body hash, by block hash.
+    blocks: HashMap<Vec<u8>, (MiningTuple, Vec<u8>)>,
+    tuples: HashSet<MiningTuple>,
+    collisions: usize,
+    duplicated_tuples: usize,
+}
+
+impl HashRegistry {
+    pub fn new() -> HashRegistry {
+        HashRegistry::default()
+    }
+
+    /// Records a newly mined block.
+    pub fn record(&self, block: &Block) {
+        let tuple = (
+            block.node_id,
+            block.nonce.clone(),
+            block.extra_nonce,
+            block.previous_block_hash.as_ref().to_vec(),
+        );
+        let body_hash = block.body.hash_bytes().to_vec();
+
+        let mut state = self.inner.lock().expect("Poisoned hash registry");
+
+        if !state.tuples.insert(tuple.clone()) {
+            error!(
+                "[#{:05}] Duplicated mining tuple: nonce {:?} on top of {:?}",
+                block.node_id, block.nonce, block.previous_block_hash
+            );
+            state.duplicated_tuples += 1;
+        }
+
+        let hash = block.hash().as_ref().to_vec();
+        let collides = match state.blocks.get(&hash) {
+            Some(recorded) => recorded.0 != tuple || recorded.1 != body_hash,
+            None => false,
+        };
+
+        if collides {
+            error!(
+                "[#{:05}] Hash collision: {}",
+                block.node_id,
+                block.hash()
+            );
+            state.collisions += 1;
+        } else {
+            state.blocks.insert(hash, (tuple, body_hash));
+        }
+    }
+
+    /// The number of distinct blocks recorded.
+    pub fn len(&self) -> usize {
+        self.inner.lock().expect("Poisoned hash registry").blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn collisions(&self) -> usize {
+        self.inner.lock().expect("Poisoned hash registry").collisions
+    }
+
+    pub fn duplicated_tuples(&self) -> usize {
+        self.inner
+            .lock()
+            .expect("Poisoned hash registry")
+            .duplicated_tuples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{BlockBody, Difficulty};
+    use btclike::blockchain::{Body, COINBASE_AMOUNT};
+    use btclike::crypto::{self, Hasher};
+    use btclike::genesis::GenesisConfig;
+    use btclike::transaction::{Address, TxOut};
+
+    #[test]
+    fn accepts_distinct_blocks() {
+        let registry = HashRegistry::new();
+        let genesis =
+            Block::genesis_block(Arc::new(Difficulty::min_difficulty()), Hasher::Sha256, &GenesisConfig::default());
+
+        let mut nonce = Nonce::new();
+        for _i in 0..10 {
+            nonce.increment().unwrap();
+            registry.record(&child_block(&genesis, nonce.clone(), COINBASE_AMOUNT));
+        }
+
+        assert_eq!(10, registry.len());
+        assert_eq!(0, registry.collisions());
+        assert_eq!(0, registry.duplicated_tuples());
+    }
+
+    #[test]
+    fn flags_duplicated_tuples_and_collisions() {
+        let registry = HashRegistry::new();
+        let genesis =
+            Block::genesis_block(Arc::new(Difficulty::min_difficulty()), Hasher::Sha256, &GenesisConfig::default());
+
+        registry.record(&child_block(&genesis, Nonce::new(), COINBASE_AMOUNT));
+        let mut other_body_block = child_block(&genesis, Nonce::new(), COINBASE_AMOUNT + 1);
+        registry.record(&other_body_block);
+        assert_eq!(1, registry.duplicated_tuples());
+        assert_eq!(0, registry.collisions());
+
+        // Forge a block with the same hash as the first one but different fields.
+        let mut nonce = Nonce::new();
+        nonce.increment().unwrap();
+        other_body_block.nonce = nonce;
+        other_body_block.hash = child_block(&genesis, Nonce::new(), COINBASE_AMOUNT).hash;
+        registry.record(&other_body_block);
+        assert_eq!(1, registry.collisions());
+        assert_eq!(2, registry.len());
+    }
+
+    fn child_block(parent: &Block, nonce: Nonce, coinbase_amount: u32) -> Block {
+        let burn_address = Address::from_hash(crypto::Hash::min());
+        let body = Body::new(TxOut::new(coinbase_amount, burn_address), vec![]);
+        Block::new(
+            1,
+            nonce,
+            0,
+            &parent.difficulty,
+            parent.hasher,
+            parent.hash().clone(),
+            parent.height + 1,
+            &Arc::new(BlockBody::new(body).unwrap()),
+        )
+    }
+}
@@ -0,0 +1,156 @@
+use crate::blockchain::{Message, NodeMetric};
+use netsim::network::WireEncoder;
+
+/// The bytes the nodes sent to relay blocks, compared with the bytes the whole blocks would
+/// have taken, and how the compact blocks were received.
+#[derive(Default)]
+pub struct RelayStats {
+    bytes: u64,
+    full_block_bytes: u64,
+    reconstructed_blocks: u64,
+    /// The reconstructed blocks that needed transactions missing from the mempool.
+    incomplete_blocks: u64,
+    requested_transactions: u64,
+    fallbacks: u64,
+}
+
+impl RelayStats {
+    pub fn new() -> RelayStats {
+        RelayStats::default()
+    }
+
+    pub fn record(&mut self, metric: &NodeMetric) {
+        match *metric {
+            NodeMetric::BlockRelayed {
+                bytes,
+                full_block_bytes,
+                ..
+            } => {
+                self.bytes += bytes;
+                self.full_block_bytes += full_block_bytes;
+            }
+            NodeMetric::CompactBlockReconstructed {
+                missing_transactions, ..
+            } => {
+                self.reconstructed_blocks += 1;
+                if missing_transactions > 0 {
+                    self.incomplete_blocks += 1;
+                    self.requested_transactions += missing_transactions as u64;
+                }
+            }
+            NodeMetric::CompactBlockFallback { .. } => self.fallbacks += 1,
+            _ => {}
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn full_block_bytes(&self) -> u64 {
+        self.full_block_bytes
+    }
+
+    /// The share of the full block bytes that was saved, in percent. Negative if more bytes
+    /// were sent, none if no block was relayed.
+    pub fn savings(&self) -> Option<f64> {
+        if self.full_block_bytes == 0 {
+            None
+        } else {
+            Some(100.0 * (1.0 - self.bytes as f64 / self.full_block_bytes as f64))
+        }
+    }
+
+    pub fn reconstructed_blocks(&self) -> u64 {
+        self.reconstructed_blocks
+    }
+
+    pub fn incomplete_blocks(&self) -> u64 {
+        self.incomplete_blocks
+    }
+
+    pub fn requested_transactions(&self) -> u64 {
+        self.requested_transactions
+    }
+
+    pub fn fallbacks(&self) -> u64 {
+        self.fallbacks
+    }
+}
+
+/// Serializes the blocks the nodes relay to each other, to measure the bandwidth they take
+/// once compressed: a chain as the body of its head block, the block it announces, and the
+/// transactions asked for by a compact block. The other messages are left out, the short ids
+/// of the compact blocks being hashes that hardly compress.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BlockEncoder;
+
+impl WireEncoder<Message> for BlockEncoder {
+    fn encode(&self, message: &Message) -> Option<Vec<u8>> {
+        match message {
+            Message::Chain(chain) | Message::RequestedChain(chain) => {
+                bincode::serialize(chain.head().body().body()).ok()
+            }
+            Message::BlockTransactions(_hash, transactions) => bincode::serialize(transactions).ok(),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::tests::expand;
+    use crate::blockchain::{Chain, Difficulty};
+    use std::sync::Arc;
+
+    #[test]
+    fn compares_the_relayed_bytes_with_the_full_blocks() {
+        let mut stats = RelayStats::new();
+        assert_eq!(None, stats.savings());
+
+        for metric in [
+            NodeMetric::BlockRelayed {
+                node_id: 1,
+                bytes: 200,
+                full_block_bytes: 1000,
+            },
+            NodeMetric::BlockRelayed {
+                node_id: 2,
+                bytes: 300,
+                full_block_bytes: 0,
+            },
+            NodeMetric::CompactBlockReconstructed {
+                node_id: 2,
+                missing_transactions: 0,
+            },
+            NodeMetric::CompactBlockReconstructed {
+                node_id: 3,
+                missing_transactions: 4,
+            },
+            NodeMetric::CompactBlockFallback { node_id: 3 },
+            NodeMetric::Fork { node_id: 3 },
+        ] {
+            stats.record(&metric);
+        }
+
+        assert_eq!(500, stats.bytes());
+        assert_eq!(1000, stats.full_block_bytes());
+        assert_eq!(Some(50.0), stats.savings());
+        assert_eq!(2, stats.reconstructed_blocks());
+        assert_eq!(1, stats.incomplete_blocks());
+        assert_eq!(4, stats.requested_transactions());
+        assert_eq!(1, stats.fallbacks());
+    }
+
+    #[test]
+    fn encodes_the_head_block_of_the_chains() {
+        let genesis = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let chain = expand(&expand(&genesis, 1), 2);
+
+        let encoded = BlockEncoder.encode(&Message::Chain(chain.clone())).unwrap();
+        assert_eq!(bincode::serialize(chain.head().body().body()).unwrap(), encoded);
+        assert_eq!(Some(encoded), BlockEncoder.encode(&Message::RequestedChain(chain)));
+        assert_eq!(None, BlockEncoder.encode(&Message::GetChain));
+    }
+}
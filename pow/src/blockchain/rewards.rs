@@ -0,0 +1,159 @@
+use crate::blockchain::NodeMetric;
+use std::collections::{BTreeMap, HashMap};
+
+/// How the coinbase rewards of the mined blocks are distributed among the miners, compared
+/// with how their hash rates, or their stakes, are. Luck makes the rewards deviate from the
+/// expected ones, the more so that the blocks are few.
+///
+/// Every mined block counts, the stale ones included: the report measures the variance of
+/// the mining, not the blocks each miner kept in the strongest chain.
+pub struct RewardStats {
+    /// The share of the blocks each miner is expected to mine, up to a factor.
+    weights: BTreeMap<u32, f64>,
+    rewards: HashMap<u32, u64>,
+    mined_blocks: u64,
+}
+
+impl RewardStats {
+    /// Accounts the rewards of the given miners, along with their weights: their average hash
+    /// rates or their stakes.
+    pub fn new(weights: BTreeMap<u32, f64>) -> RewardStats {
+        RewardStats {
+            weights,
+            rewards: HashMap::new(),
+            mined_blocks: 0,
+        }
+    }
+
+    pub fn record(&mut self, metric: &NodeMetric) {
+        if let NodeMetric::BlockMined { node_id, reward, .. } = *metric {
+            if self.weights.contains_key(&node_id) {
+                *self.rewards.entry(node_id).or_insert(0) += u64::from(reward);
+                self.mined_blocks += 1;
+            }
+        }
+    }
+
+    pub fn miners(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// The miners that mined at least one block.
+    pub fn rewarded_miners(&self) -> usize {
+        self.rewards.len()
+    }
+
+    pub fn mined_blocks(&self) -> u64 {
+        self.mined_blocks
+    }
+
+    pub fn total_rewards(&self) -> u64 {
+        self.rewards.values().sum()
+    }
+
+    /// The Gini coefficient of the rewards of the miners: 0 if they all collected as much, close
+    /// to 1 if a single one collected everything. None before the first reward.
+    pub fn gini(&self) -> Option<f64> {
+        gini(&self.weights.keys().map(|node_id| self.reward(*node_id)).collect::<Vec<f64>>())
+    }
+
+    /// The Gini coefficient of the weights of the miners, what the one of the rewards tends to.
+    pub fn weight_gini(&self) -> Option<f64> {
+        gini(&self.weights.values().copied().collect::<Vec<f64>>())
+    }
+
+    /// The share of the rewards the given number of best rewarded miners collected, and their
+    /// share of the weights, none before the first reward.
+    pub fn top_share(&self, miners: usize) -> Option<(f64, f64)> {
+        let total_rewards = self.total_rewards() as f64;
+        let total_weight: f64 = self.weights.values().sum();
+        if total_rewards == 0.0 || total_weight == 0.0 {
+            return None;
+        }
+
+        let mut miners_by_reward: Vec<(f64, f64)> =
+            self.weights.iter().map(|(node_id, &weight)| (self.reward(*node_id), weight)).collect();
+        miners_by_reward.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let (rewards, weights) = miners_by_reward
+            .iter()
+            .take(miners)
+            .fold((0.0, 0.0), |(rewards, weights), (reward, weight)| (rewards + reward, weights + weight));
+        Some((rewards / total_rewards, weights / total_weight))
+    }
+
+    /// The largest difference between the share of the rewards a miner collected and its share
+    /// of the weights, none before the first reward.
+    pub fn max_share_deviation(&self) -> Option<f64> {
+        let total_rewards = self.total_rewards() as f64;
+        let total_weight: f64 = self.weights.values().sum();
+        if total_rewards == 0.0 || total_weight == 0.0 {
+            return None;
+        }
+
+        self.weights
+            .iter()
+            .map(|(node_id, &weight)| (self.reward(*node_id) / total_rewards - weight / total_weight).abs())
+            .reduce(f64::max)
+    }
+
+    fn reward(&self, node_id: u32) -> f64 {
+        self.rewards.get(&node_id).copied().unwrap_or(0) as f64
+    }
+}
+
+/// The Gini coefficient of the given values, none if they are all null.
+fn gini(values: &[f64]) -> Option<f64> {
+    let total: f64 = values.iter().sum();
+    if total <= 0.0 {
+        return None;
+    }
+
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let count = sorted.len() as f64;
+    let weighted_sum: f64 = sorted.iter().enumerate().map(|(index, value)| (index + 1) as f64 * value).sum();
+    Some(2.0 * weighted_sum / (count * total) - (count + 1.0) / count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mined(node_id: u32, reward: u32) -> NodeMetric {
+        NodeMetric::BlockMined {
+            node_id,
+            height: 1,
+            hash: String::new(),
+            reward,
+        }
+    }
+
+    #[test]
+    fn compares_the_rewards_with_the_weights_of_the_miners() {
+        let mut stats = RewardStats::new([(0, 1.0), (1, 1.0), (2, 1.0), (3, 1.0)].into_iter().collect());
+        assert_eq!(None, stats.gini());
+        assert_eq!(Some(0.0), stats.weight_gini());
+        assert_eq!(None, stats.top_share(1));
+
+        for metric in [mined(0, 50), mined(0, 50), mined(1, 100), mined(4, 100), NodeMetric::Fork { node_id: 2 }] {
+            stats.record(&metric);
+        }
+
+        assert_eq!(4, stats.miners());
+        assert_eq!(2, stats.rewarded_miners());
+        assert_eq!(3, stats.mined_blocks());
+        assert_eq!(200, stats.total_rewards());
+        assert_eq!(Some(0.5), stats.gini());
+        assert_eq!(Some((0.5, 0.25)), stats.top_share(1));
+        assert_eq!(Some((1.0, 1.0)), stats.top_share(10));
+        assert_eq!(Some(0.25), stats.max_share_deviation());
+    }
+
+    #[test]
+    fn measures_the_inequality_of_the_values() {
+        assert_eq!(None, gini(&[]));
+        assert_eq!(None, gini(&[0.0, 0.0]));
+        assert_eq!(Some(0.0), gini(&[3.0, 3.0, 3.0]));
+        assert_eq!(Some(0.75), gini(&[0.0, 0.0, 0.0, 8.0]));
+    }
+}
@@ -0,0 +1,133 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// The default misbehavior score from which a peer is banned.
+pub const DEFAULT_BAN_THRESHOLD: u32 = 100;
+/// The default duration of a ban.
+pub const DEFAULT_BAN_DURATION: Duration = Duration::from_secs(60);
+
+/// What a peer can do wrong, from the worst to the most benign.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Misbehavior {
+    /// A chain breaking the consensus rules.
+    InvalidChain,
+    /// The very chain the peer sent last, which an honest peer never sends twice.
+    Spam,
+    /// A chain no stronger than one the peer sent before. The latency of the connection may
+    /// reorder the chains of an honest peer, so it weighs little.
+    StaleData,
+}
+
+impl Misbehavior {
+    /// How much the misbehavior adds to the score of the peer.
+    pub fn score(self) -> u32 {
+        match self {
+            Misbehavior::InvalidChain => 20,
+            Misbehavior::Spam => 10,
+            Misbehavior::StaleData => 1,
+        }
+    }
+}
+
+/// When and for how long a node bans its peers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BanPolicy {
+    ban_threshold: u32,
+    ban_duration: Duration,
+}
+
+impl Default for BanPolicy {
+    fn default() -> BanPolicy {
+        BanPolicy::new()
+    }
+}
+
+impl BanPolicy {
+    pub fn new() -> BanPolicy {
+        BanPolicy {
+            ban_threshold: DEFAULT_BAN_THRESHOLD,
+            ban_duration: DEFAULT_BAN_DURATION,
+        }
+    }
+
+    /// Bans the peers once their misbehavior score reaches the given one.
+    pub fn with_ban_threshold(mut self, ban_threshold: u32) -> BanPolicy {
+        self.ban_threshold = ban_threshold;
+        self
+    }
+
+    pub fn with_ban_duration(mut self, ban_duration: Duration) -> BanPolicy {
+        self.ban_duration = ban_duration;
+        self
+    }
+}
+
+/// The misbehavior scores of the peers of a node, and the peers it banned. A ban forgives
+/// the past misbehavior of the peer, which starts from a null score once the ban is over.
+pub struct PeerScores {
+    policy: BanPolicy,
+    scores: HashMap<u32, u32>,
+    /// The end of the ban of every banned peer, by address.
+    bans: HashMap<u32, Instant>,
+}
+
+impl PeerScores {
+    pub fn new(policy: BanPolicy) -> PeerScores {
+        PeerScores {
+            policy,
+            scores: HashMap::new(),
+            bans: HashMap::new(),
+        }
+    }
+
+    /// Adds the misbehavior to the score of the peer. Returns whether the peer is banned from
+    /// now on.
+    pub fn penalize(&mut self, peer_id: u32, misbehavior: Misbehavior, now: Instant) -> bool {
+        let score = self.scores.entry(peer_id).or_insert(0);
+        *score = score.saturating_add(misbehavior.score());
+
+        if *score >= self.policy.ban_threshold {
+            self.scores.remove(&peer_id);
+            self.bans.insert(peer_id, now + self.policy.ban_duration);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn score(&self, peer_id: u32) -> u32 {
+        self.scores.get(&peer_id).cloned().unwrap_or(0)
+    }
+
+    pub fn is_banned(&self, peer_id: u32, now: Instant) -> bool {
+        self.bans.get(&peer_id).is_some_and(|until| *until > now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bans_the_peers_reaching_the_threshold_for_a_while() {
+        let policy = BanPolicy::new()
+            .with_ban_threshold(30)
+            .with_ban_duration(Duration::from_secs(10));
+        let mut scores = PeerScores::new(policy);
+        let start = Instant::now();
+
+        assert!(!scores.penalize(1, Misbehavior::InvalidChain, start));
+        assert!(!scores.penalize(2, Misbehavior::StaleData, start));
+        assert!(!scores.penalize(1, Misbehavior::StaleData, start));
+        assert_eq!(21, scores.score(1));
+        assert!(scores.penalize(1, Misbehavior::Spam, start));
+
+        assert!(scores.is_banned(1, start + Duration::from_secs(9)));
+        assert!(!scores.is_banned(2, start));
+        assert!(!scores.is_banned(1, start + Duration::from_secs(10)));
+        assert_eq!(0, scores.score(1));
+        assert_eq!(1, scores.score(2));
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+
+/// The last items a node saw, transactions or chains, so that it handles each of them once.
+/// Past the capacity, the oldest ones are forgotten.
+pub struct SeenSet<T> {
+    capacity: usize,
+    items: HashSet<T>,
+    /// The same items, the oldest first.
+    order: VecDeque<T>,
+}
+
+impl<T: Hash + Eq + Clone> SeenSet<T> {
+    pub fn new(capacity: usize) -> SeenSet<T> {
+        SeenSet {
+            capacity,
+            items: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Remembers the item. Returns whether it was not seen before.
+    pub fn insert(&mut self, item: &T) -> bool {
+        if !self.items.insert(item.clone()) {
+            return false;
+        }
+
+        self.order.push_back(item.clone());
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.items.remove(&oldest);
+            }
+        }
+        true
+    }
+
+    pub fn contains(&self, item: &T) -> bool {
+        self.items.contains(item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_the_first_seen_items_even_if_seen_again() {
+        let mut seen_chains = SeenSet::new(2);
+        assert!(seen_chains.is_empty());
+        assert!(seen_chains.insert(&1));
+        assert!(seen_chains.insert(&2));
+        assert!(!seen_chains.insert(&1));
+        assert!(seen_chains.insert(&3));
+
+        assert_eq!(2, seen_chains.len());
+        assert!(!seen_chains.contains(&1));
+        assert!(seen_chains.contains(&2));
+        assert!(seen_chains.contains(&3));
+    }
+}
@@ -0,0 +1,244 @@
+use crate::blockchain::NodeMetric;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// A sudden change of the hash rate of some of the miners during a simulation: from `at_secs`
+/// on, the first `miners` share of the honest full nodes mine at `hashrate` times their
+/// initial rate, 0 to stop mining.
+///
+/// Parsed from `AT_SECS:MINERS:HASHRATE`, `60:0.5:0` for half of the miners to stop after a
+/// minute for instance.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct HashrateShock {
+    pub at_secs: f64,
+    pub miners: f64,
+    pub hashrate: f64,
+}
+
+impl HashrateShock {
+    /// When the shock happens, from the start of the simulation.
+    pub fn at(&self) -> Duration {
+        Duration::from_secs_f64(self.at_secs)
+    }
+
+    /// The number of the given miners the shock hits, the first ones.
+    pub fn hit_miners(&self, miners: u32) -> u32 {
+        (f64::from(miners) * self.miners).round() as u32
+    }
+
+    /// The delay between two mining attempts of a hit miner once the shock happened, none if
+    /// it stops mining.
+    pub fn attempt_delay(&self, mining_attempt_delay: Duration) -> Option<Duration> {
+        if self.hashrate > 0.0 {
+            Some(mining_attempt_delay.div_f64(self.hashrate))
+        } else {
+            None
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.at_secs.is_finite() && self.at_secs >= 0.0) {
+            Err(format!("Invalid hash rate shock time: {}", self.at_secs))
+        } else if !(0.0..=1.0).contains(&self.miners) {
+            Err(format!("Invalid share of miners hit by a hash rate shock: {}", self.miners))
+        } else if !(0.0..=1000.0).contains(&self.hashrate) {
+            Err(format!("Invalid hash rate factor of a shock: {}", self.hashrate))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl FromStr for HashrateShock {
+    type Err = String;
+
+    fn from_str(raw_shock: &str) -> Result<HashrateShock, String> {
+        let invalid = || format!("Invalid hash rate shock {}, expected AT_SECS:MINERS:HASHRATE", raw_shock);
+        let values = raw_shock
+            .split(':')
+            .map(|value| value.parse::<f64>().map_err(|_err| invalid()))
+            .collect::<Result<Vec<f64>, String>>()?;
+        match values[..] {
+            [at_secs, miners, hashrate] => {
+                let shock = HashrateShock {
+                    at_secs,
+                    miners,
+                    hashrate,
+                };
+                shock.validate()?;
+                Ok(shock)
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The hash rate of a miner averaged over the given duration, relative to its initial one. The
+/// shocks hitting it are given as when they happen and its delay between two mining attempts
+/// from then on, none if it stops mining.
+pub fn average_hashrate(
+    mining_attempt_delay: Duration,
+    shocks: &[(Duration, Option<Duration>)],
+    duration: Duration,
+) -> f64 {
+    if duration.is_zero() {
+        return 1.0;
+    }
+
+    let mut shocks = shocks.to_vec();
+    shocks.sort_by_key(|&(at, _attempt_delay)| at);
+    let mut hashrate = 1.0;
+    let mut since = Duration::ZERO;
+    let mut total = 0.0;
+    for (at, attempt_delay) in shocks {
+        let at = at.min(duration);
+        total += hashrate * (at - since).as_secs_f64();
+        since = at;
+        hashrate = attempt_delay.map_or(0.0, |attempt_delay| {
+            mining_attempt_delay.as_secs_f64() / attempt_delay.as_secs_f64()
+        });
+    }
+    total += hashrate * (duration - since).as_secs_f64();
+    total / duration.as_secs_f64()
+}
+
+/// Measures the intervals between the blocks, the first one mined at each height, in each
+/// phase of a simulation: before the first hash rate shock, then from each shock to the next.
+pub struct BlockIntervalStats {
+    start: Instant,
+    /// When each phase starts, from the start.
+    phases: Vec<Duration>,
+    /// When the first block of each height was mined, from the start.
+    first_mined: BTreeMap<u32, Duration>,
+}
+
+/// The blocks mined during a phase of a simulation, and the intervals between them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PhaseIntervals {
+    /// When the phase starts, from the start of the simulation.
+    pub start: Duration,
+    pub blocks: u64,
+    pub mean: Duration,
+    pub standard_deviation: Duration,
+}
+
+impl BlockIntervalStats {
+    /// Measures the phases split by the given shocks, from now on.
+    pub fn new(shocks: &[HashrateShock]) -> BlockIntervalStats {
+        let mut phases: Vec<Duration> = shocks.iter().map(HashrateShock::at).collect();
+        phases.push(Duration::ZERO);
+        phases.sort();
+        phases.dedup();
+        BlockIntervalStats {
+            start: Instant::now(),
+            phases,
+            first_mined: BTreeMap::new(),
+        }
+    }
+
+    /// Records a metric published by a node now.
+    pub fn record(&mut self, metric: &NodeMetric) {
+        self.record_at(metric, Instant::now());
+    }
+
+    fn record_at(&mut self, metric: &NodeMetric, at: Instant) {
+        if let NodeMetric::BlockMined { height, .. } = *metric {
+            let elapsed = at.saturating_duration_since(self.start);
+            self.first_mined.entry(height).or_insert(elapsed);
+        }
+    }
+
+    /// The intervals of each phase, in order. An interval belongs to the phase its block was
+    /// mined in.
+    pub fn phases(&self) -> Vec<PhaseIntervals> {
+        let mut mined_at: Vec<Duration> = self.first_mined.values().copied().collect();
+        mined_at.sort();
+
+        self.phases
+            .iter()
+            .enumerate()
+            .map(|(index, &start)| {
+                let end = self.phases.get(index + 1).copied().unwrap_or(Duration::MAX);
+                let intervals: Vec<f64> = mined_at
+                    .windows(2)
+                    .filter(|pair| start <= pair[1] && pair[1] < end)
+                    .map(|pair| (pair[1] - pair[0]).as_secs_f64())
+                    .collect();
+                let count = intervals.len().max(1) as f64;
+                let mean = intervals.iter().sum::<f64>() / count;
+                let variance = intervals.iter().map(|interval| (interval - mean).powi(2)).sum::<f64>() / count;
+                PhaseIntervals {
+                    start,
+                    blocks: intervals.len() as u64,
+                    mean: Duration::from_secs_f64(mean),
+                    standard_deviation: Duration::from_secs_f64(variance.sqrt()),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_hashrate_shock() {
+        let shock: HashrateShock = "60:0.5:0".parse().unwrap();
+        assert_eq!(Duration::from_secs(60), shock.at());
+        assert_eq!(5, shock.hit_miners(10));
+        assert_eq!(None, shock.attempt_delay(Duration::from_millis(100)));
+        let shock: HashrateShock = "0:1:2".parse().unwrap();
+        assert_eq!(Some(Duration::from_millis(50)), shock.attempt_delay(Duration::from_millis(100)));
+
+        assert!("60:0.5".parse::<HashrateShock>().is_err());
+        assert!("60:1.5:0".parse::<HashrateShock>().is_err());
+        assert!("60:half:0".parse::<HashrateShock>().is_err());
+    }
+
+    #[test]
+    fn averages_the_hashrate_over_the_shocks() {
+        let delay = Duration::from_millis(100);
+        let duration = Duration::from_secs(100);
+        assert_eq!(1.0, average_hashrate(delay, &[], duration));
+
+        let shocks = [
+            (Duration::from_secs(50), Some(Duration::from_millis(50))),
+            (Duration::from_secs(25), None),
+            (Duration::from_secs(200), Some(delay)),
+        ];
+        assert_eq!(1.25, average_hashrate(delay, &shocks, duration));
+    }
+
+    #[test]
+    fn measures_the_block_intervals_of_each_phase() {
+        let shock = HashrateShock {
+            at_secs: 10.0,
+            miners: 0.5,
+            hashrate: 0.0,
+        };
+        let mut stats = BlockIntervalStats::new(&[shock]);
+        let start = stats.start;
+        let mined = |height| NodeMetric::BlockMined {
+            node_id: 0,
+            height,
+            hash: height.to_string(),
+            reward: 0,
+        };
+        for (height, secs) in [(1, 2), (2, 4), (3, 6), (3, 7), (4, 12), (5, 20)] {
+            stats.record_at(&mined(height), start + Duration::from_secs(secs));
+        }
+
+        let phases = stats.phases();
+        assert_eq!(2, phases.len());
+        assert_eq!(2, phases[0].blocks);
+        assert_eq!(Duration::from_secs(2), phases[0].mean);
+        assert_eq!(Duration::ZERO, phases[0].standard_deviation);
+        assert_eq!(Duration::from_secs(10), phases[1].start);
+        assert_eq!(2, phases[1].blocks);
+        assert_eq!(Duration::from_secs(7), phases[1].mean);
+        assert_eq!(Duration::from_secs(1), phases[1].standard_deviation);
+    }
+}
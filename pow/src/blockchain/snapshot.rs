@@ -0,0 +1,326 @@
+use crate::blockchain::pow::Nonce;
+use crate::blockchain::Difficulty;
+use crate::blockchain::{Block, BlockBody, Chain};
+use crate::error::Error;
+use btclike::blockchain::Body;
+use btclike::crypto::{self, Hasher, SignatureAlgorithm};
+use btclike::utxo::UtxoSet;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Changed whenever the format of the snapshots changes, so that older ones are rejected.
+const SNAPSHOT_VERSION: u32 = 2;
+/// Same as `SNAPSHOT_VERSION`, for the UTXO snapshots.
+const UTXO_SNAPSHOT_VERSION: u32 = 2;
+
+/// The blocks of a chain, oldest first, along with the parameters of its genesis block. The
+/// hashes are not stored: they are computed again when the chain is loaded, and the chain is
+/// validated like one received from a peer.
+#[derive(Serialize, Deserialize)]
+struct ChainSnapshot {
+    version: u32,
+    difficulty: Difficulty,
+    hasher: Hasher,
+    signature_algorithm: SignatureAlgorithm,
+    /// Every block but the genesis one, which is derived from the parameters above.
+    blocks: Vec<BlockRecord>,
+}
+
+/// The fields of a block that cannot be derived from its parent.
+#[derive(Serialize, Deserialize)]
+struct BlockRecord {
+    node_id: u32,
+    nonce: [u8; 8],
+    extra_nonce: u32,
+    body: Body,
+}
+
+/// The headers of a chain, oldest first, along with its unspent outputs once its head block is
+/// applied: a node starting from it only validates the blocks mined after the head, trusting
+/// the unspent outputs, as with Bitcoin's assumeutxo.
+#[derive(Serialize, Deserialize)]
+struct UtxoSnapshot {
+    version: u32,
+    difficulty: Difficulty,
+    hasher: Hasher,
+    signature_algorithm: SignatureAlgorithm,
+    /// The headers of every block but the genesis one.
+    headers: Vec<HeaderRecord>,
+    utxo_set: UtxoSet,
+}
+
+/// The fields of a block header that cannot be derived from its parent.
+#[derive(Serialize, Deserialize)]
+struct HeaderRecord {
+    node_id: u32,
+    nonce: [u8; 8],
+    extra_nonce: u32,
+    merkle_root: crypto::Hash,
+}
+
+impl Chain {
+    /// Writes the blocks of this chain to a file, in bincode, overwriting it.
+    pub fn write_snapshot<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let bytes = self.encode_snapshot()?;
+        fs::write(path, bytes).map_err(|err| Error::Snapshot(err.to_string()))
+    }
+
+    /// Same as `write_snapshot`, to the content of the file.
+    pub fn encode_snapshot(&self) -> Result<Vec<u8>, Error> {
+        let mut blocks: Vec<BlockRecord> = self
+            .blocks()
+            .take_while(|block| block.height > 0)
+            .map(|block| BlockRecord {
+                node_id: block.node_id,
+                nonce: *block.nonce.bytes(),
+                extra_nonce: block.extra_nonce,
+                body: block.body.body().clone(),
+            })
+            .collect();
+        blocks.reverse();
+
+        let snapshot = ChainSnapshot {
+            version: SNAPSHOT_VERSION,
+            difficulty: (*self.head.difficulty).clone(),
+            hasher: self.head.hasher,
+            signature_algorithm: self.signature_algorithm,
+            blocks,
+        };
+        bincode::serialize(&snapshot).map_err(|err| Error::Snapshot(err.to_string()))
+    }
+
+    /// Rebuilds the chain written to a file by `write_snapshot`, then validates it from the
+    /// genesis block.
+    pub fn read_snapshot<P: AsRef<Path>>(path: P) -> Result<Arc<Chain>, Error> {
+        let bytes = fs::read(path).map_err(|err| Error::Snapshot(err.to_string()))?;
+        Chain::decode_snapshot(&bytes)
+    }
+
+    /// Same as `read_snapshot`, from the content of the file. Any bytes are rejected with an
+    /// error rather than a panic.
+    pub fn decode_snapshot(bytes: &[u8]) -> Result<Arc<Chain>, Error> {
+        let snapshot: ChainSnapshot = bincode::deserialize(bytes).map_err(|err| Error::Snapshot(err.to_string()))?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(Error::Snapshot(format!(
+                "Unsupported version {}, expected {}",
+                snapshot.version, SNAPSHOT_VERSION
+            )));
+        }
+
+        let mut chain = Arc::new(
+            Chain::init_new(snapshot.difficulty)
+                .with_hasher(snapshot.hasher)
+                .with_signature_algorithm(snapshot.signature_algorithm),
+        );
+        for record in snapshot.blocks {
+            let body = BlockBody::new(record.body).map_err(|reason| Error::Snapshot(reason.to_string()))?;
+            let block = Block::new(
+                record.node_id,
+                Nonce::from_bytes(record.nonce),
+                record.extra_nonce,
+                &chain.head.difficulty,
+                chain.head.hasher,
+                chain.head.hash.clone(),
+                chain.height() + 1,
+                &Arc::new(body),
+            );
+            chain = Chain::expand(&chain, block)?;
+        }
+
+        // The proof of work was checked block by block, the transactions are left.
+        chain.validate()?;
+        Ok(chain)
+    }
+
+    /// Writes the headers of this chain and the given unspent outputs, which must be the ones
+    /// of this chain, to a file, in bincode, overwriting it. The bodies may be pruned.
+    pub fn write_utxo_snapshot<P: AsRef<Path>>(&self, utxo_set: &UtxoSet, path: P) -> Result<(), Error> {
+        let mut headers: Vec<HeaderRecord> = self
+            .blocks()
+            .take_while(|block| block.height > 0)
+            .map(|block| HeaderRecord {
+                node_id: block.node_id,
+                nonce: *block.nonce.bytes(),
+                extra_nonce: block.extra_nonce,
+                merkle_root: block.body.hash().clone(),
+            })
+            .collect();
+        headers.reverse();
+
+        let snapshot = UtxoSnapshot {
+            version: UTXO_SNAPSHOT_VERSION,
+            difficulty: (*self.head.difficulty).clone(),
+            hasher: self.head.hasher,
+            signature_algorithm: self.signature_algorithm,
+            headers,
+            utxo_set: utxo_set.clone(),
+        };
+        let bytes = bincode::serialize(&snapshot).map_err(|err| Error::Snapshot(err.to_string()))?;
+        fs::write(path, bytes).map_err(|err| Error::Snapshot(err.to_string()))
+    }
+
+    /// Rebuilds the chain written to a file by `write_utxo_snapshot` from the headers alone,
+    /// whose proof of work is checked, along with its unspent outputs. The bodies of its blocks
+    /// are pruned but the genesis one.
+    pub fn read_utxo_snapshot<P: AsRef<Path>>(path: P) -> Result<(Arc<Chain>, UtxoSet), Error> {
+        let bytes = fs::read(path).map_err(|err| Error::Snapshot(err.to_string()))?;
+        Chain::decode_utxo_snapshot(&bytes)
+    }
+
+    /// Same as `read_utxo_snapshot`, from the content of the file.
+    pub fn decode_utxo_snapshot(bytes: &[u8]) -> Result<(Arc<Chain>, UtxoSet), Error> {
+        let snapshot: UtxoSnapshot = bincode::deserialize(bytes).map_err(|err| Error::Snapshot(err.to_string()))?;
+        if snapshot.version != UTXO_SNAPSHOT_VERSION {
+            return Err(Error::Snapshot(format!(
+                "Unsupported UTXO snapshot version {}, expected {}",
+                snapshot.version, UTXO_SNAPSHOT_VERSION
+            )));
+        }
+
+        let mut chain = Arc::new(
+            Chain::init_new(snapshot.difficulty)
+                .with_hasher(snapshot.hasher)
+                .with_signature_algorithm(snapshot.signature_algorithm),
+        );
+        for record in snapshot.headers {
+            let block = Block::new(
+                record.node_id,
+                Nonce::from_bytes(record.nonce),
+                record.extra_nonce,
+                &chain.head.difficulty,
+                chain.head.hasher,
+                chain.head.hash.clone(),
+                chain.height() + 1,
+                &Arc::new(BlockBody::header_only(record.merkle_root)),
+            );
+            chain = Chain::expand_header(&chain, block)?;
+        }
+
+        Ok((chain, snapshot.utxo_set))
+    }
+}
+
+/// Keeps the strongest chain adopted by the nodes of a network, the one to export once it ran.
+#[derive(Clone)]
+pub struct StrongestChain {
+    inner: Arc<Mutex<Arc<Chain>>>,
+}
+
+impl StrongestChain {
+    pub fn new(genesis_chain: Arc<Chain>) -> StrongestChain {
+        StrongestChain {
+            inner: Arc::new(Mutex::new(genesis_chain)),
+        }
+    }
+
+    /// Keeps the given chain if it is stronger than the current one.
+    pub fn report(&self, chain: &Arc<Chain>) {
+        let mut strongest = self.inner.lock().expect("Poisoned strongest chain");
+        if chain.stronger_than(&strongest) {
+            *strongest = chain.clone();
+        }
+    }
+
+    pub fn get(&self) -> Arc<Chain> {
+        self.inner.lock().expect("Poisoned strongest chain").clone()
+    }
+}
+
+/// Keeps the last chain each node of a network adopted, to check where every node stands while
+/// the network runs or once it ran.
+#[derive(Clone)]
+pub struct NodeChains {
+    genesis_chain: Arc<Chain>,
+    inner: Arc<Mutex<HashMap<u32, Arc<Chain>>>>,
+}
+
+impl NodeChains {
+    pub fn new(genesis_chain: Arc<Chain>) -> NodeChains {
+        NodeChains {
+            genesis_chain,
+            inner: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn report(&self, node_id: u32, chain: &Arc<Chain>) {
+        self.inner.lock().expect("Poisoned node chains").insert(node_id, chain.clone());
+    }
+
+    /// The last chain the given node adopted, the genesis chain if none.
+    pub fn get(&self, node_id: u32) -> Arc<Chain> {
+        self.inner
+            .lock()
+            .expect("Poisoned node chains")
+            .get(&node_id)
+            .cloned()
+            .unwrap_or_else(|| self.genesis_chain.clone())
+    }
+
+    /// The last chain of every node that adopted one.
+    pub fn all(&self) -> HashMap<u32, Arc<Chain>> {
+        self.inner.lock().expect("Poisoned node chains").clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::tests::expand;
+    use std::env;
+
+    #[test]
+    fn reads_the_chain_written() {
+        let mut chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()).with_hasher(Hasher::DoubleSha256));
+        for node_id in 0..3 {
+            chain = expand(&chain, node_id);
+        }
+        let path = env::temp_dir().join(format!("pow_snapshot_{}.bin", std::process::id()));
+
+        chain.write_snapshot(&path).unwrap();
+        let read_chain = Chain::read_snapshot(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(3, read_chain.height());
+        assert_eq!(chain.head().hash(), read_chain.head().hash());
+        assert_eq!(chain.work(), read_chain.work());
+    }
+
+    #[test]
+    fn reads_the_headers_and_the_unspent_outputs_written() {
+        let mut chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        for node_id in 0..3 {
+            chain = expand(&chain, node_id);
+        }
+        let utxo_set = chain.validate().unwrap();
+        let path = env::temp_dir().join(format!("pow_utxo_snapshot_{}.bin", std::process::id()));
+
+        chain.write_utxo_snapshot(&utxo_set, &path).unwrap();
+        let (read_chain, read_utxo_set) = Chain::read_utxo_snapshot(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(3, read_chain.height());
+        assert_eq!(chain.head().hash(), read_chain.head().hash());
+        assert_eq!(utxo_set.len(), read_utxo_set.len());
+        assert!(read_chain.head().body().is_pruned());
+        assert!(Chain::decode_utxo_snapshot(b"Garneray").is_err());
+
+        // The blocks extending the snapshot are validated from its unspent outputs.
+        let extended_chain = expand(&read_chain, 3);
+        assert!(extended_chain.validate_from(&read_chain, &read_utxo_set).is_ok());
+    }
+
+    #[test]
+    fn keeps_the_strongest_chain_reported() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let chain = expand(&genesis_chain, 0);
+        let strongest_chain = StrongestChain::new(genesis_chain.clone());
+
+        strongest_chain.report(&chain);
+        strongest_chain.report(&genesis_chain);
+
+        assert_eq!(chain.head().hash(), strongest_chain.get().head().hash());
+    }
+}
@@ -0,0 +1,196 @@
+use crate::blockchain::Chain;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often the heads of the nodes are sampled to detect the splits.
+pub const SPLIT_SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A head of the network no other node is ahead of, along with the nodes on its branch only:
+/// the nodes on it, and the ones behind it but past the fork.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompetingHead {
+    pub height: u32,
+    pub hash: String,
+    pub nodes: usize,
+}
+
+/// Finds the heads the given chains of the nodes compete with, the highest first: the heads no
+/// other chain contains. The network is split when there is more than one.
+pub fn competing_heads(node_chains: &HashMap<u32, Arc<Chain>>) -> Vec<CompetingHead> {
+    let mut heads: Vec<(Arc<Chain>, usize)> = vec![];
+    for chain in node_chains.values() {
+        match heads.iter_mut().find(|(head, _nodes)| head.head().hash() == chain.head().hash()) {
+            Some((_head, nodes)) => *nodes += 1,
+            None => heads.push((chain.clone(), 1)),
+        }
+    }
+    heads.sort_by_key(|(chain, _nodes)| Reverse(chain.height()));
+
+    // The heads are sorted by height, so a head is only contained by the ones before it.
+    let mut competing: Vec<(Arc<Chain>, CompetingHead)> = vec![];
+    for (chain, nodes) in heads {
+        let mut containing = competing.iter_mut().filter(|(tip, _head)| contains(tip, &chain));
+        match (containing.next(), containing.next()) {
+            (Some((_tip, head)), None) => head.nodes += nodes,
+            // Behind the fork, on none of the branches.
+            (Some(_first), Some(_second)) => {}
+            (None, _) => {
+                let head = CompetingHead {
+                    height: chain.height(),
+                    hash: chain.head().hash().to_string(),
+                    nodes,
+                };
+                competing.push((chain, head));
+            }
+        }
+    }
+    competing.into_iter().map(|(_chain, head)| head).collect()
+}
+
+fn contains(chain: &Chain, other: &Chain) -> bool {
+    chain
+        .block_at(other.height())
+        .is_some_and(|block| block.hash() == other.head().hash())
+}
+
+/// Tells the transient forks from the splits of the network: the periods the nodes stay on
+/// competing heads for longer than a threshold.
+pub struct SplitDetector {
+    threshold: Duration,
+    /// When the current split started, none while the nodes agree.
+    split_start: Option<Instant>,
+    /// Whether the current split lasted longer than the threshold.
+    reported: bool,
+    splits: u32,
+    longest_split: Duration,
+}
+
+/// What happened to the network since the previous sample.
+#[derive(Debug, PartialEq)]
+pub enum SplitEvent {
+    /// The nodes have been split for the given time, just longer than the threshold.
+    Split(Duration),
+    /// The nodes agree again after a split of the given duration, longer than the threshold.
+    Resolved(Duration),
+}
+
+impl SplitDetector {
+    pub fn new(threshold: Duration) -> SplitDetector {
+        SplitDetector {
+            threshold,
+            split_start: None,
+            reported: false,
+            splits: 0,
+            longest_split: Duration::from_secs(0),
+        }
+    }
+
+    /// Records the competing heads sampled now. Reports a split once it lasted longer than the
+    /// threshold, then once it is resolved.
+    pub fn sample(&mut self, heads: &[CompetingHead]) -> Option<SplitEvent> {
+        self.sample_at(heads, Instant::now())
+    }
+
+    fn sample_at(&mut self, heads: &[CompetingHead], at: Instant) -> Option<SplitEvent> {
+        if heads.len() <= 1 {
+            let split_start = self.split_start.take()?;
+            if !std::mem::take(&mut self.reported) {
+                return None;
+            }
+            let duration = at.saturating_duration_since(split_start);
+            self.longest_split = self.longest_split.max(duration);
+            return Some(SplitEvent::Resolved(duration));
+        }
+
+        let split_start = *self.split_start.get_or_insert(at);
+        let duration = at.saturating_duration_since(split_start);
+        if self.reported {
+            self.longest_split = self.longest_split.max(duration);
+            return None;
+        }
+        if duration < self.threshold {
+            return None;
+        }
+        self.reported = true;
+        self.splits += 1;
+        self.longest_split = self.longest_split.max(duration);
+        Some(SplitEvent::Split(duration))
+    }
+
+    /// The splits longer than the threshold, including the current one.
+    pub fn splits(&self) -> u32 {
+        self.splits
+    }
+
+    /// The longest split longer than the threshold, zero if none.
+    pub fn longest_split(&self) -> Duration {
+        self.longest_split
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::tests::expand;
+    use crate::blockchain::Difficulty;
+
+    fn head(height: u32, nodes: usize) -> CompetingHead {
+        CompetingHead {
+            height,
+            hash: String::new(),
+            nodes,
+        }
+    }
+
+    #[test]
+    fn finds_the_heads_no_other_chain_contains() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let fork = expand(&genesis_chain, 0);
+        let chain = expand(&expand(&fork, 1), 2);
+        let other_chain = expand(&fork, 3);
+
+        let mut node_chains = HashMap::new();
+        node_chains.insert(0, chain.clone());
+        node_chains.insert(1, chain.tail().unwrap());
+        node_chains.insert(2, other_chain.clone());
+        node_chains.insert(3, fork.clone());
+        node_chains.insert(4, genesis_chain.clone());
+        let heads = competing_heads(&node_chains);
+        assert_eq!(2, heads.len());
+        assert_eq!((3, 2), (heads[0].height, heads[0].nodes));
+        assert_eq!(chain.head().hash().to_string(), heads[0].hash);
+        assert_eq!((2, 1), (heads[1].height, heads[1].nodes));
+
+        // The nodes behind the same head are not split.
+        node_chains.remove(&2);
+        assert_eq!(1, competing_heads(&node_chains).len());
+    }
+
+    #[test]
+    fn reports_the_splits_longer_than_the_threshold() {
+        let mut detector = SplitDetector::new(Duration::from_secs(5));
+        let start = Instant::now();
+        let split = [head(3, 2), head(3, 1)];
+        assert_eq!(None, detector.sample_at(&split, start));
+        assert_eq!(None, detector.sample_at(&split, start + Duration::from_secs(4)));
+        // A transient fork is not reported.
+        assert_eq!(None, detector.sample_at(&[head(4, 3)], start + Duration::from_secs(4)));
+        assert_eq!(0, detector.splits());
+
+        let start = start + Duration::from_secs(10);
+        assert_eq!(None, detector.sample_at(&split, start));
+        assert_eq!(
+            Some(SplitEvent::Split(Duration::from_secs(6))),
+            detector.sample_at(&split, start + Duration::from_secs(6))
+        );
+        assert_eq!(None, detector.sample_at(&split, start + Duration::from_secs(7)));
+        assert_eq!(
+            Some(SplitEvent::Resolved(Duration::from_secs(8))),
+            detector.sample_at(&[head(4, 3)], start + Duration::from_secs(8))
+        );
+        assert_eq!(1, detector.splits());
+        assert_eq!(Duration::from_secs(8), detector.longest_split());
+    }
+}
@@ -0,0 +1,117 @@
+use crate::blockchain::{Chain, NodeMetric};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+/// The blocks each node mined, to tell once the simulation is over which of them ended up
+/// outside of the strongest chain: the work spent on them was wasted.
+#[derive(Default)]
+pub struct StaleStats {
+    /// The hashes of the blocks each node mined, in hexadecimal.
+    mined: HashMap<u32, Vec<String>>,
+}
+
+/// The blocks mined by the nodes with the same number of connections.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DegreeStaleBlocks {
+    pub degree: u32,
+    /// The nodes with this degree that mined at least one block.
+    pub miners: u32,
+    pub mined: u64,
+    /// The mined blocks that are not in the strongest chain.
+    pub stale: u64,
+}
+
+impl DegreeStaleBlocks {
+    /// The share of the mined blocks that are stale, none if no block was mined.
+    pub fn stale_rate(&self) -> Option<f64> {
+        if self.mined == 0 {
+            None
+        } else {
+            Some(self.stale as f64 / self.mined as f64)
+        }
+    }
+}
+
+impl StaleStats {
+    pub fn new() -> StaleStats {
+        StaleStats::default()
+    }
+
+    pub fn record(&mut self, metric: &NodeMetric) {
+        if let NodeMetric::BlockMined { node_id, ref hash, .. } = *metric {
+            self.mined.entry(node_id).or_default().push(hash.clone());
+        }
+    }
+
+    /// The blocks of each node, mined and stale, by number of connections of the nodes: the
+    /// degree of each node is given by its id. The blocks mined right before the end count as
+    /// stale if the strongest chain did not adopt them yet. A multi-chain network gives the
+    /// strongest chain of each of its chains.
+    pub fn by_degree(&self, strongest_chains: &[Arc<Chain>], degrees: &[u32]) -> Vec<DegreeStaleBlocks> {
+        let best_blocks: HashSet<String> = strongest_chains
+            .iter()
+            .flat_map(|chain| chain.blocks())
+            .map(|block| block.hash().to_string())
+            .collect();
+
+        let mut by_degree: BTreeMap<u32, DegreeStaleBlocks> = BTreeMap::new();
+        for (&node_id, hashes) in &self.mined {
+            let degree = degrees.get(node_id as usize).copied().unwrap_or(0);
+            let stale_blocks = by_degree.entry(degree).or_insert(DegreeStaleBlocks {
+                degree,
+                miners: 0,
+                mined: 0,
+                stale: 0,
+            });
+            stale_blocks.miners += 1;
+            stale_blocks.mined += hashes.len() as u64;
+            stale_blocks.stale += hashes.iter().filter(|hash| !best_blocks.contains(*hash)).count() as u64;
+        }
+        by_degree.into_values().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::tests::expand;
+    use crate::blockchain::Difficulty;
+    use std::sync::Arc;
+
+    #[test]
+    fn tells_the_stale_blocks_by_degree() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let best_chain = expand(&expand(&genesis_chain, 0), 1);
+        let stale_chain = expand(&genesis_chain, 2);
+        let mined = |node_id, chain: &Arc<Chain>| NodeMetric::BlockMined {
+            node_id,
+            height: chain.height(),
+            hash: chain.head().hash().to_string(),
+            reward: 0,
+        };
+
+        let mut stats = StaleStats::new();
+        for metric in [
+            mined(0, &expand(&genesis_chain, 0)),
+            mined(1, &best_chain),
+            mined(2, &stale_chain),
+            NodeMetric::Fork { node_id: 1 },
+        ] {
+            stats.record(&metric);
+        }
+
+        let by_degree = stats.by_degree(std::slice::from_ref(&best_chain), &[3, 5, 3]);
+        assert_eq!(2, by_degree.len());
+        assert_eq!(
+            DegreeStaleBlocks {
+                degree: 3,
+                miners: 2,
+                mined: 2,
+                stale: 1,
+            },
+            by_degree[0]
+        );
+        assert_eq!(Some(0.5), by_degree[0].stale_rate());
+        assert_eq!(Some(0.0), by_degree[1].stale_rate());
+    }
+}
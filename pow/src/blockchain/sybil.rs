@@ -0,0 +1,70 @@
+use std::ops::Range;
+use std::time::Duration;
+
+/// A single adversary running many nodes, its sybils. They do not mine: they only relay the
+/// chains they receive, to one another at once, and to the honest nodes late or never, which
+/// slows down the propagation of the blocks and makes the honest miners fork more often.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SybilAdversary {
+    /// The addresses of the nodes of the adversary.
+    nodes: Range<u32>,
+    /// How long the chains wait before being relayed to an honest peer, none to never relay
+    /// them.
+    relay_delay: Option<Duration>,
+}
+
+impl SybilAdversary {
+    /// An adversary withholding every chain from the honest nodes.
+    pub fn new(nodes: Range<u32>) -> SybilAdversary {
+        SybilAdversary {
+            nodes,
+            relay_delay: None,
+        }
+    }
+
+    /// Makes the nodes of the adversary relay the chains to the honest nodes after the given
+    /// delay instead of withholding them.
+    pub fn with_relay_delay(mut self, relay_delay: Duration) -> SybilAdversary {
+        self.relay_delay = Some(relay_delay);
+        self
+    }
+
+    pub fn controls(&self, node_id: u32) -> bool {
+        self.nodes.contains(&node_id)
+    }
+
+    pub fn len(&self) -> u32 {
+        self.nodes.len() as u32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// How long a chain waits before being relayed to the given peer, none if it never is.
+    pub fn relay_delay(&self, peer_id: u32) -> Option<Duration> {
+        if self.controls(peer_id) {
+            Some(Duration::ZERO)
+        } else {
+            self.relay_delay
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relays_to_the_other_sybils_at_once() {
+        let withholding = SybilAdversary::new(12..16);
+        assert!(withholding.controls(12));
+        assert!(!withholding.controls(16));
+        assert_eq!(Some(Duration::ZERO), withholding.relay_delay(15));
+        assert_eq!(None, withholding.relay_delay(0));
+
+        let delaying = withholding.with_relay_delay(Duration::from_millis(500));
+        assert_eq!(Some(Duration::ZERO), delaying.relay_delay(13));
+        assert_eq!(Some(Duration::from_millis(500)), delaying.relay_delay(0));
+    }
+}
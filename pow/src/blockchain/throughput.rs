@@ -0,0 +1,165 @@
+use btclike::crypto::Hash;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Gathers the payments submitted by the load generators of a network, to measure how many
+/// transactions per second the network confirms, how long they wait for it, and how the
+/// mempools fill up over time.
+#[derive(Clone)]
+pub struct ThroughputStats {
+    inner: Arc<Mutex<Throughput>>,
+}
+
+struct Throughput {
+    start: Instant,
+    /// The payments not confirmed yet, along with the instant they were submitted.
+    pending: HashMap<Hash, Instant>,
+    rejected: u64,
+    /// The time every confirmed payment waited for its first block, in confirmation order.
+    confirmation_latencies: Vec<Duration>,
+    /// The latest number of pending payments of each node.
+    backlogs: HashMap<u32, usize>,
+    /// The total backlog of the nodes at the end of every second of the run.
+    backlog_timeline: Vec<usize>,
+}
+
+impl Default for ThroughputStats {
+    fn default() -> ThroughputStats {
+        ThroughputStats::new()
+    }
+}
+
+impl ThroughputStats {
+    /// The run is measured from now on.
+    pub fn new() -> ThroughputStats {
+        ThroughputStats {
+            inner: Arc::new(Mutex::new(Throughput {
+                start: Instant::now(),
+                pending: HashMap::new(),
+                rejected: 0,
+                confirmation_latencies: vec![],
+                backlogs: HashMap::new(),
+                backlog_timeline: vec![],
+            })),
+        }
+    }
+
+    /// Records a payment accepted by the mempool of a node.
+    pub fn submit(&self, hash: Hash) {
+        self.lock().pending.insert(hash, Instant::now());
+    }
+
+    /// Records a payment the wallet could not build or the mempool refused.
+    pub fn reject(&self) {
+        self.lock().rejected += 1;
+    }
+
+    /// Records the inclusion of a transaction in a block adopted by a node. Only the first
+    /// block including a submitted payment confirms it: the transactions of the blocks of
+    /// later forks, as well as the ones not submitted by a load generator, are ignored.
+    pub fn confirm(&self, hash: &Hash) {
+        let mut inner = self.lock();
+        if let Some(submitted_at) = inner.pending.remove(hash) {
+            inner.confirmation_latencies.push(submitted_at.elapsed());
+        }
+    }
+
+    /// Records the number of pending payments of a node, replacing the previous one.
+    pub fn report_backlog(&self, node_id: u32, backlog: usize) {
+        let mut inner = self.lock();
+        inner.backlogs.insert(node_id, backlog);
+
+        let total = inner.backlogs.values().sum();
+        let second = inner.start.elapsed().as_secs() as usize;
+        // The seconds without any report kept the backlog of the previous one.
+        let previous = inner.backlog_timeline.last().cloned().unwrap_or(0);
+        inner.backlog_timeline.resize(second + 1, previous);
+        inner.backlog_timeline[second] = total;
+    }
+
+    /// The time elapsed since the stats were created.
+    pub fn elapsed(&self) -> Duration {
+        self.lock().start.elapsed()
+    }
+
+    pub fn submitted(&self) -> u64 {
+        let inner = self.lock();
+        (inner.pending.len() + inner.confirmation_latencies.len()) as u64
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.lock().rejected
+    }
+
+    pub fn confirmed(&self) -> u64 {
+        self.lock().confirmation_latencies.len() as u64
+    }
+
+    /// The number of payments confirmed per second since the stats were created.
+    pub fn confirmed_per_second(&self) -> f64 {
+        self.confirmed() as f64 / self.elapsed().as_secs_f64()
+    }
+
+    /// The confirmation latency the given percentage of the confirmed payments did not exceed,
+    /// none if none was confirmed.
+    pub fn confirmation_latency_percentile(&self, percentage: u8) -> Option<Duration> {
+        let mut latencies = self.lock().confirmation_latencies.clone();
+        if latencies.is_empty() {
+            return None;
+        }
+
+        latencies.sort();
+        let rank = (latencies.len() - 1) * percentage.min(100) as usize / 100;
+        Some(latencies[rank])
+    }
+
+    /// The total number of pending payments of the nodes at the end of every second of the
+    /// run, up to the latest report.
+    pub fn backlog_timeline(&self) -> Vec<usize> {
+        self.lock().backlog_timeline.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Throughput> {
+        self.inner.lock().expect("Poisoned throughput stats")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use btclike::crypto;
+
+    #[test]
+    fn measures_the_confirmations_of_the_submitted_payments() {
+        let stats = ThroughputStats::new();
+        assert_eq!(None, stats.confirmation_latency_percentile(50));
+
+        let hashes: Vec<Hash> = (0u32..4).map(|index| crypto::hash(&index.to_le_bytes())).collect();
+        for hash in &hashes {
+            stats.submit(hash.clone());
+        }
+        stats.reject();
+        stats.confirm(&hashes[0]);
+        stats.confirm(&hashes[1]);
+        // Another fork confirms it again, or it was never submitted.
+        stats.confirm(&hashes[1]);
+        stats.confirm(&crypto::hash(b"unknown"));
+
+        assert_eq!(4, stats.submitted());
+        assert_eq!(1, stats.rejected());
+        assert_eq!(2, stats.confirmed());
+        let median = stats.confirmation_latency_percentile(50).unwrap();
+        assert!(median <= stats.confirmation_latency_percentile(100).unwrap());
+    }
+
+    #[test]
+    fn sums_the_backlogs_of_every_node() {
+        let stats = ThroughputStats::new();
+        stats.report_backlog(0, 3);
+        stats.report_backlog(1, 2);
+        stats.report_backlog(0, 1);
+
+        assert_eq!(vec![3], stats.backlog_timeline());
+    }
+}
@@ -0,0 +1,105 @@
+use crate::blockchain::{Chain, Message};
+use netsim::error::Error;
+use netsim::network::TraceCodec;
+
+/// The tags of the chain messages in a trace file.
+const CHAIN_TAG: u8 = 0;
+const REQUESTED_CHAIN_TAG: u8 = 1;
+
+/// Writes the chains the nodes send each other to the trace files, as chain snapshots, and
+/// leaves the other messages out: the chains are what the nodes agree on, so a divergence
+/// is replayed from them. A chain is validated again when a trace file is read.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChainCodec;
+
+impl TraceCodec<Message> for ChainCodec {
+    fn encode(&self, message: &Message) -> Option<Vec<u8>> {
+        let (tag, chain) = match message {
+            Message::Chain(chain) => (CHAIN_TAG, chain),
+            Message::RequestedChain(chain) => (REQUESTED_CHAIN_TAG, chain),
+            _ => return None,
+        };
+
+        let mut payload = vec![tag];
+        payload.extend(chain.encode_snapshot().ok()?);
+        Some(payload)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Message, Error> {
+        let (tag, snapshot) = payload
+            .split_first()
+            .ok_or_else(|| Error::InvalidTrace("Empty payload".to_owned()))?;
+        let chain = Chain::decode_snapshot(snapshot).map_err(|err| Error::InvalidTrace(err.to_string()))?;
+        match *tag {
+            CHAIN_TAG => Ok(Message::Chain(chain)),
+            REQUESTED_CHAIN_TAG => Ok(Message::RequestedChain(chain)),
+            tag => Err(Error::InvalidTrace(format!("Unknown message {}", tag))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{Difficulty, PowNode};
+    use netsim::clock::MockClock;
+    use netsim::network::{Recorder, Topology, Trace};
+    use netsim::simulation::SimulationBuilder;
+    use std::env;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn height_of(message: &Message) -> Option<u32> {
+        match message {
+            Message::Chain(chain) | Message::RequestedChain(chain) => Some(chain.height()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn replays_the_chains_a_node_received() {
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let node = move |node_id: u32| {
+            let node = PowNode::new(
+                node_id,
+                genesis_chain.clone(),
+                Duration::from_millis(100),
+                Duration::from_secs(1),
+            );
+            // Only the first node mines.
+            if node_id == 0 {
+                node
+            } else {
+                node.with_hashrate_shock(Duration::from_secs(0), None)
+            }
+        };
+        let recorder = Recorder::new();
+        let node_id = AtomicU32::new(0);
+        let factory_node = node.clone();
+        SimulationBuilder::new(3, move || factory_node(node_id.fetch_add(1, Ordering::Relaxed)))
+            .with_topology(Topology::Ring)
+            .with_connections(1)
+            .with_latency(Duration::from_millis(10))
+            .with_duration(Duration::from_secs(1))
+            .with_clock(MockClock)
+            .with_recorder(recorder.clone())
+            .build()
+            .run();
+
+        let path = env::temp_dir().join(format!("pow-{}.trace", std::process::id()));
+        recorder.trace().write(&path, &ChainCodec).unwrap();
+        let trace = Trace::read(&path, &ChainCodec).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let received: Vec<_> = trace.received_by(2).filter_map(|record| height_of(&record.message)).collect();
+        assert!(!received.is_empty());
+
+        // The node relays the chains of the miner to its other peer, as it did in the network.
+        let replayed = trace.replay(2, node(2), Duration::from_secs(1));
+        let relayed = |trace: &Trace<Message>| -> Vec<u32> {
+            trace.sent_by(2).filter_map(|record| height_of(&record.message)).collect()
+        };
+        assert!(!relayed(&replayed).is_empty());
+        assert_eq!(relayed(&trace).last(), relayed(&replayed).last());
+    }
+}
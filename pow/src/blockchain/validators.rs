@@ -0,0 +1,228 @@
+use crate::blockchain::{Authorities, BlockHeader, NodeMetric};
+use crate::error::{ChainError, Error};
+use btclike::crypto::{Hash, KeyPair, SignatureAlgorithm};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+
+/// The validators of a proof of stake chain, with their stakes. The leader allowed to produce
+/// the block on top of a parent is drawn among them with a probability proportional to its
+/// stake, the hash of the parent being the randomness, so that every node elects the same one.
+///
+/// A validator that signs two blocks on top of the same parent is slashed: its whole stake is
+/// burnt. The registry of a chain keeps the stakes it was created with, the nodes slash the
+/// validators in their own copy as they observe the conflicting blocks.
+#[derive(Clone)]
+pub struct ValidatorRegistry {
+    /// The keys the validators sign their blocks with.
+    authorities: Authorities,
+    /// Ordered by node id, for the draws of the leaders to be the same everywhere.
+    stakes: BTreeMap<u32, u64>,
+    /// The block each validator signed on top of each parent it was observed to extend.
+    signed_blocks: HashMap<(u32, Hash), Hash>,
+}
+
+impl ValidatorRegistry {
+    pub fn new(signature_algorithm: SignatureAlgorithm) -> ValidatorRegistry {
+        ValidatorRegistry {
+            authorities: Authorities::new(signature_algorithm),
+            stakes: BTreeMap::new(),
+            signed_blocks: HashMap::new(),
+        }
+    }
+
+    /// Generates a key pair for each of the given nodes, which become validators with the
+    /// given stakes. Returns the key pairs the nodes sign their blocks with, by node id.
+    pub fn generate<I: IntoIterator<Item = (u32, u64)>>(
+        stakes: I,
+        signature_algorithm: SignatureAlgorithm,
+    ) -> Result<(ValidatorRegistry, HashMap<u32, Arc<KeyPair>>), Error> {
+        let stakes: BTreeMap<u32, u64> = stakes.into_iter().collect();
+        let (authorities, key_pairs) = Authorities::generate(stakes.keys().copied(), signature_algorithm)?;
+        Ok((
+            ValidatorRegistry {
+                authorities,
+                stakes,
+                signed_blocks: HashMap::new(),
+            },
+            key_pairs,
+        ))
+    }
+
+    pub fn len(&self) -> usize {
+        self.stakes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stakes.is_empty()
+    }
+
+    pub fn stakes(&self) -> &BTreeMap<u32, u64> {
+        &self.stakes
+    }
+
+    pub fn total_stake(&self) -> u64 {
+        self.stakes.values().sum()
+    }
+
+    /// The validator allowed to produce the block on top of the given parent, none if no
+    /// validator has a stake left.
+    pub fn leader(&self, parent_hash: &Hash) -> Option<u32> {
+        let total_stake = self.total_stake();
+        if total_stake == 0 {
+            return None;
+        }
+
+        // The first bytes of a hash meeting the difficulty are zeros.
+        let hash_bytes = parent_hash.as_ref();
+        let mut seed = [0u8; 8];
+        seed.copy_from_slice(&hash_bytes[hash_bytes.len() - 8..]);
+        let mut draw = u64::from_le_bytes(seed) % total_stake;
+        for (&node_id, &stake) in &self.stakes {
+            if draw < stake {
+                return Some(node_id);
+            }
+            draw -= stake;
+        }
+        None
+    }
+
+    /// Checks that the block was produced by the leader elected for its parent, which signed
+    /// its hash.
+    pub fn verify(&self, header: &BlockHeader) -> Result<(), ChainError> {
+        self.authorities.verify(header)?;
+        if self.leader(&header.previous_block_hash) == Some(header.node_id) {
+            Ok(())
+        } else {
+            Err(ChainError::NotLeader)
+        }
+    }
+
+    /// Remembers the block a validator signed, and slashes it if it signed another one on top
+    /// of the same parent. Returns the stake slashed, if any. The blocks without a valid
+    /// signature are no evidence.
+    pub fn observe(&mut self, header: &BlockHeader) -> Option<u64> {
+        self.authorities.verify(header).ok()?;
+        let signed_block = self
+            .signed_blocks
+            .entry((header.node_id, header.previous_block_hash.clone()))
+            .or_insert_with(|| header.hash.clone());
+        if *signed_block == header.hash {
+            return None;
+        }
+
+        match self.slash(header.node_id) {
+            0 => None,
+            stake => Some(stake),
+        }
+    }
+
+    /// Burns the whole stake of the validator. Returns the stake burnt.
+    pub fn slash(&mut self, node_id: u32) -> u64 {
+        self.stakes.get_mut(&node_id).map_or(0, std::mem::take)
+    }
+}
+
+/// Compares the blocks each validator mined with its share of the stake, and counts the
+/// slashed validators.
+pub struct ValidatorStats {
+    stakes: BTreeMap<u32, u64>,
+    mined_blocks: HashMap<u32, u64>,
+    slashed_validators: u64,
+}
+
+impl ValidatorStats {
+    pub fn new(stakes: BTreeMap<u32, u64>) -> ValidatorStats {
+        ValidatorStats {
+            stakes,
+            mined_blocks: HashMap::new(),
+            slashed_validators: 0,
+        }
+    }
+
+    pub fn record(&mut self, metric: &NodeMetric) {
+        match *metric {
+            NodeMetric::BlockMined { node_id, .. } if self.stakes.contains_key(&node_id) => {
+                *self.mined_blocks.entry(node_id).or_insert(0) += 1;
+            }
+            NodeMetric::ValidatorSlashed { .. } => self.slashed_validators += 1,
+            _ => {}
+        }
+    }
+
+    pub fn mined_blocks(&self) -> u64 {
+        self.mined_blocks.values().sum()
+    }
+
+    /// The largest difference between the share of the blocks a validator mined and its share
+    /// of the stake, none before the first block.
+    pub fn max_share_deviation(&self) -> Option<f64> {
+        let mined_blocks = self.mined_blocks();
+        let total_stake: u64 = self.stakes.values().sum();
+        if mined_blocks == 0 || total_stake == 0 {
+            return None;
+        }
+
+        self.stakes
+            .iter()
+            .map(|(node_id, &stake)| {
+                let blocks = self.mined_blocks.get(node_id).copied().unwrap_or(0);
+                (blocks as f64 / mined_blocks as f64 - stake as f64 / total_stake as f64).abs()
+            })
+            .reduce(f64::max)
+    }
+
+    /// The slashings reported by the nodes, once per node that observed the conflicting blocks.
+    pub fn slashed_validators(&self) -> u64 {
+        self.slashed_validators
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{Block, Chain, Difficulty, Nonce};
+    use btclike::crypto;
+
+    #[test]
+    fn elects_the_validators_in_proportion_to_their_stakes() {
+        let (registry, _key_pairs) =
+            ValidatorRegistry::generate(vec![(0, 10), (1, 30), (2, 0)], SignatureAlgorithm::Ed25519).unwrap();
+        let mut elections = HashMap::new();
+        for seed in 0u32..4000 {
+            let parent_hash = Hash::from(*crypto::hash(&seed.to_le_bytes()).as_ref());
+            *elections.entry(registry.leader(&parent_hash).unwrap()).or_insert(0u32) += 1;
+        }
+
+        assert!(!elections.contains_key(&2));
+        assert!((800..1200).contains(&elections[&0]), "{:?}", elections);
+    }
+
+    #[test]
+    fn slashes_the_validators_signing_two_blocks_on_the_same_parent() {
+        let (mut registry, key_pairs) =
+            ValidatorRegistry::generate(vec![(0, 10), (1, 30)], SignatureAlgorithm::Ed25519).unwrap();
+        let chain = Chain::init_new(Difficulty::min_difficulty());
+        let block = |extra_nonce| {
+            Block::new(
+                0,
+                Nonce::new(),
+                extra_nonce,
+                chain.head().difficulty(),
+                chain.head().hasher(),
+                chain.head().hash().clone(),
+                1,
+                chain.head().body(),
+            )
+        };
+
+        assert_eq!(None, registry.observe(&block(0).sign(&key_pairs[&0]).header()));
+        assert_eq!(None, registry.observe(&block(0).sign(&key_pairs[&0]).header()));
+        // Unsigned, the conflicting block proves nothing.
+        assert_eq!(None, registry.observe(&block(1).header()));
+        assert_eq!(Some(10), registry.observe(&block(1).sign(&key_pairs[&0]).header()));
+        assert_eq!(None, registry.observe(&block(2).sign(&key_pairs[&0]).header()));
+        assert_eq!(30, registry.total_stake());
+        assert_eq!(Some(1), registry.leader(chain.head().hash()));
+    }
+}
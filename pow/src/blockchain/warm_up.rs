@@ -0,0 +1,139 @@
+use crate::blockchain::NodeMetric;
+use std::time::{Duration, Instant};
+
+/// Leaves the first blocks after the genesis one out of the statistics, along with what the
+/// nodes did while they were mined: the nodes are still connecting then, and the chain is too
+/// short for the forks to resolve as they would later on.
+///
+/// The warm-up ends when a node mines the first block above it. The metrics of the blocks of
+/// the warm-up are left out even once it ended, a late propagation for instance.
+pub struct WarmUp {
+    /// The height of the last block of the warm-up, none for no warm-up.
+    blocks: u32,
+    start: Instant,
+    /// How long the warm-up lasted, none while it lasts.
+    duration: Option<Duration>,
+    excluded_metrics: u64,
+    blocks_mined: u64,
+    forks: u64,
+}
+
+impl WarmUp {
+    pub fn new(blocks: u32) -> WarmUp {
+        WarmUp {
+            blocks,
+            start: Instant::now(),
+            duration: None,
+            excluded_metrics: 0,
+            blocks_mined: 0,
+            forks: 0,
+        }
+    }
+
+    /// Whether the metric belongs to the measurement. Counts it as part of the warm-up
+    /// otherwise.
+    pub fn measures(&mut self, metric: &NodeMetric) -> bool {
+        self.measures_at(metric, Instant::now())
+    }
+
+    fn measures_at(&mut self, metric: &NodeMetric, at: Instant) -> bool {
+        if self.blocks == 0 {
+            return true;
+        }
+        if let NodeMetric::BlockMined { height, .. } = *metric {
+            if height > self.blocks && self.duration.is_none() {
+                self.duration = Some(at.saturating_duration_since(self.start));
+            }
+        }
+
+        let warming_up = match metric.height() {
+            Some(height) => height <= self.blocks,
+            None => self.duration.is_none(),
+        };
+        if !warming_up {
+            return true;
+        }
+
+        self.excluded_metrics += 1;
+        match metric {
+            NodeMetric::BlockMined { .. } => self.blocks_mined += 1,
+            NodeMetric::Fork { .. } => self.forks += 1,
+            _ => {}
+        }
+        false
+    }
+
+    /// The number of blocks of the warm-up, 0 for none.
+    pub fn blocks(&self) -> u32 {
+        self.blocks
+    }
+
+    /// How long the warm-up lasted, none if it did not end.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    /// The metrics left out of the statistics.
+    pub fn excluded_metrics(&self) -> u64 {
+        self.excluded_metrics
+    }
+
+    /// The blocks mined during the warm-up, forks included.
+    pub fn blocks_mined(&self) -> u64 {
+        self.blocks_mined
+    }
+
+    pub fn forks(&self) -> u64 {
+        self.forks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mined(height: u32) -> NodeMetric {
+        NodeMetric::BlockMined {
+            node_id: 1,
+            height,
+            hash: format!("{:02x}", height),
+            reward: 50,
+        }
+    }
+
+    #[test]
+    fn leaves_the_first_blocks_out_of_the_measurement() {
+        let mut warm_up = WarmUp::new(2);
+        let start = warm_up.start;
+
+        assert!(!warm_up.measures_at(&mined(1), start));
+        assert!(!warm_up.measures_at(&NodeMetric::Fork { node_id: 2 }, start));
+        assert!(!warm_up.measures_at(&mined(2), start));
+        assert_eq!(None, warm_up.duration());
+
+        assert!(warm_up.measures_at(&mined(3), start + Duration::from_secs(20)));
+        assert!(warm_up.measures_at(&NodeMetric::Fork { node_id: 2 }, start));
+        // A late metric of a block of the warm-up.
+        let late_propagation = NodeMetric::BlockPropagated {
+            node_id: 2,
+            peer_id: 1,
+            height: 2,
+            hash: "02".to_owned(),
+        };
+        assert!(!warm_up.measures_at(&late_propagation, start + Duration::from_secs(21)));
+
+        assert_eq!(Some(Duration::from_secs(20)), warm_up.duration());
+        assert_eq!(4, warm_up.excluded_metrics());
+        assert_eq!(2, warm_up.blocks_mined());
+        assert_eq!(1, warm_up.forks());
+    }
+
+    #[test]
+    fn measures_everything_without_warm_up() {
+        let mut warm_up = WarmUp::new(0);
+
+        assert!(warm_up.measures(&mined(1)));
+        assert!(warm_up.measures(&NodeMetric::Fork { node_id: 2 }));
+        assert_eq!(0, warm_up.excluded_metrics());
+    }
+}
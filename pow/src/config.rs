@@ -0,0 +1,637 @@
+use crate::blockchain::{DifficultySetting, HashrateShock};
+use crate::error::Error;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// The parameters of a simulation, built with the `with_` methods or read from a TOML file,
+/// named after the long flags of the CLI. Every parameter is optional, the missing ones take
+/// their default values, and a CLI flag overrides the value of the file:
+///
+/// ```toml
+/// network_size = 512
+/// connections = 3
+/// difficulty = "normal"
+/// duration_in_seconds = 60
+/// topology = "ring"
+/// latency = 20
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SimulationConfig {
+    pub network_size: Option<u32>,
+    pub connections: Option<u8>,
+    pub channel_capacity: Option<usize>,
+    /// The threads running the nodes, one per core by default.
+    pub worker_threads: Option<usize>,
+    /// The threads validating the chains received, apart from the event loops of the nodes.
+    pub blocking_threads: Option<usize>,
+    /// The number of single threaded runtimes the nodes are spread over, none to run them all
+    /// on the worker threads.
+    pub shards: Option<usize>,
+    /// A difficulty factor or a preset.
+    pub difficulty: Option<DifficultySetting>,
+    /// The block interval to calibrate the difficulty for, in seconds.
+    pub block_interval: Option<f64>,
+    pub duration_in_seconds: Option<u64>,
+    /// The first blocks after the genesis one, left out of the statistics.
+    pub warm_up: Option<u32>,
+    pub mining_delay: Option<u64>,
+    pub payment_delay: Option<u64>,
+    pub light_nodes: Option<u32>,
+    /// How many full nodes submit payments at the load rate, to measure the throughput.
+    pub load_generators: Option<u32>,
+    /// The payments per second of every load generator.
+    pub load_rate: Option<u32>,
+    /// How many full nodes an adversary runs to relay the chains late or never.
+    pub sybil_nodes: Option<u32>,
+    /// How long the sybils wait before relaying a chain to an honest node, in milliseconds.
+    /// They never do without it.
+    pub sybil_delay: Option<u64>,
+    /// How many full nodes send invalid chains instead of mining.
+    pub byzantine_nodes: Option<u32>,
+    /// The delay between two invalid chains of a byzantine node, in milliseconds.
+    pub byzantine_delay: Option<u64>,
+    /// The messages per second a node handles from each peer, without limit by default.
+    pub inbound_rate: Option<u32>,
+    /// The connections the peers initiate a node keeps at most, without limit by default.
+    pub max_inbound: Option<usize>,
+    /// The connections a node initiates it keeps at most, without limit by default.
+    pub max_outbound: Option<usize>,
+    /// Which peer a node evicts beyond its connection limits: oldest or worst_latency.
+    pub eviction: Option<String>,
+    /// The first connections a node initiates that it never evicts.
+    pub anchors: Option<usize>,
+    /// The misbehavior score from which a node bans a peer.
+    pub ban_threshold: Option<u32>,
+    /// How long a ban lasts, in seconds.
+    pub ban_duration: Option<u64>,
+    /// Whether the full nodes relay the new blocks as compact blocks.
+    pub compact_relay: Option<bool>,
+    /// Whether the blocks relayed are measured once compressed with snappy.
+    pub compression: Option<bool>,
+    /// Whether the full nodes announce the pending transactions to their peers.
+    pub transaction_gossip: Option<bool>,
+    /// Whether the light nodes load Bloom filters of their addresses on the full nodes.
+    pub bloom_filters: Option<bool>,
+    /// The false positive rate of the Bloom filters, between 0 and 1.
+    pub bloom_fp_rate: Option<f64>,
+    /// The number of blocks whose bodies the full nodes keep, all of them by default.
+    pub prune: Option<u32>,
+    pub hash_registry: Option<bool>,
+    pub replace_by_fee: Option<u32>,
+    /// The fees per kilobyte the nodes relay a transaction for at least, 0 by default.
+    pub min_relay_fee_rate: Option<u64>,
+    /// The largest transaction the nodes relay, in bytes.
+    pub max_tx_size: Option<usize>,
+    /// The amount every output of a transaction relayed must reach at least, 0 by default.
+    pub dust_threshold: Option<u32>,
+    /// The number of full nodes applying the relay policy above, all of them by default.
+    pub relay_policy_nodes: Option<u32>,
+    pub secp256k1: Option<bool>,
+    pub hasher: Option<String>,
+    /// "main", the default, "testnet" or "regtest": the rules of the chain.
+    pub network: Option<String>,
+    /// A TOML file defining the genesis block: its timestamp, its premined outputs and its
+    /// difficulty.
+    pub genesis: Option<String>,
+    /// The file to write the definition of the genesis block to, difficulty included.
+    pub export_genesis: Option<String>,
+    /// "pow", the default, "poa" for the blocks to be signed by authorities, or "pos" for them
+    /// to be signed by validators elected in proportion to their stakes.
+    pub consensus: Option<String>,
+    /// How many of the honest full nodes are authorities, in proof of authority, or validators,
+    /// in proof of stake. All of them by default.
+    pub authorities: Option<u32>,
+    /// The number of blocks between two checkpoints the nodes vote on, none for no finality
+    /// votes.
+    pub finality: Option<u32>,
+    /// How long the honest full nodes must stay on competing heads for the split of the network
+    /// to be reported, in seconds. The splits are not monitored without it.
+    pub split_threshold: Option<u64>,
+    /// The changes of the hash rate of some of the miners while the simulation runs.
+    pub hashrate_shock: Option<Vec<HashrateShock>>,
+    pub scenario: Option<String>,
+    /// The latency of every connection, in milliseconds.
+    pub latency: Option<u64>,
+    /// A CSV file of the round trip times between regions the nodes are spread over, added to
+    /// the latency.
+    pub latency_matrix: Option<String>,
+    /// A CSV file of the regions of some nodes, the others being assigned in turn.
+    pub regions: Option<String>,
+    /// The seed of the random topology.
+    pub seed: Option<u64>,
+    pub topology: Option<String>,
+    /// The number of independent chains the nodes of the network are spread over, each with its
+    /// own genesis block.
+    pub chains: Option<u32>,
+    /// The share of the connections between nodes of different chains, when there are several.
+    pub cross_chain_fraction: Option<f64>,
+    /// The share of the nodes behind a NAT, which do not accept any connection.
+    pub nat_fraction: Option<f64>,
+    /// Whether to show the live dashboard instead of the logs.
+    pub tui: Option<bool>,
+    /// The address to broadcast the events of the simulation on, to WebSocket clients.
+    pub websocket: Option<String>,
+    /// A snapshot file of the chain to start from.
+    pub import_snapshot: Option<String>,
+    /// The file to write the strongest chain to, once the simulation ran.
+    pub export_snapshot: Option<String>,
+    /// A UTXO snapshot file of the chain to start from, validating only the blocks after it.
+    pub import_utxo_snapshot: Option<String>,
+    /// The file to write the headers and the unspent outputs of the strongest chain to, once
+    /// the simulation ran.
+    pub export_utxo_snapshot: Option<String>,
+    /// The file to write the chains the nodes sent each other to, once the simulation ran.
+    pub record_trace: Option<String>,
+    /// An address book file of the peers the nodes reconnect to, from a previous simulation.
+    pub import_address_book: Option<String>,
+    /// The file to write the peers the nodes connected to, once the simulation ran.
+    pub export_address_book: Option<String>,
+}
+
+impl SimulationConfig {
+    pub fn new() -> SimulationConfig {
+        SimulationConfig::default()
+    }
+
+    pub fn with_network_size(mut self, network_size: u32) -> SimulationConfig {
+        self.network_size = Some(network_size);
+        self
+    }
+
+    pub fn with_connections(mut self, connections: u8) -> SimulationConfig {
+        self.connections = Some(connections);
+        self
+    }
+
+    pub fn with_channel_capacity(mut self, channel_capacity: usize) -> SimulationConfig {
+        self.channel_capacity = Some(channel_capacity);
+        self
+    }
+
+    pub fn with_worker_threads(mut self, worker_threads: usize) -> SimulationConfig {
+        self.worker_threads = Some(worker_threads);
+        self
+    }
+
+    pub fn with_blocking_threads(mut self, blocking_threads: usize) -> SimulationConfig {
+        self.blocking_threads = Some(blocking_threads);
+        self
+    }
+
+    pub fn with_shards(mut self, shards: usize) -> SimulationConfig {
+        self.shards = Some(shards);
+        self
+    }
+
+    pub fn with_difficulty(mut self, difficulty: DifficultySetting) -> SimulationConfig {
+        self.difficulty = Some(difficulty);
+        self
+    }
+
+    pub fn with_block_interval(mut self, block_interval: f64) -> SimulationConfig {
+        self.block_interval = Some(block_interval);
+        self
+    }
+
+    pub fn with_duration_in_seconds(mut self, duration_in_seconds: u64) -> SimulationConfig {
+        self.duration_in_seconds = Some(duration_in_seconds);
+        self
+    }
+
+    pub fn with_warm_up(mut self, warm_up: u32) -> SimulationConfig {
+        self.warm_up = Some(warm_up);
+        self
+    }
+
+    pub fn with_mining_delay(mut self, mining_delay: u64) -> SimulationConfig {
+        self.mining_delay = Some(mining_delay);
+        self
+    }
+
+    pub fn with_payment_delay(mut self, payment_delay: u64) -> SimulationConfig {
+        self.payment_delay = Some(payment_delay);
+        self
+    }
+
+    pub fn with_light_nodes(mut self, light_nodes: u32) -> SimulationConfig {
+        self.light_nodes = Some(light_nodes);
+        self
+    }
+
+    pub fn with_load_generators(mut self, load_generators: u32) -> SimulationConfig {
+        self.load_generators = Some(load_generators);
+        self
+    }
+
+    pub fn with_load_rate(mut self, load_rate: u32) -> SimulationConfig {
+        self.load_rate = Some(load_rate);
+        self
+    }
+
+    pub fn with_sybil_nodes(mut self, sybil_nodes: u32) -> SimulationConfig {
+        self.sybil_nodes = Some(sybil_nodes);
+        self
+    }
+
+    pub fn with_sybil_delay(mut self, sybil_delay: u64) -> SimulationConfig {
+        self.sybil_delay = Some(sybil_delay);
+        self
+    }
+
+    pub fn with_byzantine_nodes(mut self, byzantine_nodes: u32) -> SimulationConfig {
+        self.byzantine_nodes = Some(byzantine_nodes);
+        self
+    }
+
+    pub fn with_byzantine_delay(mut self, byzantine_delay: u64) -> SimulationConfig {
+        self.byzantine_delay = Some(byzantine_delay);
+        self
+    }
+
+    pub fn with_inbound_rate(mut self, inbound_rate: u32) -> SimulationConfig {
+        self.inbound_rate = Some(inbound_rate);
+        self
+    }
+
+    pub fn with_max_inbound(mut self, max_inbound: usize) -> SimulationConfig {
+        self.max_inbound = Some(max_inbound);
+        self
+    }
+
+    pub fn with_max_outbound(mut self, max_outbound: usize) -> SimulationConfig {
+        self.max_outbound = Some(max_outbound);
+        self
+    }
+
+    pub fn with_eviction(mut self, eviction: &str) -> SimulationConfig {
+        self.eviction = Some(eviction.to_owned());
+        self
+    }
+
+    pub fn with_anchors(mut self, anchors: usize) -> SimulationConfig {
+        self.anchors = Some(anchors);
+        self
+    }
+
+    pub fn with_ban_threshold(mut self, ban_threshold: u32) -> SimulationConfig {
+        self.ban_threshold = Some(ban_threshold);
+        self
+    }
+
+    pub fn with_ban_duration(mut self, ban_duration: u64) -> SimulationConfig {
+        self.ban_duration = Some(ban_duration);
+        self
+    }
+
+    pub fn with_compact_relay(mut self, compact_relay: bool) -> SimulationConfig {
+        self.compact_relay = Some(compact_relay);
+        self
+    }
+
+    pub fn with_compression(mut self, compression: bool) -> SimulationConfig {
+        self.compression = Some(compression);
+        self
+    }
+
+    pub fn with_transaction_gossip(mut self, transaction_gossip: bool) -> SimulationConfig {
+        self.transaction_gossip = Some(transaction_gossip);
+        self
+    }
+
+    pub fn with_bloom_filters(mut self, bloom_filters: bool) -> SimulationConfig {
+        self.bloom_filters = Some(bloom_filters);
+        self
+    }
+
+    pub fn with_bloom_fp_rate(mut self, bloom_fp_rate: f64) -> SimulationConfig {
+        self.bloom_fp_rate = Some(bloom_fp_rate);
+        self
+    }
+
+    pub fn with_prune(mut self, prune: u32) -> SimulationConfig {
+        self.prune = Some(prune);
+        self
+    }
+
+    pub fn with_hash_registry(mut self, hash_registry: bool) -> SimulationConfig {
+        self.hash_registry = Some(hash_registry);
+        self
+    }
+
+    pub fn with_replace_by_fee(mut self, replace_by_fee: u32) -> SimulationConfig {
+        self.replace_by_fee = Some(replace_by_fee);
+        self
+    }
+
+    pub fn with_min_relay_fee_rate(mut self, min_relay_fee_rate: u64) -> SimulationConfig {
+        self.min_relay_fee_rate = Some(min_relay_fee_rate);
+        self
+    }
+
+    pub fn with_max_tx_size(mut self, max_tx_size: usize) -> SimulationConfig {
+        self.max_tx_size = Some(max_tx_size);
+        self
+    }
+
+    pub fn with_dust_threshold(mut self, dust_threshold: u32) -> SimulationConfig {
+        self.dust_threshold = Some(dust_threshold);
+        self
+    }
+
+    pub fn with_relay_policy_nodes(mut self, relay_policy_nodes: u32) -> SimulationConfig {
+        self.relay_policy_nodes = Some(relay_policy_nodes);
+        self
+    }
+
+    pub fn with_secp256k1(mut self, secp256k1: bool) -> SimulationConfig {
+        self.secp256k1 = Some(secp256k1);
+        self
+    }
+
+    pub fn with_hasher<S: Into<String>>(mut self, hasher: S) -> SimulationConfig {
+        self.hasher = Some(hasher.into());
+        self
+    }
+
+    pub fn with_network<S: Into<String>>(mut self, network: S) -> SimulationConfig {
+        self.network = Some(network.into());
+        self
+    }
+
+    pub fn with_genesis<S: Into<String>>(mut self, genesis: S) -> SimulationConfig {
+        self.genesis = Some(genesis.into());
+        self
+    }
+
+    pub fn with_export_genesis<S: Into<String>>(mut self, export_genesis: S) -> SimulationConfig {
+        self.export_genesis = Some(export_genesis.into());
+        self
+    }
+
+    pub fn with_consensus<S: Into<String>>(mut self, consensus: S) -> SimulationConfig {
+        self.consensus = Some(consensus.into());
+        self
+    }
+
+    pub fn with_authorities(mut self, authorities: u32) -> SimulationConfig {
+        self.authorities = Some(authorities);
+        self
+    }
+
+    pub fn with_finality(mut self, finality: u32) -> SimulationConfig {
+        self.finality = Some(finality);
+        self
+    }
+
+    pub fn with_split_threshold(mut self, split_threshold: u64) -> SimulationConfig {
+        self.split_threshold = Some(split_threshold);
+        self
+    }
+
+    pub fn with_hashrate_shock(mut self, hashrate_shock: HashrateShock) -> SimulationConfig {
+        self.hashrate_shock.get_or_insert_with(Vec::new).push(hashrate_shock);
+        self
+    }
+
+    pub fn with_scenario<S: Into<String>>(mut self, scenario: S) -> SimulationConfig {
+        self.scenario = Some(scenario.into());
+        self
+    }
+
+    pub fn with_latency(mut self, latency: u64) -> SimulationConfig {
+        self.latency = Some(latency);
+        self
+    }
+
+    pub fn with_latency_matrix<S: Into<String>>(mut self, latency_matrix: S) -> SimulationConfig {
+        self.latency_matrix = Some(latency_matrix.into());
+        self
+    }
+
+    pub fn with_regions<S: Into<String>>(mut self, regions: S) -> SimulationConfig {
+        self.regions = Some(regions.into());
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> SimulationConfig {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_topology<S: Into<String>>(mut self, topology: S) -> SimulationConfig {
+        self.topology = Some(topology.into());
+        self
+    }
+
+    pub fn with_chains(mut self, chains: u32) -> SimulationConfig {
+        self.chains = Some(chains);
+        self
+    }
+
+    pub fn with_cross_chain_fraction(mut self, cross_chain_fraction: f64) -> SimulationConfig {
+        self.cross_chain_fraction = Some(cross_chain_fraction);
+        self
+    }
+
+    pub fn with_nat_fraction(mut self, nat_fraction: f64) -> SimulationConfig {
+        self.nat_fraction = Some(nat_fraction);
+        self
+    }
+
+    pub fn with_tui(mut self, tui: bool) -> SimulationConfig {
+        self.tui = Some(tui);
+        self
+    }
+
+    pub fn with_websocket<S: Into<String>>(mut self, websocket: S) -> SimulationConfig {
+        self.websocket = Some(websocket.into());
+        self
+    }
+
+    pub fn with_import_snapshot<S: Into<String>>(mut self, import_snapshot: S) -> SimulationConfig {
+        self.import_snapshot = Some(import_snapshot.into());
+        self
+    }
+
+    pub fn with_export_snapshot<S: Into<String>>(mut self, export_snapshot: S) -> SimulationConfig {
+        self.export_snapshot = Some(export_snapshot.into());
+        self
+    }
+
+    pub fn with_import_address_book<S: Into<String>>(mut self, import_address_book: S) -> SimulationConfig {
+        self.import_address_book = Some(import_address_book.into());
+        self
+    }
+
+    pub fn with_export_address_book<S: Into<String>>(mut self, export_address_book: S) -> SimulationConfig {
+        self.export_address_book = Some(export_address_book.into());
+        self
+    }
+
+    pub fn with_import_utxo_snapshot<S: Into<String>>(mut self, import_utxo_snapshot: S) -> SimulationConfig {
+        self.import_utxo_snapshot = Some(import_utxo_snapshot.into());
+        self
+    }
+
+    pub fn with_export_utxo_snapshot<S: Into<String>>(mut self, export_utxo_snapshot: S) -> SimulationConfig {
+        self.export_utxo_snapshot = Some(export_utxo_snapshot.into());
+        self
+    }
+
+    pub fn with_record_trace<S: Into<String>>(mut self, record_trace: S) -> SimulationConfig {
+        self.record_trace = Some(record_trace.into());
+        self
+    }
+
+    /// This configuration, with the parameters given by the overrides replaced.
+    pub fn overridden_by(self, overrides: SimulationConfig) -> SimulationConfig {
+        SimulationConfig {
+            network_size: overrides.network_size.or(self.network_size),
+            connections: overrides.connections.or(self.connections),
+            channel_capacity: overrides.channel_capacity.or(self.channel_capacity),
+            worker_threads: overrides.worker_threads.or(self.worker_threads),
+            blocking_threads: overrides.blocking_threads.or(self.blocking_threads),
+            shards: overrides.shards.or(self.shards),
+            difficulty: overrides.difficulty.or(self.difficulty),
+            block_interval: overrides.block_interval.or(self.block_interval),
+            duration_in_seconds: overrides.duration_in_seconds.or(self.duration_in_seconds),
+            warm_up: overrides.warm_up.or(self.warm_up),
+            mining_delay: overrides.mining_delay.or(self.mining_delay),
+            payment_delay: overrides.payment_delay.or(self.payment_delay),
+            light_nodes: overrides.light_nodes.or(self.light_nodes),
+            load_generators: overrides.load_generators.or(self.load_generators),
+            load_rate: overrides.load_rate.or(self.load_rate),
+            sybil_nodes: overrides.sybil_nodes.or(self.sybil_nodes),
+            sybil_delay: overrides.sybil_delay.or(self.sybil_delay),
+            byzantine_nodes: overrides.byzantine_nodes.or(self.byzantine_nodes),
+            byzantine_delay: overrides.byzantine_delay.or(self.byzantine_delay),
+            inbound_rate: overrides.inbound_rate.or(self.inbound_rate),
+            max_inbound: overrides.max_inbound.or(self.max_inbound),
+            max_outbound: overrides.max_outbound.or(self.max_outbound),
+            eviction: overrides.eviction.or(self.eviction),
+            anchors: overrides.anchors.or(self.anchors),
+            ban_threshold: overrides.ban_threshold.or(self.ban_threshold),
+            ban_duration: overrides.ban_duration.or(self.ban_duration),
+            compact_relay: overrides.compact_relay.or(self.compact_relay),
+            compression: overrides.compression.or(self.compression),
+            transaction_gossip: overrides.transaction_gossip.or(self.transaction_gossip),
+            bloom_filters: overrides.bloom_filters.or(self.bloom_filters),
+            bloom_fp_rate: overrides.bloom_fp_rate.or(self.bloom_fp_rate),
+            prune: overrides.prune.or(self.prune),
+            hash_registry: overrides.hash_registry.or(self.hash_registry),
+            replace_by_fee: overrides.replace_by_fee.or(self.replace_by_fee),
+            min_relay_fee_rate: overrides.min_relay_fee_rate.or(self.min_relay_fee_rate),
+            max_tx_size: overrides.max_tx_size.or(self.max_tx_size),
+            dust_threshold: overrides.dust_threshold.or(self.dust_threshold),
+            relay_policy_nodes: overrides.relay_policy_nodes.or(self.relay_policy_nodes),
+            secp256k1: overrides.secp256k1.or(self.secp256k1),
+            hasher: overrides.hasher.or(self.hasher),
+            network: overrides.network.or(self.network),
+            genesis: overrides.genesis.or(self.genesis),
+            export_genesis: overrides.export_genesis.or(self.export_genesis),
+            consensus: overrides.consensus.or(self.consensus),
+            authorities: overrides.authorities.or(self.authorities),
+            finality: overrides.finality.or(self.finality),
+            split_threshold: overrides.split_threshold.or(self.split_threshold),
+            hashrate_shock: overrides.hashrate_shock.or(self.hashrate_shock),
+            scenario: overrides.scenario.or(self.scenario),
+            latency: overrides.latency.or(self.latency),
+            latency_matrix: overrides.latency_matrix.or(self.latency_matrix),
+            regions: overrides.regions.or(self.regions),
+            seed: overrides.seed.or(self.seed),
+            topology: overrides.topology.or(self.topology),
+            chains: overrides.chains.or(self.chains),
+            cross_chain_fraction: overrides.cross_chain_fraction.or(self.cross_chain_fraction),
+            nat_fraction: overrides.nat_fraction.or(self.nat_fraction),
+            tui: overrides.tui.or(self.tui),
+            websocket: overrides.websocket.or(self.websocket),
+            import_snapshot: overrides.import_snapshot.or(self.import_snapshot),
+            export_snapshot: overrides.export_snapshot.or(self.export_snapshot),
+            import_utxo_snapshot: overrides.import_utxo_snapshot.or(self.import_utxo_snapshot),
+            export_utxo_snapshot: overrides.export_utxo_snapshot.or(self.export_utxo_snapshot),
+            record_trace: overrides.record_trace.or(self.record_trace),
+            import_address_book: overrides.import_address_book.or(self.import_address_book),
+            export_address_book: overrides.export_address_book.or(self.export_address_book),
+        }
+    }
+
+    pub fn from_toml(toml: &str) -> Result<SimulationConfig, Error> {
+        toml::from_str(toml).map_err(|err| Error::Config(err.to_string()))
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<SimulationConfig, Error> {
+        let toml = fs::read_to_string(path).map_err(|err| Error::Config(err.to_string()))?;
+        SimulationConfig::from_toml(&toml)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn reads_the_parameters_given() {
+        let config = SimulationConfig::from_toml(
+            r#"
+            network_size = 512
+            difficulty = "fast"
+            secp256k1 = true
+            topology = "ring"
+            "#,
+        )
+        .unwrap();
+
+        let expected = SimulationConfig {
+            network_size: Some(512),
+            difficulty: Some(DifficultySetting::BlockInterval(Duration::from_secs(1))),
+            secp256k1: Some(true),
+            topology: Some("ring".to_owned()),
+            ..SimulationConfig::default()
+        };
+        assert_eq!(expected, config);
+    }
+
+    #[test]
+    fn overrides_the_parameters_given() {
+        let config = SimulationConfig::new()
+            .with_network_size(512)
+            .with_topology("ring")
+            .overridden_by(SimulationConfig::new().with_network_size(16).with_latency(20));
+
+        let expected = SimulationConfig::new()
+            .with_network_size(16)
+            .with_topology("ring")
+            .with_latency(20);
+        assert_eq!(expected, config);
+    }
+
+    #[test]
+    fn rejects_unknown_and_invalid_parameters() {
+        assert!(SimulationConfig::from_toml("network_sise = 512").is_err());
+        assert!(SimulationConfig::from_toml("connections = 256").is_err());
+        assert!(SimulationConfig::from_toml("difficulty = 225").is_err());
+        assert_eq!(
+            Some(DifficultySetting::Factor(12)),
+            SimulationConfig::from_toml("difficulty = 12").unwrap().difficulty
+        );
+    }
+
+    #[test]
+    fn reads_the_hashrate_shock_tables() {
+        let config = SimulationConfig::from_toml(
+            r#"
+            [[hashrate_shock]]
+            at_secs = 60
+            miners = 0.5
+            hashrate = 0
+            "#,
+        )
+        .unwrap();
+
+        let expected = SimulationConfig::new().with_hashrate_shock("60:0.5:0".parse().unwrap());
+        assert_eq!(expected, config);
+    }
+}
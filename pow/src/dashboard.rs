@@ -0,0 +1,313 @@
+//! A live view of a running simulation, drawn in the terminal from the metrics the nodes
+//! publish on a `MetricsBus`: the chain heights of the full nodes, the natural forks, the
+//! message throughput and the intervals between the blocks extending the highest chain.
+//!
+//! The logs are muted while the dashboard is open. `q` closes it, the simulation goes on,
+//! and `Ctrl-C` stops the simulation.
+
+use crate::blockchain::NodeMetric;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::widgets::{BarChart, Block, Paragraph, Sparkline};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::ops::Range;
+use std::process;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The delay between two frames, during which the keys are read.
+const FRAME_DELAY: Duration = Duration::from_millis(250);
+/// How many of the highest chain heights the histogram shows.
+const HISTOGRAM_HEIGHTS: u32 = 10;
+/// How many samples the sparklines keep.
+const SPARKLINE_SAMPLES: usize = 256;
+
+/// What the dashboard shows, updated with the metrics of the nodes.
+pub struct DashboardState {
+    start: Instant,
+    duration: Duration,
+    /// The chain height of each full node, by address.
+    heights: HashMap<u32, u32>,
+    max_height: u32,
+    /// When the highest chain was last extended.
+    last_block_at: Instant,
+    /// The intervals between the blocks extending the highest chain, in milliseconds.
+    block_intervals: VecDeque<u64>,
+    forks: usize,
+    messages: usize,
+    /// The messages received by the nodes during each second, the current one excluded.
+    throughput: VecDeque<u64>,
+    current_second: (Instant, u64),
+}
+
+impl DashboardState {
+    pub fn new(full_nodes: Range<u32>, duration: Duration, start: Instant) -> DashboardState {
+        DashboardState {
+            start,
+            duration,
+            heights: full_nodes.map(|node_id| (node_id, 0)).collect(),
+            max_height: 0,
+            last_block_at: start,
+            block_intervals: VecDeque::new(),
+            forks: 0,
+            messages: 0,
+            throughput: VecDeque::new(),
+            current_second: (start, 0),
+        }
+    }
+
+    pub fn apply(&mut self, metric: NodeMetric, at: Instant) {
+        match metric {
+            NodeMetric::ChainHeight { node_id, height } => {
+                self.heights.insert(node_id, height);
+                if height > self.max_height {
+                    push_sample(
+                        &mut self.block_intervals,
+                        at.duration_since(self.last_block_at).as_millis() as u64,
+                    );
+                    self.max_height = height;
+                    self.last_block_at = at;
+                }
+            }
+            NodeMetric::Fork { .. } => self.forks += 1,
+            NodeMetric::MessageReceived { .. } => {
+                self.messages += 1;
+                self.current_second.1 += 1;
+            }
+            NodeMetric::BlockMined { .. }
+            | NodeMetric::BlockReceived { .. }
+            | NodeMetric::BlockPropagated { .. }
+            | NodeMetric::LinkLatency { .. }
+            | NodeMetric::PeerMisbehaved { .. }
+            | NodeMetric::MessageThrottled { .. }
+            | NodeMetric::ChainDuplicate { .. }
+            | NodeMetric::ForeignChain { .. }
+            | NodeMetric::ChainRejected { .. }
+            | NodeMetric::PeerBanned { .. }
+            | NodeMetric::PeerEvicted { .. }
+            | NodeMetric::BlockRelayed { .. }
+            | NodeMetric::CompactBlockReconstructed { .. }
+            | NodeMetric::CompactBlockFallback { .. }
+            | NodeMetric::TransactionsAnnounced { .. }
+            | NodeMetric::InventoryReceived { .. }
+            | NodeMetric::TransactionsReceived { .. }
+            | NodeMetric::TransactionsFiltered { .. }
+            | NodeMetric::FilteredTransactionsReceived { .. }
+            | NodeMetric::BlocksPruned { .. }
+            | NodeMetric::ProofRequestPruned { .. }
+            | NodeMetric::NodeBootstrapped { .. }
+            | NodeMetric::ValidatorSlashed { .. }
+            | NodeMetric::CheckpointFinalized { .. } => {}
+        }
+    }
+
+    /// Closes the seconds passed since the last throughput sample.
+    pub fn tick(&mut self, now: Instant) {
+        while now.duration_since(self.current_second.0) >= Duration::from_secs(1) {
+            push_sample(&mut self.throughput, self.current_second.1);
+            self.current_second = (self.current_second.0 + Duration::from_secs(1), 0);
+        }
+    }
+
+    /// How many full nodes are at each of the highest chain heights, lowest first. The nodes
+    /// further behind are counted with the lowest height shown.
+    pub fn height_histogram(&self) -> Vec<(u32, u64)> {
+        let lowest_height = self.max_height.saturating_sub(HISTOGRAM_HEIGHTS - 1);
+        let mut histogram: Vec<(u32, u64)> = (lowest_height..=self.max_height).map(|height| (height, 0)).collect();
+        for height in self.heights.values() {
+            histogram[(height.max(&lowest_height) - lowest_height) as usize].1 += 1;
+        }
+        histogram
+    }
+
+    pub fn forks(&self) -> usize {
+        self.forks
+    }
+
+    pub fn block_intervals(&self) -> &VecDeque<u64> {
+        &self.block_intervals
+    }
+
+    pub fn throughput(&self) -> &VecDeque<u64> {
+        &self.throughput
+    }
+}
+
+fn push_sample(samples: &mut VecDeque<u64>, sample: u64) {
+    if samples.len() == SPARKLINE_SAMPLES {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+/// The dashboard, drawn by its own thread until the metrics end, when every node stopped.
+pub struct Dashboard {
+    handle: JoinHandle<io::Result<()>>,
+    log_level: log::LevelFilter,
+}
+
+impl Dashboard {
+    /// Takes over the terminal and draws the metrics received.
+    pub fn start(metrics: Receiver<NodeMetric>, full_nodes: Range<u32>, duration: Duration) -> io::Result<Dashboard> {
+        let terminal = ratatui::try_init()?;
+        let log_level = log::max_level();
+        log::set_max_level(log::LevelFilter::Off);
+
+        let state = DashboardState::new(full_nodes, duration, Instant::now());
+        let handle = thread::spawn(move || {
+            let result = draw_until_closed(terminal, state, metrics);
+            ratatui::restore();
+            result
+        });
+
+        Ok(Dashboard { handle, log_level })
+    }
+
+    /// Waits for the dashboard to close, then gives the terminal and the logs back.
+    pub fn join(self) -> io::Result<()> {
+        let result = self.handle.join().unwrap_or(Ok(()));
+        log::set_max_level(self.log_level);
+        result
+    }
+}
+
+fn draw_until_closed(
+    mut terminal: DefaultTerminal,
+    mut state: DashboardState,
+    metrics: Receiver<NodeMetric>,
+) -> io::Result<()> {
+    loop {
+        loop {
+            match metrics.try_recv() {
+                Ok(metric) => state.apply(metric, Instant::now()),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+
+        state.tick(Instant::now());
+        terminal.draw(|frame| render(frame, &state))?;
+
+        if event::poll(FRAME_DELAY)? {
+            if let Event::Key(key) = event::read()? {
+                match key.code {
+                    KeyCode::Char('q') => return Ok(()),
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        ratatui::restore();
+                        process::exit(130);
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+fn render(frame: &mut Frame, state: &DashboardState) {
+    let [summary_area, histogram_area, sparklines_area] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(8),
+        Constraint::Length(10),
+    ])
+    .areas(frame.area());
+    let [intervals_area, throughput_area] =
+        Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(sparklines_area);
+
+    let summary = format!(
+        "{:.0?} / {:?}   height: {}   forks: {}   messages: {} ({}/s)",
+        state.start.elapsed().min(state.duration),
+        state.duration,
+        state.max_height,
+        state.forks,
+        state.messages,
+        state.throughput.back().unwrap_or(&0)
+    );
+    frame.render_widget(
+        Paragraph::new(summary).block(Block::bordered().title(" Simulation - q: close, Ctrl-C: stop ")),
+        summary_area,
+    );
+
+    let histogram: Vec<(String, u64)> = state
+        .height_histogram()
+        .into_iter()
+        .map(|(height, nodes)| (height.to_string(), nodes))
+        .collect();
+    let histogram: Vec<(&str, u64)> = histogram.iter().map(|(height, nodes)| (height.as_str(), *nodes)).collect();
+    frame.render_widget(
+        BarChart::default()
+            .block(Block::bordered().title(" Full nodes by chain height "))
+            .data(histogram.as_slice())
+            .bar_width(6)
+            .bar_gap(1),
+        histogram_area,
+    );
+
+    // The sparklines show their latest samples.
+    let latest = |samples: &VecDeque<u64>, area_width: u16| -> Vec<u64> {
+        let width = usize::from(area_width.saturating_sub(2));
+        samples.iter().skip(samples.len().saturating_sub(width)).copied().collect()
+    };
+    frame.render_widget(
+        Sparkline::default()
+            .block(Block::bordered().title(format!(
+                " Block intervals, last: {}ms ",
+                state.block_intervals.back().unwrap_or(&0)
+            )))
+            .data(latest(&state.block_intervals, intervals_area.width)),
+        intervals_area,
+    );
+    frame.render_widget(
+        Sparkline::default()
+            .block(Block::bordered().title(" Messages per second "))
+            .data(latest(&state.throughput, throughput_area.width)),
+        throughput_area,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_the_full_nodes_at_the_highest_heights() {
+        let start = Instant::now();
+        let mut state = DashboardState::new(2..5, Duration::from_secs(30), start);
+        state.apply(NodeMetric::ChainHeight { node_id: 2, height: 12 }, start);
+        state.apply(NodeMetric::ChainHeight { node_id: 3, height: 11 }, start);
+
+        let histogram = state.height_histogram();
+        assert_eq!(10, histogram.len());
+        // The node still at the genesis is counted with the lowest height shown.
+        assert_eq!((3, 1), histogram[0]);
+        assert_eq!((11, 1), histogram[8]);
+        assert_eq!((12, 1), histogram[9]);
+    }
+
+    #[test]
+    fn measures_the_intervals_between_the_highest_blocks() {
+        let start = Instant::now();
+        let mut state = DashboardState::new(0..2, Duration::from_secs(30), start);
+        state.apply(NodeMetric::ChainHeight { node_id: 0, height: 1 }, start + Duration::from_secs(2));
+        // Another node catching up does not extend the highest chain.
+        state.apply(NodeMetric::ChainHeight { node_id: 1, height: 1 }, start + Duration::from_secs(3));
+        state.apply(NodeMetric::ChainHeight { node_id: 1, height: 2 }, start + Duration::from_secs(7));
+        state.apply(NodeMetric::Fork { node_id: 0 }, start + Duration::from_secs(7));
+
+        assert_eq!(&VecDeque::from(vec![2000, 5000]), state.block_intervals());
+        assert_eq!(1, state.forks());
+    }
+
+    #[test]
+    fn samples_the_messages_of_every_second() {
+        let start = Instant::now();
+        let mut state = DashboardState::new(0..1, Duration::from_secs(30), start);
+        state.apply(NodeMetric::MessageReceived { node_id: 0 }, start);
+        state.apply(NodeMetric::MessageReceived { node_id: 0 }, start);
+        state.tick(start + Duration::from_millis(2500));
+
+        assert_eq!(&VecDeque::from(vec![2, 0]), state.throughput());
+    }
+}
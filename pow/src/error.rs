@@ -0,0 +1,129 @@
+use serde::Serialize;
+use std::error;
+use std::fmt;
+
+/// The failures of the nodes, by origin, so that a node can tell the ones caused by its peers
+/// or by the network, which it recovers from, from its own bugs.
+#[derive(Debug)]
+pub enum Error {
+    /// A block or a chain that could not be validated.
+    Validation(ChainError),
+    /// A transaction or a block rejected by the ledger.
+    Ledger(btclike::Error),
+    /// A peer went away or did not answer in time.
+    Network(netsim::error::Error),
+    /// A broken invariant of the node itself.
+    Internal(String),
+    /// A chain snapshot could not be written or read.
+    Snapshot(String),
+    /// The parameters of the simulation are invalid, or their file could not be read.
+    Config(String),
+    /// A process of a distributed simulation failed, or could not be coordinated with the
+    /// others.
+    Orchestration(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Validation(ref err) => write!(f, "{}", err),
+            Error::Ledger(ref err) => write!(f, "{}", err),
+            Error::Network(ref err) => write!(f, "{}", err),
+            Error::Internal(ref reason) => write!(f, "Internal error: {}", reason),
+            Error::Snapshot(ref reason) => write!(f, "Invalid chain snapshot: {}", reason),
+            Error::Config(ref reason) => write!(f, "Invalid configuration: {}", reason),
+            Error::Orchestration(ref reason) => write!(f, "Orchestration failed: {}", reason),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Why a block or a chain was rejected. All of them but `Pruned` break the consensus rules:
+/// whoever sent the block forged it, or relays the blocks of someone who did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChainError {
+    /// The hash of the block does not match its fields.
+    InvalidHash,
+    /// The hash of the block is higher than the difficulty threshold.
+    HashHigherThanDifficulty,
+    /// The transactions of the block do not match its Merkle root.
+    InvalidBodyHash,
+    /// Not a fault of the block: the node dropped its transactions, or the undo data needed
+    /// to roll back to the fork the chain grows from.
+    Pruned,
+    /// The block does not reference the hash of its parent.
+    HashMismatch,
+    /// The block is not one block higher than its parent.
+    HeightMismatch,
+    /// The chain does not start from the genesis block of the node.
+    InvalidGenesis,
+    /// The block does not have the difficulty of its parent.
+    InvalidDifficulty,
+    /// The block does not have the hash function of its parent.
+    InvalidHasher,
+    /// The block spends outputs that do not exist, or were spent already.
+    InvalidTransactions,
+    /// The block exceeds the maximum block size of the network.
+    BlockTooLarge,
+    /// The block of a proof of authority chain was produced by another node.
+    UnknownProducer,
+    /// The block of a proof of authority chain is not signed.
+    MissingSignature,
+    /// The signature of the block does not match its producer.
+    InvalidSignature,
+    /// The block of a proof of stake chain was produced by another validator than the leader.
+    NotLeader,
+}
+
+impl ChainError {
+    /// Whether the block was forged, rather than only unverifiable by this node. The peers
+    /// sending forged blocks are penalized.
+    pub fn is_forged(self) -> bool {
+        self != ChainError::Pruned
+    }
+}
+
+impl fmt::Display for ChainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match *self {
+            ChainError::InvalidHash => "Invalid hash",
+            ChainError::HashHigherThanDifficulty => "Hash higher than difficulty",
+            ChainError::InvalidBodyHash => "Invalid body hash",
+            ChainError::Pruned => "Pruned block",
+            ChainError::HashMismatch => "Hash mismatch",
+            ChainError::HeightMismatch => "Height mismatch",
+            ChainError::InvalidGenesis => "Invalid genesis",
+            ChainError::InvalidDifficulty => "Invalid difficulty",
+            ChainError::InvalidHasher => "Invalid hash function",
+            ChainError::InvalidTransactions => "Invalid transactions",
+            ChainError::BlockTooLarge => "Block too large",
+            ChainError::UnknownProducer => "Unknown block producer",
+            ChainError::MissingSignature => "Missing producer signature",
+            ChainError::InvalidSignature => "Invalid producer signature",
+            ChainError::NotLeader => "Block producer not the leader",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+impl error::Error for ChainError {}
+
+impl From<ChainError> for Error {
+    fn from(err: ChainError) -> Self {
+        Error::Validation(err)
+    }
+}
+
+impl From<btclike::Error> for Error {
+    fn from(err: btclike::Error) -> Self {
+        Error::Ledger(err)
+    }
+}
+
+impl From<netsim::error::Error> for Error {
+    fn from(err: netsim::error::Error) -> Self {
+        Error::Network(err)
+    }
+}
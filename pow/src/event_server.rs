@@ -0,0 +1,187 @@
+//! A WebSocket server broadcasting the blocks mined, the blocks propagated and the link
+//! latencies of a running simulation as JSON text messages, so that a browser can animate the
+//! propagation of the blocks across the network:
+//!
+//! ```json
+//! {"event":"block_mined","node_id":3,"height":12,"hash":"00a3..."}
+//! {"event":"block_propagated","node_id":5,"peer_id":3,"height":12,"hash":"00a3..."}
+//! {"event":"link_latency","node_id":5,"peer_id":3,"round_trip_time_ms":0.42}
+//! ```
+//!
+//! The visualizers only get the events that happen once they are connected. One that does
+//! not keep up misses some.
+
+use crate::blockchain::NodeMetric;
+use futures::SinkExt;
+use std::io;
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::sync::mpsc::Receiver;
+use std::thread::{self, JoinHandle};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::runtime;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_tungstenite::tungstenite::Message;
+
+/// How many events a visualizer may lag behind before missing some.
+const EVENT_BUFFER: usize = 4096;
+
+/// The server, run by its own thread until the metrics end, when every node stopped.
+pub struct EventServer {
+    address: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl EventServer {
+    /// Listens on the given address, `127.0.0.1:9001` for instance, and broadcasts the
+    /// events among the metrics received.
+    pub fn start(address: &str, metrics: Receiver<NodeMetric>) -> io::Result<EventServer> {
+        let listener = StdTcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let address = listener.local_addr()?;
+        let runtime = runtime::Builder::new_current_thread().enable_all().build()?;
+
+        let handle = thread::spawn(move || runtime.block_on(serve(listener, metrics)));
+        info!("Broadcasting the simulation events on ws://{}", address);
+
+        Ok(EventServer { address, handle })
+    }
+
+    /// The address the server listens on.
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Waits for the metrics to end and the visualizers to be told.
+    pub fn join(self) {
+        if self.handle.join().is_err() {
+            warn!("The event server panicked");
+        }
+    }
+}
+
+/// Whether a visualizer animates the metric.
+fn is_event(metric: &NodeMetric) -> bool {
+    matches!(
+        *metric,
+        NodeMetric::BlockMined { .. } | NodeMetric::BlockPropagated { .. } | NodeMetric::LinkLatency { .. }
+    )
+}
+
+async fn serve(listener: StdTcpListener, metrics: Receiver<NodeMetric>) {
+    let listener = match TcpListener::from_std(listener) {
+        Ok(listener) => listener,
+        Err(err) => {
+            warn!("The event server could not listen: {}", err);
+            return;
+        }
+    };
+    let (events, _receiver) = broadcast::channel(EVENT_BUFFER);
+
+    // The metrics are received by a blocking task, since the nodes publish them on a
+    // standard channel.
+    let event_sender = events.clone();
+    let forwarder = tokio::task::spawn_blocking(move || {
+        for metric in metrics.iter().filter(is_event) {
+            match serde_json::to_string(&metric) {
+                // There may be no visualizer.
+                Ok(json) => {
+                    let _ = event_sender.send(json);
+                }
+                Err(err) => warn!("Could not serialize an event: {}", err),
+            }
+        }
+    });
+    tokio::pin!(forwarder);
+
+    let mut visualizers = vec![];
+    loop {
+        tokio::select! {
+            _ = &mut forwarder => break,
+            accepted = listener.accept() => match accepted {
+                Ok((stream, address)) => {
+                    debug!("New visualizer: {}", address);
+                    // Subscribed before the handshake, so that the visualizer gets every
+                    // event once connected.
+                    visualizers.push(tokio::spawn(stream_events(stream, events.subscribe())));
+                }
+                Err(err) => warn!("Could not accept a visualizer: {}", err),
+            },
+        }
+    }
+
+    // The visualizers close their connection once they got every event.
+    drop(events);
+    for visualizer in visualizers {
+        let _ = visualizer.await;
+    }
+}
+
+async fn stream_events(stream: TcpStream, mut events: broadcast::Receiver<String>) {
+    let mut websocket = match tokio_tungstenite::accept_async(stream).await {
+        Ok(websocket) => websocket,
+        Err(err) => {
+            debug!("WebSocket handshake failed: {}", err);
+            return;
+        }
+    };
+
+    loop {
+        match events.recv().await {
+            Ok(json) => {
+                if websocket.send(Message::Text(json)).await.is_err() {
+                    // The visualizer is gone.
+                    return;
+                }
+            }
+            Err(RecvError::Lagged(missed_events)) => debug!("A visualizer missed {} events", missed_events),
+            Err(RecvError::Closed) => break,
+        }
+    }
+
+    let _ = websocket.close(None).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::MetricsBus;
+    use futures::StreamExt;
+
+    #[test]
+    fn broadcasts_the_events_as_json() {
+        let mut bus = MetricsBus::new();
+        let server = EventServer::start("127.0.0.1:0", bus.subscribe()).unwrap();
+        let url = format!("ws://{}", server.address());
+        let runtime = runtime::Builder::new_current_thread().enable_all().build().unwrap();
+
+        let messages = runtime.block_on(async move {
+            let (mut websocket, _response) = tokio_tungstenite::connect_async(url).await.unwrap();
+
+            bus.publish(NodeMetric::MessageReceived { node_id: 1 });
+            bus.publish(NodeMetric::BlockPropagated {
+                node_id: 1,
+                peer_id: 0,
+                height: 2,
+                hash: "00ff".to_owned(),
+            });
+            drop(bus);
+
+            let mut messages = vec![];
+            while let Some(Ok(message)) = websocket.next().await {
+                messages.push(message);
+            }
+            messages
+        });
+        server.join();
+
+        assert_eq!(
+            vec![
+                Message::Text(
+                    r#"{"event":"block_propagated","node_id":1,"peer_id":0,"height":2,"hash":"00ff"}"#.to_owned()
+                ),
+                Message::Close(None),
+            ],
+            messages
+        );
+    }
+}
@@ -0,0 +1,44 @@
+//! A simulation of a proof of work blockchain network, run by the `pow_blockchain_simulation`
+//! binary or embedded in other programs:
+//!
+//! ```no_run
+//! extern crate pow_blockchain_simulation as pow;
+//!
+//! let config = pow::SimulationConfig::new()
+//!     .with_network_size(64)
+//!     .with_duration_in_seconds(10);
+//! pow::simulation::run(&config).expect("Invalid simulation parameters");
+//! ```
+
+extern crate bincode;
+extern crate btclike_simulation as btclike;
+#[macro_use]
+extern crate log;
+extern crate futures;
+extern crate network_simulator as netsim;
+#[cfg(feature = "tui")]
+extern crate ratatui;
+extern crate rand;
+extern crate ring;
+extern crate serde;
+#[cfg(feature = "websocket")]
+extern crate serde_json;
+extern crate tokio;
+#[cfg(feature = "websocket")]
+extern crate tokio_tungstenite;
+extern crate toml;
+
+pub mod blockchain;
+pub mod config;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+pub mod error;
+#[cfg(feature = "websocket")]
+pub mod event_server;
+pub mod orchestrator;
+pub mod simulation;
+pub mod testkit;
+
+pub use crate::blockchain::{Chain, LightNode, LogCapture, PowNode, SimulationNode};
+pub use crate::config::SimulationConfig;
+pub use netsim::network::{Network, Node};
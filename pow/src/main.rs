@@ -1,25 +1,16 @@
 extern crate clap;
+extern crate env_logger;
 #[macro_use]
 extern crate log;
-extern crate env_logger;
-extern crate futures;
-extern crate network_simulator as netsim;
-extern crate ring;
-extern crate tokio_timer;
+extern crate pow_blockchain_simulation as pow;
 
-pub mod blockchain;
-
-use blockchain::{Chain, Difficulty, PowNode};
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use log::LevelFilter;
-use netsim::network::Network;
-use std::cmp::PartialOrd;
-use std::fmt::Debug;
-use std::num::ParseIntError;
+use pow::orchestrator::Worker;
+use pow::SimulationConfig;
+use std::fmt::Display;
+use std::process;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
 
 fn main() {
     // Always print backtrace on panic.
@@ -34,6 +25,13 @@ fn main() {
         .version("0.1")
         .author("Pierre L. <pierre.larger@gmail.com>")
         .about("Simulates a Proof-of-Work blockchain network")
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .value_name("CONFIG_FILE")
+                .help("A TOML file of parameters named after the long flags. The flags override its values.")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("number_of_nodes")
                 .short("n")
@@ -49,12 +47,47 @@ fn main() {
                 .value_name("INITIATED_CONNECTIONS_PER_NODE")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("channel_capacity")
+                .long("channel_capacity")
+                .value_name("CHANNEL_CAPACITY")
+                .help("How many messages a connection buffers before the messages sent through it are dropped. Default: 64")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("worker_threads")
+                .long("worker_threads")
+                .value_name("THREADS")
+                .help("The threads running the nodes. Default: one per core")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("blocking_threads")
+                .long("blocking_threads")
+                .value_name("THREADS")
+                .help("The threads validating the chains received, apart from the event loops of the nodes. Default: 512")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shards")
+                .long("shards")
+                .value_name("SHARDS")
+                .help("Spreads the nodes over as many single threaded runtimes, each on its own thread, instead of running them on the worker threads.")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("difficulty_factor")
                 .short("d")
                 .long("difficulty")
-                .value_name("DIFFICULTY_FACTOR")
-                .help("Number of times the minimum difficult is doubled")
+                .value_name("DIFFICULTY")
+                .help("Number of times the minimum difficulty is doubled, or a preset calibrated on this host: fast, normal or slow for a block every 1, 10 or 60 seconds. Default: 15")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("block_interval")
+                .long("block_interval")
+                .value_name("BLOCK_INTERVAL_IN_SECONDS")
+                .help("Calibrates the difficulty on this host for a block every BLOCK_INTERVAL_IN_SECONDS. Overrides the difficulty.")
                 .takes_value(true),
         )
         .arg(
@@ -65,6 +98,13 @@ fn main() {
                 .help("The duration of the simulation in seconds.")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("warm_up")
+                .long("warm_up")
+                .value_name("BLOCKS")
+                .help("Leaves the first blocks after the genesis one, and what the nodes did until a block above them was mined, out of the statistics.")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("mining_delay")
                 .short("m")
@@ -73,95 +113,575 @@ fn main() {
                 .help("The delay between every attempt of a node to mine a new block.")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("payment_delay")
+                .short("p")
+                .long("payment_delay")
+                .value_name("PAYMENT_DELAY_IN_MILLIS")
+                .help("The delay between every attempt of a node to send a payment.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("light_nodes")
+                .short("l")
+                .long("light_nodes")
+                .value_name("NUMBER_OF_LIGHT_NODES")
+                .help("How many nodes only follow the headers and verify transactions with Merkle proofs.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("load_generators")
+                .long("load_generators")
+                .value_name("NUMBER_OF_LOAD_GENERATORS")
+                .help("How many full nodes submit payments at the load rate, to measure the confirmed transactions per second.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("load_rate")
+                .long("load_rate")
+                .value_name("PAYMENTS_PER_SECOND")
+                .help("How many payments every load generator submits per second.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sybil_nodes")
+                .long("sybil_nodes")
+                .value_name("NUMBER_OF_SYBIL_NODES")
+                .help("How many full nodes a single adversary runs. They do not mine, and relay the chains to the honest nodes late or never.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sybil_delay")
+                .long("sybil_delay")
+                .value_name("SYBIL_DELAY_IN_MILLIS")
+                .help("How long the sybil nodes wait before relaying a chain to an honest node. They withhold the chains without it.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("byzantine_nodes")
+                .long("byzantine_nodes")
+                .value_name("NUMBER_OF_BYZANTINE_NODES")
+                .help("How many full nodes do not mine, and send their peers chains breaking the consensus rules instead.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("byzantine_delay")
+                .long("byzantine_delay")
+                .value_name("BYZANTINE_DELAY_IN_MILLIS")
+                .help("The delay between two invalid chains of a byzantine node.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("inbound_rate")
+                .long("inbound_rate")
+                .value_name("MESSAGES_PER_SECOND")
+                .help("The messages per second a node handles from each peer, after a burst of as many. The others are dropped. No limit by default.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max_inbound")
+                .long("max_inbound")
+                .value_name("CONNECTIONS")
+                .help("The connections the peers initiate a node keeps at most. Beyond, it evicts one of them. No limit by default.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max_outbound")
+                .long("max_outbound")
+                .value_name("CONNECTIONS")
+                .help("The connections a node initiates it keeps at most. Beyond, it evicts one of them. No limit by default.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("eviction")
+                .long("eviction")
+                .value_name("POLICY")
+                .help("Which peer a node evicts beyond its connection limits: the oldest one, or the one with the worst round trip time. Default: oldest")
+                .possible_values(&["oldest", "worst_latency"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("anchors")
+                .long("anchors")
+                .value_name("CONNECTIONS")
+                .help("The first connections a node initiates, which it never evicts so that the peers connecting to it cannot surround it. Default: 2")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ban_threshold")
+                .long("ban_threshold")
+                .value_name("BAN_THRESHOLD")
+                .help("The misbehavior score from which a node bans a peer. An invalid chain scores 20, the same chain sent twice 10, and a chain no stronger than a previous one 1.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ban_duration")
+                .long("ban_duration")
+                .value_name("BAN_DURATION_IN_SECONDS")
+                .help("How long a node refuses the connections of a peer it banned.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("compact_relay")
+                .long("compact_relay")
+                .help("Relays the new blocks as compact blocks, rebuilt by the peers from their mempools, and reports the bandwidth saved."),
+        )
+        .arg(
+            Arg::with_name("compression")
+                .long("compression")
+                .help("Measures the bytes of the blocks relayed on each connection once compressed with snappy, and reports the bandwidth saved."),
+        )
+        .arg(
+            Arg::with_name("transaction_gossip")
+                .long("transaction_gossip")
+                .help("Makes the full nodes announce the pending transactions to their peers, which ask for the ones they did not see, so that every miner can confirm them."),
+        )
+        .arg(
+            Arg::with_name("bloom_filters")
+                .long("bloom_filters")
+                .help("Makes the light nodes load Bloom filters of their addresses on the full nodes, which send them the matching transactions, and reports the false positives."),
+        )
+        .arg(
+            Arg::with_name("bloom_fp_rate")
+                .long("bloom_fp_rate")
+                .value_name("RATE")
+                .help("The false positive rate of the Bloom filters of the light nodes, between 0 and 1. Defaults to 0.01.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("prune")
+                .long("prune")
+                .value_name("DEPTH")
+                .help("Makes the full nodes drop the transactions of the blocks deeper than DEPTH but the coinbase ones, keeping the headers and the unspent outputs. The light nodes ask other peers for the proofs of the pruned blocks.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("hash_registry")
+                .long("hash_registry")
+                .help("Records every mined block hash and reports hash collisions, for debugging."),
+        )
+        .arg(
+            Arg::with_name("replace_by_fee")
+                .long("replace_by_fee")
+                .value_name("MIN_FEE_BUMP")
+                .help("Lets payments replace conflicting pending ones paying at least MIN_FEE_BUMP less fees. By default, the first seen payment wins.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("min_relay_fee_rate")
+                .long("min_relay_fee_rate")
+                .value_name("FEES_PER_KB")
+                .help("The nodes only relay and mine the payments paying at least FEES_PER_KB fees per kilobyte. The blocks are accepted whatever their payments.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max_tx_size")
+                .long("max_tx_size")
+                .value_name("BYTES")
+                .help("The nodes only relay and mine the payments of at most BYTES bytes. Default: 100000")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dust_threshold")
+                .long("dust_threshold")
+                .value_name("AMOUNT")
+                .help("The nodes only relay and mine the payments whose outputs are all worth AMOUNT at least.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("relay_policy_nodes")
+                .long("relay_policy_nodes")
+                .value_name("NODES")
+                .help("Only the first NODES full nodes apply the relay policy, the others relaying any valid payment. By default, every full node applies it.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("secp256k1")
+                .long("secp256k1")
+                .help("Signs the transactions with ECDSA over secp256k1, like Bitcoin, instead of Ed25519."),
+        )
+        .arg(
+            Arg::with_name("scenario")
+                .long("scenario")
+                .value_name("SCENARIO_FILE")
+                .help("A TOML file of timed events to apply to the network: partitions, node kills and latency changes.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("latency")
+                .long("latency")
+                .value_name("LATENCY_IN_MILLIS")
+                .help("The delay before a message sent is received. Default: 0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("latency_matrix")
+                .long("latency_matrix")
+                .value_name("CSV_FILE")
+                .help("Spreads the nodes over regions, in turn by address: a message takes half the round trip time between the regions of its sender and receiver, on top of the latency. The first line of the file names the regions after an empty cell, then each line gives the round trip times in milliseconds from a region to the others, in the same order.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("regions")
+                .long("regions")
+                .value_name("CSV_FILE")
+                .help("Assigns nodes to the regions of the latency matrix, one node per line: its address, then its region. The other nodes are assigned in turn.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("topology")
+                .long("topology")
+                .value_name("TOPOLOGY")
+                .help("How the nodes choose the peers they connect to. Default: random")
+                .possible_values(&["random", "ring"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .value_name("SEED")
+                .help("The seed of the random topology, for the same connections on every run.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("chains")
+                .long("chains")
+                .value_name("CHAINS")
+                .help("Spreads the nodes over as many independent chains, each with its own genesis block. Default: 1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cross_chain_fraction")
+                .long("cross_chain_fraction")
+                .value_name("FRACTION")
+                .help("The share of the connections between nodes of different chains. Default: 0.1")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("nat_fraction")
+                .long("nat_fraction")
+                .value_name("FRACTION")
+                .help("The share of the nodes behind a NAT: they connect to the others, but do not accept any connection, so only the reachable nodes form the backbone of the network. Default: 0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tui")
+                .long("tui")
+                .help("Shows a live dashboard of the chain heights, forks, message throughput and block intervals instead of the logs."),
+        )
+        .arg(
+            Arg::with_name("websocket")
+                .long("websocket")
+                .value_name("ADDRESS")
+                .help("Broadcasts the blocks mined, the blocks propagated and the link latencies as JSON to the WebSocket clients connected to ADDRESS, 127.0.0.1:9001 for instance.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("import_snapshot")
+                .long("import_snapshot")
+                .value_name("SNAPSHOT_FILE")
+                .help("Starts from the chain of a snapshot, along with its difficulty, hash function and signature scheme.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("export_snapshot")
+                .long("export_snapshot")
+                .value_name("SNAPSHOT_FILE")
+                .help("Writes the strongest chain to a snapshot once the simulation ran.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("import_utxo_snapshot")
+                .long("import_utxo_snapshot")
+                .value_name("UTXO_SNAPSHOT_FILE")
+                .help("Starts from the headers and the unspent outputs of a UTXO snapshot, trusted by the full nodes, which only validate the blocks mined after it.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("export_utxo_snapshot")
+                .long("export_utxo_snapshot")
+                .value_name("UTXO_SNAPSHOT_FILE")
+                .help("Writes the headers and the unspent outputs of the strongest chain to a UTXO snapshot once the simulation ran.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("record_trace")
+                .long("record_trace")
+                .value_name("TRACE_FILE")
+                .help("Writes the chains the nodes sent each other, with their senders, receivers and delivery times, to a trace file once the simulation ran.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("import_address_book")
+                .long("import_address_book")
+                .value_name("ADDRESS_BOOK_FILE")
+                .help("Restarts the nodes of a previous simulation: each node reconnects to the peers it knew, the ones gone or behind a NAT being replaced by peers of the topology.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("export_address_book")
+                .long("export_address_book")
+                .value_name("ADDRESS_BOOK_FILE")
+                .help("Writes the peers each node connected to, to an address book file once the simulation ran.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("hasher")
+                .long("hasher")
+                .value_name("HASH_FUNCTION")
+                .help("The hash function of the proof of work. Default: sha256")
+                .possible_values(&["sha256", "double_sha256", "sha512_256"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("network")
+                .long("network")
+                .value_name("NETWORK")
+                .help("The rules of the chain: its coinbase schedule, its coinbase maturity and its maximum block size. Without a difficulty, the difficulty is calibrated for the block interval of the network: 10s for main, 5s for testnet and 1s for regtest. Default: main")
+                .possible_values(&["main", "testnet", "regtest"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("genesis")
+                .long("genesis")
+                .value_name("GENESIS_FILE")
+                .help("Builds the genesis block from the definition of a TOML file: its timestamp, its premined outputs and its difficulty, calibrated otherwise.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("export_genesis")
+                .long("export_genesis")
+                .value_name("GENESIS_FILE")
+                .help("Writes the definition of the genesis block, difficulty included, so that other runs and processes start from the same one.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("consensus")
+                .long("consensus")
+                .value_name("CONSENSUS")
+                .help("pow to let any node mine the blocks, poa for proof of authority: only the authorities produce the blocks, which they sign, and the nodes reject the others. pos for proof of stake: the leader elected for each block among the validators, in proportion to their random stakes, produces it. The proof of work still paces the blocks. Default: pow")
+                .possible_values(&["pow", "poa", "pos"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("authorities")
+                .long("authorities")
+                .value_name("NUMBER_OF_AUTHORITIES")
+                .help("In proof of authority or proof of stake, how many of the honest full nodes are authorities or validators, the first ones. Default: all of them")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("finality")
+                .long("finality")
+                .value_name("CHECKPOINT_INTERVAL")
+                .help("Makes the honest full nodes vote on a checkpoint every CHECKPOINT_INTERVAL blocks, with signed votes relayed by the nodes, and report the checkpoints 2/3 of the voting weight voted for as finalized. The authorities or validators vote instead if any, the validators weighted by their stakes.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("split_threshold")
+                .long("split_threshold")
+                .value_name("SECONDS")
+                .help("Samples the heads of the honest full nodes while they run, and reports the splits of the network across competing heads lasting longer than SECONDS, with the heads and the nodes on each of them.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("hashrate_shock")
+                .long("hashrate_shock")
+                .value_name("AT_SECS:MINERS:HASHRATE")
+                .help("Makes the first MINERS share of the honest miners mine at HASHRATE times their initial hash rate from AT_SECS seconds on, 0 to stop mining: 60:0.5:0 for half of them to stop after a minute. Repeat the flag for several shocks. The block intervals are reported before and after each shock.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("worker")
+                .long("worker")
+                .value_name("INDEX")
+                .help("Only runs the INDEX-th share of the nodes, connected to the ones of the other workers over TCP. Set by the orchestrator binary, which launches the workers.")
+                .takes_value(true)
+                .requires("workers"),
+        )
+        .arg(
+            Arg::with_name("workers")
+                .long("workers")
+                .value_name("COUNT")
+                .help("The number of workers the nodes are shared by, along with --worker.")
+                .takes_value(true)
+                .requires("worker"),
+        )
         .get_matches();
 
-    let number_of_nodes: u32 = parse_unsigned_integer(
-        matches.value_of("number_of_nodes"),
-        "2048",
-        100000,
-        "Invalid number of nodes, expected [1-100000]",
-    );
-
-    let initiated_connections_per_node: u8 = parse_unsigned_integer(
-        matches.value_of("initiated_connections_per_node"),
-        "3",
-        255,
-        "Invalid number of initiated connections per node, expected [1-255]",
-    );
-
-    let difficulty_factor: u8 = parse_unsigned_integer(
-        matches.value_of("difficulty_factor"),
-        "15",
-        224,
-        "Invalid difficulty factor, expected [1-224]",
-    );
-
-    let duration_in_seconds: u64 = parse_unsigned_integer(
-        matches.value_of("duration_in_seconds"),
-        "30",
-        999999,
-        "Invalid duration in seconds, expected [1-999999]",
-    );
+    let file_config = match matches.value_of("config") {
+        Some(path) => SimulationConfig::from_file(path).expect("Could not read the configuration"),
+        None => SimulationConfig::new(),
+    };
 
-    let mining_delay: u64 = parse_unsigned_integer(
-        matches.value_of("mining_delay"),
-        "10",
-        999999,
-        "Invalid hash duration in milliseconds, expected [1-999999]",
-    );
+    let flags_config = SimulationConfig {
+        network_size: parse_flag(&matches, "number_of_nodes", "Invalid number of nodes, expected [1-100000]"),
+        connections: parse_flag(
+            &matches,
+            "initiated_connections_per_node",
+            "Invalid number of initiated connections per node, expected [1-255]",
+        ),
+        channel_capacity: parse_flag(&matches, "channel_capacity", "Invalid channel capacity, expected [0-999999]"),
+        worker_threads: parse_flag(&matches, "worker_threads", "Invalid number of worker threads, expected [1-1024]"),
+        blocking_threads: parse_flag(&matches, "blocking_threads", "Invalid number of blocking threads, expected [1-4096]"),
+        shards: parse_flag(&matches, "shards", "Invalid number of shards, expected [1-1024]"),
+        difficulty: matches
+            .value_of("difficulty_factor")
+            .map(|raw_setting| raw_setting.parse().unwrap_or_else(|err: String| panic!("{}", err))),
+        block_interval: parse_flag(&matches, "block_interval", "Invalid block interval in seconds"),
+        duration_in_seconds: parse_flag(
+            &matches,
+            "duration_in_seconds",
+            "Invalid duration in seconds, expected [1-999999]",
+        ),
+        warm_up: parse_flag(&matches, "warm_up", "Invalid number of warm-up blocks, expected [0-999999]"),
+        mining_delay: parse_flag(
+            &matches,
+            "mining_delay",
+            "Invalid hash duration in milliseconds, expected [1-999999]",
+        ),
+        payment_delay: parse_flag(
+            &matches,
+            "payment_delay",
+            "Invalid payment delay in milliseconds, expected [1-999999]",
+        ),
+        light_nodes: parse_flag(
+            &matches,
+            "light_nodes",
+            "Invalid number of light nodes, expected [0-NUMBER_OF_NODES]",
+        ),
+        load_generators: parse_flag(
+            &matches,
+            "load_generators",
+            "Invalid number of load generators, expected [0-NUMBER_OF_FULL_NODES]",
+        ),
+        load_rate: parse_flag(&matches, "load_rate", "Invalid load rate, expected [1-1000]"),
+        sybil_nodes: parse_flag(
+            &matches,
+            "sybil_nodes",
+            "Invalid number of sybil nodes, expected [0-NUMBER_OF_FULL_NODES]",
+        ),
+        sybil_delay: parse_flag(&matches, "sybil_delay", "Invalid sybil delay in milliseconds, expected [0-999999]"),
+        byzantine_nodes: parse_flag(
+            &matches,
+            "byzantine_nodes",
+            "Invalid number of byzantine nodes, expected [0-NUMBER_OF_FULL_NODES]",
+        ),
+        byzantine_delay: parse_flag(
+            &matches,
+            "byzantine_delay",
+            "Invalid byzantine delay in milliseconds, expected [1-999999]",
+        ),
+        inbound_rate: parse_flag(
+            &matches,
+            "inbound_rate",
+            "Invalid inbound rate in messages per second, expected [1-999999]",
+        ),
+        max_inbound: parse_flag(&matches, "max_inbound", "Invalid maximum inbound connections, expected [0-999999]"),
+        max_outbound: parse_flag(&matches, "max_outbound", "Invalid maximum outbound connections, expected [1-255]"),
+        eviction: matches.value_of("eviction").map(str::to_owned),
+        anchors: parse_flag(&matches, "anchors", "Invalid number of anchors, expected [0-255]"),
+        ban_threshold: parse_flag(&matches, "ban_threshold", "Invalid ban threshold, expected [1-999999]"),
+        ban_duration: parse_flag(
+            &matches,
+            "ban_duration",
+            "Invalid ban duration in seconds, expected [0-999999]",
+        ),
+        compact_relay: present_flag(&matches, "compact_relay"),
+        compression: present_flag(&matches, "compression"),
+        transaction_gossip: present_flag(&matches, "transaction_gossip"),
+        bloom_filters: present_flag(&matches, "bloom_filters"),
+        bloom_fp_rate: parse_flag(&matches, "bloom_fp_rate", "Invalid Bloom filter false positive rate"),
+        prune: parse_flag(&matches, "prune", "Invalid prune depth, expected [6-999999]"),
+        hash_registry: present_flag(&matches, "hash_registry"),
+        replace_by_fee: parse_flag(&matches, "replace_by_fee", "Invalid minimum fee bump, expected [0-999999]"),
+        min_relay_fee_rate: parse_flag(
+            &matches,
+            "min_relay_fee_rate",
+            "Invalid minimum relay fee rate, expected [0-99999999]",
+        ),
+        max_tx_size: parse_flag(&matches, "max_tx_size", "Invalid maximum transaction size, expected [1-1000000]"),
+        dust_threshold: parse_flag(&matches, "dust_threshold", "Invalid dust threshold, expected [0-999999]"),
+        relay_policy_nodes: parse_flag(&matches, "relay_policy_nodes", "Invalid number of nodes, expected [0-999999]"),
+        secp256k1: present_flag(&matches, "secp256k1"),
+        hasher: matches.value_of("hasher").map(str::to_owned),
+        network: matches.value_of("network").map(str::to_owned),
+        genesis: matches.value_of("genesis").map(str::to_owned),
+        export_genesis: matches.value_of("export_genesis").map(str::to_owned),
+        consensus: matches.value_of("consensus").map(str::to_owned),
+        authorities: parse_flag(
+            &matches,
+            "authorities",
+            "Invalid number of authorities, expected [1-NUMBER_OF_HONEST_FULL_NODES]",
+        ),
+        finality: parse_flag(&matches, "finality", "Invalid checkpoint interval, expected [1-999999]"),
+        split_threshold: parse_flag(
+            &matches,
+            "split_threshold",
+            "Invalid split threshold in seconds, expected [1-999999]",
+        ),
+        hashrate_shock: matches.values_of("hashrate_shock").map(|raw_shocks| {
+            raw_shocks
+                .map(|raw_shock| raw_shock.parse().unwrap_or_else(|err: String| panic!("{}", err)))
+                .collect()
+        }),
+        scenario: matches.value_of("scenario").map(str::to_owned),
+        latency: parse_flag(&matches, "latency", "Invalid latency in milliseconds, expected [0-999999]"),
+        latency_matrix: matches.value_of("latency_matrix").map(str::to_owned),
+        regions: matches.value_of("regions").map(str::to_owned),
+        seed: parse_flag(&matches, "seed", "Invalid seed, expected [0-2^64)"),
+        topology: matches.value_of("topology").map(str::to_owned),
+        chains: parse_flag(&matches, "chains", "Invalid number of chains, expected [1-64]"),
+        cross_chain_fraction: parse_flag(
+            &matches,
+            "cross_chain_fraction",
+            "Invalid cross-chain fraction, expected [0-1]",
+        ),
+        nat_fraction: parse_flag(&matches, "nat_fraction", "Invalid NAT fraction, expected [0-1)"),
+        tui: present_flag(&matches, "tui"),
+        websocket: matches.value_of("websocket").map(str::to_owned),
+        import_snapshot: matches.value_of("import_snapshot").map(str::to_owned),
+        export_snapshot: matches.value_of("export_snapshot").map(str::to_owned),
+        import_utxo_snapshot: matches.value_of("import_utxo_snapshot").map(str::to_owned),
+        export_utxo_snapshot: matches.value_of("export_utxo_snapshot").map(str::to_owned),
+        record_trace: matches.value_of("record_trace").map(str::to_owned),
+        import_address_book: matches.value_of("import_address_book").map(str::to_owned),
+        export_address_book: matches.value_of("export_address_book").map(str::to_owned),
+    };
 
-    pow_network_simulation(
-        number_of_nodes,
-        initiated_connections_per_node,
-        difficulty_factor,
-        Duration::from_secs(duration_in_seconds),
-        Duration::from_millis(mining_delay),
-    )
-}
-
-pub fn pow_network_simulation(
-    number_of_nodes: u32,
-    initiated_connections_per_node: u8,
-    difficulty_factor: u8,
-    duration: Duration,
-    mining_attempt_delay: Duration,
-) {
-    // Set up a chain.
-    let mut difficulty = Difficulty::min_difficulty();
-    for _i in 0u8..difficulty_factor {
-        difficulty.increase();
+    let config = file_config.overridden_by(flags_config);
+    let worker_index: Option<u32> = parse_flag(&matches, "worker", "Invalid worker index, expected [0-COUNT)");
+    let workers: Option<u32> = parse_flag(&matches, "workers", "Invalid number of workers, expected [1-1024]");
+    let result = match (worker_index, workers) {
+        (Some(index), Some(workers)) => {
+            Worker::new(index, workers).and_then(|mut worker| pow::simulation::run_worker(&config, &mut worker))
+        }
+        _ => pow::simulation::run(&config),
+    };
+    if let Err(err) = result {
+        error!("{}", err);
+        process::exit(1);
     }
-
-    info!("Chain difficulty threshold: {:?}", difficulty);
-
-    let chain = Arc::new(Chain::init_new(difficulty));
-    let node_id = AtomicUsize::new(0);
-
-    // Run the blockchain network.
-    let network = Network::new(number_of_nodes, initiated_connections_per_node);
-    network.run(
-        move || {
-            let node_id = node_id.fetch_add(1, Ordering::Relaxed) as u32;
-            PowNode::new(node_id, chain.clone(), mining_attempt_delay)
-        },
-        duration,
-    );
 }
 
-pub fn parse_unsigned_integer<I>(
-    raw_value: Option<&str>,
-    default: &str,
-    max_value: I,
-    error_message: &'static str,
-) -> I
+/// Parses the value of a CLI flag, if given.
+fn parse_flag<T>(matches: &ArgMatches, name: &str, error_message: &'static str) -> Option<T>
 where
-    I: FromStr<Err = ParseIntError> + Debug + PartialOrd,
+    T: FromStr,
+    T::Err: Display,
 {
-    let value = raw_value.unwrap_or(default).parse().expect(error_message);
+    matches
+        .value_of(name)
+        .map(|raw_value| raw_value.parse().unwrap_or_else(|err| panic!("{}: {}", error_message, err)))
+}
 
-    if value > max_value {
-        panic!(error_message);
+/// Whether a CLI switch is on, none if it is not given so that the configuration file decides.
+fn present_flag(matches: &ArgMatches, name: &str) -> Option<bool> {
+    if matches.is_present(name) {
+        Some(true)
     } else {
-        value
+        None
     }
 }
@@ -0,0 +1,398 @@
+//! Spreads a simulation over several processes, for networks larger than a single process
+//! runs.
+//!
+//! The `orchestrator` binary launches the simulation binary once per process, the workers,
+//! with the same flags along with `--worker INDEX --workers COUNT`. They speak over their
+//! standard input and output, the logs staying on the standard error:
+//!
+//! ```text
+//! worker:       listening 0 512 127.0.0.1:41234
+//! orchestrator: nodes 512 1024 127.0.0.1:41235
+//! orchestrator: start
+//! worker:       report 0 512 12 0 0 37 00a3...
+//! ```
+//!
+//! Each worker hosts an equal share of the nodes and listens for the connections of the other
+//! workers to them with a `TcpBridge`, whose address it tells the orchestrator. Once they all
+//! listen, the orchestrator gives each of them the addresses of the others and starts them
+//! together. Each worker then runs its nodes for the duration of the simulation, and reports
+//! the messages its connections dropped, delayed and lost along with the strongest chain of
+//! its nodes, which the orchestrator gathers.
+//!
+//! The workers build the same network, each running its own share of it: the topology needs a
+//! seed, and the genesis block must not depend on the host or on random keys. The statistics
+//! each worker logs are about its own nodes.
+
+use crate::blockchain::{ChainCodec, Message};
+use crate::error::Error;
+use btclike::crypto::Hash;
+use netsim::network::TcpBridge;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+
+/// The most processes a simulation is spread over.
+pub const MAX_PROCESSES: u32 = 1024;
+
+/// The nodes of the given worker, out of the given number of workers sharing them equally.
+pub fn share(number_of_nodes: u32, workers: u32, index: u32) -> Range<u32> {
+    let bound = |index: u32| (number_of_nodes as u64 * index as u64 / workers as u64) as u32;
+    bound(index)..bound(index + 1)
+}
+
+/// What a worker reports once its nodes stopped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkerReport {
+    /// The nodes the worker hosted.
+    pub nodes: Range<u32>,
+    pub dropped_messages: usize,
+    pub delayed_messages: usize,
+    pub lost_messages: usize,
+    /// The height of the strongest chain of its nodes.
+    pub height: u32,
+    /// The head of the strongest chain of its nodes.
+    pub head: Hash,
+}
+
+impl fmt::Display for WorkerReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {} {}",
+            self.nodes.start,
+            self.nodes.end,
+            self.dropped_messages,
+            self.delayed_messages,
+            self.lost_messages,
+            self.height,
+            self.head
+        )
+    }
+}
+
+impl FromStr for WorkerReport {
+    type Err = String;
+
+    fn from_str(raw_report: &str) -> Result<WorkerReport, String> {
+        let fields: Vec<&str> = raw_report.split_whitespace().collect();
+        if fields.len() != 7 {
+            return Err(format!("Invalid worker report: {}", raw_report));
+        }
+        let invalid = |_err| format!("Invalid worker report: {}", raw_report);
+        Ok(WorkerReport {
+            nodes: fields[0].parse().map_err(invalid)?..fields[1].parse().map_err(invalid)?,
+            dropped_messages: fields[2].parse().map_err(invalid)?,
+            delayed_messages: fields[3].parse().map_err(invalid)?,
+            lost_messages: fields[4].parse().map_err(invalid)?,
+            height: fields[5].parse().map_err(invalid)?,
+            head: fields[6]
+                .parse()
+                .map_err(|_err| format!("Invalid worker report: {}", raw_report))?,
+        })
+    }
+}
+
+/// A process running a share of the nodes of a simulation, see `simulation::run_worker`.
+pub struct Worker {
+    index: u32,
+    workers: u32,
+    /// Where the orchestrator writes to, the standard input by default.
+    input: Box<dyn BufRead + Send>,
+    /// Where the orchestrator reads from, the standard output by default.
+    output: Box<dyn Write + Send>,
+}
+
+impl Worker {
+    /// The worker of the given index out of the given number, speaking to the orchestrator
+    /// over its standard input and output.
+    pub fn new(index: u32, workers: u32) -> Result<Worker, Error> {
+        if !(1..=MAX_PROCESSES).contains(&workers) || index >= workers {
+            return Err(Error::Config(format!(
+                "Invalid worker {} of {}, expected [0-{}) of [1-{}]",
+                index, workers, workers, MAX_PROCESSES
+            )));
+        }
+
+        Ok(Worker {
+            index,
+            workers,
+            input: Box::new(BufReader::new(io::stdin())),
+            output: Box::new(io::stdout()),
+        })
+    }
+
+    /// Speaks to the orchestrator over the given streams instead, for a worker embedded in
+    /// another program.
+    pub fn with_io<R, W>(mut self, input: R, output: W) -> Worker
+    where
+        R: BufRead + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        self.input = Box::new(input);
+        self.output = Box::new(output);
+        self
+    }
+
+    /// The nodes of this worker, out of the given number.
+    pub fn nodes(&self, number_of_nodes: u32) -> Range<u32> {
+        share(number_of_nodes, self.workers, self.index)
+    }
+
+    /// Listens for the connections of the other workers to the nodes of this one, tells the
+    /// orchestrator where, then waits for the addresses of the others until it is told to
+    /// start.
+    pub(crate) fn join(&mut self, number_of_nodes: u32) -> Result<TcpBridge<Message>, Error> {
+        if self.workers > number_of_nodes {
+            return Err(Error::Config(format!(
+                "{} workers for {} nodes, expected at most one per node",
+                self.workers, number_of_nodes
+            )));
+        }
+        let nodes = self.nodes(number_of_nodes);
+        let mut bridge = TcpBridge::bind(SocketAddr::from(([127, 0, 0, 1], 0)), nodes.clone(), ChainCodec)
+            .map_err(|err| Error::Orchestration(format!("Could not listen for the other workers: {}", err)))?;
+        let address = bridge.local_addr().map_err(orchestration_error)?;
+        writeln!(self.output, "listening {} {} {}", nodes.start, nodes.end, address).map_err(orchestration_error)?;
+        self.output.flush().map_err(orchestration_error)?;
+
+        loop {
+            let line = read_line(&mut self.input)?.ok_or_else(|| {
+                Error::Orchestration("The orchestrator stopped before starting the worker".to_owned())
+            })?;
+            if line == "start" {
+                return Ok(bridge);
+            }
+            match line.split_once(' ') {
+                Some(("nodes", remote_nodes)) => {
+                    let (nodes, address) = parse_nodes(remote_nodes)?;
+                    bridge = bridge.with_remote_nodes(nodes, address);
+                }
+                _ => return Err(Error::Orchestration(format!("Unexpected message: {}", line))),
+            }
+        }
+    }
+
+    /// Tells the orchestrator what this worker measured.
+    pub(crate) fn report(&mut self, report: &WorkerReport) -> Result<(), Error> {
+        writeln!(self.output, "report {}", report).map_err(orchestration_error)?;
+        self.output.flush().map_err(orchestration_error)
+    }
+}
+
+/// Launches the workers of a simulation, each of them a process of the simulation binary,
+/// and gathers their reports.
+pub struct Orchestrator {
+    program: PathBuf,
+    args: Vec<String>,
+    processes: u32,
+}
+
+impl Orchestrator {
+    /// Runs the given simulation binary in the given number of processes.
+    pub fn new(program: PathBuf, processes: u32) -> Orchestrator {
+        Orchestrator {
+            program,
+            args: vec![],
+            processes,
+        }
+    }
+
+    /// The flags of the simulation, given to every worker.
+    pub fn with_args<I>(mut self, args: I) -> Orchestrator
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.args.extend(args);
+        self
+    }
+
+    /// Launches the workers, starts them once they all listen, then waits for their reports,
+    /// in the order of their nodes. The workers still running are killed if one of them fails.
+    pub fn run(&self) -> Result<Vec<WorkerReport>, Error> {
+        if !(1..=MAX_PROCESSES).contains(&self.processes) {
+            return Err(Error::Config(format!(
+                "Invalid number of processes {}, expected [1-{}]",
+                self.processes, MAX_PROCESSES
+            )));
+        }
+
+        let mut processes = Vec::with_capacity(self.processes as usize);
+        let mut links = Vec::with_capacity(self.processes as usize);
+        for index in 0..self.processes {
+            let mut child = Command::new(&self.program)
+                .args(&self.args)
+                .args(["--worker", &index.to_string(), "--workers", &self.processes.to_string()])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .map_err(|err| {
+                    Error::Orchestration(format!("Could not launch {}: {}", self.program.display(), err))
+                })?;
+            let input = child.stdin.take().expect("The input of the worker is piped");
+            let output = BufReader::new(child.stdout.take().expect("The output of the worker is piped"));
+            processes.push(WorkerProcess(child));
+            links.push((output, input));
+        }
+
+        let reports = coordinate(&mut links)?;
+        drop(links);
+        for (index, mut process) in processes.into_iter().enumerate() {
+            let status = process.0.wait().map_err(orchestration_error)?;
+            if !status.success() {
+                return Err(Error::Orchestration(format!("Worker #{} failed: {}", index, status)));
+            }
+        }
+        Ok(reports)
+    }
+}
+
+/// A worker process, killed if it is still running when dropped.
+struct WorkerProcess(Child);
+
+impl Drop for WorkerProcess {
+    fn drop(&mut self) {
+        if let Ok(None) = self.0.try_wait() {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+}
+
+/// Exchanges the addresses of the workers, given their output and input, starts them, then
+/// gathers their reports.
+fn coordinate<R, W>(links: &mut [(R, W)]) -> Result<Vec<WorkerReport>, Error>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut listening = Vec::with_capacity(links.len());
+    for (index, (output, _input)) in links.iter_mut().enumerate() {
+        listening.push(parse_nodes(&read_message(output, index, "listening")?)?);
+    }
+
+    for (index, (_output, input)) in links.iter_mut().enumerate() {
+        let others = listening.iter().enumerate().filter(|(other, _listening)| *other != index);
+        for (_other, (nodes, address)) in others {
+            writeln!(input, "nodes {} {} {}", nodes.start, nodes.end, address).map_err(orchestration_error)?;
+        }
+        writeln!(input, "start").map_err(orchestration_error)?;
+        input.flush().map_err(orchestration_error)?;
+    }
+
+    links
+        .iter_mut()
+        .enumerate()
+        .map(|(index, (output, _input))| {
+            read_message(output, index, "report")?
+                .parse()
+                .map_err(Error::Orchestration)
+        })
+        .collect()
+}
+
+/// The content of the next message of the worker, which must be of the given kind.
+fn read_message<R: BufRead>(output: &mut R, index: usize, kind: &str) -> Result<String, Error> {
+    let line = read_line(output)?
+        .ok_or_else(|| Error::Orchestration(format!("Worker #{} stopped before its {} message", index, kind)))?;
+    match line.split_once(' ') {
+        Some((message_kind, content)) if message_kind == kind => Ok(content.to_owned()),
+        _ => Err(Error::Orchestration(format!("Unexpected message from worker #{}: {}", index, line))),
+    }
+}
+
+/// The next line, without its line break, none at the end of the stream.
+fn read_line<R: BufRead + ?Sized>(reader: &mut R) -> Result<Option<String>, Error> {
+    let mut line = String::new();
+    match reader.read_line(&mut line).map_err(orchestration_error)? {
+        0 => Ok(None),
+        _ => Ok(Some(line.trim_end().to_owned())),
+    }
+}
+
+/// The nodes of a worker and the address it listens on: `START END ADDRESS`.
+fn parse_nodes(raw_nodes: &str) -> Result<(Range<u32>, SocketAddr), Error> {
+    let invalid = || Error::Orchestration(format!("Invalid nodes of a worker: {}", raw_nodes));
+    let fields: Vec<&str> = raw_nodes.split_whitespace().collect();
+    match fields[..] {
+        [start, end, address] => Ok((
+            start.parse().map_err(|_err| invalid())?..end.parse().map_err(|_err| invalid())?,
+            address.parse().map_err(|_err| invalid())?,
+        )),
+        _ => Err(invalid()),
+    }
+}
+
+fn orchestration_error(err: io::Error) -> Error {
+    Error::Orchestration(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{DifficultySetting, HashrateShock};
+    use crate::simulation;
+    use crate::SimulationConfig;
+    use std::thread;
+
+    #[test]
+    fn shares_every_node_once() {
+        for (number_of_nodes, workers) in [(16, 2), (10, 3), (7, 7), (1000, 1024)] {
+            let shares: Vec<Range<u32>> = (0..workers).map(|index| share(number_of_nodes, workers, index)).collect();
+            assert_eq!(0, shares[0].start);
+            assert_eq!(number_of_nodes, shares[shares.len() - 1].end);
+            assert!(shares.windows(2).all(|shares| shares[0].end == shares[1].start));
+        }
+    }
+
+    #[test]
+    fn parses_the_reports_it_displays() {
+        let report = WorkerReport {
+            nodes: 8..16,
+            dropped_messages: 1,
+            delayed_messages: 2,
+            lost_messages: 3,
+            height: 37,
+            head: Hash::from_bytes(&[7; 32]).unwrap(),
+        };
+        assert_eq!(Ok(report.clone()), report.to_string().parse());
+        assert!("8 16 1 2 3 37".parse::<WorkerReport>().is_err());
+    }
+
+    #[test]
+    fn relays_the_chains_between_the_workers() {
+        // Only the nodes of the second worker mine: those of the first learn of its chains
+        // over TCP.
+        let config = SimulationConfig::new()
+            .with_network_size(8)
+            .with_duration_in_seconds(2)
+            .with_seed(7)
+            .with_difficulty(DifficultySetting::Factor(4))
+            .with_hashrate_shock(HashrateShock {
+                at_secs: 0.0,
+                miners: 0.5,
+                hashrate: 0.0,
+            });
+
+        let mut links = vec![];
+        let mut workers = vec![];
+        for index in 0..2 {
+            let (input_reader, input) = io::pipe().unwrap();
+            let (output_reader, output) = io::pipe().unwrap();
+            let mut worker = Worker::new(index, 2).unwrap().with_io(BufReader::new(input_reader), output);
+            let config = config.clone();
+            workers.push(thread::spawn(move || simulation::run_worker(&config, &mut worker)));
+            links.push((BufReader::new(output_reader), input));
+        }
+        let reports = coordinate(&mut links).unwrap();
+        for worker in workers {
+            worker.join().unwrap().unwrap();
+        }
+
+        assert_eq!(vec![0..4, 4..8], reports.iter().map(|report| report.nodes.clone()).collect::<Vec<_>>());
+        assert!(reports[0].height > 0);
+    }
+}
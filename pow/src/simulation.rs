@@ -0,0 +1,1800 @@
+use crate::blockchain::{
+    average_hashrate, competing_heads, Authorities, BanPolicy, BlockEncoder, BlockIntervalStats, BootstrapStats,
+    ByzantineNode, Chain, ChainCodec, ConnectionLimits, Difficulty, DifficultySetting, DoubleSpendCounter,
+    EvictionPolicy, FilterStats, FinalityStats, FinalityVoters, GossipStats, HashRegistry, HashrateShock,
+    LatencyStats, LightNode, LogCapture, Message, MetricsBus, MisbehaviorStats, NodeChains, NodeLogger, NodeMetric,
+    PowNode, PropagationStats, PruningHorizon, PruningStats, RelayStats, RewardStats, SimulationNode, SplitDetector,
+    SplitEvent, StaleStats, StrongestChain, SybilAdversary, ThroughputStats, ValidatorRegistry, ValidatorStats, WarmUp,
+    CONFIRMATION_DEPTH, DEFAULT_ANCHORS, DEFAULT_BAN_DURATION, DEFAULT_BAN_THRESHOLD, DEFAULT_FALSE_POSITIVE_RATE,
+    MIN_PRUNE_DEPTH, SPLIT_SAMPLE_INTERVAL,
+};
+use crate::config::SimulationConfig;
+#[cfg(feature = "tui")]
+use crate::dashboard::Dashboard;
+use crate::error::Error;
+use crate::orchestrator::{Worker, WorkerReport};
+#[cfg(feature = "websocket")]
+use crate::event_server::EventServer;
+use btclike::chain_params::{ChainParams, MAX_BLOCK_SIZE};
+use btclike::crypto::{Hasher, SignatureAlgorithm};
+use btclike::genesis::GenesisConfig;
+use btclike::mempool::ConflictPolicy;
+use btclike::policy::{RelayPolicy, DEFAULT_MAX_TX_SIZE};
+use btclike::transaction::Address;
+use btclike::wallet::Wallet;
+use netsim::clock::RuntimeConfig;
+use netsim::network::{AddressBook, BandwidthMeter, Compression, LatencyMatrix, Recorder, Scenario, Topology};
+use netsim::simulation::{LogSink, MetricsSink, SimulationBuilder, SimulationReport};
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The parameters of a simulation, once the default values are given to the missing ones.
+struct Parameters {
+    number_of_nodes: u32,
+    number_of_light_nodes: u32,
+    /// The full nodes submitting payments at the load rate, after the light ones.
+    number_of_load_generators: u32,
+    /// The delay between two payments of a load generator.
+    load_payment_delay: Duration,
+    /// The nodes sending invalid chains, right before the sybils.
+    number_of_byzantine_nodes: u32,
+    /// The delay between two invalid chains of a byzantine node.
+    forgery_delay: Duration,
+    /// The sybils of the adversary are the last nodes, if any.
+    sybil_adversary: Option<SybilAdversary>,
+    ban_policy: BanPolicy,
+    /// The messages per second a node handles from each peer, none for no limit.
+    inbound_rate: Option<u32>,
+    /// The connections the full nodes keep in each direction, none for no limit.
+    connection_limits: Option<ConnectionLimits>,
+    with_compact_relay: bool,
+    /// How the blocks relayed are compressed, none if their bandwidth is not measured.
+    compression: Option<Compression>,
+    with_transaction_gossip: bool,
+    /// The false positive rate of the Bloom filters of the light nodes, none if they load none.
+    bloom_fp_rate: Option<f64>,
+    /// The number of blocks whose bodies the full nodes keep, none to keep them all.
+    prune_depth: Option<u32>,
+    initiated_connections_per_node: u8,
+    /// The independent chains of the network, one unless the topology is a multi-chain one.
+    number_of_chains: u32,
+    topology: Topology,
+    /// The share of the nodes behind a NAT, which only initiate connections.
+    nat_fraction: f64,
+    channel_capacity: usize,
+    runtime_config: RuntimeConfig,
+    latency: Duration,
+    /// The latencies between the regions the nodes are spread over, if any.
+    latency_matrix: Option<LatencyMatrix>,
+    difficulty_factor: u8,
+    /// Whether the difficulty factor was calibrated on this host.
+    calibrated_difficulty: bool,
+    duration: Duration,
+    /// The first blocks after the genesis one, left out of the statistics.
+    warm_up_blocks: u32,
+    mining_attempt_delay: Duration,
+    payment_attempt_delay: Duration,
+    with_hash_registry: bool,
+    conflict_policy: ConflictPolicy,
+    /// The rules the mempools of the first full nodes apply on top of the consensus ones, none
+    /// if every node relays any valid payment.
+    relay_policy: Option<RelayPolicy>,
+    /// The number of full nodes applying the relay policy, from the first one on.
+    relay_policy_nodes: u32,
+    signature_algorithm: SignatureAlgorithm,
+    hasher: Hasher,
+    /// The rules of the chain, the main network's by default.
+    chain_params: ChainParams,
+    /// The definition of the genesis block. Without a difficulty, the calibrated one is used.
+    genesis: GenesisConfig,
+    /// The file to write the definition of the genesis block to, difficulty included.
+    export_genesis: Option<String>,
+    /// The honest full nodes producing the blocks of a proof of authority or proof of stake
+    /// chain, the first ones. None for proof of work.
+    number_of_authorities: Option<u32>,
+    /// Whether the authorities are validators with stakes, one of them leading each block.
+    with_proof_of_stake: bool,
+    /// The number of blocks between two checkpoints the nodes vote on, none for no finality
+    /// votes.
+    checkpoint_interval: Option<u32>,
+    /// How long the honest full nodes must stay on competing heads for the split to be
+    /// reported, none not to monitor the splits.
+    split_threshold: Option<Duration>,
+    /// The changes of the hash rate of the first honest miners while the simulation runs.
+    hashrate_shocks: Vec<HashrateShock>,
+    scenario: Scenario,
+    #[cfg_attr(not(feature = "tui"), allow(dead_code))]
+    with_dashboard: bool,
+    /// The address to broadcast the events on.
+    #[cfg_attr(not(feature = "websocket"), allow(dead_code))]
+    websocket: Option<String>,
+    /// The snapshot file of the chain to start from, instead of the genesis block.
+    import_snapshot: Option<String>,
+    /// The file to write the strongest chain to once the nodes stopped.
+    export_snapshot: Option<String>,
+    /// The UTXO snapshot file of the chain to start from, instead of the genesis block.
+    import_utxo_snapshot: Option<String>,
+    /// The file to write the headers and the unspent outputs of the strongest chain to once
+    /// the nodes stopped.
+    export_utxo_snapshot: Option<String>,
+    /// The file to write the chains delivered to the nodes to once they stopped.
+    record_trace: Option<String>,
+    /// The peers the nodes of a previous simulation knew, which they reconnect to.
+    address_book: Option<AddressBook>,
+    /// The file to write the peers of the nodes to once they stopped.
+    export_address_book: Option<String>,
+}
+
+impl Parameters {
+    fn new(config: &SimulationConfig) -> Result<Parameters, Error> {
+        let number_of_nodes = bounded(config.network_size, 2048, 1, 100000, "number of nodes")?;
+        let number_of_light_nodes = bounded(config.light_nodes, 0, 0, number_of_nodes, "number of light nodes")?;
+        let number_of_load_generators = bounded(
+            config.load_generators,
+            0,
+            0,
+            number_of_nodes - number_of_light_nodes,
+            "number of load generators",
+        )?;
+        let load_rate = bounded(config.load_rate, 10, 1, 1000, "load rate in payments per second")?;
+        let number_of_sybil_nodes = bounded(
+            config.sybil_nodes,
+            0,
+            0,
+            number_of_nodes - number_of_light_nodes - number_of_load_generators,
+            "number of sybil nodes",
+        )?;
+        let sybil_adversary = if number_of_sybil_nodes > 0 {
+            let adversary = SybilAdversary::new(number_of_nodes - number_of_sybil_nodes..number_of_nodes);
+            Some(match config.sybil_delay {
+                Some(delay) => adversary.with_relay_delay(Duration::from_millis(bounded(
+                    Some(delay),
+                    0,
+                    0,
+                    999999,
+                    "sybil delay in milliseconds",
+                )?)),
+                None => adversary,
+            })
+        } else {
+            None
+        };
+        let number_of_byzantine_nodes = bounded(
+            config.byzantine_nodes,
+            0,
+            0,
+            number_of_nodes - number_of_light_nodes - number_of_load_generators - number_of_sybil_nodes,
+            "number of byzantine nodes",
+        )?;
+        let mining_attempt_delay = bounded(config.mining_delay, 10, 1, 999999, "mining delay in milliseconds")?;
+
+        let hasher = match config.hasher.as_deref() {
+            None | Some("sha256") => Hasher::Sha256,
+            Some("double_sha256") => Hasher::DoubleSha256,
+            Some("sha512_256") => Hasher::Sha512Trunc256,
+            Some(other) => return Err(Error::Config(format!("Invalid hash function: {}", other))),
+        };
+
+        let number_of_honest_nodes =
+            number_of_nodes - number_of_light_nodes - number_of_sybil_nodes - number_of_byzantine_nodes;
+        let with_proof_of_stake = config.consensus.as_deref() == Some("pos");
+        let number_of_authorities = match config.consensus.as_deref() {
+            None | Some("pow") if config.authorities.is_some() => {
+                return Err(Error::Config(
+                    "Authorities need the proof of authority or proof of stake consensus".to_owned(),
+                ))
+            }
+            None | Some("pow") => None,
+            Some("poa") | Some("pos") => Some(bounded(
+                config.authorities,
+                number_of_honest_nodes,
+                1,
+                number_of_honest_nodes,
+                "number of authorities",
+            )?),
+            Some(other) => return Err(Error::Config(format!("Invalid consensus: {}", other))),
+        };
+
+        let hashrate_shocks = config.hashrate_shock.clone().unwrap_or_default();
+        for hashrate_shock in &hashrate_shocks {
+            hashrate_shock.validate().map_err(Error::Config)?;
+        }
+
+        let topology = match config.topology.as_deref() {
+            None | Some("random") => Topology::Random { seed: config.seed },
+            Some("ring") => Topology::Ring,
+            Some(other) => return Err(Error::Config(format!("Invalid topology: {}", other))),
+        };
+        let chains = bounded(config.chains, 1, 1, MAX_CHAINS, "number of chains")?;
+        let cross_chain_fraction = config.cross_chain_fraction.unwrap_or(DEFAULT_CROSS_CHAIN_FRACTION);
+        if !(0.0..=1.0).contains(&cross_chain_fraction) {
+            return Err(Error::Config(format!(
+                "Invalid cross-chain fraction {}, expected [0-1]",
+                cross_chain_fraction
+            )));
+        }
+        let topology = match topology {
+            _ if chains == 1 => topology,
+            Topology::Random { seed } => Topology::MultiChain {
+                chains,
+                cross_chain_fraction,
+                seed,
+            },
+            _ => return Err(Error::Config("Several chains need a random topology".to_owned())),
+        };
+        // Nodes that all are behind a NAT cannot connect to each other.
+        let nat_fraction = config.nat_fraction.unwrap_or(0.0);
+        if !(0.0..1.0).contains(&nat_fraction) {
+            return Err(Error::Config(format!("Invalid NAT fraction {}, expected [0-1)", nat_fraction)));
+        }
+
+        let chain_params = match config.network.as_deref() {
+            None => ChainParams::main(),
+            Some(network) => ChainParams::by_name(network)
+                .ok_or_else(|| Error::Config(format!("Invalid network: {}", network)))?,
+        };
+
+        // The difficulty of an explicit network is calibrated for its block interval.
+        let difficulty_setting = match config.block_interval {
+            Some(seconds) => DifficultySetting::BlockInterval(
+                Duration::try_from_secs_f64(seconds)
+                    .map_err(|_err| Error::Config(format!("Invalid block interval: {}", seconds)))?,
+            ),
+            None => config.difficulty.unwrap_or(match config.network {
+                Some(_) => DifficultySetting::BlockInterval(chain_params.target_block_interval()),
+                None => DifficultySetting::Factor(15),
+            }),
+        };
+
+        let conflict_policy = match config.replace_by_fee {
+            Some(min_fee_bump) => ConflictPolicy::ReplaceByFee {
+                min_fee_bump: bounded(Some(min_fee_bump), 0, 0, 999999, "minimum fee bump")?,
+            },
+            None => ConflictPolicy::FirstSeen,
+        };
+
+        let signature_algorithm = if config.secp256k1.unwrap_or(false) {
+            SignatureAlgorithm::Secp256k1
+        } else {
+            SignatureAlgorithm::Ed25519
+        };
+
+        let with_dashboard = config.tui.unwrap_or(false);
+        if with_dashboard && cfg!(not(feature = "tui")) {
+            return Err(Error::Config("The dashboard needs the tui feature".to_owned()));
+        }
+        if config.websocket.is_some() && cfg!(not(feature = "websocket")) {
+            return Err(Error::Config("The event stream needs the websocket feature".to_owned()));
+        }
+
+        let bloom_fp_rate = config.bloom_fp_rate.unwrap_or(DEFAULT_FALSE_POSITIVE_RATE);
+        if !(bloom_fp_rate > 0.0 && bloom_fp_rate < 1.0) {
+            return Err(Error::Config(format!(
+                "Invalid Bloom filter false positive rate {}, expected ]0-1[",
+                bloom_fp_rate
+            )));
+        }
+
+        let prune_depth = match config.prune {
+            Some(prune_depth) => Some(bounded(
+                Some(prune_depth),
+                MIN_PRUNE_DEPTH,
+                MIN_PRUNE_DEPTH,
+                999999,
+                "prune depth",
+            )?),
+            None => None,
+        };
+        // The strongest chain is validated from its genesis block to be exported.
+        let export = config.export_snapshot.is_some() || config.export_utxo_snapshot.is_some();
+        if prune_depth.is_some() && export {
+            return Err(Error::Config("Pruned chains cannot be exported".to_owned()));
+        }
+        if config.import_utxo_snapshot.is_some() && export {
+            return Err(Error::Config(
+                "A chain imported from a UTXO snapshot cannot be exported".to_owned(),
+            ));
+        }
+        // The snapshots do not keep the authorities, the validators nor the signatures of the
+        // blocks.
+        let import = config.import_snapshot.is_some() || config.import_utxo_snapshot.is_some();
+        if number_of_authorities.is_some() && (import || export) {
+            return Err(Error::Config(
+                "Proof of authority and proof of stake chains cannot be imported nor exported".to_owned(),
+            ));
+        }
+        // Nor the network of the chain, which must be the main one.
+        if chain_params != ChainParams::main() && (import || export) {
+            return Err(Error::Config(
+                "Only the chains of the main network can be imported or exported".to_owned(),
+            ));
+        }
+        // Nor the definition of the genesis block.
+        if (config.genesis.is_some() || config.export_genesis.is_some()) && (import || export) {
+            return Err(Error::Config(
+                "The chains of a genesis definition cannot be imported nor exported".to_owned(),
+            ));
+        }
+        let genesis = match config.genesis {
+            Some(ref path) => GenesisConfig::load(path)
+                .map_err(|err| Error::Config(format!("Invalid genesis definition {}: {}", path, err)))?,
+            None => GenesisConfig::default(),
+        };
+        // Every chain of a multi-chain network starts from a genesis block of its own.
+        if chains > 1 && (import || export) {
+            return Err(Error::Config(
+                "The chains of a multi-chain network cannot be imported nor exported".to_owned(),
+            ));
+        }
+        if config.import_snapshot.is_some() && config.import_utxo_snapshot.is_some() {
+            return Err(Error::Config(
+                "A chain snapshot and a UTXO snapshot cannot be imported together".to_owned(),
+            ));
+        }
+
+        let scenario = match config.scenario {
+            Some(ref path) => Scenario::from_file(path)?,
+            None => Scenario::new(),
+        };
+        let latency_matrix = match (&config.latency_matrix, &config.regions) {
+            (Some(path), None) => Some(LatencyMatrix::from_file(path)?),
+            (Some(path), Some(regions)) => Some(LatencyMatrix::from_file(path)?.with_mapping_file(regions)?),
+            (None, Some(_regions)) => return Err(Error::Config("The regions need a latency matrix".to_owned())),
+            (None, None) => None,
+        };
+        let address_book = match config.import_address_book {
+            Some(ref path) => Some(AddressBook::read(path)?),
+            None => None,
+        };
+
+        Ok(Parameters {
+            number_of_nodes,
+            number_of_light_nodes,
+            number_of_load_generators,
+            load_payment_delay: Duration::from_secs(1) / load_rate,
+            number_of_byzantine_nodes,
+            forgery_delay: Duration::from_millis(bounded(
+                config.byzantine_delay,
+                1000,
+                1,
+                999999,
+                "byzantine delay in milliseconds",
+            )?),
+            sybil_adversary,
+            ban_policy: BanPolicy::new()
+                .with_ban_threshold(bounded(
+                    config.ban_threshold,
+                    DEFAULT_BAN_THRESHOLD,
+                    1,
+                    999999,
+                    "ban threshold",
+                )?)
+                .with_ban_duration(Duration::from_secs(bounded(
+                    config.ban_duration,
+                    DEFAULT_BAN_DURATION.as_secs(),
+                    0,
+                    999999,
+                    "ban duration in seconds",
+                )?)),
+            inbound_rate: match config.inbound_rate {
+                Some(inbound_rate) => Some(bounded(
+                    Some(inbound_rate),
+                    1,
+                    1,
+                    999999,
+                    "inbound rate in messages per second",
+                )?),
+                None => None,
+            },
+            connection_limits: connection_limits(config)?,
+            initiated_connections_per_node: bounded(config.connections, 3, 1, 255, "number of initiated connections")?,
+            number_of_chains: chains,
+            topology,
+            nat_fraction,
+            channel_capacity: bounded(config.channel_capacity, 64, 0, 999999, "channel capacity")?,
+            runtime_config: runtime_config(config)?,
+            latency: Duration::from_millis(bounded(config.latency, 0, 0, 999999, "latency in milliseconds")?),
+            latency_matrix,
+            // A single validator, the leader, mines each block of a proof of stake chain.
+            difficulty_factor: difficulty_setting.difficulty_factor(
+                hasher,
+                if with_proof_of_stake {
+                    1
+                } else {
+                    number_of_authorities.unwrap_or(number_of_honest_nodes)
+                },
+                Duration::from_millis(mining_attempt_delay),
+            ),
+            calibrated_difficulty: matches!(difficulty_setting, DifficultySetting::BlockInterval(_)),
+            duration: Duration::from_secs(bounded(config.duration_in_seconds, 30, 1, 999999, "duration in seconds")?),
+            warm_up_blocks: bounded(config.warm_up, 0, 0, 999999, "number of warm-up blocks")?,
+            mining_attempt_delay: Duration::from_millis(mining_attempt_delay),
+            payment_attempt_delay: Duration::from_millis(bounded(
+                config.payment_delay,
+                1000,
+                1,
+                999999,
+                "payment delay in milliseconds",
+            )?),
+            with_compact_relay: config.compact_relay.unwrap_or(false),
+            compression: if config.compression.unwrap_or(false) {
+                Some(snappy()?)
+            } else {
+                None
+            },
+            with_transaction_gossip: config.transaction_gossip.unwrap_or(false),
+            bloom_fp_rate: if config.bloom_filters.unwrap_or(false) {
+                Some(bloom_fp_rate)
+            } else {
+                None
+            },
+            prune_depth,
+            with_hash_registry: config.hash_registry.unwrap_or(false),
+            conflict_policy,
+            relay_policy: relay_policy(config)?,
+            relay_policy_nodes: bounded(config.relay_policy_nodes, 999999, 0, 999999, "number of relay policy nodes")?,
+            signature_algorithm,
+            hasher,
+            chain_params,
+            genesis,
+            export_genesis: config.export_genesis.clone(),
+            number_of_authorities,
+            with_proof_of_stake,
+            checkpoint_interval: match config.finality {
+                Some(checkpoint_interval) => Some(bounded(
+                    Some(checkpoint_interval),
+                    1,
+                    1,
+                    999999,
+                    "checkpoint interval",
+                )?),
+                None => None,
+            },
+            split_threshold: match config.split_threshold {
+                Some(split_threshold) => Some(Duration::from_secs(bounded(
+                    Some(split_threshold),
+                    1,
+                    1,
+                    999999,
+                    "split threshold in seconds",
+                )?)),
+                None => None,
+            },
+            hashrate_shocks,
+            scenario,
+            with_dashboard,
+            websocket: config.websocket.clone(),
+            import_snapshot: config.import_snapshot.clone(),
+            export_snapshot: config.export_snapshot.clone(),
+            import_utxo_snapshot: config.import_utxo_snapshot.clone(),
+            export_utxo_snapshot: config.export_utxo_snapshot.clone(),
+            record_trace: config.record_trace.clone(),
+            address_book,
+            export_address_book: config.export_address_book.clone(),
+        })
+    }
+
+    /// Fails if the workers of a distributed simulation could not build the same network from
+    /// these parameters, or would all write to the same files.
+    fn check_distributable(&self) -> Result<(), Error> {
+        let reason = match self.topology {
+            Topology::Random { seed: None } | Topology::MultiChain { seed: None, .. } => {
+                Some("a random topology needs a seed, for every worker to draw the same connections")
+            }
+            _ if self.calibrated_difficulty && self.genesis.difficulty().is_none() => {
+                Some("each worker would calibrate the difficulty on its own, give a difficulty factor instead")
+            }
+            _ if self.number_of_authorities.is_some() => {
+                Some("each worker would draw other keys for the authorities or the validators")
+            }
+            _ if self.with_dashboard => Some("the dashboard would garble the messages of the workers"),
+            _ if self.websocket.is_some() => Some("every worker would broadcast its events on the same address"),
+            _ if self.export_genesis.is_some()
+                || self.export_snapshot.is_some()
+                || self.export_utxo_snapshot.is_some()
+                || self.record_trace.is_some()
+                || self.export_address_book.is_some() =>
+            {
+                Some("every worker would write to the same file")
+            }
+            _ => None,
+        };
+
+        match reason {
+            Some(reason) => Err(Error::Config(format!("The simulation cannot be distributed: {}", reason))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The chains a network can host at most.
+const MAX_CHAINS: u32 = 64;
+/// The share of the connections between the nodes of different chains, unless told otherwise.
+const DEFAULT_CROSS_CHAIN_FRACTION: f64 = 0.1;
+
+/// The threads of the runtimes, the defaults of Tokio for the missing ones.
+fn runtime_config(config: &SimulationConfig) -> Result<RuntimeConfig, Error> {
+    let mut runtime_config = RuntimeConfig::new();
+    if let Some(worker_threads) = config.worker_threads {
+        runtime_config =
+            runtime_config.with_worker_threads(bounded(Some(worker_threads), 1, 1, 1024, "number of worker threads")?);
+    }
+    if let Some(blocking_threads) = config.blocking_threads {
+        runtime_config = runtime_config.with_max_blocking_threads(bounded(
+            Some(blocking_threads),
+            1,
+            1,
+            4096,
+            "number of blocking threads",
+        )?);
+    }
+    if let Some(shards) = config.shards {
+        runtime_config = runtime_config.with_shards(bounded(Some(shards), 1, 1, 1024, "number of shards")?);
+    }
+    Ok(runtime_config)
+}
+
+/// The limits of the connections of the full nodes, if any of them is set.
+fn connection_limits(config: &SimulationConfig) -> Result<Option<ConnectionLimits>, Error> {
+    let eviction_policy = match config.eviction.as_deref() {
+        None | Some("oldest") => EvictionPolicy::Oldest,
+        Some("worst_latency") => EvictionPolicy::WorstLatency,
+        Some(eviction) => return Err(Error::Config(format!("Invalid eviction policy: {}", eviction))),
+    };
+    let mut connection_limits = ConnectionLimits::new()
+        .with_eviction_policy(eviction_policy)
+        .with_anchors(bounded(config.anchors, DEFAULT_ANCHORS, 0, 255, "number of anchors")?);
+    if let Some(max_inbound) = config.max_inbound {
+        connection_limits = connection_limits.with_max_inbound(bounded(
+            Some(max_inbound),
+            0,
+            0,
+            999999,
+            "maximum inbound connections",
+        )?);
+    }
+    if let Some(max_outbound) = config.max_outbound {
+        connection_limits = connection_limits.with_max_outbound(bounded(
+            Some(max_outbound),
+            1,
+            1,
+            255,
+            "maximum outbound connections",
+        )?);
+    }
+    Ok(Some(connection_limits).filter(|_limits| config.max_inbound.is_some() || config.max_outbound.is_some()))
+}
+
+/// The relay policy of the full nodes, if any of its rules is set.
+fn relay_policy(config: &SimulationConfig) -> Result<Option<RelayPolicy>, Error> {
+    let mut relay_policy = RelayPolicy::new();
+    if let Some(min_relay_fee_rate) = config.min_relay_fee_rate {
+        relay_policy = relay_policy.with_min_relay_fee_rate(bounded(
+            Some(min_relay_fee_rate),
+            0,
+            0,
+            99999999,
+            "minimum relay fee rate",
+        )?);
+    }
+    if let Some(max_tx_size) = config.max_tx_size {
+        relay_policy = relay_policy.with_max_tx_size(bounded(
+            Some(max_tx_size),
+            DEFAULT_MAX_TX_SIZE,
+            1,
+            MAX_BLOCK_SIZE,
+            "maximum transaction size",
+        )?);
+    }
+    if let Some(dust_threshold) = config.dust_threshold {
+        relay_policy = relay_policy.with_dust_threshold(bounded(Some(dust_threshold), 0, 0, 999999, "dust threshold")?);
+    }
+
+    let with_relay_policy =
+        config.min_relay_fee_rate.is_some() || config.max_tx_size.is_some() || config.dust_threshold.is_some();
+    if config.relay_policy_nodes.is_some() && !with_relay_policy {
+        return Err(Error::Config("The relay policy nodes need a relay policy".to_owned()));
+    }
+    Ok(Some(relay_policy).filter(|_policy| with_relay_policy))
+}
+
+/// The compression of the bandwidth meters, which needs the compression feature.
+fn snappy() -> Result<Compression, Error> {
+    #[cfg(feature = "compression")]
+    return Ok(Compression::Snappy);
+    #[cfg(not(feature = "compression"))]
+    Err(Error::Config("The compression needs the compression feature".to_owned()))
+}
+
+/// The given value or else the default one, if within the bounds.
+fn bounded<I>(value: Option<I>, default: I, min_value: I, max_value: I, name: &str) -> Result<I, Error>
+where
+    I: PartialOrd + Display + Copy,
+{
+    let value = value.unwrap_or(default);
+    if value < min_value || value > max_value {
+        Err(Error::Config(format!(
+            "Invalid {} {}, expected [{}-{}]",
+            name, value, min_value, max_value
+        )))
+    } else {
+        Ok(value)
+    }
+}
+
+/// Runs the simulation described by the configuration, the missing parameters taking their
+/// default values, then logs its metrics. Fails if a parameter is invalid.
+pub fn run(config: &SimulationConfig) -> Result<SimulationReport, Error> {
+    run_with(config, None, None).map(|(report, _strongest_chain)| report)
+}
+
+/// Runs the simulation like `run`, and also keeps the log lines of every node in the given
+/// capture, to tell what each of them did once the simulation is over.
+pub fn run_with_log_capture(config: &SimulationConfig, log_capture: &LogCapture) -> Result<SimulationReport, Error> {
+    run_with(config, Some(log_capture.clone()), None).map(|(report, _strongest_chain)| report)
+}
+
+/// Runs the nodes of a simulation the worker hosts, connected to the ones of the other
+/// workers, then reports to the orchestrator, see the `orchestrator` module. Fails if a
+/// parameter is invalid, or cannot be shared by the workers.
+pub fn run_worker(config: &SimulationConfig, worker: &mut Worker) -> Result<SimulationReport, Error> {
+    let (report, strongest_chain) = run_with(config, None, Some(&mut *worker))?;
+    worker.report(&WorkerReport {
+        nodes: worker.nodes(report.network_size),
+        dropped_messages: report.dropped_messages,
+        delayed_messages: report.delayed_messages,
+        lost_messages: report.lost_messages,
+        height: strongest_chain.height(),
+        head: strongest_chain.head().hash().clone(),
+    })?;
+    Ok(report)
+}
+
+/// Runs the simulation, only the nodes of the worker if any, and returns the strongest chain
+/// of the nodes along with the report.
+fn run_with(
+    config: &SimulationConfig,
+    log_capture: Option<LogCapture>,
+    worker: Option<&mut Worker>,
+) -> Result<(SimulationReport, Arc<Chain>), Error> {
+    let parameters = Parameters::new(config)?;
+    if worker.is_some() {
+        parameters.check_distributable()?;
+    }
+    // The log lines of the nodes tell the time from here.
+    let simulation_start = Instant::now();
+
+    // The first honest full nodes sign the blocks of a proof of authority or proof of stake
+    // chain, the validators with random stakes.
+    let (authorities, validators, producer_keys) = match parameters.number_of_authorities {
+        Some(number_of_authorities) if parameters.with_proof_of_stake => {
+            let first_validator = parameters.number_of_light_nodes;
+            let mut rng = rand::thread_rng();
+            let stakes: Vec<(u32, u64)> = (first_validator..first_validator + number_of_authorities)
+                .map(|node_id| (node_id, rng.gen_range(1, 101)))
+                .collect();
+            info!("Proof of stake: stakes of the validators {:?}", stakes);
+            let (validators, producer_keys) = ValidatorRegistry::generate(stakes, parameters.signature_algorithm)?;
+            (None, Some(validators), producer_keys)
+        }
+        Some(number_of_authorities) => {
+            let first_authority = parameters.number_of_light_nodes;
+            let (authorities, producer_keys) = Authorities::generate(
+                first_authority..first_authority + number_of_authorities,
+                parameters.signature_algorithm,
+            )?;
+            info!(
+                "Proof of authority: nodes {:?} produce the blocks",
+                first_authority..first_authority + number_of_authorities
+            );
+            (Some(authorities), None, producer_keys)
+        }
+        None => (None, None, HashMap::new()),
+    };
+
+    // Set up a chain. A snapshot comes with its own difficulty, hash function and signature
+    // scheme, and a UTXO snapshot with the unspent outputs of its chain as well.
+    let (chains, utxo_set) = match (&parameters.import_snapshot, &parameters.import_utxo_snapshot) {
+        (Some(path), _) => {
+            let chain = Chain::read_snapshot(path)?;
+            info!("Imported a chain of height {} from {}", chain.height(), path);
+            (vec![chain], None)
+        }
+        (None, Some(path)) => {
+            let (chain, utxo_set) = Chain::read_utxo_snapshot(path)?;
+            info!(
+                "Imported the headers of a chain of height {} and {} unspent outputs from {}",
+                chain.height(),
+                utxo_set.len(),
+                path
+            );
+            (vec![chain], Some(utxo_set))
+        }
+        (None, None) => {
+            let mut difficulty = Difficulty::min_difficulty();
+            for _i in 0u8..parameters.difficulty_factor {
+                difficulty
+                    .increase()
+                    .expect("The difficulty factor is bounded by the size of the threshold");
+            }
+
+            // The exported definition keeps the difficulty of the run, so that the runs importing
+            // it build the very same genesis block.
+            let mut genesis = parameters.genesis.clone();
+            match genesis.difficulty() {
+                Some(genesis_difficulty) => difficulty = genesis_difficulty.clone(),
+                None if parameters.export_genesis.is_some() => genesis = genesis.with_difficulty(difficulty.clone()),
+                None => (),
+            }
+            if let Some(ref path) = parameters.export_genesis {
+                genesis
+                    .save(path)
+                    .map_err(|err| Error::Config(format!("Could not export the genesis definition: {}", err)))?;
+                info!("Exported the genesis definition to {}", path);
+            }
+
+            info!("Chain difficulty threshold: {:?}", difficulty);
+            info!("Proof of work hash function: {:?}", parameters.hasher);
+            info!("Network: {}", parameters.chain_params.name());
+            // The other chains of a multi-chain network start from the same genesis block, at
+            // other times.
+            let chains = (0..parameters.number_of_chains as u64)
+                .map(|index| {
+                    let genesis = genesis.clone().with_timestamp(genesis.timestamp() + index);
+                    let chain = Chain::init_new(difficulty.clone())
+                        .with_hasher(parameters.hasher)
+                        .with_genesis(genesis)
+                        .with_signature_algorithm(parameters.signature_algorithm)
+                        .with_params(parameters.chain_params.clone());
+                    info!("Genesis block: {}", chain.head().hash());
+                    let chain = match authorities {
+                        Some(ref authorities) => chain.with_authorities(authorities.clone()),
+                        None => chain,
+                    };
+                    let chain = match validators {
+                        Some(ref validators) => chain.with_validators(validators.clone()),
+                        None => chain,
+                    };
+                    Arc::new(chain)
+                })
+                .collect();
+            (chains, None)
+        }
+    };
+    let chain = chains[0].clone();
+    let start_height = chain.height();
+    info!("Topology: {:?}, latency: {:?}", parameters.topology, parameters.latency);
+
+    let node_id = AtomicUsize::new(0);
+    let hash_registry = if parameters.with_hash_registry {
+        Some(HashRegistry::new())
+    } else {
+        None
+    };
+    let registry = hash_registry.clone();
+    // The strongest chain tells the stale blocks apart once the nodes stopped, and is exported.
+    // A multi-chain network keeps the strongest chain of each of its chains.
+    let strongest_chains: Vec<StrongestChain> = chains.iter().cloned().map(StrongestChain::new).collect();
+    let strongest = strongest_chains.clone();
+    // The honest full nodes of each chain report the chains they adopt, to sample their heads.
+    let node_chains: Vec<NodeChains> = match parameters.split_threshold {
+        Some(_threshold) => chains.iter().cloned().map(NodeChains::new).collect(),
+        None => vec![],
+    };
+    let reported_chains = node_chains.clone();
+    let double_spend_counter = DoubleSpendCounter::new();
+    let counter = double_spend_counter.clone();
+    let latency_stats = LatencyStats::new();
+    let stats = latency_stats.clone();
+    let throughput_stats = ThroughputStats::new();
+    let throughput = throughput_stats.clone();
+
+    // The observers of the nodes subscribe to their metrics before they start.
+    #[allow(unused_mut)]
+    let mut metrics_bus = MetricsBus::new();
+    #[cfg(feature = "websocket")]
+    let event_server = match parameters.websocket {
+        Some(ref address) => Some(
+            EventServer::start(address, metrics_bus.subscribe())
+                .map_err(|err| Error::Config(format!("Could not listen on {}: {}", address, err)))?,
+        ),
+        None => None,
+    };
+    // The dashboard takes over the terminal while the nodes run.
+    #[cfg(feature = "tui")]
+    let dashboard = if parameters.with_dashboard {
+        let full_nodes = parameters.number_of_light_nodes..parameters.number_of_nodes;
+        let dashboard = Dashboard::start(metrics_bus.subscribe(), full_nodes, parameters.duration)
+            .map_err(|err| Error::Config(format!("Could not start the dashboard: {}", err)))?;
+        Some(dashboard)
+    } else {
+        None
+    };
+
+    // The sybils and the byzantine nodes, like the light nodes, are not among the measured nodes.
+    let number_of_sybil_nodes = parameters.sybil_adversary.as_ref().map_or(0, SybilAdversary::len);
+    let byzantine_nodes_end = parameters.number_of_nodes - number_of_sybil_nodes;
+    let byzantine_nodes = byzantine_nodes_end - parameters.number_of_byzantine_nodes..byzantine_nodes_end;
+    let honest_nodes = parameters.number_of_light_nodes..byzantine_nodes.start;
+    let stakes = validators.as_ref().map(|validators| validators.stakes().clone()).unwrap_or_default();
+    // The authorities or the validators vote on the checkpoints if any, the honest full nodes
+    // otherwise.
+    let (finality_voters, voter_keys) = match parameters.checkpoint_interval {
+        Some(checkpoint_interval) => {
+            let weights: Vec<(u32, u64)> = if stakes.is_empty() {
+                let number_of_voters = parameters.number_of_authorities.unwrap_or(honest_nodes.len() as u32);
+                (honest_nodes.start..honest_nodes.start + number_of_voters).map(|node_id| (node_id, 1)).collect()
+            } else {
+                stakes.iter().map(|(&node_id, &stake)| (node_id, stake)).collect()
+            };
+            let (finality_voters, voter_keys) =
+                FinalityVoters::generate(weights, checkpoint_interval, parameters.signature_algorithm)?;
+            info!(
+                "Finality: {} voters, {} total weight, a checkpoint every {} blocks",
+                finality_voters.len(),
+                finality_voters.total_weight(),
+                checkpoint_interval
+            );
+            (Some(Arc::new(finality_voters)), voter_keys)
+        }
+        None => (None, HashMap::new()),
+    };
+    let checkpoint_interval = parameters.checkpoint_interval;
+    // The shocks hit the first honest miners, the authorities or the validators if any.
+    let number_of_miners = parameters.number_of_authorities.unwrap_or(honest_nodes.len() as u32);
+    let mut hashrate_shocks: HashMap<u32, Vec<(Duration, Option<Duration>)>> = HashMap::new();
+    for hashrate_shock in &parameters.hashrate_shocks {
+        let hit_miners = honest_nodes.start..honest_nodes.start + hashrate_shock.hit_miners(number_of_miners);
+        info!("Hash rate shock at {:?}: nodes {:?}", hashrate_shock.at(), hit_miners);
+        for node_id in hit_miners {
+            hashrate_shocks.entry(node_id).or_default().push((
+                hashrate_shock.at(),
+                hashrate_shock.attempt_delay(parameters.mining_attempt_delay),
+            ));
+        }
+    }
+    // The rewards of the miners are compared with their stakes, or with their average hash
+    // rates.
+    let reward_weights: BTreeMap<u32, f64> = if stakes.is_empty() {
+        (honest_nodes.start..honest_nodes.start + number_of_miners)
+            .map(|node_id| {
+                let shocks = hashrate_shocks.get(&node_id).map_or(&[][..], Vec::as_slice);
+                (node_id, average_hashrate(parameters.mining_attempt_delay, shocks, parameters.duration))
+            })
+            .collect()
+    } else {
+        stakes.iter().map(|(&node_id, &stake)| (node_id, stake as f64)).collect()
+    };
+    let split_monitor = parameters.split_threshold.map(|threshold| {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let monitor = thread::spawn(move || monitor_splits(&node_chains, threshold, &stopped));
+        (stop, monitor)
+    });
+    let measured_nodes = honest_nodes.clone();
+    let phases = parameters.hashrate_shocks.clone();
+    let warm_up_blocks = parameters.warm_up_blocks;
+    let propagation_metrics = metrics_bus.subscribe();
+    let propagation = thread::spawn(move || {
+        let mut propagation_stats = PropagationStats::new(honest_nodes.clone());
+        let mut bootstrap_stats = BootstrapStats::new(honest_nodes);
+        let mut misbehavior_stats = MisbehaviorStats::new();
+        let mut relay_stats = RelayStats::new();
+        let mut gossip_stats = GossipStats::new();
+        let mut filter_stats = FilterStats::new();
+        let mut pruning_stats = PruningStats::new();
+        let mut validator_stats = ValidatorStats::new(stakes);
+        let mut block_interval_stats = BlockIntervalStats::new(&phases);
+        let mut reward_stats = RewardStats::new(reward_weights);
+        let mut stale_stats = StaleStats::new();
+        let mut finality_stats = checkpoint_interval.map(FinalityStats::new);
+        let mut throttled_messages = 0u64;
+        let mut evicted_peers = 0u64;
+        let mut duplicate_chains = 0u64;
+        let mut foreign_chains = 0u64;
+        let mut warm_up = WarmUp::new(warm_up_blocks);
+        for metric in propagation_metrics.iter() {
+            // The nodes bootstrap before the first blocks are mined, whatever the warm-up.
+            bootstrap_stats.record(&metric);
+            // So do most connections, and the evictions they cause.
+            if let NodeMetric::PeerEvicted { .. } = metric {
+                evicted_peers += 1;
+            }
+            if !warm_up.measures(&metric) {
+                continue;
+            }
+            propagation_stats.record(&metric);
+            misbehavior_stats.record(&metric);
+            relay_stats.record(&metric);
+            gossip_stats.record(&metric);
+            filter_stats.record(&metric);
+            pruning_stats.record(&metric);
+            validator_stats.record(&metric);
+            block_interval_stats.record(&metric);
+            reward_stats.record(&metric);
+            stale_stats.record(&metric);
+            if let Some(ref mut finality_stats) = finality_stats {
+                finality_stats.record(&metric);
+            }
+            if let NodeMetric::MessageThrottled { .. } = metric {
+                throttled_messages += 1;
+            }
+            if let NodeMetric::ChainDuplicate { .. } = metric {
+                duplicate_chains += 1;
+            }
+            if let NodeMetric::ForeignChain { .. } = metric {
+                foreign_chains += 1;
+            }
+        }
+        (
+            propagation_stats,
+            misbehavior_stats,
+            relay_stats,
+            gossip_stats,
+            filter_stats,
+            pruning_stats,
+            bootstrap_stats,
+            validator_stats,
+            finality_stats,
+            block_interval_stats,
+            reward_stats,
+            stale_stats,
+            throttled_messages,
+            evicted_peers,
+            duplicate_chains,
+            foreign_chains,
+            warm_up,
+        )
+    });
+    if !byzantine_nodes.is_empty() {
+        info!("Byzantine nodes: {:?}", byzantine_nodes);
+    }
+    if let Some(ref sybil_adversary) = parameters.sybil_adversary {
+        info!("Sybils: {:?}", sybil_adversary);
+    }
+    if let Some(ref latency_matrix) = parameters.latency_matrix {
+        log_regions(latency_matrix, parameters.number_of_nodes);
+    }
+
+    // Run the blockchain network.
+    let number_of_light_nodes = parameters.number_of_light_nodes;
+    let mining_attempt_delay = parameters.mining_attempt_delay;
+    let payment_attempt_delay = parameters.payment_attempt_delay;
+    let load_generators = number_of_light_nodes..number_of_light_nodes + parameters.number_of_load_generators;
+    let load_payment_delay = parameters.load_payment_delay;
+    let conflict_policy = parameters.conflict_policy;
+    let relay_policy = parameters.relay_policy;
+    let relay_policy_nodes = number_of_light_nodes..number_of_light_nodes.saturating_add(parameters.relay_policy_nodes);
+    let sybil_adversary = parameters.sybil_adversary.clone();
+    let forgery_delay = parameters.forgery_delay;
+    let ban_policy = parameters.ban_policy;
+    let inbound_rate = parameters.inbound_rate;
+    let connection_limits = parameters.connection_limits;
+    let with_compact_relay = parameters.with_compact_relay;
+    let with_transaction_gossip = parameters.with_transaction_gossip;
+    let bloom_fp_rate = parameters.bloom_fp_rate;
+    // The full nodes pay the addresses the light nodes filter the transactions for.
+    let watched_addresses = match bloom_fp_rate {
+        Some(_rate) => (0..number_of_light_nodes)
+            .map(|_node_id| {
+                let mut wallet = Wallet::new();
+                (0..WATCHED_ADDRESSES).map(|_i| wallet.new_address()).collect()
+            })
+            .collect::<Result<Vec<Vec<Address>>, _>>()?,
+        None => vec![],
+    };
+    let payees = Arc::new(watched_addresses.iter().flatten().cloned().collect::<Vec<Address>>());
+    let prune_depth = parameters.prune_depth;
+    // Every full node but the byzantine ones shares the blocks and prunes them.
+    let pruning_horizon = PruningHorizon::new(
+        (number_of_light_nodes..parameters.number_of_nodes).filter(|node_id| !byzantine_nodes.contains(node_id)),
+    );
+    let topology = parameters.topology;
+    let node_factory = move || {
+        let node_id = node_id.fetch_add(1, Ordering::Relaxed) as u32;
+        let chain_index = topology.chain_of(node_id) as usize;
+        let chain = &chains[chain_index];
+        let logger = NodeLogger::new(node_id, simulation_start);
+        let logger = match log_capture {
+            Some(ref log_capture) => logger.with_capture(log_capture.clone()),
+            None => logger,
+        };
+
+        // Light nodes ask for a proof as often as full nodes pay.
+        if node_id < number_of_light_nodes {
+            let light_node = LightNode::new(node_id, chain, payment_attempt_delay)
+                .with_metrics_bus(metrics_bus.clone())
+                .with_logger(logger);
+            return SimulationNode::Light(match bloom_fp_rate {
+                Some(bloom_fp_rate) => light_node
+                    .with_bloom_filter(watched_addresses[node_id as usize].clone(), bloom_fp_rate)
+                    .expect("The false positive rate is validated"),
+                None => light_node,
+            });
+        }
+
+        if byzantine_nodes.contains(&node_id) {
+            let byzantine_node = ByzantineNode::new(node_id, chain.clone(), forgery_delay).with_logger(logger);
+            return SimulationNode::Byzantine(byzantine_node);
+        }
+
+        let is_load_generator = load_generators.contains(&node_id);
+        let node_payment_delay = if is_load_generator { load_payment_delay } else { payment_attempt_delay };
+        // The next nodes are only built once this one bootstrapped, like late joiners.
+        let bootstrap_start = Instant::now();
+        let node = match utxo_set {
+            Some(ref utxo_set) => PowNode::from_utxo_set(
+                node_id,
+                chain.clone(),
+                utxo_set.clone(),
+                mining_attempt_delay,
+                node_payment_delay,
+            ),
+            None => PowNode::new(node_id, chain.clone(), mining_attempt_delay, node_payment_delay),
+        };
+        metrics_bus.publish(NodeMetric::NodeBootstrapped {
+            node_id,
+            height: chain.height(),
+            bootstrap_ms: bootstrap_start.elapsed().as_secs_f64() * 1000.0,
+        });
+        let mut node = node
+            .with_conflict_policy(conflict_policy)
+            .with_ban_policy(ban_policy)
+            .with_double_spend_counter(counter.clone())
+            .with_latency_stats(stats.clone())
+            .with_metrics_bus(metrics_bus.clone())
+            .with_logger(logger)
+            .with_payees(payees.clone());
+        if is_load_generator {
+            node = node.with_throughput_stats(throughput.clone());
+        }
+        if let Some(inbound_rate) = inbound_rate {
+            node = node.with_inbound_rate(inbound_rate);
+        }
+        if let Some(connection_limits) = connection_limits {
+            node = node.with_connection_limits(connection_limits);
+        }
+        if let Some(relay_policy) = relay_policy {
+            if relay_policy_nodes.contains(&node_id) {
+                node = node.with_relay_policy(relay_policy);
+            }
+        }
+        if with_compact_relay {
+            node = node.with_compact_relay();
+        }
+        if with_transaction_gossip {
+            node = node.with_transaction_gossip();
+        }
+        if let Some(ref sybil_adversary) = sybil_adversary {
+            if sybil_adversary.controls(node_id) {
+                node = node.with_sybil_adversary(sybil_adversary.clone());
+            }
+        }
+        if let Some(producer_key) = producer_keys.get(&node_id) {
+            node = node.with_producer_key(producer_key.clone());
+        }
+        for &(at, attempt_delay) in hashrate_shocks.get(&node_id).into_iter().flatten() {
+            node = node.with_hashrate_shock(at, attempt_delay);
+        }
+        if let Some(ref finality_voters) = finality_voters {
+            node = node.with_finality(finality_voters.clone(), voter_keys.get(&node_id).cloned());
+        }
+        if let Some(prune_depth) = prune_depth {
+            node = node.with_pruning(prune_depth, pruning_horizon.clone());
+        }
+        if let Some(ref registry) = registry {
+            node = node.with_hash_registry(registry.clone());
+        }
+        node = node.with_strongest_chain(strongest[chain_index].clone());
+        if let Some(node_chains) = reported_chains.get(chain_index) {
+            if measured_nodes.contains(&node_id) {
+                node = node.with_node_chains(node_chains.clone());
+            }
+        }
+
+        SimulationNode::Full(Box::new(node))
+    };
+    let mut simulation = SimulationBuilder::new(parameters.number_of_nodes, node_factory)
+        .with_connections(parameters.initiated_connections_per_node)
+        .with_topology(parameters.topology)
+        .with_nat_fraction(parameters.nat_fraction)
+        .with_channel_capacity(parameters.channel_capacity)
+        .with_runtime_config(parameters.runtime_config)
+        .with_latency(parameters.latency)
+        .with_scenario(parameters.scenario)
+        .with_duration(parameters.duration);
+    if let Some(ref latency_matrix) = parameters.latency_matrix {
+        simulation = simulation.with_latency_matrix(latency_matrix.clone());
+    }
+    if let Some(ref address_book) = parameters.address_book {
+        simulation = simulation.with_address_book(address_book.clone());
+    }
+    let recorder = parameters.record_trace.as_ref().map(|_path| Recorder::new());
+    if let Some(ref recorder) = recorder {
+        simulation = simulation.with_recorder(recorder.clone());
+    }
+    let bandwidth = parameters
+        .compression
+        .map(|compression| BandwidthMeter::new(BlockEncoder).with_compression(compression));
+    if let Some(ref bandwidth) = bandwidth {
+        simulation = simulation.with_bandwidth_meter(bandwidth.clone());
+    }
+    if let Some(worker) = worker {
+        simulation = simulation.with_tcp_bridge(worker.join(parameters.number_of_nodes)?);
+    }
+    let report = simulation.build().run();
+    let split_detectors = split_monitor.map(|(stop, monitor)| {
+        drop(stop);
+        monitor.join().expect("The split monitor panicked")
+    });
+
+    // The metrics end with the nodes, then the logs are back.
+    #[cfg(feature = "tui")]
+    if let Some(dashboard) = dashboard {
+        if let Err(err) = dashboard.join() {
+            warn!("The dashboard failed: {}", err);
+        }
+    }
+    LogSink.record(&report);
+    #[cfg(feature = "websocket")]
+    if let Some(event_server) = event_server {
+        event_server.join();
+    }
+
+    info!(
+        "Double spends: {} attempts, {} replacements",
+        double_spend_counter.attempts(),
+        double_spend_counter.replacements()
+    );
+
+    info!(
+        "Peer latencies: {} measured connections, {:?} average round trip time, {:?} max",
+        latency_stats.len(),
+        latency_stats.average().unwrap_or_default(),
+        latency_stats.max().unwrap_or_default()
+    );
+
+    let (
+        propagation_stats,
+        misbehavior_stats,
+        relay_stats,
+        gossip_stats,
+        filter_stats,
+        pruning_stats,
+        bootstrap_stats,
+        validator_stats,
+        finality_stats,
+        block_interval_stats,
+        reward_stats,
+        stale_stats,
+        throttled_messages,
+        evicted_peers,
+        duplicate_chains,
+        foreign_chains,
+        warm_up,
+    ) = propagation.join().expect("The propagation stats panicked");
+    log_warm_up(&warm_up, report.elapsed);
+    info!(
+        "Propagation: {} blocks mined by the honest nodes, {} reached half of them in {:?} (median), {} reached 90% of them in {:?}",
+        propagation_stats.blocks_mined(),
+        propagation_stats.blocks_reaching(50),
+        propagation_stats.median_time_to_reach(50).unwrap_or_default(),
+        propagation_stats.blocks_reaching(90),
+        propagation_stats.median_time_to_reach(90).unwrap_or_default()
+    );
+    info!(
+        "Forks: {} seen by the honest nodes, {:.3} per block mined",
+        propagation_stats.forks(),
+        propagation_stats.fork_rate()
+    );
+    info!(
+        "Duplicate chains: {} dropped before validation, already received from another peer",
+        duplicate_chains
+    );
+    if parameters.number_of_chains > 1 {
+        info!(
+            "Chains: {} chains on the same network, {} chains dropped by the nodes of another chain",
+            parameters.number_of_chains, foreign_chains
+        );
+    }
+    log_block_delays(&propagation_stats);
+    if let (Some(threshold), Some(split_detectors)) = (parameters.split_threshold, split_detectors) {
+        for (chain_index, split_detector) in split_detectors.iter().enumerate() {
+            info!(
+                "{}Splits: {} lasting longer than {:?}, the longest one for {:?}",
+                chain_label(chain_index, split_detectors.len()),
+                split_detector.splits(),
+                threshold,
+                split_detector.longest_split()
+            );
+        }
+    }
+
+    let total = misbehavior_stats.total();
+    info!(
+        "Misbehavior: {} invalid chains, {} spam, {} stale data, {} peer bans",
+        total.invalid_chains, total.spam, total.stale_data, total.bans
+    );
+    for (peer_id, misbehavior) in misbehavior_stats.peers() {
+        info!(
+            "Peer #{:05}: {} invalid chains, {} spam, {} stale data, banned by {} nodes",
+            peer_id, misbehavior.invalid_chains, misbehavior.spam, misbehavior.stale_data, misbehavior.bans
+        );
+    }
+
+    if let Some(inbound_rate) = parameters.inbound_rate {
+        info!(
+            "Inbound rate limit: {} messages per second per peer, {} messages throttled",
+            inbound_rate, throttled_messages
+        );
+    }
+    if let Some(ref connection_limits) = parameters.connection_limits {
+        info!("Connection limits: {:?}, {} peers evicted", connection_limits, evicted_peers);
+    }
+
+    info!(
+        "Block relay: {} bytes sent, {} bytes with whole blocks, {:.1}% saved",
+        relay_stats.bytes(),
+        relay_stats.full_block_bytes(),
+        relay_stats.savings().unwrap_or_default()
+    );
+    if let Some(ref bandwidth) = bandwidth {
+        log_bandwidth(bandwidth);
+    }
+    if parameters.with_compact_relay {
+        info!(
+            "Compact blocks: {} rebuilt, {} of them with {} transactions missing from the mempool, {} whole chains asked for instead",
+            relay_stats.reconstructed_blocks(),
+            relay_stats.incomplete_blocks(),
+            relay_stats.requested_transactions(),
+            relay_stats.fallbacks()
+        );
+    }
+
+    if parameters.with_transaction_gossip {
+        info!(
+            "Transaction gossip: {} announcements, {} of the {} received were new, {} transactions fetched, {} accepted in the mempools",
+            gossip_stats.announced(),
+            gossip_stats.unknown_announcements(),
+            gossip_stats.received_announcements(),
+            gossip_stats.fetched(),
+            gossip_stats.accepted()
+        );
+    }
+
+    if let Some(ref relay_policy) = parameters.relay_policy {
+        let full_nodes = parameters.number_of_nodes - parameters.number_of_light_nodes;
+        let policy_rejections = double_spend_counter.policy_rejections();
+        info!(
+            "Relay policy: {:?} for {} full nodes, {} valid payments refused: {} for their fee rate, {} for their size, {} for their dust outputs, {} of them fetched from peers",
+            relay_policy,
+            parameters.relay_policy_nodes.min(full_nodes),
+            policy_rejections.total(),
+            policy_rejections.insufficient_fee,
+            policy_rejections.too_large,
+            policy_rejections.dust,
+            gossip_stats.refused()
+        );
+    }
+
+    if let Some(bloom_fp_rate) = parameters.bloom_fp_rate {
+        info!(
+            "Bloom filters: {} transactions tested, {} matched, {} received by the light nodes, {} of them paying them, {:.2}% false positives per transaction for {:.2}% per element tested",
+            filter_stats.tested(),
+            filter_stats.matched(),
+            filter_stats.received(),
+            filter_stats.relevant(),
+            filter_stats.false_positive_rate().unwrap_or_default(),
+            100.0 * bloom_fp_rate
+        );
+    }
+
+    if let Some(prune_depth) = parameters.prune_depth {
+        info!(
+            "Pruning: {} blocks deeper than {} pruned by the full nodes, {} bodies dropped from the shared blocks, {} bytes, {} proof requests answered as pruned",
+            pruning_stats.pruned_blocks(),
+            prune_depth,
+            pruning_stats.dropped_bodies(),
+            pruning_stats.dropped_bytes(),
+            pruning_stats.pruned_proof_requests()
+        );
+    }
+
+    if start_height > 0 {
+        info!(
+            "Bootstrap: {} honest nodes started from the chain of height {} {} in {:?} (median), {:?} max, {} of them adopted a block from a peer {:?} after starting to bootstrap (median), {:?} max",
+            bootstrap_stats.nodes(),
+            start_height,
+            if parameters.import_utxo_snapshot.is_some() {
+                "of a UTXO snapshot"
+            } else {
+                "validated from the genesis block"
+            },
+            bootstrap_stats.bootstrap_percentile(50).unwrap_or_default(),
+            bootstrap_stats.bootstrap_percentile(100).unwrap_or_default(),
+            bootstrap_stats.synced_nodes(),
+            bootstrap_stats.time_to_consensus_percentile(50).unwrap_or_default(),
+            bootstrap_stats.time_to_consensus_percentile(100).unwrap_or_default()
+        );
+    }
+
+    if parameters.with_proof_of_stake {
+        info!(
+            "Proof of stake: {} blocks mined by the validators, {:.1}% max deviation of a block share from its stake share, {} validators slashed",
+            validator_stats.mined_blocks(),
+            100.0 * validator_stats.max_share_deviation().unwrap_or_default(),
+            validator_stats.slashed_validators()
+        );
+    }
+
+    if let (Some(checkpoint_interval), Some(finality_stats)) = (parameters.checkpoint_interval, finality_stats) {
+        info!(
+            "Finality: {} blocks mined at the checkpoints every {} blocks, {} finalized, by the nodes in {:?} (median), {:?} max, {:?} (median), {:?} max to reach {} confirmations instead",
+            finality_stats.mined_checkpoints(),
+            checkpoint_interval,
+            finality_stats.finalized_checkpoints(),
+            finality_stats.time_to_finality_percentile(50).unwrap_or_default(),
+            finality_stats.time_to_finality_percentile(100).unwrap_or_default(),
+            finality_stats.time_to_depth_percentile(50).unwrap_or_default(),
+            finality_stats.time_to_depth_percentile(100).unwrap_or_default(),
+            CONFIRMATION_DEPTH
+        );
+    }
+
+    if !parameters.hashrate_shocks.is_empty() {
+        for phase in block_interval_stats.phases() {
+            info!(
+                "Block intervals from {:?}: {} blocks, {:?} mean, {:?} standard deviation",
+                phase.start, phase.blocks, phase.mean, phase.standard_deviation
+            );
+        }
+    }
+
+    log_rewards(&reward_stats, if parameters.with_proof_of_stake { "stake" } else { "hash rate" });
+
+    if parameters.number_of_load_generators > 0 {
+        log_throughput(&throughput_stats);
+    }
+
+    if let Some(hash_registry) = hash_registry {
+        info!(
+            "Hash registry: {} mined blocks, {} hash collisions, {} duplicated mining tuples",
+            hash_registry.len(),
+            hash_registry.collisions(),
+            hash_registry.duplicated_tuples()
+        );
+    }
+
+    let strongest_chains: Vec<Arc<Chain>> = strongest_chains.iter().map(StrongestChain::get).collect();
+    log_stale_blocks(&stale_stats, &strongest_chains, &report.degrees);
+    if parameters.nat_fraction > 0.0 {
+        log_nat(&report.degrees, &report.behind_nat);
+    }
+    let strongest_chain = &strongest_chains[0];
+
+    if let Some(path) = parameters.export_snapshot {
+        strongest_chain.write_snapshot(&path)?;
+        info!("Exported the chain of height {} to {}", strongest_chain.height(), path);
+    }
+    if let (Some(path), Some(recorder)) = (parameters.record_trace, recorder) {
+        let trace = recorder.trace();
+        trace.write(&path, &ChainCodec)?;
+        info!("Recorded {} messages delivered, wrote the chains to {}", trace.records().len(), path);
+    }
+    if parameters.address_book.is_some() {
+        info!("Address book: {} stale addresses dropped, replaced by peers of the topology", report.stale_addresses);
+    }
+    if let Some(path) = parameters.export_address_book {
+        report.address_book.write(&path)?;
+        info!("Wrote the peers of {} nodes to {}", report.address_book.len(), path);
+    }
+    if let Some(path) = parameters.export_utxo_snapshot {
+        let utxo_set = strongest_chain.validate()?;
+        strongest_chain.write_utxo_snapshot(&utxo_set, &path)?;
+        info!(
+            "Exported the headers of the chain of height {} and {} unspent outputs to {}",
+            strongest_chain.height(),
+            utxo_set.len(),
+            path
+        );
+    }
+
+    Ok((report, strongest_chain.clone()))
+}
+
+/// The number of addresses the Bloom filter of a light node is made of.
+const WATCHED_ADDRESSES: usize = 20;
+
+/// The number of points of the mempool backlog timeline in the report.
+const BACKLOG_TIMELINE_POINTS: usize = 10;
+
+/// Logs the warm-up, if any, apart from the measurement the other statistics are about.
+/// Samples the heads of the nodes of every chain until told to stop, and logs the splits
+/// lasting longer than the threshold. Returns the detectors of the splits of every chain.
+fn monitor_splits(node_chains: &[NodeChains], threshold: Duration, stopped: &Receiver<()>) -> Vec<SplitDetector> {
+    let mut split_detectors: Vec<SplitDetector> = node_chains.iter().map(|_| SplitDetector::new(threshold)).collect();
+    while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(SPLIT_SAMPLE_INTERVAL) {
+        for (chain_index, (chains, split_detector)) in node_chains.iter().zip(&mut split_detectors).enumerate() {
+            let heads = competing_heads(&chains.all());
+            let label = chain_label(chain_index, node_chains.len());
+            match split_detector.sample(&heads) {
+                Some(SplitEvent::Split(duration)) => {
+                    let heads: Vec<String> = heads
+                        .iter()
+                        .map(|head| format!("{} nodes on {} at height {}", head.nodes, head.hash, head.height))
+                        .collect();
+                    warn!("{}Split for {:?} across {} heads: {}", label, duration, heads.len(), heads.join(", "));
+                }
+                Some(SplitEvent::Resolved(duration)) => info!("{}Split resolved after {:?}", label, duration),
+                None => {}
+            }
+        }
+    }
+    split_detectors
+}
+
+/// Prefixes the logs of a chain of a multi-chain network with its index.
+fn chain_label(chain_index: usize, number_of_chains: usize) -> String {
+    if number_of_chains > 1 {
+        format!("Chain {}: ", chain_index)
+    } else {
+        String::new()
+    }
+}
+
+fn log_warm_up(warm_up: &WarmUp, elapsed: Duration) {
+    if warm_up.blocks() == 0 {
+        return;
+    }
+
+    match warm_up.duration() {
+        Some(duration) => {
+            info!(
+                "Warm-up: blocks 1 to {}, {} mined in {:?}, {} forks, {} metrics left out of the statistics",
+                warm_up.blocks(),
+                warm_up.blocks_mined(),
+                duration,
+                warm_up.forks(),
+                warm_up.excluded_metrics()
+            );
+            info!(
+                "Measurement: from block {} on, for {:?}",
+                warm_up.blocks() + 1,
+                elapsed.saturating_sub(duration)
+            );
+        }
+        None => warn!(
+            "Warm-up: blocks 1 to {} did not end, {} mined, no statistics measured",
+            warm_up.blocks(),
+            warm_up.blocks_mined()
+        ),
+    }
+}
+
+/// Logs the bytes of the blocks relayed on every connection together, then on each of them.
+fn log_bandwidth(bandwidth: &BandwidthMeter<Message>) {
+    let total = bandwidth.total();
+    info!(
+        "Compressed relay: {} messages, {} bytes serialized, {} bytes compressed, {:.1}% saved",
+        total.messages,
+        total.raw_bytes,
+        total.compressed_bytes,
+        total.savings().unwrap_or_default()
+    );
+    for ((sender_id, receiver_id), bytes) in bandwidth.connections() {
+        debug!(
+            "Connection #{:05} -> #{:05}: {} messages, {} bytes serialized, {} bytes compressed",
+            sender_id, receiver_id, bytes.messages, bytes.raw_bytes, bytes.compressed_bytes
+        );
+    }
+}
+
+/// Logs how long each block mined by the honest nodes took to reach them, then the delays of
+/// every block together.
+fn log_block_delays(propagation_stats: &PropagationStats) {
+    let reach = |delay: Option<Duration>| delay.map_or_else(|| "never".to_owned(), |delay| format!("{:?}", delay));
+    for block_delays in propagation_stats.block_delays() {
+        info!(
+            "Block {} at height {}: learned of by {} honest nodes, reached 50% of them in {}, 90% in {}, 99% in {}",
+            block_delays.hash,
+            block_delays.height,
+            block_delays.nodes,
+            reach(block_delays.median),
+            reach(block_delays.p90),
+            reach(block_delays.p99)
+        );
+    }
+
+    let percentile = |percentage| propagation_stats.learning_delay_percentile(percentage).unwrap_or_default();
+    info!(
+        "Block propagation delays: {:?} median, {:?} 90th percentile, {:?} 99th percentile, {:?} max for the honest nodes to learn of a block mined by another",
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        percentile(100)
+    );
+}
+
+/// The share of the miners, the best rewarded ones, whose share of the rewards is reported.
+const TOP_MINERS_SHARE: f64 = 0.1;
+
+/// Logs how the rewards are distributed among the miners, compared with their weights: their
+/// hash rates or their stakes.
+fn log_rewards(reward_stats: &RewardStats, weight: &str) {
+    let top_miners = (reward_stats.miners() as f64 * TOP_MINERS_SHARE).ceil() as usize;
+    let (top_rewards, top_weights) = reward_stats.top_share(top_miners).unwrap_or_default();
+    info!(
+        "Rewards: {} collected in {} blocks by {} of the {} miners, {:.3} Gini coefficient for {:.3} of the {}, the top {} miners collected {:.1}% for {:.1}% of the {}, {:.1}% max deviation of a reward share from its {} share",
+        reward_stats.total_rewards(),
+        reward_stats.mined_blocks(),
+        reward_stats.rewarded_miners(),
+        reward_stats.miners(),
+        reward_stats.gini().unwrap_or_default(),
+        reward_stats.weight_gini().unwrap_or_default(),
+        weight,
+        top_miners,
+        100.0 * top_rewards,
+        100.0 * top_weights,
+        weight,
+        100.0 * reward_stats.max_share_deviation().unwrap_or_default(),
+        weight
+    );
+}
+
+/// Logs the share of the mined blocks left out of the strongest chain, for every node then
+/// by number of connections of their miners.
+fn log_stale_blocks(stale_stats: &StaleStats, strongest_chains: &[Arc<Chain>], degrees: &[u32]) {
+    let by_degree = stale_stats.by_degree(strongest_chains, degrees);
+    let mined: u64 = by_degree.iter().map(|stale_blocks| stale_blocks.mined).sum();
+    let stale: u64 = by_degree.iter().map(|stale_blocks| stale_blocks.stale).sum();
+    let heights: Vec<String> = strongest_chains.iter().map(|chain| chain.height().to_string()).collect();
+    info!(
+        "Stale blocks: {} of the {} blocks mined are not in the strongest chain{} of height {}, {:.1}%",
+        stale,
+        mined,
+        if strongest_chains.len() > 1 { "s" } else { "" },
+        heights.join(", "),
+        if mined == 0 { 0.0 } else { 100.0 * stale as f64 / mined as f64 }
+    );
+    for stale_blocks in by_degree {
+        info!(
+            "Stale blocks of the miners with {} connections: {} miners, {} of the {} blocks they mined, {:.1}%",
+            stale_blocks.degree,
+            stale_blocks.miners,
+            stale_blocks.stale,
+            stale_blocks.mined,
+            100.0 * stale_blocks.stale_rate().unwrap_or_default()
+        );
+    }
+}
+
+/// Logs how many nodes are behind a NAT, and the connections of these nodes and of the
+/// reachable ones.
+fn log_nat(degrees: &[u32], behind_nat: &[bool]) {
+    let average_degree = |nat: bool| {
+        let nodes: Vec<u32> = degrees
+            .iter()
+            .zip(behind_nat)
+            .filter(|(_degree, behind_nat)| **behind_nat == nat)
+            .map(|(degree, _behind_nat)| *degree)
+            .collect();
+        let connections: u32 = nodes.iter().sum();
+        (nodes.len(), connections as f64 / nodes.len().max(1) as f64)
+    };
+    let (nat_nodes, nat_degree) = average_degree(true);
+    let (reachable_nodes, reachable_degree) = average_degree(false);
+    info!(
+        "NAT: {} nodes behind a NAT with {:.1} connections on average, {} reachable nodes with {:.1}",
+        nat_nodes, nat_degree, reachable_nodes, reachable_degree
+    );
+}
+
+fn log_regions(latency_matrix: &LatencyMatrix, number_of_nodes: u32) {
+    let mut nodes = vec![0; latency_matrix.regions().len()];
+    for node_id in 0..number_of_nodes {
+        nodes[latency_matrix.region_of(node_id)] += 1;
+    }
+    let regions: Vec<String> = latency_matrix
+        .regions()
+        .iter()
+        .zip(nodes)
+        .map(|(region, nodes)| format!("{} nodes in {}", nodes, region))
+        .collect();
+    info!("Regions: {}", regions.join(", "));
+}
+
+fn log_throughput(throughput_stats: &ThroughputStats) {
+    info!(
+        "Throughput: {} payments submitted, {} rejected, {} confirmed, {:.2} confirmed transactions per second",
+        throughput_stats.submitted(),
+        throughput_stats.rejected(),
+        throughput_stats.confirmed(),
+        throughput_stats.confirmed_per_second()
+    );
+
+    let percentile = |percentage| throughput_stats.confirmation_latency_percentile(percentage).unwrap_or_default();
+    info!(
+        "Confirmation latency: {:?} min, {:?} median, {:?} 90th percentile, {:?} 99th percentile, {:?} max",
+        percentile(0),
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        percentile(100)
+    );
+
+    let timeline = throughput_stats.backlog_timeline();
+    let step = timeline.len().div_ceil(BACKLOG_TIMELINE_POINTS).max(1);
+    let points: Vec<String> = timeline
+        .iter()
+        .enumerate()
+        .step_by(step)
+        .map(|(second, backlog)| format!("{}s: {}", second, backlog))
+        .collect();
+    info!("Mempool backlog: {}", points.join(", "));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gives_default_values_to_the_missing_parameters() {
+        let parameters = Parameters::new(&SimulationConfig::new().with_network_size(16)).unwrap();
+
+        assert_eq!(16, parameters.number_of_nodes);
+        assert_eq!(3, parameters.initiated_connections_per_node);
+        assert_eq!(15, parameters.difficulty_factor);
+        assert_eq!(Duration::from_secs(30), parameters.duration);
+        assert_eq!(Topology::Random { seed: None }, parameters.topology);
+    }
+
+    #[test]
+    fn rejects_the_parameters_out_of_bounds() {
+        let too_many_light_nodes = SimulationConfig::new().with_network_size(16).with_light_nodes(17);
+        assert!(Parameters::new(&too_many_light_nodes).is_err());
+
+        let too_many_load_generators = SimulationConfig::new()
+            .with_network_size(16)
+            .with_light_nodes(8)
+            .with_load_generators(9);
+        assert!(Parameters::new(&too_many_load_generators).is_err());
+
+        let too_many_sybil_nodes = SimulationConfig::new()
+            .with_network_size(16)
+            .with_load_generators(8)
+            .with_sybil_nodes(9);
+        assert!(Parameters::new(&too_many_sybil_nodes).is_err());
+
+        let too_many_byzantine_nodes = SimulationConfig::new()
+            .with_network_size(16)
+            .with_sybil_nodes(8)
+            .with_byzantine_nodes(9);
+        assert!(Parameters::new(&too_many_byzantine_nodes).is_err());
+
+        let null_inbound_rate = SimulationConfig::new().with_inbound_rate(0);
+        assert!(Parameters::new(&null_inbound_rate).is_err());
+
+        let null_ban_threshold = SimulationConfig::new().with_ban_threshold(0);
+        assert!(Parameters::new(&null_ban_threshold).is_err());
+
+        assert!(Parameters::new(&SimulationConfig::new().with_max_outbound(0)).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_max_inbound(8).with_anchors(256)).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_max_inbound(8).with_eviction("newest")).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_import_address_book("missing.toml")).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_latency_matrix("missing.csv")).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_regions("regions.csv")).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_max_tx_size(0)).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_dust_threshold(1000000)).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_relay_policy_nodes(8)).is_err());
+
+        assert!(Parameters::new(&SimulationConfig::new().with_worker_threads(0)).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_blocking_threads(0)).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_shards(0)).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_warm_up(1000000)).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_split_threshold(0)).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_nat_fraction(1.0)).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_chains(0)).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_chains(65)).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_chains(2).with_cross_chain_fraction(1.5)).is_err());
+        assert!(Parameters::new(&SimulationConfig::new().with_chains(2).with_topology("ring")).is_err());
+
+        let certain_false_positives = SimulationConfig::new().with_bloom_filters(true).with_bloom_fp_rate(1.0);
+        assert!(Parameters::new(&certain_false_positives).is_err());
+
+        let shallow_pruning = SimulationConfig::new().with_prune(MIN_PRUNE_DEPTH - 1);
+        assert!(Parameters::new(&shallow_pruning).is_err());
+        let exported_pruned_chain = SimulationConfig::new().with_prune(10).with_export_snapshot("chain.snapshot");
+        assert!(Parameters::new(&exported_pruned_chain).is_err());
+
+        let two_imported_snapshots = SimulationConfig::new()
+            .with_import_snapshot("chain.snapshot")
+            .with_import_utxo_snapshot("utxo.snapshot");
+        assert!(Parameters::new(&two_imported_snapshots).is_err());
+        let exported_assumed_chain = SimulationConfig::new()
+            .with_import_utxo_snapshot("utxo.snapshot")
+            .with_export_utxo_snapshot("other_utxo.snapshot");
+        assert!(Parameters::new(&exported_assumed_chain).is_err());
+        let imported_defined_chain = SimulationConfig::new()
+            .with_export_genesis("genesis.toml")
+            .with_import_snapshot("chain.snapshot");
+        assert!(Parameters::new(&imported_defined_chain).is_err());
+        let missing_genesis = SimulationConfig::new().with_genesis("missing_genesis.toml");
+        assert!(Parameters::new(&missing_genesis).is_err());
+
+        let unknown_hasher = SimulationConfig::new().with_hasher("md5");
+        assert!(Parameters::new(&unknown_hasher).is_err());
+
+        let too_many_authorities = SimulationConfig::new()
+            .with_network_size(16)
+            .with_byzantine_nodes(4)
+            .with_consensus("poa")
+            .with_authorities(13);
+        assert!(Parameters::new(&too_many_authorities).is_err());
+        let proof_of_work_authorities = SimulationConfig::new().with_authorities(1);
+        assert!(Parameters::new(&proof_of_work_authorities).is_err());
+        let exported_authority_chain = SimulationConfig::new()
+            .with_consensus("poa")
+            .with_export_snapshot("chain.snapshot");
+        assert!(Parameters::new(&exported_authority_chain).is_err());
+    }
+
+    #[test]
+    fn makes_every_honest_full_node_an_authority_by_default() {
+        let config = SimulationConfig::new()
+            .with_network_size(16)
+            .with_light_nodes(2)
+            .with_byzantine_nodes(4)
+            .with_consensus("poa");
+        assert_eq!(Some(10), Parameters::new(&config).unwrap().number_of_authorities);
+        assert_eq!(None, Parameters::new(&SimulationConfig::new()).unwrap().number_of_authorities);
+    }
+
+    #[test]
+    fn rejects_the_invalid_hashrate_shocks() {
+        let shock = HashrateShock {
+            at_secs: 60.0,
+            miners: 2.0,
+            hashrate: 0.0,
+        };
+        assert!(Parameters::new(&SimulationConfig::new().with_hashrate_shock(shock)).is_err());
+        let config = SimulationConfig::new().with_hashrate_shock(HashrateShock { miners: 0.5, ..shock });
+        assert_eq!(1, Parameters::new(&config).unwrap().hashrate_shocks.len());
+    }
+
+    #[test]
+    fn votes_on_checkpoints_only_if_asked_to() {
+        assert_eq!(None, Parameters::new(&SimulationConfig::new()).unwrap().checkpoint_interval);
+        let config = SimulationConfig::new().with_finality(10);
+        assert_eq!(Some(10), Parameters::new(&config).unwrap().checkpoint_interval);
+        assert!(Parameters::new(&SimulationConfig::new().with_finality(0)).is_err());
+    }
+
+    #[test]
+    fn calibrates_the_difficulty_of_a_proof_of_stake_chain_for_a_single_leader() {
+        let config = SimulationConfig::new().with_difficulty(DifficultySetting::BlockInterval(Duration::from_secs(10)));
+        let proof_of_authority = Parameters::new(&config.clone().with_consensus("poa")).unwrap();
+        let proof_of_stake = Parameters::new(&config.with_consensus("pos")).unwrap();
+        assert!(proof_of_stake.with_proof_of_stake);
+        assert_eq!(proof_of_authority.number_of_authorities, proof_of_stake.number_of_authorities);
+        assert!(proof_of_stake.difficulty_factor < proof_of_authority.difficulty_factor);
+    }
+
+    #[test]
+    fn captures_the_log_lines_of_every_node() {
+        let config = SimulationConfig::new().with_network_size(4).with_duration_in_seconds(1);
+        let log_capture = LogCapture::new();
+        run_with_log_capture(&config, &log_capture).unwrap();
+
+        assert_eq!(vec![0, 1, 2, 3], log_capture.nodes());
+        for node_id in 0..4 {
+            let lines = log_capture.lines(node_id);
+            assert!(lines.iter().any(|line| line.event == "peer"));
+            assert!(lines.windows(2).all(|lines| lines[0].time <= lines[1].time));
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! Runs a small network of full nodes in process, with some latency or partitions, until its
+//! nodes reach a given state or a timeout, so that a test checks the chains of the nodes rather
+//! than counters shared with them. The network follows a virtual clock by default, so that a
+//! scenario of minutes runs in milliseconds:
+//!
+//! ```no_run
+//! extern crate pow_blockchain_simulation as pow;
+//!
+//! use pow::testkit::TestNetwork;
+//! use std::time::Duration;
+//!
+//! let run = TestNetwork::new(8)
+//!     .with_miners(1)
+//!     .with_latency(Duration::from_millis(20))
+//!     .run_until(|state| state.all_at_height(5));
+//! assert!(run.reached());
+//! assert!(run.state().converged());
+//! ```
+
+use crate::blockchain::{Chain, Difficulty, NodeChains, PowNode};
+use netsim::clock::{Clock, MockClock};
+use netsim::network::scenario::Action;
+use netsim::network::{Scenario, Topology};
+use netsim::simulation::{SimulationBuilder, SimulationReport};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The chains of the nodes of a test network at some point, by node id.
+pub struct NetworkState {
+    chains: Vec<Arc<Chain>>,
+}
+
+impl NetworkState {
+    fn new(node_chains: &NodeChains, network_size: u32) -> NetworkState {
+        NetworkState {
+            chains: (0..network_size).map(|node_id| node_chains.get(node_id)).collect(),
+        }
+    }
+
+    pub fn chain(&self, node_id: u32) -> &Arc<Chain> {
+        &self.chains[node_id as usize]
+    }
+
+    /// The heights of the chains of the nodes, by node id.
+    pub fn heights(&self) -> Vec<u32> {
+        self.chains.iter().map(|chain| chain.height()).collect()
+    }
+
+    pub fn min_height(&self) -> u32 {
+        self.chains.iter().map(|chain| chain.height()).min().unwrap_or(0)
+    }
+
+    /// Whether every node adopted a chain at least as high as the given height.
+    pub fn all_at_height(&self, height: u32) -> bool {
+        self.min_height() >= height
+    }
+
+    /// Whether every node has the same head block.
+    pub fn converged(&self) -> bool {
+        self.chains.windows(2).all(|chains| chains[0].head().hash() == chains[1].head().hash())
+    }
+}
+
+/// What a test network did: whether its nodes reached the expected state before the timeout,
+/// and their chains once they stopped.
+pub struct TestRun {
+    report: SimulationReport,
+    state: NetworkState,
+    reached: bool,
+}
+
+impl TestRun {
+    pub fn report(&self) -> &SimulationReport {
+        &self.report
+    }
+
+    pub fn state(&self) -> &NetworkState {
+        &self.state
+    }
+
+    /// Whether the nodes reached the expected state, rather than running until the timeout.
+    pub fn reached(&self) -> bool {
+        self.reached
+    }
+}
+
+/// Configures a test network of full nodes sharing a genesis block of the minimal difficulty:
+/// every mining attempt finds a block, so that the chains grow at the pace of the mining delay.
+pub struct TestNetwork {
+    network_size: u32,
+    connections: u8,
+    topology: Topology,
+    latency: Duration,
+    scenario: Scenario,
+    timeout: Duration,
+    miners: u32,
+    mining_attempt_delay: Duration,
+    payment_attempt_delay: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl TestNetwork {
+    pub fn new(network_size: u32) -> TestNetwork {
+        TestNetwork {
+            network_size,
+            connections: 2,
+            topology: Topology::Random { seed: Some(0) },
+            latency: Duration::from_millis(0),
+            scenario: Scenario::new(),
+            timeout: Duration::from_secs(10),
+            miners: network_size,
+            mining_attempt_delay: Duration::from_millis(50),
+            payment_attempt_delay: Duration::from_millis(200),
+            clock: Arc::new(MockClock),
+        }
+    }
+
+    /// How many connections each node initiates. Default: 2
+    pub fn with_connections(mut self, connections: u8) -> TestNetwork {
+        self.connections = connections;
+        self
+    }
+
+    /// Default: random peers, always the same ones.
+    pub fn with_topology(mut self, topology: Topology) -> TestNetwork {
+        self.topology = topology;
+        self
+    }
+
+    /// The delay of every message. Default: none
+    pub fn with_latency(mut self, latency: Duration) -> TestNetwork {
+        self.latency = latency;
+        self
+    }
+
+    /// The events changing the network while it runs. Default: none
+    pub fn with_scenario(mut self, scenario: Scenario) -> TestNetwork {
+        self.scenario = scenario;
+        self
+    }
+
+    /// Splits the network in the given groups of nodes at the given time.
+    pub fn with_partition(mut self, at: Duration, groups: Vec<Vec<u32>>) -> TestNetwork {
+        self.scenario = self.scenario.with_event(at, Action::Partition { groups });
+        self
+    }
+
+    /// Ends the partition at the given time.
+    pub fn with_heal(mut self, at: Duration) -> TestNetwork {
+        self.scenario = self.scenario.with_event(at, Action::Heal);
+        self
+    }
+
+    /// How long the nodes run if they do not reach the expected state, by the clock of the
+    /// network. Default: 10 seconds
+    pub fn with_timeout(mut self, timeout: Duration) -> TestNetwork {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Only the nodes with the lowest ids mine, the others stop mining at once. Default: all
+    pub fn with_miners(mut self, miners: u32) -> TestNetwork {
+        self.miners = miners;
+        self
+    }
+
+    /// The delay between two blocks of a miner. Default: 50 milliseconds
+    pub fn with_mining_delay(mut self, mining_attempt_delay: Duration) -> TestNetwork {
+        self.mining_attempt_delay = mining_attempt_delay;
+        self
+    }
+
+    /// The clock of the network, the `SystemClock` for the nodes to run in real time. Default:
+    /// a virtual clock
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> TestNetwork {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Runs the nodes until their chains satisfy the given condition, or until the timeout.
+    pub fn run_until<P>(self, condition: P) -> TestRun
+    where
+        P: Fn(&NetworkState) -> bool + Send + 'static,
+    {
+        let network_size = self.network_size;
+        let genesis_chain = Arc::new(Chain::init_new(Difficulty::min_difficulty()));
+        let node_chains = NodeChains::new(genesis_chain.clone());
+        let reached = Arc::new(AtomicBool::new(false));
+
+        let node_id = AtomicUsize::new(0);
+        let factory_node_chains = node_chains.clone();
+        let miners = self.miners;
+        let mining_attempt_delay = self.mining_attempt_delay;
+        let payment_attempt_delay = self.payment_attempt_delay;
+        let node_factory = move || {
+            let node_id = node_id.fetch_add(1, Ordering::Relaxed) as u32;
+            let node = PowNode::new(node_id, genesis_chain.clone(), mining_attempt_delay, payment_attempt_delay)
+                .with_node_chains(factory_node_chains.clone());
+            if node_id < miners {
+                node
+            } else {
+                node.with_hashrate_shock(Duration::from_secs(0), None)
+            }
+        };
+
+        let condition_node_chains = node_chains.clone();
+        let condition_reached = reached.clone();
+        let report = SimulationBuilder::new(network_size, node_factory)
+            .with_connections(self.connections)
+            .with_topology(self.topology)
+            .with_latency(self.latency)
+            .with_scenario(self.scenario)
+            .with_duration(self.timeout)
+            .with_clock(self.clock)
+            .build()
+            .run_until(move || {
+                let holds = condition(&NetworkState::new(&condition_node_chains, network_size));
+                if holds {
+                    condition_reached.store(true, Ordering::Relaxed);
+                }
+                holds
+            });
+
+        TestRun {
+            report,
+            state: NetworkState::new(&node_chains, network_size),
+            reached: reached.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn runs_until_every_node_follows_the_miner() {
+        let run = TestNetwork::new(6)
+            .with_miners(1)
+            .with_latency(Duration::from_millis(5))
+            .run_until(|state| state.all_at_height(3) && state.converged());
+
+        assert!(run.reached());
+        assert!(run.state().all_at_height(3));
+        assert!(run.report().elapsed < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn keeps_the_chain_of_the_miner_on_its_side_of_a_partition() {
+        let run = TestNetwork::new(4)
+            .with_topology(Topology::Ring)
+            .with_connections(1)
+            .with_miners(1)
+            .with_partition(Duration::from_secs(0), vec![vec![0, 1], vec![2, 3]])
+            .with_timeout(Duration::from_millis(500))
+            .run_until(|state| state.all_at_height(1));
+
+        assert!(!run.reached());
+        assert!(run.state().chain(0).height() >= 1);
+        assert_eq!(vec![0, 0], run.state().heights()[2..].to_vec());
+    }
+
+    #[test]
+    fn catches_up_after_a_long_partition_in_virtual_time() {
+        let real_start = Instant::now();
+        let run = TestNetwork::new(4)
+            .with_topology(Topology::Ring)
+            .with_connections(1)
+            .with_miners(1)
+            .with_mining_delay(Duration::from_secs(10))
+            .with_partition(Duration::from_secs(0), vec![vec![0, 1], vec![2, 3]])
+            .with_heal(Duration::from_secs(600))
+            .with_timeout(Duration::from_secs(3600))
+            .run_until(|state| state.all_at_height(50) && state.converged());
+
+        assert!(run.reached());
+        assert!(run.report().elapsed >= Duration::from_secs(600));
+        assert!(real_start.elapsed() < Duration::from_secs(60));
+    }
+}